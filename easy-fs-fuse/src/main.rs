@@ -1,5 +1,7 @@
-use clap::{App, Arg};
-use easy_fs::{BlockDevice, EasyFileSystem};
+use clap::{App, Arg, ArgMatches};
+use easy_fs::{block_cache_sync_all, BlockDevice, EasyFileSystem};
+#[cfg(test)]
+use fat32::Fat32FileSystem;
 use std::fs::{read_dir, File, OpenOptions};
 use std::io::{Read, Seek, SeekFrom, Write};
 use std::sync::Arc;
@@ -29,11 +31,17 @@ impl BlockDevice for BlockFile {
     }
 }
 
-fn main() {
-    easy_fs_pack().expect("Error when packing easy-fs!");
-}
+/// Where the kernel leaves its crash record on panic, after the file system
+/// and the 4MiB of swap, see `crash` in the kernel.
+const CRASH_DUMP_START_BLOCK: usize = 32 * 2048 + 8192;
+const CRASH_DUMP_BLOCKS: usize = 128;
+const CRASH_MAGIC: &[u8; 8] = b"rCoreDmp";
+/// blocks of kernel stack and of log after the header
+const CRASH_STACK_BLOCKS: usize = 32;
+const CRASH_KMSG_BLOCKS: usize = 32;
+const CRASH_HEADER_WORDS: usize = 6 + 32 + 5;
 
-fn easy_fs_pack() -> std::io::Result<()> {
+fn main() {
     let matches = App::new("EasyFileSystem packer")
         .arg(
             Arg::with_name("source")
@@ -49,7 +57,101 @@ fn easy_fs_pack() -> std::io::Result<()> {
                 .takes_value(true)
                 .help("Executable target dir(with backslash)"),
         )
+        .arg(
+            Arg::with_name("crash-dump")
+                .long("crash-dump")
+                .takes_value(true)
+                .help("Print the crash record the kernel left in this image"),
+        )
         .get_matches();
+    if let Some(image) = matches.value_of("crash-dump") {
+        print_crash_dump(image).expect("Error when reading the crash dump!");
+    } else {
+        easy_fs_pack(&matches).expect("Error when packing easy-fs!");
+    }
+}
+
+fn print_crash_dump(image: &str) -> std::io::Result<()> {
+    let mut f = File::open(image)?;
+    f.seek(SeekFrom::Start((CRASH_DUMP_START_BLOCK * BLOCK_SZ) as u64))?;
+    let mut record = vec![0u8; CRASH_DUMP_BLOCKS * BLOCK_SZ];
+    f.read_exact(&mut record)?;
+    match decode_crash_dump(&record) {
+        Some(text) => print!("{}", text),
+        None => println!("no crash record in {}", image),
+    }
+    Ok(())
+}
+
+/// The crash record `record` in words, `None` if the kernel has not
+/// written one.
+fn decode_crash_dump(record: &[u8]) -> Option<String> {
+    use std::convert::TryInto;
+    use std::fmt::Write;
+    const ABI_NAMES: [&str; 32] = [
+        "zero", "ra", "sp", "gp", "tp", "t0", "t1", "t2", "s0", "s1", "a0", "a1", "a2", "a3", "a4",
+        "a5", "a6", "a7", "s2", "s3", "s4", "s5", "s6", "s7", "s8", "s9", "s10", "s11", "t3", "t4",
+        "t5", "t6",
+    ];
+    if !record.starts_with(CRASH_MAGIC) {
+        return None;
+    }
+    let words: Vec<usize> = record[CRASH_MAGIC.len()..]
+        .chunks_exact(8)
+        .take(CRASH_HEADER_WORDS)
+        .map(|word| u64::from_le_bytes(word.try_into().unwrap()) as usize)
+        .collect();
+    let (hart, ns, csrs, x) = (words[0], words[1], &words[2..6], &words[6..38]);
+    let (sp, stack_len, kmsg_len, tasks_len, message_len) =
+        (words[38], words[39], words[40], words[41], words[42]);
+    let message_start = CRASH_MAGIC.len() + CRASH_HEADER_WORDS * 8;
+    let message = &record[message_start..(message_start + message_len).min(BLOCK_SZ)];
+    let area = |index: usize, len: usize| {
+        let start = index * BLOCK_SZ;
+        &record[start.min(record.len())..(start + len).min(record.len())]
+    };
+    let stack = area(1, stack_len);
+    let kmsg = area(1 + CRASH_STACK_BLOCKS, kmsg_len);
+    let tasks = area(1 + CRASH_STACK_BLOCKS + CRASH_KMSG_BLOCKS, tasks_len);
+
+    let mut text = String::new();
+    writeln!(
+        text,
+        "panic on hart {} at {}.{:06}s: {}",
+        hart,
+        ns / 1_000_000_000,
+        ns % 1_000_000_000 / 1000,
+        String::from_utf8_lossy(message)
+    )
+    .unwrap();
+    writeln!(
+        text,
+        "sepc={:#x} scause={:#x} stval={:#x} sstatus={:#x}",
+        csrs[0], csrs[1], csrs[2], csrs[3]
+    )
+    .unwrap();
+    for (i, (name, value)) in ABI_NAMES.iter().zip(x).enumerate() {
+        let end = if i % 4 == 3 { "\n" } else { " " };
+        write!(text, "{:>4}={:#018x}{}", name, value, end).unwrap();
+    }
+    writeln!(text, "--- stack from {:#x} ---", sp).unwrap();
+    for (i, line) in stack.chunks(16).enumerate() {
+        write!(text, "{:#x}:", sp + i * 16).unwrap();
+        for word in line.chunks(8) {
+            let mut bytes = [0u8; 8];
+            bytes[..word.len()].copy_from_slice(word);
+            write!(text, " {:016x}", u64::from_le_bytes(bytes)).unwrap();
+        }
+        writeln!(text).unwrap();
+    }
+    writeln!(text, "--- log ---").unwrap();
+    text.push_str(&String::from_utf8_lossy(kmsg));
+    writeln!(text, "--- tasks (pid tid state) ---").unwrap();
+    text.push_str(&String::from_utf8_lossy(tasks));
+    Some(text)
+}
+
+fn easy_fs_pack(matches: &ArgMatches) -> std::io::Result<()> {
     let src_path = matches.value_of("source").unwrap();
     let target_path = matches.value_of("target").unwrap();
     println!("src_path = {}\ntarget_path = {}", src_path, target_path);
@@ -59,7 +161,10 @@ fn easy_fs_pack() -> std::io::Result<()> {
             .write(true)
             .create(true)
             .open(format!("{}{}", target_path, "fs.img"))?;
-        f.set_len(32 * 2048 * 512).unwrap();
+        // the kernel swaps to the 4MiB after the file system, and leaves
+        // a crash record after that
+        f.set_len(((CRASH_DUMP_START_BLOCK + CRASH_DUMP_BLOCKS) * BLOCK_SZ) as u64)
+            .unwrap();
         f
     })));
     // 32MiB, at most 4095 files
@@ -88,6 +193,8 @@ fn easy_fs_pack() -> std::io::Result<()> {
     // for app in root_inode.ls() {
     //     println!("{}", app);
     // }
+    // the block cache only writes back when asked or on eviction
+    block_cache_sync_all();
     Ok(())
 }
 
@@ -151,5 +258,318 @@ fn efs_test() -> std::io::Result<()> {
     random_str_test(1000 * BLOCK_SZ);
     random_str_test(2000 * BLOCK_SZ);
 
+    assert!(Fat32FileSystem::open(block_file.clone()).is_none());
+    efs_dir_test(block_file);
+    efs_journal_test()?;
+    fat32_test()?;
+    Ok(())
+}
+
+/// Called from `efs_test`, as the block cache is shared by all images.
+#[cfg(test)]
+fn efs_dir_test(block_file: Arc<BlockFile>) {
+    EasyFileSystem::create(block_file.clone(), 4096, 1);
+    let efs = EasyFileSystem::open(block_file.clone());
+    let root_inode = Arc::new(EasyFileSystem::root_inode(&efs));
+    assert!(root_inode.is_dir());
+    assert_eq!(root_inode.ls(), vec![".", ".."]);
+
+    let dira = root_inode.create_dir("dira").unwrap();
+    assert!(dira.is_dir());
+    assert!(root_inode.create_dir("dira").is_none());
+    assert!(root_inode.create("dira").is_none());
+    let filea = dira.create("filea").unwrap();
+    assert!(!filea.is_dir());
+    assert!(filea.create("fileb").is_none());
+    dira.create_dir("dirb").unwrap();
+    assert_eq!(dira.ls(), vec![".", "..", "filea", "dirb"]);
+
+    let greet_str = "Hello, world!";
+    filea.write_at(0, greet_str.as_bytes());
+    let found = root_inode.find_path("/dira//filea").unwrap();
+    let mut buffer = [0u8; 233];
+    let len = found.read_at(0, &mut buffer);
+    assert_eq!(greet_str, core::str::from_utf8(&buffer[..len]).unwrap());
+    assert!(root_inode.find_path("dira/dirb/../filea").is_some());
+    assert!(root_inode.find_path("dira/dirb/./..").unwrap().is_dir());
+    assert!(root_inode.find_path("dira/filea/..").is_none());
+    assert!(root_inode.find_path("dira/nothing").is_none());
+    assert!(root_inode.find_path("/").unwrap().is_dir());
+
+    // "/", "/." and "/dira/.."
+    assert_eq!(root_inode.nlink(), 3);
+    assert_eq!(dira.nlink(), 3);
+    assert_eq!(filea.nlink(), 1);
+    assert!(root_inode.link("linka", &filea));
+    assert!(!root_inode.link("linka", &filea));
+    assert!(!root_inode.link("linkb", &dira));
+    let linka = root_inode.find("linka").unwrap();
+    assert_eq!(linka.inode_id(), filea.inode_id());
+    assert_eq!(filea.nlink(), 2);
+    assert!(dira.unlink("filea"));
+    assert!(!dira.unlink("filea"));
+    assert!(!root_inode.unlink("dira"));
+    assert_eq!(dira.ls(), vec![".", "..", "dirb"]);
+    assert_eq!(linka.nlink(), 1);
+    let len = linka.read_at(0, &mut buffer);
+    assert_eq!(greet_str, core::str::from_utf8(&buffer[..len]).unwrap());
+    // the freed inode and the removed entry are reused
+    let inode_id = linka.inode_id();
+    assert!(root_inode.unlink("linka"));
+    let filec = dira.create("filec").unwrap();
+    assert_eq!(filec.inode_id(), inode_id);
+    assert_eq!(dira.ls(), vec![".", "..", "filec", "dirb"]);
+}
+
+/// Drops all writes after the journal header is written once armed,
+/// like a power cut right after an operation is committed.
+#[cfg(test)]
+struct CrashFile {
+    file: BlockFile,
+    header_block: usize,
+    armed: std::sync::atomic::AtomicBool,
+    crashed: std::sync::atomic::AtomicBool,
+}
+
+#[cfg(test)]
+impl BlockDevice for CrashFile {
+    fn read_block(&self, block_id: usize, buf: &mut [u8]) {
+        self.file.read_block(block_id, buf);
+    }
+
+    fn write_block(&self, block_id: usize, buf: &[u8]) {
+        use std::sync::atomic::Ordering;
+        if self.crashed.load(Ordering::SeqCst) {
+            return;
+        }
+        self.file.write_block(block_id, buf);
+        if block_id == self.header_block && self.armed.load(Ordering::SeqCst) {
+            self.crashed.store(true, Ordering::SeqCst);
+        }
+    }
+
+    fn handle_irq(&self) {}
+}
+
+#[cfg(test)]
+fn efs_journal_test() -> std::io::Result<()> {
+    use easy_fs::JOURNAL_BLOCKS;
+    use std::sync::atomic::Ordering;
+    let header_block = 4096 - JOURNAL_BLOCKS as usize;
+    let crash_file = Arc::new(CrashFile {
+        file: BlockFile(Mutex::new({
+            let f = OpenOptions::new()
+                .read(true)
+                .write(true)
+                .create(true)
+                .truncate(true)
+                .open("target/fs_journal.img")?;
+            f.set_len(8192 * 512).unwrap();
+            f
+        })),
+        header_block,
+        armed: false.into(),
+        crashed: false.into(),
+    });
+    // whether a name made it to its home, outside of the journal
+    let on_disk = |name: &str| -> std::io::Result<bool> {
+        let image = std::fs::read("target/fs_journal.img")?;
+        Ok(image[..header_block * BLOCK_SZ]
+            .windows(name.len())
+            .any(|window| window == name.as_bytes()))
+    };
+    EasyFileSystem::create(crash_file.clone(), 4096, 1);
+    let efs = EasyFileSystem::open(crash_file.clone());
+    let root_inode = EasyFileSystem::root_inode(&efs);
+    root_inode.create("journal_a").unwrap();
+    assert!(on_disk("journal_a")?);
+
+    crash_file.armed.store(true, Ordering::SeqCst);
+    root_inode.create("journal_b").unwrap();
+    assert!(crash_file.crashed.load(Ordering::SeqCst));
+    assert!(!on_disk("journal_b")?);
+
+    // reboot, the committed operation is replayed
+    crash_file.armed.store(false, Ordering::SeqCst);
+    crash_file.crashed.store(false, Ordering::SeqCst);
+    let efs = EasyFileSystem::open(crash_file.clone());
+    assert!(on_disk("journal_b")?);
+    let root_inode = EasyFileSystem::root_inode(&efs);
+    assert_eq!(root_inode.ls(), vec![".", "..", "journal_a", "journal_b"]);
+    Ok(())
+}
+
+/// A FAT32 image as mkfs.vfat would make it, in an MBR partition.
+#[cfg(test)]
+fn fat32_test() -> std::io::Result<()> {
+    const PARTITION_START: usize = 8;
+    const FAT_START: usize = PARTITION_START + 4;
+    // two FATs of 8 sectors, one sector per cluster
+    const DATA_START: usize = FAT_START + 2 * 8;
+    let mut image = vec![0u8; 4096 * BLOCK_SZ];
+    let put = |image: &mut Vec<u8>, offset: usize, bytes: &[u8]| {
+        image[offset..offset + bytes.len()].copy_from_slice(bytes);
+    };
+    let cluster = |n: u32| (DATA_START + n as usize - 2) * BLOCK_SZ;
+    // MBR with a FAT32 LBA partition
+    put(&mut image, 446 + 4, &[0x0c]);
+    put(&mut image, 446 + 8, &(PARTITION_START as u32).to_le_bytes());
+    put(&mut image, 446 + 12, &(4088u32).to_le_bytes());
+    put(&mut image, 510, &[0x55, 0xaa]);
+    // boot sector
+    let boot = PARTITION_START * BLOCK_SZ;
+    put(&mut image, boot + 11, &512u16.to_le_bytes());
+    put(&mut image, boot + 13, &[1]);
+    put(&mut image, boot + 14, &4u16.to_le_bytes());
+    put(&mut image, boot + 16, &[2]);
+    put(&mut image, boot + 32, &4088u32.to_le_bytes());
+    put(&mut image, boot + 36, &8u32.to_le_bytes());
+    put(&mut image, boot + 44, &2u32.to_le_bytes());
+    put(&mut image, boot + 82, b"FAT32   ");
+    put(&mut image, boot + 510, &[0x55, 0xaa]);
+    // root 2, sub 3, hello.txt 4, long_file_name.txt 5 -> 9 -> 6, inner 7
+    let end = 0x0fff_ffffu32;
+    let fat = [0x0fff_fff8, end, end, end, end, 9, end, end, 0, 6];
+    for (i, entry) in fat.iter().enumerate() {
+        put(
+            &mut image,
+            FAT_START * BLOCK_SZ + i * 4,
+            &entry.to_le_bytes(),
+        );
+    }
+    let dirent = |name: &[u8; 11], attr: u8, nt_res: u8, cluster: u32, size: u32| {
+        let mut dirent = [0u8; 32];
+        dirent[..11].copy_from_slice(name);
+        dirent[11] = attr;
+        dirent[12] = nt_res;
+        dirent[20..22].copy_from_slice(&((cluster >> 16) as u16).to_le_bytes());
+        dirent[26..28].copy_from_slice(&(cluster as u16).to_le_bytes());
+        dirent[28..32].copy_from_slice(&size.to_le_bytes());
+        dirent
+    };
+    let short_name = b"LONG_F~1TXT";
+    let checksum = short_name
+        .iter()
+        .fold(0u8, |sum, &b| sum.rotate_right(1).wrapping_add(b));
+    let long_name_part = |order: u8, chars: &[u16]| {
+        let mut dirent = [0u8; 32];
+        dirent[0] = order;
+        dirent[11] = 0x0f;
+        dirent[13] = checksum;
+        let offsets = [1, 3, 5, 7, 9, 14, 16, 18, 20, 22, 24, 28, 30];
+        for (i, offset) in offsets.iter().enumerate() {
+            let c = chars
+                .get(i)
+                .cloned()
+                .unwrap_or(if i == chars.len() { 0 } else { 0xffff });
+            dirent[*offset..*offset + 2].copy_from_slice(&c.to_le_bytes());
+        }
+        dirent
+    };
+    let long_name: Vec<u16> = "long_file_name.txt".encode_utf16().collect();
+    let mut deleted = dirent(b"GONE    TXT", 0x20, 0, 8, 1);
+    deleted[0] = 0xe5;
+    let root_dirents = [
+        dirent(b"TESTVOL    ", 0x08, 0, 0, 0),
+        long_name_part(0x42, &long_name[13..]),
+        long_name_part(0x01, &long_name[..13]),
+        dirent(short_name, 0x20, 0, 5, 1300),
+        deleted,
+        dirent(b"HELLO   TXT", 0x20, 0x18, 4, 5),
+        dirent(b"SUB        ", 0x10, 0, 3, 0),
+    ];
+    for (i, dirent) in root_dirents.iter().enumerate() {
+        put(&mut image, cluster(2) + i * 32, dirent);
+    }
+    let sub_dirents = [
+        dirent(b".          ", 0x10, 0, 3, 0),
+        dirent(b"..         ", 0x10, 0, 0, 0),
+        dirent(b"INNER      ", 0x20, 0, 7, 3),
+    ];
+    for (i, dirent) in sub_dirents.iter().enumerate() {
+        put(&mut image, cluster(3) + i * 32, dirent);
+    }
+    put(&mut image, cluster(4), b"hello");
+    put(&mut image, cluster(7), b"abc");
+    let long_data: Vec<u8> = (0..1300).map(|i| (i % 251) as u8).collect();
+    put(&mut image, cluster(5), &long_data[..512]);
+    put(&mut image, cluster(9), &long_data[512..1024]);
+    put(&mut image, cluster(6), &long_data[1024..]);
+    std::fs::write("target/fat32.img", &image)?;
+
+    let block_file = Arc::new(BlockFile(Mutex::new(
+        OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open("target/fat32.img")?,
+    )));
+    let fs = Fat32FileSystem::open(block_file).unwrap();
+    let root_inode = Arc::new(Fat32FileSystem::root_inode(&fs));
+    assert!(root_inode.is_dir());
+    assert_eq!(
+        root_inode.ls(),
+        vec!["long_file_name.txt", "hello.txt", "SUB"]
+    );
+    let read_all = |inode: &fat32::Inode| {
+        let mut buf = vec![0u8; inode.size() + 10];
+        let len = inode.read_at(0, &mut buf);
+        buf.truncate(len);
+        buf
+    };
+    // names ignore case
+    let hello = root_inode.find("HELLO.TXT").unwrap();
+    assert!(!hello.is_dir());
+    assert_eq!(read_all(&hello), b"hello");
+    let long_file = root_inode.find("long_file_name.txt").unwrap();
+    assert_eq!(read_all(&long_file), long_data);
+    // across the clusters 5 and 9
+    let mut buf = [0u8; 100];
+    assert_eq!(long_file.read_at(480, &mut buf), 100);
+    assert_eq!(&buf[..], &long_data[480..580]);
+    assert_eq!(long_file.read_at(1250, &mut buf), 50);
+    assert!(root_inode.find("gone.txt").is_none());
+    assert!(root_inode.find("testvol").is_none());
+    assert!(hello.find("x").is_none());
+
+    let sub = root_inode.find("sub").unwrap();
+    assert!(sub.is_dir());
+    assert_eq!(sub.ls(), vec![".", "..", "INNER"]);
+    assert_eq!(read_all(&sub.find("inner").unwrap()), b"abc");
+    let parent = sub.find("..").unwrap();
+    assert_eq!(parent.first_cluster(), root_inode.first_cluster());
+    assert_eq!(parent.ls(), root_inode.ls());
     Ok(())
 }
+
+#[test]
+fn crash_dump_test() {
+    let mut record = vec![0u8; CRASH_DUMP_BLOCKS * BLOCK_SZ];
+    assert!(decode_crash_dump(&record).is_none());
+    let message = b"src/main.rs:1 oops";
+    let log = b"[    1.000000] [ INFO] [0] booted\n";
+    let tasks = b"0 0 blocked\n1 0 running\n";
+    let mut x = [0usize; 32];
+    x[1] = 0x8020_1234;
+    let words = [1, 2_500_000_000, 0x8020_0000, 0xd, 0x10, 0x120]
+        .iter()
+        .chain(&x)
+        .chain(&[0x8030_0000, 24, log.len(), tasks.len(), message.len()])
+        .flat_map(|word| (*word as u64).to_le_bytes())
+        .collect::<Vec<u8>>();
+    record[..8].copy_from_slice(CRASH_MAGIC);
+    record[8..8 + words.len()].copy_from_slice(&words);
+    record[8 + words.len()..8 + words.len() + message.len()].copy_from_slice(message);
+    record[BLOCK_SZ..BLOCK_SZ + 24].fill(0xab);
+    let kmsg_start = (1 + CRASH_STACK_BLOCKS) * BLOCK_SZ;
+    record[kmsg_start..kmsg_start + log.len()].copy_from_slice(log);
+    let tasks_start = (1 + CRASH_STACK_BLOCKS + CRASH_KMSG_BLOCKS) * BLOCK_SZ;
+    record[tasks_start..tasks_start + tasks.len()].copy_from_slice(tasks);
+
+    let text = decode_crash_dump(&record).unwrap();
+    assert!(text.starts_with("panic on hart 1 at 2.500000s: src/main.rs:1 oops\n"));
+    assert!(text.contains("sepc=0x80200000 scause=0xd stval=0x10 sstatus=0x120"));
+    assert!(text.contains("  ra=0x0000000080201234"));
+    assert!(text.contains("0x80300010: abababababababab\n"));
+    assert!(text.contains("booted\n"));
+    assert!(text.ends_with("1 0 running\n"));
+}
@@ -60,6 +60,10 @@ impl BlockCache {
         f(self.get_mut(offset))
     }
 
+    pub fn is_modified(&self) -> bool {
+        self.modified
+    }
+
     pub fn sync(&mut self) {
         if self.modified {
             self.modified = false;
@@ -74,10 +78,19 @@ impl Drop for BlockCache {
     }
 }
 
-const BLOCK_CACHE_SIZE: usize = 16;
+const BLOCK_CACHE_SIZE: usize = 64;
+
+/// Blocks of different devices are told apart by the device address.
+fn device_id(block_device: &Arc<dyn BlockDevice>) -> usize {
+    Arc::as_ptr(block_device) as *const () as usize
+}
+
+/// (device, block_id) and the cache of that block
+type CacheEntry = ((usize, usize), Arc<Mutex<BlockCache>>);
 
 pub struct BlockCacheManager {
-    queue: VecDeque<(usize, Arc<Mutex<BlockCache>>)>,
+    /// in least recently used first order
+    queue: VecDeque<CacheEntry>,
 }
 
 impl BlockCacheManager {
@@ -92,18 +105,19 @@ impl BlockCacheManager {
         block_id: usize,
         block_device: Arc<dyn BlockDevice>,
     ) -> Arc<Mutex<BlockCache>> {
-        if let Some(pair) = self.queue.iter().find(|pair| pair.0 == block_id) {
-            Arc::clone(&pair.1)
+        let key = (device_id(&block_device), block_id);
+        if let Some(idx) = self.queue.iter().position(|pair| pair.0 == key) {
+            let pair = self.queue.remove(idx).unwrap();
+            let block_cache = Arc::clone(&pair.1);
+            self.queue.push_back(pair);
+            block_cache
         } else {
-            // substitute
+            // substitute, dirty blocks must go through the journal
             if self.queue.len() == BLOCK_CACHE_SIZE {
-                // from front to tail
-                if let Some((idx, _)) = self
-                    .queue
-                    .iter()
-                    .enumerate()
-                    .find(|(_, pair)| Arc::strong_count(&pair.1) == 1)
-                {
+                // from least to most recently used
+                if let Some((idx, _)) = self.queue.iter().enumerate().find(|(_, pair)| {
+                    Arc::strong_count(&pair.1) == 1 && !pair.1.lock().is_modified()
+                }) {
                     self.queue.drain(idx..=idx);
                 } else {
                     panic!("Run out of BlockCache!");
@@ -114,7 +128,7 @@ impl BlockCacheManager {
                 block_id,
                 Arc::clone(&block_device),
             )));
-            self.queue.push_back((block_id, Arc::clone(&block_cache)));
+            self.queue.push_back((key, Arc::clone(&block_cache)));
             block_cache
         }
     }
@@ -134,6 +148,30 @@ pub fn get_block_cache(
         .get_block_cache(block_id, block_device)
 }
 
+/// Return (cached, capacity) number of blocks.
+pub fn block_cache_stats() -> (usize, usize) {
+    (BLOCK_CACHE_MANAGER.lock().queue.len(), BLOCK_CACHE_SIZE)
+}
+
+/// Return the modified blocks of `block_device`.
+pub fn dirty_block_caches(
+    block_device: &Arc<dyn BlockDevice>,
+) -> Vec<(usize, Arc<Mutex<BlockCache>>)> {
+    let device = device_id(block_device);
+    let caches: Vec<_> = BLOCK_CACHE_MANAGER
+        .lock()
+        .queue
+        .iter()
+        .filter(|((cache_device, _), _)| *cache_device == device)
+        .map(|((_, block_id), cache)| (*block_id, Arc::clone(cache)))
+        .collect();
+    caches
+        .into_iter()
+        .filter(|(_, cache)| cache.lock().is_modified())
+        .collect()
+}
+
+/// Write all dirty blocks back to their devices.
 pub fn block_cache_sync_all() {
     let manager = BLOCK_CACHE_MANAGER.lock();
     for (_, cache) in manager.queue.iter() {
@@ -1,6 +1,6 @@
 use super::{
     block_cache_sync_all, get_block_cache, Bitmap, BlockDevice, DiskInode, DiskInodeType, Inode,
-    SuperBlock,
+    Journal, SuperBlock, JOURNAL_BLOCKS,
 };
 use crate::BLOCK_SZ;
 use alloc::sync::Arc;
@@ -12,6 +12,7 @@ pub struct EasyFileSystem {
     pub data_bitmap: Bitmap,
     inode_area_start_block: u32,
     data_area_start_block: u32,
+    journal: Journal,
 }
 
 type DataBlock = [u8; BLOCK_SZ];
@@ -28,7 +29,8 @@ impl EasyFileSystem {
         let inode_area_blocks =
             ((inode_num * core::mem::size_of::<DiskInode>() + BLOCK_SZ - 1) / BLOCK_SZ) as u32;
         let inode_total_blocks = inode_bitmap_blocks + inode_area_blocks;
-        let data_total_blocks = total_blocks - 1 - inode_total_blocks;
+        // the journal takes the last blocks
+        let data_total_blocks = total_blocks - 1 - inode_total_blocks - JOURNAL_BLOCKS;
         let data_bitmap_blocks = (data_total_blocks + 4096) / 4097;
         let data_area_blocks = data_total_blocks - data_bitmap_blocks;
         let data_bitmap = Bitmap::new(
@@ -41,16 +43,18 @@ impl EasyFileSystem {
             data_bitmap,
             inode_area_start_block: 1 + inode_bitmap_blocks,
             data_area_start_block: 1 + inode_total_blocks + data_bitmap_blocks,
+            journal: Journal::new(total_blocks - JOURNAL_BLOCKS),
         };
-        // clear all blocks
+        // clear all blocks, writing them out at once to keep the cache clean
         for i in 0..total_blocks {
-            get_block_cache(i as usize, Arc::clone(&block_device))
-                .lock()
-                .modify(0, |data_block: &mut DataBlock| {
-                    for byte in data_block.iter_mut() {
-                        *byte = 0;
-                    }
-                });
+            let block_cache = get_block_cache(i as usize, Arc::clone(&block_device));
+            let mut block_cache = block_cache.lock();
+            block_cache.modify(0, |data_block: &mut DataBlock| {
+                for byte in data_block.iter_mut() {
+                    *byte = 0;
+                }
+            });
+            block_cache.sync();
         }
         // initialize SuperBlock
         get_block_cache(0, Arc::clone(&block_device)).lock().modify(
@@ -62,6 +66,7 @@ impl EasyFileSystem {
                     inode_area_blocks,
                     data_bitmap_blocks,
                     data_area_blocks,
+                    JOURNAL_BLOCKS,
                 );
             },
         );
@@ -74,15 +79,19 @@ impl EasyFileSystem {
             .modify(root_inode_offset, |disk_inode: &mut DiskInode| {
                 disk_inode.initialize(DiskInodeType::Directory);
             });
+        let efs = Arc::new(Mutex::new(efs));
+        // the parent of "/" is itself
+        Self::root_inode(&efs).init_dir(0, &mut efs.lock());
         block_cache_sync_all();
-        Arc::new(Mutex::new(efs))
+        efs
     }
 
+    /// Open the file system, finishing an operation cut short by a crash.
     pub fn open(block_device: Arc<dyn BlockDevice>) -> Arc<Mutex<Self>> {
         // read SuperBlock
-        get_block_cache(0, Arc::clone(&block_device))
-            .lock()
-            .read(0, |super_block: &SuperBlock| {
+        let efs = get_block_cache(0, Arc::clone(&block_device)).lock().read(
+            0,
+            |super_block: &SuperBlock| {
                 assert!(super_block.is_valid(), "Error loading EFS!");
                 let inode_total_blocks =
                     super_block.inode_bitmap_blocks + super_block.inode_area_blocks;
@@ -95,9 +104,22 @@ impl EasyFileSystem {
                     ),
                     inode_area_start_block: 1 + super_block.inode_bitmap_blocks,
                     data_area_start_block: 1 + inode_total_blocks + super_block.data_bitmap_blocks,
+                    journal: Journal::new(super_block.total_blocks - super_block.journal_blocks),
                 };
                 Arc::new(Mutex::new(efs))
-            })
+            },
+        );
+        {
+            let efs = efs.lock();
+            efs.journal.replay(&efs.block_device);
+        }
+        efs
+    }
+
+    /// Make the changes of the operation so far reach the disk at once or
+    /// not at all. Every operation that changes the disk ends with this.
+    pub fn commit(&self) {
+        self.journal.commit(&self.block_device);
     }
 
     pub fn root_inode(efs: &Arc<Mutex<Self>>) -> Inode {
@@ -118,6 +140,14 @@ impl EasyFileSystem {
         )
     }
 
+    /// Inverse of `get_disk_inode_pos`.
+    pub fn get_inode_id(&self, block_id: u32, block_offset: usize) -> u32 {
+        let inode_size = core::mem::size_of::<DiskInode>();
+        let inodes_per_block = (BLOCK_SZ / inode_size) as u32;
+        (block_id - self.inode_area_start_block) * inodes_per_block
+            + (block_offset / inode_size) as u32
+    }
+
     pub fn get_data_block_id(&self, data_block_id: u32) -> u32 {
         self.data_area_start_block + data_block_id
     }
@@ -126,12 +156,15 @@ impl EasyFileSystem {
         self.inode_bitmap.alloc(&self.block_device).unwrap() as u32
     }
 
-    /// Return a block ID not ID in the data area.
-    pub fn alloc_data(&mut self) -> u32 {
-        self.data_bitmap.alloc(&self.block_device).unwrap() as u32 + self.data_area_start_block
+    pub fn dealloc_inode(&mut self, inode_id: u32) {
+        self.inode_bitmap
+            .dealloc(&self.block_device, inode_id as usize)
     }
 
-    pub fn dealloc_data(&mut self, block_id: u32) {
+    /// Return a block ID not ID in the data area.
+    pub fn alloc_data(&mut self) -> u32 {
+        let block_id =
+            self.data_bitmap.alloc(&self.block_device).unwrap() as u32 + self.data_area_start_block;
         get_block_cache(block_id as usize, Arc::clone(&self.block_device))
             .lock()
             .modify(0, |data_block: &mut DataBlock| {
@@ -139,6 +172,10 @@ impl EasyFileSystem {
                     *p = 0;
                 })
             });
+        block_id
+    }
+
+    pub fn dealloc_data(&mut self, block_id: u32) {
         self.data_bitmap.dealloc(
             &self.block_device,
             (block_id - self.data_area_start_block) as usize,
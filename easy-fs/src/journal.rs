@@ -0,0 +1,101 @@
+//! Write-ahead log at the end of the file system. The blocks changed by
+//! one operation are copied into the log, and only once a header naming
+//! them is on disk are they written to their homes. A header still found
+//! on mount belongs to an operation cut short, which is then replayed.
+
+use super::{dirty_block_caches, get_block_cache, BlockDevice, BLOCK_SZ};
+use alloc::sync::Arc;
+
+const JOURNAL_MAGIC: u32 = 0x6a6f726e;
+/// Most blocks one operation may change.
+const JOURNAL_CAPACITY: usize = 32;
+/// The header and the logged blocks.
+pub const JOURNAL_BLOCKS: u32 = 1 + JOURNAL_CAPACITY as u32;
+
+type DataBlock = [u8; BLOCK_SZ];
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct JournalHeader {
+    magic: u32,
+    /// number of logged blocks, 0 once they all reached their homes
+    count: u32,
+    /// home of each logged block
+    blocks: [u32; JOURNAL_CAPACITY],
+}
+
+pub struct Journal {
+    start_block: usize,
+}
+
+impl Journal {
+    pub fn new(start_block: u32) -> Self {
+        Self {
+            start_block: start_block as usize,
+        }
+    }
+
+    fn read_header(&self, block_device: &Arc<dyn BlockDevice>) -> JournalHeader {
+        let mut buf = [0u8; BLOCK_SZ];
+        block_device.read_block(self.start_block, &mut buf);
+        unsafe { (buf.as_ptr() as *const JournalHeader).read_unaligned() }
+    }
+
+    fn write_header(&self, header: &JournalHeader, block_device: &Arc<dyn BlockDevice>) {
+        let mut buf = [0u8; BLOCK_SZ];
+        unsafe { (buf.as_mut_ptr() as *mut JournalHeader).write_unaligned(*header) };
+        block_device.write_block(self.start_block, &buf);
+    }
+
+    /// Write every dirty cached block of `block_device` back through the log.
+    pub fn commit(&self, block_device: &Arc<dyn BlockDevice>) {
+        let dirty = dirty_block_caches(block_device);
+        if dirty.is_empty() {
+            return;
+        }
+        assert!(
+            dirty.len() <= JOURNAL_CAPACITY,
+            "Transaction too large for the journal!"
+        );
+        let mut header = JournalHeader {
+            magic: JOURNAL_MAGIC,
+            count: 0,
+            blocks: [0; JOURNAL_CAPACITY],
+        };
+        for (i, (block_id, block_cache)) in dirty.iter().enumerate() {
+            block_cache.lock().read(0, |data_block: &DataBlock| {
+                block_device.write_block(self.start_block + 1 + i, data_block);
+            });
+            header.blocks[i] = *block_id as u32;
+        }
+        // the operation survives a crash from here on
+        header.count = dirty.len() as u32;
+        self.write_header(&header, block_device);
+        for (_, block_cache) in dirty.iter() {
+            block_cache.lock().sync();
+        }
+        header.count = 0;
+        self.write_header(&header, block_device);
+    }
+
+    /// Finish writing the blocks of an operation committed before a crash.
+    pub fn replay(&self, block_device: &Arc<dyn BlockDevice>) {
+        let mut header = self.read_header(block_device);
+        if header.magic != JOURNAL_MAGIC || header.count == 0 {
+            return;
+        }
+        let mut buf = [0u8; BLOCK_SZ];
+        for (i, block_id) in header.blocks[..header.count as usize].iter().enumerate() {
+            block_device.read_block(self.start_block + 1 + i, &mut buf);
+            // through the cache, which may hold the stale block
+            let block_cache = get_block_cache(*block_id as usize, Arc::clone(block_device));
+            let mut block_cache = block_cache.lock();
+            block_cache.modify(0, |data_block: &mut DataBlock| {
+                data_block.copy_from_slice(&buf);
+            });
+            block_cache.sync();
+        }
+        header.count = 0;
+        self.write_header(&header, block_device);
+    }
+}
@@ -6,13 +6,17 @@ mod bitmap;
 mod block_cache;
 mod block_dev;
 mod efs;
+mod journal;
 mod layout;
 mod vfs;
 
 pub const BLOCK_SZ: usize = 512;
 use bitmap::Bitmap;
-use block_cache::{block_cache_sync_all, get_block_cache};
+use block_cache::dirty_block_caches;
+pub use block_cache::{block_cache_stats, block_cache_sync_all, get_block_cache, BlockCache};
 pub use block_dev::BlockDevice;
 pub use efs::EasyFileSystem;
+use journal::Journal;
+pub use journal::JOURNAL_BLOCKS;
 use layout::*;
 pub use vfs::Inode;
@@ -1,12 +1,16 @@
 use super::{
-    block_cache_sync_all, get_block_cache, BlockDevice, DirEntry, DiskInode, DiskInodeType,
-    EasyFileSystem, DIRENT_SZ,
+    get_block_cache, BlockDevice, DirEntry, DiskInode, DiskInodeType, EasyFileSystem, BLOCK_SZ,
+    DIRENT_SZ, NAME_LENGTH_LIMIT,
 };
 use alloc::string::String;
 use alloc::sync::Arc;
 use alloc::vec::Vec;
 use spin::{Mutex, MutexGuard};
 
+/// Bytes written or grown by one operation, few enough blocks with the
+/// bitmap and indirect blocks involved to fit in the journal.
+const WRITE_CHUNK_SZ: usize = 8 * BLOCK_SZ;
+
 pub struct Inode {
     block_id: usize,
     block_offset: usize,
@@ -43,8 +47,9 @@ impl Inode {
     }
 
     fn find_inode_id(&self, name: &str, disk_inode: &DiskInode) -> Option<u32> {
-        // assert it is a directory
-        assert!(disk_inode.is_dir());
+        if !disk_inode.is_dir() {
+            return None;
+        }
         let file_count = (disk_inode.size as usize) / DIRENT_SZ;
         let mut dirent = DirEntry::empty();
         for i in 0..file_count {
@@ -74,6 +79,20 @@ impl Inode {
         })
     }
 
+    /// Look up a `/`-separated path relative to this directory.
+    pub fn find_path(self: &Arc<Self>, path: &str) -> Option<Arc<Inode>> {
+        let mut inode = Arc::clone(self);
+        for name in path.split('/').filter(|name| !name.is_empty()) {
+            inode = inode.find(name)?;
+        }
+        Some(inode)
+    }
+
+    pub fn is_dir(&self) -> bool {
+        let _fs = self.fs.lock();
+        self.read_disk_inode(|disk_inode| disk_inode.is_dir())
+    }
+
     fn increase_size(
         &self,
         new_size: u32,
@@ -91,54 +110,173 @@ impl Inode {
         disk_inode.increase_size(new_size, v, &self.block_device);
     }
 
-    pub fn create(&self, name: &str) -> Option<Arc<Inode>> {
+    /// Add an entry to the directory `dir`, which is this inode, reusing
+    /// the slot of a removed entry if there is one.
+    fn append_dirent(
+        &self,
+        dir: &mut DiskInode,
+        name: &str,
+        inode_id: u32,
+        fs: &mut MutexGuard<EasyFileSystem>,
+    ) {
+        let file_count = (dir.size as usize) / DIRENT_SZ;
+        let mut dirent = DirEntry::empty();
+        let slot = (0..file_count).find(|i| {
+            dir.read_at(i * DIRENT_SZ, dirent.as_bytes_mut(), &self.block_device);
+            dirent.is_empty()
+        });
+        let slot = slot.unwrap_or_else(|| {
+            let new_size = (file_count + 1) * DIRENT_SZ;
+            self.increase_size(new_size as u32, dir, fs);
+            file_count
+        });
+        let dirent = DirEntry::new(name, inode_id);
+        dir.write_at(slot * DIRENT_SZ, dirent.as_bytes(), &self.block_device);
+    }
+
+    /// Add the `.` and `..` entries to this freshly made directory.
+    pub(crate) fn init_dir(&self, parent_id: u32, fs: &mut MutexGuard<EasyFileSystem>) {
+        let self_id = fs.get_inode_id(self.block_id as u32, self.block_offset);
+        self.modify_disk_inode(|dir| {
+            self.append_dirent(dir, ".", self_id, fs);
+            self.append_dirent(dir, "..", parent_id, fs);
+        });
+    }
+
+    fn create_inode(&self, name: &str, type_: DiskInodeType) -> Option<Arc<Inode>> {
         let mut fs = self.fs.lock();
-        let op = |root_inode: &mut DiskInode| {
-            // assert it is a directory
-            assert!(root_inode.is_dir());
-            // has the file been created?
-            self.find_inode_id(name, root_inode)
+        if name.is_empty() || name.len() > NAME_LENGTH_LIMIT || name.contains('/') {
+            return None;
+        }
+        let op = |dir: &mut DiskInode| {
+            // only directories hold entries
+            dir.is_dir() && self.find_inode_id(name, dir).is_none()
         };
-        if self.modify_disk_inode(op).is_some() {
+        if !self.modify_disk_inode(op) {
             return None;
         }
-        // create a new file
         // alloc a inode with an indirect block
         let new_inode_id = fs.alloc_inode();
-        // initialize inode
-        let (new_inode_block_id, new_inode_block_offset) = fs.get_disk_inode_pos(new_inode_id);
-        get_block_cache(new_inode_block_id as usize, Arc::clone(&self.block_device))
-            .lock()
-            .modify(new_inode_block_offset, |new_inode: &mut DiskInode| {
-                new_inode.initialize(DiskInodeType::File);
-            });
-        self.modify_disk_inode(|root_inode| {
-            // append file in the dirent
-            let file_count = (root_inode.size as usize) / DIRENT_SZ;
-            let new_size = (file_count + 1) * DIRENT_SZ;
-            // increase size
-            self.increase_size(new_size as u32, root_inode, &mut fs);
-            // write dirent
-            let dirent = DirEntry::new(name, new_inode_id);
-            root_inode.write_at(
-                file_count * DIRENT_SZ,
-                dirent.as_bytes(),
-                &self.block_device,
-            );
-        });
-
         let (block_id, block_offset) = fs.get_disk_inode_pos(new_inode_id);
-        block_cache_sync_all();
-        // return inode
-        Some(Arc::new(Self::new(
+        let new_inode = Self::new(
             block_id,
             block_offset,
             self.fs.clone(),
             self.block_device.clone(),
-        )))
+        );
+        new_inode.modify_disk_inode(|disk_inode| disk_inode.initialize(type_));
+        if type_ == DiskInodeType::Directory {
+            let self_id = fs.get_inode_id(self.block_id as u32, self.block_offset);
+            new_inode.init_dir(self_id, &mut fs);
+        }
+        self.modify_disk_inode(|dir| {
+            self.append_dirent(dir, name, new_inode_id, &mut fs);
+            // the ".." of a new directory refers to this one
+            if type_ == DiskInodeType::Directory {
+                dir.nlink += 1;
+            }
+        });
+        fs.commit();
+        Some(Arc::new(new_inode))
         // release efs lock automatically by compiler
     }
 
+    /// Create a regular file in this directory.
+    pub fn create(&self, name: &str) -> Option<Arc<Inode>> {
+        self.create_inode(name, DiskInodeType::File)
+    }
+
+    /// Create a directory holding `.` and `..` in this directory.
+    pub fn create_dir(&self, name: &str) -> Option<Arc<Inode>> {
+        self.create_inode(name, DiskInodeType::Directory)
+    }
+
+    /// Add an entry `name` in this directory for the file `target`.
+    /// Directories cannot be linked to.
+    pub fn link(&self, name: &str, target: &Inode) -> bool {
+        // names cannot cross file systems
+        if !Arc::ptr_eq(&self.fs, &target.fs) {
+            return false;
+        }
+        let mut fs = self.fs.lock();
+        if name.is_empty() || name.len() > NAME_LENGTH_LIMIT || name.contains('/') {
+            return false;
+        }
+        let op = |dir: &mut DiskInode| dir.is_dir() && self.find_inode_id(name, dir).is_none();
+        if !self.modify_disk_inode(op) || target.read_disk_inode(|inode| inode.is_dir()) {
+            return false;
+        }
+        let target_id = fs.get_inode_id(target.block_id as u32, target.block_offset);
+        self.modify_disk_inode(|dir| self.append_dirent(dir, name, target_id, &mut fs));
+        target.modify_disk_inode(|inode| inode.nlink += 1);
+        fs.commit();
+        true
+    }
+
+    /// Remove the entry `name` of a file from this directory, freeing the
+    /// file once no entry refers to it, even if it is still open.
+    pub fn unlink(&self, name: &str) -> bool {
+        let mut fs = self.fs.lock();
+        let found = self.read_disk_inode(|dir| {
+            if !dir.is_dir() {
+                return None;
+            }
+            let file_count = (dir.size as usize) / DIRENT_SZ;
+            let mut dirent = DirEntry::empty();
+            (0..file_count).find_map(|i| {
+                dir.read_at(i * DIRENT_SZ, dirent.as_bytes_mut(), &self.block_device);
+                (dirent.name() == name).then(|| (i, dirent.inode_number()))
+            })
+        });
+        let (slot, inode_id) = match found {
+            Some(found) => found,
+            None => return false,
+        };
+        let (block_id, block_offset) = fs.get_disk_inode_pos(inode_id);
+        let inode = Self::new(
+            block_id,
+            block_offset,
+            self.fs.clone(),
+            self.block_device.clone(),
+        );
+        if inode.read_disk_inode(|disk_inode| disk_inode.is_dir()) {
+            return false;
+        }
+        self.modify_disk_inode(|dir| {
+            dir.write_at(
+                slot * DIRENT_SZ,
+                DirEntry::empty().as_bytes(),
+                &self.block_device,
+            );
+        });
+        let nlink = inode.modify_disk_inode(|disk_inode| {
+            disk_inode.nlink -= 1;
+            disk_inode.nlink
+        });
+        if nlink == 0 {
+            inode.clear_locked(&mut fs);
+            fs.dealloc_inode(inode_id);
+        }
+        fs.commit();
+        true
+    }
+
+    /// Inode number, which is the same for all links to a file.
+    pub fn inode_id(&self) -> u32 {
+        let fs = self.fs.lock();
+        fs.get_inode_id(self.block_id as u32, self.block_offset)
+    }
+
+    pub fn nlink(&self) -> u32 {
+        let _fs = self.fs.lock();
+        self.read_disk_inode(|disk_inode| disk_inode.nlink)
+    }
+
+    pub fn size(&self) -> u32 {
+        let _fs = self.fs.lock();
+        self.read_disk_inode(|disk_inode| disk_inode.size)
+    }
+
     pub fn ls(&self) -> Vec<String> {
         let _fs = self.fs.lock();
         self.read_disk_inode(|disk_inode| {
@@ -150,7 +288,9 @@ impl Inode {
                     disk_inode.read_at(i * DIRENT_SZ, dirent.as_bytes_mut(), &self.block_device,),
                     DIRENT_SZ,
                 );
-                v.push(String::from(dirent.name()));
+                if !dirent.is_empty() {
+                    v.push(String::from(dirent.name()));
+                }
             }
             v
         })
@@ -161,18 +301,39 @@ impl Inode {
         self.read_disk_inode(|disk_inode| disk_inode.read_at(offset, buf, &self.block_device))
     }
 
+    /// Large writes take several operations so that each fits in the
+    /// journal, a crash may leave only some of them done.
     pub fn write_at(&self, offset: usize, buf: &[u8]) -> usize {
         let mut fs = self.fs.lock();
-        let size = self.modify_disk_inode(|disk_inode| {
-            self.increase_size((offset + buf.len()) as u32, disk_inode, &mut fs);
-            disk_inode.write_at(offset, buf, &self.block_device)
-        });
-        block_cache_sync_all();
+        let end = offset + buf.len();
+        loop {
+            let grown = self.modify_disk_inode(|disk_inode| {
+                let new_size = end.min(disk_inode.size as usize + WRITE_CHUNK_SZ);
+                self.increase_size(new_size as u32, disk_inode, &mut fs);
+                new_size == end
+            });
+            fs.commit();
+            if grown {
+                break;
+            }
+        }
+        let mut size = 0;
+        for chunk in buf.chunks(WRITE_CHUNK_SZ) {
+            size += self.modify_disk_inode(|disk_inode| {
+                disk_inode.write_at(offset + size, chunk, &self.block_device)
+            });
+            fs.commit();
+        }
         size
     }
 
     pub fn clear(&self) {
         let mut fs = self.fs.lock();
+        self.clear_locked(&mut fs);
+        fs.commit();
+    }
+
+    fn clear_locked(&self, fs: &mut MutexGuard<EasyFileSystem>) {
         self.modify_disk_inode(|disk_inode| {
             let size = disk_inode.size;
             let data_blocks_dealloc = disk_inode.clear_size(&self.block_device);
@@ -181,6 +342,5 @@ impl Inode {
                 fs.dealloc_data(data_block);
             }
         });
-        block_cache_sync_all();
     }
 }
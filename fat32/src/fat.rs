@@ -0,0 +1,107 @@
+use super::{
+    fat32_partitions, get_block_cache, BlockDevice, BootSector, Inode, BLOCK_SZ, BOOT_SIGNATURE,
+    BOOT_SIGNATURE_OFFSET, FAT_ENTRY_MASK, FIRST_DATA_CLUSTER,
+};
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+
+pub struct Fat32FileSystem {
+    pub block_device: Arc<dyn BlockDevice>,
+    sectors_per_cluster: u32,
+    fat_start_sector: u32,
+    data_start_sector: u32,
+    root_cluster: u32,
+    cluster_count: u32,
+}
+
+impl Fat32FileSystem {
+    /// Open the FAT32 volume filling the device, or else the first FAT32
+    /// partition in its MBR. Return `None` if there is neither.
+    pub fn open(block_device: Arc<dyn BlockDevice>) -> Option<Arc<Self>> {
+        let start_sector = Self::find_volume(&block_device)?;
+        let fs = get_block_cache(start_sector as usize, Arc::clone(&block_device))
+            .lock()
+            .read(0, |boot_sector: &BootSector| {
+                let fat_start_sector = start_sector + boot_sector.reserved_sectors as u32;
+                let data_start_sector =
+                    fat_start_sector + boot_sector.fat_count as u32 * boot_sector.fat_size_32;
+                let sectors_per_cluster = boot_sector.sectors_per_cluster as u32;
+                let data_sectors =
+                    (start_sector + boot_sector.total_sectors()).checked_sub(data_start_sector)?;
+                Some(Self {
+                    block_device: Arc::clone(&block_device),
+                    sectors_per_cluster,
+                    fat_start_sector,
+                    data_start_sector,
+                    root_cluster: boot_sector.root_cluster,
+                    cluster_count: data_sectors / sectors_per_cluster,
+                })
+            })?;
+        Some(Arc::new(fs))
+    }
+
+    fn find_volume(block_device: &Arc<dyn BlockDevice>) -> Option<u32> {
+        let is_fat32 = |sector: u32| {
+            let block_cache = get_block_cache(sector as usize, Arc::clone(block_device));
+            let block_cache = block_cache.lock();
+            block_cache.read(BOOT_SIGNATURE_OFFSET, |signature: &u16| {
+                *signature == BOOT_SIGNATURE
+            }) && block_cache.read(0, BootSector::is_valid)
+        };
+        if is_fat32(0) {
+            return Some(0);
+        }
+        let partitions = get_block_cache(0, Arc::clone(block_device))
+            .lock()
+            .read(0, fat32_partitions);
+        partitions.into_iter().find(|&start| is_fat32(start))
+    }
+
+    pub fn root_inode(fs: &Arc<Self>) -> Inode {
+        Inode::new(Arc::clone(fs), fs.root_cluster, None)
+    }
+
+    pub fn cluster_size(&self) -> usize {
+        self.sectors_per_cluster as usize * BLOCK_SZ
+    }
+
+    /// The directory entry `..` holds cluster 0 for the root.
+    pub fn dir_cluster(&self, cluster: u32) -> u32 {
+        if cluster == 0 {
+            self.root_cluster
+        } else {
+            cluster
+        }
+    }
+
+    /// Return the sector holding byte `offset` of `cluster`.
+    pub fn sector_of(&self, cluster: u32, offset: usize) -> usize {
+        (self.data_start_sector + (cluster - FIRST_DATA_CLUSTER) * self.sectors_per_cluster)
+            as usize
+            + offset / BLOCK_SZ
+    }
+
+    fn next_cluster(&self, cluster: u32) -> u32 {
+        let offset = cluster as usize * 4;
+        get_block_cache(
+            self.fat_start_sector as usize + offset / BLOCK_SZ,
+            Arc::clone(&self.block_device),
+        )
+        .lock()
+        .read(offset % BLOCK_SZ, |entry: &u32| *entry & FAT_ENTRY_MASK)
+    }
+
+    /// Follow the FAT from `first`, stopping at the end mark or at anything
+    /// that is not a data cluster, and at a loop of a broken FAT.
+    pub fn cluster_chain(&self, first: u32) -> Vec<u32> {
+        let mut chain = Vec::new();
+        let mut cluster = first;
+        while (FIRST_DATA_CLUSTER..FIRST_DATA_CLUSTER + self.cluster_count).contains(&cluster)
+            && chain.len() < self.cluster_count as usize
+        {
+            chain.push(cluster);
+            cluster = self.next_cluster(cluster);
+        }
+        chain
+    }
+}
@@ -0,0 +1,235 @@
+use super::BLOCK_SZ;
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+
+/// Found at the end of both the MBR and the boot sector.
+pub const BOOT_SIGNATURE: u16 = 0xaa55;
+pub const BOOT_SIGNATURE_OFFSET: usize = 510;
+const PARTITION_TABLE_OFFSET: usize = 446;
+const PARTITION_ENTRY_SZ: usize = 16;
+/// Partition types of FAT32 with CHS and LBA addressing.
+const PARTITION_FAT32: [u8; 2] = [0x0b, 0x0c];
+
+/// Entries of the FAT, only the low 28 bits count.
+pub const FAT_ENTRY_MASK: u32 = 0x0fff_ffff;
+/// Data clusters are numbered from 2.
+pub const FIRST_DATA_CLUSTER: u32 = 2;
+
+const ATTR_DIRECTORY: u8 = 0x10;
+const ATTR_VOLUME_ID: u8 = 0x08;
+/// Read-only, hidden, system and volume ID at once mark a long name part.
+const ATTR_LONG_NAME: u8 = 0x0f;
+/// In `nt_res`, set when the base name or the extension is all lower case.
+const NT_LOWER_BASE: u8 = 0x08;
+const NT_LOWER_EXT: u8 = 0x10;
+const ENTRY_END: u8 = 0x00;
+const ENTRY_DELETED: u8 = 0xe5;
+/// Stands for a first name byte that really is 0xe5.
+const ENTRY_KANJI_E5: u8 = 0x05;
+const LONG_NAME_LAST: u8 = 0x40;
+const LONG_NAME_CHARS: usize = 13;
+
+/// Start sectors of the FAT32 partitions in the MBR `sector`.
+pub fn fat32_partitions(sector: &[u8; BLOCK_SZ]) -> Vec<u32> {
+    sector[PARTITION_TABLE_OFFSET..BOOT_SIGNATURE_OFFSET]
+        .chunks(PARTITION_ENTRY_SZ)
+        .filter(|entry| PARTITION_FAT32.contains(&entry[4]))
+        .map(|entry| u32::from_le_bytes([entry[8], entry[9], entry[10], entry[11]]))
+        .filter(|&start| start != 0)
+        .collect()
+}
+
+/// The BIOS parameter block at the start of a FAT32 volume, its fields are
+/// unaligned.
+#[repr(C, packed)]
+pub struct BootSector {
+    _jump_and_oem_name: [u8; 11],
+    bytes_per_sector: u16,
+    pub sectors_per_cluster: u8,
+    pub reserved_sectors: u16,
+    pub fat_count: u8,
+    root_entry_count: u16,
+    total_sectors_16: u16,
+    _media: u8,
+    fat_size_16: u16,
+    _geometry_and_hidden_sectors: [u8; 8],
+    total_sectors_32: u32,
+    pub fat_size_32: u32,
+    _ext_flags_and_version: [u8; 4],
+    pub root_cluster: u32,
+}
+
+impl BootSector {
+    /// FAT12 and FAT16 have a fixed root directory and a 16-bit FAT size
+    /// instead.
+    pub fn is_valid(&self) -> bool {
+        let sectors_per_cluster = self.sectors_per_cluster;
+        self.bytes_per_sector as usize == BLOCK_SZ
+            && sectors_per_cluster.is_power_of_two()
+            && self.fat_count != 0
+            && self.root_entry_count == 0
+            && self.fat_size_16 == 0
+            && self.fat_size_32 != 0
+            && self.root_cluster >= FIRST_DATA_CLUSTER
+    }
+    pub fn total_sectors(&self) -> u32 {
+        if self.total_sectors_16 != 0 {
+            self.total_sectors_16 as u32
+        } else {
+            self.total_sectors_32
+        }
+    }
+}
+
+/// An 8.3 directory entry, the only kind that points to a file.
+#[repr(C)]
+#[derive(Clone)]
+pub struct ShortDirEntry {
+    name: [u8; 11],
+    attr: u8,
+    nt_res: u8,
+    _create_time_tenth: u8,
+    _create_time: u16,
+    _create_date: u16,
+    _access_date: u16,
+    first_cluster_hi: u16,
+    _write_time: u16,
+    _write_date: u16,
+    first_cluster_lo: u16,
+    pub file_size: u32,
+}
+
+pub const DIRENT_SZ: usize = 32;
+
+impl ShortDirEntry {
+    pub fn empty() -> Self {
+        unsafe { core::mem::zeroed() }
+    }
+    pub fn as_bytes_mut(&mut self) -> &mut [u8] {
+        unsafe { core::slice::from_raw_parts_mut(self as *mut _ as usize as *mut u8, DIRENT_SZ) }
+    }
+    pub fn is_end(&self) -> bool {
+        self.name[0] == ENTRY_END
+    }
+    pub fn is_deleted(&self) -> bool {
+        self.name[0] == ENTRY_DELETED
+    }
+    pub fn is_long_name(&self) -> bool {
+        self.attr & ATTR_LONG_NAME == ATTR_LONG_NAME
+    }
+    pub fn is_volume_id(&self) -> bool {
+        self.attr & ATTR_VOLUME_ID != 0
+    }
+    pub fn is_dir(&self) -> bool {
+        self.attr & ATTR_DIRECTORY != 0
+    }
+    pub fn first_cluster(&self) -> u32 {
+        (self.first_cluster_hi as u32) << 16 | self.first_cluster_lo as u32
+    }
+    pub fn as_long_name(&self) -> &LongDirEntry {
+        unsafe { &*(self as *const _ as usize as *const LongDirEntry) }
+    }
+    /// The 8.3 name as `base.ext`, in lower case where Windows asked for it.
+    pub fn name(&self) -> String {
+        let mut raw = self.name;
+        if raw[0] == ENTRY_KANJI_E5 {
+            raw[0] = ENTRY_DELETED;
+        }
+        let part = |bytes: &[u8], lower: bool| -> String {
+            let len = bytes.iter().rposition(|&b| b != b' ').map_or(0, |i| i + 1);
+            bytes[..len]
+                .iter()
+                .map(|&b| {
+                    if lower {
+                        b.to_ascii_lowercase() as char
+                    } else {
+                        b as char
+                    }
+                })
+                .collect()
+        };
+        let mut name = part(&raw[..8], self.nt_res & NT_LOWER_BASE != 0);
+        let ext = part(&raw[8..], self.nt_res & NT_LOWER_EXT != 0);
+        if !ext.is_empty() {
+            name.push('.');
+            name.push_str(&ext);
+        }
+        name
+    }
+    /// Kept in each long name part to tie it to this entry.
+    pub fn checksum(&self) -> u8 {
+        self.name
+            .iter()
+            .fold(0u8, |sum, &b| sum.rotate_right(1).wrapping_add(b))
+    }
+}
+
+/// A part of a long file name, 13 UCS-2 characters stored before the
+/// short entry in reverse order.
+#[repr(C, packed)]
+pub struct LongDirEntry {
+    order: u8,
+    name1: [u16; 5],
+    _attr: u8,
+    _kind: u8,
+    checksum: u8,
+    name2: [u16; 6],
+    _first_cluster_lo: u16,
+    name3: [u16; 2],
+}
+
+/// Gathers the parts of a long file name from the directory entries.
+#[derive(Default)]
+pub struct LongName {
+    chars: Vec<u16>,
+    checksum: u8,
+    /// order of the next part expected, 0 when complete
+    next_order: u8,
+    valid: bool,
+}
+
+impl LongName {
+    pub fn push(&mut self, entry: &LongDirEntry) {
+        let order = entry.order & !LONG_NAME_LAST;
+        if entry.order & LONG_NAME_LAST != 0 {
+            self.chars = vec![0xffff; order as usize * LONG_NAME_CHARS];
+            self.checksum = entry.checksum;
+            self.next_order = order;
+            self.valid = order != 0;
+        }
+        if !self.valid || order != self.next_order || entry.checksum != self.checksum {
+            self.valid = false;
+            return;
+        }
+        let (name1, name2, name3) = (entry.name1, entry.name2, entry.name3);
+        let start = (order as usize - 1) * LONG_NAME_CHARS;
+        for (i, c) in name1
+            .iter()
+            .chain(name2.iter())
+            .chain(name3.iter())
+            .enumerate()
+        {
+            self.chars[start + i] = *c;
+        }
+        self.next_order -= 1;
+    }
+    /// The long name of `entry` if all its parts came before it.
+    pub fn take(&mut self, entry: &ShortDirEntry) -> Option<String> {
+        let valid = self.valid && self.next_order == 0 && self.checksum == entry.checksum();
+        self.valid = false;
+        if !valid {
+            return None;
+        }
+        let chars = self.chars.iter().cloned().take_while(|&c| c != 0);
+        Some(
+            char::decode_utf16(chars)
+                .map(|c| c.unwrap_or(char::REPLACEMENT_CHARACTER))
+                .collect(),
+        )
+    }
+    /// A deleted or volume ID entry ends any long name.
+    pub fn reset(&mut self) {
+        self.valid = false;
+    }
+}
@@ -0,0 +1,14 @@
+//! Read-only FAT32, enough to load programs from the SD-card images real
+//! boards boot from. Blocks go through the easy-fs block cache.
+#![no_std]
+
+extern crate alloc;
+
+mod fat;
+mod layout;
+mod vfs;
+
+use easy_fs::{get_block_cache, BlockDevice, BLOCK_SZ};
+pub use fat::Fat32FileSystem;
+use layout::*;
+pub use vfs::Inode;
@@ -0,0 +1,119 @@
+use super::{get_block_cache, Fat32FileSystem, LongName, ShortDirEntry, BLOCK_SZ, DIRENT_SZ};
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+
+type DataBlock = [u8; BLOCK_SZ];
+
+/// A file or directory, with its cluster chain walked once when found.
+pub struct Inode {
+    fs: Arc<Fat32FileSystem>,
+    first_cluster: u32,
+    clusters: Vec<u32>,
+    size: usize,
+    is_dir: bool,
+}
+
+impl Inode {
+    /// `size` is the file size, or `None` for a directory, which takes all
+    /// of its clusters.
+    pub fn new(fs: Arc<Fat32FileSystem>, first_cluster: u32, size: Option<u32>) -> Self {
+        let clusters = fs.cluster_chain(first_cluster);
+        let chain_size = clusters.len() * fs.cluster_size();
+        Self {
+            fs,
+            first_cluster,
+            clusters,
+            size: size.map_or(chain_size, |size| chain_size.min(size as usize)),
+            is_dir: size.is_none(),
+        }
+    }
+
+    fn dirent_inode(&self, dirent: &ShortDirEntry) -> Inode {
+        if dirent.is_dir() {
+            let first_cluster = self.fs.dir_cluster(dirent.first_cluster());
+            Self::new(Arc::clone(&self.fs), first_cluster, None)
+        } else {
+            let size = Some(dirent.file_size);
+            Self::new(Arc::clone(&self.fs), dirent.first_cluster(), size)
+        }
+    }
+
+    /// Return (name, entry) of each file in this directory, under the long
+    /// name if it has one.
+    fn dirents(&self) -> Vec<(String, ShortDirEntry)> {
+        let mut v = Vec::new();
+        if !self.is_dir {
+            return v;
+        }
+        let mut long_name = LongName::default();
+        let mut dirent = ShortDirEntry::empty();
+        for i in 0..self.size / DIRENT_SZ {
+            assert_eq!(
+                self.read_at(i * DIRENT_SZ, dirent.as_bytes_mut()),
+                DIRENT_SZ
+            );
+            if dirent.is_end() {
+                break;
+            }
+            if dirent.is_deleted() {
+                long_name.reset();
+            } else if dirent.is_long_name() {
+                long_name.push(dirent.as_long_name());
+            } else if dirent.is_volume_id() {
+                long_name.reset();
+            } else {
+                let name = long_name.take(&dirent).unwrap_or_else(|| dirent.name());
+                v.push((name, dirent.clone()));
+            }
+        }
+        v
+    }
+
+    /// Names are matched ignoring ASCII case, as FAT does.
+    pub fn find(&self, name: &str) -> Option<Arc<Inode>> {
+        self.dirents()
+            .into_iter()
+            .find(|(dirent_name, _)| dirent_name.eq_ignore_ascii_case(name))
+            .map(|(_, dirent)| Arc::new(self.dirent_inode(&dirent)))
+    }
+
+    pub fn ls(&self) -> Vec<String> {
+        self.dirents().into_iter().map(|(name, _)| name).collect()
+    }
+
+    pub fn is_dir(&self) -> bool {
+        self.is_dir
+    }
+
+    /// The first cluster tells files apart, empty files have none.
+    pub fn first_cluster(&self) -> u32 {
+        self.first_cluster
+    }
+
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    pub fn read_at(&self, offset: usize, buf: &mut [u8]) -> usize {
+        let cluster_size = self.fs.cluster_size();
+        let mut start = offset;
+        let end = (offset + buf.len()).min(self.size);
+        let mut read_size = 0usize;
+        while start < end {
+            let end_current_block = ((start / BLOCK_SZ + 1) * BLOCK_SZ).min(end);
+            let block_read_size = end_current_block - start;
+            let cluster = self.clusters[start / cluster_size];
+            let block_id = self.fs.sector_of(cluster, start % cluster_size);
+            get_block_cache(block_id, Arc::clone(&self.fs.block_device))
+                .lock()
+                .read(0, |data_block: &DataBlock| {
+                    let src = &data_block[start % BLOCK_SZ..start % BLOCK_SZ + block_read_size];
+                    buf[read_size..read_size + block_read_size].copy_from_slice(src);
+                });
+            read_size += block_read_size;
+            start = end_current_block;
+        }
+        read_size
+    }
+}
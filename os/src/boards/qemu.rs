@@ -1,40 +1,88 @@
 pub const CLOCK_FREQ: usize = 12500000;
 pub const MEMORY_END: usize = 0x8800_0000;
 
-pub const MMIO: &[(usize, usize)] = &[
-    (0x0010_0000, 0x00_2000), // VIRT_TEST/RTC  in virt machine
-    (0x2000000, 0x10000),     // core local interrupter (CLINT)
-    (0xc000000, 0x210000),    // VIRT_PLIC in virt machine
-    (0x10000000, 0x9000),     // VIRT_UART0 with GPU  in virt machine
-];
-
 pub type BlockDeviceImpl = crate::drivers::block::VirtIOBlock;
-pub type CharDeviceImpl = crate::drivers::chardev::NS16550a<VIRT_UART>;
+pub type CharDeviceImpl = crate::drivers::chardev::NS16550a;
 
-pub const VIRT_PLIC: usize = 0xC00_0000;
-pub const VIRT_UART: usize = 0x1000_0000;
+// the virt machine layout, used when there is no device tree
+const VIRT_RTC: usize = 0x10_1000;
+const VIRT_PLIC: usize = 0xC00_0000;
+const VIRT_UART: usize = 0x1000_0000;
+const UART_IRQ: usize = 10;
+// virtio-mmio transports, irq 1 onwards
+const VIRT_VIRTIO: usize = 0x1000_1000;
+const VIRTIO_SLOTS: usize = 8;
 #[allow(unused)]
 pub const VIRTGPU_XRES: u32 = 1280;
 #[allow(unused)]
 pub const VIRTGPU_YRES: u32 = 800;
 
 use crate::drivers::block::BLOCK_DEVICE;
-use crate::drivers::chardev::{CharDevice, UART};
+use crate::drivers::bus::{device, DeviceKind};
+use crate::drivers::chardev::TTY;
 use crate::drivers::plic::{IntrTargetPriority, PLIC};
+use crate::drivers::rng::add_interrupt_randomness;
+use crate::drivers::virtio::{virtio_device, VIRTIO_BLOCK, VIRTIO_INPUT, VIRTIO_NET};
 use crate::drivers::{KEYBOARD_DEVICE, MOUSE_DEVICE};
+use crate::dtb::DeviceNode;
+use crate::net::net_interrupt_handler;
+use crate::task::hart_id;
+use alloc::vec;
+use alloc::vec::Vec;
+use lazy_static::*;
+
+/// The devices of the virt machine, by address.
+pub fn fallback_devices() -> Vec<DeviceNode> {
+    let node = |compatible: &str, reg, irq| DeviceNode {
+        compatible: vec![compatible.into()],
+        reg,
+        irq,
+    };
+    let mut devices = vec![
+        node("google,goldfish-rtc", (VIRT_RTC, 0x1000), None),
+        node("riscv,plic0", (VIRT_PLIC, 0x21_0000), None),
+        node("ns16550a", (VIRT_UART, 0x100), Some(UART_IRQ)),
+    ];
+    devices.extend((0..VIRTIO_SLOTS).map(|slot| {
+        let base = VIRT_VIRTIO + slot * 0x1000;
+        node("virtio,mmio", (base, 0x1000), Some(slot + 1))
+    }));
+    devices
+}
+
+lazy_static! {
+    static ref PLIC_BASE: usize = device(DeviceKind::Plic, 0).expect("no plic").reg.0;
+    /// irq nums of the keyboard, mouse, block device, uart and network
+    /// device, as probed
+    static ref IRQS: [usize; 5] = [
+        virtio_device(VIRTIO_INPUT, 0).and_then(|node| node.irq).unwrap_or(0),
+        virtio_device(VIRTIO_INPUT, 1).and_then(|node| node.irq).unwrap_or(0),
+        virtio_device(VIRTIO_BLOCK, 0).and_then(|node| node.irq).unwrap_or(0),
+        device(DeviceKind::Uart, 0).and_then(|node| node.irq).unwrap_or(0),
+        virtio_device(VIRTIO_NET, 0).and_then(|node| node.irq).unwrap_or(0),
+    ];
+}
 
 pub fn device_init() {
+    let mut plic = unsafe { PLIC::new(*PLIC_BASE) };
+    for &intr_src_id in IRQS.iter().filter(|&&irq| irq != 0) {
+        plic.set_priority(intr_src_id, 1);
+    }
+    hart_irq_init();
+}
+
+/// Route the device interrupts to this hart as well, whichever hart claims
+/// one first handles it.
+pub fn hart_irq_init() {
     use riscv::register::sie;
-    let mut plic = unsafe { PLIC::new(VIRT_PLIC) };
-    let hart_id: usize = 0;
+    let mut plic = unsafe { PLIC::new(*PLIC_BASE) };
+    let hart_id = hart_id();
     let supervisor = IntrTargetPriority::Supervisor;
     let machine = IntrTargetPriority::Machine;
     plic.set_threshold(hart_id, supervisor, 0);
     plic.set_threshold(hart_id, machine, 1);
-    //irq nums: 5 keyboard, 6 mouse, 8 block, 10 uart
-    for intr_src_id in [5usize, 6, 8, 10] {
+    for &intr_src_id in IRQS.iter().filter(|&&irq| irq != 0) {
         plic.enable(hart_id, supervisor, intr_src_id);
-        plic.set_priority(intr_src_id, 1);
     }
     unsafe {
         sie::set_sext();
@@ -42,14 +90,21 @@ pub fn device_init() {
 }
 
 pub fn irq_handler() {
-    let mut plic = unsafe { PLIC::new(VIRT_PLIC) };
-    let intr_src_id = plic.claim(0, IntrTargetPriority::Supervisor);
-    match intr_src_id {
-        5 => KEYBOARD_DEVICE.handle_irq(),
-        6 => MOUSE_DEVICE.handle_irq(),
-        8 => BLOCK_DEVICE.handle_irq(),
-        10 => UART.handle_irq(),
+    let mut plic = unsafe { PLIC::new(*PLIC_BASE) };
+    let hart_id = hart_id();
+    let intr_src_id = plic.claim(hart_id, IntrTargetPriority::Supervisor);
+    // claimed by another hart first
+    if intr_src_id == 0 {
+        return;
+    }
+    add_interrupt_randomness(intr_src_id);
+    match IRQS.iter().position(|&irq| irq == intr_src_id as usize) {
+        Some(0) => KEYBOARD_DEVICE.handle_irq(),
+        Some(1) => MOUSE_DEVICE.handle_irq(),
+        Some(2) => BLOCK_DEVICE.handle_irq(),
+        Some(3) => TTY.handle_irq(),
+        Some(4) => net_interrupt_handler(),
         _ => panic!("unsupported IRQ {}", intr_src_id),
     }
-    plic.complete(0, IntrTargetPriority::Supervisor, intr_src_id);
+    plic.complete(hart_id, IntrTargetPriority::Supervisor, intr_src_id);
 }
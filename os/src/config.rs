@@ -1,12 +1,81 @@
 #[allow(unused)]
 
 pub const USER_STACK_SIZE: usize = 4096 * 2;
+// room each user stack may grow down into from its initial USER_STACK_SIZE
+pub const USER_STACK_MAX: usize = 4096 * 16;
 pub const KERNEL_STACK_SIZE: usize = 4096 * 2;
 pub const KERNEL_HEAP_SIZE: usize = 0x100_0000;
+// keep in sync with entry.asm
+pub const BOOT_STACK_SIZE: usize = 4096 * 16;
+// bytes of kernel log kept for sys_syslog and /proc/kmsg
+pub const KMSG_SIZE: usize = 0x4000;
+// room for the symbol table patched in after linking, keep in sync with the Makefile
+pub const KSYMS_SIZE: usize = 0x8_0000;
+pub const MAX_HARTS: usize = 8;
+// how long a task runs before the timer preempts it
+pub const TIME_SLICE_MS: usize = 10;
+// share of each hart deadline tasks may reserve, the rest is left to the others
+pub const DEADLINE_BANDWIDTH_PERCENT: usize = 95;
+// tasks a hart picks from its own queue between looks at the others
+pub const LOAD_BALANCE_INTERVAL: usize = 16;
 pub const PAGE_SIZE: usize = 0x1000;
 pub const PAGE_SIZE_BITS: usize = 0xc;
+// sv39 level-1 leaves, used where fixed mappings are large enough
+pub const HUGE_PAGE_SIZE: usize = 0x20_0000;
 
 pub const TRAMPOLINE: usize = usize::MAX - PAGE_SIZE + 1;
 pub const TRAP_CONTEXT_BASE: usize = TRAMPOLINE - PAGE_SIZE;
+// user mappings live in the lower half of the sv39 address space
+pub const USER_SPACE_END: usize = 1 << 38;
 
-pub use crate::board::{CLOCK_FREQ, MEMORY_END, MMIO};
+// fault executable ELF pages in from the file instead of copying them at exec
+pub const ELF_DEMAND_PAGING: bool = true;
+
+// where user stacks, the program break, kernel chosen mmap areas and
+// position independent programs go is randomized, unless `norandmaps` is
+// on the kernel command line for layouts that repeat from run to run
+
+// user stacks start up to this far above the program
+pub const ASLR_STACK_RANGE: usize = 0x800_0000;
+// the program break starts from BRK_BASE plus up to this, above the user
+// stacks, and the heap may grow up to MMAP_BASE
+pub const BRK_BASE: usize = 1 << 36;
+pub const ASLR_BRK_RANGE: usize = 0x4000_0000;
+// mmap areas the kernel places go from MMAP_BASE plus up to this
+pub const MMAP_BASE: usize = 1 << 37;
+pub const ASLR_MMAP_RANGE: usize = 0x4000_0000;
+// position independent programs are loaded from PIE_BASE plus up to this
+pub const PIE_BASE: usize = 0x4000_0000;
+pub const ASLR_PIE_RANGE: usize = 0x4000_0000;
+
+// swap area right after the 32MiB file system image, see easy-fs-fuse
+pub const SWAP_START_BLOCK: usize = 32 * 2048;
+pub const SWAP_PAGES: usize = 1024;
+// the panic handler leaves a crash record right after the swap area, read
+// it back with `easy-fs-fuse --crash-dump`
+pub const CRASH_DUMP_START_BLOCK: usize = SWAP_START_BLOCK + SWAP_PAGES * PAGE_SIZE / 512;
+pub const CRASH_DUMP_BLOCKS: usize = 128;
+
+// issue fence/fence.i on every user<->kernel crossing as a speculation barrier
+pub const TRAP_BARRIER: bool = false;
+
+// time the code wrapped in statistic_time! and print the probes at shutdown
+pub const PROFILE: bool = false;
+
+// count how long each place that takes a spinlock holds it, printed at
+// shutdown with the profile
+pub const LOCK_STAT: bool = false;
+
+// wait for GDB on the second UART at boot and hand it every kernel ebreak,
+// see debug::gdbstub
+pub const GDBSTUB: bool = false;
+
+// print the kernel page table at boot, once it passed the W^X audit, see
+// mm::debug_dump_kernel_mappings
+pub const DUMP_KERNEL_MAPPINGS: bool = false;
+
+// report a hart that has not been back in the scheduler for this many
+// seconds, see task::watchdog; 0 turns the watchdog off
+pub const WATCHDOG_SECS: usize = 10;
+
+pub use crate::board::CLOCK_FREQ;
@@ -0,0 +1,235 @@
+//! The crash record the panic handler leaves on the block device, after
+//! the swap area, to be read with `easy-fs-fuse --crash-dump` once QEMU is
+//! gone.
+//!
+//! Block 0 is the header, written last so that a record cut short does not
+//! pass for one: the magic, then little endian u64s for the hart, the time
+//! in ns, sepc, scause, stval and sstatus, x0 to x31 where the dump began,
+//! the address the stack bytes start at and the lengths of the stack, log,
+//! task list and message, and then the panic message itself. The stack from
+//! sp up, the log and the task list follow in areas of their own.
+//!
+//! Nothing here allocates or waits for a lock, the hart that panicked may
+//! hold the heap or anything else. Blocks go out through
+//! `write_block_polled`, which only tries the locks of the device queues,
+//! and when one cannot be written the header is left out.
+
+use crate::config::{CRASH_DUMP_BLOCKS, CRASH_DUMP_START_BLOCK, KMSG_SIZE};
+use crate::drivers::block::write_block_polled;
+use crate::logging::kmsg_slices;
+use crate::task::{current_kstack, hart_id, write_task_list};
+use crate::timer::get_time_ns;
+use core::arch::asm;
+use core::fmt::{self, Write};
+use core::panic::PanicInfo;
+use core::sync::atomic::{AtomicBool, Ordering};
+use easy_fs::BLOCK_SZ;
+
+const CRASH_MAGIC: &[u8; 8] = b"rCoreDmp";
+const STACK_BLOCKS: usize = 32;
+const KMSG_BLOCKS: usize = KMSG_SIZE / BLOCK_SZ;
+const TASK_BLOCKS: usize = CRASH_DUMP_BLOCKS - 1 - STACK_BLOCKS - KMSG_BLOCKS;
+/// u64s in the header before the message
+const HEADER_WORDS: usize = 6 + 32 + 5;
+
+/// Set once a dump starts, a panic while writing it must not start another.
+static DUMPING: AtomicBool = AtomicBool::new(false);
+
+/// Bytes going block by block into an area of the dump, dropping what does
+/// not fit.
+struct AreaWriter {
+    block: usize,
+    end: usize,
+    buf: [u8; BLOCK_SZ],
+    fill: usize,
+    len: usize,
+    /// every block written so far made it to the device
+    ok: bool,
+}
+
+impl AreaWriter {
+    fn new(start: usize, blocks: usize) -> Self {
+        Self {
+            block: start,
+            end: start + blocks,
+            buf: [0; BLOCK_SZ],
+            fill: 0,
+            len: 0,
+            ok: true,
+        }
+    }
+    fn write(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            if self.block == self.end {
+                return;
+            }
+            self.buf[self.fill] = byte;
+            self.fill += 1;
+            self.len += 1;
+            if self.fill == BLOCK_SZ {
+                self.flush();
+            }
+        }
+    }
+    fn flush(&mut self) {
+        if self.fill > 0 {
+            self.buf[self.fill..].fill(0);
+            self.ok &= write_block_polled(self.block, &self.buf);
+            self.block += 1;
+            self.fill = 0;
+        }
+    }
+    /// Write out what is left, giving the number of bytes kept, or None
+    /// if a block of the area failed.
+    fn finish(mut self) -> Option<usize> {
+        self.flush();
+        self.ok.then_some(self.len)
+    }
+}
+
+impl Write for AreaWriter {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.write(s.as_bytes());
+        Ok(())
+    }
+}
+
+/// The panic message, cut off at the end of the header block.
+struct MessageWriter<'a> {
+    buf: &'a mut [u8],
+    len: usize,
+}
+
+impl Write for MessageWriter<'_> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let n = s.len().min(self.buf.len() - self.len);
+        self.buf[self.len..self.len + n].copy_from_slice(&s.as_bytes()[..n]);
+        self.len += n;
+        Ok(())
+    }
+}
+
+/// x0 to x31 as they are here.
+#[inline(always)]
+fn save_regs() -> [usize; 32] {
+    let mut x = [0usize; 32];
+    unsafe {
+        asm!(
+            "sd x1, 8({0})",
+            "sd x2, 16({0})",
+            "sd x3, 24({0})",
+            "sd x4, 32({0})",
+            "sd x5, 40({0})",
+            "sd x6, 48({0})",
+            "sd x7, 56({0})",
+            "sd x8, 64({0})",
+            "sd x9, 72({0})",
+            "sd x10, 80({0})",
+            "sd x11, 88({0})",
+            "sd x12, 96({0})",
+            "sd x13, 104({0})",
+            "sd x14, 112({0})",
+            "sd x15, 120({0})",
+            "sd x16, 128({0})",
+            "sd x17, 136({0})",
+            "sd x18, 144({0})",
+            "sd x19, 152({0})",
+            "sd x20, 160({0})",
+            "sd x21, 168({0})",
+            "sd x22, 176({0})",
+            "sd x23, 184({0})",
+            "sd x24, 192({0})",
+            "sd x25, 200({0})",
+            "sd x26, 208({0})",
+            "sd x27, 216({0})",
+            "sd x28, 224({0})",
+            "sd x29, 232({0})",
+            "sd x30, 240({0})",
+            "sd x31, 248({0})",
+            in(reg) x.as_mut_ptr(),
+        );
+    }
+    x
+}
+
+/// sepc, scause, stval and sstatus.
+fn trap_csrs() -> [usize; 4] {
+    let (sepc, scause, stval, sstatus): (usize, usize, usize, usize);
+    unsafe {
+        asm!(
+            "csrr {}, sepc",
+            "csrr {}, scause",
+            "csrr {}, stval",
+            "csrr {}, sstatus",
+            out(reg) sepc,
+            out(reg) scause,
+            out(reg) stval,
+            out(reg) sstatus,
+        );
+    }
+    [sepc, scause, stval, sstatus]
+}
+
+/// Write the crash record for the panic `info`, the other harts already
+/// halted.
+pub fn crash_dump(info: &PanicInfo) {
+    let x = save_regs();
+    if DUMPING.swap(true, Ordering::Relaxed) {
+        return;
+    }
+    let mut area = CRASH_DUMP_START_BLOCK + 1;
+    let sp = x[2];
+    let (bottom, top) = current_kstack();
+    let mut stack = AreaWriter::new(area, STACK_BLOCKS);
+    if (bottom..top).contains(&sp) {
+        let len = (top - sp).min(STACK_BLOCKS * BLOCK_SZ);
+        stack.write(unsafe { core::slice::from_raw_parts(sp as *const u8, len) });
+    }
+    let stack_len = stack.finish();
+    area += STACK_BLOCKS;
+
+    let mut kmsg = AreaWriter::new(area, KMSG_BLOCKS);
+    kmsg_slices(|older, newer| {
+        kmsg.write(older);
+        kmsg.write(newer);
+    });
+    let kmsg_len = kmsg.finish();
+    area += KMSG_BLOCKS;
+
+    let mut tasks = AreaWriter::new(area, TASK_BLOCKS);
+    let _ = write_task_list(&mut tasks);
+    let tasks_len = tasks.finish();
+    let (stack_len, kmsg_len, tasks_len) = match (stack_len, kmsg_len, tasks_len) {
+        (Some(stack_len), Some(kmsg_len), Some(tasks_len)) => (stack_len, kmsg_len, tasks_len),
+        // with an area missing the record must not pass for a whole one
+        _ => return,
+    };
+
+    let mut header = [0u8; BLOCK_SZ];
+    let (words, message) = header[CRASH_MAGIC.len()..].split_at_mut(HEADER_WORDS * 8);
+    let mut message = MessageWriter {
+        buf: message,
+        len: 0,
+    };
+    let _ = match info.location() {
+        Some(location) => write!(
+            message,
+            "{}:{} {}",
+            location.file(),
+            location.line(),
+            info.message().unwrap()
+        ),
+        None => write!(message, "{}", info.message().unwrap()),
+    };
+    let message_len = message.len;
+    let fields = [hart_id(), get_time_ns()]
+        .into_iter()
+        .chain(trap_csrs())
+        .chain(x)
+        .chain([sp, stack_len, kmsg_len, tasks_len, message_len]);
+    for (word, value) in words.chunks_exact_mut(8).zip(fields) {
+        word.copy_from_slice(&(value as u64).to_le_bytes());
+    }
+    header[..CRASH_MAGIC.len()].copy_from_slice(CRASH_MAGIC);
+    write_block_polled(CRASH_DUMP_START_BLOCK, &header);
+}
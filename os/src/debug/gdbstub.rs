@@ -0,0 +1,405 @@
+//! A stub speaking the GDB remote serial protocol on the second UART, so
+//! that the kernel can be debugged from GDB without QEMU's own stub. With
+//! `GDBSTUB` set the boot hart waits for GDB before going on, and every
+//! ebreak the kernel runs into afterwards, planted by GDB or compiled in,
+//! hands the hart over to GDB until it continues. One hart talks to GDB at
+//! a time, the others run on or wait at a breakpoint for their turn.
+
+use crate::config::{GDBSTUB, PAGE_SIZE};
+use crate::drivers::bus::{device, DeviceKind};
+use crate::drivers::chardev::NS16550aRaw;
+use crate::ipi::online_harts;
+use crate::mm::{PageTable, VirtAddr};
+use crate::sbi::{remote_fence_i, HartMask};
+use crate::sync::SpinMutexIrqSave;
+use crate::task::{next_pcs, C_EBREAK};
+use crate::trap::TrapContext;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::arch::asm;
+use core::fmt::Write;
+use lazy_static::*;
+use log::{info, warn};
+use riscv::register::{satp, sstatus};
+
+/// GDB numbers x0 to x31 and then the pc.
+const PC_REGNO: usize = 32;
+const EBREAK: u32 = 0x0010_0073;
+
+struct GdbStub {
+    uart: NS16550aRaw,
+    /// breakpoints GDB asked for, with the halfword each replaced
+    breakpoints: Vec<(usize, u16)>,
+    /// planted for a single step
+    steps: Vec<(usize, u16)>,
+}
+
+lazy_static! {
+    static ref STUB: SpinMutexIrqSave<Option<GdbStub>> = SpinMutexIrqSave::new(None);
+}
+
+/// Take the second UART for GDB and wait for it to attach, if enabled in
+/// `config`.
+pub fn init() {
+    if !GDBSTUB {
+        return;
+    }
+    let node = match device(DeviceKind::Uart, 1) {
+        Some(node) => node,
+        None => {
+            warn!("gdbstub: no second uart");
+            return;
+        }
+    };
+    *STUB.exclusive_access() = Some(GdbStub {
+        uart: NS16550aRaw::new(node.reg.0),
+        breakpoints: Vec::new(),
+        steps: Vec::new(),
+    });
+    info!("gdbstub: waiting for gdb on the uart at {:#x}", node.reg.0);
+    unsafe {
+        asm!("ebreak");
+    }
+}
+
+/// Let GDB take over the hart that trapped on an ebreak in the kernel with
+/// the registers in `cx`, returning once it continues. False if there is
+/// no GDB to take it.
+pub fn handle_breakpoint(cx: &mut TrapContext) -> bool {
+    if !GDBSTUB {
+        return false;
+    }
+    let mut stub = STUB.exclusive_access();
+    let stub = match stub.as_mut() {
+        Some(stub) => stub,
+        None => return false,
+    };
+    // a step ends wherever the hart stopped
+    let steps = core::mem::take(&mut stub.steps);
+    unplant(&steps);
+    // an ebreak compiled in is run past, there is nothing to put back
+    if !stub.breakpoints.iter().any(|(addr, _)| *addr == cx.sepc) {
+        match read_inst(cx.sepc) {
+            Some(inst) if inst == C_EBREAK as u32 => cx.sepc += 2,
+            Some(EBREAK) => cx.sepc += 4,
+            _ => {}
+        }
+    }
+    stub.session(cx);
+    true
+}
+
+impl GdbStub {
+    fn getc(&mut self) -> u8 {
+        loop {
+            if let Some(ch) = self.uart.read() {
+                return ch;
+            }
+            core::hint::spin_loop();
+        }
+    }
+
+    /// The data of the next packet with a good checksum, acked.
+    fn recv_packet(&mut self) -> Vec<u8> {
+        loop {
+            while self.getc() != b'$' {}
+            let mut data = Vec::new();
+            let mut sum = 0u8;
+            loop {
+                let ch = self.getc();
+                if ch == b'#' {
+                    break;
+                }
+                sum = sum.wrapping_add(ch);
+                data.push(ch);
+            }
+            let checksum = [self.getc(), self.getc()];
+            if parse_hex(&checksum) == Some(sum as usize) {
+                self.uart.write(b'+');
+                return data;
+            }
+            self.uart.write(b'-');
+        }
+    }
+
+    /// Send `data` until GDB acks it.
+    fn send_packet(&mut self, data: &str) {
+        let sum = data.bytes().fold(0u8, |sum, ch| sum.wrapping_add(ch));
+        loop {
+            self.uart.write(b'$');
+            for ch in data.bytes() {
+                self.uart.write(ch);
+            }
+            self.uart.write(b'#');
+            for ch in hex(&[sum]).bytes() {
+                self.uart.write(ch);
+            }
+            if self.getc() != b'-' {
+                return;
+            }
+        }
+    }
+
+    /// Serve GDB until it continues, steps or goes away.
+    fn session(&mut self, cx: &mut TrapContext) {
+        self.send_packet("S05");
+        loop {
+            let packet = self.recv_packet();
+            let (&command, args) = match packet.split_first() {
+                Some(split) => split,
+                None => continue,
+            };
+            let reply = match command {
+                b'?' => String::from("S05"),
+                b'g' => {
+                    let regs: Vec<u8> = (0..=PC_REGNO)
+                        .flat_map(|regno| reg(cx, regno).unwrap().to_le_bytes())
+                        .collect();
+                    hex(&regs)
+                }
+                b'G' => {
+                    for (regno, value) in args.chunks_exact(16).enumerate() {
+                        if let Some(value) = parse_le(value) {
+                            set_reg(cx, regno, value);
+                        }
+                    }
+                    String::from("OK")
+                }
+                b'p' => match parse_hex(args).and_then(|regno| reg(cx, regno)) {
+                    Some(value) => hex(&value.to_le_bytes()),
+                    None => String::from("E01"),
+                },
+                b'P' => {
+                    let mut fields = args.splitn(2, |&ch| ch == b'=');
+                    let regno = fields.next().and_then(parse_hex);
+                    let value = fields.next().and_then(parse_le);
+                    match (regno, value) {
+                        (Some(regno), Some(value)) if set_reg(cx, regno, value) => {
+                            String::from("OK")
+                        }
+                        _ => String::from("E01"),
+                    }
+                }
+                b'm' => {
+                    let (addr, len) = parse_range(args).unwrap_or((0, 0));
+                    match read_memory(addr, len) {
+                        Some(bytes) if len > 0 => hex(&bytes),
+                        _ => String::from("E01"),
+                    }
+                }
+                b'M' => {
+                    let mut fields = args.splitn(2, |&ch| ch == b':');
+                    let range = fields.next().and_then(parse_range);
+                    let bytes = fields.next().and_then(parse_bytes);
+                    match (range, bytes) {
+                        (Some((addr, len)), Some(bytes))
+                            if bytes.len() == len && write_memory(addr, &bytes) =>
+                        {
+                            String::from("OK")
+                        }
+                        _ => String::from("E01"),
+                    }
+                }
+                b'Z' | b'z' if args.starts_with(b"0,") => {
+                    let addr = parse_range(&args[2..]).map(|(addr, _)| addr);
+                    match addr {
+                        Some(addr) if self.set_breakpoint(addr, command == b'Z') => {
+                            String::from("OK")
+                        }
+                        _ => String::from("E01"),
+                    }
+                }
+                b'c' | b's' => {
+                    if let Some(addr) = parse_hex(args) {
+                        cx.sepc = addr;
+                    }
+                    if command == b's' {
+                        self.plant_steps(cx);
+                    }
+                    return;
+                }
+                b'D' | b'k' => {
+                    let breakpoints = core::mem::take(&mut self.breakpoints);
+                    unplant(&breakpoints);
+                    if command == b'D' {
+                        self.send_packet("OK");
+                    }
+                    return;
+                }
+                b'H' => String::from("OK"),
+                b'q' if args.starts_with(b"Supported") => String::from("PacketSize=1000"),
+                b'q' if args == b"Attached" => String::from("1"),
+                _ => String::new(),
+            };
+            self.send_packet(&reply);
+        }
+    }
+
+    /// Plant a breakpoint at `addr`, or take it out unless `set`.
+    fn set_breakpoint(&mut self, addr: usize, set: bool) -> bool {
+        let idx = self.breakpoints.iter().position(|(at, _)| *at == addr);
+        match (idx, set) {
+            (Some(_), true) => true,
+            (None, true) => match plant(addr) {
+                Some(orig) => {
+                    self.breakpoints.push((addr, orig));
+                    true
+                }
+                None => false,
+            },
+            (Some(idx), false) => {
+                let breakpoint = self.breakpoints.remove(idx);
+                unplant(&[breakpoint]);
+                true
+            }
+            (None, false) => true,
+        }
+    }
+
+    /// Plant a breakpoint wherever the instruction at the pc may go, so the
+    /// hart stops again after running it.
+    fn plant_steps(&mut self, cx: &TrapContext) {
+        let inst = match read_inst(cx.sepc) {
+            Some(inst) => inst,
+            None => return,
+        };
+        let mut x = cx.x;
+        x[0] = 0;
+        let mut targets = next_pcs(inst, cx.sepc, &x);
+        targets.dedup();
+        for target in targets {
+            if let Some(orig) = plant(target) {
+                self.steps.push((target, orig));
+            }
+        }
+    }
+}
+
+fn reg(cx: &TrapContext, regno: usize) -> Option<usize> {
+    match regno {
+        0 => Some(0),
+        1..=31 => Some(cx.x[regno]),
+        PC_REGNO => Some(cx.sepc),
+        _ => None,
+    }
+}
+
+/// The sp is where the trap frame sits and stays as it is.
+fn set_reg(cx: &mut TrapContext, regno: usize, value: usize) -> bool {
+    match regno {
+        0 | 2 => {}
+        1..=31 => cx.x[regno] = value,
+        PC_REGNO => cx.sepc = value,
+        _ => return false,
+    }
+    true
+}
+
+/// Whether `len` bytes at `addr` can be read in the kernel address space.
+fn mapped(addr: usize, len: usize) -> bool {
+    // sv39 addresses are sign-extended from bit 38
+    let canonical = |addr: usize| matches!((addr as isize) >> 38, 0 | -1);
+    let end = match addr.checked_add(len) {
+        Some(end) if canonical(addr) && canonical(end) => end,
+        _ => return false,
+    };
+    let page_table = PageTable::from_token(satp::read().bits());
+    let mut va = addr;
+    while va < end {
+        match page_table.translate(VirtAddr::from(va).floor()) {
+            Some(pte) if pte.is_valid() && (pte.readable() || pte.executable()) => {}
+            _ => return false,
+        }
+        va = (va / PAGE_SIZE + 1) * PAGE_SIZE;
+    }
+    true
+}
+
+fn read_memory(addr: usize, len: usize) -> Option<Vec<u8>> {
+    if !mapped(addr, len) {
+        return None;
+    }
+    // kernel code is mapped execute-only, MXR lets it be read meanwhile
+    unsafe { sstatus::set_mxr() };
+    let bytes = (addr..addr + len)
+        .map(|va| unsafe { (va as *const u8).read_volatile() })
+        .collect();
+    unsafe { sstatus::clear_mxr() };
+    Some(bytes)
+}
+
+/// Write `bytes` at `addr`, code included, and make every hart see it.
+fn write_memory(addr: usize, bytes: &[u8]) -> bool {
+    if !mapped(addr, bytes.len()) {
+        return false;
+    }
+    let page_table = PageTable::from_token(satp::read().bits());
+    for (i, byte) in bytes.iter().enumerate() {
+        page_table.force_write(VirtAddr::from(addr + i), *byte);
+    }
+    let _ = remote_fence_i(HartMask::from_mask(online_harts()));
+    true
+}
+
+/// The instruction at `pc`, either length.
+fn read_inst(pc: usize) -> Option<u32> {
+    let low = read_memory(pc, 2)?;
+    let mut inst = u16::from_le_bytes([low[0], low[1]]) as u32;
+    if inst & 0b11 == 0b11 {
+        let high = read_memory(pc + 2, 2)?;
+        inst |= (u16::from_le_bytes([high[0], high[1]]) as u32) << 16;
+    }
+    Some(inst)
+}
+
+/// Put a c.ebreak at `addr`, returning the halfword it replaced.
+fn plant(addr: usize) -> Option<u16> {
+    let orig = read_memory(addr, 2)?;
+    write_memory(addr, &C_EBREAK.to_le_bytes()).then(|| u16::from_le_bytes([orig[0], orig[1]]))
+}
+
+/// Put back what `breakpoints` replaced, the last planted first.
+fn unplant(breakpoints: &[(usize, u16)]) {
+    for (addr, orig) in breakpoints.iter().rev() {
+        write_memory(*addr, &orig.to_le_bytes());
+    }
+}
+
+fn hex(bytes: &[u8]) -> String {
+    let mut string = String::new();
+    for byte in bytes {
+        write!(string, "{:02x}", byte).unwrap();
+    }
+    string
+}
+
+fn parse_hex(digits: &[u8]) -> Option<usize> {
+    let digits = core::str::from_utf8(digits).ok()?;
+    usize::from_str_radix(digits, 16).ok()
+}
+
+fn parse_bytes(digits: &[u8]) -> Option<Vec<u8>> {
+    if digits.len() % 2 != 0 {
+        return None;
+    }
+    digits
+        .chunks_exact(2)
+        .map(|pair| parse_hex(pair).map(|byte| byte as u8))
+        .collect()
+}
+
+/// A register value, sent in target byte order.
+fn parse_le(digits: &[u8]) -> Option<usize> {
+    let bytes = parse_bytes(digits)?;
+    let mut value = [0u8; 8];
+    value.get_mut(..bytes.len())?.copy_from_slice(&bytes);
+    Some(usize::from_le_bytes(value))
+}
+
+/// `addr,len` in hex.
+fn parse_range(args: &[u8]) -> Option<(usize, usize)> {
+    let mut fields = args.splitn(2, |&ch| ch == b',');
+    let addr = parse_hex(fields.next()?)?;
+    let len = parse_hex(fields.next()?)?;
+    Some((addr, len))
+}
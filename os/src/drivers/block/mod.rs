@@ -8,7 +8,14 @@ use easy_fs::BlockDevice;
 use lazy_static::*;
 
 lazy_static! {
-    pub static ref BLOCK_DEVICE: Arc<dyn BlockDevice> = Arc::new(BlockDeviceImpl::new());
+    static ref BLOCK_DEVICE_IMPL: Arc<BlockDeviceImpl> = Arc::new(BlockDeviceImpl::new());
+    pub static ref BLOCK_DEVICE: Arc<dyn BlockDevice> = BLOCK_DEVICE_IMPL.clone();
+}
+
+/// Write a block for the crash dump, which cannot sleep or wait for a
+/// lock. False if the block could not be written.
+pub fn write_block_polled(block_id: usize, buf: &[u8]) -> bool {
+    BLOCK_DEVICE_IMPL.write_block_polled(block_id, buf)
 }
 
 #[allow(unused)]
@@ -1,87 +1,320 @@
+//! virtio-blk over the legacy virtio-mmio transport. When the device offers
+//! VIRTIO_BLK_F_MQ every hart gets a request queue of its own, as many as
+//! the device has, each behind a lock of its own so that harts reading and
+//! writing blocks at once do not all wait on one queue. The device raises
+//! a single interrupt, whichever hart claims it takes the completions of
+//! every queue and wakes the task waiting on each.
+
 use super::BlockDevice;
-use crate::drivers::bus::virtio::VirtioHal;
-use crate::sync::{Condvar, UPIntrFreeCell};
-use crate::task::schedule;
+use crate::config::{CLOCK_FREQ, MAX_HARTS, PAGE_SIZE};
+use crate::drivers::bus::virtio::{
+    read_reg, virtio_device, write_reg, Descriptor, VirtQueue, DESC_F_NEXT, DESC_F_WRITE,
+    REG_CONFIG, REG_GUEST_FEATURES, REG_GUEST_PAGE_SIZE, REG_HOST_FEATURES, REG_INTERRUPT_ACK,
+    REG_INTERRUPT_STATUS, REG_STATUS, REG_VERSION, STATUS_ACKNOWLEDGE, STATUS_DRIVER,
+    STATUS_DRIVER_OK, VIRTIO_BLOCK,
+};
+use crate::mm::{frame_alloc, FrameTracker, PhysAddr};
+use crate::sync::{preemptible, Condvar, SpinMutexIrqSave};
+use crate::task::{hart_id, schedule};
+use crate::timer::get_time;
 use crate::DEV_NON_BLOCKING_ACCESS;
-use alloc::collections::BTreeMap;
-use virtio_drivers::{BlkResp, RespStatus, VirtIOBlk, VirtIOHeader};
+use alloc::vec::Vec;
+use core::mem::size_of;
+
+/// The device has more than one request queue.
+const VIRTIO_BLK_F_MQ: u32 = 1 << 12;
+/// `num_queues` in the config space, there with VIRTIO_BLK_F_MQ.
+const CONFIG_NUM_QUEUES: usize = 34;
+
+/// Descriptors of each queue.
+const QUEUE_SIZE: usize = 32;
+/// Requests in flight on a queue, each a chain of three descriptors: the
+/// header, the data and the status.
+const SLOTS: usize = QUEUE_SIZE / 3;
+
+const BLK_T_IN: u32 = 0;
+const BLK_T_OUT: u32 = 1;
+const BLK_S_OK: u8 = 0;
+
+const BLOCK_SIZE: usize = 512;
+// each slot has a page: the header at its start, then these
+const STATUS_OFFSET: usize = 16;
+const DATA_OFFSET: usize = 512;
 
 #[allow(unused)]
-const VIRTIO0: usize = 0x10008000;
+#[repr(C)]
+struct ReqHeader {
+    kind: u32,
+    reserved: u32,
+    sector: u64,
+}
 
-pub struct VirtIOBlock {
-    virtio_blk: UPIntrFreeCell<VirtIOBlk<'static, VirtioHal>>,
-    condvars: BTreeMap<u16, Condvar>,
+/// A virtqueue and the requests on it, each in a slot with a page of its
+/// own.
+struct RequestQueue {
+    queue: VirtQueue,
+    /// the page of each slot
+    pages: Vec<FrameTracker>,
+    /// slots not with the device
+    free: Vec<usize>,
+    /// slots the device is done with, not yet taken by their submitter
+    done: [bool; SLOTS],
 }
 
-impl BlockDevice for VirtIOBlock {
-    fn read_block(&self, block_id: usize, buf: &mut [u8]) {
-        let nb = *DEV_NON_BLOCKING_ACCESS.exclusive_access();
-        if nb {
-            let mut resp = BlkResp::default();
-            let task_cx_ptr = self.virtio_blk.exclusive_session(|blk| {
-                let token = unsafe { blk.read_block_nb(block_id, buf, &mut resp).unwrap() };
-                self.condvars.get(&token).unwrap().wait_no_sched()
-            });
-            schedule(task_cx_ptr);
-            assert_eq!(
-                resp.status(),
-                RespStatus::Ok,
-                "Error when reading VirtIOBlk"
-            );
-        } else {
-            self.virtio_blk
-                .exclusive_access()
-                .read_block(block_id, buf)
-                .expect("Error when reading VirtIOBlk");
+impl RequestQueue {
+    fn new(base: usize, index: u32) -> Self {
+        let queue = VirtQueue::new(base, index, QUEUE_SIZE).expect("no virtio-blk queue");
+        let pages: Vec<FrameTracker> = (0..SLOTS)
+            .map(|_| frame_alloc().expect("no frames for virtio-blk buffers"))
+            .collect();
+        // the chain of each slot never changes, only the direction of its data
+        for (slot, page) in pages.iter().enumerate() {
+            let page = PhysAddr::from(page.ppn).0 as u64;
+            let head = (slot * 3) as u16;
+            *queue.descriptor(head) = Descriptor {
+                addr: page,
+                len: size_of::<ReqHeader>() as u32,
+                flags: DESC_F_NEXT,
+                next: head + 1,
+            };
+            *queue.descriptor(head + 1) = Descriptor {
+                addr: page + DATA_OFFSET as u64,
+                len: BLOCK_SIZE as u32,
+                flags: DESC_F_NEXT,
+                next: head + 2,
+            };
+            *queue.descriptor(head + 2) = Descriptor {
+                addr: page + STATUS_OFFSET as u64,
+                len: 1,
+                flags: DESC_F_WRITE,
+                next: 0,
+            };
+        }
+        Self {
+            queue,
+            pages,
+            free: (0..SLOTS).rev().collect(),
+            done: [false; SLOTS],
         }
     }
-    fn write_block(&self, block_id: usize, buf: &[u8]) {
-        let nb = *DEV_NON_BLOCKING_ACCESS.exclusive_access();
+    fn data(&self, slot: usize) -> &'static mut [u8] {
+        &mut self.pages[slot].ppn.get_bytes_array()[DATA_OFFSET..DATA_OFFSET + BLOCK_SIZE]
+    }
+    fn status(&self, slot: usize) -> u8 {
+        self.pages[slot].ppn.get_bytes_array()[STATUS_OFFSET]
+    }
+    /// Hand the request in `slot` to the device.
+    fn push(&mut self, slot: usize, kind: u32, block_id: usize) {
+        *self.pages[slot].ppn.get_mut::<ReqHeader>() = ReqHeader {
+            kind,
+            reserved: 0,
+            sector: block_id as u64,
+        };
+        let head = (slot * 3) as u16;
+        self.queue.descriptor(head + 1).flags = match kind {
+            BLK_T_IN => DESC_F_NEXT | DESC_F_WRITE,
+            _ => DESC_F_NEXT,
+        };
+        self.queue.push(head);
+        self.queue.notify();
+    }
+    /// A slot the device is done with.
+    fn pop_used(&mut self) -> Option<usize> {
+        self.queue.pop_used().map(|(head, _)| head as usize / 3)
+    }
+}
+
+/// One request queue of the device together with the condvars of its
+/// in-flight requests, indexed by slot.
+struct BlkQueue {
+    queue: SpinMutexIrqSave<RequestQueue>,
+    condvars: Vec<Condvar>,
+    /// tasks waiting for a slot while every one is in flight
+    slot_freed: Condvar,
+}
+
+impl BlkQueue {
+    fn new(base: usize, index: u32) -> Self {
+        Self {
+            queue: SpinMutexIrqSave::new(RequestQueue::new(base, index)),
+            condvars: (0..SLOTS).map(|_| Condvar::new()).collect(),
+            slot_freed: Condvar::new(),
+        }
+    }
+    /// Take what the device is done with and wake whoever waits on it.
+    fn reap(&self, queue: &mut RequestQueue) {
+        while let Some(slot) = queue.pop_used() {
+            queue.done[slot] = true;
+            self.condvars[slot].signal();
+        }
+    }
+    /// Submit a `kind` request on `block_id`, with its data filled by `fill`
+    /// and read back by `take`, and wait until it is done. With non-blocking
+    /// access the task sleeps until the completion interrupt. Before that,
+    /// and for a caller holding locks, such as the frame allocator swapping
+    /// pages out, the used ring is polled. Returns the status from the
+    /// device.
+    fn request(
+        &self,
+        kind: u32,
+        block_id: usize,
+        fill: impl FnOnce(&mut [u8]),
+        take: impl FnOnce(&[u8]),
+    ) -> u8 {
+        let nb = preemptible() && *DEV_NON_BLOCKING_ACCESS.exclusive_access();
+        let mut queue = self.queue.exclusive_access();
+        let slot = loop {
+            if let Some(slot) = queue.free.pop() {
+                break slot;
+            }
+            if nb {
+                let task_cx_ptr = self.slot_freed.wait_no_sched();
+                drop(queue);
+                schedule(task_cx_ptr);
+            } else {
+                drop(queue);
+            }
+            queue = self.queue.exclusive_access();
+        };
+        fill(queue.data(slot));
+        queue.push(slot, kind, block_id);
         if nb {
-            let mut resp = BlkResp::default();
-            let task_cx_ptr = self.virtio_blk.exclusive_session(|blk| {
-                let token = unsafe { blk.write_block_nb(block_id, buf, &mut resp).unwrap() };
-                self.condvars.get(&token).unwrap().wait_no_sched()
-            });
+            let task_cx_ptr = self.condvars[slot].wait_no_sched();
+            drop(queue);
             schedule(task_cx_ptr);
-            assert_eq!(
-                resp.status(),
-                RespStatus::Ok,
-                "Error when writing VirtIOBlk"
-            );
-        } else {
-            self.virtio_blk
-                .exclusive_access()
-                .write_block(block_id, buf)
-                .expect("Error when writing VirtIOBlk");
+            queue = self.queue.exclusive_access();
+        }
+        loop {
+            self.reap(&mut queue);
+            if queue.done[slot] {
+                break;
+            }
+            drop(queue);
+            queue = self.queue.exclusive_access();
         }
+        queue.done[slot] = false;
+        let status = queue.status(slot);
+        take(queue.data(slot));
+        queue.free.push(slot);
+        drop(queue);
+        self.slot_freed.signal();
+        status
+    }
+}
+
+pub struct VirtIOBlock {
+    base: usize,
+    queues: Vec<BlkQueue>,
+}
+
+impl BlockDevice for VirtIOBlock {
+    fn read_block(&self, block_id: usize, buf: &mut [u8]) {
+        statistic_time!("read_block", self.read_block_timed(block_id, buf))
+    }
+    fn write_block(&self, block_id: usize, buf: &[u8]) {
+        statistic_time!("write_block", self.write_block_timed(block_id, buf))
     }
     fn handle_irq(&self) {
-        self.virtio_blk.exclusive_session(|blk| {
-            while let Ok(token) = blk.pop_used() {
-                self.condvars.get(&token).unwrap().signal();
-            }
-        });
+        let status = read_reg(self.base, REG_INTERRUPT_STATUS);
+        write_reg(self.base, REG_INTERRUPT_ACK, status);
+        for queue in self.queues.iter() {
+            queue.reap(&mut queue.queue.exclusive_access());
+        }
     }
 }
 
 impl VirtIOBlock {
+    fn read_block_timed(&self, block_id: usize, buf: &mut [u8]) {
+        let status = self.submission_queue().request(
+            BLK_T_IN,
+            block_id,
+            |_| {},
+            |data| buf.copy_from_slice(data),
+        );
+        assert_eq!(status, BLK_S_OK, "Error when reading VirtIOBlk");
+    }
+    fn write_block_timed(&self, block_id: usize, buf: &[u8]) {
+        let status = self.submission_queue().request(
+            BLK_T_OUT,
+            block_id,
+            |data| data.copy_from_slice(buf),
+            |_| {},
+        );
+        assert_eq!(status, BLK_S_OK, "Error when writing VirtIOBlk");
+    }
     pub fn new() -> Self {
-        let virtio_blk = unsafe {
-            UPIntrFreeCell::new(
-                VirtIOBlk::<VirtioHal>::new(&mut *(VIRTIO0 as *mut VirtIOHeader)).unwrap(),
-            )
+        let base = virtio_device(VIRTIO_BLOCK, 0)
+            .expect("no block device")
+            .reg
+            .0;
+        assert_eq!(
+            read_reg(base, REG_VERSION),
+            1,
+            "only legacy virtio-mmio is supported"
+        );
+        // reset, then say we know the device
+        write_reg(base, REG_STATUS, 0);
+        write_reg(base, REG_STATUS, STATUS_ACKNOWLEDGE);
+        write_reg(base, REG_STATUS, STATUS_ACKNOWLEDGE | STATUS_DRIVER);
+        let features = read_reg(base, REG_HOST_FEATURES) & VIRTIO_BLK_F_MQ;
+        write_reg(base, REG_GUEST_FEATURES, features);
+        write_reg(base, REG_GUEST_PAGE_SIZE, PAGE_SIZE as u32);
+        let num_queues = if features & VIRTIO_BLK_F_MQ != 0 {
+            let num_queues = (base + REG_CONFIG + CONFIG_NUM_QUEUES) as *const u16;
+            unsafe { num_queues.read_volatile() as usize }
+        } else {
+            1
         };
-        let mut condvars = BTreeMap::new();
-        let channels = virtio_blk.exclusive_access().virt_queue_size();
-        for i in 0..channels {
-            let condvar = Condvar::new();
-            condvars.insert(i, condvar);
-        }
-        Self {
-            virtio_blk,
-            condvars,
+        // no more than one queue a hart, the rest would sit unused
+        let queues = (0..num_queues.clamp(1, MAX_HARTS))
+            .map(|index| BlkQueue::new(base, index as u32))
+            .collect();
+        write_reg(
+            base,
+            REG_STATUS,
+            STATUS_ACKNOWLEDGE | STATUS_DRIVER | STATUS_DRIVER_OK,
+        );
+        Self { base, queues }
+    }
+    /// Write a block with neither a lock waited for nor an allocation,
+    /// for the crash dump: the hart that panicked or a halted one may hold
+    /// any queue. Takes the first queue free to take with a slot free and
+    /// polls it, giving up after a second. Completions of other requests
+    /// found meanwhile are only marked done, nobody is woken any more.
+    pub fn write_block_polled(&self, block_id: usize, buf: &[u8]) -> bool {
+        let first = hart_id() % self.queues.len();
+        for i in 0..self.queues.len() {
+            let blk = &self.queues[(first + i) % self.queues.len()];
+            let mut queue = match blk.queue.try_exclusive_access() {
+                Some(queue) => queue,
+                None => continue,
+            };
+            let slot = match queue.free.pop() {
+                Some(slot) => slot,
+                None => continue,
+            };
+            queue.data(slot).copy_from_slice(buf);
+            queue.push(slot, BLK_T_OUT, block_id);
+            let deadline = get_time() + CLOCK_FREQ;
+            while !queue.done[slot] {
+                if get_time() > deadline {
+                    return false;
+                }
+                while let Some(done) = queue.pop_used() {
+                    queue.done[done] = true;
+                }
+            }
+            queue.done[slot] = false;
+            let status = queue.status(slot);
+            queue.free.push(slot);
+            return status == BLK_S_OK;
         }
+        false
+    }
+    /// Each hart submits to its own queue, so that requests issued from
+    /// different harts do not contend on the same lock. Harts share queues
+    /// when the device has fewer queues than there are harts.
+    fn submission_queue(&self) -> &BlkQueue {
+        &self.queues[hart_id() % self.queues.len()]
     }
 }
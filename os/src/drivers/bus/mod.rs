@@ -1 +1,70 @@
+//! Binding device tree nodes to drivers by their `compatible` strings, so
+//! devices are found wherever the board puts them.
+
 pub mod virtio;
+
+use crate::dtb::{devices, DeviceNode};
+use alloc::vec::Vec;
+use lazy_static::*;
+
+/// What a driver found a node to be.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum DeviceKind {
+    /// a virtio-mmio transport with a device of this id behind it
+    Virtio(u32),
+    Uart,
+    Rtc,
+    Plic,
+}
+
+/// Look a node over, `None` if the driver cannot use it after all.
+type Probe = fn(&DeviceNode) -> Option<DeviceKind>;
+
+/// Drivers by the `compatible` string they bind to.
+const DRIVERS: &[(&str, Probe)] = &[
+    ("virtio,mmio", virtio::probe),
+    ("ns16550a", |_| Some(DeviceKind::Uart)),
+    ("google,goldfish-rtc", |_| Some(DeviceKind::Rtc)),
+    ("riscv,plic0", |_| Some(DeviceKind::Plic)),
+    ("sifive,plic-1.0.0", |_| Some(DeviceKind::Plic)),
+];
+
+lazy_static! {
+    /// Nodes bound to a driver, by address.
+    static ref BOUND: Vec<(DeviceKind, &'static DeviceNode)> = devices()
+        .iter()
+        .filter_map(|node| Some((driver_of(node)?(node)?, node)))
+        .collect();
+}
+
+/// The driver for `node`, going by its most specific `compatible` first.
+fn driver_of(node: &DeviceNode) -> Option<Probe> {
+    node.compatible.iter().find_map(|compatible| {
+        DRIVERS
+            .iter()
+            .find(|(name, _)| name == compatible)
+            .map(|(_, probe)| *probe)
+    })
+}
+
+/// Registers of the nodes some driver knows, for the kernel to map.
+pub fn mmio_regions() -> impl Iterator<Item = (usize, usize)> {
+    devices()
+        .iter()
+        .filter(|node| driver_of(node).is_some())
+        .map(|node| node.reg)
+}
+
+/// Bind every node to its driver, their registers must be mapped by now.
+pub fn probe() {
+    lazy_static::initialize(&BOUND);
+}
+
+/// The `nth` node bound as `kind`, by address.
+pub fn device(kind: DeviceKind, nth: usize) -> Option<&'static DeviceNode> {
+    BOUND
+        .iter()
+        .filter(|(bound, _)| *bound == kind)
+        .nth(nth)
+        .map(|(_, node)| *node)
+}
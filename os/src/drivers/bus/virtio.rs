@@ -1,37 +1,204 @@
+use super::{device, DeviceKind};
+use crate::config::PAGE_SIZE;
+use crate::dtb::DeviceNode;
 use crate::mm::{
-    frame_alloc_more, frame_dealloc, kernel_token, FrameTracker, PageTable, PhysAddr, PhysPageNum,
-    StepByOne, VirtAddr,
+    frame_alloc_contiguous, kernel_translate_va, FrameTracker, PhysAddr, PhysPageNum, VirtAddr,
 };
-use crate::sync::UPIntrFreeCell;
+use crate::sync::SpinMutexIrqSave;
 use alloc::vec::Vec;
+use core::mem::size_of;
+use core::sync::atomic::{fence, Ordering};
 use lazy_static::*;
 use virtio_drivers::Hal;
 
+pub const VIRTIO_NET: u32 = 1;
+pub const VIRTIO_BLOCK: u32 = 2;
+pub const VIRTIO_RNG: u32 = 4;
+pub const VIRTIO_GPU: u32 = 16;
+pub const VIRTIO_INPUT: u32 = 18;
+
+/// "virt" at the start of every virtio-mmio transport
+const VIRTIO_MAGIC: u32 = 0x7472_6976;
+
+// registers of the legacy transport, for the drivers of our own
+pub const REG_VERSION: usize = 0x004;
+pub const REG_HOST_FEATURES: usize = 0x010;
+pub const REG_GUEST_FEATURES: usize = 0x020;
+pub const REG_GUEST_PAGE_SIZE: usize = 0x028;
+pub const REG_QUEUE_SEL: usize = 0x030;
+pub const REG_QUEUE_NUM_MAX: usize = 0x034;
+pub const REG_QUEUE_NUM: usize = 0x038;
+pub const REG_QUEUE_ALIGN: usize = 0x03c;
+pub const REG_QUEUE_PFN: usize = 0x040;
+pub const REG_QUEUE_NOTIFY: usize = 0x050;
+pub const REG_INTERRUPT_STATUS: usize = 0x060;
+pub const REG_INTERRUPT_ACK: usize = 0x064;
+pub const REG_STATUS: usize = 0x070;
+/// where the config space of the device starts
+pub const REG_CONFIG: usize = 0x100;
+
+pub const STATUS_ACKNOWLEDGE: u32 = 1;
+pub const STATUS_DRIVER: u32 = 2;
+pub const STATUS_DRIVER_OK: u32 = 4;
+
+/// The buffer goes on in the descriptor `next`.
+pub const DESC_F_NEXT: u16 = 1;
+/// The device writes the buffer instead of reading it.
+pub const DESC_F_WRITE: u16 = 2;
+
 lazy_static! {
-    static ref QUEUE_FRAMES: UPIntrFreeCell<Vec<FrameTracker>> =
-        unsafe { UPIntrFreeCell::new(Vec::new()) };
+    /// Frames lent to devices, freed when dropped from here.
+    static ref QUEUE_FRAMES: SpinMutexIrqSave<Vec<FrameTracker>> =
+        SpinMutexIrqSave::new(Vec::new());
+}
+
+#[repr(C)]
+pub struct Descriptor {
+    pub addr: u64,
+    pub len: u32,
+    pub flags: u16,
+    pub next: u16,
+}
+
+#[repr(C)]
+struct UsedElem {
+    id: u32,
+    len: u32,
+}
+
+/// A virtqueue of the legacy transport, for the drivers of our own: the
+/// descriptors and the available ring on one page, the used ring on the
+/// next. Which buffers the descriptors point to is up to the driver.
+pub struct VirtQueue {
+    base: usize,
+    index: u32,
+    size: usize,
+    /// the pages of the rings
+    rings: Vec<FrameTracker>,
+    /// used entries taken so far
+    last_used: u16,
+}
+
+impl VirtQueue {
+    /// Set up queue `index` of the transport at `base` with `size`
+    /// descriptors, a power of two. None if the device has no queue that
+    /// large there or no frames are left for the rings.
+    pub fn new(base: usize, index: u32, size: usize) -> Option<Self> {
+        // the descriptors and the available ring share the first page
+        assert!(size * (size_of::<Descriptor>() + 2) + 6 <= PAGE_SIZE);
+        write_reg(base, REG_QUEUE_SEL, index);
+        if (read_reg(base, REG_QUEUE_NUM_MAX) as usize) < size {
+            return None;
+        }
+        write_reg(base, REG_QUEUE_NUM, size as u32);
+        let queue = Self {
+            base,
+            index,
+            size,
+            rings: frame_alloc_contiguous(2)?,
+            last_used: 0,
+        };
+        write_reg(base, REG_QUEUE_ALIGN, PAGE_SIZE as u32);
+        write_reg(base, REG_QUEUE_PFN, queue.rings[0].ppn.0 as u32);
+        Some(queue)
+    }
+    pub fn descriptor(&self, id: u16) -> &'static mut Descriptor {
+        let desc_table = PhysAddr::from(self.rings[0].ppn).0 as *mut Descriptor;
+        unsafe { &mut *desc_table.add(id as usize) }
+    }
+    /// `flags`, `idx` and then the ring of the available ring.
+    fn avail(&self) -> *mut u16 {
+        (PhysAddr::from(self.rings[0].ppn).0 + self.size * size_of::<Descriptor>()) as *mut u16
+    }
+    /// `flags` and `idx` of the used ring, its ring follows.
+    fn used(&self) -> *mut u16 {
+        PhysAddr::from(self.rings[1].ppn).0 as *mut u16
+    }
+    /// Have the device not interrupt when it is done with a buffer, for a
+    /// queue whose used ring is polled.
+    pub fn no_interrupt(&self) {
+        unsafe { self.avail().write_volatile(1) };
+    }
+    /// Hand the chain of descriptors from `head` to the device, which
+    /// only looks once notified.
+    pub fn push(&mut self, head: u16) {
+        let avail = self.avail();
+        unsafe {
+            let idx = avail.add(1).read_volatile();
+            avail.add(2 + idx as usize % self.size).write_volatile(head);
+            // the device must see the descriptors and the entry before the
+            // index that covers them
+            fence(Ordering::SeqCst);
+            avail.add(1).write_volatile(idx.wrapping_add(1));
+        }
+    }
+    pub fn notify(&self) {
+        fence(Ordering::SeqCst);
+        write_reg(self.base, REG_QUEUE_NOTIFY, self.index);
+    }
+    /// The head of a chain the device is done with, and how many bytes of
+    /// it the device wrote.
+    pub fn pop_used(&mut self) -> Option<(u16, usize)> {
+        let used = self.used();
+        let idx = unsafe { used.add(1).read_volatile() };
+        if idx == self.last_used {
+            return None;
+        }
+        fence(Ordering::SeqCst);
+        let elem = unsafe {
+            (used.add(2) as *const UsedElem)
+                .add(self.last_used as usize % self.size)
+                .read_volatile()
+        };
+        self.last_used = self.last_used.wrapping_add(1);
+        Some((elem.id as u16, elem.len as usize))
+    }
+}
+
+/// Transports without a device behind them read device id 0.
+pub fn probe(node: &DeviceNode) -> Option<DeviceKind> {
+    let base = node.reg.0;
+    let magic = unsafe { (base as *const u32).read_volatile() };
+    let device_id = unsafe { ((base + 8) as *const u32).read_volatile() };
+    (magic == VIRTIO_MAGIC && device_id != 0).then_some(DeviceKind::Virtio(device_id))
+}
+
+/// The `nth` transport with a `device_id` device behind it, by address.
+pub fn virtio_device(device_id: u32, nth: usize) -> Option<&'static DeviceNode> {
+    device(DeviceKind::Virtio(device_id), nth)
+}
+
+pub fn read_reg(base: usize, reg: usize) -> u32 {
+    unsafe { ((base + reg) as *const u32).read_volatile() }
+}
+
+pub fn write_reg(base: usize, reg: usize, value: u32) {
+    unsafe { ((base + reg) as *mut u32).write_volatile(value) }
 }
 
 pub struct VirtioHal;
 
 impl Hal for VirtioHal {
+    /// Return 0 when out of frames, which the driver reports as a DMA error.
     fn dma_alloc(pages: usize) -> usize {
-        let trakcers = frame_alloc_more(pages);
-        let ppn_base = trakcers.as_ref().unwrap().last().unwrap().ppn;
-        QUEUE_FRAMES
-            .exclusive_access()
-            .append(&mut trakcers.unwrap());
+        let mut trackers = match frame_alloc_contiguous(pages) {
+            Some(trackers) => trackers,
+            None => return 0,
+        };
+        let ppn_base = trackers[0].ppn;
+        QUEUE_FRAMES.exclusive_access().append(&mut trackers);
         let pa: PhysAddr = ppn_base.into();
         pa.0
     }
 
     fn dma_dealloc(pa: usize, pages: usize) -> i32 {
         let pa = PhysAddr::from(pa);
-        let mut ppn_base: PhysPageNum = pa.into();
-        for _ in 0..pages {
-            frame_dealloc(ppn_base);
-            ppn_base.step();
-        }
+        let ppn_base: PhysPageNum = pa.into();
+        let ppn_end = ppn_base.0 + pages;
+        // dropping the trackers frees the frames
+        QUEUE_FRAMES
+            .exclusive_access()
+            .retain(|tracker| !(ppn_base.0..ppn_end).contains(&tracker.ppn.0));
         0
     }
 
@@ -40,9 +207,6 @@ impl Hal for VirtioHal {
     }
 
     fn virt_to_phys(vaddr: usize) -> usize {
-        PageTable::from_token(kernel_token())
-            .translate_va(VirtAddr::from(vaddr))
-            .unwrap()
-            .0
+        kernel_translate_va(VirtAddr::from(vaddr)).unwrap().0
     }
 }
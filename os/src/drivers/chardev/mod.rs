@@ -1,17 +1,23 @@
 mod ns16550a;
+mod tty;
 
 use crate::board::CharDeviceImpl;
+use crate::drivers::bus::{device, DeviceKind};
 use alloc::sync::Arc;
 use lazy_static::*;
-pub use ns16550a::NS16550a;
+pub use ns16550a::{NS16550a, NS16550aRaw};
+pub use tty::TTY;
 
 pub trait CharDevice {
     fn init(&self);
-    fn read(&self) -> u8;
+    /// Take a received byte without waiting.
+    fn try_read(&self) -> Option<u8>;
     fn write(&self, ch: u8);
     fn handle_irq(&self);
 }
 
 lazy_static! {
-    pub static ref UART: Arc<CharDeviceImpl> = Arc::new(CharDeviceImpl::new());
+    pub static ref UART: Arc<CharDeviceImpl> = Arc::new(CharDeviceImpl::new(
+        device(DeviceKind::Uart, 0).expect("no uart").reg.0
+    ));
 }
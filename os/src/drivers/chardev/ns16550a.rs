@@ -2,8 +2,7 @@
 ///! Ref: ns16550a datasheet: https://datasheetspdf.com/pdf-file/605590/NationalSemiconductor/NS16550A/1
 ///! Ref: ns16450 datasheet: https://datasheetspdf.com/pdf-file/1311818/NationalSemiconductor/NS16450/1
 use super::CharDevice;
-use crate::sync::{Condvar, UPIntrFreeCell};
-use crate::task::schedule;
+use crate::sync::SpinMutexIrqSave;
 use alloc::collections::VecDeque;
 use bitflags::*;
 use volatile::{ReadOnly, Volatile, WriteOnly};
@@ -119,68 +118,55 @@ impl NS16550aRaw {
     }
 }
 
+/// Bytes received but not yet read, at most.
+const READ_BUFFER_SIZE: usize = 4096;
+
 struct NS16550aInner {
     ns16550a: NS16550aRaw,
     read_buffer: VecDeque<u8>,
 }
 
-pub struct NS16550a<const BASE_ADDR: usize> {
-    inner: UPIntrFreeCell<NS16550aInner>,
-    condvar: Condvar,
+pub struct NS16550a {
+    inner: SpinMutexIrqSave<NS16550aInner>,
 }
 
-impl<const BASE_ADDR: usize> NS16550a<BASE_ADDR> {
-    pub fn new() -> Self {
+impl NS16550a {
+    pub fn new(base_addr: usize) -> Self {
         let inner = NS16550aInner {
-            ns16550a: NS16550aRaw::new(BASE_ADDR),
-            read_buffer: VecDeque::new(),
+            ns16550a: NS16550aRaw::new(base_addr),
+            read_buffer: VecDeque::with_capacity(READ_BUFFER_SIZE),
         };
         //inner.ns16550a.init();
         Self {
-            inner: unsafe { UPIntrFreeCell::new(inner) },
-            condvar: Condvar::new(),
+            inner: SpinMutexIrqSave::new(inner),
         }
     }
-
-    pub fn read_buffer_is_empty(&self) -> bool {
-        self.inner
-            .exclusive_session(|inner| inner.read_buffer.is_empty())
-    }
 }
 
-impl<const BASE_ADDR: usize> CharDevice for NS16550a<BASE_ADDR> {
+impl CharDevice for NS16550a {
     fn init(&self) {
         let mut inner = self.inner.exclusive_access();
         inner.ns16550a.init();
         drop(inner);
     }
 
-    fn read(&self) -> u8 {
-        loop {
-            let mut inner = self.inner.exclusive_access();
-            if let Some(ch) = inner.read_buffer.pop_front() {
-                return ch;
-            } else {
-                let task_cx_ptr = self.condvar.wait_no_sched();
-                drop(inner);
-                schedule(task_cx_ptr);
-            }
-        }
+    fn try_read(&self) -> Option<u8> {
+        self.inner
+            .exclusive_session(|inner| inner.read_buffer.pop_front())
     }
     fn write(&self, ch: u8) {
         let mut inner = self.inner.exclusive_access();
         inner.ns16550a.write(ch);
     }
+    /// Input nobody takes is dropped once the buffer is full, the line
+    /// discipline takes it right after.
     fn handle_irq(&self) {
-        let mut count = 0;
         self.inner.exclusive_session(|inner| {
             while let Some(ch) = inner.ns16550a.read() {
-                count += 1;
-                inner.read_buffer.push_back(ch);
+                if inner.read_buffer.len() < READ_BUFFER_SIZE {
+                    inner.read_buffer.push_back(ch);
+                }
             }
         });
-        if count > 0 {
-            self.condvar.signal();
-        }
     }
 }
@@ -0,0 +1,265 @@
+//! Line discipline of the console: edits input lines before programs see
+//! them and turns Ctrl-C into SIGINT and Ctrl-Z into SIGTSTP for the
+//! foreground process group, as a terminal does.
+
+use super::{CharDevice, UART};
+use crate::sync::{PollWaker, PollWakers, SpinMutexIrqSave, WaitQueue};
+use crate::task::{process_group, schedule, SignalFlags};
+use alloc::collections::VecDeque;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use bitflags::*;
+use lazy_static::*;
+
+bitflags! {
+    /// Local modes, numbered as the `c_lflag` bits of Linux.
+    pub struct LocalFlags: u32 {
+        /// Ctrl-C raises SIGINT and Ctrl-Z SIGTSTP
+        const ISIG = 0o1;
+        /// input is read a line at a time, after editing
+        const ICANON = 0o2;
+        const ECHO = 0o10;
+    }
+}
+
+/// Get the local modes.
+pub const TCGETS: u32 = 0x5401;
+/// Set the local modes to `arg`.
+pub const TCSETS: u32 = 0x5402;
+/// Get the foreground process group.
+pub const TIOCGPGRP: u32 = 0x540f;
+/// Make the process group `arg` the foreground one, 0 for none.
+pub const TIOCSPGRP: u32 = 0x5410;
+
+/// Bytes received but not yet read, at most.
+const INPUT_SIZE: usize = 4096;
+
+const CTRL_C: u8 = 0x03;
+const BS: u8 = 0x08;
+const LF: u8 = 0x0a;
+const CR: u8 = 0x0d;
+const CTRL_U: u8 = 0x15;
+const CTRL_Z: u8 = 0x1a;
+const DL: u8 = 0x7f;
+
+struct LineDisciplineInner {
+    lflag: LocalFlags,
+    /// the line being edited in canonical mode
+    line: Vec<u8>,
+    /// input that can be read
+    input: VecDeque<u8>,
+    /// the process group receiving the signals, 0 for none
+    foreground: usize,
+    /// polling for input
+    pollers: PollWakers,
+}
+
+impl LineDisciplineInner {
+    /// Take in one received byte, adding what to write back to `echo`.
+    /// Returns the signal it generates, if any.
+    fn receive(&mut self, ch: u8, echo: &mut Vec<u8>) -> Option<SignalFlags> {
+        let ch = if ch == CR { LF } else { ch };
+        let echoing = self.lflag.contains(LocalFlags::ECHO);
+        let isig = self.lflag.contains(LocalFlags::ISIG);
+        let generated = match ch {
+            CTRL_C if isig => Some((SignalFlags::SIGINT, b"^C\n")),
+            CTRL_Z if isig => Some((SignalFlags::SIGTSTP, b"^Z\n")),
+            _ => None,
+        };
+        if let Some((signal, caret)) = generated {
+            self.line.clear();
+            if echoing {
+                echo.extend_from_slice(caret);
+            }
+            return Some(signal);
+        }
+        if !self.lflag.contains(LocalFlags::ICANON) {
+            if self.input.len() < INPUT_SIZE {
+                self.input.push_back(ch);
+                if echoing {
+                    echo.push(ch);
+                }
+            }
+            return None;
+        }
+        match ch {
+            BS | DL => {
+                if self.line.pop().is_some() && echoing {
+                    echo.extend_from_slice(&[BS, b' ', BS]);
+                }
+            }
+            CTRL_U => {
+                for _ in self.line.drain(..) {
+                    if echoing {
+                        echo.extend_from_slice(&[BS, b' ', BS]);
+                    }
+                }
+            }
+            // the newline always fits, so a full line can still be ended
+            LF => {
+                self.line.push(LF);
+                self.input.extend(self.line.drain(..));
+                if echoing {
+                    echo.push(LF);
+                }
+            }
+            _ => {
+                if self.input.len() + self.line.len() + 1 < INPUT_SIZE {
+                    self.line.push(ch);
+                    if echoing {
+                        echo.push(ch);
+                    }
+                }
+            }
+        }
+        None
+    }
+
+    /// Take up to `len` bytes of input, stopping after a newline in
+    /// canonical mode.
+    fn take(&mut self, len: usize) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        while bytes.len() < len {
+            match self.input.pop_front() {
+                Some(ch) => {
+                    bytes.push(ch);
+                    if ch == LF && self.lflag.contains(LocalFlags::ICANON) {
+                        break;
+                    }
+                }
+                None => break,
+            }
+        }
+        bytes
+    }
+}
+
+pub struct LineDiscipline {
+    inner: SpinMutexIrqSave<LineDisciplineInner>,
+    readers: WaitQueue,
+}
+
+impl LineDiscipline {
+    pub fn new() -> Self {
+        let inner = LineDisciplineInner {
+            lflag: LocalFlags::all(),
+            line: Vec::new(),
+            input: VecDeque::with_capacity(INPUT_SIZE),
+            foreground: 0,
+            pollers: PollWakers::new(),
+        };
+        Self {
+            inner: SpinMutexIrqSave::new(inner),
+            readers: WaitQueue::new(),
+        }
+    }
+
+    /// Whether a read would return at once.
+    pub fn can_read(&self) -> bool {
+        self.inner
+            .exclusive_session(|inner| !inner.input.is_empty())
+    }
+
+    pub fn register_waker(&self, waker: &Arc<PollWaker>) {
+        self.inner.exclusive_access().pollers.register(waker);
+    }
+
+    /// Wait for input and take up to `len` bytes of it. Returns nothing
+    /// if a signal comes first.
+    pub fn read(&self, len: usize) -> Vec<u8> {
+        if len == 0 {
+            return Vec::new();
+        }
+        loop {
+            let mut inner = self.inner.exclusive_access();
+            if !inner.input.is_empty() {
+                return inner.take(len);
+            }
+            match self.readers.wait_no_sched_interruptible() {
+                Some(task_cx_ptr) => {
+                    drop(inner);
+                    schedule(task_cx_ptr);
+                }
+                None => return Vec::new(),
+            }
+        }
+    }
+
+    /// Take up to `len` bytes of input without waiting, none if there is
+    /// no input yet.
+    pub fn try_read(&self, len: usize) -> Option<Vec<u8>> {
+        let mut inner = self.inner.exclusive_access();
+        if len > 0 && inner.input.is_empty() {
+            return None;
+        }
+        Some(inner.take(len))
+    }
+
+    pub fn ioctl(&self, cmd: u32, arg: usize) -> isize {
+        let mut inner = self.inner.exclusive_access();
+        match cmd {
+            TCGETS => inner.lflag.bits() as isize,
+            TCSETS => match LocalFlags::from_bits(arg as u32) {
+                Some(lflag) => {
+                    // a half edited line becomes input when leaving canonical mode
+                    if !lflag.contains(LocalFlags::ICANON) {
+                        let line: Vec<u8> = inner.line.drain(..).collect();
+                        inner.input.extend(line);
+                    }
+                    inner.lflag = lflag;
+                    inner.pollers.wake_all();
+                    drop(inner);
+                    self.readers.wake_all();
+                    0
+                }
+                None => -1,
+            },
+            TIOCGPGRP => inner.foreground as isize,
+            TIOCSPGRP => {
+                if arg != 0 && process_group(arg).is_empty() {
+                    return -1;
+                }
+                inner.foreground = arg;
+                0
+            }
+            _ => -1,
+        }
+    }
+
+    /// Take in what the UART received, echo it and raise the signals it
+    /// generates in the foreground process group.
+    pub fn handle_irq(&self) {
+        UART.handle_irq();
+        let mut echo = Vec::new();
+        let mut signals = SignalFlags::empty();
+        let foreground = self.inner.exclusive_session(|inner| {
+            while let Some(ch) = UART.try_read() {
+                if let Some(signal) = inner.receive(ch, &mut echo) {
+                    signals |= signal;
+                }
+            }
+            inner.foreground
+        });
+        for ch in echo {
+            UART.write(ch);
+        }
+        if !signals.is_empty() && foreground != 0 {
+            for process in process_group(foreground) {
+                for signal in [SignalFlags::SIGINT, SignalFlags::SIGTSTP] {
+                    if signals.contains(signal) {
+                        process.send_signal(signal);
+                    }
+                }
+            }
+        }
+        // under the lock, so a reader cannot miss it between checking and
+        // going to sleep
+        let mut inner = self.inner.exclusive_access();
+        self.readers.wake_all();
+        inner.pollers.wake_all();
+    }
+}
+
+lazy_static! {
+    pub static ref TTY: LineDiscipline = LineDiscipline::new();
+}
@@ -1,68 +1,25 @@
-use crate::drivers::bus::virtio::VirtioHal;
-use crate::sync::UPIntrFreeCell;
-use alloc::{sync::Arc, vec::Vec};
+mod virtio_gpu;
+
+pub use virtio_gpu::VirtIOGpu;
+
+use crate::mm::PhysPageNum;
+use alloc::sync::Arc;
 use core::any::Any;
-use embedded_graphics::pixelcolor::Rgb888;
-use tinybmp::Bmp;
-use virtio_drivers::{VirtIOGpu, VirtIOHeader};
-const VIRTIO7: usize = 0x10007000;
+
+/// A display showing one framebuffer.
 pub trait GpuDevice: Send + Sync + Any {
-    fn update_cursor(&self);
+    /// Width and height in pixels.
+    fn resolution(&self) -> (u32, u32);
+    /// The pixels row by row, each as bytes blue, green, red and one
+    /// ignored.
     fn get_framebuffer(&self) -> &mut [u8];
+    /// The first of the contiguous pages of the framebuffer and how many
+    /// there are, for mapping it to user space.
+    fn framebuffer_pages(&self) -> (PhysPageNum, usize);
+    /// Show what was drawn on the framebuffer.
     fn flush(&self);
 }
 
 lazy_static::lazy_static!(
-    pub static ref GPU_DEVICE: Arc<dyn GpuDevice> = Arc::new(VirtIOGpuWrapper::new());
+    pub static ref GPU_DEVICE: Arc<dyn GpuDevice> = Arc::new(VirtIOGpu::new());
 );
-
-pub struct VirtIOGpuWrapper {
-    gpu: UPIntrFreeCell<VirtIOGpu<'static, VirtioHal>>,
-    fb: &'static [u8],
-}
-static BMP_DATA: &[u8] = include_bytes!("../../assert/mouse.bmp");
-impl VirtIOGpuWrapper {
-    pub fn new() -> Self {
-        unsafe {
-            let mut virtio =
-                VirtIOGpu::<VirtioHal>::new(&mut *(VIRTIO7 as *mut VirtIOHeader)).unwrap();
-
-            let fbuffer = virtio.setup_framebuffer().unwrap();
-            let len = fbuffer.len();
-            let ptr = fbuffer.as_mut_ptr();
-            let fb = core::slice::from_raw_parts_mut(ptr, len);
-
-            let bmp = Bmp::<Rgb888>::from_slice(BMP_DATA).unwrap();
-            let raw = bmp.as_raw();
-            let mut b = Vec::new();
-            for i in raw.image_data().chunks(3) {
-                let mut v = i.to_vec();
-                b.append(&mut v);
-                if i == [255, 255, 255] {
-                    b.push(0x0)
-                } else {
-                    b.push(0xff)
-                }
-            }
-            virtio.setup_cursor(b.as_slice(), 50, 50, 50, 50).unwrap();
-
-            Self {
-                gpu: UPIntrFreeCell::new(virtio),
-                fb,
-            }
-        }
-    }
-}
-
-impl GpuDevice for VirtIOGpuWrapper {
-    fn flush(&self) {
-        self.gpu.exclusive_access().flush().unwrap();
-    }
-    fn get_framebuffer(&self) -> &mut [u8] {
-        unsafe {
-            let ptr = self.fb.as_ptr() as *const _ as *mut u8;
-            core::slice::from_raw_parts_mut(ptr, self.fb.len())
-        }
-    }
-    fn update_cursor(&self) {}
-}
@@ -0,0 +1,266 @@
+//! virtio-gpu over the legacy virtio-mmio transport, 2D only: a resource
+//! backed by guest memory is the scanout, and a flush copies it to the
+//! host and shows it. Commands are few and far between, so each is sent
+//! alone and waited for by spinning on the used ring.
+
+use super::GpuDevice;
+use crate::config::PAGE_SIZE;
+use crate::drivers::bus::virtio::{
+    read_reg, virtio_device, write_reg, Descriptor, VirtQueue, DESC_F_NEXT, DESC_F_WRITE,
+    REG_GUEST_FEATURES, REG_GUEST_PAGE_SIZE, REG_STATUS, REG_VERSION, STATUS_ACKNOWLEDGE,
+    STATUS_DRIVER, STATUS_DRIVER_OK, VIRTIO_GPU,
+};
+use crate::mm::{frame_alloc, frame_alloc_contiguous, FrameTracker, PhysAddr, PhysPageNum};
+use crate::sync::SpinMutexIrqSave;
+use alloc::vec::Vec;
+use embedded_graphics::pixelcolor::Rgb888;
+use tinybmp::Bmp;
+
+const CONTROL_QUEUE: u32 = 0;
+const CURSOR_QUEUE: u32 = 1;
+/// Descriptors of each queue: a request and the response to it.
+const QUEUE_SIZE: usize = 2;
+
+const CMD_GET_DISPLAY_INFO: u32 = 0x0100;
+const CMD_RESOURCE_CREATE_2D: u32 = 0x0101;
+const CMD_SET_SCANOUT: u32 = 0x0103;
+const CMD_RESOURCE_FLUSH: u32 = 0x0104;
+const CMD_TRANSFER_TO_HOST_2D: u32 = 0x0105;
+const CMD_RESOURCE_ATTACH_BACKING: u32 = 0x0106;
+const CMD_UPDATE_CURSOR: u32 = 0x0300;
+const RESP_OK_NODATA: u32 = 0x1100;
+const RESP_OK_DISPLAY_INFO: u32 = 0x1101;
+
+/// `struct virtio_gpu_ctrl_hdr`: type, flags, fence id, context and
+/// padding, all 0 but the type.
+const CTRL_HDR_LEN: usize = 24;
+
+/// Bytes blue, green, red and one ignored, as user programs draw.
+const FORMAT_B8G8R8X8: u32 = 2;
+/// The same with alpha instead, for the cursor.
+const FORMAT_B8G8R8A8: u32 = 1;
+const BYTES_PER_PIXEL: usize = 4;
+
+const FRAMEBUFFER_RESOURCE: u32 = 1;
+const CURSOR_RESOURCE: u32 = 2;
+const SCANOUT: u32 = 0;
+/// Cursor images are always this wide and high.
+const CURSOR_SIZE: u32 = 64;
+/// Used when the device has no display enabled, the one QEMU gives by
+/// default.
+const DEFAULT_RESOLUTION: (u32, u32) = (1280, 800);
+
+static CURSOR_BMP: &[u8] = include_bytes!("../../assert/mouse.bmp");
+
+/// A command of type `kind` with `fields` after its header.
+fn command(kind: u32, fields: &[u32]) -> Vec<u8> {
+    let mut request = Vec::with_capacity(CTRL_HDR_LEN + fields.len() * 4);
+    request.extend_from_slice(&kind.to_le_bytes());
+    request.resize(CTRL_HDR_LEN, 0);
+    for field in fields {
+        request.extend_from_slice(&field.to_le_bytes());
+    }
+    request
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap())
+}
+
+/// A virtqueue that holds one request at a time: its first descriptor is
+/// the request, chained to the second for the response, each with a page
+/// of its own.
+struct CommandQueue {
+    queue: VirtQueue,
+    request: FrameTracker,
+    response: FrameTracker,
+}
+
+impl CommandQueue {
+    fn new(base: usize, index: u32) -> Self {
+        let queue = VirtQueue::new(base, index, QUEUE_SIZE).expect("no virtio-gpu queue");
+        let request = frame_alloc().expect("no frames for virtio-gpu requests");
+        let response = frame_alloc().expect("no frames for virtio-gpu responses");
+        *queue.descriptor(0) = Descriptor {
+            addr: PhysAddr::from(request.ppn).0 as u64,
+            len: 0,
+            flags: DESC_F_NEXT,
+            next: 1,
+        };
+        *queue.descriptor(1) = Descriptor {
+            addr: PhysAddr::from(response.ppn).0 as u64,
+            len: PAGE_SIZE as u32,
+            flags: DESC_F_WRITE,
+            next: 0,
+        };
+        // the answer is spun for
+        queue.no_interrupt();
+        Self {
+            queue,
+            request,
+            response,
+        }
+    }
+    /// Send `request` and wait for the device to answer it, returns the
+    /// type of the response and the response.
+    fn send(&mut self, request: &[u8]) -> (u32, &[u8]) {
+        self.request.ppn.get_bytes_array()[..request.len()].copy_from_slice(request);
+        self.queue.descriptor(0).len = request.len() as u32;
+        self.queue.push(0);
+        self.queue.notify();
+        while self.queue.pop_used().is_none() {
+            core::hint::spin_loop();
+        }
+        let response = self.response.ppn.get_bytes_array();
+        (read_u32(response, 0), response)
+    }
+    /// Send a command that answers with nothing, false if it failed.
+    fn send_ok(&mut self, request: &[u8]) -> bool {
+        self.send(request).0 == RESP_OK_NODATA
+    }
+}
+
+struct VirtIOGpuInner {
+    control: CommandQueue,
+    /// only used to set the cursor up, kept as the device holds on to its
+    /// rings
+    #[allow(unused)]
+    cursor: CommandQueue,
+}
+
+pub struct VirtIOGpu {
+    width: u32,
+    height: u32,
+    /// the pages of the scanout resource, contiguous
+    framebuffer: Vec<FrameTracker>,
+    /// the cursor image, kept for the resource it backs
+    #[allow(unused)]
+    cursor_image: Vec<FrameTracker>,
+    inner: SpinMutexIrqSave<VirtIOGpuInner>,
+}
+
+impl VirtIOGpu {
+    pub fn new() -> Self {
+        let base = virtio_device(VIRTIO_GPU, 0).expect("no gpu").reg.0;
+        assert_eq!(
+            read_reg(base, REG_VERSION),
+            1,
+            "only legacy virtio-mmio is supported"
+        );
+        // reset, then say we know the device and want none of its features
+        write_reg(base, REG_STATUS, 0);
+        write_reg(base, REG_STATUS, STATUS_ACKNOWLEDGE);
+        write_reg(base, REG_STATUS, STATUS_ACKNOWLEDGE | STATUS_DRIVER);
+        write_reg(base, REG_GUEST_FEATURES, 0);
+        write_reg(base, REG_GUEST_PAGE_SIZE, PAGE_SIZE as u32);
+        let mut control = CommandQueue::new(base, CONTROL_QUEUE);
+        let mut cursor = CommandQueue::new(base, CURSOR_QUEUE);
+        write_reg(
+            base,
+            REG_STATUS,
+            STATUS_ACKNOWLEDGE | STATUS_DRIVER | STATUS_DRIVER_OK,
+        );
+
+        // the first display as the host shows it
+        let (width, height) = match control.send(&command(CMD_GET_DISPLAY_INFO, &[])) {
+            (RESP_OK_DISPLAY_INFO, info) if read_u32(info, CTRL_HDR_LEN + 16) != 0 => (
+                read_u32(info, CTRL_HDR_LEN + 8),
+                read_u32(info, CTRL_HDR_LEN + 12),
+            ),
+            _ => DEFAULT_RESOLUTION,
+        };
+        let len = width as usize * height as usize * BYTES_PER_PIXEL;
+        let framebuffer =
+            frame_alloc_contiguous(len.div_ceil(PAGE_SIZE)).expect("no frames for the framebuffer");
+        let fb_pa = PhysAddr::from(framebuffer[0].ppn).0 as u64;
+        let set_up = control.send_ok(&command(
+            CMD_RESOURCE_CREATE_2D,
+            &[FRAMEBUFFER_RESOURCE, FORMAT_B8G8R8X8, width, height],
+        )) && control.send_ok(&command(
+            CMD_RESOURCE_ATTACH_BACKING,
+            &[
+                FRAMEBUFFER_RESOURCE,
+                1,
+                fb_pa as u32,
+                (fb_pa >> 32) as u32,
+                len as u32,
+                0,
+            ],
+        )) && control.send_ok(&command(
+            CMD_SET_SCANOUT,
+            &[0, 0, width, height, SCANOUT, FRAMEBUFFER_RESOURCE],
+        ));
+        assert!(set_up, "virtio-gpu scanout setup failed");
+
+        // a missing cursor leaves only the pointer unseen
+        let cursor_len = (CURSOR_SIZE * CURSOR_SIZE) as usize * BYTES_PER_PIXEL;
+        let cursor_image = frame_alloc_contiguous(cursor_len.div_ceil(PAGE_SIZE))
+            .expect("no frames for the cursor");
+        let cursor_pa = PhysAddr::from(cursor_image[0].ppn).0;
+        let pixels = unsafe { core::slice::from_raw_parts_mut(cursor_pa as *mut u8, cursor_len) };
+        let bmp = Bmp::<Rgb888>::from_slice(CURSOR_BMP).unwrap();
+        for (pixel, bgr) in pixels
+            .chunks_mut(BYTES_PER_PIXEL)
+            .zip(bmp.as_raw().image_data().chunks(3))
+        {
+            pixel[..3].copy_from_slice(bgr);
+            // white is the background
+            pixel[3] = if bgr == [255, 255, 255] { 0 } else { 0xff };
+        }
+        let cursor_pa = cursor_pa as u64;
+        let _ = control.send_ok(&command(
+            CMD_RESOURCE_CREATE_2D,
+            &[CURSOR_RESOURCE, FORMAT_B8G8R8A8, CURSOR_SIZE, CURSOR_SIZE],
+        )) && control.send_ok(&command(
+            CMD_RESOURCE_ATTACH_BACKING,
+            &[
+                CURSOR_RESOURCE,
+                1,
+                cursor_pa as u32,
+                (cursor_pa >> 32) as u32,
+                cursor_len as u32,
+                0,
+            ],
+        )) && control.send_ok(&command(
+            CMD_TRANSFER_TO_HOST_2D,
+            &[0, 0, CURSOR_SIZE, CURSOR_SIZE, 0, 0, CURSOR_RESOURCE, 0],
+        )) && cursor.send_ok(&command(
+            CMD_UPDATE_CURSOR,
+            &[SCANOUT, 50, 50, 0, CURSOR_RESOURCE, 0, 0, 0],
+        ));
+
+        Self {
+            width,
+            height,
+            framebuffer,
+            cursor_image,
+            inner: SpinMutexIrqSave::new(VirtIOGpuInner { control, cursor }),
+        }
+    }
+}
+
+impl GpuDevice for VirtIOGpu {
+    fn resolution(&self) -> (u32, u32) {
+        (self.width, self.height)
+    }
+    fn get_framebuffer(&self) -> &mut [u8] {
+        let len = self.width as usize * self.height as usize * BYTES_PER_PIXEL;
+        let pa = PhysAddr::from(self.framebuffer[0].ppn).0;
+        unsafe { core::slice::from_raw_parts_mut(pa as *mut u8, len) }
+    }
+    fn framebuffer_pages(&self) -> (PhysPageNum, usize) {
+        (self.framebuffer[0].ppn, self.framebuffer.len())
+    }
+    fn flush(&self) {
+        let (width, height) = (self.width, self.height);
+        let mut inner = self.inner.exclusive_access();
+        // a failed flush only leaves the screen as it was
+        let _ = inner.control.send_ok(&command(
+            CMD_TRANSFER_TO_HOST_2D,
+            &[0, 0, width, height, 0, 0, FRAMEBUFFER_RESOURCE, 0],
+        )) && inner.control.send_ok(&command(
+            CMD_RESOURCE_FLUSH,
+            &[0, 0, width, height, FRAMEBUFFER_RESOURCE, 0],
+        ));
+    }
+}
@@ -1,83 +1,58 @@
-use crate::drivers::bus::virtio::VirtioHal;
-use crate::sync::{Condvar, UPIntrFreeCell};
-use crate::task::schedule;
-use alloc::collections::VecDeque;
+mod virtio_input;
+
+use crate::drivers::bus::virtio::{virtio_device, VIRTIO_INPUT};
+use crate::sync::PollWaker;
 use alloc::sync::Arc;
 use core::any::Any;
-use virtio_drivers::{VirtIOHeader, VirtIOInput};
-
-const VIRTIO5: usize = 0x10005000;
-const VIRTIO6: usize = 0x10006000;
-
-struct VirtIOInputInner {
-    virtio_input: VirtIOInput<'static, VirtioHal>,
-    events: VecDeque<u64>,
+use virtio_input::VirtIOInputWrapper;
+
+/// Bytes of `struct input_event` on 64 bit Linux, as `/dev/input/event*`
+/// gives them.
+pub const INPUT_EVENT_LEN: usize = 24;
+
+/// An event of an input device, stamped when it came in.
+#[derive(Clone, Copy, Debug)]
+pub struct InputEvent {
+    /// nanoseconds since boot
+    pub time_ns: usize,
+    pub event_type: u16,
+    pub code: u16,
+    pub value: u32,
 }
 
-struct VirtIOInputWrapper {
-    inner: UPIntrFreeCell<VirtIOInputInner>,
-    condvar: Condvar,
+impl InputEvent {
+    /// Laid out as `struct input_event`: seconds and microseconds, then
+    /// the type, code and value.
+    pub fn to_bytes(&self) -> [u8; INPUT_EVENT_LEN] {
+        let mut bytes = [0; INPUT_EVENT_LEN];
+        let sec = (self.time_ns / 1_000_000_000) as u64;
+        let usec = (self.time_ns % 1_000_000_000 / 1000) as u64;
+        bytes[0..8].copy_from_slice(&sec.to_le_bytes());
+        bytes[8..16].copy_from_slice(&usec.to_le_bytes());
+        bytes[16..18].copy_from_slice(&self.event_type.to_le_bytes());
+        bytes[18..20].copy_from_slice(&self.code.to_le_bytes());
+        bytes[20..24].copy_from_slice(&self.value.to_le_bytes());
+        bytes
+    }
 }
 
 pub trait InputDevice: Send + Sync + Any {
-    fn read_event(&self) -> u64;
-    fn handle_irq(&self);
+    /// Take the oldest event, waiting for one unless not `wait`. Fails if
+    /// a signal comes, or with `EAGAIN` if it would wait.
+    fn read_event(&self, wait: bool) -> Result<InputEvent, isize>;
     fn is_empty(&self) -> bool;
+    /// Have `waker` woken once an event comes.
+    fn register_waker(&self, waker: &Arc<PollWaker>);
+    fn handle_irq(&self);
 }
 
 lazy_static::lazy_static!(
-    pub static ref KEYBOARD_DEVICE: Arc<dyn InputDevice> = Arc::new(VirtIOInputWrapper::new(VIRTIO5));
-    pub static ref MOUSE_DEVICE: Arc<dyn InputDevice> = Arc::new(VirtIOInputWrapper::new(VIRTIO6));
+    /// the first input device by address, `/dev/input/event0`
+    pub static ref KEYBOARD_DEVICE: Arc<dyn InputDevice> = Arc::new(VirtIOInputWrapper::new(
+        virtio_device(VIRTIO_INPUT, 0).expect("no keyboard").reg.0
+    ));
+    /// the second, `/dev/input/event1`
+    pub static ref MOUSE_DEVICE: Arc<dyn InputDevice> = Arc::new(VirtIOInputWrapper::new(
+        virtio_device(VIRTIO_INPUT, 1).expect("no mouse").reg.0
+    ));
 );
-
-impl VirtIOInputWrapper {
-    pub fn new(addr: usize) -> Self {
-        let inner = VirtIOInputInner {
-            virtio_input: unsafe {
-                VirtIOInput::<VirtioHal>::new(&mut *(addr as *mut VirtIOHeader)).unwrap()
-            },
-            events: VecDeque::new(),
-        };
-        Self {
-            inner: unsafe { UPIntrFreeCell::new(inner) },
-            condvar: Condvar::new(),
-        }
-    }
-}
-
-impl InputDevice for VirtIOInputWrapper {
-    fn is_empty(&self) -> bool {
-        self.inner.exclusive_access().events.is_empty()
-    }
-
-    fn read_event(&self) -> u64 {
-        loop {
-            let mut inner = self.inner.exclusive_access();
-            if let Some(event) = inner.events.pop_front() {
-                return event;
-            } else {
-                let task_cx_ptr = self.condvar.wait_no_sched();
-                drop(inner);
-                schedule(task_cx_ptr);
-            }
-        }
-    }
-
-    fn handle_irq(&self) {
-        let mut count = 0;
-        let mut result = 0;
-        self.inner.exclusive_session(|inner| {
-            inner.virtio_input.ack_interrupt();
-            while let Some(event) = inner.virtio_input.pop_pending_event() {
-                count += 1;
-                result = (event.event_type as u64) << 48
-                    | (event.code as u64) << 32
-                    | (event.value) as u64;
-                inner.events.push_back(result);
-            }
-        });
-        if count > 0 {
-            self.condvar.signal();
-        };
-    }
-}
@@ -0,0 +1,96 @@
+//! virtio-input through `virtio_drivers`. Events are taken from the device
+//! as they come in and kept in a ring buffer of each device until read.
+
+use super::{InputDevice, InputEvent};
+use crate::drivers::bus::virtio::VirtioHal;
+use crate::fs::EAGAIN;
+use crate::sync::{PollWaker, PollWakers, SpinMutexIrqSave, WaitQueue};
+use crate::task::schedule;
+use crate::timer::get_time_ns;
+use alloc::collections::VecDeque;
+use alloc::sync::Arc;
+use virtio_drivers::{VirtIOHeader, VirtIOInput};
+
+/// Events a device keeps, the oldest are dropped for more.
+const RING_EVENTS: usize = 256;
+
+struct VirtIOInputInner {
+    virtio_input: VirtIOInput<'static, VirtioHal>,
+    events: VecDeque<InputEvent>,
+    /// waiting for an event
+    readers: WaitQueue,
+    pollers: PollWakers,
+}
+
+pub struct VirtIOInputWrapper {
+    inner: SpinMutexIrqSave<VirtIOInputInner>,
+}
+
+impl VirtIOInputWrapper {
+    pub fn new(addr: usize) -> Self {
+        let inner = VirtIOInputInner {
+            virtio_input: unsafe {
+                VirtIOInput::<VirtioHal>::new(&mut *(addr as *mut VirtIOHeader)).unwrap()
+            },
+            events: VecDeque::with_capacity(RING_EVENTS),
+            readers: WaitQueue::new(),
+            pollers: PollWakers::new(),
+        };
+        Self {
+            inner: SpinMutexIrqSave::new(inner),
+        }
+    }
+}
+
+impl InputDevice for VirtIOInputWrapper {
+    fn is_empty(&self) -> bool {
+        self.inner.exclusive_access().events.is_empty()
+    }
+
+    fn read_event(&self, wait: bool) -> Result<InputEvent, isize> {
+        loop {
+            let mut inner = self.inner.exclusive_access();
+            if let Some(event) = inner.events.pop_front() {
+                return Ok(event);
+            }
+            if !wait {
+                return Err(EAGAIN);
+            }
+            match inner.readers.wait_no_sched_interruptible() {
+                Some(task_cx_ptr) => {
+                    drop(inner);
+                    schedule(task_cx_ptr);
+                }
+                None => return Err(-1),
+            }
+        }
+    }
+
+    fn register_waker(&self, waker: &Arc<PollWaker>) {
+        self.inner.exclusive_access().pollers.register(waker);
+    }
+
+    fn handle_irq(&self) {
+        let time_ns = get_time_ns();
+        self.inner.exclusive_session(|inner| {
+            inner.virtio_input.ack_interrupt();
+            let mut count = 0;
+            while let Some(event) = inner.virtio_input.pop_pending_event() {
+                if inner.events.len() == RING_EVENTS {
+                    inner.events.pop_front();
+                }
+                inner.events.push_back(InputEvent {
+                    time_ns,
+                    event_type: event.event_type,
+                    code: event.code,
+                    value: event.value,
+                });
+                count += 1;
+            }
+            if count > 0 {
+                inner.readers.wake_all();
+                inner.pollers.wake_all();
+            }
+        });
+    }
+}
@@ -5,8 +5,11 @@ pub mod gpu;
 pub mod input;
 pub mod net;
 pub mod plic;
+pub mod rng;
+pub mod rtc;
 
 pub use block::BLOCK_DEVICE;
+pub use bus::probe;
 pub use bus::*;
 pub use gpu::*;
 pub use input::*;
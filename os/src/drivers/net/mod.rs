@@ -1,46 +1,30 @@
-use core::any::Any;
+mod loopback;
+mod virtio_net;
+
+pub use loopback::Loopback;
+pub use virtio_net::{VirtIONet, FRAME_MAX};
 
-use crate::drivers::virtio::VirtioHal;
-use crate::sync::UPIntrFreeCell;
+use crate::drivers::bus::virtio::{virtio_device, VIRTIO_NET};
 use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::any::Any;
 use lazy_static::*;
-use virtio_drivers::{VirtIOHeader, VirtIONet};
-
-const VIRTIO8: usize = 0x10004000;
 
 lazy_static! {
-    pub static ref NET_DEVICE: Arc<dyn NetDevice> = Arc::new(VirtIONetWrapper::new());
+    /// `eth0`, none if the machine has no virtio-net device.
+    pub static ref NET_DEVICE: Option<Arc<dyn NetDevice>> = virtio_device(VIRTIO_NET, 0)
+        .map(|_| Arc::new(VirtIONet::new()) as Arc<dyn NetDevice>);
+    /// `lo`, always there.
+    pub static ref LOOPBACK_DEVICE: Arc<dyn NetDevice> = Arc::new(Loopback::new());
 }
 
+/// An Ethernet interface, taking and giving whole frames.
 pub trait NetDevice: Send + Sync + Any {
-    fn transmit(&self, data: &[u8]);
-    fn receive(&self, data: &mut [u8]) -> usize;
-}
-
-pub struct VirtIONetWrapper(UPIntrFreeCell<VirtIONet<'static, VirtioHal>>);
-
-impl NetDevice for VirtIONetWrapper {
-    fn transmit(&self, data: &[u8]) {
-        self.0
-            .exclusive_access()
-            .send(data)
-            .expect("can't send data")
-    }
-
-    fn receive(&self, data: &mut [u8]) -> usize {
-        self.0
-            .exclusive_access()
-            .recv(data)
-            .expect("can't receive data")
-    }
-}
-
-impl VirtIONetWrapper {
-    pub fn new() -> Self {
-        unsafe {
-            let virtio = VirtIONet::<VirtioHal>::new(&mut *(VIRTIO8 as *mut VirtIOHeader))
-                .expect("can't create net device by virtio");
-            VirtIONetWrapper(UPIntrFreeCell::new(virtio))
-        }
-    }
+    fn mac(&self) -> [u8; 6];
+    /// Send `frame`, returns false if the device had no room for it and
+    /// it was dropped.
+    fn transmit(&self, frame: &[u8]) -> bool;
+    /// The next frame received, if any.
+    fn receive(&self) -> Option<Vec<u8>>;
+    fn handle_irq(&self);
 }
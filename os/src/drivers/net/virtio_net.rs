@@ -0,0 +1,161 @@
+//! virtio-net over the legacy virtio-mmio transport, the one QEMU offers
+//! unless told otherwise. Every receive buffer stays posted to the device,
+//! so frames come in by interrupt instead of a receive spinning for them.
+
+use super::NetDevice;
+use crate::config::PAGE_SIZE;
+use crate::drivers::bus::virtio::{
+    read_reg, virtio_device, write_reg, VirtQueue, DESC_F_WRITE, REG_CONFIG, REG_GUEST_FEATURES,
+    REG_GUEST_PAGE_SIZE, REG_HOST_FEATURES, REG_INTERRUPT_ACK, REG_INTERRUPT_STATUS, REG_STATUS,
+    REG_VERSION, STATUS_ACKNOWLEDGE, STATUS_DRIVER, STATUS_DRIVER_OK, VIRTIO_NET,
+};
+use crate::mm::{frame_alloc, FrameTracker, PhysAddr};
+use crate::sync::SpinMutexIrqSave;
+use alloc::vec::Vec;
+
+/// The device has a MAC address of its own.
+const VIRTIO_NET_F_MAC: u32 = 1 << 5;
+/// Used when the device has no MAC address, the one QEMU gives by default.
+const DEFAULT_MAC: [u8; 6] = [0x52, 0x54, 0x00, 0x12, 0x34, 0x56];
+
+const RX_QUEUE: u32 = 0;
+const TX_QUEUE: u32 = 1;
+/// Descriptors of each queue, each with a page of buffer of its own.
+const QUEUE_SIZE: usize = 16;
+
+/// `struct virtio_net_hdr` before each frame, all 0 as no offloading is
+/// negotiated.
+const NET_HDR_LEN: usize = 10;
+/// Largest Ethernet frame, without the checksum the device deals with.
+pub const FRAME_MAX: usize = 1514;
+
+/// A virtqueue whose descriptors each point to a page of buffer, no
+/// buffer taking more than one.
+struct FrameQueue {
+    queue: VirtQueue,
+    /// the buffer of each descriptor
+    buffers: Vec<FrameTracker>,
+    /// descriptors not with the device
+    free: Vec<u16>,
+}
+
+impl FrameQueue {
+    fn new(base: usize, index: u32) -> Self {
+        let queue = VirtQueue::new(base, index, QUEUE_SIZE).expect("no virtio-net queue");
+        let buffers: Vec<FrameTracker> = (0..QUEUE_SIZE)
+            .map(|_| frame_alloc().expect("no frames for virtio-net buffers"))
+            .collect();
+        for (id, buffer) in buffers.iter().enumerate() {
+            queue.descriptor(id as u16).addr = PhysAddr::from(buffer.ppn).0 as u64;
+        }
+        Self {
+            queue,
+            buffers,
+            free: (0..QUEUE_SIZE as u16).rev().collect(),
+        }
+    }
+    fn buffer(&self, id: u16) -> &'static mut [u8] {
+        self.buffers[id as usize].ppn.get_bytes_array()
+    }
+    /// Hand descriptor `id` to the device with `len` bytes of its buffer.
+    fn push(&mut self, id: u16, len: usize, flags: u16) {
+        let desc = self.queue.descriptor(id);
+        desc.len = len as u32;
+        desc.flags = flags;
+        self.queue.push(id);
+    }
+}
+
+struct VirtIONetInner {
+    rx: FrameQueue,
+    tx: FrameQueue,
+}
+
+pub struct VirtIONet {
+    base: usize,
+    mac: [u8; 6],
+    inner: SpinMutexIrqSave<VirtIONetInner>,
+}
+
+impl VirtIONet {
+    pub fn new() -> Self {
+        let base = virtio_device(VIRTIO_NET, 0).expect("no net device").reg.0;
+        assert_eq!(
+            read_reg(base, REG_VERSION),
+            1,
+            "only legacy virtio-mmio is supported"
+        );
+        // reset, then say we know the device
+        write_reg(base, REG_STATUS, 0);
+        write_reg(base, REG_STATUS, STATUS_ACKNOWLEDGE);
+        write_reg(base, REG_STATUS, STATUS_ACKNOWLEDGE | STATUS_DRIVER);
+        let features = read_reg(base, REG_HOST_FEATURES) & VIRTIO_NET_F_MAC;
+        write_reg(base, REG_GUEST_FEATURES, features);
+        write_reg(base, REG_GUEST_PAGE_SIZE, PAGE_SIZE as u32);
+        let mut rx = FrameQueue::new(base, RX_QUEUE);
+        let tx = FrameQueue::new(base, TX_QUEUE);
+        // transmitted buffers are taken back when sending the next frames
+        tx.queue.no_interrupt();
+        let mac = if features & VIRTIO_NET_F_MAC != 0 {
+            core::array::from_fn(|i| unsafe {
+                ((base + REG_CONFIG + i) as *const u8).read_volatile()
+            })
+        } else {
+            DEFAULT_MAC
+        };
+        while let Some(id) = rx.free.pop() {
+            rx.push(id, NET_HDR_LEN + FRAME_MAX, DESC_F_WRITE);
+        }
+        write_reg(
+            base,
+            REG_STATUS,
+            STATUS_ACKNOWLEDGE | STATUS_DRIVER | STATUS_DRIVER_OK,
+        );
+        rx.queue.notify();
+        Self {
+            base,
+            mac,
+            inner: SpinMutexIrqSave::new(VirtIONetInner { rx, tx }),
+        }
+    }
+}
+
+impl NetDevice for VirtIONet {
+    fn mac(&self) -> [u8; 6] {
+        self.mac
+    }
+    fn transmit(&self, frame: &[u8]) -> bool {
+        if frame.len() > FRAME_MAX {
+            return false;
+        }
+        let mut inner = self.inner.exclusive_access();
+        while let Some((id, _)) = inner.tx.queue.pop_used() {
+            inner.tx.free.push(id);
+        }
+        // every buffer is still with the device, drop the frame like a
+        // full NIC queue would
+        let id = match inner.tx.free.pop() {
+            Some(id) => id,
+            None => return false,
+        };
+        let buffer = inner.tx.buffer(id);
+        buffer[..NET_HDR_LEN].fill(0);
+        buffer[NET_HDR_LEN..NET_HDR_LEN + frame.len()].copy_from_slice(frame);
+        inner.tx.push(id, NET_HDR_LEN + frame.len(), 0);
+        inner.tx.queue.notify();
+        true
+    }
+    fn receive(&self) -> Option<Vec<u8>> {
+        let mut inner = self.inner.exclusive_access();
+        let (id, len) = inner.rx.queue.pop_used()?;
+        let frame = inner.rx.buffer(id)[NET_HDR_LEN..len.max(NET_HDR_LEN)].to_vec();
+        // the buffer goes straight back for the next frame
+        inner.rx.push(id, NET_HDR_LEN + FRAME_MAX, DESC_F_WRITE);
+        inner.rx.queue.notify();
+        Some(frame)
+    }
+    fn handle_irq(&self) {
+        let status = read_reg(self.base, REG_INTERRUPT_STATUS);
+        write_reg(self.base, REG_INTERRUPT_ACK, status);
+    }
+}
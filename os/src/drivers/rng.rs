@@ -0,0 +1,223 @@
+//! The entropy pool and the generator drawn from it, for `/dev/urandom`,
+//! `sys_getrandom`, address space layout randomization and TCP sequence
+//! numbers.
+//!
+//! The pool gathers timing samples: the jitter of the timer at boot and
+//! when each device interrupt is taken, and the bytes of virtio-rng if the
+//! machine has one. It is folded into the key of a ChaCha20 generator at
+//! boot and again once enough new samples came in. The key is replaced
+//! after every request, so what was handed out before cannot be worked
+//! out from the state afterwards.
+
+use crate::config::PAGE_SIZE;
+use crate::drivers::bus::virtio::{
+    read_reg, virtio_device, write_reg, VirtQueue, DESC_F_WRITE, REG_GUEST_FEATURES,
+    REG_GUEST_PAGE_SIZE, REG_STATUS, REG_VERSION, STATUS_ACKNOWLEDGE, STATUS_DRIVER,
+    STATUS_DRIVER_OK, VIRTIO_RNG,
+};
+use crate::drivers::rtc::RTC;
+use crate::mm::{frame_alloc, PhysAddr};
+use crate::sync::SpinMutexIrqSave;
+use lazy_static::*;
+use riscv::register::time;
+
+/// Timer samples taken at boot, each around a ChaCha20 block whose time
+/// varies with the caches and the other harts.
+const JITTER_SAMPLES: usize = 256;
+/// Samples gathered before the next request reseeds the generator.
+const RESEED_SAMPLES: usize = 64;
+/// Bytes asked of virtio-rng at boot, one key worth.
+const VIRTIO_RNG_BYTES: usize = 32;
+/// Polls of the used ring before giving up on virtio-rng.
+const VIRTIO_RNG_SPINS: usize = 1 << 24;
+
+/// "expand 32-byte k"
+const SIGMA: [u32; 4] = [0x6170_7865, 0x3320_646e, 0x7962_2d32, 0x6b20_6574];
+
+fn quarter_round(s: &mut [u32; 16], a: usize, b: usize, c: usize, d: usize) {
+    s[a] = s[a].wrapping_add(s[b]);
+    s[d] = (s[d] ^ s[a]).rotate_left(16);
+    s[c] = s[c].wrapping_add(s[d]);
+    s[b] = (s[b] ^ s[c]).rotate_left(12);
+    s[a] = s[a].wrapping_add(s[b]);
+    s[d] = (s[d] ^ s[a]).rotate_left(8);
+    s[c] = s[c].wrapping_add(s[d]);
+    s[b] = (s[b] ^ s[c]).rotate_left(7);
+}
+
+/// Block `counter` of the ChaCha20 stream of `key`, with a zero nonce.
+fn chacha20_block(key: &[u32; 8], counter: u64) -> [u32; 16] {
+    let mut input = [0u32; 16];
+    input[..4].copy_from_slice(&SIGMA);
+    input[4..12].copy_from_slice(key);
+    input[12] = counter as u32;
+    input[13] = (counter >> 32) as u32;
+    let mut s = input;
+    for _ in 0..10 {
+        quarter_round(&mut s, 0, 4, 8, 12);
+        quarter_round(&mut s, 1, 5, 9, 13);
+        quarter_round(&mut s, 2, 6, 10, 14);
+        quarter_round(&mut s, 3, 7, 11, 15);
+        quarter_round(&mut s, 0, 5, 10, 15);
+        quarter_round(&mut s, 1, 6, 11, 12);
+        quarter_round(&mut s, 2, 7, 8, 13);
+        quarter_round(&mut s, 3, 4, 9, 14);
+    }
+    for (word, input) in s.iter_mut().zip(input.iter()) {
+        *word = word.wrapping_add(*input);
+    }
+    s
+}
+
+struct Rng {
+    /// the ChaCha20 key, the only state output is drawn from
+    key: [u32; 8],
+    /// samples gathered since the last reseed
+    pool: [u32; 8],
+    /// the word of the pool the next sample goes into
+    pool_pos: usize,
+    /// how many samples the pool holds
+    pending: usize,
+}
+
+impl Rng {
+    /// Stir a sample into the pool. This only has to keep what the sample
+    /// does not give away, ChaCha20 does the mixing when reseeding.
+    fn mix(&mut self, sample: u64) {
+        let i = self.pool_pos;
+        let j = (i + 1) % self.pool.len();
+        self.pool[i] =
+            (self.pool[i].rotate_left(7) ^ sample as u32).wrapping_add((sample >> 32) as u32);
+        self.pool[j] ^= self.pool[i].rotate_left(13);
+        self.pool_pos = j;
+        self.pending += 1;
+    }
+    /// Fold the pool into the key. The old key stays in, so samples an
+    /// attacker knows or picks take nothing away.
+    fn reseed(&mut self) {
+        for (word, sample) in self.key.iter_mut().zip(self.pool.iter()) {
+            *word ^= *sample;
+        }
+        let block = chacha20_block(&self.key, u64::MAX);
+        self.key.copy_from_slice(&block[..8]);
+        self.pool = [0; 8];
+        self.pending = 0;
+    }
+    fn fill(&mut self, buf: &mut [u8]) {
+        if self.pending >= RESEED_SAMPLES {
+            self.reseed();
+        }
+        // block 0 becomes the next key, the output starts from block 1
+        for (counter, chunk) in buf.chunks_mut(64).enumerate() {
+            let block = chacha20_block(&self.key, counter as u64 + 1);
+            for (bytes, word) in chunk.chunks_mut(4).zip(block.iter()) {
+                bytes.copy_from_slice(&word.to_le_bytes()[..bytes.len()]);
+            }
+        }
+        let next = chacha20_block(&self.key, 0);
+        self.key.copy_from_slice(&next[..8]);
+    }
+}
+
+lazy_static! {
+    static ref RNG: SpinMutexIrqSave<Rng> = SpinMutexIrqSave::new(Rng {
+        key: [0; 8],
+        pool: [0; 8],
+        pool_pos: 0,
+        pending: 0,
+    });
+}
+
+/// Seed the generator at boot, once the RTC can be read and before
+/// anything draws from it. Returns how many bytes virtio-rng gave.
+pub fn init() -> usize {
+    let mut buf = [0u8; VIRTIO_RNG_BYTES];
+    let len = match virtio_device(VIRTIO_RNG, 0) {
+        Some(node) => read_virtio_rng(node.reg.0, &mut buf),
+        None => 0,
+    };
+    let mut rng = RNG.exclusive_access();
+    rng.mix(RTC.read_ns());
+    let mut key = rng.key;
+    for _ in 0..JITTER_SAMPLES {
+        let start = time::read();
+        key = chacha20_block(&key, start as u64)[..8].try_into().unwrap();
+        rng.mix((time::read() - start) as u64 ^ (start as u64).rotate_left(32));
+    }
+    for word in buf[..len].chunks(8) {
+        let mut bytes = [0u8; 8];
+        bytes[..word.len()].copy_from_slice(word);
+        rng.mix(u64::from_le_bytes(bytes));
+    }
+    rng.reseed();
+    len
+}
+
+/// Called on every device interrupt, whose timing nobody outside quite
+/// knows.
+pub fn add_interrupt_randomness(irq: u32) {
+    let sample = time::read() as u64 ^ (irq as u64) << 48;
+    RNG.exclusive_access().mix(sample);
+}
+
+/// Stir in bytes from user space, as written to `/dev/urandom`.
+pub fn add_entropy(bytes: &[u8]) {
+    let mut rng = RNG.exclusive_access();
+    for word in bytes.chunks(8) {
+        let mut sample = [0u8; 8];
+        sample[..word.len()].copy_from_slice(word);
+        rng.mix(u64::from_le_bytes(sample));
+    }
+}
+
+/// Fill `buf` from the generator, reseeding it first if the pool has
+/// enough new samples.
+pub fn fill_bytes(buf: &mut [u8]) {
+    RNG.exclusive_access().fill(buf);
+}
+
+/// The next 64 random bits.
+pub fn random() -> u64 {
+    let mut bytes = [0u8; 8];
+    fill_bytes(&mut bytes);
+    u64::from_le_bytes(bytes)
+}
+
+/// Ask the legacy virtio-rng at `base` for `buf.len()` bytes, with a queue
+/// of a single descriptor polled until it is back. The device is only
+/// read at boot, so it is reset again before its pages are freed. Returns
+/// how many bytes it gave, 0 if it did not answer.
+fn read_virtio_rng(base: usize, buf: &mut [u8]) -> usize {
+    if read_reg(base, REG_VERSION) != 1 {
+        return 0;
+    }
+    write_reg(base, REG_STATUS, 0);
+    write_reg(base, REG_STATUS, STATUS_ACKNOWLEDGE);
+    write_reg(base, REG_STATUS, STATUS_ACKNOWLEDGE | STATUS_DRIVER);
+    write_reg(base, REG_GUEST_FEATURES, 0);
+    write_reg(base, REG_GUEST_PAGE_SIZE, PAGE_SIZE as u32);
+    let (mut queue, buffer) = match (VirtQueue::new(base, 0, 1), frame_alloc()) {
+        (Some(queue), Some(buffer)) => (queue, buffer),
+        _ => return 0,
+    };
+    let desc = queue.descriptor(0);
+    desc.addr = PhysAddr::from(buffer.ppn).0 as u64;
+    desc.len = buf.len() as u32;
+    desc.flags = DESC_F_WRITE;
+    // polled, not interrupted
+    queue.no_interrupt();
+    write_reg(
+        base,
+        REG_STATUS,
+        STATUS_ACKNOWLEDGE | STATUS_DRIVER | STATUS_DRIVER_OK,
+    );
+    queue.push(0);
+    queue.notify();
+    let len = (0..VIRTIO_RNG_SPINS)
+        .find_map(|_| queue.pop_used())
+        .map_or(0, |(_, len)| len);
+    write_reg(base, REG_STATUS, 0);
+    let len = len.min(buf.len());
+    buf[..len].copy_from_slice(&buffer.ppn.get_bytes_array()[..len]);
+    len
+}
@@ -0,0 +1,31 @@
+//! The goldfish RTC of the QEMU virt machine, counting nanoseconds since
+//! the Unix epoch.
+
+use crate::drivers::bus::{device, DeviceKind};
+use lazy_static::*;
+
+const TIME_LOW: usize = 0x00;
+const TIME_HIGH: usize = 0x04;
+
+pub struct GoldfishRtc {
+    base_addr: usize,
+}
+
+impl GoldfishRtc {
+    pub fn new(base_addr: usize) -> Self {
+        Self { base_addr }
+    }
+    /// Reading the low half latches the high half.
+    pub fn read_ns(&self) -> u64 {
+        unsafe {
+            let low = ((self.base_addr + TIME_LOW) as *const u32).read_volatile();
+            let high = ((self.base_addr + TIME_HIGH) as *const u32).read_volatile();
+            (high as u64) << 32 | low as u64
+        }
+    }
+}
+
+lazy_static! {
+    pub static ref RTC: GoldfishRtc =
+        GoldfishRtc::new(device(DeviceKind::Rtc, 0).expect("no rtc").reg.0);
+}
@@ -0,0 +1,195 @@
+//! Boot parameters from the flattened device tree the SBI passes in `a1`:
+//! where memory ends, the `bootargs` of `/chosen` and the device nodes
+//! drivers bind to. Without a device tree the board constants stand in.
+
+use crate::board::{fallback_devices, MEMORY_END};
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use lazy_static::*;
+
+const FDT_MAGIC: u32 = 0xd00d_feed;
+const FDT_BEGIN_NODE: u32 = 1;
+const FDT_END_NODE: u32 = 2;
+const FDT_PROP: u32 = 3;
+const FDT_NOP: u32 = 4;
+const FDT_END: u32 = 9;
+
+pub struct BootInfo {
+    pub memory_end: usize,
+    pub bootargs: String,
+    /// nodes with registers, by address
+    pub devices: Vec<DeviceNode>,
+}
+
+pub struct DeviceNode {
+    /// most specific first
+    pub compatible: Vec<String>,
+    /// base address and size of the first `reg` entry
+    pub reg: (usize, usize),
+    pub irq: Option<usize>,
+}
+
+/// Physical address of the device tree, 0 if there is none.
+static DTB: AtomicUsize = AtomicUsize::new(0);
+
+lazy_static! {
+    static ref BOOT_INFO: BootInfo = {
+        let dtb = DTB.load(Ordering::Relaxed);
+        (dtb != 0)
+            .then(|| parse(dtb))
+            .flatten()
+            .unwrap_or_else(|| BootInfo {
+                memory_end: MEMORY_END,
+                bootargs: String::new(),
+                devices: fallback_devices(),
+            })
+    };
+}
+
+/// Read the device tree at `dtb` while it is still untouched, the frame
+/// allocator hands out the memory it sits in later.
+pub fn init(dtb: usize) {
+    DTB.store(dtb, Ordering::Relaxed);
+    lazy_static::initialize(&BOOT_INFO);
+}
+
+pub fn memory_end() -> usize {
+    BOOT_INFO.memory_end
+}
+
+/// The value of `key=value` in the kernel command line.
+pub fn bootarg(key: &str) -> Option<&'static str> {
+    BOOT_INFO
+        .bootargs
+        .split_whitespace()
+        .find_map(|arg| arg.strip_prefix(key)?.strip_prefix('='))
+}
+
+/// Whether the bare word `flag` is in the kernel command line.
+pub fn bootflag(flag: &str) -> bool {
+    BOOT_INFO.bootargs.split_whitespace().any(|arg| arg == flag)
+}
+
+pub fn bootargs() -> &'static str {
+    &BOOT_INFO.bootargs
+}
+
+pub fn devices() -> &'static [DeviceNode] {
+    &BOOT_INFO.devices
+}
+
+fn be32(bytes: &[u8], offset: usize) -> Option<u32> {
+    let bytes = bytes.get(offset..offset + 4)?;
+    Some(u32::from_be_bytes(bytes.try_into().unwrap()))
+}
+
+/// A number made of `cells` big-endian 32-bit cells.
+fn read_cells(bytes: &[u8], cells: usize) -> Option<usize> {
+    (0..cells).try_fold(0, |value, i| {
+        Some(value << 32 | be32(bytes, i * 4)? as usize)
+    })
+}
+
+/// A NUL terminated string at the start of `bytes`.
+fn c_str(bytes: &[u8]) -> &str {
+    let len = bytes.iter().position(|&b| b == 0).unwrap_or(bytes.len());
+    core::str::from_utf8(&bytes[..len]).unwrap_or("")
+}
+
+/// A node being walked, its properties come before its children.
+struct Node<'a> {
+    name: &'a str,
+    address_cells: usize,
+    size_cells: usize,
+    reg: Option<&'a [u8]>,
+    compatible: &'a [u8],
+    irq: Option<usize>,
+}
+
+fn parse(dtb: usize) -> Option<BootInfo> {
+    let header = unsafe { core::slice::from_raw_parts(dtb as *const u8, 40) };
+    if be32(header, 0)? != FDT_MAGIC {
+        return None;
+    }
+    let blob = unsafe { core::slice::from_raw_parts(dtb as *const u8, be32(header, 4)? as usize) };
+    let structs = blob.get(be32(header, 8)? as usize..)?;
+    let strings = blob.get(be32(header, 12)? as usize..)?;
+    let mut info = BootInfo {
+        memory_end: 0,
+        bootargs: String::new(),
+        devices: Vec::new(),
+    };
+    let mut nodes: Vec<Node> = Vec::new();
+    let mut offset = 0;
+    loop {
+        let token = be32(structs, offset)?;
+        offset += 4;
+        match token {
+            FDT_BEGIN_NODE => {
+                let name = c_str(structs.get(offset..)?);
+                offset += (name.len() + 4) & !3;
+                nodes.push(Node {
+                    name,
+                    address_cells: 2,
+                    size_cells: 1,
+                    reg: None,
+                    compatible: &[],
+                    irq: None,
+                });
+            }
+            FDT_END_NODE => {
+                let node = nodes.pop()?;
+                // reg is laid out by the cells of the parent
+                let (address_cells, size_cells) = nodes
+                    .last()
+                    .map_or((2, 1), |parent| (parent.address_cells, parent.size_cells));
+                let base = node.reg.and_then(|reg| read_cells(reg, address_cells));
+                let size = node
+                    .reg
+                    .and_then(|reg| read_cells(reg.get(address_cells * 4..)?, size_cells));
+                if nodes.len() == 1 && (node.name == "memory" || node.name.starts_with("memory@")) {
+                    if let (Some(base), Some(size)) = (base, size) {
+                        info.memory_end = info.memory_end.max(base + size);
+                    }
+                } else if let (Some(base), Some(size @ 1..)) = (base, size) {
+                    // cpus have a reg without a size and are left out
+                    info.devices.push(DeviceNode {
+                        compatible: node
+                            .compatible
+                            .split(|&b| b == 0)
+                            .filter(|compatible| !compatible.is_empty())
+                            .map(|compatible| c_str(compatible).into())
+                            .collect(),
+                        reg: (base, size),
+                        irq: node.irq,
+                    });
+                }
+            }
+            FDT_PROP => {
+                let len = be32(structs, offset)? as usize;
+                let name = c_str(strings.get(be32(structs, offset + 4)? as usize..)?);
+                let value = structs.get(offset + 8..offset + 8 + len)?;
+                offset += (8 + len + 3) & !3;
+                let node = nodes.last_mut()?;
+                match name {
+                    "#address-cells" => node.address_cells = read_cells(value, 1)?,
+                    "#size-cells" => node.size_cells = read_cells(value, 1)?,
+                    "reg" => node.reg = Some(value),
+                    "compatible" => node.compatible = value,
+                    "interrupts" => node.irq = read_cells(value, 1),
+                    "bootargs" if node.name == "chosen" => info.bootargs = c_str(value).into(),
+                    _ => {}
+                }
+            }
+            FDT_NOP => {}
+            FDT_END => break,
+            _ => return None,
+        }
+    }
+    if info.memory_end == 0 {
+        return None;
+    }
+    info.devices.sort_unstable_by_key(|device| device.reg.0);
+    Some(info)
+}
@@ -1,12 +1,28 @@
     .section .text.entry
     .globl _start
 _start:
-    la sp, boot_stack_top
+    # a0 = hartid, the kernel keeps it in tp, a1 = device tree
+    mv tp, a0
+    call set_boot_stack
     call rust_main
 
+    .globl _start_secondary
+_start_secondary:
+    mv tp, a0
+    call set_boot_stack
+    call rust_main_secondary
+
+# every hart gets BOOT_STACK_SIZE bytes below boot_stack_top - hartid * BOOT_STACK_SIZE
+set_boot_stack:
+    la sp, boot_stack_top
+    slli t0, tp, 16
+    sub sp, sp, t0
+    ret
+
     .section .bss.stack
     .globl boot_stack_lower_bound
 boot_stack_lower_bound:
-    .space 4096 * 16
+    # BOOT_STACK_SIZE * MAX_HARTS
+    .space 4096 * 16 * 8
     .globl boot_stack_top
 boot_stack_top:
@@ -0,0 +1,331 @@
+//! `/dev`, nodes that open as device files rather than as inodes.
+
+use super::stdio::Tty;
+use super::vfs::{FileSystem, VfsInode};
+use super::{File, PollFlags, Stat, StatMode, EAGAIN};
+use crate::drivers::rng;
+use crate::drivers::rtc::RTC;
+use crate::drivers::{InputDevice, GPU_DEVICE, INPUT_EVENT_LEN, KEYBOARD_DEVICE, MOUSE_DEVICE};
+use crate::mm::{PhysPageNum, UserBuffer};
+use crate::sync::PollWaker;
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::any::Any;
+use core::sync::atomic::{AtomicBool, Ordering};
+
+pub struct DevFs;
+
+impl FileSystem for DevFs {
+    fn root_inode(&self) -> Arc<dyn VfsInode> {
+        Arc::new(DevInode::Root)
+    }
+}
+
+#[derive(Clone, Copy)]
+enum DevInode {
+    Root,
+    Zero,
+    Null,
+    Tty,
+    Rtc,
+    Fb0,
+    /// `/dev/input`
+    Input,
+    Event0,
+    Event1,
+    Random,
+    Urandom,
+}
+
+const DEVICES: [(&str, DevInode); 8] = [
+    ("zero", DevInode::Zero),
+    ("null", DevInode::Null),
+    ("tty", DevInode::Tty),
+    ("rtc", DevInode::Rtc),
+    ("fb0", DevInode::Fb0),
+    ("random", DevInode::Random),
+    ("urandom", DevInode::Urandom),
+    ("input", DevInode::Input),
+];
+
+/// The keyboard and the mouse, in the order they are probed.
+const INPUT_DEVICES: [(&str, DevInode); 2] =
+    [("event0", DevInode::Event0), ("event1", DevInode::Event1)];
+
+impl DevInode {
+    /// What a directory holds.
+    fn entries(&self) -> &'static [(&'static str, DevInode)] {
+        match self {
+            Self::Root => &DEVICES,
+            Self::Input => &INPUT_DEVICES,
+            _ => &[],
+        }
+    }
+}
+
+impl VfsInode for DevInode {
+    fn read_at(&self, _offset: usize, _buf: &mut [u8]) -> usize {
+        0
+    }
+    fn stat(&self) -> Stat {
+        let mode = match self {
+            Self::Root | Self::Input => StatMode::DIR,
+            _ => StatMode::CHR,
+        };
+        Stat::new(*self as u64 + 1, mode, 1, 0)
+    }
+    fn find(&self, name: &str) -> Option<Arc<dyn VfsInode>> {
+        self.entries()
+            .iter()
+            .find(|(device_name, _)| *device_name == name)
+            .map(|(_, inode)| Arc::new(*inode) as Arc<dyn VfsInode>)
+    }
+    fn ls(&self) -> Vec<String> {
+        self.entries()
+            .iter()
+            .map(|(name, _)| String::from(*name))
+            .collect()
+    }
+    fn device(&self) -> Option<Arc<dyn File + Send + Sync>> {
+        match self {
+            Self::Root | Self::Input => None,
+            Self::Zero => Some(Arc::new(Zero)),
+            Self::Null => Some(Arc::new(Null)),
+            Self::Tty => Some(Arc::new(Tty::new())),
+            Self::Rtc => Some(Arc::new(Rtc)),
+            Self::Fb0 => Some(Arc::new(Framebuffer)),
+            Self::Event0 => Some(Arc::new(EventFile::new(*self, KEYBOARD_DEVICE.clone()))),
+            Self::Event1 => Some(Arc::new(EventFile::new(*self, MOUSE_DEVICE.clone()))),
+            Self::Random | Self::Urandom => Some(Arc::new(Random { inode: *self })),
+        }
+    }
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+/// Reads as endless zeros, takes any write.
+struct Zero;
+
+/// Reads as empty, takes any write.
+struct Null;
+
+/// The framebuffer of the GPU, only mapped with `mmap` and shown by
+/// `sys_framebuffer_flush`. Its size is that of the framebuffer.
+struct Framebuffer;
+
+/// An input device, read as whole `struct input_event`s.
+struct EventFile {
+    inode: DevInode,
+    device: Arc<dyn InputDevice>,
+    /// `O_NONBLOCK`
+    nonblock: AtomicBool,
+}
+
+/// Reads from the generator of the entropy pool, seeded at boot so it
+/// never waits, `random` and `urandom` alike. Writes are stirred into the
+/// pool.
+struct Random {
+    inode: DevInode,
+}
+
+/// Reads as the wall-clock time, nanoseconds since the Unix epoch as a
+/// little endian u64.
+struct Rtc;
+
+impl File for Zero {
+    fn readable(&self) -> bool {
+        true
+    }
+    fn writable(&self) -> bool {
+        true
+    }
+    fn read(&self, user_buf: UserBuffer) -> usize {
+        let len = user_buf.len();
+        for byte in user_buf {
+            unsafe {
+                *byte = 0;
+            }
+        }
+        len
+    }
+    fn write(&self, user_buf: UserBuffer) -> usize {
+        user_buf.len()
+    }
+    fn stat(&self) -> Option<Stat> {
+        Some(DevInode::Zero.stat())
+    }
+}
+
+impl File for Null {
+    fn readable(&self) -> bool {
+        true
+    }
+    fn writable(&self) -> bool {
+        true
+    }
+    fn read(&self, _user_buf: UserBuffer) -> usize {
+        0
+    }
+    fn write(&self, user_buf: UserBuffer) -> usize {
+        user_buf.len()
+    }
+    fn stat(&self) -> Option<Stat> {
+        Some(DevInode::Null.stat())
+    }
+}
+
+impl File for Rtc {
+    fn readable(&self) -> bool {
+        true
+    }
+    fn writable(&self) -> bool {
+        false
+    }
+    fn read(&self, user_buf: UserBuffer) -> usize {
+        let time = RTC.read_ns().to_le_bytes();
+        let mut len = 0;
+        for (byte, src) in user_buf.into_iter().zip(time.iter()) {
+            unsafe {
+                *byte = *src;
+            }
+            len += 1;
+        }
+        len
+    }
+    fn write(&self, _user_buf: UserBuffer) -> usize {
+        0
+    }
+    fn stat(&self) -> Option<Stat> {
+        Some(DevInode::Rtc.stat())
+    }
+}
+
+impl File for Random {
+    fn readable(&self) -> bool {
+        true
+    }
+    fn writable(&self) -> bool {
+        true
+    }
+    fn read(&self, user_buf: UserBuffer) -> usize {
+        let len = user_buf.len();
+        let mut chunk = [0u8; 256];
+        for (i, byte) in user_buf.into_iter().enumerate() {
+            if i % chunk.len() == 0 {
+                let rest = (len - i).min(chunk.len());
+                rng::fill_bytes(&mut chunk[..rest]);
+            }
+            unsafe {
+                *byte = chunk[i % chunk.len()];
+            }
+        }
+        len
+    }
+    fn write(&self, user_buf: UserBuffer) -> usize {
+        let bytes: Vec<u8> = user_buf.into_iter().map(|byte| unsafe { *byte }).collect();
+        rng::add_entropy(&bytes);
+        bytes.len()
+    }
+    fn stat(&self) -> Option<Stat> {
+        Some(self.inode.stat())
+    }
+}
+
+impl File for Framebuffer {
+    fn readable(&self) -> bool {
+        true
+    }
+    fn writable(&self) -> bool {
+        true
+    }
+    fn read(&self, _user_buf: UserBuffer) -> usize {
+        0
+    }
+    fn write(&self, _user_buf: UserBuffer) -> usize {
+        0
+    }
+    fn stat(&self) -> Option<Stat> {
+        let mut stat = DevInode::Fb0.stat();
+        stat.size = GPU_DEVICE.get_framebuffer().len() as u64;
+        Some(stat)
+    }
+    fn mmap_pages(&self) -> Option<(PhysPageNum, usize)> {
+        Some(GPU_DEVICE.framebuffer_pages())
+    }
+}
+
+impl EventFile {
+    fn new(inode: DevInode, device: Arc<dyn InputDevice>) -> Self {
+        Self {
+            inode,
+            device,
+            nonblock: AtomicBool::new(false),
+        }
+    }
+    /// Read as many events as fit in `buf`, waiting for the first unless
+    /// not `wait`. None if it would wait, a buffer too small for one event
+    /// reads nothing.
+    fn read_events(&self, user_buf: UserBuffer, wait: bool) -> Option<usize> {
+        let room = user_buf.len() / INPUT_EVENT_LEN * INPUT_EVENT_LEN;
+        if room == 0 {
+            return Some(0);
+        }
+        let mut bytes = Vec::with_capacity(room);
+        match self.device.read_event(wait) {
+            Ok(event) => bytes.extend_from_slice(&event.to_bytes()),
+            Err(EAGAIN) => return None,
+            Err(_) => return Some(0),
+        }
+        while bytes.len() < room {
+            match self.device.read_event(false) {
+                Ok(event) => bytes.extend_from_slice(&event.to_bytes()),
+                Err(_) => break,
+            }
+        }
+        for (byte, src) in user_buf.into_iter().zip(bytes.iter()) {
+            unsafe {
+                *byte = *src;
+            }
+        }
+        Some(bytes.len())
+    }
+}
+
+impl File for EventFile {
+    fn readable(&self) -> bool {
+        true
+    }
+    fn writable(&self) -> bool {
+        false
+    }
+    fn read(&self, user_buf: UserBuffer) -> usize {
+        self.read_events(user_buf, true).unwrap()
+    }
+    fn write(&self, _user_buf: UserBuffer) -> usize {
+        0
+    }
+    fn try_read(&self, user_buf: UserBuffer) -> Option<usize> {
+        self.read_events(user_buf, false)
+    }
+    fn nonblocking(&self) -> bool {
+        self.nonblock.load(Ordering::Relaxed)
+    }
+    fn set_nonblocking(&self, nonblocking: bool) {
+        self.nonblock.store(nonblocking, Ordering::Relaxed);
+    }
+    fn stat(&self) -> Option<Stat> {
+        Some(self.inode.stat())
+    }
+    fn poll(&self) -> PollFlags {
+        if self.device.is_empty() {
+            PollFlags::empty()
+        } else {
+            PollFlags::IN
+        }
+    }
+    fn register_waker(&self, waker: &Arc<PollWaker>) {
+        self.device.register_waker(waker);
+    }
+}
@@ -0,0 +1,155 @@
+//! eventfd: a counter as a file, for one thread or process to wake
+//! another that may be polling other files too. A write adds its 8 byte
+//! value to the counter, a read takes the whole count, or one of it in
+//! semaphore mode, waiting while it is 0.
+
+use super::{File, PollFlags};
+use crate::mm::UserBuffer;
+use crate::sync::{PollWaker, PollWakers, SpinMutexIrqSave, WaitQueue};
+use crate::task::schedule;
+use alloc::sync::Arc;
+use core::mem::size_of;
+use core::sync::atomic::{AtomicBool, Ordering};
+
+/// Largest count, a write that would go over waits.
+const COUNT_MAX: u64 = u64::MAX - 1;
+
+pub struct EventFd {
+    /// a read takes one of the count instead of all
+    semaphore: bool,
+    /// `EFD_NONBLOCK`
+    nonblock: AtomicBool,
+    inner: SpinMutexIrqSave<EventFdInner>,
+}
+
+struct EventFdInner {
+    count: u64,
+    /// waiting for a count
+    readers: WaitQueue,
+    /// waiting for room to add to it
+    writers: WaitQueue,
+    pollers: PollWakers,
+}
+
+impl EventFd {
+    pub fn new(count: u64, semaphore: bool) -> Self {
+        Self {
+            semaphore,
+            nonblock: AtomicBool::new(false),
+            inner: SpinMutexIrqSave::new(EventFdInner {
+                count,
+                readers: WaitQueue::new(),
+                writers: WaitQueue::new(),
+                pollers: PollWakers::new(),
+            }),
+        }
+    }
+    /// Take the count into the first 8 bytes of `buf`. Returns 0 if it
+    /// is shorter or a signal came while waiting, and none instead of
+    /// waiting unless `wait`.
+    fn take(&self, buf: UserBuffer, wait: bool) -> Option<usize> {
+        if buf.len() < size_of::<u64>() {
+            return Some(0);
+        }
+        loop {
+            let mut inner = self.inner.exclusive_access();
+            if inner.count > 0 {
+                let value = if self.semaphore { 1 } else { inner.count };
+                inner.count -= value;
+                inner.writers.wake_all();
+                inner.pollers.wake_all();
+                drop(inner);
+                for (byte_ref, byte) in buf.into_iter().zip(value.to_ne_bytes()) {
+                    unsafe { *byte_ref = byte };
+                }
+                return Some(size_of::<u64>());
+            }
+            if !wait {
+                return None;
+            }
+            match inner.readers.wait_no_sched_interruptible() {
+                Some(task_cx_ptr) => {
+                    drop(inner);
+                    schedule(task_cx_ptr);
+                }
+                None => return Some(0),
+            }
+        }
+    }
+    /// Add the value in the first 8 bytes of `buf` to the count. Returns
+    /// 0 if it is shorter, the value is `u64::MAX` or a signal came while
+    /// waiting, and none instead of waiting unless `wait`.
+    fn add(&self, buf: UserBuffer, wait: bool) -> Option<usize> {
+        if buf.len() < size_of::<u64>() {
+            return Some(0);
+        }
+        let mut bytes = [0u8; size_of::<u64>()];
+        for (byte, byte_ref) in bytes.iter_mut().zip(buf) {
+            *byte = unsafe { *byte_ref };
+        }
+        let value = u64::from_ne_bytes(bytes);
+        if value > COUNT_MAX {
+            return Some(0);
+        }
+        loop {
+            let mut inner = self.inner.exclusive_access();
+            if COUNT_MAX - inner.count >= value {
+                inner.count += value;
+                inner.readers.wake_all();
+                inner.pollers.wake_all();
+                return Some(size_of::<u64>());
+            }
+            if !wait {
+                return None;
+            }
+            match inner.writers.wait_no_sched_interruptible() {
+                Some(task_cx_ptr) => {
+                    drop(inner);
+                    schedule(task_cx_ptr);
+                }
+                None => return Some(0),
+            }
+        }
+    }
+}
+
+impl File for EventFd {
+    fn readable(&self) -> bool {
+        true
+    }
+    fn writable(&self) -> bool {
+        true
+    }
+    fn read(&self, buf: UserBuffer) -> usize {
+        self.take(buf, true).unwrap()
+    }
+    fn write(&self, buf: UserBuffer) -> usize {
+        self.add(buf, true).unwrap()
+    }
+    fn try_read(&self, buf: UserBuffer) -> Option<usize> {
+        self.take(buf, false)
+    }
+    fn try_write(&self, buf: UserBuffer) -> Option<usize> {
+        self.add(buf, false)
+    }
+    fn nonblocking(&self) -> bool {
+        self.nonblock.load(Ordering::Relaxed)
+    }
+    fn set_nonblocking(&self, nonblocking: bool) {
+        self.nonblock.store(nonblocking, Ordering::Relaxed);
+    }
+    fn poll(&self) -> PollFlags {
+        let inner = self.inner.exclusive_access();
+        let mut flags = PollFlags::empty();
+        if inner.count > 0 {
+            flags |= PollFlags::IN;
+        }
+        if inner.count < COUNT_MAX {
+            flags |= PollFlags::OUT;
+        }
+        flags
+    }
+    fn register_waker(&self, waker: &Arc<PollWaker>) {
+        self.inner.exclusive_access().pollers.register(waker);
+    }
+}
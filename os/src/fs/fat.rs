@@ -0,0 +1,52 @@
+use super::vfs::{FileSystem, VfsInode};
+use super::{Stat, StatMode};
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::any::Any;
+use fat32::{Fat32FileSystem, Inode};
+
+/// FAT32 as found on SD-card images, read only.
+pub struct Fat32 {
+    root_inode: Arc<Inode>,
+}
+
+impl Fat32 {
+    pub fn new(fs: Arc<Fat32FileSystem>) -> Self {
+        Self {
+            root_inode: Arc::new(Fat32FileSystem::root_inode(&fs)),
+        }
+    }
+}
+
+impl FileSystem for Fat32 {
+    fn root_inode(&self) -> Arc<dyn VfsInode> {
+        self.root_inode.clone()
+    }
+}
+
+impl VfsInode for Inode {
+    fn read_at(&self, offset: usize, buf: &mut [u8]) -> usize {
+        Inode::read_at(self, offset, buf)
+    }
+    fn stat(&self) -> Stat {
+        let mode = if Inode::is_dir(self) {
+            StatMode::DIR
+        } else {
+            StatMode::FILE
+        };
+        Stat::new(self.first_cluster() as u64, mode, 1, self.size() as u64)
+    }
+    fn is_dir(&self) -> bool {
+        Inode::is_dir(self)
+    }
+    fn find(&self, name: &str) -> Option<Arc<dyn VfsInode>> {
+        Inode::find(self, name).map(|inode| inode as Arc<dyn VfsInode>)
+    }
+    fn ls(&self) -> Vec<String> {
+        Inode::ls(self)
+    }
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
@@ -1,32 +1,43 @@
-use super::File;
+use super::devfs::DevFs;
+use super::fat::Fat32;
+use super::procfs::ProcFs;
+use super::vfs::{lookup, lookup_parent, mount, FileSystem, VfsInode};
+use super::{File, Stat, StatMode};
 use crate::drivers::BLOCK_DEVICE;
 use crate::mm::UserBuffer;
-use crate::sync::UPIntrFreeCell;
+use crate::sync::SpinMutexIrqSave;
+use alloc::format;
+use alloc::string::String;
 use alloc::sync::Arc;
 use alloc::vec::Vec;
 use bitflags::*;
-use easy_fs::{EasyFileSystem, Inode};
-use lazy_static::*;
+use core::any::Any;
+use easy_fs::{block_cache_sync_all, EasyFileSystem, Inode};
+use fat32::Fat32FileSystem;
+use log::{info, warn};
 
 pub struct OSInode {
     readable: bool,
     writable: bool,
-    inner: UPIntrFreeCell<OSInodeInner>,
+    inner: SpinMutexIrqSave<OSInodeInner>,
 }
 
 pub struct OSInodeInner {
     offset: usize,
-    inode: Arc<Inode>,
+    inode: Arc<dyn VfsInode>,
 }
 
 impl OSInode {
-    pub fn new(readable: bool, writable: bool, inode: Arc<Inode>) -> Self {
+    pub fn new(readable: bool, writable: bool, inode: Arc<dyn VfsInode>) -> Self {
         Self {
             readable,
             writable,
-            inner: unsafe { UPIntrFreeCell::new(OSInodeInner { offset: 0, inode }) },
+            inner: SpinMutexIrqSave::new(OSInodeInner { offset: 0, inode }),
         }
     }
+    pub fn inode(&self) -> Arc<dyn VfsInode> {
+        Arc::clone(&self.inner.exclusive_access().inode)
+    }
     pub fn read_all(&self) -> Vec<u8> {
         let mut inner = self.inner.exclusive_access();
         let mut buffer = [0u8; 512];
@@ -43,17 +54,112 @@ impl OSInode {
     }
 }
 
-lazy_static! {
-    pub static ref ROOT_INODE: Arc<Inode> = {
-        let efs = EasyFileSystem::open(BLOCK_DEVICE.clone());
-        Arc::new(EasyFileSystem::root_inode(&efs))
+/// easy-fs on the block device.
+struct EasyFs {
+    root_inode: Arc<Inode>,
+}
+
+impl FileSystem for EasyFs {
+    fn root_inode(&self) -> Arc<dyn VfsInode> {
+        self.root_inode.clone()
+    }
+    fn sync(&self) {
+        block_cache_sync_all();
+    }
+}
+
+impl VfsInode for Inode {
+    fn read_at(&self, offset: usize, buf: &mut [u8]) -> usize {
+        Inode::read_at(self, offset, buf)
+    }
+    fn writable(&self) -> bool {
+        true
+    }
+    fn write_at(&self, offset: usize, buf: &[u8]) -> usize {
+        Inode::write_at(self, offset, buf)
+    }
+    fn clear(&self) {
+        Inode::clear(self)
+    }
+    fn stat(&self) -> Stat {
+        let mode = if Inode::is_dir(self) {
+            StatMode::DIR
+        } else {
+            StatMode::FILE
+        };
+        Stat::new(
+            self.inode_id() as u64,
+            mode,
+            self.nlink(),
+            self.size() as u64,
+        )
+    }
+    fn is_dir(&self) -> bool {
+        Inode::is_dir(self)
+    }
+    fn find(&self, name: &str) -> Option<Arc<dyn VfsInode>> {
+        Inode::find(self, name).map(|inode| inode as Arc<dyn VfsInode>)
+    }
+    fn create(&self, name: &str) -> Option<Arc<dyn VfsInode>> {
+        Inode::create(self, name).map(|inode| inode as Arc<dyn VfsInode>)
+    }
+    fn create_dir(&self, name: &str) -> Option<Arc<dyn VfsInode>> {
+        Inode::create_dir(self, name).map(|inode| inode as Arc<dyn VfsInode>)
+    }
+    fn link(&self, name: &str, target: &Arc<dyn VfsInode>) -> bool {
+        match target.as_any().downcast_ref::<Inode>() {
+            Some(target) => Inode::link(self, name, target),
+            None => false,
+        }
+    }
+    fn unlink(&self, name: &str) -> bool {
+        Inode::unlink(self, name)
+    }
+    fn ls(&self) -> Vec<String> {
+        Inode::ls(self)
+    }
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+/// Mount the block device as the root, FAT32 if it is an SD-card image
+/// and easy-fs otherwise.
+pub fn init() {
+    let fs: Arc<dyn FileSystem> = match Fat32FileSystem::open(BLOCK_DEVICE.clone()) {
+        Some(fat) => {
+            info!("mount fat32 on /");
+            Arc::new(Fat32::new(fat))
+        }
+        None => {
+            let efs = EasyFileSystem::open(BLOCK_DEVICE.clone());
+            let root_inode = Arc::new(EasyFileSystem::root_inode(&efs));
+            Arc::new(EasyFs { root_inode })
+        }
     };
+    assert!(mount("/", fs));
+    mount_pseudo("proc", Arc::new(ProcFs));
+    mount_pseudo("dev", Arc::new(DevFs));
+}
+
+/// Mount `fs` on the directory `name` under the root, creating it if the
+/// root file system can.
+fn mount_pseudo(name: &str, fs: Arc<dyn FileSystem>) {
+    let root_inode = lookup("/").unwrap();
+    if root_inode.find(name).is_none() {
+        root_inode.create_dir(name);
+    }
+    if !mount(&format!("/{}", name), fs) {
+        warn!("cannot mount /{}", name);
+    }
 }
 
 pub fn list_apps() {
     println!("/**** APPS ****");
-    for app in ROOT_INODE.ls() {
-        println!("{}", app);
+    for app in lookup("/").unwrap().ls() {
+        if app != "." && app != ".." {
+            println!("{}", app);
+        }
     }
     println!("**************/")
 }
@@ -65,6 +171,8 @@ bitflags! {
         const RDWR = 1 << 1;
         const CREATE = 1 << 9;
         const TRUNC = 1 << 10;
+        /// reads and writes that would wait fail with `EAGAIN` instead
+        const NONBLOCK = 1 << 11;
     }
 }
 
@@ -72,9 +180,10 @@ impl OpenFlags {
     /// Do not check validity for simplicity
     /// Return (readable, writable)
     pub fn read_write(&self) -> (bool, bool) {
-        if self.is_empty() {
+        let flags = self.difference(Self::NONBLOCK);
+        if flags.is_empty() {
             (true, false)
-        } else if self.contains(Self::WRONLY) {
+        } else if flags.contains(Self::WRONLY) {
             (false, true)
         } else {
             (true, true)
@@ -82,27 +191,37 @@ impl OpenFlags {
     }
 }
 
-pub fn open_file(name: &str, flags: OpenFlags) -> Option<Arc<OSInode>> {
+pub fn open_file(path: &str, flags: OpenFlags) -> Option<Arc<OSInode>> {
     let (readable, writable) = flags.read_write();
-    if flags.contains(OpenFlags::CREATE) {
-        if let Some(inode) = ROOT_INODE.find(name) {
+    let inode = if flags.contains(OpenFlags::CREATE) {
+        let (parent, name) = lookup_parent(path)?;
+        if let Some(inode) = parent.find(name) {
+            if inode.is_dir() || !inode.writable() {
+                return None;
+            }
             // clear size
             inode.clear();
-            Some(Arc::new(OSInode::new(readable, writable, inode)))
+            inode
         } else {
             // create file
-            ROOT_INODE
-                .create(name)
-                .map(|inode| Arc::new(OSInode::new(readable, writable, inode)))
+            parent.create(name)?
         }
     } else {
-        ROOT_INODE.find(name).map(|inode| {
-            if flags.contains(OpenFlags::TRUNC) {
-                inode.clear();
-            }
-            Arc::new(OSInode::new(readable, writable, inode))
-        })
-    }
+        let inode = lookup(path)?;
+        if writable && (inode.is_dir() || !inode.writable()) {
+            return None;
+        }
+        if flags.contains(OpenFlags::TRUNC) {
+            inode.clear();
+        }
+        inode
+    };
+    Some(Arc::new(OSInode::new(readable, writable, inode)))
+}
+
+/// Open the device node at `path` as its device file.
+pub fn open_device(path: &str) -> Option<Arc<dyn File + Send + Sync>> {
+    lookup(path)?.device()
 }
 
 impl File for OSInode {
@@ -130,10 +249,16 @@ impl File for OSInode {
         let mut total_write_size = 0usize;
         for slice in buf.buffers.iter() {
             let write_size = inner.inode.write_at(inner.offset, *slice);
-            assert_eq!(write_size, slice.len());
             inner.offset += write_size;
             total_write_size += write_size;
+            // the file system is full or read-only
+            if write_size < slice.len() {
+                break;
+            }
         }
         total_write_size
     }
+    fn stat(&self) -> Option<Stat> {
+        Some(self.inode().stat())
+    }
 }
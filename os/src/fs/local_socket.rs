@@ -0,0 +1,383 @@
+//! Local stream sockets, like UNIX domain ones. A connected socket is one
+//! end of a pair of byte channels, one each way, so both ends read and
+//! write. A listening socket is found by the path it was bound to and
+//! hands a new connected socket to `accept` for each `connect`.
+//!
+//! The paths live in a namespace of their own, no file is created for
+//! them, and a path is free again once its socket is closed.
+
+use super::{File, PollFlags, Stat, StatMode, EAGAIN};
+use crate::mm::UserBuffer;
+use crate::sync::{PollWaker, PollWakers, SpinMutex, SpinMutexIrqSave, WaitQueue};
+use crate::task::{current_add_signal, schedule, SignalFlags};
+use alloc::collections::{BTreeMap, VecDeque};
+use alloc::string::String;
+use alloc::sync::{Arc, Weak};
+use core::sync::atomic::{AtomicBool, Ordering};
+use lazy_static::*;
+
+/// Bytes a channel holds before its writer waits.
+const CHANNEL_BYTES: usize = 4096;
+
+/// Bytes going one way between two connected sockets.
+struct Channel {
+    bytes: VecDeque<u8>,
+    /// the reading end was closed
+    reader_gone: bool,
+    /// the writing end was closed
+    writer_gone: bool,
+    /// waiting for bytes to read
+    readers: WaitQueue,
+    /// waiting for room to write
+    writers: WaitQueue,
+    /// polling either end
+    pollers: PollWakers,
+}
+
+type ChannelRef = Arc<SpinMutexIrqSave<Channel>>;
+
+impl Channel {
+    fn new() -> ChannelRef {
+        Arc::new(SpinMutexIrqSave::new(Self {
+            bytes: VecDeque::new(),
+            reader_gone: false,
+            writer_gone: false,
+            readers: WaitQueue::new(),
+            writers: WaitQueue::new(),
+            pollers: PollWakers::new(),
+        }))
+    }
+}
+
+enum SocketState {
+    /// neither listening nor connected yet, maybe bound to a path
+    Idle,
+    Listening {
+        /// connected sockets not accepted yet
+        pending: VecDeque<Arc<LocalSocket>>,
+        /// how many may be pending
+        backlog: usize,
+        /// waiting in `accept`
+        acceptors: WaitQueue,
+        /// polling for a connection to accept
+        pollers: PollWakers,
+    },
+    Connected {
+        rx: ChannelRef,
+        tx: ChannelRef,
+    },
+}
+
+pub struct LocalSocket {
+    state: SpinMutexIrqSave<SocketState>,
+    /// `O_NONBLOCK`, `accept` fails too instead of waiting
+    nonblock: AtomicBool,
+}
+
+lazy_static! {
+    /// Sockets by the path they were bound to.
+    static ref BOUND_SOCKETS: SpinMutex<BTreeMap<String, Weak<LocalSocket>>> =
+        SpinMutex::new(BTreeMap::new());
+}
+
+impl LocalSocket {
+    pub fn new() -> Self {
+        Self {
+            state: SpinMutexIrqSave::new(SocketState::Idle),
+            nonblock: AtomicBool::new(false),
+        }
+    }
+    fn connected(rx: ChannelRef, tx: ChannelRef) -> Self {
+        Self {
+            state: SpinMutexIrqSave::new(SocketState::Connected { rx, tx }),
+            nonblock: AtomicBool::new(false),
+        }
+    }
+    /// Give `socket` the name `path`, returns false if it is taken.
+    pub fn bind(socket: &Arc<Self>, path: String) -> bool {
+        if !matches!(*socket.state.exclusive_access(), SocketState::Idle) {
+            return false;
+        }
+        let mut bound = BOUND_SOCKETS.exclusive_access();
+        // paths of the sockets that were closed are free again
+        bound.retain(|_, socket| socket.strong_count() > 0);
+        if bound.contains_key(&path)
+            || bound
+                .values()
+                .any(|bound| core::ptr::eq(bound.as_ptr(), Arc::as_ptr(socket)))
+        {
+            return false;
+        }
+        bound.insert(path, Arc::downgrade(socket));
+        true
+    }
+    /// Start taking connections, at most `backlog` of them waiting for
+    /// `accept` at a time.
+    pub fn listen(&self, backlog: usize) -> bool {
+        let mut state = self.state.exclusive_access();
+        match *state {
+            SocketState::Idle => {
+                *state = SocketState::Listening {
+                    pending: VecDeque::new(),
+                    backlog: backlog.max(1),
+                    acceptors: WaitQueue::new(),
+                    pollers: PollWakers::new(),
+                };
+                true
+            }
+            // only the backlog changes
+            SocketState::Listening {
+                backlog: ref mut old,
+                ..
+            } => {
+                *old = backlog.max(1);
+                true
+            }
+            SocketState::Connected { .. } => false,
+        }
+    }
+    /// Connect to the socket listening at `path`. Fails instead of
+    /// waiting if its backlog is full.
+    pub fn connect(&self, path: &str) -> bool {
+        let mut state = self.state.exclusive_access();
+        if !matches!(*state, SocketState::Idle) {
+            return false;
+        }
+        let server = match BOUND_SOCKETS
+            .exclusive_access()
+            .get(path)
+            .and_then(Weak::upgrade)
+        {
+            Some(server) => server,
+            None => return false,
+        };
+        // connecting to itself would take its lock twice
+        if core::ptr::eq(Arc::as_ptr(&server), self) {
+            return false;
+        }
+        let mut server_state = server.state.exclusive_access();
+        match *server_state {
+            SocketState::Listening {
+                ref mut pending,
+                backlog,
+                ref acceptors,
+                ref mut pollers,
+            } if pending.len() < backlog => {
+                let (rx, tx) = (Channel::new(), Channel::new());
+                pending.push_back(Arc::new(Self::connected(tx.clone(), rx.clone())));
+                acceptors.wake_one();
+                pollers.wake_all();
+                *state = SocketState::Connected { rx, tx };
+                true
+            }
+            _ => false,
+        }
+    }
+    /// Wait for a connection and return the socket for it. Fails if this
+    /// one is not listening or a signal came, or with `EAGAIN` if it is
+    /// nonblocking and nobody is connecting.
+    pub fn accept(&self) -> Result<Arc<Self>, isize> {
+        loop {
+            let mut state = self.state.exclusive_access();
+            let task_cx_ptr = match *state {
+                SocketState::Listening {
+                    ref mut pending,
+                    ref acceptors,
+                    ..
+                } => match pending.pop_front() {
+                    Some(socket) => return Ok(socket),
+                    None if self.nonblocking() => return Err(EAGAIN),
+                    None => match acceptors.wait_no_sched_interruptible() {
+                        Some(task_cx_ptr) => task_cx_ptr,
+                        None => return Err(-1),
+                    },
+                },
+                _ => return Err(-1),
+            };
+            drop(state);
+            schedule(task_cx_ptr);
+        }
+    }
+    fn channels(&self) -> Option<(ChannelRef, ChannelRef)> {
+        match &*self.state.exclusive_access() {
+            SocketState::Connected { rx, tx } => Some((rx.clone(), tx.clone())),
+            _ => None,
+        }
+    }
+    /// Read what has arrived, waiting if nothing has unless not `wait`,
+    /// then returns none. Returns 0 once the peer is closed, or at once
+    /// if not connected.
+    fn receive(&self, buf: UserBuffer, wait: bool) -> Option<usize> {
+        let rx = match self.channels() {
+            Some((rx, _)) => rx,
+            None => return Some(0),
+        };
+        loop {
+            let mut channel = rx.exclusive_access();
+            if channel.bytes.is_empty() {
+                if channel.writer_gone {
+                    return Some(0);
+                }
+                if !wait {
+                    return None;
+                }
+                match channel.readers.wait_no_sched_interruptible() {
+                    Some(task_cx_ptr) => {
+                        drop(channel);
+                        schedule(task_cx_ptr);
+                        continue;
+                    }
+                    None => return Some(0),
+                }
+            }
+            let mut read = 0;
+            for byte_ref in buf {
+                match channel.bytes.pop_front() {
+                    Some(byte) => unsafe { *byte_ref = byte },
+                    None => break,
+                }
+                read += 1;
+            }
+            channel.writers.wake_all();
+            channel.pollers.wake_all();
+            return Some(read);
+        }
+    }
+    /// Write all of `buf`, waiting for room as needed unless not `wait`,
+    /// then returns none if there was no room at all. A closed peer
+    /// raises SIGPIPE like a pipe without readers.
+    fn send(&self, buf: UserBuffer, wait: bool) -> Option<usize> {
+        let tx = match self.channels() {
+            Some((_, tx)) => tx,
+            None => return Some(0),
+        };
+        let mut buf_iter = buf.into_iter().peekable();
+        let mut written = 0;
+        while buf_iter.peek().is_some() {
+            let mut channel = tx.exclusive_access();
+            if channel.reader_gone {
+                drop(channel);
+                current_add_signal(SignalFlags::SIGPIPE);
+                return Some(written);
+            }
+            if channel.bytes.len() == CHANNEL_BYTES {
+                if !wait {
+                    return (written > 0).then_some(written);
+                }
+                match channel.writers.wait_no_sched_interruptible() {
+                    Some(task_cx_ptr) => {
+                        drop(channel);
+                        schedule(task_cx_ptr);
+                        continue;
+                    }
+                    None => return Some(written),
+                }
+            }
+            while channel.bytes.len() < CHANNEL_BYTES {
+                match buf_iter.next() {
+                    Some(byte_ref) => channel.bytes.push_back(unsafe { *byte_ref }),
+                    None => break,
+                }
+                written += 1;
+            }
+            channel.readers.wake_all();
+            channel.pollers.wake_all();
+        }
+        Some(written)
+    }
+}
+
+/// Two sockets connected to each other.
+pub fn make_socket_pair() -> (Arc<LocalSocket>, Arc<LocalSocket>) {
+    let (a_to_b, b_to_a) = (Channel::new(), Channel::new());
+    (
+        Arc::new(LocalSocket::connected(b_to_a.clone(), a_to_b.clone())),
+        Arc::new(LocalSocket::connected(a_to_b, b_to_a)),
+    )
+}
+
+impl Drop for LocalSocket {
+    fn drop(&mut self) {
+        // the peer finds this end closed once it looks again
+        if let SocketState::Connected { rx, tx } = &*self.state.exclusive_access() {
+            let mut rx = rx.exclusive_access();
+            rx.reader_gone = true;
+            rx.writers.wake_all();
+            rx.pollers.wake_all();
+            drop(rx);
+            let mut tx = tx.exclusive_access();
+            tx.writer_gone = true;
+            tx.readers.wake_all();
+            tx.pollers.wake_all();
+        }
+    }
+}
+
+impl File for LocalSocket {
+    fn readable(&self) -> bool {
+        true
+    }
+    fn writable(&self) -> bool {
+        true
+    }
+    fn read(&self, buf: UserBuffer) -> usize {
+        self.receive(buf, true).unwrap()
+    }
+    fn write(&self, buf: UserBuffer) -> usize {
+        self.send(buf, true).unwrap()
+    }
+    fn try_read(&self, buf: UserBuffer) -> Option<usize> {
+        self.receive(buf, false)
+    }
+    fn try_write(&self, buf: UserBuffer) -> Option<usize> {
+        self.send(buf, false)
+    }
+    fn nonblocking(&self) -> bool {
+        self.nonblock.load(Ordering::Relaxed)
+    }
+    fn set_nonblocking(&self, nonblocking: bool) {
+        self.nonblock.store(nonblocking, Ordering::Relaxed);
+    }
+    fn stat(&self) -> Option<Stat> {
+        let size = self
+            .channels()
+            .map_or(0, |(rx, _)| rx.exclusive_access().bytes.len());
+        Some(Stat::new(0, StatMode::SOCK, 1, size as u64))
+    }
+    fn poll(&self) -> PollFlags {
+        let (rx, tx) = match &*self.state.exclusive_access() {
+            SocketState::Idle => return PollFlags::HUP,
+            SocketState::Listening { pending, .. } if pending.is_empty() => {
+                return PollFlags::empty()
+            }
+            SocketState::Listening { .. } => return PollFlags::IN,
+            SocketState::Connected { rx, tx } => (rx.clone(), tx.clone()),
+        };
+        let mut flags = PollFlags::empty();
+        let rx = rx.exclusive_access();
+        if !rx.bytes.is_empty() {
+            flags |= PollFlags::IN;
+        }
+        if rx.writer_gone {
+            flags |= PollFlags::HUP;
+        }
+        drop(rx);
+        let tx = tx.exclusive_access();
+        if tx.reader_gone {
+            flags |= PollFlags::ERR;
+        } else if tx.bytes.len() < CHANNEL_BYTES {
+            flags |= PollFlags::OUT;
+        }
+        flags
+    }
+    fn register_waker(&self, waker: &Arc<PollWaker>) {
+        match &mut *self.state.exclusive_access() {
+            SocketState::Idle => {}
+            SocketState::Listening { pollers, .. } => pollers.register(waker),
+            SocketState::Connected { rx, tx } => {
+                rx.exclusive_access().pollers.register(waker);
+                tx.exclusive_access().pollers.register(waker);
+            }
+        }
+    }
+}
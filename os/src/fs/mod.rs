@@ -1,16 +1,147 @@
+mod devfs;
+mod eventfd;
+mod fat;
 mod inode;
+mod local_socket;
 mod pipe;
+mod procfs;
 mod stdio;
+mod vfs;
 
-use crate::mm::UserBuffer;
+use crate::mm::{PhysPageNum, UserBuffer};
+use crate::sync::PollWaker;
+use crate::task::AsAny;
+use alloc::sync::Arc;
+use bitflags::*;
 
-pub trait File: Send + Sync {
+/// An open file. Sockets and the like are found behind `dyn File` with
+/// `as_any`.
+pub trait File: AsAny + Send + Sync {
     fn readable(&self) -> bool;
     fn writable(&self) -> bool;
     fn read(&self, buf: UserBuffer) -> usize;
     fn write(&self, buf: UserBuffer) -> usize;
+    /// Network sockets have no status for now.
+    fn stat(&self) -> Option<Stat> {
+        None
+    }
+    /// Device specific requests, only the console takes any.
+    fn ioctl(&self, _cmd: u32, _arg: usize) -> isize {
+        -1
+    }
+    /// What a read or write would not wait for now. Files that never
+    /// wait are always ready for what they can do.
+    fn poll(&self) -> PollFlags {
+        let mut flags = PollFlags::empty();
+        if self.readable() {
+            flags |= PollFlags::IN;
+        }
+        if self.writable() {
+            flags |= PollFlags::OUT;
+        }
+        flags
+    }
+    /// Have `waker` woken once `poll` may tell something new. Files
+    /// that are always ready need not.
+    fn register_waker(&self, _waker: &Arc<PollWaker>) {}
+    /// Whether reads and writes that would wait fail with `EAGAIN`.
+    fn nonblocking(&self) -> bool {
+        false
+    }
+    /// Files that never wait ignore it.
+    fn set_nonblocking(&self, _nonblocking: bool) {}
+    /// `read` without waiting, none if it would wait before reading
+    /// anything.
+    fn try_read(&self, buf: UserBuffer) -> Option<usize> {
+        Some(self.read(buf))
+    }
+    /// `write` without waiting, none if it would wait before writing
+    /// anything.
+    fn try_write(&self, buf: UserBuffer) -> Option<usize> {
+        Some(self.write(buf))
+    }
+    /// The first of the contiguous pages of device memory behind the
+    /// file and how many there are, for `sys_mmap` to map them as they
+    /// are. Other files cannot be mapped.
+    fn mmap_pages(&self) -> Option<(PhysPageNum, usize)> {
+        None
+    }
 }
 
-pub use inode::{list_apps, open_file, OpenFlags};
-pub use pipe::make_pipe;
+/// A read or write on a nonblocking file would have waited.
+pub const EAGAIN: isize = -3;
+
+/// File status filled in by `sys_fstat`, laid out the same as in the
+/// user library.
+#[repr(C)]
+#[derive(Debug, Default)]
+pub struct Stat {
+    /// ID of the device holding the file
+    pub dev: u64,
+    /// inode number
+    pub ino: u64,
+    /// file type
+    pub mode: StatMode,
+    /// number of hard links
+    pub nlink: u32,
+    /// size in bytes, or bytes waiting to be read from a pipe
+    pub size: u64,
+    /// last access, easy-fs keeps no times so these are 0 for now
+    pub atime: i64,
+    /// last modification
+    pub mtime: i64,
+    /// last status change
+    pub ctime: i64,
+}
+
+impl Stat {
+    pub fn new(ino: u64, mode: StatMode, nlink: u32, size: u64) -> Self {
+        Self {
+            ino,
+            mode,
+            nlink,
+            size,
+            ..Default::default()
+        }
+    }
+    pub fn as_bytes(&self) -> &[u8] {
+        unsafe {
+            core::slice::from_raw_parts(self as *const _ as *const u8, core::mem::size_of::<Self>())
+        }
+    }
+}
+
+bitflags! {
+    #[derive(Default)]
+    pub struct StatMode: u32 {
+        const NULL = 0;
+        const FIFO = 0o010000;
+        const CHR = 0o020000;
+        const DIR = 0o040000;
+        const FILE = 0o100000;
+        const SOCK = 0o140000;
+    }
+}
+
+bitflags! {
+    /// Readiness of a file, numbered as the `poll` events of Linux.
+    pub struct PollFlags: u16 {
+        /// there is something to read
+        const IN = 0x1;
+        /// there is room to write
+        const OUT = 0x4;
+        /// the other end is gone, writes fail
+        const ERR = 0x8;
+        /// the other end is gone, reads end
+        const HUP = 0x10;
+        /// the fd is not open, only ever reported
+        const NVAL = 0x20;
+    }
+}
+
+pub use eventfd::EventFd;
+pub use inode::{init, list_apps, open_device, open_file, OSInode, OpenFlags};
+pub use local_socket::{make_socket_pair, LocalSocket};
+pub use pipe::{make_pipe, PipeRingBuffer};
 pub use stdio::{Stdin, Stdout};
+pub use vfs::{absolute_path, lookup, lookup_parent, sync_all, VfsInode};
@@ -1,28 +1,33 @@
-use super::File;
+use super::{File, PollFlags, Stat, StatMode};
 use crate::mm::UserBuffer;
-use crate::sync::UPIntrFreeCell;
+use crate::sync::{PollWaker, PollWakers, SpinMutexIrqSave, WaitQueue};
 use alloc::sync::{Arc, Weak};
+use core::sync::atomic::{AtomicBool, Ordering};
 
-use crate::task::suspend_current_and_run_next;
+use crate::task::{current_add_signal, schedule, SignalFlags};
 
 pub struct Pipe {
     readable: bool,
     writable: bool,
-    buffer: Arc<UPIntrFreeCell<PipeRingBuffer>>,
+    /// `O_NONBLOCK`
+    nonblock: AtomicBool,
+    buffer: Arc<SpinMutexIrqSave<PipeRingBuffer>>,
 }
 
 impl Pipe {
-    pub fn read_end_with_buffer(buffer: Arc<UPIntrFreeCell<PipeRingBuffer>>) -> Self {
+    pub fn read_end_with_buffer(buffer: Arc<SpinMutexIrqSave<PipeRingBuffer>>) -> Self {
         Self {
             readable: true,
             writable: false,
+            nonblock: AtomicBool::new(false),
             buffer,
         }
     }
-    pub fn write_end_with_buffer(buffer: Arc<UPIntrFreeCell<PipeRingBuffer>>) -> Self {
+    pub fn write_end_with_buffer(buffer: Arc<SpinMutexIrqSave<PipeRingBuffer>>) -> Self {
         Self {
             readable: false,
             writable: true,
+            nonblock: AtomicBool::new(false),
             buffer,
         }
     }
@@ -42,7 +47,14 @@ pub struct PipeRingBuffer {
     head: usize,
     tail: usize,
     status: RingBufferStatus,
+    read_end: Option<Weak<Pipe>>,
     write_end: Option<Weak<Pipe>>,
+    /// waiting for bytes to read
+    readers: WaitQueue,
+    /// waiting for room to write
+    writers: WaitQueue,
+    /// polling either end
+    pollers: PollWakers,
 }
 
 impl PipeRingBuffer {
@@ -52,9 +64,16 @@ impl PipeRingBuffer {
             head: 0,
             tail: 0,
             status: RingBufferStatus::Empty,
+            read_end: None,
             write_end: None,
+            readers: WaitQueue::new(),
+            writers: WaitQueue::new(),
+            pollers: PollWakers::new(),
         }
     }
+    pub fn set_read_end(&mut self, read_end: &Arc<Pipe>) {
+        self.read_end = Some(Arc::downgrade(read_end));
+    }
     pub fn set_write_end(&mut self, write_end: &Arc<Pipe>) {
         self.write_end = Some(Arc::downgrade(write_end));
     }
@@ -91,6 +110,9 @@ impl PipeRingBuffer {
             RING_BUFFER_SIZE - self.available_read()
         }
     }
+    pub fn all_read_ends_closed(&self) -> bool {
+        self.read_end.as_ref().unwrap().upgrade().is_none()
+    }
     pub fn all_write_ends_closed(&self) -> bool {
         self.write_end.as_ref().unwrap().upgrade().is_none()
     }
@@ -98,21 +120,20 @@ impl PipeRingBuffer {
 
 /// Return (read_end, write_end)
 pub fn make_pipe() -> (Arc<Pipe>, Arc<Pipe>) {
-    let buffer = Arc::new(unsafe { UPIntrFreeCell::new(PipeRingBuffer::new()) });
+    let buffer = Arc::new(SpinMutexIrqSave::new(PipeRingBuffer::new()));
     let read_end = Arc::new(Pipe::read_end_with_buffer(buffer.clone()));
     let write_end = Arc::new(Pipe::write_end_with_buffer(buffer.clone()));
-    buffer.exclusive_access().set_write_end(&write_end);
+    let mut ring_buffer = buffer.exclusive_access();
+    ring_buffer.set_read_end(&read_end);
+    ring_buffer.set_write_end(&write_end);
+    drop(ring_buffer);
     (read_end, write_end)
 }
 
-impl File for Pipe {
-    fn readable(&self) -> bool {
-        self.readable
-    }
-    fn writable(&self) -> bool {
-        self.writable
-    }
-    fn read(&self, buf: UserBuffer) -> usize {
+impl Pipe {
+    /// Fill `buf`, waiting for bytes as needed if `wait`. Otherwise
+    /// returns none instead of waiting before any byte was read.
+    fn read_bytes(&self, buf: UserBuffer, wait: bool) -> Option<usize> {
         assert!(self.readable());
         let want_to_read = buf.len();
         let mut buf_iter = buf.into_iter();
@@ -122,12 +143,22 @@ impl File for Pipe {
             let loop_read = ring_buffer.available_read();
             if loop_read == 0 {
                 if ring_buffer.all_write_ends_closed() {
-                    return already_read;
+                    return Some(already_read);
+                }
+                if !wait {
+                    return (already_read > 0).then_some(already_read);
+                }
+                // a signal ends the read with what it got so far
+                match ring_buffer.readers.wait_no_sched_interruptible() {
+                    Some(task_cx_ptr) => {
+                        drop(ring_buffer);
+                        schedule(task_cx_ptr);
+                        continue;
+                    }
+                    None => return Some(already_read),
                 }
-                drop(ring_buffer);
-                suspend_current_and_run_next();
-                continue;
             }
+            let mut done = false;
             for _ in 0..loop_read {
                 if let Some(byte_ref) = buf_iter.next() {
                     unsafe {
@@ -135,39 +166,135 @@ impl File for Pipe {
                     }
                     already_read += 1;
                     if already_read == want_to_read {
-                        return want_to_read;
+                        done = true;
+                        break;
                     }
                 } else {
-                    return already_read;
+                    done = true;
+                    break;
                 }
             }
+            ring_buffer.writers.wake_all();
+            ring_buffer.pollers.wake_all();
+            if done {
+                return Some(already_read);
+            }
         }
     }
-    fn write(&self, buf: UserBuffer) -> usize {
+    /// Write all of `buf`, waiting for room as needed if `wait`.
+    /// Otherwise returns none instead of waiting before any byte was
+    /// written.
+    fn write_bytes(&self, buf: UserBuffer, wait: bool) -> Option<usize> {
         assert!(self.writable());
         let want_to_write = buf.len();
         let mut buf_iter = buf.into_iter();
         let mut already_write = 0usize;
         loop {
             let mut ring_buffer = self.buffer.exclusive_access();
+            if ring_buffer.all_read_ends_closed() {
+                // nobody will ever read it, raise SIGPIPE like Linux
+                drop(ring_buffer);
+                current_add_signal(SignalFlags::SIGPIPE);
+                return Some(already_write);
+            }
             let loop_write = ring_buffer.available_write();
             if loop_write == 0 {
-                drop(ring_buffer);
-                suspend_current_and_run_next();
-                continue;
+                if !wait {
+                    return (already_write > 0).then_some(already_write);
+                }
+                match ring_buffer.writers.wait_no_sched_interruptible() {
+                    Some(task_cx_ptr) => {
+                        drop(ring_buffer);
+                        schedule(task_cx_ptr);
+                        continue;
+                    }
+                    None => return Some(already_write),
+                }
             }
             // write at most loop_write bytes
+            let mut done = false;
             for _ in 0..loop_write {
                 if let Some(byte_ref) = buf_iter.next() {
                     ring_buffer.write_byte(unsafe { *byte_ref });
                     already_write += 1;
                     if already_write == want_to_write {
-                        return want_to_write;
+                        done = true;
+                        break;
                     }
                 } else {
-                    return already_write;
+                    done = true;
+                    break;
                 }
             }
+            ring_buffer.readers.wake_all();
+            ring_buffer.pollers.wake_all();
+            if done {
+                return Some(already_write);
+            }
+        }
+    }
+}
+
+impl Drop for Pipe {
+    fn drop(&mut self) {
+        // the other end finds this one closed once it looks again
+        let mut ring_buffer = self.buffer.exclusive_access();
+        ring_buffer.readers.wake_all();
+        ring_buffer.writers.wake_all();
+        ring_buffer.pollers.wake_all();
+    }
+}
+
+impl File for Pipe {
+    fn readable(&self) -> bool {
+        self.readable
+    }
+    fn writable(&self) -> bool {
+        self.writable
+    }
+    fn read(&self, buf: UserBuffer) -> usize {
+        self.read_bytes(buf, true).unwrap()
+    }
+    fn write(&self, buf: UserBuffer) -> usize {
+        self.write_bytes(buf, true).unwrap()
+    }
+    fn try_read(&self, buf: UserBuffer) -> Option<usize> {
+        self.read_bytes(buf, false)
+    }
+    fn try_write(&self, buf: UserBuffer) -> Option<usize> {
+        self.write_bytes(buf, false)
+    }
+    fn nonblocking(&self) -> bool {
+        self.nonblock.load(Ordering::Relaxed)
+    }
+    fn set_nonblocking(&self, nonblocking: bool) {
+        self.nonblock.store(nonblocking, Ordering::Relaxed);
+    }
+    fn stat(&self) -> Option<Stat> {
+        let size = self.buffer.exclusive_access().available_read();
+        Some(Stat::new(0, StatMode::FIFO, 1, size as u64))
+    }
+    fn poll(&self) -> PollFlags {
+        let ring_buffer = self.buffer.exclusive_access();
+        let mut flags = PollFlags::empty();
+        if self.readable {
+            if ring_buffer.available_read() > 0 {
+                flags |= PollFlags::IN;
+            }
+            if ring_buffer.all_write_ends_closed() {
+                flags |= PollFlags::HUP;
+            }
         }
+        if self.writable {
+            if ring_buffer.all_read_ends_closed() {
+                flags |= PollFlags::ERR;
+            } else if ring_buffer.available_write() > 0 {
+                flags |= PollFlags::OUT;
+            }
+        }
+        flags
+    }
+    fn register_waker(&self, waker: &Arc<PollWaker>) {
+        self.buffer.exclusive_access().pollers.register(waker);
     }
 }
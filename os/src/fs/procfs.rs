@@ -0,0 +1,201 @@
+//! `/proc`, read-only files describing the kernel, made up each time they
+//! are read.
+
+use super::vfs::{FileSystem, VfsInode};
+use super::{Stat, StatMode};
+use crate::config::PAGE_SIZE;
+use crate::logging::kmsg;
+use crate::mm::meminfo;
+use crate::task::{hart_times, pid2process, processes, TaskStatus};
+use crate::timer::get_time_ms;
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::sync::Arc;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::any::Any;
+
+pub struct ProcFs;
+
+impl FileSystem for ProcFs {
+    fn root_inode(&self) -> Arc<dyn VfsInode> {
+        Arc::new(ProcInode::Root)
+    }
+}
+
+enum ProcInode {
+    Root,
+    /// `/proc/<pid>`
+    Process(usize),
+    /// `/proc/<pid>/status`
+    Status(usize),
+    /// `/proc/<pid>/maps`
+    Maps(usize),
+    Meminfo,
+    Uptime,
+    /// The kernel log ring buffer
+    Kmsg,
+    /// Time spent per hart
+    Stat,
+}
+
+impl ProcInode {
+    /// Inode numbers, with 16 for each process after the fixed ones.
+    fn ino(&self) -> u64 {
+        match self {
+            Self::Root => 1,
+            Self::Meminfo => 2,
+            Self::Uptime => 3,
+            Self::Kmsg => 4,
+            Self::Stat => 5,
+            Self::Process(pid) => (*pid as u64 + 1) << 4,
+            Self::Status(pid) => ((*pid as u64 + 1) << 4) + 1,
+            Self::Maps(pid) => ((*pid as u64 + 1) << 4) + 2,
+        }
+    }
+
+    fn contents(&self) -> Option<String> {
+        match self {
+            Self::Meminfo => Some(meminfo()),
+            Self::Uptime => {
+                let ms = get_time_ms();
+                Some(format!("{}.{:02}\n", ms / 1000, ms % 1000 / 10))
+            }
+            Self::Status(pid) => process_status(*pid),
+            Self::Maps(pid) => {
+                let process = pid2process(*pid)?;
+                let maps = process.inner_exclusive_access().memory_set.maps();
+                Some(maps)
+            }
+            Self::Kmsg => Some(kmsg()),
+            Self::Stat => Some(stat()),
+            _ => None,
+        }
+    }
+}
+
+/// The first columns of the Linux layout: user, nice, system and idle
+/// clock ticks, summed up on the `cpu` line and then for each hart.
+fn stat() -> String {
+    let harts: Vec<_> = hart_times().collect();
+    let [user, kernel, idle] = harts.iter().fold([0; 3], |sum, (_, times)| {
+        [sum[0] + times[0], sum[1] + times[1], sum[2] + times[2]]
+    });
+    let mut stat = format!("cpu  {} 0 {} {}\n", user, kernel, idle);
+    for (hart, [user, kernel, idle]) in harts {
+        stat += &format!("cpu{} {} 0 {} {}\n", hart, user, kernel, idle);
+    }
+    stat
+}
+
+/// Ids, state, priority, memory usage and context switches of the process
+/// `pid`, or `None` if it is gone. Counts are summed over its threads.
+fn process_status(pid: usize) -> Option<String> {
+    let process = pid2process(pid)?;
+    let inner = process.inner_exclusive_access();
+    let mut state = if inner.is_zombie {
+        "Z (zombie)"
+    } else {
+        "S (sleeping)"
+    };
+    let mut priority = 0;
+    let mut threads = 0;
+    let (mut nvcsw, mut nivcsw) = (0, 0);
+    for task in inner.tasks.iter().flatten() {
+        let task_inner = task.inner_exclusive_access();
+        threads += 1;
+        priority = priority.max(task_inner.priority);
+        nvcsw += task_inner.nvcsw;
+        nivcsw += task_inner.nivcsw;
+        match task_inner.task_status {
+            TaskStatus::Running => state = "R (running)",
+            TaskStatus::Ready if state != "R (running)" => state = "R (runnable)",
+            _ => {}
+        }
+    }
+    if inner.stopped && !inner.is_zombie {
+        state = "T (stopped)";
+    }
+    Some(format!(
+        "Pid:\t{}\nPPid:\t{}\nPgid:\t{}\nSid:\t{}\nState:\t{}\nThreads:\t{}\nPriority:\t{}\nVmRSS:\t{} kB\n\
+         voluntary_ctxt_switches:\t{}\nnonvoluntary_ctxt_switches:\t{}\n",
+        pid,
+        inner
+            .parent
+            .as_ref()
+            .and_then(|parent| parent.upgrade())
+            .map_or(0, |parent| parent.getpid()),
+        inner.pgid,
+        inner.sid,
+        state,
+        threads,
+        priority,
+        inner.memory_set.rss_pages() * PAGE_SIZE / 1024,
+        nvcsw,
+        nivcsw,
+    ))
+}
+
+impl VfsInode for ProcInode {
+    fn read_at(&self, offset: usize, buf: &mut [u8]) -> usize {
+        let contents = match self.contents() {
+            Some(contents) => contents,
+            None => return 0,
+        };
+        let bytes = contents.as_bytes();
+        if offset >= bytes.len() {
+            return 0;
+        }
+        let len = buf.len().min(bytes.len() - offset);
+        buf[..len].copy_from_slice(&bytes[offset..offset + len]);
+        len
+    }
+    /// Sizes are 0 as the contents are only known when read.
+    fn stat(&self) -> Stat {
+        let mode = match self {
+            Self::Root | Self::Process(_) => StatMode::DIR,
+            _ => StatMode::FILE,
+        };
+        Stat::new(self.ino(), mode, 1, 0)
+    }
+    fn find(&self, name: &str) -> Option<Arc<dyn VfsInode>> {
+        let inode = match (self, name) {
+            (Self::Root, "meminfo") => Self::Meminfo,
+            (Self::Root, "uptime") => Self::Uptime,
+            (Self::Root, "kmsg") => Self::Kmsg,
+            (Self::Root, "stat") => Self::Stat,
+            (Self::Root, pid) => {
+                let pid = pid.parse().ok()?;
+                pid2process(pid)?;
+                Self::Process(pid)
+            }
+            (Self::Process(pid), "status") if pid2process(*pid).is_some() => Self::Status(*pid),
+            (Self::Process(pid), "maps") if pid2process(*pid).is_some() => Self::Maps(*pid),
+            _ => return None,
+        };
+        Some(Arc::new(inode))
+    }
+    fn ls(&self) -> Vec<String> {
+        match self {
+            Self::Root => {
+                let mut names = vec![
+                    String::from("meminfo"),
+                    String::from("uptime"),
+                    String::from("kmsg"),
+                    String::from("stat"),
+                ];
+                names.extend(
+                    processes()
+                        .iter()
+                        .map(|process| process.getpid().to_string()),
+                );
+                names
+            }
+            Self::Process(_) => vec![String::from("status"), String::from("maps")],
+            _ => Vec::new(),
+        }
+    }
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
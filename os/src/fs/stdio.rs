@@ -1,11 +1,25 @@
-use super::File;
-use crate::drivers::chardev::CharDevice;
-use crate::drivers::chardev::UART;
+use super::{File, PollFlags, Stat, StatMode};
+use crate::drivers::chardev::TTY;
 use crate::mm::UserBuffer;
+use crate::sync::PollWaker;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicBool, Ordering};
 
-pub struct Stdin;
+pub struct Stdin {
+    /// `O_NONBLOCK`
+    nonblock: AtomicBool,
+}
 pub struct Stdout;
 
+impl Stdin {
+    pub fn new() -> Self {
+        Self {
+            nonblock: AtomicBool::new(false),
+        }
+    }
+}
+
 impl File for Stdin {
     fn readable(&self) -> bool {
         true
@@ -13,18 +27,35 @@ impl File for Stdin {
     fn writable(&self) -> bool {
         false
     }
-    fn read(&self, mut user_buf: UserBuffer) -> usize {
-        assert_eq!(user_buf.len(), 1);
-        //println!("before UART.read() in Stdin::read()");
-        let ch = UART.read();
-        unsafe {
-            user_buf.buffers[0].as_mut_ptr().write_volatile(ch);
-        }
-        1
+    fn read(&self, user_buf: UserBuffer) -> usize {
+        let bytes = TTY.read(user_buf.len());
+        copy_console(user_buf, bytes)
     }
     fn write(&self, _user_buf: UserBuffer) -> usize {
         panic!("Cannot write to stdin!");
     }
+    fn try_read(&self, user_buf: UserBuffer) -> Option<usize> {
+        let bytes = TTY.try_read(user_buf.len())?;
+        Some(copy_console(user_buf, bytes))
+    }
+    fn nonblocking(&self) -> bool {
+        self.nonblock.load(Ordering::Relaxed)
+    }
+    fn set_nonblocking(&self, nonblocking: bool) {
+        self.nonblock.store(nonblocking, Ordering::Relaxed);
+    }
+    fn stat(&self) -> Option<Stat> {
+        Some(Stat::new(0, StatMode::CHR, 1, 0))
+    }
+    fn ioctl(&self, cmd: u32, arg: usize) -> isize {
+        TTY.ioctl(cmd, arg)
+    }
+    fn poll(&self) -> PollFlags {
+        poll_console()
+    }
+    fn register_waker(&self, waker: &Arc<PollWaker>) {
+        TTY.register_waker(waker);
+    }
 }
 
 impl File for Stdout {
@@ -43,4 +74,82 @@ impl File for Stdout {
         }
         user_buf.len()
     }
+    fn stat(&self) -> Option<Stat> {
+        Some(Stat::new(0, StatMode::CHR, 1, 0))
+    }
+    fn ioctl(&self, cmd: u32, arg: usize) -> isize {
+        TTY.ioctl(cmd, arg)
+    }
+}
+
+/// The console as one file, for `/dev/tty`.
+pub struct Tty {
+    /// `O_NONBLOCK`, output never waits anyway
+    nonblock: AtomicBool,
+}
+
+impl Tty {
+    pub fn new() -> Self {
+        Self {
+            nonblock: AtomicBool::new(false),
+        }
+    }
+}
+
+impl File for Tty {
+    fn readable(&self) -> bool {
+        true
+    }
+    fn writable(&self) -> bool {
+        true
+    }
+    fn read(&self, user_buf: UserBuffer) -> usize {
+        let bytes = TTY.read(user_buf.len());
+        copy_console(user_buf, bytes)
+    }
+    fn write(&self, user_buf: UserBuffer) -> usize {
+        Stdout.write(user_buf)
+    }
+    fn try_read(&self, user_buf: UserBuffer) -> Option<usize> {
+        let bytes = TTY.try_read(user_buf.len())?;
+        Some(copy_console(user_buf, bytes))
+    }
+    fn nonblocking(&self) -> bool {
+        self.nonblock.load(Ordering::Relaxed)
+    }
+    fn set_nonblocking(&self, nonblocking: bool) {
+        self.nonblock.store(nonblocking, Ordering::Relaxed);
+    }
+    fn stat(&self) -> Option<Stat> {
+        Some(Stat::new(0, StatMode::CHR, 1, 0))
+    }
+    fn ioctl(&self, cmd: u32, arg: usize) -> isize {
+        TTY.ioctl(cmd, arg)
+    }
+    fn poll(&self) -> PollFlags {
+        // output never waits
+        poll_console() | PollFlags::OUT
+    }
+    fn register_waker(&self, waker: &Arc<PollWaker>) {
+        TTY.register_waker(waker);
+    }
+}
+
+/// Copy what the line discipline let through, a line at most in
+/// canonical mode, to `user_buf`.
+fn copy_console(user_buf: UserBuffer, bytes: Vec<u8>) -> usize {
+    for (byte, src) in user_buf.into_iter().zip(bytes.iter()) {
+        unsafe {
+            *byte = *src;
+        }
+    }
+    bytes.len()
+}
+
+fn poll_console() -> PollFlags {
+    if TTY.can_read() {
+        PollFlags::IN
+    } else {
+        PollFlags::empty()
+    }
 }
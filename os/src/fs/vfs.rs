@@ -0,0 +1,156 @@
+//! The file systems the kernel can use and where they are mounted. Paths
+//! are resolved here, so the syscalls need not know which file system a
+//! file lives on.
+
+use super::{File, Stat};
+use crate::sync::SpinMutex;
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::any::Any;
+use lazy_static::*;
+
+pub trait FileSystem: Send + Sync {
+    fn root_inode(&self) -> Arc<dyn VfsInode>;
+    /// Write cached changes back to the device.
+    fn sync(&self) {}
+}
+
+/// A file or directory of some file system. Everything but reading and
+/// the status is refused by default, as a read-only file system would.
+pub trait VfsInode: Send + Sync {
+    fn read_at(&self, offset: usize, buf: &mut [u8]) -> usize;
+    /// Whether files may be opened for writing at all.
+    fn writable(&self) -> bool {
+        false
+    }
+    fn write_at(&self, _offset: usize, _buf: &[u8]) -> usize {
+        0
+    }
+    /// Drop the contents of a file.
+    fn clear(&self) {}
+    fn stat(&self) -> Stat;
+    fn is_dir(&self) -> bool {
+        self.stat().mode.contains(super::StatMode::DIR)
+    }
+    fn find(&self, _name: &str) -> Option<Arc<dyn VfsInode>> {
+        None
+    }
+    fn create(&self, _name: &str) -> Option<Arc<dyn VfsInode>> {
+        None
+    }
+    fn create_dir(&self, _name: &str) -> Option<Arc<dyn VfsInode>> {
+        None
+    }
+    /// Add `name` for `target`, which must be on the same file system.
+    fn link(&self, _name: &str, _target: &Arc<dyn VfsInode>) -> bool {
+        false
+    }
+    fn unlink(&self, _name: &str) -> bool {
+        false
+    }
+    fn ls(&self) -> Vec<String> {
+        Vec::new()
+    }
+    /// The file to open in place of a device node.
+    fn device(&self) -> Option<Arc<dyn File + Send + Sync>> {
+        None
+    }
+    /// For a file system to recognize its own inodes.
+    fn as_any(&self) -> &dyn Any;
+}
+
+lazy_static! {
+    /// (absolute path, file system) of each mount
+    static ref MOUNTS: SpinMutex<Vec<(String, Arc<dyn FileSystem>)>> =
+        SpinMutex::new(Vec::new());
+}
+
+/// Mount `fs` on the directory `path`, or as the root if nothing is
+/// mounted yet.
+pub fn mount(path: &str, fs: Arc<dyn FileSystem>) -> bool {
+    let root_mount = MOUNTS.exclusive_access().is_empty();
+    if root_mount != (path == "/") {
+        return false;
+    }
+    if !root_mount && !lookup(path).map_or(false, |inode| inode.is_dir()) {
+        return false;
+    }
+    let mut mounts = MOUNTS.exclusive_access();
+    if mounts.iter().any(|(mount_path, _)| mount_path == path) {
+        return false;
+    }
+    mounts.push((String::from(path), fs));
+    true
+}
+
+pub fn sync_all() {
+    let mounts: Vec<_> = MOUNTS
+        .exclusive_access()
+        .iter()
+        .map(|(_, fs)| Arc::clone(fs))
+        .collect();
+    for fs in mounts {
+        fs.sync();
+    }
+}
+
+/// Turn `path` into an absolute path without `.` or `..` components,
+/// taking it relative to `cwd` unless it starts with `/`.
+pub fn absolute_path(cwd: &str, path: &str) -> String {
+    let mut names: Vec<&str> = Vec::new();
+    let base = if path.starts_with('/') { "" } else { cwd };
+    for name in base.split('/').chain(path.split('/')) {
+        match name {
+            "" | "." => {}
+            ".." => {
+                names.pop();
+            }
+            name => names.push(name),
+        }
+    }
+    let mut abs_path = String::new();
+    for name in names {
+        abs_path.push('/');
+        abs_path.push_str(name);
+    }
+    if abs_path.is_empty() {
+        abs_path.push('/');
+    }
+    abs_path
+}
+
+/// Find the inode at the absolute path `path`, starting from the file
+/// system mounted deepest along it.
+pub fn lookup(path: &str) -> Option<Arc<dyn VfsInode>> {
+    let (mount_len, fs) = {
+        let mounts = MOUNTS.exclusive_access();
+        let (mount_path, fs) = mounts
+            .iter()
+            .filter(|(mount_path, _)| {
+                mount_path == "/"
+                    || path == mount_path
+                    || path
+                        .strip_prefix(mount_path.as_str())
+                        .map_or(false, |rest| rest.starts_with('/'))
+            })
+            .max_by_key(|(mount_path, _)| mount_path.len())?;
+        (mount_path.len(), Arc::clone(fs))
+    };
+    let mut inode = fs.root_inode();
+    for name in path[mount_len..].split('/').filter(|name| !name.is_empty()) {
+        inode = inode.find(name)?;
+    }
+    Some(inode)
+}
+
+/// Find the directory holding the absolute path `path`, and the last
+/// name in it.
+pub fn lookup_parent(path: &str) -> Option<(Arc<dyn VfsInode>, &str)> {
+    let (parent, name) = path.rsplit_once('/')?;
+    if name.is_empty() {
+        return None;
+    }
+    let parent = if parent.is_empty() { "/" } else { parent };
+    Some((lookup(parent)?, name))
+}
@@ -0,0 +1,206 @@
+//! Messages between harts. Posting sets the message in the pending set of
+//! the target and raises its supervisor software interrupt through the
+//! SBI, the target handles whatever is pending when it takes it. Messages
+//! of one kind coalesce until handled.
+
+use crate::config::{MAX_HARTS, PAGE_SIZE};
+use crate::mm::flush_local;
+use crate::net::loopback_interrupt_handler;
+use crate::sbi::{hart_stop, send_ipi, HartMask, SbiError};
+use crate::task::hart_id;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use lazy_static::*;
+use riscv::asm::{sfence_vma_all, wfi};
+use riscv::register::{sie, sip, sstatus};
+
+/// Look for a task to run, an idle hart may have been given one.
+pub const IPI_RESCHEDULE: usize = 1 << 0;
+/// Flush the pages asked for and acknowledge it.
+pub const IPI_TLB_SHOOTDOWN: usize = 1 << 1;
+/// Stop taking messages and hand the hart back to the SBI.
+pub const IPI_HALT: usize = 1 << 2;
+/// Take in the frames queued on the loopback device.
+pub const IPI_LOOPBACK: usize = 1 << 3;
+
+lazy_static! {
+    /// Messages posted to each hart and not handled yet.
+    static ref PENDING: [AtomicUsize; MAX_HARTS] = core::array::from_fn(|_| AtomicUsize::new(0));
+    /// Shootdowns asked of each hart and how many of them it did, a sender
+    /// waits for the second to reach its ticket.
+    static ref SHOOTDOWNS_ASKED: [AtomicUsize; MAX_HARTS] =
+        core::array::from_fn(|_| AtomicUsize::new(0));
+    static ref SHOOTDOWNS_DONE: [AtomicUsize; MAX_HARTS] =
+        core::array::from_fn(|_| AtomicUsize::new(0));
+    /// The start and end of the range covering the addresses the pending
+    /// shootdowns of each hart are for, empty as `usize::MAX..0`.
+    static ref SHOOTDOWN_START: [AtomicUsize; MAX_HARTS] =
+        core::array::from_fn(|_| AtomicUsize::new(usize::MAX));
+    static ref SHOOTDOWN_END: [AtomicUsize; MAX_HARTS] =
+        core::array::from_fn(|_| AtomicUsize::new(0));
+}
+
+/// Harts that take messages.
+static ONLINE: AtomicUsize = AtomicUsize::new(0);
+/// Harts waiting for a task in the idle loop.
+static IDLE: AtomicUsize = AtomicUsize::new(0);
+
+/// Start taking messages on this hart.
+pub fn init() {
+    unsafe {
+        sie::set_ssoft();
+    }
+    ONLINE.fetch_or(1 << hart_id(), Ordering::SeqCst);
+}
+
+fn post(harts: usize, message: usize) -> Result<(), SbiError> {
+    for hart in (0..MAX_HARTS).filter(|hart| harts & 1 << hart != 0) {
+        PENDING[hart].fetch_or(message, Ordering::SeqCst);
+    }
+    send_ipi(HartMask::from_mask(harts))
+}
+
+/// Handle the messages posted to this hart, true if it should reschedule.
+pub fn handle_ipi() -> bool {
+    unsafe {
+        sip::clear_ssoft();
+    }
+    let pending = PENDING[hart_id()].swap(0, Ordering::SeqCst);
+    if pending & IPI_TLB_SHOOTDOWN != 0 {
+        handle_tlb_shootdown();
+    }
+    if pending & IPI_HALT != 0 {
+        halt();
+    }
+    if pending & IPI_LOOPBACK != 0 {
+        loopback_interrupt_handler();
+    }
+    pending & IPI_RESCHEDULE != 0
+}
+
+/// Do the shootdowns asked of this hart. Takes no locks, so it is also
+/// called while spinning with interrupts masked, the sender may hold the
+/// lock this hart waits for.
+pub fn handle_tlb_shootdown() {
+    let hart = hart_id();
+    let asked = SHOOTDOWNS_ASKED[hart].load(Ordering::Acquire);
+    if SHOOTDOWNS_DONE[hart].load(Ordering::Relaxed) != asked {
+        let start = SHOOTDOWN_START[hart].swap(usize::MAX, Ordering::AcqRel);
+        let end = SHOOTDOWN_END[hart].swap(0, Ordering::AcqRel);
+        if start < end {
+            flush_local(start, (end - start) / PAGE_SIZE);
+        } else if start != usize::MAX || end != 0 {
+            // taken in the middle of a sender widening the range, the part
+            // left behind is not a range of its own
+            unsafe {
+                sfence_vma_all();
+            }
+        }
+        SHOOTDOWNS_DONE[hart].store(asked, Ordering::Release);
+    }
+}
+
+/// Fence `size` bytes from `va` on `harts` and wait until all of them did.
+pub fn tlb_shootdown(harts: usize, va: usize, size: usize) {
+    let harts = harts & ONLINE.load(Ordering::SeqCst);
+    if harts == 0 {
+        return;
+    }
+    let mut tickets = [0; MAX_HARTS];
+    for hart in (0..MAX_HARTS).filter(|hart| harts & 1 << hart != 0) {
+        SHOOTDOWN_START[hart].fetch_min(va, Ordering::AcqRel);
+        SHOOTDOWN_END[hart].fetch_max(va + size, Ordering::AcqRel);
+        tickets[hart] = SHOOTDOWNS_ASKED[hart].fetch_add(1, Ordering::AcqRel) + 1;
+    }
+    post(harts, IPI_TLB_SHOOTDOWN).expect("SBI IPI failed");
+    for hart in (0..MAX_HARTS).filter(|hart| harts & 1 << hart != 0) {
+        // others may ask for more meanwhile, so done can pass the ticket
+        while (SHOOTDOWNS_DONE[hart]
+            .load(Ordering::Acquire)
+            .wrapping_sub(tickets[hart]) as isize)
+            < 0
+        {
+            // the target may be waiting for this hart the same way
+            handle_tlb_shootdown();
+            core::hint::spin_loop();
+        }
+    }
+}
+
+pub fn online_harts() -> usize {
+    ONLINE.load(Ordering::SeqCst)
+}
+
+pub fn idle_harts() -> usize {
+    IDLE.load(Ordering::SeqCst)
+}
+
+/// Only raise the software interrupt of `hart`, for a request the
+/// interrupt handler finds elsewhere.
+pub fn poke_hart(hart: usize) {
+    let _ = post(1 << hart, 0);
+}
+
+/// Post `message` to this hart, which handles it once it takes
+/// interrupts again. A task moving to another hart meanwhile only leaves
+/// it to the hart it was posted to.
+pub fn post_self(message: usize) {
+    let _ = post(1 << hart_id(), message);
+}
+
+/// A task was queued on `hart`: have it look for the task if it is idle,
+/// else some other idle hart that may steal it.
+pub fn kick_idle_hart(hart: usize) {
+    let idle = IDLE.load(Ordering::SeqCst) & !(1 << hart_id());
+    let target = if idle & 1 << hart != 0 {
+        1 << hart
+    } else {
+        idle & idle.wrapping_neg()
+    };
+    if target != 0 {
+        // a lost kick only leaves the task to the next timer tick
+        let _ = post(target, IPI_RESCHEDULE);
+    }
+}
+
+/// A task that should preempt the one `hart` runs was queued there: have
+/// it look at its queue whether it is idle or not.
+pub fn preempt_hart(hart: usize) {
+    // a lost message only leaves the task to the next timer interrupt
+    let _ = post(1 << hart, IPI_RESCHEDULE);
+}
+
+/// Sleep until an interrupt, unless `has_work` finds something to do
+/// once this hart is marked idle, so that a kick is not missed.
+pub fn idle_wait(has_work: impl Fn() -> bool) {
+    let hart = 1 << hart_id();
+    IDLE.fetch_or(hart, Ordering::SeqCst);
+    if !has_work() {
+        unsafe {
+            sstatus::set_sie();
+            wfi();
+            sstatus::clear_sie();
+        }
+    }
+    IDLE.fetch_and(!hart, Ordering::SeqCst);
+}
+
+/// Stop the other harts, as far as they take messages.
+pub fn halt_others() {
+    let others = ONLINE.load(Ordering::SeqCst) & !(1 << hart_id());
+    if others != 0 {
+        let _ = post(others, IPI_HALT);
+    }
+}
+
+fn halt() -> ! {
+    ONLINE.fetch_and(!(1 << hart_id()), Ordering::SeqCst);
+    unsafe {
+        sstatus::clear_sie();
+    }
+    hart_stop();
+    loop {
+        unsafe {
+            wfi();
+        }
+    }
+}
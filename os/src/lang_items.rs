@@ -1,11 +1,36 @@
+use crate::config::KSYMS_SIZE;
+use crate::crash::crash_dump;
+use crate::drivers::rng::random;
+use crate::ipi::halt_others;
 use crate::sbi::shutdown;
-use crate::task::current_kstack_top;
+use crate::task::{current_kstack, hart_id, try_current_task};
 use core::arch::asm;
 use core::panic::PanicInfo;
 use log::*;
 
+/// Room for the "addr name" lines of every kernel function, sorted by
+/// address and filled in by the Makefile after linking.
+#[used]
+#[link_section = ".ksyms"]
+static KSYMS: [u8; KSYMS_SIZE] = [0; KSYMS_SIZE];
+
+/// The canary every function with a buffer on its stack keeps below its
+/// return address and checks before returning, built with
+/// `-Z stack-protector=strong`. 0 until `init_stack_guard`.
+#[no_mangle]
+#[allow(non_upper_case_globals)]
+static mut __stack_chk_guard: usize = 0;
+
+extern "C" {
+    fn stext();
+    fn etext();
+    fn sksyms();
+    fn eksyms();
+}
+
 #[panic_handler]
 fn panic(info: &PanicInfo) -> ! {
+    halt_others();
     if let Some(location) = info.location() {
         error!(
             "[kernel] Panicked at {}:{} {}",
@@ -19,20 +44,98 @@ fn panic(info: &PanicInfo) -> ! {
     unsafe {
         backtrace();
     }
+    crash_dump(info);
     shutdown(true)
 }
 
+/// Seed the canary from the entropy pool, once it is seeded and before the
+/// other harts start. The frames below keep the old canary, so this must
+/// not be called from a function that returns, rust_main never does.
+pub fn init_stack_guard() {
+    let guard = random() as usize;
+    unsafe {
+        core::ptr::addr_of_mut!(__stack_chk_guard).write_volatile(guard);
+    }
+}
+
+/// Called instead of returning by a function whose canary was overwritten,
+/// right after whatever wrote past the end of a buffer on its stack.
+#[no_mangle]
+extern "C" fn __stack_chk_fail() -> ! {
+    let fp: usize;
+    unsafe { asm!("mv {}, s0", out(reg) fp) };
+    error!("[kernel] stack smashing detected on hart {}", hart_id());
+    // the processor may be locked by the very frame that was smashed
+    match try_current_task() {
+        Some(Some(task)) => {
+            let pid = task.process.upgrade().map_or(0, |process| process.getpid());
+            match task.inner.try_exclusive_access() {
+                Some(inner) => error!(
+                    "[kernel] in pid {} tid {}",
+                    pid,
+                    inner.res.as_ref().map_or(0, |res| res.tid)
+                ),
+                None => error!("[kernel] in pid {}", pid),
+            }
+        }
+        Some(None) => error!("[kernel] in the scheduler"),
+        None => {}
+    }
+    unsafe {
+        trace_stack(fp);
+    }
+    panic!("stack smashing detected");
+}
+
 unsafe fn backtrace() {
-    let mut fp: usize;
-    let stop = current_kstack_top();
+    let fp: usize;
     asm!("mv {}, s0", out(reg) fp);
+    trace_stack(fp);
+}
+
+/// Print the return addresses up the kernel stack from the frame `fp`,
+/// stopping at a frame pointer off the stack as one overwritten would be.
+pub unsafe fn trace_stack(mut fp: usize) {
+    let (bottom, stop) = current_kstack();
     println!("---START BACKTRACE---");
     for i in 0..10 {
-        if fp == stop {
+        if fp >= stop || fp <= bottom + 16 || fp % 8 != 0 {
             break;
         }
-        println!("#{}:ra={:#x}", i, *((fp - 8) as *const usize));
+        let ra = *((fp - 8) as *const usize);
+        match symbolize(ra) {
+            Some((name, offset)) => println!("#{}:ra={:#x} {}+{:#x}", i, ra, name, offset),
+            None => println!("#{}:ra={:#x}", i, ra),
+        }
         fp = *((fp - 16) as *const usize);
     }
     println!("---END   BACKTRACE---");
 }
+
+/// The function containing `addr` and how far into it `addr` is.
+pub fn symbolize(addr: usize) -> Option<(&'static str, usize)> {
+    if !(stext as usize..etext as usize).contains(&addr) {
+        return None;
+    }
+    // read through the linker symbols, the compiler only knows KSYMS as zeros
+    let table = unsafe {
+        core::slice::from_raw_parts(
+            sksyms as usize as *const u8,
+            eksyms as usize - sksyms as usize,
+        )
+    };
+    let mut found = None;
+    for line in table.split(|&b| b == b'\n') {
+        if line.first().map_or(true, |&b| b == 0) {
+            break;
+        }
+        let line = core::str::from_utf8(line).ok()?;
+        let (start, name) = line.split_once(' ')?;
+        let start = usize::from_str_radix(start, 16).ok()?;
+        if start > addr {
+            break;
+        }
+        found = Some((name, addr - start));
+    }
+    found
+}
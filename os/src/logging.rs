@@ -0,0 +1,163 @@
+//! Kernel log: `log` records filtered per module by the `LOG` spec given at
+//! build time, or by `log=` on the kernel command line (`make run
+//! BOOTARGS=log=debug`), printed with a timestamp and kept in a ring buffer
+//! read back through `sys_syslog` and `/proc/kmsg`.
+
+use crate::config::KMSG_SIZE;
+use crate::console::print;
+use crate::dtb::bootarg;
+use crate::sync::SpinMutexIrqSave;
+use crate::task::hart_id;
+use crate::timer::get_time_ns;
+use alloc::boxed::Box;
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::fmt::{self, Write};
+use lazy_static::*;
+use log::{Level, LevelFilter, Log, Metadata, Record};
+
+/// Like `RUST_LOG`, a default level and `module=level` overrides separated
+/// by commas, e.g. `warn,os::fs=debug`. `log=` on the kernel command line
+/// takes its place.
+const LOG_SPEC: &str = match option_env!("LOG") {
+    Some(spec) => spec,
+    None => "info",
+};
+
+/// The last `KMSG_SIZE` bytes logged, oldest first from `start`.
+struct Kmsg {
+    buf: Vec<u8>,
+    start: usize,
+    len: usize,
+}
+
+impl Kmsg {
+    fn push(&mut self, byte: u8) {
+        self.buf[(self.start + self.len) % KMSG_SIZE] = byte;
+        if self.len == KMSG_SIZE {
+            self.start = (self.start + 1) % KMSG_SIZE;
+        } else {
+            self.len += 1;
+        }
+    }
+}
+
+impl Write for Kmsg {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        s.bytes().for_each(|byte| self.push(byte));
+        Ok(())
+    }
+}
+
+lazy_static! {
+    static ref KMSG: SpinMutexIrqSave<Kmsg> = SpinMutexIrqSave::new(Kmsg {
+        buf: vec![0; KMSG_SIZE],
+        start: 0,
+        len: 0,
+    });
+}
+
+/// The level `spec` sets for records from `target`, the longest module
+/// prefix wins.
+fn level_for(spec: &str, target: &str) -> LevelFilter {
+    let mut level = LevelFilter::Info;
+    let mut matched = None;
+    for directive in spec.split(',').map(str::trim) {
+        let (module, filter) = match directive.split_once('=') {
+            Some((module, filter)) => (module, filter),
+            None => ("", directive),
+        };
+        let covers = module.is_empty()
+            || target == module
+            || target
+                .strip_prefix(module)
+                .map_or(false, |rest| rest.starts_with("::"));
+        if !covers || matched.map_or(false, |len| len > module.len()) {
+            continue;
+        }
+        if let Ok(filter) = filter.parse() {
+            level = filter;
+            matched = Some(module.len());
+        }
+    }
+    level
+}
+
+fn level_color(level: Level) -> u8 {
+    match level {
+        Level::Error => 31,
+        Level::Warn => 93,
+        Level::Info => 34,
+        Level::Debug => 32,
+        Level::Trace => 90,
+    }
+}
+
+struct KernelLogger {
+    spec: &'static str,
+}
+
+impl Log for KernelLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= level_for(self.spec, metadata.target())
+    }
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        let us = get_time_ns() / 1000;
+        // one line at a time on the console, the lock keeps harts apart
+        let mut kmsg = KMSG.exclusive_access();
+        let _ = writeln!(
+            kmsg,
+            "[{:>5}.{:06}] [{:>5}] [{}] {}",
+            us / 1_000_000,
+            us % 1_000_000,
+            record.level(),
+            hart_id(),
+            record.args()
+        );
+        print(format_args!(
+            "\u{1b}[{}m[{:>5}.{:06}] [{:>5}] [{}] {}\u{1b}[0m\n",
+            level_color(record.level()),
+            us / 1_000_000,
+            us % 1_000_000,
+            record.level(),
+            hart_id(),
+            record.args()
+        ));
+    }
+    fn flush(&self) {}
+}
+
+pub fn init() {
+    let spec = bootarg("log").unwrap_or(LOG_SPEC);
+    log::set_logger(Box::leak(Box::new(KernelLogger { spec }))).unwrap();
+    log::set_max_level(LevelFilter::Trace);
+}
+
+/// Everything still in the ring buffer, oldest first.
+pub fn kmsg() -> String {
+    let kmsg = KMSG.exclusive_access();
+    let bytes: Vec<u8> = (0..kmsg.len)
+        .map(|i| kmsg.buf[(kmsg.start + i) % KMSG_SIZE])
+        .collect();
+    String::from_utf8_lossy(&bytes).into_owned()
+}
+
+/// The ring buffer as its older and newer halves, unless it is locked.
+pub fn kmsg_slices(f: impl FnOnce(&[u8], &[u8])) {
+    if let Some(kmsg) = KMSG.try_exclusive_access() {
+        let end = kmsg.start + kmsg.len;
+        let older = &kmsg.buf[kmsg.start..end.min(KMSG_SIZE)];
+        let newer = &kmsg.buf[..end.saturating_sub(KMSG_SIZE)];
+        f(older, newer);
+    }
+}
+
+pub fn kmsg_clear() {
+    let mut kmsg = KMSG.exclusive_access();
+    kmsg.start = 0;
+    kmsg.len = 0;
+}
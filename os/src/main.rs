@@ -15,12 +15,20 @@ mod board;
 
 #[macro_use]
 mod console;
+#[macro_use]
+mod profile;
 mod config;
+mod crash;
+mod debug;
 mod drivers;
+mod dtb;
 mod fs;
+mod ipi;
 mod lang_items;
+mod logging;
 mod mm;
 mod net;
+mod perf;
 mod sbi;
 mod sync;
 mod syscall;
@@ -45,32 +53,88 @@ fn clear_bss() {
 }
 
 use lazy_static::*;
-use sync::UPIntrFreeCell;
+use log::{info, warn};
+use sync::SpinMutexIrqSave;
 
 lazy_static! {
-    pub static ref DEV_NON_BLOCKING_ACCESS: UPIntrFreeCell<bool> =
-        unsafe { UPIntrFreeCell::new(false) };
+    pub static ref DEV_NON_BLOCKING_ACCESS: SpinMutexIrqSave<bool> = SpinMutexIrqSave::new(false);
 }
 
+/// Entered by the boot hart with the device tree address from the SBI.
 #[no_mangle]
-pub fn rust_main() -> ! {
+pub fn rust_main(_hart_id: usize, dtb: usize) -> ! {
     clear_bss();
+    mm::init_heap();
+    dtb::init(dtb);
     mm::init();
+    drivers::probe();
     UART.init();
-    println!("KERN: init gpu");
-    let _gpu = GPU_DEVICE.clone();
-    println!("KERN: init keyboard");
+    logging::init();
+    let (major, minor) = sbi::spec_version();
+    info!("SBI v{}.{}", major, minor);
+    for extension in sbi::missing_extensions() {
+        warn!("SBI lacks the {} extension", extension);
+    }
+    info!(
+        "memory ends at {:#x}, bootargs \"{}\"",
+        dtb::memory_end(),
+        dtb::bootargs()
+    );
+    mm::audit_kernel_mappings();
+    if config::DUMP_KERNEL_MAPPINGS {
+        mm::debug_dump_kernel_mappings();
+    }
+    info!("init gpu");
+    let (width, height) = GPU_DEVICE.resolution();
+    info!("gpu scanout {}x{}", width, height);
+    info!("init keyboard");
     let _keyboard = KEYBOARD_DEVICE.clone();
-    println!("KERN: init mouse");
+    info!("init mouse");
     let _mouse = MOUSE_DEVICE.clone();
-    println!("KERN: init trap");
+    info!("init trap");
     trap::init();
+    debug::gdbstub::init();
     trap::enable_timer_interrupt();
+    ipi::init();
     timer::set_next_trigger();
+    timer::init_realtime();
+    let seed_len = drivers::rng::init();
+    info!("entropy pool seeded, {} bytes from virtio-rng", seed_len);
+    lang_items::init_stack_guard();
     board::device_init();
+    fs::init();
     fs::list_apps();
     task::add_initproc();
     *DEV_NON_BLOCKING_ACCESS.exclusive_access() = true;
+    start_secondary_harts();
     task::run_tasks();
     panic!("Unreachable in rust_main!");
 }
+
+/// Wake up the other harts through SBI HSM, those that do not exist have
+/// no status.
+fn start_secondary_harts() {
+    extern "C" {
+        fn _start_secondary();
+    }
+    for hart_id in (0..config::MAX_HARTS).filter(|id| *id != task::hart_id()) {
+        if sbi::hart_status(hart_id) == Ok(sbi::HartState::Stopped) {
+            if let Err(err) = sbi::hart_start(hart_id, _start_secondary as usize, 0) {
+                warn!("cannot start hart {}: {:?}", hart_id, err);
+            }
+        }
+    }
+}
+
+#[no_mangle]
+pub fn rust_main_secondary() -> ! {
+    mm::KERNEL_SPACE.shared_access().activate();
+    trap::init();
+    trap::enable_timer_interrupt();
+    ipi::init();
+    timer::set_next_trigger();
+    board::hart_irq_init();
+    info!("hart {} started", task::hart_id());
+    task::run_tasks();
+    panic!("Unreachable in rust_main_secondary!");
+}
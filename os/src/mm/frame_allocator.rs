@@ -1,25 +1,41 @@
+use super::swap::reclaim_on_oom;
 use super::{PhysAddr, PhysPageNum};
-use crate::config::MEMORY_END;
-use crate::sync::UPIntrFreeCell;
+use crate::dtb::memory_end;
+use crate::sync::SpinMutexIrqSave;
+use alloc::collections::{BTreeMap, BTreeSet};
+use alloc::format;
+use alloc::string::String;
 use alloc::vec::Vec;
 use core::fmt::{self, Debug, Formatter};
+use core::panic::Location;
 use lazy_static::*;
 
 pub struct FrameTracker {
     pub ppn: PhysPageNum,
+    /// where the frame was allocated
+    site: &'static Location<'static>,
 }
 
 impl FrameTracker {
-    pub fn new(ppn: PhysPageNum) -> Self {
+    fn new(ppn: PhysPageNum, site: &'static Location<'static>) -> Self {
         // page cleaning
         let bytes_array = ppn.get_bytes_array();
         for i in bytes_array {
             *i = 0;
         }
-        Self { ppn }
+        FRAME_SITES
+            .exclusive_access()
+            .entry((site.file(), site.line()))
+            .or_default()
+            .alloc();
+        Self { ppn, site }
     }
 }
 
+/// No frame was left to allocate.
+#[derive(Debug)]
+pub struct OutOfMemory;
+
 impl Debug for FrameTracker {
     fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
         f.write_fmt(format_args!("FrameTracker:PPN={:#x}", self.ppn.0))
@@ -29,73 +45,149 @@ impl Debug for FrameTracker {
 impl Drop for FrameTracker {
     fn drop(&mut self) {
         frame_dealloc(self.ppn);
+        if let Some(stats) = FRAME_SITES
+            .exclusive_access()
+            .get_mut(&(self.site.file(), self.site.line()))
+        {
+            stats.current -= 1;
+        }
+    }
+}
+
+/// Frames allocated from one call site.
+#[derive(Default, Clone, Copy)]
+pub struct SiteStats {
+    pub current: usize,
+    pub peak: usize,
+    pub allocs: usize,
+}
+
+impl SiteStats {
+    fn alloc(&mut self) {
+        self.current += 1;
+        self.peak = self.peak.max(self.current);
+        self.allocs += 1;
     }
 }
 
 trait FrameAllocator {
     fn new() -> Self;
     fn alloc(&mut self) -> Option<PhysPageNum>;
-    fn alloc_more(&mut self, pages: usize) -> Option<Vec<PhysPageNum>>;
+    fn alloc_contiguous(&mut self, pages: usize) -> Option<PhysPageNum>;
     fn dealloc(&mut self, ppn: PhysPageNum);
 }
 
-pub struct StackFrameAllocator {
-    current: usize,
+/// Blocks hold at most 2^MAX_ORDER frames.
+const MAX_ORDER: usize = 10;
+
+/// Hands out blocks of 2^order frames, splitting larger blocks on demand
+/// and merging a freed block with its buddy, so that runs of contiguous
+/// frames survive fragmentation.
+pub struct BuddyFrameAllocator {
+    start: usize,
     end: usize,
-    recycled: Vec<usize>,
+    /// free blocks of each order, as offsets from `start`
+    free_lists: Vec<BTreeSet<usize>>,
+    allocated: usize,
+    /// the most frames allocated at once
+    peak: usize,
 }
 
-impl StackFrameAllocator {
+impl BuddyFrameAllocator {
     pub fn init(&mut self, l: PhysPageNum, r: PhysPageNum) {
-        self.current = l.0;
+        self.start = l.0;
         self.end = r.0;
-        // println!("last {} Physical Frames.", self.end - self.current);
+        // cover the range with the largest aligned blocks that fit
+        let total = self.end - self.start;
+        let mut offset = 0;
+        while offset < total {
+            let mut order = MAX_ORDER;
+            while offset % (1 << order) != 0 || offset + (1 << order) > total {
+                order -= 1;
+            }
+            self.free_lists[order].insert(offset);
+            offset += 1 << order;
+        }
+    }
+    /// Return (used, peak used, total) number of frames.
+    pub fn stats(&self) -> (usize, usize, usize) {
+        (self.allocated, self.peak, self.end - self.start)
+    }
+    /// Take a free block of `order`, splitting a larger one if needed.
+    fn alloc_block(&mut self, order: usize) -> Option<usize> {
+        let found = (order..=MAX_ORDER).find(|&o| !self.free_lists[o].is_empty())?;
+        let offset = self.free_lists[found].pop_first().unwrap();
+        // the upper halves split off stay free
+        for o in order..found {
+            self.free_lists[o].insert(offset + (1 << o));
+        }
+        Some(offset)
+    }
+    /// Give back a block of `order`, merging it with its buddy while that
+    /// is free too.
+    fn free_block(&mut self, mut offset: usize, mut order: usize) {
+        while order < MAX_ORDER {
+            let buddy = offset ^ (1 << order);
+            if !self.free_lists[order].remove(&buddy) {
+                break;
+            }
+            offset = offset.min(buddy);
+            order += 1;
+        }
+        self.free_lists[order].insert(offset);
+    }
+    /// Whether the frame at `offset` lies in a free block.
+    fn is_free(&self, offset: usize) -> bool {
+        (0..=MAX_ORDER).any(|order| self.free_lists[order].contains(&(offset >> order << order)))
     }
 }
-impl FrameAllocator for StackFrameAllocator {
+
+impl FrameAllocator for BuddyFrameAllocator {
     fn new() -> Self {
         Self {
-            current: 0,
+            start: 0,
             end: 0,
-            recycled: Vec::new(),
+            free_lists: (0..=MAX_ORDER).map(|_| BTreeSet::new()).collect(),
+            allocated: 0,
+            peak: 0,
         }
     }
     fn alloc(&mut self) -> Option<PhysPageNum> {
-        if let Some(ppn) = self.recycled.pop() {
-            Some(ppn.into())
-        } else if self.current == self.end {
-            None
-        } else {
-            self.current += 1;
-            Some((self.current - 1).into())
-        }
+        self.alloc_contiguous(1)
     }
-    fn alloc_more(&mut self, pages: usize) -> Option<Vec<PhysPageNum>> {
-        if self.current + pages >= self.end {
-            None
-        } else {
-            self.current += pages;
-            let arr: Vec<usize> = (1..pages + 1).collect();
-            let v = arr.iter().map(|x| (self.current - x).into()).collect();
-            Some(v)
+    fn alloc_contiguous(&mut self, pages: usize) -> Option<PhysPageNum> {
+        let order = pages.next_power_of_two().trailing_zeros() as usize;
+        if pages == 0 || order > MAX_ORDER {
+            return None;
+        }
+        let offset = self.alloc_block(order)?;
+        // frames are freed one by one, so the unused tail can go back now
+        for tail in offset + pages..offset + (1 << order) {
+            self.free_block(tail, 0);
         }
+        self.allocated += pages;
+        self.peak = self.peak.max(self.allocated);
+        Some((self.start + offset).into())
     }
     fn dealloc(&mut self, ppn: PhysPageNum) {
         let ppn = ppn.0;
         // validity check
-        if ppn >= self.current || self.recycled.iter().any(|&v| v == ppn) {
+        if !(self.start..self.end).contains(&ppn) || self.is_free(ppn - self.start) {
             panic!("Frame ppn={:#x} has not been allocated!", ppn);
         }
-        // recycle
-        self.recycled.push(ppn);
+        self.free_block(ppn - self.start, 0);
+        self.allocated -= 1;
     }
 }
 
-type FrameAllocatorImpl = StackFrameAllocator;
+type FrameAllocatorImpl = BuddyFrameAllocator;
 
 lazy_static! {
-    pub static ref FRAME_ALLOCATOR: UPIntrFreeCell<FrameAllocatorImpl> =
-        unsafe { UPIntrFreeCell::new(FrameAllocatorImpl::new()) };
+    pub static ref FRAME_ALLOCATOR: SpinMutexIrqSave<FrameAllocatorImpl> =
+        SpinMutexIrqSave::new(FrameAllocatorImpl::new());
+    /// frame usage by the file and line that allocated the frames
+    static ref FRAME_SITES: SpinMutexIrqSave<BTreeMap<(&'static str, u32), SiteStats>> =
+        SpinMutexIrqSave::new(BTreeMap::new());
 }
 
 pub fn init_frame_allocator() {
@@ -104,28 +196,64 @@ pub fn init_frame_allocator() {
     }
     FRAME_ALLOCATOR.exclusive_access().init(
         PhysAddr::from(ekernel as usize).ceil(),
-        PhysAddr::from(MEMORY_END).floor(),
+        PhysAddr::from(memory_end()).floor(),
     );
 }
 
+/// Take frames with `alloc`. When none are left, swap user pages out to
+/// make room for `pages` and try once more.
+fn alloc_or_reclaim(
+    pages: usize,
+    alloc: impl Fn(&mut FrameAllocatorImpl) -> Option<PhysPageNum>,
+) -> Option<PhysPageNum> {
+    let ppn = alloc(&mut FRAME_ALLOCATOR.exclusive_access());
+    if ppn.is_some() {
+        return ppn;
+    }
+    reclaim_on_oom(pages);
+    alloc(&mut FRAME_ALLOCATOR.exclusive_access())
+}
+
+#[track_caller]
 pub fn frame_alloc() -> Option<FrameTracker> {
-    FRAME_ALLOCATOR
-        .exclusive_access()
-        .alloc()
-        .map(FrameTracker::new)
+    let site = Location::caller();
+    let ppn = statistic_time!(
+        "frame_alloc",
+        alloc_or_reclaim(1, |allocator| allocator.alloc())
+    )?;
+    Some(FrameTracker::new(ppn, site))
 }
 
-pub fn frame_alloc_more(num: usize) -> Option<Vec<FrameTracker>> {
-    FRAME_ALLOCATOR
-        .exclusive_access()
-        .alloc_more(num)
-        .map(|x| x.iter().map(|&t| FrameTracker::new(t)).collect())
+/// Allocate `num` physically contiguous frames, in ascending order.
+#[track_caller]
+pub fn frame_alloc_contiguous(num: usize) -> Option<Vec<FrameTracker>> {
+    let site = Location::caller();
+    let base = alloc_or_reclaim(num, |allocator| allocator.alloc_contiguous(num))?;
+    Some(
+        (base.0..base.0 + num)
+            .map(|ppn| FrameTracker::new(ppn.into(), site))
+            .collect(),
+    )
 }
 
 pub fn frame_dealloc(ppn: PhysPageNum) {
     FRAME_ALLOCATOR.exclusive_access().dealloc(ppn);
 }
 
+pub fn frame_stats() -> (usize, usize, usize) {
+    FRAME_ALLOCATOR.exclusive_access().stats()
+}
+
+/// Frame usage of every call site that allocated frames, as
+/// ("file:line", stats).
+pub fn frame_site_stats() -> Vec<(String, SiteStats)> {
+    FRAME_SITES
+        .exclusive_access()
+        .iter()
+        .map(|((file, line), stats)| (format!("{}:{}", file, line), *stats))
+        .collect()
+}
+
 #[allow(unused)]
 pub fn frame_allocator_test() {
     let mut v: Vec<FrameTracker> = Vec::new();
@@ -145,18 +273,21 @@ pub fn frame_allocator_test() {
 }
 
 #[allow(unused)]
-pub fn frame_allocator_alloc_more_test() {
+pub fn frame_allocator_contiguous_test() {
     let mut v: Vec<FrameTracker> = Vec::new();
-    let frames = frame_alloc_more(5).unwrap();
-    for frame in &frames {
-        println!("{:?}", frame);
+    let frames = frame_alloc_contiguous(5).unwrap();
+    for (i, frame) in frames.iter().enumerate() {
+        assert_eq!(frame.ppn.0, frames[0].ppn.0 + i);
     }
     v.extend(frames);
+    // a hole left by a single frame does not break up a later run
+    let single = frame_alloc().unwrap();
     v.clear();
-    let frames = frame_alloc_more(5).unwrap();
+    let frames = frame_alloc_contiguous(5).unwrap();
     for frame in &frames {
         println!("{:?}", frame);
     }
-    drop(v);
-    println!("frame_allocator_test passed!");
+    drop(single);
+    drop(frames);
+    println!("frame_allocator_contiguous_test passed!");
 }
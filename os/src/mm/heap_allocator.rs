@@ -1,8 +1,45 @@
+use super::slab::{slab_alloc, slab_dealloc};
 use crate::config::KERNEL_HEAP_SIZE;
 use buddy_system_allocator::LockedHeap;
+use core::alloc::{GlobalAlloc, Layout};
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+/// `LockedHeap` that also counts what goes through it, with the object
+/// caches in front of it.
+struct CountingHeap {
+    heap: LockedHeap,
+    /// requested bytes in use, the most so far
+    peak: AtomicUsize,
+    current: AtomicUsize,
+    allocs: AtomicUsize,
+    frees: AtomicUsize,
+}
+
+unsafe impl GlobalAlloc for CountingHeap {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let ptr = slab_alloc(&self.heap, layout);
+        if !ptr.is_null() {
+            let current = self.current.fetch_add(layout.size(), Ordering::Relaxed) + layout.size();
+            self.peak.fetch_max(current, Ordering::Relaxed);
+            self.allocs.fetch_add(1, Ordering::Relaxed);
+        }
+        ptr
+    }
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        slab_dealloc(&self.heap, ptr, layout);
+        self.current.fetch_sub(layout.size(), Ordering::Relaxed);
+        self.frees.fetch_add(1, Ordering::Relaxed);
+    }
+}
 
 #[global_allocator]
-static HEAP_ALLOCATOR: LockedHeap = LockedHeap::empty();
+static HEAP_ALLOCATOR: CountingHeap = CountingHeap {
+    heap: LockedHeap::empty(),
+    peak: AtomicUsize::new(0),
+    current: AtomicUsize::new(0),
+    allocs: AtomicUsize::new(0),
+    frees: AtomicUsize::new(0),
+};
 
 #[alloc_error_handler]
 pub fn handle_alloc_error(layout: core::alloc::Layout) -> ! {
@@ -14,11 +51,31 @@ static mut HEAP_SPACE: [u8; KERNEL_HEAP_SIZE] = [0; KERNEL_HEAP_SIZE];
 pub fn init_heap() {
     unsafe {
         HEAP_ALLOCATOR
+            .heap
             .lock()
             .init(HEAP_SPACE.as_ptr() as usize, KERNEL_HEAP_SIZE);
     }
 }
 
+/// Return (requested, actually allocated, total) bytes of the kernel heap.
+pub fn heap_stats() -> (usize, usize, usize) {
+    let heap = HEAP_ALLOCATOR.heap.lock();
+    (
+        heap.stats_alloc_user(),
+        heap.stats_alloc_actual(),
+        heap.stats_total_bytes(),
+    )
+}
+
+/// Return (peak requested bytes, allocations, frees) of the kernel heap.
+pub fn heap_counts() -> (usize, usize, usize) {
+    (
+        HEAP_ALLOCATOR.peak.load(Ordering::Relaxed),
+        HEAP_ALLOCATOR.allocs.load(Ordering::Relaxed),
+        HEAP_ALLOCATOR.frees.load(Ordering::Relaxed),
+    )
+}
+
 #[allow(unused)]
 pub fn heap_test() {
     use alloc::boxed::Box;
@@ -0,0 +1,79 @@
+use super::frame_allocator::{frame_site_stats, frame_stats};
+use super::heap_allocator::{heap_counts, heap_stats};
+use super::slab::slab_stats;
+use super::swap::swap_stats;
+use crate::config::PAGE_SIZE;
+use crate::task::processes;
+use alloc::string::String;
+use core::fmt::Write;
+use easy_fs::{block_cache_stats, BLOCK_SZ};
+
+/// A snapshot of kernel memory usage in a human readable form,
+/// shared by `sys_meminfo` and anything else that wants to print it.
+pub fn meminfo() -> String {
+    let mut report = String::new();
+    let (used_frames, peak_frames, total_frames) = frame_stats();
+    writeln!(
+        report,
+        "Frames:     {} / {} pages used ({} KiB free), peak {}",
+        used_frames,
+        total_frames,
+        (total_frames - used_frames) * PAGE_SIZE / 1024,
+        peak_frames
+    )
+    .unwrap();
+    let (heap_user, heap_actual, heap_total) = heap_stats();
+    let (heap_peak, heap_allocs, heap_frees) = heap_counts();
+    writeln!(
+        report,
+        "KernelHeap: {} B requested, {} / {} B allocated, peak {} B, {} allocs {} frees",
+        heap_user, heap_actual, heap_total, heap_peak, heap_allocs, heap_frees
+    )
+    .unwrap();
+    writeln!(report, "CACHE   SIZE   INUSE  PEAK   ALLOCS  SLABS").unwrap();
+    for stats in slab_stats() {
+        writeln!(
+            report,
+            "{:<7} {:<6} {:<6} {:<6} {:<7} {}",
+            stats.name, stats.object_size, stats.in_use, stats.peak, stats.allocs, stats.slabs
+        )
+        .unwrap();
+    }
+    let (cached_blocks, cache_capacity) = block_cache_stats();
+    writeln!(
+        report,
+        "BlockCache: {} / {} blocks ({} B)",
+        cached_blocks,
+        cache_capacity,
+        cached_blocks * BLOCK_SZ
+    )
+    .unwrap();
+    let (used_slots, total_slots) = swap_stats();
+    writeln!(
+        report,
+        "Swap:       {} / {} pages used",
+        used_slots, total_slots
+    )
+    .unwrap();
+    writeln!(report, "PID   RSS(KiB)").unwrap();
+    let mut total_rss = 0;
+    for process in processes() {
+        let rss = process.inner_exclusive_access().memory_set.rss_pages();
+        total_rss += rss;
+        writeln!(report, "{:<5} {}", process.getpid(), rss * PAGE_SIZE / 1024).unwrap();
+    }
+    writeln!(report, "Total RSS:  {} KiB", total_rss * PAGE_SIZE / 1024).unwrap();
+    // the sites holding the most frames first
+    let mut sites = frame_site_stats();
+    sites.sort_by(|a, b| b.1.current.cmp(&a.1.current));
+    writeln!(report, "FRAMES PEAK   ALLOCS  SITE").unwrap();
+    for (site, stats) in sites {
+        writeln!(
+            report,
+            "{:<6} {:<6} {:<7} {}",
+            stats.current, stats.peak, stats.allocs, site
+        )
+        .unwrap();
+    }
+    report
+}
@@ -1,15 +1,32 @@
-use super::{frame_alloc, FrameTracker};
-use super::{PTEFlags, PageTable, PageTableEntry};
+use super::shm::ShmSegment;
+use super::swap::{is_pinned, swap_read, SwapSlot};
+use super::tlb;
+use super::{frame_alloc, FrameTracker, OutOfMemory};
+use super::{PTEFlags, PageTable, PageTableEntry, PAGES_PER_HUGE_PAGE};
 use super::{PhysAddr, PhysPageNum, VirtAddr, VirtPageNum};
 use super::{StepByOne, VPNRange};
-use crate::config::{MEMORY_END, MMIO, PAGE_SIZE, TRAMPOLINE};
-use crate::sync::UPIntrFreeCell;
+use crate::config::{
+    ASLR_BRK_RANGE, ASLR_MMAP_RANGE, ASLR_PIE_RANGE, ASLR_STACK_RANGE, BRK_BASE, MMAP_BASE,
+    PAGE_SIZE, PIE_BASE, TRAMPOLINE, USER_SPACE_END,
+};
+use crate::drivers::bus::mmio_regions;
+use crate::drivers::rng::random;
+use crate::dtb::{bootflag, memory_end};
+use crate::fs::VfsInode;
+use crate::sync::RwSpinLock;
 use alloc::collections::BTreeMap;
+use alloc::format;
+use alloc::string::String;
 use alloc::sync::Arc;
+use alloc::vec;
 use alloc::vec::Vec;
 use core::arch::asm;
 use lazy_static::*;
+use log::warn;
 use riscv::register::satp;
+use xmas_elf::header;
+use xmas_elf::program::{ProgramHeader, Type};
+use xmas_elf::ElfFile;
 
 extern "C" {
     fn stext();
@@ -25,42 +42,344 @@ extern "C" {
 }
 
 lazy_static! {
-    pub static ref KERNEL_SPACE: Arc<UPIntrFreeCell<MemorySet>> =
-        Arc::new(unsafe { UPIntrFreeCell::new(MemorySet::new_kernel()) });
+    /// Shared for reading its token and translations, held exclusively
+    /// only to map or unmap kernel stacks.
+    pub static ref KERNEL_SPACE: Arc<RwSpinLock<MemorySet>> = Arc::new(RwSpinLock::new(
+        MemorySet::new_kernel().expect("no memory for the kernel space")
+    ));
 }
 
 pub fn kernel_token() -> usize {
-    KERNEL_SPACE.exclusive_access().token()
+    KERNEL_SPACE.shared_access().token()
+}
+
+/// Where `va` is mapped in the kernel space, with no kernel stack being
+/// mapped or unmapped meanwhile.
+pub fn kernel_translate_va(va: VirtAddr) -> Option<PhysAddr> {
+    KERNEL_SPACE.shared_access().page_table.translate_va(va)
 }
 
 pub struct MemorySet {
     page_table: PageTable,
     areas: Vec<MapArea>,
+    /// where `evict_one` resumes its scan
+    clock_hand: VirtPageNum,
+    /// the most pages owned at once, taken before any are freed
+    peak_rss: usize,
+    /// where `find_free_area` starts looking
+    mmap_base: usize,
+    /// where the program break started, the heap goes from here
+    brk_base: usize,
+    /// the program break, the end of the heap
+    brk: usize,
 }
 
 impl MemorySet {
-    pub fn new_bare() -> Self {
-        Self {
-            page_table: PageTable::new(),
+    pub fn new_bare() -> Result<Self, OutOfMemory> {
+        Ok(Self {
+            page_table: PageTable::new()?,
             areas: Vec::new(),
-        }
+            clock_hand: VirtPageNum(0),
+            peak_rss: 0,
+            mmap_base: MMAP_BASE,
+            brk_base: BRK_BASE,
+            brk: BRK_BASE,
+        })
     }
     pub fn token(&self) -> usize {
         self.page_table.token()
     }
+    /// Number of frames owned by this address space, page tables included.
+    pub fn rss_pages(&self) -> usize {
+        self.page_table.frame_count()
+            + self
+                .areas
+                .iter()
+                .map(|area| area.data_frames.len())
+                .sum::<usize>()
+    }
+    /// The most pages this address space has owned at once.
+    pub fn peak_rss_pages(&self) -> usize {
+        self.peak_rss.max(self.rss_pages())
+    }
+    /// Remember the current size before freeing pages.
+    fn record_peak_rss(&mut self) {
+        self.peak_rss = self.peak_rss_pages();
+    }
     /// Assume that no conflicts.
     pub fn insert_framed_area(
         &mut self,
         start_va: VirtAddr,
         end_va: VirtAddr,
         permission: MapPermission,
-    ) {
+    ) -> Result<(), OutOfMemory> {
         self.push(
             MapArea::new(start_va, end_va, MapType::Framed, permission),
             None,
-        );
+        )
+    }
+    /// The lowest address from the mmap base on with `len` bytes that no
+    /// area uses.
+    pub fn find_free_area(&self, len: usize) -> Option<usize> {
+        let pages = len.div_ceil(PAGE_SIZE);
+        let mut start = VirtAddr::from(self.mmap_base).floor();
+        loop {
+            let range = VPNRange::new(start, VirtPageNum(start.0 + pages));
+            let overlapping = self.areas.iter().filter(|area| area.overlaps(&range));
+            match overlapping.map(|area| area.vpn_range.get_end()).max() {
+                Some(end) => start = end,
+                None => break,
+            }
+        }
+        let start: usize = VirtAddr::from(start).into();
+        (start + len <= USER_SPACE_END).then_some(start)
+    }
+    /// Like `insert_framed_area`, but fails instead of panicking if the
+    /// range is not page aligned or overlaps anything mapped already.
+    pub fn insert_framed_area_checked(
+        &mut self,
+        start_va: VirtAddr,
+        end_va: VirtAddr,
+        permission: MapPermission,
+    ) -> bool {
+        if !start_va.aligned() || start_va.0 >= end_va.0 {
+            return false;
+        }
+        let vpn_range = VPNRange::new(start_va.floor(), end_va.ceil());
+        if self.areas.iter().any(|area| area.overlaps(&vpn_range))
+            || vpn_range.into_iter().any(|vpn| {
+                self.page_table
+                    .translate(vpn)
+                    .map_or(false, |pte| pte.is_valid())
+            })
+        {
+            return false;
+        }
+        self.insert_framed_area(start_va, end_va, permission)
+            .is_ok()
+    }
+    /// Map all of `shm` at `start_va`, failing if the address is not page
+    /// aligned or anything is mapped in the way.
+    pub fn attach_shm(
+        &mut self,
+        start_va: VirtAddr,
+        shm: Arc<ShmSegment>,
+        permission: MapPermission,
+    ) -> bool {
+        let end_va = match start_va.0.checked_add(shm.pages() * PAGE_SIZE) {
+            Some(end) if start_va.aligned() && end <= USER_SPACE_END => VirtAddr::from(end),
+            _ => return false,
+        };
+        let vpn_range = VPNRange::new(start_va.floor(), end_va.ceil());
+        if self.areas.iter().any(|area| area.overlaps(&vpn_range))
+            || vpn_range.into_iter().any(|vpn| {
+                self.page_table
+                    .translate(vpn)
+                    .map_or(false, |pte| pte.is_valid())
+            })
+        {
+            return false;
+        }
+        let mut area = MapArea::new(start_va, end_va, MapType::Shared, permission);
+        area.shm = Some(shm);
+        self.push(area, None).is_ok()
+    }
+    /// Map `[start_va, end_va)` to the physical pages from `ppn` on, such
+    /// as the memory of a device, failing if the range is not page
+    /// aligned or anything is mapped in the way.
+    pub fn insert_linear_area_checked(
+        &mut self,
+        start_va: VirtAddr,
+        end_va: VirtAddr,
+        ppn: PhysPageNum,
+        permission: MapPermission,
+    ) -> bool {
+        if !start_va.aligned() || start_va.0 >= end_va.0 {
+            return false;
+        }
+        let vpn_range = VPNRange::new(start_va.floor(), end_va.ceil());
+        if self.areas.iter().any(|area| area.overlaps(&vpn_range))
+            || vpn_range.into_iter().any(|vpn| {
+                self.page_table
+                    .translate(vpn)
+                    .map_or(false, |pte| pte.is_valid())
+            })
+        {
+            return false;
+        }
+        let pn_offset = ppn.0 as isize - vpn_range.get_start().0 as isize;
+        self.push(
+            MapArea::new(start_va, end_va, MapType::Linear(pn_offset), permission),
+            None,
+        )
+        .is_ok()
+    }
+    /// Unmap the shared memory segment attached at `start_va`.
+    pub fn detach_shm(&mut self, start_va: VirtAddr) -> bool {
+        if !start_va.aligned() {
+            return false;
+        }
+        let start = start_va.floor();
+        match self.areas.iter().position(|area| {
+            area.map_type == MapType::Shared && area.vpn_range.get_start() == start
+        }) {
+            Some(idx) => {
+                self.areas.remove(idx).unmap(&mut self.page_table);
+                true
+            }
+            None => false,
+        }
+    }
+    /// Unmap `[start_va, end_va)`, splitting the framed areas it cuts
+    /// through. Fails without changing anything if the range is not page
+    /// aligned, any page in it is not in a framed or linear area, or it
+    /// cuts through a linear one: those may have huge pages, so they are
+    /// only unmapped whole.
+    pub fn remove_area_range(&mut self, start_va: VirtAddr, end_va: VirtAddr) -> bool {
+        if !start_va.aligned() || start_va.0 >= end_va.0 {
+            return false;
+        }
+        let vpn_range = VPNRange::new(start_va.floor(), end_va.ceil());
+        let covered = vpn_range.into_iter().all(|vpn| {
+            self.areas.iter().any(|area| {
+                matches!(area.map_type, MapType::Framed | MapType::Linear(_)) && area.contains(vpn)
+            })
+        });
+        let (start, end) = (vpn_range.get_start(), vpn_range.get_end());
+        let cuts_linear = self.areas.iter().any(|area| {
+            matches!(area.map_type, MapType::Linear(_))
+                && area.overlaps(&vpn_range)
+                && (area.vpn_range.get_start() < start || area.vpn_range.get_end() > end)
+        });
+        if !covered || cuts_linear {
+            return false;
+        }
+        self.record_peak_rss();
+        let mut kept = Vec::new();
+        for mut area in self.areas.drain(..) {
+            if !area.overlaps(&vpn_range) {
+                kept.push(area);
+                continue;
+            }
+            // [area_start, start) | [start, end) | [end, area_end)
+            let tail = area.split_off(end);
+            if let Some(mut middle) = area.split_off(start) {
+                middle.unmap(&mut self.page_table);
+            }
+            if !area.is_empty() {
+                kept.push(area);
+            }
+            if let Some(tail) = tail {
+                kept.push(tail);
+            }
+        }
+        self.areas = kept;
+        true
+    }
+    /// Extend the framed area ending at `end` down to `start`, mapping the
+    /// new pages. Fails if there is no such area, it reaches `start`
+    /// already, something else is mapped in between or memory runs out.
+    pub fn extend_area_down(&mut self, end: VirtPageNum, start: VirtPageNum) -> bool {
+        let idx =
+            match self.areas.iter().position(|area| {
+                area.map_type == MapType::Framed && area.vpn_range.get_end() == end
+            }) {
+                Some(idx) => idx,
+                None => return false,
+            };
+        let old_start = self.areas[idx].vpn_range.get_start();
+        if start >= old_start {
+            return false;
+        }
+        let new_range = VPNRange::new(start, old_start);
+        if self.areas.iter().any(|area| area.overlaps(&new_range))
+            || new_range.into_iter().any(|vpn| {
+                self.page_table
+                    .translate(vpn)
+                    .map_or(false, |pte| pte.is_valid())
+            })
+        {
+            return false;
+        }
+        // downwards, so the area keeps covering just what got mapped
+        let area = &mut self.areas[idx];
+        let vpns: Vec<VirtPageNum> = new_range.into_iter().collect();
+        for vpn in vpns.into_iter().rev() {
+            let frame = match frame_alloc() {
+                Some(frame) => frame,
+                None => return false,
+            };
+            if area.map_frame(&mut self.page_table, vpn, frame).is_err() {
+                return false;
+            }
+            area.vpn_range = VPNRange::new(vpn, end);
+        }
+        true
+    }
+    /// The program break.
+    pub fn brk(&self) -> usize {
+        self.brk
+    }
+    /// Move the program break to `new_brk`, growing the heap area up to it
+    /// or unmapping the pages past it. New heap pages are only given frames
+    /// when first touched. Fails and leaves the break where it was if it
+    /// would go below where it started or into the mmap areas, or
+    /// something else is mapped in the way.
+    pub fn set_brk(&mut self, new_brk: usize) -> bool {
+        if new_brk < self.brk_base || new_brk > MMAP_BASE {
+            return false;
+        }
+        let old_end = VirtAddr::from(self.brk).ceil();
+        let new_end = VirtAddr::from(new_brk).ceil();
+        let moved = if new_end > old_end {
+            self.grow_heap(old_end, new_end)
+        } else if new_end < old_end {
+            self.remove_area_range(new_end.into(), old_end.into())
+        } else {
+            true
+        };
+        if moved {
+            self.brk = new_brk;
+        }
+        moved
+    }
+    /// Extend the heap area ending at `start` up to `end`, or add one there
+    /// if there is none, e.g. before the first `brk` or after the top of
+    /// the heap got unmapped. Nothing is mapped until faulted in.
+    fn grow_heap(&mut self, start: VirtPageNum, end: VirtPageNum) -> bool {
+        let new_range = VPNRange::new(start, end);
+        if self.areas.iter().any(|area| area.overlaps(&new_range)) {
+            return false;
+        }
+        match self
+            .areas
+            .iter_mut()
+            .find(|area| area.heap && area.vpn_range.get_end() == start)
+        {
+            Some(area) => area.vpn_range = VPNRange::new(area.vpn_range.get_start(), end),
+            None => {
+                let mut area = MapArea::new(
+                    start.into(),
+                    end.into(),
+                    MapType::Framed,
+                    MapPermission::R | MapPermission::W | MapPermission::U,
+                );
+                area.heap = true;
+                self.push_lazy(area);
+            }
+        }
+        true
+    }
+    /// Remove the area containing `vpn`, wherever it starts.
+    pub fn remove_area_containing(&mut self, vpn: VirtPageNum) {
+        self.record_peak_rss();
+        if let Some(idx) = self.areas.iter().position(|area| area.contains(vpn)) {
+            let mut area = self.areas.remove(idx);
+            area.unmap(&mut self.page_table);
+        }
     }
     pub fn remove_area_with_start_vpn(&mut self, start_vpn: VirtPageNum) {
+        self.record_peak_rss();
         if let Some((idx, area)) = self
             .areas
             .iter_mut()
@@ -74,26 +393,127 @@ impl MemorySet {
     /// Add a new MapArea into this MemorySet.
     /// Assuming that there are no conflicts in the virtual address
     /// space.
-    pub fn push(&mut self, mut map_area: MapArea, data: Option<&[u8]>) {
-        map_area.map(&mut self.page_table);
+    pub fn push(&mut self, mut map_area: MapArea, data: Option<&[u8]>) -> Result<(), OutOfMemory> {
+        map_area.map(&mut self.page_table)?;
         if let Some(data) = data {
             map_area.copy_data(&self.page_table, data);
         }
         self.areas.push(map_area);
+        Ok(())
+    }
+    /// Add a file-backed or heap MapArea whose pages are filled in on
+    /// first access.
+    fn push_lazy(&mut self, map_area: MapArea) {
+        assert!(map_area.is_lazy());
+        self.areas.push(map_area);
+    }
+    /// Where the contents of `vpn` come from if it belongs to this
+    /// address space but is not present, i.e. not loaded or swapped out.
+    pub fn fault_source(&self, vpn: VirtPageNum) -> Option<PageSource> {
+        let area = self.areas.iter().find(|area| area.contains(vpn))?;
+        if area.data_frames.contains_key(&vpn) {
+            return None;
+        }
+        if let Some(slot) = area.swapped.get(&vpn) {
+            return Some(PageSource::Swap(slot.id()));
+        }
+        if area.heap {
+            return Some(PageSource::Zero);
+        }
+        area.backing.clone().map(PageSource::File)
+    }
+    /// Install `frame`, filled from `source`, at `vpn`. Returns false if
+    /// the area went away while the page was read.
+    pub fn map_faulted_page(
+        &mut self,
+        vpn: VirtPageNum,
+        frame: FrameTracker,
+        source: &PageSource,
+    ) -> Result<bool, OutOfMemory> {
+        let area = match self.areas.iter_mut().find(|area| area.contains(vpn)) {
+            Some(area) => area,
+            None => return Ok(false),
+        };
+        // another thread may have faulted the page in meanwhile, or it
+        // may have moved elsewhere; either way the access is retried
+        if area.data_frames.contains_key(&vpn) {
+            return Ok(true);
+        }
+        match source {
+            PageSource::Swap(slot) => {
+                if area.swapped.get(&vpn).map(|s| s.id()) != Some(*slot) {
+                    return Ok(true);
+                }
+                // the slot is only given up once the page is mapped
+                area.map_frame(&mut self.page_table, vpn, frame)?;
+                area.swapped.remove(&vpn);
+                return Ok(true);
+            }
+            PageSource::File(_) if area.backing.is_none() => return Ok(false),
+            PageSource::Zero if !area.heap => return Ok(false),
+            _ if area.swapped.contains_key(&vpn) => return Ok(true),
+            _ => {}
+        }
+        area.map_frame(&mut self.page_table, vpn, frame)?;
+        Ok(true)
+    }
+    /// Pick a resident user page with the clock algorithm: a page whose
+    /// accessed bit is set gets it cleared and a second chance. The page
+    /// is unmapped and its frame returned together with the id of `slot`
+    /// if the caller has to write it there; clean file-backed pages are
+    /// simply dropped and read from the file again later.
+    pub fn evict_one(&mut self, slot: Option<SwapSlot>) -> Option<(FrameTracker, Option<usize>)> {
+        let mut candidates: Vec<VirtPageNum> = self
+            .areas
+            .iter()
+            .filter(|area| area.map_perm.contains(MapPermission::U))
+            .filter(|area| slot.is_some() || area.is_clean_file())
+            .flat_map(|area| area.data_frames.iter())
+            .filter(|(_, frame)| !is_pinned(frame.ppn))
+            .map(|(vpn, _)| *vpn)
+            .collect();
+        if candidates.is_empty() {
+            return None;
+        }
+        candidates.sort();
+        let len = candidates.len();
+        let start = candidates.partition_point(|vpn| *vpn < self.clock_hand);
+        // after one sweep every accessed bit is clear
+        let victim = (0..2 * len)
+            .map(|i| candidates[(start + i) % len])
+            .find(|vpn| !self.page_table.test_and_clear_accessed(*vpn))
+            .unwrap();
+        self.clock_hand = VirtPageNum(victim.0 + 1);
+        self.record_peak_rss();
+        let area = self
+            .areas
+            .iter_mut()
+            .find(|area| area.contains(victim))
+            .unwrap();
+        let frame = area.data_frames.remove(&victim).unwrap();
+        self.page_table.unmap(victim);
+        tlb::flush(self.page_table.token(), victim, 1);
+        if area.is_clean_file() {
+            return Some((frame, None));
+        }
+        let slot = slot.unwrap();
+        let id = slot.id();
+        area.swapped.insert(victim, slot);
+        Some((frame, Some(id)))
     }
     /// Mention that trampoline is not collected by areas.
-    fn map_trampoline(&mut self) {
+    fn map_trampoline(&mut self) -> Result<(), OutOfMemory> {
         self.page_table.map(
             VirtAddr::from(TRAMPOLINE).into(),
             PhysAddr::from(strampoline as usize).into(),
-            PTEFlags::R | PTEFlags::X,
-        );
+            PTEFlags::X,
+        )
     }
     /// Without kernel stacks.
-    pub fn new_kernel() -> Self {
-        let mut memory_set = Self::new_bare();
+    pub fn new_kernel() -> Result<Self, OutOfMemory> {
+        let mut memory_set = Self::new_bare()?;
         // map trampoline
-        memory_set.map_trampoline();
+        memory_set.map_trampoline()?;
         // map kernel sections
         // println!(".text [{:#x}, {:#x})", stext as usize, etext as usize);
         // println!(".rodata [{:#x}, {:#x})", srodata as usize, erodata as usize);
@@ -108,10 +528,10 @@ impl MemorySet {
                 (stext as usize).into(),
                 (etext as usize).into(),
                 MapType::Identical,
-                MapPermission::R | MapPermission::X,
+                MapPermission::X,
             ),
             None,
-        );
+        )?;
         // println!("mapping .rodata section");
         memory_set.push(
             MapArea::new(
@@ -121,7 +541,7 @@ impl MemorySet {
                 MapPermission::R,
             ),
             None,
-        );
+        )?;
         // println!("mapping .data section");
         memory_set.push(
             MapArea::new(
@@ -131,7 +551,7 @@ impl MemorySet {
                 MapPermission::R | MapPermission::W,
             ),
             None,
-        );
+        )?;
         // println!("mapping .bss section");
         memory_set.push(
             MapArea::new(
@@ -141,95 +561,240 @@ impl MemorySet {
                 MapPermission::R | MapPermission::W,
             ),
             None,
-        );
+        )?;
         // println!("mapping physical memory");
         memory_set.push(
             MapArea::new(
                 (ekernel as usize).into(),
-                MEMORY_END.into(),
+                memory_end().into(),
                 MapType::Identical,
                 MapPermission::R | MapPermission::W,
             ),
             None,
-        );
+        )?;
         //println!("mapping memory-mapped registers");
-        for pair in MMIO {
+        // devices by address, some may share a page which is mapped once
+        let mut mapped_end = 0;
+        for (base, size) in mmio_regions() {
+            let start = (base & !(PAGE_SIZE - 1)).max(mapped_end);
+            let end = (base + size + PAGE_SIZE - 1) & !(PAGE_SIZE - 1);
+            if start >= end {
+                continue;
+            }
             memory_set.push(
                 MapArea::new(
-                    (*pair).0.into(),
-                    ((*pair).0 + (*pair).1).into(),
+                    start.into(),
+                    end.into(),
                     MapType::Identical,
                     MapPermission::R | MapPermission::W,
                 ),
                 None,
-            );
+            )?;
+            mapped_end = end;
         }
-        memory_set
+        Ok(memory_set)
     }
     /// Include sections in elf and trampoline,
     /// also returns user_sp_base and entry point.
-    pub fn from_elf(elf_data: &[u8]) -> (Self, usize, usize) {
-        let mut memory_set = Self::new_bare();
+    /// Position independent programs are moved by `load_bias` and have
+    /// their relative relocations applied.
+    pub fn from_elf(elf_data: &[u8]) -> Result<(Self, usize, usize), OutOfMemory> {
+        let mut memory_set = Self::new_bare()?;
         // map trampoline
-        memory_set.map_trampoline();
+        memory_set.map_trampoline()?;
         // map program headers of elf, with U flag
         let elf = xmas_elf::ElfFile::new(elf_data).unwrap();
         let elf_header = elf.header;
         let magic = elf_header.pt1.magic;
         assert_eq!(magic, [0x7f, 0x45, 0x4c, 0x46], "invalid elf!");
         let ph_count = elf_header.pt2.ph_count();
+        let bias = load_bias(&elf);
         let mut max_end_vpn = VirtPageNum(0);
         for i in 0..ph_count {
             let ph = elf.program_header(i).unwrap();
-            if ph.get_type().unwrap() == xmas_elf::program::Type::Load {
-                let start_va: VirtAddr = (ph.virtual_addr() as usize).into();
-                let end_va: VirtAddr = ((ph.virtual_addr() + ph.mem_size()) as usize).into();
-                let mut map_perm = MapPermission::U;
-                let ph_flags = ph.flags();
-                if ph_flags.is_read() {
-                    map_perm |= MapPermission::R;
-                }
-                if ph_flags.is_write() {
-                    map_perm |= MapPermission::W;
-                }
-                if ph_flags.is_execute() {
-                    map_perm |= MapPermission::X;
-                }
+            if ph.get_type().unwrap() == Type::Load {
+                let start_va: VirtAddr = (bias + ph.virtual_addr() as usize).into();
+                let end_va: VirtAddr = (bias + (ph.virtual_addr() + ph.mem_size()) as usize).into();
+                let map_perm = elf_map_perm(&ph);
                 let map_area = MapArea::new(start_va, end_va, MapType::Framed, map_perm);
                 max_end_vpn = map_area.vpn_range.get_end();
                 memory_set.push(
                     map_area,
                     Some(&elf.input[ph.offset() as usize..(ph.offset() + ph.file_size()) as usize]),
-                );
+                )?;
             }
         }
+        if bias != 0 {
+            memory_set.relocate(&elf, bias, |offset, len| {
+                elf_data[offset..offset + len].to_vec()
+            });
+        }
         let max_end_va: VirtAddr = max_end_vpn.into();
         let mut user_stack_base: usize = max_end_va.into();
-        user_stack_base += PAGE_SIZE;
-        (
+        user_stack_base += PAGE_SIZE + aslr_offset(ASLR_STACK_RANGE);
+        memory_set.mmap_base = MMAP_BASE + aslr_offset(ASLR_MMAP_RANGE);
+        memory_set.brk_base = BRK_BASE + aslr_offset(ASLR_BRK_RANGE);
+        memory_set.brk = memory_set.brk_base;
+        Ok((
             memory_set,
             user_stack_base,
-            elf.header.pt2.entry_point() as usize,
-        )
+            bias + elf.header.pt2.entry_point() as usize,
+        ))
     }
-    pub fn from_existed_user(user_space: &MemorySet) -> MemorySet {
-        let mut memory_set = Self::new_bare();
+    /// Like `from_elf`, but only reads the headers up front: executable
+    /// segments are left unmapped and faulted in from `inode` page by
+    /// page, the others are still copied eagerly.
+    pub fn from_elf_inode(inode: &Arc<dyn VfsInode>) -> Result<(Self, usize, usize), OutOfMemory> {
+        let mut memory_set = Self::new_bare()?;
         // map trampoline
-        memory_set.map_trampoline();
+        memory_set.map_trampoline()?;
+        // the program header table usually fits in the first page
+        let mut header = read_file(inode, 0, PAGE_SIZE);
+        let elf = xmas_elf::ElfFile::new(&header).unwrap();
+        let magic = elf.header.pt1.magic;
+        assert_eq!(magic, [0x7f, 0x45, 0x4c, 0x46], "invalid elf!");
+        let ph_end = elf.header.pt2.ph_offset() as usize
+            + elf.header.pt2.ph_count() as usize * elf.header.pt2.ph_entry_size() as usize;
+        if ph_end > header.len() {
+            header = read_file(inode, 0, ph_end);
+        }
+        let elf = xmas_elf::ElfFile::new(&header).unwrap();
+        let ph_count = elf.header.pt2.ph_count();
+        let bias = load_bias(&elf);
+        let mut max_end_vpn = VirtPageNum(0);
+        for i in 0..ph_count {
+            let ph = elf.program_header(i).unwrap();
+            if ph.get_type().unwrap() == Type::Load {
+                let start_va: VirtAddr = (bias + ph.virtual_addr() as usize).into();
+                let end_va: VirtAddr = (bias + (ph.virtual_addr() + ph.mem_size()) as usize).into();
+                let map_perm = elf_map_perm(&ph);
+                let mut map_area = MapArea::new(start_va, end_va, MapType::Framed, map_perm);
+                max_end_vpn = map_area.vpn_range.get_end();
+                if map_perm.contains(MapPermission::X) {
+                    map_area.backing = Some(FileBacking {
+                        inode: Arc::clone(inode),
+                        start_va: bias + ph.virtual_addr() as usize,
+                        offset: ph.offset() as usize,
+                        len: ph.file_size() as usize,
+                    });
+                    memory_set.push_lazy(map_area);
+                } else {
+                    let data = read_file(inode, ph.offset() as usize, ph.file_size() as usize);
+                    memory_set.push(map_area, Some(data.as_slice()))?;
+                }
+            }
+        }
+        if bias != 0 {
+            memory_set.relocate(&elf, bias, |offset, len| read_file(inode, offset, len));
+        }
+        let max_end_va: VirtAddr = max_end_vpn.into();
+        let mut user_stack_base: usize = max_end_va.into();
+        user_stack_base += PAGE_SIZE + aslr_offset(ASLR_STACK_RANGE);
+        memory_set.mmap_base = MMAP_BASE + aslr_offset(ASLR_MMAP_RANGE);
+        memory_set.brk_base = BRK_BASE + aslr_offset(ASLR_BRK_RANGE);
+        memory_set.brk = memory_set.brk_base;
+        Ok((
+            memory_set,
+            user_stack_base,
+            bias + elf.header.pt2.entry_point() as usize,
+        ))
+    }
+    /// Apply the dynamic relocations of a program loaded `bias` bytes
+    /// from where it was linked. `read` returns `len` bytes of the file
+    /// from `offset` on. Only the relative relocations of a static PIE
+    /// are supported, and only in writable segments, which are loaded
+    /// eagerly.
+    fn relocate(&mut self, elf: &ElfFile, bias: usize, read: impl Fn(usize, usize) -> Vec<u8>) {
+        let dynamic = (0..elf.header.pt2.ph_count())
+            .map(|i| elf.program_header(i).unwrap())
+            .find(|ph| ph.get_type() == Ok(Type::Dynamic));
+        let dynamic = match dynamic {
+            Some(ph) => read(ph.offset() as usize, ph.file_size() as usize),
+            None => return,
+        };
+        let (mut rela, mut rela_size) = (None, 0);
+        for entry in dynamic.chunks_exact(16) {
+            match (read_u64(entry, 0), read_u64(entry, 8)) {
+                (DT_NULL, _) => break,
+                (DT_RELA, addr) => rela = Some(addr),
+                (DT_RELASZ, size) => rela_size = size as usize,
+                _ => {}
+            }
+        }
+        let rela = match rela.and_then(|addr| elf_file_offset(elf, addr)) {
+            Some(offset) => read(offset, rela_size),
+            None => return,
+        };
+        for entry in rela.chunks_exact(RELA_SIZE) {
+            let (offset, info, addend) =
+                (read_u64(entry, 0), read_u64(entry, 8), read_u64(entry, 16));
+            match info as u32 {
+                R_RISCV_NONE => {}
+                R_RISCV_RELATIVE => {
+                    let va = bias + offset as usize;
+                    let value = bias.wrapping_add(addend as usize);
+                    if !self.write_at_load(va, &value.to_le_bytes()) {
+                        warn!("relocation at {:#x} not in a writable segment", va);
+                    }
+                }
+                kind => warn!("unsupported relocation type {}", kind),
+            }
+        }
+    }
+    /// Write `bytes` at `va` of a writable area that is being loaded.
+    fn write_at_load(&mut self, va: usize, bytes: &[u8]) -> bool {
+        for (i, byte) in bytes.iter().enumerate() {
+            let va = VirtAddr::from(va + i);
+            let writable = self.areas.iter().any(|area| {
+                area.contains(va.floor())
+                    && area.backing.is_none()
+                    && area.map_perm.contains(MapPermission::W)
+            });
+            if !writable {
+                return false;
+            }
+            let ppn = self.translate(va.floor()).unwrap().ppn();
+            ppn.get_bytes_array()[va.page_offset()] = *byte;
+        }
+        true
+    }
+    pub fn from_existed_user(user_space: &MemorySet) -> Result<MemorySet, OutOfMemory> {
+        let mut memory_set = Self::new_bare()?;
+        memory_set.mmap_base = user_space.mmap_base;
+        memory_set.brk_base = user_space.brk_base;
+        memory_set.brk = user_space.brk;
+        // map trampoline
+        memory_set.map_trampoline()?;
         // copy data sections/trap_context/user_stack
         for area in user_space.areas.iter() {
-            let new_area = MapArea::from_another(area);
-            memory_set.push(new_area, None);
-            // copy data from another space
-            for vpn in area.vpn_range {
-                let src_ppn = user_space.translate(vpn).unwrap().ppn();
+            let mut new_area = MapArea::from_another(area);
+            let present = |vpn: &VirtPageNum| {
+                area.data_frames.contains_key(vpn) || area.swapped.contains_key(vpn)
+            };
+            if new_area.is_lazy() {
+                // only the pages faulted in so far, the rest stay lazy
+                for vpn in area.vpn_range.into_iter().filter(present) {
+                    new_area.map_one(&mut memory_set.page_table, vpn)?;
+                }
+                memory_set.push_lazy(new_area);
+            } else {
+                memory_set.push(new_area, None)?;
+            }
+            // copy data from another space, the child gets swapped out
+            // pages back in memory
+            for vpn in area.vpn_range.into_iter().filter(present) {
                 let dst_ppn = memory_set.translate(vpn).unwrap().ppn();
+                if let Some(slot) = area.swapped.get(&vpn) {
+                    swap_read(slot.id(), dst_ppn);
+                    continue;
+                }
+                let src_ppn = user_space.translate(vpn).unwrap().ppn();
                 dst_ppn
                     .get_bytes_array()
                     .copy_from_slice(src_ppn.get_bytes_array());
             }
         }
-        memory_set
+        Ok(memory_set)
     }
     pub fn activate(&self) {
         let satp = self.page_table.token();
@@ -241,7 +806,117 @@ impl MemorySet {
     pub fn translate(&self, vpn: VirtPageNum) -> Option<PageTableEntry> {
         self.page_table.translate(vpn)
     }
+    /// The PTE flags and area found at `va`, for fault reports.
+    pub fn describe(&self, va: VirtAddr) -> String {
+        let vpn = va.floor();
+        let pte = match self.page_table.translate(vpn) {
+            Some(pte) if pte.is_valid() => format!("{:?}", pte.flags()),
+            _ => String::from("none"),
+        };
+        match self.areas.iter().find(|area| area.contains(vpn)) {
+            Some(area) => format!(
+                "pte {}, area {:#x}..{:#x} {:?}",
+                pte,
+                VirtAddr::from(area.vpn_range.get_start()).0,
+                VirtAddr::from(area.vpn_range.get_end()).0,
+                area.map_perm
+            ),
+            None => format!("pte {}, no area", pte),
+        }
+    }
+    /// A line for each area by address, for `/proc/<pid>/maps`: its range,
+    /// its permissions with `p` for private or `s` for shared, resident and
+    /// total pages, its type, and where its contents come from. Areas
+    /// without U, such as trap contexts, are `[kernel]`, those of the heap
+    /// `[heap]`.
+    pub fn maps(&self) -> String {
+        let mut areas: Vec<&MapArea> = self.areas.iter().collect();
+        areas.sort_unstable_by_key(|area| area.vpn_range.get_start());
+        let mut maps = String::new();
+        for area in areas {
+            let start = area.vpn_range.get_start();
+            let pages = area.vpn_range.get_end().0 - start.0;
+            let perm = |flag, c| if area.map_perm.contains(flag) { c } else { '-' };
+            let resident = match area.map_type {
+                MapType::Framed => area.data_frames.len(),
+                _ => pages,
+            };
+            let source = match &area.backing {
+                Some(backing) => format!(" file@{:#x}", backing.offset),
+                None if !area.map_perm.contains(MapPermission::U) => String::from(" [kernel]"),
+                None if area.heap => String::from(" [heap]"),
+                None => String::new(),
+            };
+            maps += &format!(
+                "{:08x}-{:08x} {}{}{}{} {}/{} {:?}{}\n",
+                canonical_va(start),
+                canonical_va(start) + pages * PAGE_SIZE,
+                perm(MapPermission::R, 'r'),
+                perm(MapPermission::W, 'w'),
+                perm(MapPermission::X, 'x'),
+                if area.map_type == MapType::Framed {
+                    'p'
+                } else {
+                    's'
+                },
+                resident,
+                pages,
+                area.map_type,
+                source
+            );
+        }
+        maps
+    }
+    /// Copy between `buf` and the user memory at `va` whatever its
+    /// permissions, as a debugger does, into it if `write`. Returns the
+    /// first address whose page is not resident for the caller to fault
+    /// in and retry.
+    pub fn debug_copy(&mut self, va: usize, buf: &mut [u8], write: bool) -> Result<(), usize> {
+        let mut copied = 0;
+        while copied < buf.len() {
+            let addr = VirtAddr::from(va + copied);
+            let vpn = addr.floor();
+            let ppn = match self.page_table.translate(vpn) {
+                Some(pte) if pte.is_valid() && pte.flags().contains(PTEFlags::U) => pte.ppn(),
+                _ => return Err(addr.0),
+            };
+            let offset = addr.page_offset();
+            let len = (PAGE_SIZE - offset).min(buf.len() - copied);
+            let page = &mut ppn.get_bytes_array()[offset..offset + len];
+            if write {
+                self.make_private(vpn);
+                page.copy_from_slice(&buf[copied..copied + len]);
+            } else {
+                buf[copied..copied + len].copy_from_slice(page);
+            }
+            copied += len;
+        }
+        Ok(())
+    }
+    /// Give the page `vpn` of a clean file mapping an area of its own that
+    /// is not backed by the file, so that a change made to it is swapped
+    /// out instead of dropped with the page.
+    fn make_private(&mut self, vpn: VirtPageNum) {
+        let idx = match self
+            .areas
+            .iter()
+            .position(|area| area.contains(vpn) && area.is_clean_file())
+        {
+            Some(idx) => idx,
+            None => return,
+        };
+        let mut area = self.areas.remove(idx);
+        let tail = area.split_off(VirtPageNum(vpn.0 + 1));
+        let mut page = area.split_off(vpn).unwrap();
+        page.backing = None;
+        for area in [Some(area), Some(page), tail].into_iter().flatten() {
+            if !area.is_empty() {
+                self.areas.push(area);
+            }
+        }
+    }
     pub fn recycle_data_pages(&mut self) {
+        self.record_peak_rss();
         //*self = Self::new_bare();
         self.areas.clear();
     }
@@ -252,6 +927,14 @@ pub struct MapArea {
     data_frames: BTreeMap<VirtPageNum, FrameTracker>,
     map_type: MapType,
     map_perm: MapPermission,
+    /// Set for framed areas that are filled from a file on demand.
+    backing: Option<FileBacking>,
+    /// the area is (part of) the heap, its pages are zeroed on demand
+    heap: bool,
+    /// pages of a framed area that live in the swap area
+    swapped: BTreeMap<VirtPageNum, SwapSlot>,
+    /// the segment a shared area maps, from its first page on
+    shm: Option<Arc<ShmSegment>>,
 }
 
 impl MapArea {
@@ -268,6 +951,10 @@ impl MapArea {
             data_frames: BTreeMap::new(),
             map_type,
             map_perm,
+            backing: None,
+            heap: false,
+            swapped: BTreeMap::new(),
+            shm: None,
         }
     }
     pub fn from_another(another: &MapArea) -> Self {
@@ -276,43 +963,172 @@ impl MapArea {
             data_frames: BTreeMap::new(),
             map_type: another.map_type,
             map_perm: another.map_perm,
+            backing: another.backing.clone(),
+            heap: another.heap,
+            swapped: BTreeMap::new(),
+            shm: another.shm.clone(),
         }
     }
-    pub fn map_one(&mut self, page_table: &mut PageTable, vpn: VirtPageNum) {
+    pub fn is_empty(&self) -> bool {
+        self.vpn_range.get_start() == self.vpn_range.get_end()
+    }
+    pub fn overlaps(&self, vpn_range: &VPNRange) -> bool {
+        self.vpn_range.get_start() < vpn_range.get_end()
+            && vpn_range.get_start() < self.vpn_range.get_end()
+    }
+    pub fn contains(&self, vpn: VirtPageNum) -> bool {
+        self.vpn_range.get_start() <= vpn && vpn < self.vpn_range.get_end()
+    }
+    /// Pages of this area are only mapped once accessed.
+    fn is_lazy(&self) -> bool {
+        self.backing.is_some() || self.heap
+    }
+    /// Pages of this area can be dropped and read from the file again.
+    fn is_clean_file(&self) -> bool {
+        self.backing.is_some() && !self.map_perm.contains(MapPermission::W)
+    }
+    /// Move the pages from `at` on into a new area, leaving `self` with
+    /// the pages before it (possibly none). Shared areas are only ever
+    /// unmapped whole, never split.
+    pub fn split_off(&mut self, at: VirtPageNum) -> Option<Self> {
+        let (start, end) = (self.vpn_range.get_start(), self.vpn_range.get_end());
+        if at >= end {
+            return None;
+        }
+        let at = at.max(start);
+        let data_frames = self.data_frames.split_off(&at);
+        let swapped = self.swapped.split_off(&at);
+        self.vpn_range = VPNRange::new(start, at);
+        Some(Self {
+            vpn_range: VPNRange::new(at, end),
+            data_frames,
+            map_type: self.map_type,
+            map_perm: self.map_perm,
+            backing: self.backing.clone(),
+            heap: self.heap,
+            swapped,
+            shm: self.shm.clone(),
+        })
+    }
+    pub fn map_one(
+        &mut self,
+        page_table: &mut PageTable,
+        vpn: VirtPageNum,
+    ) -> Result<(), OutOfMemory> {
         let ppn: PhysPageNum;
+        let mut frame = None;
         match self.map_type {
             MapType::Identical => {
                 ppn = PhysPageNum(vpn.0);
             }
             MapType::Framed => {
-                let frame = frame_alloc().unwrap();
-                ppn = frame.ppn;
-                self.data_frames.insert(vpn, frame);
+                let tracker = frame_alloc().ok_or(OutOfMemory)?;
+                ppn = tracker.ppn;
+                frame = Some(tracker);
             }
             MapType::Linear(pn_offset) => {
                 // check for sv39
                 assert!(vpn.0 < (1usize << 27));
                 ppn = PhysPageNum((vpn.0 as isize + pn_offset) as usize);
             }
+            MapType::Shared => {
+                let page = vpn.0 - self.vpn_range.get_start().0;
+                ppn = self.shm.as_ref().unwrap().ppn(page);
+            }
         }
         let pte_flags = PTEFlags::from_bits(self.map_perm.bits).unwrap();
-        page_table.map(vpn, ppn, pte_flags);
+        page_table.map(vpn, ppn, pte_flags)?;
+        // only kept once mapped, so a failed map frees it again
+        if let Some(frame) = frame {
+            self.data_frames.insert(vpn, frame);
+        }
+        Ok(())
+    }
+    /// Map `vpn` to a frame that was allocated and filled by the caller.
+    pub fn map_frame(
+        &mut self,
+        page_table: &mut PageTable,
+        vpn: VirtPageNum,
+        frame: FrameTracker,
+    ) -> Result<(), OutOfMemory> {
+        assert_eq!(self.map_type, MapType::Framed);
+        let pte_flags = PTEFlags::from_bits(self.map_perm.bits).unwrap();
+        page_table.map(vpn, frame.ppn, pte_flags)?;
+        tlb::flush(page_table.token(), vpn, 1);
+        self.data_frames.insert(vpn, frame);
+        Ok(())
     }
     pub fn unmap_one(&mut self, page_table: &mut PageTable, vpn: VirtPageNum) {
-        if self.map_type == MapType::Framed {
-            self.data_frames.remove(&vpn);
+        if self.map_type == MapType::Framed && self.data_frames.remove(&vpn).is_none() {
+            // a lazy page that was never touched, or a swapped out one
+            self.swapped.remove(&vpn);
+            return;
         }
         page_table.unmap(vpn);
     }
-    pub fn map(&mut self, page_table: &mut PageTable) {
-        for vpn in self.vpn_range {
-            self.map_one(page_table, vpn);
+    /// The frame a huge page at `vpn` would map to, if one fits there.
+    /// Framed pages are allocated, faulted in and swapped one at a time, so
+    /// only fixed mappings get huge pages.
+    fn huge_ppn(&self, vpn: VirtPageNum) -> Option<PhysPageNum> {
+        let ppn = match self.map_type {
+            MapType::Identical => vpn.0,
+            MapType::Linear(pn_offset) => (vpn.0 as isize + pn_offset) as usize,
+            MapType::Framed | MapType::Shared => return None,
+        };
+        let fits = vpn.0 % PAGES_PER_HUGE_PAGE == 0
+            && ppn % PAGES_PER_HUGE_PAGE == 0
+            && vpn.0 + PAGES_PER_HUGE_PAGE <= self.vpn_range.get_end().0;
+        fits.then(|| PhysPageNum(ppn))
+    }
+    /// Map every page, or none of them if memory runs out.
+    pub fn map(&mut self, page_table: &mut PageTable) -> Result<(), OutOfMemory> {
+        let mut vpn = self.vpn_range.get_start();
+        while vpn < self.vpn_range.get_end() {
+            let mapped = match self.huge_ppn(vpn) {
+                Some(ppn) => {
+                    let pte_flags = PTEFlags::from_bits(self.map_perm.bits).unwrap();
+                    page_table
+                        .map_huge(vpn, ppn, pte_flags)
+                        .map(|_| PAGES_PER_HUGE_PAGE)
+                }
+                None => self.map_one(page_table, vpn).map(|_| 1),
+            };
+            match mapped {
+                Ok(pages) => vpn = VirtPageNum(vpn.0 + pages),
+                Err(err) => {
+                    self.unmap_until(page_table, vpn);
+                    return Err(err);
+                }
+            }
         }
+        self.flush_tlb(page_table);
+        Ok(())
     }
     pub fn unmap(&mut self, page_table: &mut PageTable) {
-        for vpn in self.vpn_range {
-            self.unmap_one(page_table, vpn);
+        self.unmap_until(page_table, self.vpn_range.get_end());
+    }
+    /// Unmap the pages from the start of the area up to `end`.
+    fn unmap_until(&mut self, page_table: &mut PageTable, end: VirtPageNum) {
+        let mut vpn = self.vpn_range.get_start();
+        while vpn < end {
+            if self.huge_ppn(vpn).is_some() {
+                page_table.unmap_huge(vpn);
+                vpn = VirtPageNum(vpn.0 + PAGES_PER_HUGE_PAGE);
+            } else {
+                self.unmap_one(page_table, vpn);
+                vpn.step();
+            }
         }
+        self.flush_tlb(page_table);
+    }
+    /// Fence the whole area wherever `page_table` is in use.
+    fn flush_tlb(&self, page_table: &PageTable) {
+        let start = self.vpn_range.get_start();
+        tlb::flush(
+            page_table.token(),
+            start,
+            self.vpn_range.get_end().0 - start.0,
+        );
     }
     /// data: start-aligned but maybe with shorter length
     /// assume that all frames were cleared before
@@ -338,12 +1154,128 @@ impl MapArea {
     }
 }
 
+/// Where a page that is not present gets its contents from.
+pub enum PageSource {
+    File(FileBacking),
+    /// id of a `SwapSlot`
+    Swap(usize),
+    /// a heap page touched for the first time
+    Zero,
+}
+
+impl PageSource {
+    /// Fill the zeroed frame `ppn` with the contents of page `vpn`.
+    pub fn fill(&self, vpn: VirtPageNum, ppn: PhysPageNum) {
+        match self {
+            PageSource::File(backing) => backing.fill(vpn, ppn),
+            PageSource::Swap(slot) => swap_read(*slot, ppn),
+            PageSource::Zero => {}
+        }
+    }
+}
+
+/// Where the initial contents of a lazily mapped area come from.
+#[derive(Clone)]
+pub struct FileBacking {
+    inode: Arc<dyn VfsInode>,
+    /// virtual address of the first byte taken from the file
+    start_va: usize,
+    /// file offset of `start_va`
+    offset: usize,
+    /// bytes taken from the file, the rest of the area reads as zero
+    len: usize,
+}
+
+impl FileBacking {
+    /// Copy the file contents of page `vpn` into the zeroed frame `ppn`.
+    fn fill(&self, vpn: VirtPageNum, ppn: PhysPageNum) {
+        let page_start: usize = VirtAddr::from(vpn).into();
+        let start = page_start.max(self.start_va);
+        let end = (page_start + PAGE_SIZE).min(self.start_va + self.len);
+        if start < end {
+            let dst = &mut ppn.get_bytes_array()[start - page_start..end - page_start];
+            self.inode.read_at(self.offset + start - self.start_va, dst);
+        }
+    }
+}
+
+const DT_NULL: u64 = 0;
+const DT_RELA: u64 = 7;
+const DT_RELASZ: u64 = 8;
+/// bytes in an `Elf64_Rela`
+const RELA_SIZE: usize = 24;
+const R_RISCV_NONE: u32 = 0;
+const R_RISCV_RELATIVE: u32 = 3;
+
+/// How far a program is moved from the addresses it was linked at:
+/// position independent ones (ET_DYN) go above `PIE_BASE`, to a random
+/// place with ASLR, the others stay put.
+fn load_bias(elf: &ElfFile) -> usize {
+    if elf.header.pt2.type_().as_type() != header::Type::SharedObject {
+        return 0;
+    }
+    let align = (0..elf.header.pt2.ph_count())
+        .map(|i| elf.program_header(i).unwrap())
+        .filter(|ph| ph.get_type() == Ok(Type::Load))
+        .map(|ph| ph.align() as usize)
+        .fold(PAGE_SIZE, usize::max);
+    let bias = PIE_BASE + aslr_offset(ASLR_PIE_RANGE);
+    bias - bias % align
+}
+
+/// The file offset of the loaded virtual address `addr`, before biasing.
+fn elf_file_offset(elf: &ElfFile, addr: u64) -> Option<usize> {
+    (0..elf.header.pt2.ph_count())
+        .map(|i| elf.program_header(i).unwrap())
+        .filter(|ph| ph.get_type() == Ok(Type::Load))
+        .find(|ph| ph.virtual_addr() <= addr && addr < ph.virtual_addr() + ph.file_size())
+        .map(|ph| (ph.offset() + addr - ph.virtual_addr()) as usize)
+}
+
+fn read_u64(bytes: &[u8], at: usize) -> u64 {
+    u64::from_le_bytes(bytes[at..at + 8].try_into().unwrap())
+}
+
+/// A page aligned offset below `range`, or 0 when booted with
+/// `norandmaps`.
+fn aslr_offset(range: usize) -> usize {
+    if bootflag("norandmaps") {
+        0
+    } else {
+        random() as usize % (range / PAGE_SIZE) * PAGE_SIZE
+    }
+}
+
+fn read_file(inode: &Arc<dyn VfsInode>, offset: usize, len: usize) -> Vec<u8> {
+    let mut data = vec![0u8; len];
+    let read = inode.read_at(offset, &mut data);
+    data.truncate(read);
+    data
+}
+
+fn elf_map_perm(ph: &ProgramHeader) -> MapPermission {
+    let mut map_perm = MapPermission::U;
+    let ph_flags = ph.flags();
+    if ph_flags.is_read() {
+        map_perm |= MapPermission::R;
+    }
+    if ph_flags.is_write() {
+        map_perm |= MapPermission::W;
+    }
+    if ph_flags.is_execute() {
+        map_perm |= MapPermission::X;
+    }
+    map_perm
+}
+
 #[derive(Copy, Clone, PartialEq, Debug)]
 pub enum MapType {
     Identical,
     Framed,
     /// offset of page num
     Linear(isize),
+    /// the frames of a shared memory segment, owned by the segment
+    Shared,
 }
 
 bitflags! {
@@ -355,9 +1287,109 @@ bitflags! {
     }
 }
 
+/// The address `vpn` starts at, sign-extended as sv39 wants it.
+fn canonical_va(vpn: VirtPageNum) -> usize {
+    let va = VirtAddr::from(vpn).0;
+    ((va << 25) as isize >> 25) as usize
+}
+
+/// What part of the kernel space `va` is in.
+fn kernel_section(va: usize) -> &'static str {
+    let sections = [
+        (stext as usize, etext as usize, ".text"),
+        (srodata as usize, erodata as usize, ".rodata"),
+        (sdata as usize, edata as usize, ".data"),
+        (sbss_with_stack as usize, ebss as usize, ".bss"),
+        (ekernel as usize, memory_end(), "memory"),
+        (USER_SPACE_END, TRAMPOLINE, "kernel stacks"),
+    ];
+    if va == TRAMPOLINE {
+        return "trampoline";
+    }
+    sections
+        .iter()
+        .find(|(start, end, _)| (*start..*end).contains(&va))
+        .map_or("mmio", |(_, _, name)| name)
+}
+
+/// Walk the kernel page table once it is built and panic at the first
+/// page that is both writable and executable, or that is in .text and
+/// not execute-only, or in .rodata and not read-only.
+pub fn audit_kernel_mappings() {
+    let leaves = KERNEL_SPACE.shared_access().page_table.leaves();
+    let rwx = PTEFlags::R | PTEFlags::W | PTEFlags::X;
+    for (vpn, pte, pages) in leaves {
+        let start = canonical_va(vpn);
+        let end = start + pages * PAGE_SIZE;
+        assert!(
+            !(pte.writable() && pte.executable()),
+            "kernel page {:#x} is writable and executable",
+            start
+        );
+        let expected = [
+            (stext as usize, etext as usize, PTEFlags::X),
+            (srodata as usize, erodata as usize, PTEFlags::R),
+        ];
+        for (section_start, section_end, flags) in expected {
+            if start < section_end && section_start < end {
+                assert_eq!(
+                    pte.flags() & rwx,
+                    flags,
+                    "kernel page {:#x} in {} has the wrong permissions",
+                    start,
+                    kernel_section(start)
+                );
+            }
+        }
+    }
+}
+
+/// Print the kernel page table, pages mapped alike and contiguously on
+/// one line each, with the section they start in.
+pub fn debug_dump_kernel_mappings() {
+    let leaves = KERNEL_SPACE.shared_access().page_table.leaves();
+    let print_run = |vpn: VirtPageNum, pte: PageTableEntry, pages: usize| {
+        let start = canonical_va(vpn);
+        let perm = |flag, c| if pte.flags().contains(flag) { c } else { '-' };
+        println!(
+            "{:#018x}-{:#018x} -> {:#010x} {}{}{}{} {}",
+            start,
+            start + pages * PAGE_SIZE,
+            PhysAddr::from(pte.ppn()).0,
+            perm(PTEFlags::R, 'r'),
+            perm(PTEFlags::W, 'w'),
+            perm(PTEFlags::X, 'x'),
+            perm(PTEFlags::G, 'g'),
+            kernel_section(start)
+        );
+    };
+    println!("---START KERNEL MAPPINGS---");
+    let mut run: Option<(VirtPageNum, PageTableEntry, usize)> = None;
+    for (vpn, pte, pages) in leaves {
+        run = match run {
+            Some((run_vpn, run_pte, run_pages))
+                if run_vpn.0 + run_pages == vpn.0
+                    && run_pte.ppn().0 + run_pages == pte.ppn().0
+                    && run_pte.flags() == pte.flags() =>
+            {
+                Some((run_vpn, run_pte, run_pages + pages))
+            }
+            Some((run_vpn, run_pte, run_pages)) => {
+                print_run(run_vpn, run_pte, run_pages);
+                Some((vpn, pte, pages))
+            }
+            None => Some((vpn, pte, pages)),
+        };
+    }
+    if let Some((vpn, pte, pages)) = run {
+        print_run(vpn, pte, pages);
+    }
+    println!("---END KERNEL MAPPINGS---");
+}
+
 #[allow(unused)]
 pub fn remap_test() {
-    let mut kernel_space = KERNEL_SPACE.exclusive_access();
+    let kernel_space = KERNEL_SPACE.shared_access();
     let mid_text: VirtAddr = ((stext as usize + etext as usize) / 2).into();
     let mid_rodata: VirtAddr = ((srodata as usize + erodata as usize) / 2).into();
     let mid_data: VirtAddr = ((sdata as usize + edata as usize) / 2).into();
@@ -376,5 +1408,12 @@ pub fn remap_test() {
         .translate(mid_data.floor())
         .unwrap()
         .executable(),);
+    // physical memory is mapped with huge pages, which still translate
+    // page by page
+    let last_page: VirtAddr = (memory_end() - PAGE_SIZE).into();
+    assert_eq!(
+        kernel_space.page_table.translate_va(last_page),
+        Some(PhysAddr::from(memory_end() - PAGE_SIZE))
+    );
     println!("remap_test passed!");
 }
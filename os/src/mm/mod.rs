@@ -1,21 +1,35 @@
 mod address;
 mod frame_allocator;
 mod heap_allocator;
+mod meminfo;
 mod memory_set;
 mod page_table;
+mod shm;
+mod slab;
+mod swap;
+mod tlb;
+mod user_check;
 
 pub use address::VPNRange;
 pub use address::{PhysAddr, PhysPageNum, StepByOne, VirtAddr, VirtPageNum};
-pub use frame_allocator::{frame_alloc, frame_alloc_more, frame_dealloc, FrameTracker};
-pub use memory_set::{kernel_token, MapArea, MapPermission, MapType, MemorySet, KERNEL_SPACE};
-use page_table::PTEFlags;
-pub use page_table::{
-    translated_byte_buffer, translated_ref, translated_refmut, translated_str, PageTable,
-    PageTableEntry, UserBuffer,
+pub use frame_allocator::{frame_alloc, frame_alloc_contiguous, FrameTracker, OutOfMemory};
+pub use meminfo::meminfo;
+pub use memory_set::{
+    audit_kernel_mappings, debug_dump_kernel_mappings, kernel_token, kernel_translate_va, MapArea,
+    MapPermission, MapType, MemorySet, KERNEL_SPACE,
 };
+use page_table::{PTEFlags, PAGES_PER_HUGE_PAGE};
+pub use page_table::{PageTable, PageTableEntry};
+pub use shm::ShmSegment;
+pub use swap::reclaim;
+pub use tlb::{enter_kernel, enter_user, flush_local};
+pub use user_check::{
+    copy_bytes_to_user, copy_from_user, copy_to_user, read_user_str, UserBuffer, UserPtr,
+};
+
+pub use heap_allocator::init_heap;
 
 pub fn init() {
-    heap_allocator::init_heap();
     frame_allocator::init_frame_allocator();
-    KERNEL_SPACE.exclusive_access().activate();
+    KERNEL_SPACE.shared_access().activate();
 }
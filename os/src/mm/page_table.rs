@@ -1,8 +1,12 @@
-use super::{frame_alloc, FrameTracker, PhysAddr, PhysPageNum, StepByOne, VirtAddr, VirtPageNum};
-use alloc::string::String;
+use super::{frame_alloc, FrameTracker, OutOfMemory, PhysAddr, PhysPageNum, VirtAddr, VirtPageNum};
+use crate::config::{HUGE_PAGE_SIZE, PAGE_SIZE};
 use alloc::vec;
 use alloc::vec::Vec;
 use bitflags::*;
+use core::arch::asm;
+
+/// Pages covered by one level-1 leaf.
+pub const PAGES_PER_HUGE_PAGE: usize = HUGE_PAGE_SIZE / PAGE_SIZE;
 
 bitflags! {
     pub struct PTEFlags: u8 {
@@ -50,6 +54,10 @@ impl PageTableEntry {
     pub fn executable(&self) -> bool {
         (self.flags() & PTEFlags::X) != PTEFlags::empty()
     }
+    /// Whether this maps a page rather than pointing to the next table.
+    pub fn is_leaf(&self) -> bool {
+        self.is_valid() && (self.readable() || self.writable() || self.executable())
+    }
 }
 
 pub struct PageTable {
@@ -57,14 +65,14 @@ pub struct PageTable {
     frames: Vec<FrameTracker>,
 }
 
-/// Assume that it won't oom when creating/mapping.
+/// Creating and mapping fail if no frame is left for a table.
 impl PageTable {
-    pub fn new() -> Self {
-        let frame = frame_alloc().unwrap();
-        PageTable {
+    pub fn new() -> Result<Self, OutOfMemory> {
+        let frame = frame_alloc().ok_or(OutOfMemory)?;
+        Ok(PageTable {
             root_ppn: frame.ppn,
             frames: vec![frame],
-        }
+        })
     }
     /// Temporarily used to get arguments from user space.
     pub fn from_token(satp: usize) -> Self {
@@ -73,47 +81,61 @@ impl PageTable {
             frames: Vec::new(),
         }
     }
-    fn find_pte_create(&mut self, vpn: VirtPageNum) -> Option<&mut PageTableEntry> {
+    /// Walk down to the entry of `vpn` at `level`, 2 for a 4KiB page and
+    /// 1 for a huge one, creating the tables on the way.
+    fn find_pte_create(
+        &mut self,
+        vpn: VirtPageNum,
+        level: usize,
+    ) -> Result<&mut PageTableEntry, OutOfMemory> {
         let idxs = vpn.indexes();
         let mut ppn = self.root_ppn;
-        let mut result: Option<&mut PageTableEntry> = None;
         for (i, idx) in idxs.iter().enumerate() {
             let pte = &mut ppn.get_pte_array()[*idx];
-            if i == 2 {
-                result = Some(pte);
-                break;
+            if i == level {
+                return Ok(pte);
             }
+            assert!(!pte.is_leaf(), "vpn {:?} is inside a huge page", vpn);
             if !pte.is_valid() {
-                let frame = frame_alloc().unwrap();
+                let frame = frame_alloc().ok_or(OutOfMemory)?;
                 *pte = PageTableEntry::new(frame.ppn, PTEFlags::V);
                 self.frames.push(frame);
             }
             ppn = pte.ppn();
         }
-        result
+        unreachable!("level {} is below the leaves", level)
     }
-    fn find_pte(&self, vpn: VirtPageNum) -> Option<&mut PageTableEntry> {
+    /// Walk down to the leaf entry of `vpn` and the level it was found at,
+    /// stopping early at a huge page.
+    fn find_leaf(&self, vpn: VirtPageNum) -> Option<(&mut PageTableEntry, usize)> {
         let idxs = vpn.indexes();
         let mut ppn = self.root_ppn;
-        let mut result: Option<&mut PageTableEntry> = None;
         for (i, idx) in idxs.iter().enumerate() {
             let pte = &mut ppn.get_pte_array()[*idx];
-            if i == 2 {
-                result = Some(pte);
-                break;
+            if i == 2 || pte.is_leaf() {
+                return Some((pte, i));
             }
             if !pte.is_valid() {
                 return None;
             }
             ppn = pte.ppn();
         }
-        result
+        None
+    }
+    fn find_pte(&self, vpn: VirtPageNum) -> Option<&mut PageTableEntry> {
+        self.find_leaf(vpn).map(|(pte, _)| pte)
     }
     #[allow(unused)]
-    pub fn map(&mut self, vpn: VirtPageNum, ppn: PhysPageNum, flags: PTEFlags) {
-        let pte = self.find_pte_create(vpn).unwrap();
+    pub fn map(
+        &mut self,
+        vpn: VirtPageNum,
+        ppn: PhysPageNum,
+        flags: PTEFlags,
+    ) -> Result<(), OutOfMemory> {
+        let pte = self.find_pte_create(vpn, 2)?;
         assert!(!pte.is_valid(), "vpn {:?} is mapped before mapping", vpn);
         *pte = PageTableEntry::new(ppn, flags | PTEFlags::V);
+        Ok(())
     }
     #[allow(unused)]
     pub fn unmap(&mut self, vpn: VirtPageNum) {
@@ -121,11 +143,69 @@ impl PageTable {
         assert!(pte.is_valid(), "vpn {:?} is invalid before unmapping", vpn);
         *pte = PageTableEntry::empty();
     }
+    /// Map the huge page at `vpn` to as many frames from `ppn`, both
+    /// aligned to a huge page.
+    pub fn map_huge(
+        &mut self,
+        vpn: VirtPageNum,
+        ppn: PhysPageNum,
+        flags: PTEFlags,
+    ) -> Result<(), OutOfMemory> {
+        assert!(vpn.0 % PAGES_PER_HUGE_PAGE == 0 && ppn.0 % PAGES_PER_HUGE_PAGE == 0);
+        let pte = self.find_pte_create(vpn, 1)?;
+        assert!(!pte.is_valid(), "vpn {:?} is mapped before mapping", vpn);
+        *pte = PageTableEntry::new(ppn, flags | PTEFlags::V);
+        Ok(())
+    }
+    pub fn unmap_huge(&mut self, vpn: VirtPageNum) {
+        match self.find_leaf(vpn) {
+            Some((pte, 1)) => *pte = PageTableEntry::empty(),
+            _ => panic!("vpn {:?} is not a huge page before unmapping", vpn),
+        }
+    }
+    /// Clear the accessed bit of `vpn`, returning whether it was set.
+    pub fn test_and_clear_accessed(&mut self, vpn: VirtPageNum) -> bool {
+        match self.find_pte(vpn) {
+            Some(pte) if pte.is_valid() => {
+                let accessed = pte.flags().contains(PTEFlags::A);
+                pte.bits &= !(PTEFlags::A.bits as usize);
+                accessed
+            }
+            _ => false,
+        }
+    }
+    /// The leaf entry of `vpn`, pointing at its own frame even if it lies
+    /// in a huge page.
     pub fn translate(&self, vpn: VirtPageNum) -> Option<PageTableEntry> {
-        self.find_pte(vpn).map(|pte| *pte)
+        self.find_leaf(vpn).map(|(pte, level)| {
+            let pages = 1usize << (9 * (2 - level));
+            let ppn = PhysPageNum(pte.ppn().0 + (vpn.0 & (pages - 1)));
+            PageTableEntry::new(ppn, pte.flags())
+        })
+    }
+    /// Write `byte` at `va` of this address space, which must be the
+    /// active one, making its page writable for the moment if it is not,
+    /// as a debugger planting breakpoints in code does.
+    pub fn force_write(&self, va: VirtAddr, byte: u8) -> bool {
+        let pte = match self.find_pte(va.floor()) {
+            Some(pte) if pte.is_valid() => pte,
+            _ => return false,
+        };
+        let bits = pte.bits;
+        // code may be mapped execute-only, and W needs R
+        pte.bits |= (PTEFlags::R | PTEFlags::W | PTEFlags::A | PTEFlags::D).bits as usize;
+        unsafe {
+            asm!("sfence.vma {}", in(reg) va.0);
+            (va.0 as *mut u8).write_volatile(byte);
+        }
+        pte.bits = bits;
+        unsafe {
+            asm!("sfence.vma {}", "fence.i", in(reg) va.0);
+        }
+        true
     }
     pub fn translate_va(&self, va: VirtAddr) -> Option<PhysAddr> {
-        self.find_pte(va.clone().floor()).map(|pte| {
+        self.translate(va.clone().floor()).map(|pte| {
             let aligned_pa: PhysAddr = pte.ppn().into();
             let offset = va.page_offset();
             let aligned_pa_usize: usize = aligned_pa.into();
@@ -135,115 +215,30 @@ impl PageTable {
     pub fn token(&self) -> usize {
         8usize << 60 | self.root_ppn.0
     }
-}
-
-pub fn translated_byte_buffer(token: usize, ptr: *const u8, len: usize) -> Vec<&'static mut [u8]> {
-    let page_table = PageTable::from_token(token);
-    let mut start = ptr as usize;
-    let end = start + len;
-    let mut v = Vec::new();
-    while start < end {
-        let start_va = VirtAddr::from(start);
-        let mut vpn = start_va.floor();
-        let ppn = page_table.translate(vpn).unwrap().ppn();
-        vpn.step();
-        let mut end_va: VirtAddr = vpn.into();
-        end_va = end_va.min(VirtAddr::from(end));
-        if end_va.page_offset() == 0 {
-            v.push(&mut ppn.get_bytes_array()[start_va.page_offset()..]);
-        } else {
-            v.push(&mut ppn.get_bytes_array()[start_va.page_offset()..end_va.page_offset()]);
-        }
-        start = end_va.into();
-    }
-    v
-}
-
-/// Load a string from other address spaces into kernel space without an end `\0`.
-pub fn translated_str(token: usize, ptr: *const u8) -> String {
-    let page_table = PageTable::from_token(token);
-    let mut string = String::new();
-    let mut va = ptr as usize;
-    loop {
-        let ch: u8 = *(page_table
-            .translate_va(VirtAddr::from(va))
-            .unwrap()
-            .get_mut());
-        if ch == 0 {
-            break;
-        }
-        string.push(ch as char);
-        va += 1;
-    }
-    string
-}
-
-pub fn translated_ref<T>(token: usize, ptr: *const T) -> &'static T {
-    let page_table = PageTable::from_token(token);
-    page_table
-        .translate_va(VirtAddr::from(ptr as usize))
-        .unwrap()
-        .get_ref()
-}
-
-pub fn translated_refmut<T>(token: usize, ptr: *mut T) -> &'static mut T {
-    let page_table = PageTable::from_token(token);
-    let va = ptr as usize;
-    page_table
-        .translate_va(VirtAddr::from(va))
-        .unwrap()
-        .get_mut()
-}
-
-pub struct UserBuffer {
-    pub buffers: Vec<&'static mut [u8]>,
-}
-
-impl UserBuffer {
-    pub fn new(buffers: Vec<&'static mut [u8]>) -> Self {
-        Self { buffers }
-    }
-    pub fn len(&self) -> usize {
-        let mut total: usize = 0;
-        for b in self.buffers.iter() {
-            total += b.len();
-        }
-        total
-    }
-}
-
-impl IntoIterator for UserBuffer {
-    type Item = *mut u8;
-    type IntoIter = UserBufferIterator;
-    fn into_iter(self) -> Self::IntoIter {
-        UserBufferIterator {
-            buffers: self.buffers,
-            current_buffer: 0,
-            current_idx: 0,
-        }
-    }
-}
-
-pub struct UserBufferIterator {
-    buffers: Vec<&'static mut [u8]>,
-    current_buffer: usize,
-    current_idx: usize,
-}
-
-impl Iterator for UserBufferIterator {
-    type Item = *mut u8;
-    fn next(&mut self) -> Option<Self::Item> {
-        if self.current_buffer >= self.buffers.len() {
-            None
-        } else {
-            let r = &mut self.buffers[self.current_buffer][self.current_idx] as *mut _;
-            if self.current_idx + 1 == self.buffers[self.current_buffer].len() {
-                self.current_idx = 0;
-                self.current_buffer += 1;
-            } else {
-                self.current_idx += 1;
+    /// Every leaf by address: the page it starts at, its entry and how
+    /// many pages it maps.
+    pub fn leaves(&self) -> Vec<(VirtPageNum, PageTableEntry, usize)> {
+        let mut leaves = Vec::new();
+        Self::collect_leaves(self.root_ppn, 0, 0, &mut leaves);
+        leaves
+    }
+    fn collect_leaves(
+        ppn: PhysPageNum,
+        level: usize,
+        prefix: usize,
+        leaves: &mut Vec<(VirtPageNum, PageTableEntry, usize)>,
+    ) {
+        let shift = 9 * (2 - level);
+        for (idx, pte) in ppn.get_pte_array().iter().enumerate() {
+            let vpn = prefix | idx << shift;
+            if pte.is_leaf() {
+                leaves.push((VirtPageNum(vpn), *pte, 1 << shift));
+            } else if pte.is_valid() && level < 2 {
+                Self::collect_leaves(pte.ppn(), level + 1, vpn, leaves);
             }
-            Some(r)
         }
     }
+    pub fn frame_count(&self) -> usize {
+        self.frames.len()
+    }
 }
@@ -0,0 +1,36 @@
+//! System V shared memory: a segment is a set of frames that every
+//! process attaching it maps, so what one writes the others see with no
+//! copy. A segment lives as long as a handle to it or a mapping of it
+//! does.
+
+use super::{frame_alloc, FrameTracker, OutOfMemory, PhysPageNum};
+use crate::config::PAGE_SIZE;
+use crate::task::{KernelObject, ObjectKind};
+use alloc::vec::Vec;
+
+pub struct ShmSegment {
+    frames: Vec<FrameTracker>,
+}
+
+impl ShmSegment {
+    /// A zeroed segment of `len` bytes, rounded up to whole pages.
+    pub fn new(len: usize) -> Result<Self, OutOfMemory> {
+        let frames = (0..len.div_ceil(PAGE_SIZE))
+            .map(|_| frame_alloc().ok_or(OutOfMemory))
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(Self { frames })
+    }
+    pub fn pages(&self) -> usize {
+        self.frames.len()
+    }
+    /// The frame of page `page` of the segment.
+    pub fn ppn(&self, page: usize) -> PhysPageNum {
+        self.frames[page].ppn
+    }
+}
+
+impl KernelObject for ShmSegment {
+    fn kind(&self) -> ObjectKind {
+        ObjectKind::Shm
+    }
+}
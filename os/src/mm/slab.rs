@@ -0,0 +1,140 @@
+//! Caches of fixed-size kernel objects. The global allocator hands
+//! allocations with the layout of a cache to it, which carves them out of
+//! slabs taken from the heap and keeps freed objects on a list for reuse,
+//! so objects allocated over and over on fork and pipe do not fragment
+//! the heap. Other allocations of the same layout share the cache.
+
+use crate::fs::PipeRingBuffer;
+use crate::sync::SpinMutexIrqSave;
+use crate::task::{ProcessControlBlock, TaskControlBlock};
+use buddy_system_allocator::LockedHeap;
+use core::alloc::{GlobalAlloc, Layout};
+use core::sync::atomic::AtomicUsize;
+use easy_fs::BLOCK_SZ;
+use lazy_static::*;
+
+/// Bytes of heap taken at a time when a cache runs dry.
+const SLAB_SIZE: usize = 4096;
+
+const CACHE_COUNT: usize = 4;
+
+pub struct SlabCache {
+    layout: Layout,
+    /// distance between objects, room for the free list link included
+    stride: usize,
+    /// first free object, each free object holds the address of the next
+    free: usize,
+    stats: SlabStats,
+}
+
+/// Usage of one cache, counted in objects except for `slabs`.
+#[derive(Clone, Copy)]
+pub struct SlabStats {
+    pub name: &'static str,
+    pub object_size: usize,
+    pub in_use: usize,
+    pub peak: usize,
+    pub allocs: usize,
+    pub slabs: usize,
+}
+
+impl SlabCache {
+    fn new(name: &'static str, layout: Layout) -> Self {
+        let word = core::mem::size_of::<usize>();
+        let stride = layout
+            .size()
+            .max(word)
+            .next_multiple_of(layout.align().max(word));
+        Self {
+            layout,
+            stride,
+            free: 0,
+            stats: SlabStats {
+                name,
+                object_size: layout.size(),
+                in_use: 0,
+                peak: 0,
+                allocs: 0,
+                slabs: 0,
+            },
+        }
+    }
+    /// Take a free object, carving a new slab out of `heap` if there is
+    /// none. Slabs are never given back.
+    unsafe fn alloc(&mut self, heap: &LockedHeap) -> *mut u8 {
+        if self.free == 0 {
+            let slab_size = SLAB_SIZE.max(self.stride);
+            let align = self.layout.align().max(core::mem::size_of::<usize>());
+            let slab = heap.alloc(Layout::from_size_align(slab_size, align).unwrap());
+            if slab.is_null() {
+                return slab;
+            }
+            for i in (0..slab_size / self.stride).rev() {
+                let object = slab as usize + i * self.stride;
+                *(object as *mut usize) = self.free;
+                self.free = object;
+            }
+            self.stats.slabs += 1;
+        }
+        let object = self.free;
+        self.free = *(object as *const usize);
+        self.stats.in_use += 1;
+        self.stats.peak = self.stats.peak.max(self.stats.in_use);
+        self.stats.allocs += 1;
+        object as *mut u8
+    }
+    unsafe fn dealloc(&mut self, ptr: *mut u8) {
+        *(ptr as *mut usize) = self.free;
+        self.free = ptr as usize;
+        self.stats.in_use -= 1;
+    }
+}
+
+/// The layout `Arc::new` allocates for a `T`: the two counts, then `T`.
+fn arc_layout<T>() -> Layout {
+    Layout::new::<[AtomicUsize; 2]>()
+        .extend(Layout::new::<T>())
+        .unwrap()
+        .0
+        .pad_to_align()
+}
+
+lazy_static! {
+    /// The caches with the layouts they serve, so finding one needs no lock.
+    static ref CACHES: [(Layout, SpinMutexIrqSave<SlabCache>); CACHE_COUNT] = [
+        ("task", arc_layout::<TaskControlBlock>()),
+        ("process", arc_layout::<ProcessControlBlock>()),
+        ("pipe", arc_layout::<SpinMutexIrqSave<PipeRingBuffer>>()),
+        // the data of a block cache entry
+        ("block", Layout::from_size_align(BLOCK_SZ, 1).unwrap()),
+    ]
+    .map(|(name, layout)| (layout, SpinMutexIrqSave::new(SlabCache::new(name, layout))));
+}
+
+fn cache_for(layout: Layout) -> Option<&'static SpinMutexIrqSave<SlabCache>> {
+    CACHES
+        .iter()
+        .find(|(cache_layout, _)| *cache_layout == layout)
+        .map(|(_, cache)| cache)
+}
+
+/// Allocate `layout` from its cache, or from `heap` if it has none.
+pub unsafe fn slab_alloc(heap: &LockedHeap, layout: Layout) -> *mut u8 {
+    match cache_for(layout) {
+        Some(cache) => cache.exclusive_access().alloc(heap),
+        None => heap.alloc(layout),
+    }
+}
+
+/// Free what `slab_alloc` returned for `layout`.
+pub unsafe fn slab_dealloc(heap: &LockedHeap, ptr: *mut u8, layout: Layout) {
+    match cache_for(layout) {
+        Some(cache) => cache.exclusive_access().dealloc(ptr),
+        None => heap.dealloc(ptr, layout),
+    }
+}
+
+/// Usage of each cache.
+pub fn slab_stats() -> [SlabStats; CACHE_COUNT] {
+    core::array::from_fn(|i| CACHES[i].1.exclusive_access().stats)
+}
@@ -0,0 +1,187 @@
+//! Swapping user pages out to a region of the block device reserved
+//! after the file system, and faulting them back in.
+
+use super::frame_allocator::frame_stats;
+use super::{FrameTracker, PhysPageNum};
+use crate::config::{PAGE_SIZE, SWAP_PAGES, SWAP_START_BLOCK};
+use crate::drivers::BLOCK_DEVICE;
+use crate::sync::SpinMutexIrqSave;
+use crate::task::processes;
+use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
+use easy_fs::BLOCK_SZ;
+use lazy_static::*;
+
+const BLOCKS_PER_PAGE: usize = PAGE_SIZE / BLOCK_SZ;
+/// Frames kept free for page tables, kernel stacks and the like.
+const LOW_WATERMARK: usize = 64;
+
+struct SwapSpace {
+    current: usize,
+    recycled: Vec<usize>,
+    /// evicted frames whose contents are still being written out
+    writeback: BTreeMap<usize, FrameTracker>,
+    /// frames the kernel holds references into, with their pin counts
+    pinned: BTreeMap<PhysPageNum, usize>,
+}
+
+lazy_static! {
+    static ref SWAP_SPACE: SpinMutexIrqSave<SwapSpace> = SpinMutexIrqSave::new(SwapSpace {
+        current: 0,
+        recycled: Vec::new(),
+        writeback: BTreeMap::new(),
+        pinned: BTreeMap::new(),
+    });
+}
+
+/// A page sized slot in the swap area, freed on drop.
+pub struct SwapSlot(usize);
+
+impl SwapSlot {
+    pub fn id(&self) -> usize {
+        self.0
+    }
+}
+
+impl Drop for SwapSlot {
+    fn drop(&mut self) {
+        SWAP_SPACE.exclusive_access().recycled.push(self.0);
+    }
+}
+
+pub fn swap_slot_alloc() -> Option<SwapSlot> {
+    let mut swap = SWAP_SPACE.exclusive_access();
+    let swap = &mut *swap;
+    // a recycled slot may still be the target of a write in progress
+    if let Some(idx) = swap
+        .recycled
+        .iter()
+        .position(|id| !swap.writeback.contains_key(id))
+    {
+        Some(SwapSlot(swap.recycled.swap_remove(idx)))
+    } else if swap.current < SWAP_PAGES {
+        swap.current += 1;
+        Some(SwapSlot(swap.current - 1))
+    } else {
+        None
+    }
+}
+
+/// Return (used, total) number of swap slots.
+pub fn swap_stats() -> (usize, usize) {
+    let swap = SWAP_SPACE.exclusive_access();
+    (swap.current - swap.recycled.len(), SWAP_PAGES)
+}
+
+fn slot_block(slot: usize) -> usize {
+    SWAP_START_BLOCK + slot * BLOCKS_PER_PAGE
+}
+
+/// Fill `ppn` with the page stored in `slot`.
+pub fn swap_read(slot: usize, ppn: PhysPageNum) {
+    let pending = SWAP_SPACE
+        .exclusive_access()
+        .writeback
+        .get(&slot)
+        .map(|frame| frame.ppn);
+    if let Some(src) = pending {
+        ppn.get_bytes_array().copy_from_slice(src.get_bytes_array());
+        return;
+    }
+    for (i, block) in ppn.get_bytes_array().chunks_mut(BLOCK_SZ).enumerate() {
+        BLOCK_DEVICE.read_block(slot_block(slot) + i, block);
+    }
+}
+
+/// Write the evicted `frame` to `slot` and release it.
+fn swap_write(slot: usize, frame: FrameTracker) {
+    let ppn = frame.ppn;
+    // keep the frame around so that faults during the write can use it
+    SWAP_SPACE.exclusive_access().writeback.insert(slot, frame);
+    for (i, block) in ppn.get_bytes_array().chunks(BLOCK_SZ).enumerate() {
+        BLOCK_DEVICE.write_block(slot_block(slot) + i, block);
+    }
+    SWAP_SPACE.exclusive_access().writeback.remove(&slot);
+}
+
+/// Frames that must stay resident while the kernel accesses them
+/// directly, e.g. the pages behind a `UserBuffer`.
+pub struct PinnedFrames(Vec<PhysPageNum>);
+
+impl PinnedFrames {
+    pub fn new(ppns: Vec<PhysPageNum>) -> Self {
+        let mut swap = SWAP_SPACE.exclusive_access();
+        for ppn in ppns.iter() {
+            *swap.pinned.entry(*ppn).or_insert(0) += 1;
+        }
+        Self(ppns)
+    }
+}
+
+impl Drop for PinnedFrames {
+    fn drop(&mut self) {
+        let mut swap = SWAP_SPACE.exclusive_access();
+        for ppn in self.0.iter() {
+            let count = swap.pinned.get_mut(ppn).unwrap();
+            *count -= 1;
+            if *count == 0 {
+                swap.pinned.remove(ppn);
+            }
+        }
+    }
+}
+
+pub fn is_pinned(ppn: PhysPageNum) -> bool {
+    SWAP_SPACE.exclusive_access().pinned.contains_key(&ppn)
+}
+
+fn under_pressure(pages: usize) -> bool {
+    let (used, _, total) = frame_stats();
+    total - used < pages + LOW_WATERMARK
+}
+
+/// Evict user pages until `pages` frames can be allocated without
+/// dipping below the low watermark. Each process gives up one page per
+/// round, chosen by its own clock over the PTE accessed bits.
+///
+/// Must be called without holding any process' inner.
+pub fn reclaim(pages: usize) {
+    evict_until(pages, true);
+}
+
+/// `reclaim` for the frame allocator once it has run out. The hart may be
+/// holding the inner of a process or any other lock there, so processes
+/// whose inner is taken are passed over.
+pub(super) fn reclaim_on_oom(pages: usize) {
+    evict_until(pages, false);
+}
+
+fn evict_until(pages: usize, wait: bool) {
+    while under_pressure(pages) {
+        let mut evicted = false;
+        for process in processes() {
+            if !under_pressure(pages) {
+                return;
+            }
+            let inner = match wait {
+                true => Some(process.inner_exclusive_access()),
+                false => process.try_inner_exclusive_access(),
+            };
+            let mut inner = match inner {
+                Some(inner) => inner,
+                None => continue,
+            };
+            let victim = inner.memory_set.evict_one(swap_slot_alloc());
+            drop(inner);
+            if let Some((frame, slot)) = victim {
+                evicted = true;
+                if let Some(slot) = slot {
+                    swap_write(slot, frame);
+                }
+            }
+        }
+        if !evicted {
+            return;
+        }
+    }
+}
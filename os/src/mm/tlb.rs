@@ -0,0 +1,67 @@
+//! Keeping the TLBs in step with the page tables. Switching between the
+//! kernel and a user space flushes the whole TLB of a hart, so a changed
+//! mapping only needs fencing on the harts that use its page table right
+//! now: any hart for the kernel space, and for a user space the harts
+//! that returned to it and have not trapped since.
+
+use super::{VirtAddr, VirtPageNum};
+use crate::config::{MAX_HARTS, PAGE_SIZE};
+use crate::ipi::tlb_shootdown;
+use crate::task::hart_id;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use lazy_static::*;
+use riscv::asm::{sfence_vma, sfence_vma_all};
+use riscv::register::satp;
+
+/// Longer ranges are fenced whole on this hart instead of page by page.
+const LOCAL_FLUSH_PAGES: usize = 32;
+
+lazy_static! {
+    /// The token of the user space each hart runs in, 0 in the kernel.
+    static ref USER_TOKENS: [AtomicUsize; MAX_HARTS] =
+        core::array::from_fn(|_| AtomicUsize::new(0));
+}
+
+/// This hart is about to return to the user space `token`.
+pub fn enter_user(token: usize) {
+    USER_TOKENS[hart_id()].store(token, Ordering::SeqCst);
+}
+
+/// This hart trapped into the kernel.
+pub fn enter_kernel() {
+    USER_TOKENS[hart_id()].store(0, Ordering::SeqCst);
+}
+
+/// Fence the `pages` pages from `start` of the page table `token` on
+/// every hart that may have cached them.
+pub fn flush(token: usize, start: VirtPageNum, pages: usize) {
+    if pages == 0 {
+        return;
+    }
+    // the page table writes before the look at who uses it
+    core::sync::atomic::fence(Ordering::SeqCst);
+    let va: usize = VirtAddr::from(start).into();
+    let kernel = token == satp::read().bits();
+    if kernel {
+        flush_local(va, pages);
+    }
+    let this = hart_id();
+    let mask = (0..MAX_HARTS)
+        .filter(|hart| *hart != this)
+        .filter(|hart| kernel || USER_TOKENS[*hart].load(Ordering::SeqCst) == token)
+        .fold(0, |mask, hart| mask | 1 << hart);
+    tlb_shootdown(mask, va, pages * PAGE_SIZE);
+}
+
+/// Fence the `pages` pages from `va` on this hart only.
+pub fn flush_local(va: usize, pages: usize) {
+    unsafe {
+        if pages > LOCAL_FLUSH_PAGES {
+            sfence_vma_all();
+        } else {
+            for page in 0..pages {
+                sfence_vma(0, va + page * PAGE_SIZE);
+            }
+        }
+    }
+}
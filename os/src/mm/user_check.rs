@@ -0,0 +1,213 @@
+//! Checked access to user memory. Every page touched must be mapped in
+//! user space with the needed permissions, after faulting it in if it was
+//! swapped out or not loaded yet, and a bad pointer gives `Err(EFAULT)`
+//! instead of a kernel panic.
+
+use super::swap::PinnedFrames;
+use super::{PTEFlags, PageTable, PhysAddr, VirtAddr};
+use crate::config::{PAGE_SIZE, USER_SPACE_END};
+use crate::task::current_process;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::marker::PhantomData;
+use core::mem::{size_of, MaybeUninit};
+use core::slice;
+
+/// Bad address. Syscalls fail with -1 whatever went wrong, so this is -1
+/// too.
+pub const EFAULT: isize = -1;
+
+/// Fault the page of `va` in if it belongs to the current process but
+/// was swapped out or not loaded yet.
+fn fault_in(page_table: &PageTable, va: VirtAddr) {
+    let present = |page_table: &PageTable| {
+        page_table
+            .translate(va.floor())
+            .map_or(false, |pte| pte.is_valid())
+    };
+    if !present(page_table) {
+        let process = current_process();
+        if process.inner_exclusive_access().memory_set.token() == page_table.token() {
+            while !present(page_table) && matches!(process.handle_page_fault(va.into()), Ok(true)) {
+            }
+        }
+    }
+}
+
+/// The bytes of `len` bytes of user memory at `ptr`, one slice per page.
+fn user_bytes(
+    token: usize,
+    ptr: usize,
+    len: usize,
+    writable: bool,
+) -> Result<Vec<&'static mut [u8]>, isize> {
+    let page_table = PageTable::from_token(token);
+    let end = match ptr.checked_add(len) {
+        Some(end) if end <= USER_SPACE_END => end,
+        _ => return Err(EFAULT),
+    };
+    let mut start = ptr;
+    let mut v = Vec::new();
+    while start < end {
+        let start_va = VirtAddr::from(start);
+        fault_in(&page_table, start_va);
+        let pte = match page_table.translate(start_va.floor()) {
+            Some(pte) if pte.is_valid() && pte.flags().contains(PTEFlags::U) => pte,
+            _ => return Err(EFAULT),
+        };
+        if writable && !pte.writable() {
+            return Err(EFAULT);
+        }
+        let offset = start_va.page_offset();
+        let page_end = (start - offset + PAGE_SIZE).min(end);
+        v.push(&mut pte.ppn().get_bytes_array()[offset..offset + page_end - start]);
+        start = page_end;
+    }
+    Ok(v)
+}
+
+/// A `T` in the user address space of `token`.
+pub struct UserPtr<T> {
+    token: usize,
+    ptr: usize,
+    _marker: PhantomData<*mut T>,
+}
+
+impl<T: Copy> UserPtr<T> {
+    pub fn new(token: usize, ptr: *const T) -> Self {
+        Self {
+            token,
+            ptr: ptr as usize,
+            _marker: PhantomData,
+        }
+    }
+    pub fn is_null(&self) -> bool {
+        self.ptr == 0
+    }
+    /// The `count`th `T` after this one.
+    pub fn add(&self, count: usize) -> Self {
+        Self::new(self.token, (self.ptr + count * size_of::<T>()) as *const T)
+    }
+    pub fn read(&self) -> Result<T, isize> {
+        let mut value = MaybeUninit::<T>::uninit();
+        let dst =
+            unsafe { slice::from_raw_parts_mut(value.as_mut_ptr() as *mut u8, size_of::<T>()) };
+        let mut copied = 0;
+        for chunk in user_bytes(self.token, self.ptr, size_of::<T>(), false)? {
+            dst[copied..copied + chunk.len()].copy_from_slice(chunk);
+            copied += chunk.len();
+        }
+        Ok(unsafe { value.assume_init() })
+    }
+    pub fn write(&self, value: T) -> Result<(), isize> {
+        let src = unsafe { slice::from_raw_parts(&value as *const T as *const u8, size_of::<T>()) };
+        copy_bytes_to_user(self.token, self.ptr as *mut u8, src)
+    }
+}
+
+/// Read a `T` from user memory.
+pub fn copy_from_user<T: Copy>(token: usize, src: *const T) -> Result<T, isize> {
+    UserPtr::new(token, src).read()
+}
+
+/// Write `value` to user memory.
+pub fn copy_to_user<T: Copy>(token: usize, dst: *mut T, value: T) -> Result<(), isize> {
+    UserPtr::new(token, dst).write(value)
+}
+
+/// Write `bytes` to user memory at `dst`.
+pub fn copy_bytes_to_user(token: usize, dst: *mut u8, bytes: &[u8]) -> Result<(), isize> {
+    let mut copied = 0;
+    for chunk in user_bytes(token, dst as usize, bytes.len(), true)? {
+        let len = chunk.len();
+        chunk.copy_from_slice(&bytes[copied..copied + len]);
+        copied += len;
+    }
+    Ok(())
+}
+
+/// Read the NUL terminated string at `ptr`, without the NUL.
+pub fn read_user_str(token: usize, ptr: *const u8) -> Result<String, isize> {
+    let mut string = String::new();
+    let mut va = ptr as usize;
+    loop {
+        // a page at a time, the string may end before the next one
+        let page_end = (va / PAGE_SIZE + 1) * PAGE_SIZE;
+        for chunk in user_bytes(token, va, page_end - va, false)? {
+            for &ch in chunk.iter() {
+                if ch == 0 {
+                    return Ok(string);
+                }
+                string.push(ch as char);
+            }
+        }
+        va = page_end;
+    }
+}
+
+pub struct UserBuffer {
+    pub buffers: Vec<&'static mut [u8]>,
+    /// keeps the pages from being swapped out while we are using them
+    pins: PinnedFrames,
+}
+
+impl UserBuffer {
+    /// Take `len` bytes of user memory at `ptr`, which must be writable
+    /// too with `writable`, for a file to read into or write from.
+    pub fn new(token: usize, ptr: *const u8, len: usize, writable: bool) -> Result<Self, isize> {
+        let buffers = user_bytes(token, ptr as usize, len, writable)?;
+        let ppns = buffers
+            .iter()
+            .map(|buffer| PhysAddr::from(buffer.as_ptr() as usize).floor())
+            .collect();
+        Ok(Self {
+            buffers,
+            pins: PinnedFrames::new(ppns),
+        })
+    }
+    pub fn len(&self) -> usize {
+        let mut total: usize = 0;
+        for b in self.buffers.iter() {
+            total += b.len();
+        }
+        total
+    }
+}
+
+impl IntoIterator for UserBuffer {
+    type Item = *mut u8;
+    type IntoIter = UserBufferIterator;
+    fn into_iter(self) -> Self::IntoIter {
+        UserBufferIterator {
+            buffers: self.buffers,
+            _pins: self.pins,
+            current_buffer: 0,
+            current_idx: 0,
+        }
+    }
+}
+
+pub struct UserBufferIterator {
+    buffers: Vec<&'static mut [u8]>,
+    _pins: PinnedFrames,
+    current_buffer: usize,
+    current_idx: usize,
+}
+
+impl Iterator for UserBufferIterator {
+    type Item = *mut u8;
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.current_buffer >= self.buffers.len() {
+            None
+        } else {
+            let r = &mut self.buffers[self.current_buffer][self.current_idx] as *mut _;
+            if self.current_idx + 1 == self.buffers[self.current_buffer].len() {
+                self.current_idx = 0;
+                self.current_buffer += 1;
+            } else {
+                self.current_idx += 1;
+            }
+            Some(r)
+        }
+    }
+}
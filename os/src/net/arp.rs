@@ -0,0 +1,111 @@
+//! ARP, to find the MAC address of the next hop of an IPv4 packet sent on
+//! `eth0`. Packets for a hop not known yet wait here until its reply
+//! comes.
+
+use super::ethernet::{self, BROADCAST_MAC, ETHERTYPE_ARP, ETHERTYPE_IPV4};
+use super::LOCAL_IP;
+use crate::drivers::NetDevice;
+use crate::sync::SpinMutexIrqSave;
+use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
+use core::net::Ipv4Addr;
+use lazy_static::*;
+
+const HTYPE_ETHERNET: u16 = 1;
+const OPER_REQUEST: u16 = 1;
+const OPER_REPLY: u16 = 2;
+/// An ARP packet for IPv4 over Ethernet.
+const PACKET_LEN: usize = 28;
+/// Packets waiting for replies at most, more are dropped.
+const PENDING_MAX: usize = 16;
+
+struct ArpTable {
+    /// MAC addresses by IPv4 address, kept for good
+    cache: BTreeMap<Ipv4Addr, [u8; 6]>,
+    /// IPv4 packets and the hops they wait for
+    pending: Vec<(Ipv4Addr, Vec<u8>)>,
+}
+
+lazy_static! {
+    static ref ARP_TABLE: SpinMutexIrqSave<ArpTable> = SpinMutexIrqSave::new(ArpTable {
+        cache: BTreeMap::new(),
+        pending: Vec::new(),
+    });
+}
+
+fn send_arp(
+    device: &dyn NetDevice,
+    oper: u16,
+    target_mac: [u8; 6],
+    target_ip: Ipv4Addr,
+    dst: [u8; 6],
+) {
+    let mut packet = Vec::with_capacity(PACKET_LEN);
+    packet.extend_from_slice(&HTYPE_ETHERNET.to_be_bytes());
+    packet.extend_from_slice(&ETHERTYPE_IPV4.to_be_bytes());
+    packet.extend_from_slice(&[6, 4]);
+    packet.extend_from_slice(&oper.to_be_bytes());
+    packet.extend_from_slice(&device.mac());
+    packet.extend_from_slice(&LOCAL_IP.octets());
+    packet.extend_from_slice(&target_mac);
+    packet.extend_from_slice(&target_ip.octets());
+    ethernet::send(device, dst, ETHERTYPE_ARP, &packet);
+}
+
+/// Send the IPv4 `packet` to `hop` on `device`, asking for its MAC address
+/// first if it is not known. Returns false if the packet was dropped.
+pub fn send_ipv4(device: &dyn NetDevice, hop: Ipv4Addr, packet: Vec<u8>) -> bool {
+    if hop.is_broadcast() {
+        return ethernet::send(device, BROADCAST_MAC, ETHERTYPE_IPV4, &packet);
+    }
+    let mut table = ARP_TABLE.exclusive_access();
+    if let Some(&mac) = table.cache.get(&hop) {
+        drop(table);
+        return ethernet::send(device, mac, ETHERTYPE_IPV4, &packet);
+    }
+    if table.pending.len() == PENDING_MAX {
+        return false;
+    }
+    // one request is enough for all the packets waiting for a hop
+    let asked = table.pending.iter().any(|(waiting, _)| *waiting == hop);
+    table.pending.push((hop, packet));
+    drop(table);
+    if !asked {
+        send_arp(device, OPER_REQUEST, [0; 6], hop, BROADCAST_MAC);
+    }
+    true
+}
+
+/// Learn from an ARP packet that came in on `device` and answer it if it
+/// asks for us.
+pub fn handle(device: &dyn NetDevice, packet: &[u8]) {
+    if packet.len() < PACKET_LEN
+        || u16::from_be_bytes([packet[0], packet[1]]) != HTYPE_ETHERNET
+        || u16::from_be_bytes([packet[2], packet[3]]) != ETHERTYPE_IPV4
+        || packet[4] != 6
+        || packet[5] != 4
+    {
+        return;
+    }
+    let oper = u16::from_be_bytes([packet[6], packet[7]]);
+    let sender_mac: [u8; 6] = packet[8..14].try_into().unwrap();
+    let sender_ip = Ipv4Addr::from(<[u8; 4]>::try_from(&packet[14..18]).unwrap());
+    let target_ip = Ipv4Addr::from(<[u8; 4]>::try_from(&packet[24..28]).unwrap());
+    if target_ip != LOCAL_IP {
+        return;
+    }
+    let mut table = ARP_TABLE.exclusive_access();
+    table.cache.insert(sender_ip, sender_mac);
+    let (ready, waiting): (Vec<_>, Vec<_>) = table
+        .pending
+        .drain(..)
+        .partition(|(hop, _)| *hop == sender_ip);
+    table.pending = waiting;
+    drop(table);
+    for (_, packet) in ready {
+        ethernet::send(device, sender_mac, ETHERTYPE_IPV4, &packet);
+    }
+    if oper == OPER_REQUEST {
+        send_arp(device, OPER_REPLY, sender_mac, sender_ip, sender_mac);
+    }
+}
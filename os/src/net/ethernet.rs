@@ -0,0 +1,42 @@
+//! Ethernet II framing.
+
+use crate::drivers::NetDevice;
+use alloc::vec::Vec;
+
+pub const ETHERTYPE_IPV4: u16 = 0x0800;
+pub const ETHERTYPE_ARP: u16 = 0x0806;
+
+pub const BROADCAST_MAC: [u8; 6] = [0xff; 6];
+
+/// Destination, source and type.
+const HEADER_LEN: usize = 14;
+/// Shorter frames are padded, the device adds the checksum.
+const FRAME_MIN: usize = 60;
+
+/// Bytes an Ethernet frame can carry.
+pub const PAYLOAD_MAX: usize = crate::drivers::FRAME_MAX - HEADER_LEN;
+
+/// The type and payload of `frame`, received on `device`, if it is for us.
+pub fn parse<'a>(device: &dyn NetDevice, frame: &'a [u8]) -> Option<(u16, &'a [u8])> {
+    if frame.len() < HEADER_LEN {
+        return None;
+    }
+    let dst: [u8; 6] = frame[..6].try_into().unwrap();
+    if dst != device.mac() && dst != BROADCAST_MAC {
+        return None;
+    }
+    let ethertype = u16::from_be_bytes([frame[12], frame[13]]);
+    Some((ethertype, &frame[HEADER_LEN..]))
+}
+
+/// Frame `payload` to `dst` and send it on `device`, returns false if the
+/// device dropped it.
+pub fn send(device: &dyn NetDevice, dst: [u8; 6], ethertype: u16, payload: &[u8]) -> bool {
+    let mut frame = Vec::with_capacity((HEADER_LEN + payload.len()).max(FRAME_MIN));
+    frame.extend_from_slice(&dst);
+    frame.extend_from_slice(&device.mac());
+    frame.extend_from_slice(&ethertype.to_be_bytes());
+    frame.extend_from_slice(payload);
+    frame.resize(frame.len().max(FRAME_MIN), 0);
+    device.transmit(&frame)
+}
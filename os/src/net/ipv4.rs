@@ -0,0 +1,150 @@
+//! IPv4, without options or fragments: packets are sent whole with DF
+//! set, and fragments coming in are dropped. Packets for us go out on
+//! `lo`, the rest on `eth0`.
+
+use super::{arp, ethernet, tcp, udp, GATEWAY, LOCAL_IP, NETMASK};
+use crate::drivers::{LOOPBACK_DEVICE, NET_DEVICE};
+use alloc::vec::Vec;
+use core::net::Ipv4Addr;
+use core::sync::atomic::{AtomicU16, Ordering};
+
+pub const PROTOCOL_TCP: u8 = 6;
+pub const PROTOCOL_UDP: u8 = 17;
+
+/// A header without options.
+const HEADER_LEN: usize = 20;
+const TTL: u8 = 64;
+/// Don't fragment.
+const FLAG_DF: u16 = 0x4000;
+/// More fragments, and the offset of this one.
+const FRAGMENT_MASK: u16 = 0x3fff;
+
+/// Bytes a packet can carry.
+pub const PAYLOAD_MAX: usize = ethernet::PAYLOAD_MAX - HEADER_LEN;
+
+/// Identification of the next packet sent.
+static NEXT_ID: AtomicU16 = AtomicU16::new(0);
+
+/// The internet checksum of `data`, carrying on from `sum`.
+pub fn checksum(mut sum: u32, data: &[u8]) -> u16 {
+    for chunk in data.chunks(2) {
+        let word = match chunk {
+            [high, low] => u16::from_be_bytes([*high, *low]),
+            [high] => u16::from_be_bytes([*high, 0]),
+            _ => unreachable!(),
+        };
+        sum += word as u32;
+    }
+    while sum > 0xffff {
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+    !(sum as u16)
+}
+
+/// The sum of the pseudo header that UDP and TCP checksums cover.
+pub fn pseudo_header_sum(src: Ipv4Addr, dst: Ipv4Addr, protocol: u8, len: usize) -> u32 {
+    let [s0, s1, s2, s3] = src.octets();
+    let [d0, d1, d2, d3] = dst.octets();
+    [
+        u16::from_be_bytes([s0, s1]),
+        u16::from_be_bytes([s2, s3]),
+        u16::from_be_bytes([d0, d1]),
+        u16::from_be_bytes([d2, d3]),
+        protocol as u16,
+        len as u16,
+    ]
+    .iter()
+    .map(|&word| word as u32)
+    .sum()
+}
+
+/// Whether `addr` is one of ours, which sockets may bind to.
+pub fn is_ours(addr: Ipv4Addr) -> bool {
+    addr == LOCAL_IP || addr.is_loopback()
+}
+
+/// The source address of packets to `dst`: 127.0.0.1 for a loopback
+/// address, else `LOCAL_IP`.
+pub fn source_for(dst: Ipv4Addr) -> Ipv4Addr {
+    if dst.is_loopback() {
+        Ipv4Addr::LOCALHOST
+    } else {
+        LOCAL_IP
+    }
+}
+
+/// Whether a packet to `dst` is for us.
+fn is_local(dst: Ipv4Addr) -> bool {
+    let subnet_broadcast = u32::from(LOCAL_IP) | !u32::from(NETMASK);
+    is_ours(dst) || dst.is_broadcast() || u32::from(dst) == subnet_broadcast
+}
+
+/// Where a packet to `dst` goes first, the gateway unless `dst` is on
+/// our subnet.
+fn next_hop(dst: Ipv4Addr) -> Ipv4Addr {
+    let mask = u32::from(NETMASK);
+    if dst.is_broadcast() || u32::from(dst) & mask == u32::from(LOCAL_IP) & mask {
+        dst
+    } else {
+        GATEWAY
+    }
+}
+
+/// Send `payload` to `dst`, returns false if it is too long, there is no
+/// device to send it on or it was dropped on the way out.
+pub fn send(dst: Ipv4Addr, protocol: u8, payload: &[u8]) -> bool {
+    if payload.len() > PAYLOAD_MAX {
+        return false;
+    }
+    let total_len = (HEADER_LEN + payload.len()) as u16;
+    let mut packet = Vec::with_capacity(total_len as usize);
+    packet.extend_from_slice(&[0x45, 0]);
+    packet.extend_from_slice(&total_len.to_be_bytes());
+    packet.extend_from_slice(&NEXT_ID.fetch_add(1, Ordering::Relaxed).to_be_bytes());
+    packet.extend_from_slice(&FLAG_DF.to_be_bytes());
+    packet.extend_from_slice(&[TTL, protocol, 0, 0]);
+    packet.extend_from_slice(&source_for(dst).octets());
+    packet.extend_from_slice(&dst.octets());
+    let sum = checksum(0, &packet);
+    packet[10..12].copy_from_slice(&sum.to_be_bytes());
+    packet.extend_from_slice(payload);
+    if is_ours(dst) {
+        // nobody to ask on `lo`, its frames go to its own address
+        let device = LOOPBACK_DEVICE.as_ref();
+        return ethernet::send(device, device.mac(), ethernet::ETHERTYPE_IPV4, &packet);
+    }
+    match NET_DEVICE.as_ref() {
+        Some(device) => arp::send_ipv4(device.as_ref(), next_hop(dst), packet),
+        None => false,
+    }
+}
+
+/// Pass an incoming packet for us on to its protocol.
+pub fn handle(packet: &[u8]) {
+    if packet.len() < HEADER_LEN || packet[0] >> 4 != 4 {
+        return;
+    }
+    let header_len = (packet[0] & 0xf) as usize * 4;
+    let total_len = u16::from_be_bytes([packet[2], packet[3]]) as usize;
+    if header_len < HEADER_LEN
+        || total_len < header_len
+        || total_len > packet.len()
+        || checksum(0, &packet[..header_len]) != 0
+    {
+        return;
+    }
+    if u16::from_be_bytes([packet[6], packet[7]]) & FRAGMENT_MASK != 0 {
+        return;
+    }
+    let src = Ipv4Addr::from(<[u8; 4]>::try_from(&packet[12..16]).unwrap());
+    let dst = Ipv4Addr::from(<[u8; 4]>::try_from(&packet[16..20]).unwrap());
+    if !is_local(dst) {
+        return;
+    }
+    let payload = &packet[header_len..total_len];
+    match packet[9] {
+        PROTOCOL_UDP => udp::handle(src, dst, payload),
+        PROTOCOL_TCP => tcp::handle(src, dst, payload),
+        _ => {}
+    }
+}
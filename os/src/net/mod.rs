@@ -1,106 +1,50 @@
-pub mod port_table;
-pub mod socket;
-pub mod tcp;
-pub mod udp;
-
-pub use lose_net_stack::IPv4;
-
-use alloc::{sync::Arc, vec};
-use lose_net_stack::{results::Packet, LoseStack, MacAddress, TcpFlags};
-
-use crate::{
-    drivers::NET_DEVICE,
-    net::socket::{get_socket, push_data},
-    sync::UPIntrFreeCell,
-};
-
-use self::{port_table::check_accept, socket::set_s_a_by_index};
-
-pub struct NetStack(UPIntrFreeCell<LoseStack>);
-
-impl NetStack {
-    pub fn new() -> Self {
-        unsafe {
-            NetStack(UPIntrFreeCell::new(LoseStack::new(
-                IPv4::new(10, 0, 2, 15),
-                MacAddress::new([0x52, 0x54, 0x00, 0x12, 0x34, 0x56]),
-            )))
+//! The network stack over `NET_DEVICE`, `eth0`, and `LOOPBACK_DEVICE`,
+//! `lo`: Ethernet, ARP and IPv4 with UDP and TCP sockets. Packets to
+//! 127.0.0.0/8 or to our own address go through `lo`, so sockets work
+//! without a virtio-net device too.
+
+mod arp;
+mod ethernet;
+mod ipv4;
+mod tcp;
+mod udp;
+
+pub use tcp::{TcpConnection, TcpSocket};
+pub use udp::UdpSocket;
+
+use crate::drivers::{NetDevice, LOOPBACK_DEVICE, NET_DEVICE};
+use core::net::Ipv4Addr;
+
+/// Addresses QEMU user networking hands out.
+pub const LOCAL_IP: Ipv4Addr = Ipv4Addr::new(10, 0, 2, 15);
+pub const NETMASK: Ipv4Addr = Ipv4Addr::new(255, 255, 255, 0);
+pub const GATEWAY: Ipv4Addr = Ipv4Addr::new(10, 0, 2, 2);
+
+/// Take in every frame `device` received.
+fn receive_frames(device: &dyn NetDevice) {
+    while let Some(frame) = device.receive() {
+        match ethernet::parse(device, &frame) {
+            Some((ethernet::ETHERTYPE_ARP, packet)) => arp::handle(device, packet),
+            Some((ethernet::ETHERTYPE_IPV4, packet)) => ipv4::handle(packet),
+            _ => {}
         }
     }
 }
 
-lazy_static::lazy_static! {
-    static ref LOSE_NET_STACK: Arc<NetStack> = Arc::new(NetStack::new());
-}
-
+/// Take in every frame `eth0` received, called for its interrupts.
 pub fn net_interrupt_handler() {
-    let mut recv_buf = vec![0u8; 1024];
-
-    let len = NET_DEVICE.receive(&mut recv_buf);
-
-    let packet = LOSE_NET_STACK
-        .0
-        .exclusive_access()
-        .analysis(&recv_buf[..len]);
-
-    // println!("[kernel] receive a packet");
-    // hexdump(&recv_buf[..len]);
-
-    match packet {
-        Packet::ARP(arp_packet) => {
-            let lose_stack = LOSE_NET_STACK.0.exclusive_access();
-            let reply_packet = arp_packet
-                .reply_packet(lose_stack.ip, lose_stack.mac)
-                .expect("can't build reply");
-            let reply_data = reply_packet.build_data();
-            NET_DEVICE.transmit(&reply_data)
-        }
-
-        Packet::UDP(udp_packet) => {
-            let target = udp_packet.source_ip;
-            let lport = udp_packet.dest_port;
-            let rport = udp_packet.source_port;
-
-            if let Some(socket_index) = get_socket(target, lport, rport) {
-                push_data(socket_index, udp_packet.data.to_vec());
-            }
-        }
-
-        Packet::TCP(tcp_packet) => {
-            let target = tcp_packet.source_ip;
-            let lport = tcp_packet.dest_port;
-            let rport = tcp_packet.source_port;
-            let flags = tcp_packet.flags;
-
-            if flags.contains(TcpFlags::S) {
-                // if it has a port to accept, then response the request
-                if check_accept(lport, &tcp_packet).is_some() {
-                    let mut reply_packet = tcp_packet.ack();
-                    reply_packet.flags = TcpFlags::S | TcpFlags::A;
-                    NET_DEVICE.transmit(&reply_packet.build_data());
-                }
-                return;
-            } else if tcp_packet.flags.contains(TcpFlags::F) {
-                // tcp disconnected
-                let reply_packet = tcp_packet.ack();
-                NET_DEVICE.transmit(&reply_packet.build_data());
-
-                let mut end_packet = reply_packet.ack();
-                end_packet.flags |= TcpFlags::F;
-                NET_DEVICE.transmit(&end_packet.build_data());
-            } else if tcp_packet.flags.contains(TcpFlags::A) && tcp_packet.data_len == 0 {
-                return;
-            }
-
-            if let Some(socket_index) = get_socket(target, lport, rport) {
-                push_data(socket_index, tcp_packet.data.to_vec());
-                set_s_a_by_index(socket_index, tcp_packet.seq, tcp_packet.ack);
-            }
-        }
-        _ => {}
+    if let Some(device) = NET_DEVICE.as_ref() {
+        device.handle_irq();
+        receive_frames(device.as_ref());
     }
 }
 
+/// Take in every frame sent to `lo`, called for the software interrupt
+/// it raises.
+pub fn loopback_interrupt_handler() {
+    receive_frames(LOOPBACK_DEVICE.as_ref());
+}
+
 #[allow(unused)]
 pub fn hexdump(data: &[u8]) {
     const PRELAND_WIDTH: usize = 70;
@@ -1,113 +1,1112 @@
-use alloc::vec;
-use lose_net_stack::packets::tcp::TCPPacket;
-use lose_net_stack::IPv4;
-use lose_net_stack::MacAddress;
-use lose_net_stack::TcpFlags;
-
-use crate::{drivers::NET_DEVICE, fs::File};
-
-use super::socket::get_s_a_by_index;
-use super::{
-    net_interrupt_handler,
-    socket::{add_socket, pop_data, remove_socket},
-    LOSE_NET_STACK,
-};
-
-// add tcp packet info to this structure
-pub struct TCP {
-    pub target: IPv4,
-    pub sport: u16,
-    pub dport: u16,
-    pub seq: u32,
-    pub ack: u32,
-    pub socket_index: usize,
-}
-
-impl TCP {
-    pub fn new(target: IPv4, sport: u16, dport: u16, seq: u32, ack: u32) -> Self {
-        let index = add_socket(target, sport, dport).expect("can't add socket");
+//! TCP over IPv4. A connection keeps what it sends until the peer
+//! acknowledges it, and when the timer heap says nothing was acknowledged
+//! for a retransmission timeout it sends it all again from the oldest
+//! byte, backing off each time. Only the next segment in order is taken
+//! in, anything else waits for the peer to send it again. The window
+//! offered is the room left in the receive buffer.
+//!
+//! A connection outlives its socket: once the fd is closed it sends its
+//! FIN and goes on through TIME-WAIT, kept by `CONNECTIONS` meanwhile.
 
-        Self {
-            target,
-            sport,
-            dport,
-            seq,
-            ack,
-            socket_index: index,
-        }
+use super::ipv4::{self, PROTOCOL_TCP};
+use crate::drivers::rng::random;
+use crate::fs::{File, PollFlags, Stat, StatMode, EAGAIN};
+use crate::mm::UserBuffer;
+use crate::sync::{PollWaker, PollWakers, SpinMutexIrqSave, WaitQueue};
+use crate::task::{current_add_signal, schedule, SignalFlags};
+use crate::timer::{add_tcp_timer, get_time_ms};
+use alloc::collections::{BTreeMap, VecDeque};
+use alloc::sync::{Arc, Weak};
+use alloc::vec::Vec;
+use core::net::{Ipv4Addr, SocketAddrV4};
+use core::ops::RangeInclusive;
+use core::sync::atomic::{AtomicBool, Ordering};
+use lazy_static::*;
+
+/// A header without options.
+const HEADER_LEN: usize = 20;
+const OPTION_END: u8 = 0;
+const OPTION_NOP: u8 = 1;
+const OPTION_MSS: u8 = 2;
+/// Largest segment we send or take, what one IPv4 packet carries.
+const MSS: usize = ipv4::PAYLOAD_MAX - HEADER_LEN;
+/// Largest segment a peer takes if it does not say, RFC 9293.
+const DEFAULT_MSS: usize = 536;
+
+const FLAG_FIN: u8 = 0x01;
+const FLAG_SYN: u8 = 0x02;
+const FLAG_RST: u8 = 0x04;
+const FLAG_PSH: u8 = 0x08;
+const FLAG_ACK: u8 = 0x10;
+
+/// Bytes a connection buffers each way.
+const BUFFER_BYTES: usize = 16384;
+/// Retransmission timeout until the first backoff, RFC 6298.
+const INITIAL_RTO_MS: usize = 1000;
+const MAX_RTO_MS: usize = 60_000;
+/// Timeouts in a row before a connection is given up.
+const MAX_RETRIES: usize = 6;
+/// How long TIME-WAIT lasts, and how long FIN-WAIT-2 waits for the peer
+/// to close, as on Linux.
+const TIME_WAIT_MS: usize = 60_000;
+/// Ports given to sockets that did not pick one, as on Linux.
+const EPHEMERAL_PORTS: RangeInclusive<u16> = 32768..=60999;
+
+/// `connect` on a nonblocking socket goes on without it, poll says when
+/// it is done.
+pub const EINPROGRESS: isize = -4;
+
+lazy_static! {
+    /// Sockets by the port they were bound to.
+    static ref TCP_PORTS: SpinMutexIrqSave<BTreeMap<u16, Weak<TcpSocket>>> =
+        SpinMutexIrqSave::new(BTreeMap::new());
+    /// Connections not closed yet, by local port and peer.
+    static ref CONNECTIONS: SpinMutexIrqSave<BTreeMap<(u16, SocketAddrV4), Arc<TcpConnection>>> =
+        SpinMutexIrqSave::new(BTreeMap::new());
+}
+
+/// Whether sequence number `a` comes before `b`, modulo 2^32.
+fn seq_lt(a: u32, b: u32) -> bool {
+    (a.wrapping_sub(b) as i32) < 0
+}
+
+fn seq_le(a: u32, b: u32) -> bool {
+    a == b || seq_lt(a, b)
+}
+
+/// An incoming segment.
+struct Segment<'a> {
+    seq: u32,
+    ack: u32,
+    flags: u8,
+    window: u16,
+    /// the MSS option, if the peer sent one
+    mss: Option<usize>,
+    payload: &'a [u8],
+}
+
+impl Segment<'_> {
+    /// Sequence numbers it takes, a SYN or a FIN taking one each.
+    fn len(&self) -> u32 {
+        (self.payload.len()
+            + (self.flags & FLAG_SYN != 0) as usize
+            + (self.flags & FLAG_FIN != 0) as usize) as u32
     }
 }
 
-impl File for TCP {
-    fn readable(&self) -> bool {
-        true
+/// The MSS option among `options`.
+fn parse_mss(mut options: &[u8]) -> Option<usize> {
+    loop {
+        match *options {
+            [] | [OPTION_END, ..] => return None,
+            [OPTION_NOP, ref rest @ ..] => options = rest,
+            [OPTION_MSS, 4, high, low, ..] => return Some(u16::from_be_bytes([high, low]) as usize),
+            [_, len, ..] if len >= 2 && len as usize <= options.len() => {
+                options = &options[len as usize..]
+            }
+            _ => return None,
+        }
     }
+}
 
-    fn writable(&self) -> bool {
-        true
+/// Send one segment from `local_port` to `remote`, with the MSS option
+/// if it is a SYN.
+fn send_segment(
+    local_port: u16,
+    remote: SocketAddrV4,
+    seq: u32,
+    ack: u32,
+    flags: u8,
+    window: u16,
+    payload: &[u8],
+) {
+    let options: &[u8] = if flags & FLAG_SYN != 0 {
+        &[OPTION_MSS, 4, (MSS >> 8) as u8, MSS as u8]
+    } else {
+        &[]
+    };
+    let header_len = HEADER_LEN + options.len();
+    let mut segment = Vec::with_capacity(header_len + payload.len());
+    segment.extend_from_slice(&local_port.to_be_bytes());
+    segment.extend_from_slice(&remote.port().to_be_bytes());
+    segment.extend_from_slice(&seq.to_be_bytes());
+    segment.extend_from_slice(&ack.to_be_bytes());
+    segment.extend_from_slice(&[((header_len / 4) as u8) << 4, flags]);
+    segment.extend_from_slice(&window.to_be_bytes());
+    // the checksum, then the urgent pointer
+    segment.extend_from_slice(&[0, 0, 0, 0]);
+    segment.extend_from_slice(options);
+    segment.extend_from_slice(payload);
+    let sum = ipv4::pseudo_header_sum(
+        ipv4::source_for(*remote.ip()),
+        *remote.ip(),
+        PROTOCOL_TCP,
+        segment.len(),
+    );
+    let checksum = ipv4::checksum(sum, &segment);
+    segment[16..18].copy_from_slice(&checksum.to_be_bytes());
+    // lost segments are sent again when they time out
+    ipv4::send(*remote.ip(), PROTOCOL_TCP, &segment);
+}
+
+/// Answer a segment no connection takes with a reset, unless it is one.
+fn send_reset(local_port: u16, remote: SocketAddrV4, seg: &Segment) {
+    if seg.flags & FLAG_RST != 0 {
+        return;
+    }
+    if seg.flags & FLAG_ACK != 0 {
+        send_segment(local_port, remote, seg.ack, 0, FLAG_RST, 0, &[]);
+    } else {
+        let ack = seg.seq.wrapping_add(seg.len());
+        send_segment(local_port, remote, 0, ack, FLAG_RST | FLAG_ACK, 0, &[]);
     }
+}
 
-    fn read(&self, mut buf: crate::mm::UserBuffer) -> usize {
-        loop {
-            if let Some(data) = pop_data(self.socket_index) {
-                let data_len = data.len();
-                let mut left = 0;
-                for i in 0..buf.buffers.len() {
-                    let buffer_i_len = buf.buffers[i].len().min(data_len - left);
+/// Take `port` for `socket`, any free one if it is 0. Returns none if it
+/// is taken.
+fn reserve_port(socket: &Arc<TcpSocket>, port: u16) -> Option<u16> {
+    let mut ports = TCP_PORTS.exclusive_access();
+    // ports of the sockets that were closed are free again
+    ports.retain(|_, socket| socket.strong_count() > 0);
+    let port = match port {
+        0 => {
+            let connections = CONNECTIONS.exclusive_access();
+            EPHEMERAL_PORTS.find(|port| {
+                !ports.contains_key(port)
+                    && !connections.keys().any(|(local_port, _)| local_port == port)
+            })?
+        }
+        port if ports.contains_key(&port) => return None,
+        port => port,
+    };
+    ports.insert(port, Arc::downgrade(socket));
+    Some(port)
+}
 
-                    buf.buffers[i][..buffer_i_len]
-                        .copy_from_slice(&data[left..(left + buffer_i_len)]);
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum State {
+    SynSent,
+    SynReceived,
+    Established,
+    FinWait1,
+    FinWait2,
+    CloseWait,
+    Closing,
+    LastAck,
+    TimeWait,
+    Closed,
+}
+
+pub struct TcpConnection {
+    local_port: u16,
+    remote: SocketAddrV4,
+    inner: SpinMutexIrqSave<ConnectionInner>,
+}
+
+struct ConnectionInner {
+    state: State,
+    /// the sequence number of our SYN
+    iss: u32,
+    /// oldest sequence number not acknowledged
+    snd_una: u32,
+    /// next sequence number to send, back at `snd_una` after a timeout
+    snd_nxt: u32,
+    /// sequence number after the last one ever sent
+    snd_max: u32,
+    /// the window the peer offers, from `snd_una`
+    snd_wnd: usize,
+    /// largest segment the peer takes
+    mss: usize,
+    /// bytes written and not acknowledged yet, the first at `send_seq`
+    send_buf: VecDeque<u8>,
+    send_seq: u32,
+    /// the socket was closed, a FIN follows the bytes of `send_buf`
+    fin_queued: bool,
+    /// next sequence number expected
+    rcv_nxt: u32,
+    /// bytes received and not read yet
+    recv_buf: VecDeque<u8>,
+    /// the peer sent its FIN, nothing more comes
+    fin_received: bool,
+    /// reset by the peer, or given up on
+    reset: bool,
+    rto_ms: usize,
+    /// timeouts in a row
+    retries: usize,
+    /// bumped whenever the timer is set or stopped, so stale entries of
+    /// the timer heap are ignored
+    timer_generation: usize,
+    timer_armed: bool,
+    /// where a connection in SYN-RECEIVED goes once established
+    listener: Weak<Listener>,
+    /// waiting for bytes to read
+    readers: WaitQueue,
+    /// waiting for room to write, or for `connect` to finish
+    writers: WaitQueue,
+    pollers: PollWakers,
+}
+
+impl ConnectionInner {
+    fn new(
+        state: State,
+        rcv_nxt: u32,
+        snd_wnd: usize,
+        mss: usize,
+        listener: Weak<Listener>,
+    ) -> Self {
+        let iss = random() as u32;
+        Self {
+            state,
+            iss,
+            snd_una: iss,
+            snd_nxt: iss,
+            snd_max: iss,
+            snd_wnd,
+            mss,
+            send_buf: VecDeque::new(),
+            send_seq: iss.wrapping_add(1),
+            fin_queued: false,
+            rcv_nxt,
+            recv_buf: VecDeque::new(),
+            fin_received: false,
+            reset: false,
+            rto_ms: INITIAL_RTO_MS,
+            retries: 0,
+            timer_generation: 0,
+            timer_armed: false,
+            listener,
+            readers: WaitQueue::new(),
+            writers: WaitQueue::new(),
+            pollers: PollWakers::new(),
+        }
+    }
+    /// The window to offer, the room left to receive into.
+    fn window(&self) -> u16 {
+        (BUFFER_BYTES - self.recv_buf.len()).min(u16::MAX as usize) as u16
+    }
+    /// The sequence number of our FIN, once the socket is closed.
+    fn fin_seq(&self) -> Option<u32> {
+        self.fin_queued
+            .then(|| self.send_seq.wrapping_add(self.send_buf.len() as u32))
+    }
+    fn stop_timer(&mut self) {
+        self.timer_generation += 1;
+        self.timer_armed = false;
+    }
+    fn wake(&mut self) {
+        self.readers.wake_all();
+        self.writers.wake_all();
+        self.pollers.wake_all();
+    }
+}
 
-                    left += buffer_i_len;
-                    if left == data_len {
+impl TcpConnection {
+    fn new(local_port: u16, remote: SocketAddrV4, inner: ConnectionInner) -> Arc<Self> {
+        Arc::new(Self {
+            local_port,
+            remote,
+            inner: SpinMutexIrqSave::new(inner),
+        })
+    }
+    /// Send a segment with what the connection acknowledges and offers.
+    fn transmit(&self, inner: &ConnectionInner, seq: u32, flags: u8, payload: &[u8]) {
+        send_segment(
+            self.local_port,
+            self.remote,
+            seq,
+            inner.rcv_nxt,
+            flags,
+            inner.window(),
+            payload,
+        );
+    }
+    fn send_ack(&self, inner: &ConnectionInner) {
+        self.transmit(inner, inner.snd_nxt, FLAG_ACK, &[]);
+    }
+    fn set_timer(self: &Arc<Self>, inner: &mut ConnectionInner, ms: usize) {
+        inner.timer_generation += 1;
+        inner.timer_armed = true;
+        add_tcp_timer(
+            get_time_ms() + ms,
+            Arc::downgrade(self),
+            inner.timer_generation,
+        );
+    }
+    /// Send what may go now: the SYN, the bytes the window of the peer
+    /// has room for and then the FIN. Sets the timer if anything is left
+    /// unacknowledged. Returns whether anything was sent.
+    fn output(self: &Arc<Self>, inner: &mut ConnectionInner) -> bool {
+        let mut sent = false;
+        match inner.state {
+            State::SynSent | State::SynReceived => {
+                if inner.snd_nxt == inner.iss {
+                    if inner.state == State::SynSent {
+                        send_segment(
+                            self.local_port,
+                            self.remote,
+                            inner.iss,
+                            0,
+                            FLAG_SYN,
+                            inner.window(),
+                            &[],
+                        );
+                    } else {
+                        self.transmit(inner, inner.iss, FLAG_SYN | FLAG_ACK, &[]);
+                    }
+                    inner.snd_nxt = inner.iss.wrapping_add(1);
+                    sent = true;
+                }
+            }
+            State::Established
+            | State::CloseWait
+            | State::FinWait1
+            | State::Closing
+            | State::LastAck => {
+                let end = inner.send_seq.wrapping_add(inner.send_buf.len() as u32);
+                while seq_lt(inner.snd_nxt, end) {
+                    let in_flight = inner.snd_nxt.wrapping_sub(inner.snd_una) as usize;
+                    // with nothing in flight a byte goes even into a zero
+                    // window, probing it until it opens again
+                    let window = if in_flight == 0 {
+                        inner.snd_wnd.max(1)
+                    } else {
+                        inner.snd_wnd
+                    };
+                    let len = (end.wrapping_sub(inner.snd_nxt) as usize)
+                        .min(window.saturating_sub(in_flight))
+                        .min(inner.mss);
+                    if len == 0 {
                         break;
                     }
+                    let offset = inner.snd_nxt.wrapping_sub(inner.send_seq) as usize;
+                    let payload: Vec<u8> = inner
+                        .send_buf
+                        .range(offset..offset + len)
+                        .copied()
+                        .collect();
+                    self.transmit(inner, inner.snd_nxt, FLAG_ACK | FLAG_PSH, &payload);
+                    inner.snd_nxt = inner.snd_nxt.wrapping_add(len as u32);
+                    sent = true;
                 }
-                return left;
+                if inner.fin_seq() == Some(inner.snd_nxt) {
+                    self.transmit(inner, inner.snd_nxt, FLAG_FIN | FLAG_ACK, &[]);
+                    inner.snd_nxt = inner.snd_nxt.wrapping_add(1);
+                    sent = true;
+                }
+            }
+            _ => {}
+        }
+        if seq_lt(inner.snd_max, inner.snd_nxt) {
+            inner.snd_max = inner.snd_nxt;
+        }
+        if inner.snd_una != inner.snd_max && !inner.timer_armed {
+            let rto_ms = inner.rto_ms;
+            self.set_timer(inner, rto_ms);
+        }
+        sent
+    }
+    /// Done with the connection: wake everyone waiting on it and forget
+    /// it. The caller holds a reference of its own.
+    fn close_connection(&self, inner: &mut ConnectionInner) {
+        inner.state = State::Closed;
+        inner.stop_timer();
+        inner.wake();
+        CONNECTIONS
+            .exclusive_access()
+            .remove(&(self.local_port, self.remote));
+    }
+    /// Give up on the connection, telling the peer.
+    fn abort(&self) {
+        let mut inner = self.inner.exclusive_access();
+        if inner.state == State::Closed {
+            return;
+        }
+        self.transmit(&inner, inner.snd_nxt, FLAG_RST | FLAG_ACK, &[]);
+        inner.reset = true;
+        self.close_connection(&mut inner);
+    }
+    /// The socket was closed: a FIN goes after what is left to send, and
+    /// the connection goes on until the peer acknowledges it.
+    fn close(self: &Arc<Self>) {
+        let mut inner = self.inner.exclusive_access();
+        match inner.state {
+            State::SynSent => return self.close_connection(&mut inner),
+            State::Established => inner.state = State::FinWait1,
+            State::CloseWait => inner.state = State::LastAck,
+            // closing or closed already
+            _ => return,
+        }
+        inner.fin_queued = true;
+        // nobody reads them any more
+        inner.recv_buf.clear();
+        self.output(&mut inner);
+    }
+    /// The timer went off: TIME-WAIT or FIN-WAIT-2 is over, or nothing
+    /// was acknowledged for a whole timeout, so all that is not goes
+    /// again and the timeout doubles.
+    pub fn timeout(self: &Arc<Self>, generation: usize) {
+        let mut inner = self.inner.exclusive_access();
+        if !inner.timer_armed || inner.timer_generation != generation {
+            return;
+        }
+        inner.timer_armed = false;
+        match inner.state {
+            State::TimeWait | State::FinWait2 => return self.close_connection(&mut inner),
+            State::Closed => return,
+            _ => {}
+        }
+        if inner.snd_una == inner.snd_max {
+            return;
+        }
+        inner.retries += 1;
+        if inner.retries > MAX_RETRIES {
+            drop(inner);
+            return self.abort();
+        }
+        inner.rto_ms = (inner.rto_ms * 2).min(MAX_RTO_MS);
+        inner.snd_nxt = inner.snd_una;
+        self.output(&mut inner);
+    }
+    /// Take in the answer to our SYN.
+    fn input_syn_sent(&self, inner: &mut ConnectionInner, seg: &Segment) {
+        let ack_ok = seg.flags & FLAG_ACK != 0 && seg.ack == inner.iss.wrapping_add(1);
+        if seg.flags & FLAG_ACK != 0 && !ack_ok {
+            return send_reset(self.local_port, self.remote, seg);
+        }
+        if seg.flags & FLAG_RST != 0 {
+            // refused
+            if ack_ok {
+                inner.reset = true;
+                self.close_connection(inner);
+            }
+            return;
+        }
+        // both ends opening at once is not supported
+        if seg.flags & FLAG_SYN == 0 || !ack_ok {
+            return;
+        }
+        inner.rcv_nxt = seg.seq.wrapping_add(1);
+        inner.snd_una = seg.ack;
+        inner.snd_wnd = seg.window as usize;
+        inner.mss = seg.mss.unwrap_or(DEFAULT_MSS).min(MSS);
+        inner.rto_ms = INITIAL_RTO_MS;
+        inner.retries = 0;
+        inner.stop_timer();
+        inner.state = State::Established;
+        inner.wake();
+        self.send_ack(inner);
+    }
+    /// Take in a segment for this connection, RFC 9293 3.10.7.
+    fn input(self: &Arc<Self>, seg: &Segment) {
+        let mut inner = self.inner.exclusive_access();
+        match inner.state {
+            State::Closed => return,
+            State::SynSent => return self.input_syn_sent(&mut inner, seg),
+            _ => {}
+        }
+        if seg.flags & FLAG_RST != 0 {
+            // a reset counts only right at the next sequence number, so
+            // nobody can guess one in
+            if seg.seq == inner.rcv_nxt {
+                // a connection not accepted yet just goes away
+                inner.reset = inner.state != State::SynReceived;
+                self.close_connection(&mut inner);
+            }
+            return;
+        }
+        if seg.flags & FLAG_SYN != 0 {
+            // our SYN-ACK got lost and the peer asks again
+            if inner.state == State::SynReceived && seg.seq.wrapping_add(1) == inner.rcv_nxt {
+                inner.snd_nxt = inner.iss;
+                self.output(&mut inner);
             } else {
-                net_interrupt_handler();
+                self.send_ack(&inner);
+            }
+            return;
+        }
+        let mut payload = seg.payload;
+        let mut fin = seg.flags & FLAG_FIN != 0;
+        // bytes received already are acknowledged again and dropped, as
+        // is anything after a gap
+        let mut need_ack = false;
+        if seq_lt(seg.seq, inner.rcv_nxt) {
+            let old = inner.rcv_nxt.wrapping_sub(seg.seq) as usize;
+            if old > payload.len() {
+                fin = false;
+            }
+            payload = &payload[old.min(payload.len())..];
+            need_ack = true;
+        } else if seg.seq != inner.rcv_nxt {
+            self.send_ack(&inner);
+            return;
+        }
+        if seg.flags & FLAG_ACK == 0 {
+            return;
+        }
+        let ack = seg.ack;
+        if inner.state == State::SynReceived {
+            if !seq_lt(inner.snd_una, ack) || seq_lt(inner.snd_max, ack) {
+                return send_reset(self.local_port, self.remote, seg);
+            }
+            inner.state = State::Established;
+            let queued = inner
+                .listener
+                .upgrade()
+                .map_or(false, |listener| listener.push(self.clone()));
+            // the listening socket is gone or its backlog is full
+            if !queued {
+                drop(inner);
+                return self.abort();
+            }
+        }
+        if seq_lt(inner.snd_max, ack) {
+            // for something never sent
+            self.send_ack(&inner);
+            return;
+        }
+        if seq_lt(inner.snd_una, ack) {
+            let mut acked = ack.wrapping_sub(inner.snd_una) as usize;
+            if inner.snd_una == inner.iss {
+                // the SYN
+                acked -= 1;
+            }
+            let bytes = acked.min(inner.send_buf.len());
+            inner.send_buf.drain(..bytes);
+            inner.send_seq = inner.send_seq.wrapping_add(bytes as u32);
+            inner.snd_una = ack;
+            if seq_lt(inner.snd_nxt, ack) {
+                inner.snd_nxt = ack;
+            }
+            inner.rto_ms = INITIAL_RTO_MS;
+            inner.retries = 0;
+            // set again below if something is still in flight
+            inner.stop_timer();
+            inner.wake();
+        }
+        if seq_le(inner.snd_una, ack) {
+            inner.snd_wnd = seg.window as usize;
+            // the peer is there, if only to say its window is still shut
+            inner.retries = 0;
+        }
+        let fin_acked = inner
+            .fin_seq()
+            .map_or(false, |fin| seq_lt(fin, inner.snd_una));
+        match inner.state {
+            State::FinWait1 if fin_acked => {
+                inner.state = State::FinWait2;
+                self.set_timer(&mut inner, TIME_WAIT_MS);
+            }
+            State::Closing if fin_acked => {
+                inner.state = State::TimeWait;
+                self.set_timer(&mut inner, TIME_WAIT_MS);
+            }
+            State::LastAck if fin_acked => return self.close_connection(&mut inner),
+            _ => {}
+        }
+        let receiving = matches!(
+            inner.state,
+            State::Established | State::FinWait1 | State::FinWait2
+        );
+        if receiving && !payload.is_empty() {
+            let taken = if inner.fin_queued {
+                // nobody reads them any more
+                payload.len()
+            } else {
+                let taken = payload.len().min(BUFFER_BYTES - inner.recv_buf.len());
+                inner.recv_buf.extend(&payload[..taken]);
+                taken
+            };
+            inner.rcv_nxt = inner.rcv_nxt.wrapping_add(taken as u32);
+            // the FIN comes after the bytes that did not fit
+            if taken < payload.len() {
+                fin = false;
+            }
+            need_ack = true;
+            inner.wake();
+        }
+        if receiving && fin {
+            inner.rcv_nxt = inner.rcv_nxt.wrapping_add(1);
+            inner.fin_received = true;
+            need_ack = true;
+            inner.wake();
+            match inner.state {
+                State::Established => inner.state = State::CloseWait,
+                State::FinWait1 => inner.state = State::Closing,
+                _ => {
+                    inner.state = State::TimeWait;
+                    self.set_timer(&mut inner, TIME_WAIT_MS);
+                }
+            }
+        }
+        // data going out acknowledges too
+        if !self.output(&mut inner) && need_ack {
+            self.send_ack(&inner);
+        }
+    }
+    /// Read what has arrived, waiting if nothing has unless not `wait`,
+    /// then returns none. Returns 0 once the peer closed its side or the
+    /// connection is gone.
+    fn receive(&self, buf: UserBuffer, wait: bool) -> Option<usize> {
+        loop {
+            let mut inner = self.inner.exclusive_access();
+            if inner.recv_buf.is_empty() {
+                if inner.fin_received || inner.state == State::Closed {
+                    return Some(0);
+                }
+                if !wait {
+                    return None;
+                }
+                match inner.readers.wait_no_sched_interruptible() {
+                    Some(task_cx_ptr) => {
+                        drop(inner);
+                        schedule(task_cx_ptr);
+                        continue;
+                    }
+                    None => return Some(0),
+                }
+            }
+            let window = inner.window() as usize;
+            let mut read = 0;
+            for byte_ref in buf {
+                match inner.recv_buf.pop_front() {
+                    Some(byte) => unsafe { *byte_ref = byte },
+                    None => break,
+                }
+                read += 1;
+            }
+            // tell the peer about a window it could not send into before,
+            // instead of leaving it to probe
+            if window < inner.mss && inner.window() as usize >= inner.mss && !inner.fin_received {
+                self.send_ack(&inner);
+            }
+            return Some(read);
+        }
+    }
+    /// Queue all of `buf` to be sent, waiting for room or for the
+    /// handshake as needed unless not `wait`, then returns none if nothing
+    /// could be queued. Writing once the connection is closed raises
+    /// SIGPIPE like a pipe without readers.
+    fn send(self: &Arc<Self>, buf: UserBuffer, wait: bool) -> Option<usize> {
+        let mut buf_iter = buf.into_iter().peekable();
+        let mut written = 0;
+        while buf_iter.peek().is_some() {
+            let mut inner = self.inner.exclusive_access();
+            let room = match inner.state {
+                State::Established | State::CloseWait => BUFFER_BYTES - inner.send_buf.len(),
+                State::SynSent => 0,
+                _ => {
+                    drop(inner);
+                    current_add_signal(SignalFlags::SIGPIPE);
+                    return Some(written);
+                }
+            };
+            if room == 0 {
+                if !wait {
+                    return (written > 0).then_some(written);
+                }
+                match inner.writers.wait_no_sched_interruptible() {
+                    Some(task_cx_ptr) => {
+                        drop(inner);
+                        schedule(task_cx_ptr);
+                        continue;
+                    }
+                    None => return Some(written),
+                }
+            }
+            for _ in 0..room {
+                match buf_iter.next() {
+                    Some(byte_ref) => inner.send_buf.push_back(unsafe { *byte_ref }),
+                    None => break,
+                }
+                written += 1;
+            }
+            self.output(&mut inner);
+        }
+        Some(written)
+    }
+    /// Wait for the handshake `connect` started. Fails if the peer
+    /// refused or never answered, or a signal came.
+    fn wait_connected(&self) -> Result<(), isize> {
+        loop {
+            let inner = self.inner.exclusive_access();
+            match inner.state {
+                State::SynSent => {}
+                State::Closed => return Err(-1),
+                _ => return Ok(()),
+            }
+            match inner.writers.wait_no_sched_interruptible() {
+                Some(task_cx_ptr) => {
+                    drop(inner);
+                    schedule(task_cx_ptr);
+                }
+                None => return Err(-1),
             }
         }
     }
+    fn poll(&self) -> PollFlags {
+        let inner = self.inner.exclusive_access();
+        let mut flags = PollFlags::empty();
+        if !inner.recv_buf.is_empty() {
+            flags |= PollFlags::IN;
+        }
+        if inner.fin_received || inner.state == State::Closed {
+            flags |= PollFlags::HUP;
+        }
+        if inner.reset {
+            flags |= PollFlags::ERR;
+        } else if matches!(inner.state, State::Established | State::CloseWait)
+            && inner.send_buf.len() < BUFFER_BYTES
+        {
+            flags |= PollFlags::OUT;
+        }
+        flags
+    }
+}
+
+/// The listening side of a socket.
+struct Listener {
+    port: u16,
+    inner: SpinMutexIrqSave<ListenerInner>,
+}
 
-    fn write(&self, buf: crate::mm::UserBuffer) -> usize {
-        let lose_net_stack = LOSE_NET_STACK.0.exclusive_access();
+struct ListenerInner {
+    /// established connections not accepted yet
+    pending: VecDeque<Arc<TcpConnection>>,
+    /// how many may be pending
+    backlog: usize,
+    /// the socket was closed, no more are taken
+    closed: bool,
+    /// waiting in `accept`
+    acceptors: WaitQueue,
+    /// polling for a connection to accept
+    pollers: PollWakers,
+}
 
-        let mut data = vec![0u8; buf.len()];
+impl Listener {
+    fn new(port: u16, backlog: usize) -> Self {
+        Self {
+            port,
+            inner: SpinMutexIrqSave::new(ListenerInner {
+                pending: VecDeque::new(),
+                backlog,
+                closed: false,
+                acceptors: WaitQueue::new(),
+                pollers: PollWakers::new(),
+            }),
+        }
+    }
+    /// Answer a SYN from `remote` with a connection in SYN-RECEIVED. The
+    /// SYN is dropped if the backlog is full, the peer sends it again.
+    fn open(self: &Arc<Self>, remote: SocketAddrV4, seg: &Segment) {
+        let inner = self.inner.exclusive_access();
+        if inner.pending.len() >= inner.backlog {
+            return;
+        }
+        drop(inner);
+        let connection = TcpConnection::new(
+            self.port,
+            remote,
+            ConnectionInner::new(
+                State::SynReceived,
+                seg.seq.wrapping_add(1),
+                seg.window as usize,
+                seg.mss.unwrap_or(DEFAULT_MSS).min(MSS),
+                Arc::downgrade(self),
+            ),
+        );
+        CONNECTIONS
+            .exclusive_access()
+            .insert((self.port, remote), connection.clone());
+        let mut inner = connection.inner.exclusive_access();
+        connection.output(&mut inner);
+    }
+    /// Queue an established connection for `accept`, false if the
+    /// backlog is full or the socket was closed.
+    fn push(&self, connection: Arc<TcpConnection>) -> bool {
+        let mut inner = self.inner.exclusive_access();
+        if inner.closed || inner.pending.len() >= inner.backlog {
+            return false;
+        }
+        inner.pending.push_back(connection);
+        inner.acceptors.wake_one();
+        inner.pollers.wake_all();
+        true
+    }
+    /// Reset the connections nobody accepted.
+    fn close(&self) {
+        let mut inner = self.inner.exclusive_access();
+        inner.closed = true;
+        let pending: Vec<_> = inner.pending.drain(..).collect();
+        drop(inner);
+        for connection in pending {
+            connection.abort();
+        }
+    }
+}
 
-        let mut left = 0;
-        for i in 0..buf.buffers.len() {
-            data[left..(left + buf.buffers[i].len())].copy_from_slice(buf.buffers[i]);
-            left += buf.buffers[i].len();
+/// Hand an incoming segment to its connection, or start one if it is a
+/// SYN for a listening port. Anything else is answered with a reset.
+pub fn handle(src: Ipv4Addr, dst: Ipv4Addr, segment: &[u8]) {
+    if !ipv4::is_ours(dst)
+        || segment.len() < HEADER_LEN
+        || ipv4::checksum(
+            ipv4::pseudo_header_sum(src, dst, PROTOCOL_TCP, segment.len()),
+            segment,
+        ) != 0
+    {
+        return;
+    }
+    let header_len = (segment[12] >> 4) as usize * 4;
+    if header_len < HEADER_LEN || header_len > segment.len() {
+        return;
+    }
+    let src_port = u16::from_be_bytes([segment[0], segment[1]]);
+    let dst_port = u16::from_be_bytes([segment[2], segment[3]]);
+    let seg = Segment {
+        seq: u32::from_be_bytes(segment[4..8].try_into().unwrap()),
+        ack: u32::from_be_bytes(segment[8..12].try_into().unwrap()),
+        flags: segment[13] & 0x3f,
+        window: u16::from_be_bytes([segment[14], segment[15]]),
+        mss: parse_mss(&segment[HEADER_LEN..header_len]),
+        payload: &segment[header_len..],
+    };
+    let remote = SocketAddrV4::new(src, src_port);
+    let connection = CONNECTIONS
+        .exclusive_access()
+        .get(&(dst_port, remote))
+        .cloned();
+    if let Some(connection) = connection {
+        return connection.input(&seg);
+    }
+    if seg.flags & (FLAG_SYN | FLAG_ACK | FLAG_RST) == FLAG_SYN {
+        let socket = TCP_PORTS
+            .exclusive_access()
+            .get(&dst_port)
+            .and_then(Weak::upgrade);
+        if let Some(listener) = socket.and_then(|socket| socket.listener()) {
+            return listener.open(remote, &seg);
         }
+    }
+    send_reset(dst_port, remote, &seg);
+}
 
-        let len = data.len();
+enum SocketState {
+    /// neither listening nor connected yet, maybe bound to a port
+    Idle {
+        port: Option<u16>,
+    },
+    Listening(Arc<Listener>),
+    Connected(Arc<TcpConnection>),
+}
 
-        // get sock and sequence
-        let (ack, seq) = get_s_a_by_index(self.socket_index).map_or((0, 0), |x| x);
+pub struct TcpSocket {
+    state: SpinMutexIrqSave<SocketState>,
+    /// `O_NONBLOCK`, `accept` and `connect` do not wait either
+    nonblock: AtomicBool,
+}
 
-        let tcp_packet = TCPPacket {
-            source_ip: lose_net_stack.ip,
-            source_mac: lose_net_stack.mac,
-            source_port: self.sport,
-            dest_ip: self.target,
-            dest_mac: MacAddress::new([0xff, 0xff, 0xff, 0xff, 0xff, 0xff]),
-            dest_port: self.dport,
-            data_len: len,
-            seq,
-            ack,
-            flags: TcpFlags::A,
-            win: 65535,
-            urg: 0,
-            data: data.as_ref(),
+impl TcpSocket {
+    pub fn new() -> Self {
+        Self {
+            state: SpinMutexIrqSave::new(SocketState::Idle { port: None }),
+            nonblock: AtomicBool::new(false),
+        }
+    }
+    fn connected(connection: Arc<TcpConnection>) -> Self {
+        Self {
+            state: SpinMutexIrqSave::new(SocketState::Connected(connection)),
+            nonblock: AtomicBool::new(false),
+        }
+    }
+    fn listener(&self) -> Option<Arc<Listener>> {
+        match &*self.state.exclusive_access() {
+            SocketState::Listening(listener) => Some(listener.clone()),
+            _ => None,
+        }
+    }
+    fn connection(&self) -> Option<Arc<TcpConnection>> {
+        match &*self.state.exclusive_access() {
+            SocketState::Connected(connection) => Some(connection.clone()),
+            _ => None,
+        }
+    }
+    /// Take the local address `addr`, any free port if its port is 0.
+    /// Fails if the socket has a port already, the port is taken or the
+    /// address is not ours.
+    pub fn bind(socket: &Arc<Self>, addr: SocketAddrV4) -> bool {
+        if !addr.ip().is_unspecified() && !ipv4::is_ours(*addr.ip()) {
+            return false;
+        }
+        let mut state = socket.state.exclusive_access();
+        if !matches!(*state, SocketState::Idle { port: None }) {
+            return false;
+        }
+        match reserve_port(socket, addr.port()) {
+            Some(port) => {
+                *state = SocketState::Idle { port: Some(port) };
+                true
+            }
+            None => false,
+        }
+    }
+    /// Start taking connections on the bound port, or any free one, at
+    /// most `backlog` of them waiting for `accept` at a time.
+    pub fn listen(socket: &Arc<Self>, backlog: usize) -> bool {
+        let mut state = socket.state.exclusive_access();
+        let port = match *state {
+            SocketState::Idle { port: Some(port) } => port,
+            SocketState::Idle { port: None } => match reserve_port(socket, 0) {
+                Some(port) => port,
+                None => return false,
+            },
+            // only the backlog changes
+            SocketState::Listening(ref listener) => {
+                listener.inner.exclusive_access().backlog = backlog.max(1);
+                return true;
+            }
+            SocketState::Connected(_) => return false,
+        };
+        *state = SocketState::Listening(Arc::new(Listener::new(port, backlog.max(1))));
+        true
+    }
+    /// Connect to `remote` from the bound port, or any free one. Waits
+    /// for the handshake unless nonblocking, then fails with
+    /// `EINPROGRESS` at once. Fails if the peer refused or never
+    /// answered, or a signal came.
+    pub fn connect(socket: &Arc<Self>, remote: SocketAddrV4) -> Result<(), isize> {
+        if remote.ip().is_unspecified() || remote.port() == 0 {
+            return Err(-1);
+        }
+        let mut state = socket.state.exclusive_access();
+        let port = match *state {
+            SocketState::Idle { port: Some(port) } => port,
+            SocketState::Idle { port: None } => match reserve_port(socket, 0) {
+                Some(port) => port,
+                None => return Err(-1),
+            },
+            _ => return Err(-1),
         };
-        NET_DEVICE.transmit(&tcp_packet.build_data());
-        len
+        *state = SocketState::Idle { port: Some(port) };
+        let connection = TcpConnection::new(
+            port,
+            remote,
+            ConnectionInner::new(State::SynSent, 0, 0, DEFAULT_MSS, Weak::new()),
+        );
+        let mut connections = CONNECTIONS.exclusive_access();
+        if connections.contains_key(&(port, remote)) {
+            return Err(-1);
+        }
+        connections.insert((port, remote), connection.clone());
+        drop(connections);
+        *state = SocketState::Connected(connection.clone());
+        drop(state);
+        let mut inner = connection.inner.exclusive_access();
+        connection.output(&mut inner);
+        drop(inner);
+        if socket.nonblocking() {
+            return Err(EINPROGRESS);
+        }
+        connection.wait_connected()
+    }
+    /// Wait for a connection and return a socket for it and the address
+    /// of the peer. Fails if this one is not listening or a signal came,
+    /// or with `EAGAIN` if it is nonblocking and nobody is connecting.
+    pub fn accept(&self) -> Result<(Arc<Self>, SocketAddrV4), isize> {
+        let listener = match self.listener() {
+            Some(listener) => listener,
+            None => return Err(-1),
+        };
+        loop {
+            let mut inner = listener.inner.exclusive_access();
+            if let Some(connection) = inner.pending.pop_front() {
+                let remote = connection.remote;
+                return Ok((Arc::new(Self::connected(connection)), remote));
+            }
+            if self.nonblocking() {
+                return Err(EAGAIN);
+            }
+            match inner.acceptors.wait_no_sched_interruptible() {
+                Some(task_cx_ptr) => {
+                    drop(inner);
+                    schedule(task_cx_ptr);
+                }
+                None => return Err(-1),
+            }
+        }
     }
 }
 
-impl Drop for TCP {
+impl Drop for TcpSocket {
     fn drop(&mut self) {
-        remove_socket(self.socket_index)
+        match &*self.state.exclusive_access() {
+            SocketState::Idle { .. } => {}
+            SocketState::Listening(listener) => listener.close(),
+            SocketState::Connected(connection) => connection.close(),
+        }
+    }
+}
+
+impl File for TcpSocket {
+    fn readable(&self) -> bool {
+        true
+    }
+    fn writable(&self) -> bool {
+        true
+    }
+    fn read(&self, buf: UserBuffer) -> usize {
+        self.connection()
+            .map_or(0, |connection| connection.receive(buf, true).unwrap())
+    }
+    fn write(&self, buf: UserBuffer) -> usize {
+        self.connection()
+            .map_or(0, |connection| connection.send(buf, true).unwrap())
+    }
+    fn try_read(&self, buf: UserBuffer) -> Option<usize> {
+        match self.connection() {
+            Some(connection) => connection.receive(buf, false),
+            None => Some(0),
+        }
+    }
+    fn try_write(&self, buf: UserBuffer) -> Option<usize> {
+        match self.connection() {
+            Some(connection) => connection.send(buf, false),
+            None => Some(0),
+        }
+    }
+    fn nonblocking(&self) -> bool {
+        self.nonblock.load(Ordering::Relaxed)
+    }
+    fn set_nonblocking(&self, nonblocking: bool) {
+        self.nonblock.store(nonblocking, Ordering::Relaxed);
+    }
+    fn stat(&self) -> Option<Stat> {
+        let size = self.connection().map_or(0, |connection| {
+            connection.inner.exclusive_access().recv_buf.len()
+        });
+        Some(Stat::new(0, StatMode::SOCK, 1, size as u64))
+    }
+    fn poll(&self) -> PollFlags {
+        match &*self.state.exclusive_access() {
+            SocketState::Idle { .. } => PollFlags::HUP,
+            SocketState::Listening(listener) => {
+                if listener.inner.exclusive_access().pending.is_empty() {
+                    PollFlags::empty()
+                } else {
+                    PollFlags::IN
+                }
+            }
+            SocketState::Connected(connection) => connection.poll(),
+        }
+    }
+    fn register_waker(&self, waker: &Arc<PollWaker>) {
+        match &*self.state.exclusive_access() {
+            SocketState::Idle { .. } => {}
+            SocketState::Listening(listener) => {
+                listener.inner.exclusive_access().pollers.register(waker)
+            }
+            SocketState::Connected(connection) => {
+                connection.inner.exclusive_access().pollers.register(waker)
+            }
+        }
     }
 }
@@ -1,95 +1,243 @@
-use super::net_interrupt_handler;
-use super::socket::{add_socket, pop_data, remove_socket};
-use super::LOSE_NET_STACK;
-use super::NET_DEVICE;
-use crate::fs::File;
-use alloc::vec;
-use lose_net_stack::packets::udp::UDPPacket;
-use lose_net_stack::IPv4;
-use lose_net_stack::MacAddress;
+//! UDP sockets. A socket is found by its local port, taking datagrams
+//! from anyone to any of our addresses; it gets a port of its own when
+//! bound, or when it first sends if it was not.
 
-pub struct UDP {
-    pub target: IPv4,
-    pub sport: u16,
-    pub dport: u16,
-    pub socket_index: usize,
+use super::ipv4::{self, PROTOCOL_UDP};
+use crate::fs::{File, PollFlags, Stat, StatMode, EAGAIN};
+use crate::mm::UserBuffer;
+use crate::sync::{PollWaker, PollWakers, SpinMutexIrqSave, WaitQueue};
+use crate::task::schedule;
+use alloc::collections::{BTreeMap, VecDeque};
+use alloc::sync::{Arc, Weak};
+use alloc::vec::Vec;
+use core::net::{Ipv4Addr, SocketAddrV4};
+use core::ops::RangeInclusive;
+use core::sync::atomic::{AtomicBool, Ordering};
+use lazy_static::*;
+
+/// Ports, length and checksum.
+const HEADER_LEN: usize = 8;
+/// Bytes a datagram can carry.
+const PAYLOAD_MAX: usize = ipv4::PAYLOAD_MAX - HEADER_LEN;
+/// Datagrams a socket holds before dropping more.
+const QUEUE_DATAGRAMS: usize = 64;
+/// Ports given to sockets that did not pick one, as on Linux.
+const EPHEMERAL_PORTS: RangeInclusive<u16> = 32768..=60999;
+
+lazy_static! {
+    /// Sockets by their local port.
+    static ref UDP_PORTS: SpinMutexIrqSave<BTreeMap<u16, Weak<UdpSocket>>> =
+        SpinMutexIrqSave::new(BTreeMap::new());
+}
+
+pub struct UdpSocket {
+    inner: SpinMutexIrqSave<UdpSocketInner>,
+    /// `O_NONBLOCK`
+    nonblock: AtomicBool,
 }
 
-impl UDP {
-    pub fn new(target: IPv4, sport: u16, dport: u16) -> Self {
-        let index = add_socket(target, sport, dport).expect("can't add socket");
+struct UdpSocketInner {
+    /// none until bound
+    port: Option<u16>,
+    /// datagrams received and who sent them
+    datagrams: VecDeque<(SocketAddrV4, Vec<u8>)>,
+    /// waiting for a datagram
+    readers: WaitQueue,
+    pollers: PollWakers,
+}
 
+impl UdpSocket {
+    pub fn new() -> Self {
         Self {
-            target,
-            sport,
-            dport,
-            socket_index: index,
+            inner: SpinMutexIrqSave::new(UdpSocketInner {
+                port: None,
+                datagrams: VecDeque::new(),
+                readers: WaitQueue::new(),
+                pollers: PollWakers::new(),
+            }),
+            nonblock: AtomicBool::new(false),
         }
     }
-}
-
-impl File for UDP {
-    fn readable(&self) -> bool {
+    /// Take the local address `addr`, any free port if its port is 0.
+    /// Fails if the socket is bound already, the port is taken or the
+    /// address is not ours.
+    pub fn bind(socket: &Arc<Self>, addr: SocketAddrV4) -> bool {
+        if !addr.ip().is_unspecified() && !ipv4::is_ours(*addr.ip()) {
+            return false;
+        }
+        let mut inner = socket.inner.exclusive_access();
+        if inner.port.is_some() {
+            return false;
+        }
+        let mut ports = UDP_PORTS.exclusive_access();
+        // ports of the sockets that were closed are free again
+        ports.retain(|_, socket| socket.strong_count() > 0);
+        let port = match addr.port() {
+            0 => match EPHEMERAL_PORTS.find(|port| !ports.contains_key(port)) {
+                Some(port) => port,
+                None => return false,
+            },
+            port if ports.contains_key(&port) => return false,
+            port => port,
+        };
+        ports.insert(port, Arc::downgrade(socket));
+        inner.port = Some(port);
         true
     }
-
-    fn writable(&self) -> bool {
-        true
+    /// Send `payload` as one datagram to `dst`, binding the socket to a
+    /// port first if it has none. Fails if it is too long or the device
+    /// dropped it.
+    pub fn send_to(socket: &Arc<Self>, payload: &[u8], dst: SocketAddrV4) -> bool {
+        if payload.len() > PAYLOAD_MAX {
+            return false;
+        }
+        let bound = socket.inner.exclusive_access().port;
+        let port = match bound {
+            Some(port) => port,
+            None => {
+                if !Self::bind(socket, SocketAddrV4::new(Ipv4Addr::UNSPECIFIED, 0)) {
+                    return false;
+                }
+                socket.inner.exclusive_access().port.unwrap()
+            }
+        };
+        let len = HEADER_LEN + payload.len();
+        let mut datagram = Vec::with_capacity(len);
+        datagram.extend_from_slice(&port.to_be_bytes());
+        datagram.extend_from_slice(&dst.port().to_be_bytes());
+        datagram.extend_from_slice(&(len as u16).to_be_bytes());
+        datagram.extend_from_slice(&[0, 0]);
+        datagram.extend_from_slice(payload);
+        let sum =
+            ipv4::pseudo_header_sum(ipv4::source_for(*dst.ip()), *dst.ip(), PROTOCOL_UDP, len);
+        // a checksum of 0 means none, so it is sent as all ones instead
+        let checksum = match ipv4::checksum(sum, &datagram) {
+            0 => 0xffff,
+            checksum => checksum,
+        };
+        datagram[6..8].copy_from_slice(&checksum.to_be_bytes());
+        ipv4::send(*dst.ip(), PROTOCOL_UDP, &datagram)
     }
-
-    fn read(&self, mut buf: crate::mm::UserBuffer) -> usize {
+    /// Take the next datagram and who sent it, waiting for one unless not
+    /// `wait`. Fails if a signal comes, or with `EAGAIN` if it would wait.
+    pub fn receive_from(&self, wait: bool) -> Result<(SocketAddrV4, Vec<u8>), isize> {
         loop {
-            if let Some(data) = pop_data(self.socket_index) {
-                let data_len = data.len();
-                let mut left = 0;
-                for i in 0..buf.buffers.len() {
-                    let buffer_i_len = buf.buffers[i].len().min(data_len - left);
-
-                    buf.buffers[i][..buffer_i_len]
-                        .copy_from_slice(&data[left..(left + buffer_i_len)]);
-
-                    left += buffer_i_len;
-                    if left == data_len {
-                        break;
-                    }
+            let mut inner = self.inner.exclusive_access();
+            if let Some(datagram) = inner.datagrams.pop_front() {
+                return Ok(datagram);
+            }
+            if !wait {
+                return Err(EAGAIN);
+            }
+            match inner.readers.wait_no_sched_interruptible() {
+                Some(task_cx_ptr) => {
+                    drop(inner);
+                    schedule(task_cx_ptr);
                 }
-                return left;
-            } else {
-                net_interrupt_handler();
+                None => return Err(-1),
             }
         }
     }
-
-    fn write(&self, buf: crate::mm::UserBuffer) -> usize {
-        let lose_net_stack = LOSE_NET_STACK.0.exclusive_access();
-
-        let mut data = vec![0u8; buf.len()];
-
-        let mut left = 0;
-        for i in 0..buf.buffers.len() {
-            data[left..(left + buf.buffers[i].len())].copy_from_slice(buf.buffers[i]);
-            left += buf.buffers[i].len();
+    /// Take the next datagram into `buf`, dropping what does not fit.
+    fn receive(&self, buf: UserBuffer, wait: bool) -> Option<usize> {
+        let (_, data) = match self.receive_from(wait) {
+            Ok(datagram) => datagram,
+            Err(EAGAIN) => return None,
+            Err(_) => return Some(0),
+        };
+        let mut read = 0;
+        for (byte_ref, byte) in buf.into_iter().zip(data) {
+            unsafe { *byte_ref = byte };
+            read += 1;
         }
+        Some(read)
+    }
+}
 
-        let len = data.len();
-
-        let udp_packet = UDPPacket::new(
-            lose_net_stack.ip,
-            lose_net_stack.mac,
-            self.sport,
-            self.target,
-            MacAddress::new([0xff, 0xff, 0xff, 0xff, 0xff, 0xff]),
-            self.dport,
-            len,
-            data.as_ref(),
-        );
-        NET_DEVICE.transmit(&udp_packet.build_data());
-        len
+/// Hand an incoming datagram to the socket on its port, if any.
+pub fn handle(src: Ipv4Addr, dst: Ipv4Addr, segment: &[u8]) {
+    if segment.len() < HEADER_LEN {
+        return;
+    }
+    let src_port = u16::from_be_bytes([segment[0], segment[1]]);
+    let dst_port = u16::from_be_bytes([segment[2], segment[3]]);
+    let len = u16::from_be_bytes([segment[4], segment[5]]) as usize;
+    let checksum = u16::from_be_bytes([segment[6], segment[7]]);
+    if len < HEADER_LEN || len > segment.len() {
+        return;
     }
+    let segment = &segment[..len];
+    if checksum != 0
+        && ipv4::checksum(
+            ipv4::pseudo_header_sum(src, dst, PROTOCOL_UDP, len),
+            segment,
+        ) != 0
+    {
+        return;
+    }
+    let socket = match UDP_PORTS
+        .exclusive_access()
+        .get(&dst_port)
+        .and_then(Weak::upgrade)
+    {
+        Some(socket) => socket,
+        None => return,
+    };
+    let mut inner = socket.inner.exclusive_access();
+    if inner.datagrams.len() == QUEUE_DATAGRAMS {
+        return;
+    }
+    inner.datagrams.push_back((
+        SocketAddrV4::new(src, src_port),
+        segment[HEADER_LEN..].to_vec(),
+    ));
+    inner.readers.wake_one();
+    inner.pollers.wake_all();
 }
 
-impl Drop for UDP {
-    fn drop(&mut self) {
-        remove_socket(self.socket_index)
+impl File for UdpSocket {
+    fn readable(&self) -> bool {
+        true
+    }
+    fn writable(&self) -> bool {
+        true
+    }
+    /// Read one datagram, waiting for it if none has come.
+    fn read(&self, buf: UserBuffer) -> usize {
+        self.receive(buf, true).unwrap()
+    }
+    /// Nowhere to write to without `sys_sendto`.
+    fn write(&self, _buf: UserBuffer) -> usize {
+        0
+    }
+    fn try_read(&self, buf: UserBuffer) -> Option<usize> {
+        self.receive(buf, false)
+    }
+    fn nonblocking(&self) -> bool {
+        self.nonblock.load(Ordering::Relaxed)
+    }
+    fn set_nonblocking(&self, nonblocking: bool) {
+        self.nonblock.store(nonblocking, Ordering::Relaxed);
+    }
+    fn stat(&self) -> Option<Stat> {
+        let size: usize = self
+            .inner
+            .exclusive_access()
+            .datagrams
+            .iter()
+            .map(|(_, data)| data.len())
+            .sum();
+        Some(Stat::new(0, StatMode::SOCK, 1, size as u64))
+    }
+    fn poll(&self) -> PollFlags {
+        // sending never waits
+        if self.inner.exclusive_access().datagrams.is_empty() {
+            PollFlags::OUT
+        } else {
+            PollFlags::IN | PollFlags::OUT
+        }
+    }
+    fn register_waker(&self, waker: &Arc<PollWaker>) {
+        self.inner.exclusive_access().pollers.register(waker);
     }
 }
@@ -0,0 +1,183 @@
+//! Hardware performance counters. Every thread is charged the cycles and
+//! instructions retired while it runs, the same way `task::accounting`
+//! charges its time, so the counts of a process add up across context
+//! switches and harts. A perf event counts those of one process while it
+//! is enabled, and reads as the count so far.
+
+use crate::fs::File;
+use crate::mm::UserBuffer;
+use crate::sync::SpinMutexIrqSave;
+use crate::task::{account_perf, current_process, current_task, ProcessControlBlock};
+use alloc::sync::{Arc, Weak};
+use core::ops::{Add, AddAssign, Sub};
+use riscv::register::{cycle, instret};
+
+/// Counter readings, or what they went up by.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct PerfCounts {
+    pub cycles: u64,
+    pub instructions: u64,
+}
+
+impl Add for PerfCounts {
+    type Output = Self;
+    fn add(self, other: Self) -> Self {
+        Self {
+            cycles: self.cycles + other.cycles,
+            instructions: self.instructions + other.instructions,
+        }
+    }
+}
+
+impl AddAssign for PerfCounts {
+    fn add_assign(&mut self, other: Self) {
+        *self = *self + other;
+    }
+}
+
+impl Sub for PerfCounts {
+    type Output = Self;
+    fn sub(self, other: Self) -> Self {
+        Self {
+            cycles: self.cycles.wrapping_sub(other.cycles),
+            instructions: self.instructions.wrapping_sub(other.instructions),
+        }
+    }
+}
+
+/// Read the counters of this hart.
+pub fn read_counters() -> PerfCounts {
+    PerfCounts {
+        cycles: cycle::read() as u64,
+        instructions: instret::read() as u64,
+    }
+}
+
+/// What an event counts, numbered as the generic hardware events of Linux.
+#[derive(Debug, Clone, Copy)]
+pub enum PerfEventKind {
+    Cycles,
+    Instructions,
+}
+
+pub const PERF_COUNT_HW_CPU_CYCLES: usize = 0;
+pub const PERF_COUNT_HW_INSTRUCTIONS: usize = 1;
+
+impl PerfEventKind {
+    pub fn from_config(config: usize) -> Option<Self> {
+        match config {
+            PERF_COUNT_HW_CPU_CYCLES => Some(Self::Cycles),
+            PERF_COUNT_HW_INSTRUCTIONS => Some(Self::Instructions),
+            _ => None,
+        }
+    }
+    fn select(self, counts: PerfCounts) -> u64 {
+        match self {
+            Self::Cycles => counts.cycles,
+            Self::Instructions => counts.instructions,
+        }
+    }
+}
+
+/// ioctl requests on a perf event, as on Linux.
+pub const PERF_EVENT_IOC_ENABLE: u32 = 0x2400;
+pub const PERF_EVENT_IOC_DISABLE: u32 = 0x2401;
+pub const PERF_EVENT_IOC_RESET: u32 = 0x2403;
+
+/// A counter of one process opened by `sys_perf_event_open`. It starts
+/// out disabled.
+pub struct PerfEvent {
+    process: Weak<ProcessControlBlock>,
+    kind: PerfEventKind,
+    inner: SpinMutexIrqSave<PerfEventInner>,
+}
+
+struct PerfEventInner {
+    /// counted in the times it was enabled before
+    count: u64,
+    /// the total of the process when it was enabled, while it is
+    enabled_at: Option<u64>,
+}
+
+impl PerfEvent {
+    pub fn new(process: &Arc<ProcessControlBlock>, kind: PerfEventKind) -> Self {
+        Self {
+            process: Arc::downgrade(process),
+            kind,
+            inner: SpinMutexIrqSave::new(PerfEventInner {
+                count: 0,
+                enabled_at: None,
+            }),
+        }
+    }
+
+    /// What the threads of the process counted so far. Threads running on
+    /// other harts are only charged up to when they last switched out.
+    fn total(&self) -> Option<u64> {
+        let process = self.process.upgrade()?;
+        if Arc::ptr_eq(&process, &current_process()) {
+            account_perf(&mut current_task().unwrap().inner_exclusive_access());
+        }
+        let counts = process.inner_exclusive_access().perf_counts();
+        Some(self.kind.select(counts))
+    }
+
+    /// The count so far. Once the process is reaped only what was counted
+    /// before the event was last disabled is left.
+    pub fn value(&self) -> u64 {
+        let total = self.total();
+        let inner = self.inner.exclusive_access();
+        match (inner.enabled_at, total) {
+            (Some(base), Some(total)) => inner.count + (total - base),
+            _ => inner.count,
+        }
+    }
+}
+
+impl File for PerfEvent {
+    fn readable(&self) -> bool {
+        true
+    }
+    fn writable(&self) -> bool {
+        false
+    }
+    /// The count as a little endian u64.
+    fn read(&self, user_buf: UserBuffer) -> usize {
+        let value = self.value().to_le_bytes();
+        let mut len = 0;
+        for (byte, src) in user_buf.into_iter().zip(value.iter()) {
+            unsafe {
+                *byte = *src;
+            }
+            len += 1;
+        }
+        len
+    }
+    fn write(&self, _user_buf: UserBuffer) -> usize {
+        0
+    }
+    fn ioctl(&self, cmd: u32, _arg: usize) -> isize {
+        let total = self.total();
+        let mut inner = self.inner.exclusive_access();
+        match cmd {
+            PERF_EVENT_IOC_ENABLE => {
+                if inner.enabled_at.is_none() {
+                    inner.enabled_at = total;
+                }
+            }
+            PERF_EVENT_IOC_DISABLE => {
+                if let (Some(base), Some(total)) = (inner.enabled_at.take(), total) {
+                    inner.count += total - base;
+                }
+            }
+            PERF_EVENT_IOC_RESET => {
+                inner.count = 0;
+                if inner.enabled_at.is_some() {
+                    inner.enabled_at = total;
+                }
+            }
+            _ => return -1,
+        }
+        0
+    }
+}
@@ -0,0 +1,107 @@
+//! Probes that time sections of kernel code. `statistic_time!` wraps an
+//! expression in a named probe, which keeps how often it ran and its
+//! total, shortest and longest time in timer ticks. `dump_profile` prints
+//! every probe that was hit, the most costly first, at shutdown. Probes
+//! cost nothing unless `PROFILE` is set in `config`.
+
+use crate::config::PROFILE;
+use crate::sync::SpinMutexIrqSave;
+use crate::timer::get_time;
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use lazy_static::*;
+
+/// Time `$body` under the probe `$name` and evaluate to its value.
+#[macro_export]
+macro_rules! statistic_time {
+    ($name: literal, $body: expr) => {{
+        static PROBE: $crate::profile::Probe = $crate::profile::Probe::new($name);
+        let _timing = PROBE.start();
+        $body
+    }};
+}
+
+pub struct Probe {
+    name: &'static str,
+    registered: AtomicBool,
+    count: AtomicUsize,
+    total: AtomicUsize,
+    min: AtomicUsize,
+    max: AtomicUsize,
+}
+
+lazy_static! {
+    /// Every probe hit so far.
+    static ref PROBES: SpinMutexIrqSave<Vec<&'static Probe>> =
+        SpinMutexIrqSave::new(Vec::new());
+}
+
+impl Probe {
+    pub const fn new(name: &'static str) -> Self {
+        Self {
+            name,
+            registered: AtomicBool::new(false),
+            count: AtomicUsize::new(0),
+            total: AtomicUsize::new(0),
+            min: AtomicUsize::new(usize::MAX),
+            max: AtomicUsize::new(0),
+        }
+    }
+    /// Start timing, the time is recorded when the result is dropped.
+    pub fn start(&'static self) -> Option<Timing> {
+        PROFILE.then(|| Timing {
+            probe: self,
+            start: get_time(),
+        })
+    }
+    fn record(&'static self, ticks: usize) {
+        if !self.registered.swap(true, Ordering::Relaxed) {
+            PROBES.exclusive_access().push(self);
+        }
+        self.count.fetch_add(1, Ordering::Relaxed);
+        self.total.fetch_add(ticks, Ordering::Relaxed);
+        self.min.fetch_min(ticks, Ordering::Relaxed);
+        self.max.fetch_max(ticks, Ordering::Relaxed);
+    }
+}
+
+/// A probe being timed.
+pub struct Timing {
+    probe: &'static Probe,
+    start: usize,
+}
+
+impl Drop for Timing {
+    fn drop(&mut self) {
+        self.probe.record(get_time() - self.start);
+    }
+}
+
+/// Print the probes hit so far, those that took the most time in total
+/// first.
+pub fn dump_profile() {
+    if !PROFILE {
+        return;
+    }
+    let mut probes = PROBES.exclusive_access().clone();
+    probes.sort_unstable_by_key(|probe| core::cmp::Reverse(probe.total.load(Ordering::Relaxed)));
+    println!("---START PROFILE (timer ticks)---");
+    println!(
+        "{:<24} {:>10} {:>14} {:>10} {:>10} {:>10}",
+        "probe", "count", "total", "avg", "min", "max"
+    );
+    for probe in probes {
+        let count = probe.count.load(Ordering::Relaxed);
+        let total = probe.total.load(Ordering::Relaxed);
+        println!(
+            "{:<24} {:>10} {:>14} {:>10} {:>10} {:>10}",
+            probe.name,
+            count,
+            total,
+            total / count.max(1),
+            probe.min.load(Ordering::Relaxed),
+            probe.max.load(Ordering::Relaxed)
+        );
+    }
+    println!("---END PROFILE---");
+}
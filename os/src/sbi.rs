@@ -1,15 +1,205 @@
-/// use sbi call to set timer
-pub fn set_timer(timer: usize) {
-    sbi_rt::set_timer(timer as _);
+//! SBI calls through the v0.2+ extensions: BASE, TIME, IPI, RFENCE, HSM
+//! and SRST, each with the `SbiRet` error turned into a `Result`.
+
+use core::arch::asm;
+
+const EID_BASE: usize = 0x10;
+const EID_TIME: usize = 0x5449_4d45;
+const EID_IPI: usize = 0x73_5049;
+const EID_RFENCE: usize = 0x5246_4e43;
+const EID_HSM: usize = 0x48_534d;
+const EID_SRST: usize = 0x5352_5354;
+
+/// What an SBI call returns in `a0` and `a1`.
+struct SbiRet {
+    error: isize,
+    value: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SbiError {
+    Failed,
+    NotSupported,
+    InvalidParam,
+    Denied,
+    InvalidAddress,
+    AlreadyAvailable,
+    AlreadyStarted,
+    AlreadyStopped,
+    NoShmem,
+    Unknown(isize),
+}
+
+impl SbiRet {
+    fn into_result(self) -> Result<usize, SbiError> {
+        let error = match self.error {
+            0 => return Ok(self.value),
+            -1 => SbiError::Failed,
+            -2 => SbiError::NotSupported,
+            -3 => SbiError::InvalidParam,
+            -4 => SbiError::Denied,
+            -5 => SbiError::InvalidAddress,
+            -6 => SbiError::AlreadyAvailable,
+            -7 => SbiError::AlreadyStarted,
+            -8 => SbiError::AlreadyStopped,
+            -9 => SbiError::NoShmem,
+            code => SbiError::Unknown(code),
+        };
+        Err(error)
+    }
+}
+
+#[inline(always)]
+fn sbi_call(eid: usize, fid: usize, args: [usize; 5]) -> SbiRet {
+    let (error, value);
+    unsafe {
+        asm!(
+            "ecall",
+            inlateout("a0") args[0] => error,
+            inlateout("a1") args[1] => value,
+            in("a2") args[2],
+            in("a3") args[3],
+            in("a4") args[4],
+            in("a6") fid,
+            in("a7") eid,
+        );
+    }
+    SbiRet { error, value }
+}
+
+/// Harts `base + i` for every bit `i` set in `mask`.
+#[derive(Clone, Copy)]
+pub struct HartMask {
+    mask: usize,
+    base: usize,
+}
+
+impl HartMask {
+    pub fn from_mask(mask: usize) -> Self {
+        Self { mask, base: 0 }
+    }
+    #[allow(unused)]
+    pub fn all() -> Self {
+        Self {
+            mask: 0,
+            base: usize::MAX,
+        }
+    }
+}
+
+/// The SBI specification version as (major, minor).
+pub fn spec_version() -> (usize, usize) {
+    let version = sbi_call(EID_BASE, 0, [0; 5]).value;
+    (version >> 24 & 0x7f, version & 0xff_ffff)
+}
+
+pub fn probe_extension(eid: usize) -> bool {
+    sbi_call(EID_BASE, 3, [eid, 0, 0, 0, 0]).value != 0
+}
+
+/// Extensions the kernel cannot go without, by name.
+pub fn missing_extensions() -> impl Iterator<Item = &'static str> {
+    [
+        (EID_TIME, "TIME"),
+        (EID_IPI, "IPI"),
+        (EID_RFENCE, "RFENCE"),
+        (EID_HSM, "HSM"),
+        (EID_SRST, "SRST"),
+    ]
+    .into_iter()
+    .filter(|(eid, _)| !probe_extension(*eid))
+    .map(|(_, name)| name)
+}
+
+/// Raise the timer interrupt once `time` reaches `stime_value`.
+pub fn set_timer(stime_value: u64) -> Result<(), SbiError> {
+    sbi_call(EID_TIME, 0, [stime_value as usize, 0, 0, 0, 0])
+        .into_result()
+        .map(|_| ())
+}
+
+/// Raise a supervisor software interrupt on the harts in `harts`.
+pub fn send_ipi(harts: HartMask) -> Result<(), SbiError> {
+    sbi_call(EID_IPI, 0, [harts.mask, harts.base, 0, 0, 0])
+        .into_result()
+        .map(|_| ())
+}
+
+/// `fence.i` on `harts`.
+pub fn remote_fence_i(harts: HartMask) -> Result<(), SbiError> {
+    sbi_call(EID_RFENCE, 0, [harts.mask, harts.base, 0, 0, 0])
+        .into_result()
+        .map(|_| ())
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HartState {
+    Started,
+    Stopped,
+    StartPending,
+    StopPending,
+    Suspended,
+    SuspendPending,
+    ResumePending,
+}
+
+/// Start `hart_id` at `start_addr` in S-mode with `a0` = its hartid and
+/// `a1` = `opaque`.
+pub fn hart_start(hart_id: usize, start_addr: usize, opaque: usize) -> Result<(), SbiError> {
+    sbi_call(EID_HSM, 0, [hart_id, start_addr, opaque, 0, 0])
+        .into_result()
+        .map(|_| ())
+}
+
+/// Hand this hart back to the SBI, only returns if that failed.
+pub fn hart_stop() -> SbiError {
+    sbi_call(EID_HSM, 1, [0; 5]).into_result().unwrap_err()
+}
+
+/// Fails with `InvalidParam` for harts that do not exist.
+pub fn hart_status(hart_id: usize) -> Result<HartState, SbiError> {
+    let state = match sbi_call(EID_HSM, 2, [hart_id, 0, 0, 0, 0]).into_result()? {
+        0 => HartState::Started,
+        1 => HartState::Stopped,
+        2 => HartState::StartPending,
+        3 => HartState::StopPending,
+        4 => HartState::Suspended,
+        5 => HartState::SuspendPending,
+        6 => HartState::ResumePending,
+        _ => return Err(SbiError::Failed),
+    };
+    Ok(state)
+}
+
+#[derive(Clone, Copy)]
+pub enum ResetType {
+    Shutdown = 0,
+    #[allow(unused)]
+    ColdReboot = 1,
+    #[allow(unused)]
+    WarmReboot = 2,
+}
+
+#[derive(Clone, Copy)]
+pub enum ResetReason {
+    NoReason = 0,
+    SystemFailure = 1,
+}
+
+/// Only returns if the reset could not be done.
+pub fn system_reset(reset_type: ResetType, reason: ResetReason) -> SbiError {
+    sbi_call(EID_SRST, 0, [reset_type as usize, reason as usize, 0, 0, 0])
+        .into_result()
+        .unwrap_err()
 }
 
 /// use sbi call to shutdown the kernel
 pub fn shutdown(failure: bool) -> ! {
-    use sbi_rt::{system_reset, NoReason, Shutdown, SystemFailure};
-    if !failure {
-        system_reset(Shutdown, NoReason);
+    let reason = if failure {
+        ResetReason::SystemFailure
     } else {
-        system_reset(Shutdown, SystemFailure);
-    }
+        ResetReason::NoReason
+    };
+    system_reset(ResetType::Shutdown, reason);
     unreachable!()
 }
@@ -1,26 +1,30 @@
-use crate::sync::{Mutex, UPIntrFreeCell};
+use crate::sync::{Mutex, SpinMutexIrqSave};
 use crate::task::{
-    block_current_and_run_next, block_current_task, current_task, wakeup_task, TaskContext,
-    TaskControlBlock,
+    block_current_and_run_next, block_current_task, current_task, wakeup_task, KernelObject,
+    ObjectKind, TaskContext, TaskControlBlock,
 };
 use alloc::{collections::VecDeque, sync::Arc};
 
 pub struct Condvar {
-    pub inner: UPIntrFreeCell<CondvarInner>,
+    pub inner: SpinMutexIrqSave<CondvarInner>,
 }
 
 pub struct CondvarInner {
     pub wait_queue: VecDeque<Arc<TaskControlBlock>>,
 }
 
+impl KernelObject for Condvar {
+    fn kind(&self) -> ObjectKind {
+        ObjectKind::Condvar
+    }
+}
+
 impl Condvar {
     pub fn new() -> Self {
         Self {
-            inner: unsafe {
-                UPIntrFreeCell::new(CondvarInner {
-                    wait_queue: VecDeque::new(),
-                })
-            },
+            inner: SpinMutexIrqSave::new(CondvarInner {
+                wait_queue: VecDeque::new(),
+            }),
         }
     }
 
@@ -31,6 +35,14 @@ impl Condvar {
         }
     }
 
+    /// Wake up every waiting task.
+    pub fn broadcast(&self) {
+        let mut inner = self.inner.exclusive_access();
+        for task in inner.wait_queue.drain(..) {
+            wakeup_task(task);
+        }
+    }
+
     /*
     pub fn wait(&self) {
         let mut inner = self.inner.exclusive_access();
@@ -47,12 +59,16 @@ impl Condvar {
         block_current_task()
     }
 
-    pub fn wait_with_mutex(&self, mutex: Arc<dyn Mutex>) {
-        mutex.unlock();
+    /// Returns false without waiting if `mutex` is not held.
+    pub fn wait_with_mutex(&self, mutex: Arc<dyn Mutex>) -> bool {
+        if !mutex.unlock() {
+            return false;
+        }
         self.inner.exclusive_session(|inner| {
             inner.wait_queue.push_back(current_task().unwrap());
         });
         block_current_and_run_next();
         mutex.lock();
+        true
     }
 }
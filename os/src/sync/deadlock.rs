@@ -0,0 +1,116 @@
+//! Banker's algorithm style deadlock detection over the mutexes or the
+//! semaphores of one process.
+
+use alloc::vec;
+use alloc::vec::Vec;
+
+/// Returned instead of blocking when a request could deadlock.
+pub const DEADLOCK: isize = -0xdead;
+
+#[derive(Default)]
+pub struct DeadlockDetector {
+    /// free units of each resource
+    available: Vec<usize>,
+    /// units of each resource held by each thread
+    allocation: Vec<Vec<usize>>,
+    /// units of each resource each thread is waiting for
+    need: Vec<Vec<usize>>,
+}
+
+impl DeadlockDetector {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn grow(&mut self, tid: usize, res: usize) {
+        if self.available.len() <= res {
+            self.available.resize(res + 1, 0);
+        }
+        let len = self.available.len();
+        for rows in [&mut self.allocation, &mut self.need] {
+            if rows.len() <= tid {
+                rows.resize(tid + 1, Vec::new());
+            }
+            for row in rows.iter_mut() {
+                row.resize(len, 0);
+            }
+        }
+    }
+
+    /// Resource `res` was created with `count` units, replacing whatever
+    /// had the same id before.
+    pub fn add_resource(&mut self, res: usize, count: usize) {
+        self.grow(0, res);
+        self.available[res] = count;
+        for row in self.allocation.iter_mut().chain(self.need.iter_mut()) {
+            row[res] = 0;
+        }
+    }
+
+    /// Thread `tid` asks for a unit of `res`. With `detect`, a request
+    /// that may leave the threads deadlocked is refused and forgotten.
+    pub fn request(&mut self, tid: usize, res: usize, detect: bool) -> bool {
+        self.grow(tid, res);
+        self.need[tid][res] += 1;
+        if detect && !self.is_safe() {
+            self.need[tid][res] -= 1;
+            return false;
+        }
+        true
+    }
+
+    /// Thread `tid` got the unit of `res` it asked for.
+    pub fn acquire(&mut self, tid: usize, res: usize) {
+        self.grow(tid, res);
+        self.need[tid][res] = self.need[tid][res].saturating_sub(1);
+        self.allocation[tid][res] += 1;
+        self.available[res] = self.available[res].saturating_sub(1);
+    }
+
+    /// Thread `tid` gave back a unit of `res`, not necessarily one it got.
+    pub fn release(&mut self, tid: usize, res: usize) {
+        self.grow(tid, res);
+        self.allocation[tid][res] = self.allocation[tid][res].saturating_sub(1);
+        self.available[res] += 1;
+    }
+
+    /// Thread `tid` is gone, and its tid may go to a new thread: what it
+    /// waited for is forgotten and what it held counts as free again.
+    pub fn remove_thread(&mut self, tid: usize) {
+        if let Some(row) = self.allocation.get_mut(tid) {
+            for (available, units) in self.available.iter_mut().zip(row.iter_mut()) {
+                *available += core::mem::take(units);
+            }
+        }
+        if let Some(row) = self.need.get_mut(tid) {
+            row.fill(0);
+        }
+    }
+
+    pub fn holds(&self, tid: usize, res: usize) -> bool {
+        self.allocation
+            .get(tid)
+            .and_then(|row| row.get(res))
+            .map_or(false, |units| *units > 0)
+    }
+
+    /// Whether the threads can all finish in some order, each one giving
+    /// back what it holds once its needs are met.
+    fn is_safe(&self) -> bool {
+        let mut work = self.available.clone();
+        let mut finish = vec![false; self.need.len()];
+        while let Some(tid) = (0..self.need.len()).find(|&tid| {
+            !finish[tid]
+                && self.need[tid]
+                    .iter()
+                    .zip(work.iter())
+                    .all(|(need, work)| need <= work)
+        }) {
+            finish[tid] = true;
+            for (work, allocation) in work.iter_mut().zip(self.allocation[tid].iter()) {
+                *work += allocation;
+            }
+        }
+        finish.iter().all(|finished| *finished)
+    }
+}
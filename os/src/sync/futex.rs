@@ -0,0 +1,109 @@
+//! Futexes: user words a thread can sleep on until another one wakes it,
+//! so user locks only enter the kernel when contended. Waiters are keyed
+//! by the physical address of the word, so a word shared between
+//! processes is one futex.
+
+use crate::mm::UserBuffer;
+use crate::sync::SpinMutexIrqSave;
+use crate::task::{block_current_task, current_task, schedule, wakeup_task, TaskControlBlock};
+use crate::timer::add_futex_timer;
+use alloc::collections::VecDeque;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use lazy_static::*;
+
+pub const FUTEX_WAIT: usize = 0;
+pub const FUTEX_WAKE: usize = 1;
+
+/// Returned by `futex_wait` if the timeout passed before a wake.
+pub const ETIMEDOUT: isize = -2;
+
+const FUTEX_BUCKETS: usize = 64;
+
+pub struct FutexWaiter {
+    key: usize,
+    task: Arc<TaskControlBlock>,
+    /// set by a wake, a timeout leaves it clear
+    woken: AtomicBool,
+    /// keeps the word resident, so its key stays valid
+    _word: UserBuffer,
+}
+
+type FutexQueue = VecDeque<Arc<FutexWaiter>>;
+
+lazy_static! {
+    /// Waiters hashed by key, several futexes may share a queue.
+    static ref FUTEX_QUEUES: Vec<SpinMutexIrqSave<FutexQueue>> = (0..FUTEX_BUCKETS)
+        .map(|_| SpinMutexIrqSave::new(VecDeque::new()))
+        .collect();
+}
+
+fn queue_of(key: usize) -> &'static SpinMutexIrqSave<FutexQueue> {
+    &FUTEX_QUEUES[key / core::mem::size_of::<u32>() % FUTEX_BUCKETS]
+}
+
+/// The physical address of the aligned user word in `word`, by which
+/// the kernel can access it too.
+fn key_of(word: &UserBuffer) -> usize {
+    word.buffers[0].as_ptr() as usize
+}
+
+/// Sleep while the word in `word` holds `val`, until woken or until
+/// `expire_ms` if given. Returns 0 when woken, -1 at once if the word
+/// held something else, or `ETIMEDOUT`.
+pub fn futex_wait(word: UserBuffer, val: u32, expire_ms: Option<usize>) -> isize {
+    let key = key_of(&word);
+    let value = unsafe { &*(key as *const AtomicU32) };
+    let waiter = Arc::new(FutexWaiter {
+        key,
+        task: current_task().unwrap(),
+        woken: AtomicBool::new(false),
+        _word: word,
+    });
+    // a wake cannot come in between the check and going to sleep
+    let mut queue = queue_of(key).exclusive_access();
+    if value.load(Ordering::SeqCst) != val {
+        return -1;
+    }
+    queue.push_back(waiter.clone());
+    if let Some(expire_ms) = expire_ms {
+        add_futex_timer(expire_ms, Arc::downgrade(&waiter));
+    }
+    let task_cx_ptr = block_current_task();
+    drop(queue);
+    schedule(task_cx_ptr);
+    if waiter.woken.load(Ordering::Acquire) {
+        0
+    } else {
+        ETIMEDOUT
+    }
+}
+
+/// Wake up to `count` waiters on the word in `word`, returning how many.
+pub fn futex_wake(word: &UserBuffer, count: usize) -> isize {
+    let key = key_of(word);
+    let mut woken = 0;
+    queue_of(key).exclusive_session(|queue| {
+        queue.retain(|waiter| {
+            if waiter.key == key && woken < count {
+                waiter.woken.store(true, Ordering::Release);
+                wakeup_task(waiter.task.clone());
+                woken += 1;
+                return false;
+            }
+            // threads torn down with their process while waiting go too
+            waiter.task.inner_exclusive_access().res.is_some()
+        });
+    });
+    woken as isize
+}
+
+/// Let `waiter` go if it is still waiting when its timeout passes.
+pub fn futex_timeout(waiter: &Arc<FutexWaiter>) {
+    let mut queue = queue_of(waiter.key).exclusive_access();
+    if let Some(idx) = queue.iter().position(|other| Arc::ptr_eq(other, waiter)) {
+        queue.remove(idx);
+        wakeup_task(waiter.task.clone());
+    }
+}
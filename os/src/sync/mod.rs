@@ -1,9 +1,29 @@
 mod condvar;
+mod deadlock;
+mod futex;
+mod msg_queue;
 mod mutex;
+mod poll;
+mod preempt;
+mod rcu;
+mod rwlock;
+mod sem_set;
 mod semaphore;
-mod up;
+mod spin;
+mod wait_queue;
 
 pub use condvar::Condvar;
-pub use mutex::{Mutex, MutexBlocking, MutexSpin};
+pub use deadlock::{DeadlockDetector, DEADLOCK};
+pub use futex::{futex_timeout, futex_wait, futex_wake, FutexWaiter, FUTEX_WAIT, FUTEX_WAKE};
+pub use msg_queue::{Message, MsgQueue};
+pub use mutex::{as_mutex, Mutex, MutexBlocking, MutexSpin};
+pub use poll::{PollWaker, PollWakers};
+pub use preempt::{preempt_disable, preempt_enable, preemptible};
+pub use rcu::{rcu_quiescent, Rcu, RcuReadGuard};
+pub use rwlock::{RwSpinLock, RwSpinLockReadGuard, RwSpinLockWriteGuard};
+pub use sem_set::{SemOp, SemSet, SEM_SET_MAX};
 pub use semaphore::Semaphore;
-pub use up::{UPIntrFreeCell, UPIntrRefMut};
+pub use spin::{
+    dump_lock_stats, SpinMutex, SpinMutexGuard, SpinMutexIrqSave, SpinMutexIrqSaveGuard,
+};
+pub use wait_queue::{interrupt_wait, WaitQueue, Waiters};
@@ -0,0 +1,125 @@
+//! System V message queues: typed messages kept in the order they were
+//! sent, a receiver taking the first one of the type it asks for. A full
+//! queue blocks its senders and an empty one its receivers, either until
+//! the other side makes way or a signal comes.
+
+use super::{SpinMutexIrqSave, WaitQueue};
+use crate::task::{schedule, KernelObject, ObjectKind};
+use alloc::collections::VecDeque;
+use alloc::vec::Vec;
+
+/// Bytes of message text a queue holds at most, `MSGMNB` of Linux.
+const MSG_QUEUE_BYTES: usize = 16384;
+
+pub struct Message {
+    /// positive, chosen by the sender
+    pub mtype: usize,
+    pub text: Vec<u8>,
+}
+
+pub struct MsgQueue {
+    inner: SpinMutexIrqSave<MsgQueueInner>,
+}
+
+struct MsgQueueInner {
+    messages: VecDeque<Message>,
+    /// text bytes of all the queued messages
+    bytes: usize,
+    /// waiting for room to send
+    senders: WaitQueue,
+    /// waiting for a message to receive
+    receivers: WaitQueue,
+}
+
+impl MsgQueueInner {
+    /// Where the message `mtype` selects is: the first one if it is 0,
+    /// the first of that type if it is positive, else the first of the
+    /// lowest type up to its absolute value.
+    fn select(&self, mtype: isize) -> Option<usize> {
+        let mut messages = self.messages.iter().enumerate();
+        match mtype {
+            0 => messages.next(),
+            mtype if mtype > 0 => messages.find(|(_, message)| message.mtype == mtype as usize),
+            mtype => messages
+                .filter(|(_, message)| message.mtype <= mtype.unsigned_abs())
+                .min_by_key(|(_, message)| message.mtype),
+        }
+        .map(|(idx, _)| idx)
+    }
+}
+
+impl MsgQueue {
+    pub fn new() -> Self {
+        Self {
+            inner: SpinMutexIrqSave::new(MsgQueueInner {
+                messages: VecDeque::new(),
+                bytes: 0,
+                senders: WaitQueue::new(),
+                receivers: WaitQueue::new(),
+            }),
+        }
+    }
+
+    /// Queue `message`, waiting for room unless `nowait`. Returns false
+    /// if it was not sent, as there was no room or a signal came.
+    pub fn send(&self, message: Message, nowait: bool) -> bool {
+        if message.text.len() > MSG_QUEUE_BYTES {
+            return false;
+        }
+        loop {
+            let mut inner = self.inner.exclusive_access();
+            if inner.bytes + message.text.len() <= MSG_QUEUE_BYTES {
+                inner.bytes += message.text.len();
+                inner.messages.push_back(message);
+                // each may want another type
+                inner.receivers.wake_all();
+                return true;
+            }
+            if nowait {
+                return false;
+            }
+            match inner.senders.wait_no_sched_interruptible() {
+                Some(task_cx_ptr) => {
+                    drop(inner);
+                    schedule(task_cx_ptr);
+                }
+                None => return false,
+            }
+        }
+    }
+
+    /// Take the message `mtype` selects, see `select`, waiting for one
+    /// unless `nowait`. One longer than `max_len` stays queued. Returns
+    /// none if nothing was taken, as no message would do or a signal
+    /// came.
+    pub fn receive(&self, mtype: isize, max_len: Option<usize>, nowait: bool) -> Option<Message> {
+        loop {
+            let mut inner = self.inner.exclusive_access();
+            if let Some(idx) = inner.select(mtype) {
+                if max_len.map_or(false, |max_len| inner.messages[idx].text.len() > max_len) {
+                    return None;
+                }
+                let message = inner.messages.remove(idx).unwrap();
+                inner.bytes -= message.text.len();
+                inner.senders.wake_all();
+                return Some(message);
+            }
+            if nowait {
+                return None;
+            }
+            match inner.receivers.wait_no_sched_interruptible() {
+                Some(task_cx_ptr) => {
+                    drop(inner);
+                    schedule(task_cx_ptr);
+                }
+                None => return None,
+            }
+        }
+    }
+}
+
+impl KernelObject for MsgQueue {
+    fn kind(&self) -> ObjectKind {
+        ObjectKind::MsgQueue
+    }
+}
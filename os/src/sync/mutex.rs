@@ -1,26 +1,44 @@
-use super::UPIntrFreeCell;
+use super::SpinMutexIrqSave;
 use crate::task::TaskControlBlock;
 use crate::task::{block_current_and_run_next, suspend_current_and_run_next};
-use crate::task::{current_task, wakeup_task};
+use crate::task::{current_task, wakeup_task, KernelObject, ObjectKind};
 use alloc::{collections::VecDeque, sync::Arc};
 
 pub trait Mutex: Sync + Send {
     fn lock(&self);
-    fn unlock(&self);
+    /// Returns false if the mutex was not locked.
+    fn unlock(&self) -> bool;
+}
+
+/// The mutex behind a handle of kind `ObjectKind::Mutex`.
+pub fn as_mutex(object: Arc<dyn KernelObject>) -> Option<Arc<dyn Mutex>> {
+    match object.as_any().downcast::<MutexSpin>() {
+        Ok(mutex) => Some(mutex),
+        Err(object) => object
+            .downcast::<MutexBlocking>()
+            .ok()
+            .map(|mutex| mutex as Arc<dyn Mutex>),
+    }
 }
 
 pub struct MutexSpin {
-    locked: UPIntrFreeCell<bool>,
+    locked: SpinMutexIrqSave<bool>,
 }
 
 impl MutexSpin {
     pub fn new() -> Self {
         Self {
-            locked: unsafe { UPIntrFreeCell::new(false) },
+            locked: SpinMutexIrqSave::new(false),
         }
     }
 }
 
+impl KernelObject for MutexSpin {
+    fn kind(&self) -> ObjectKind {
+        ObjectKind::Mutex
+    }
+}
+
 impl Mutex for MutexSpin {
     fn lock(&self) {
         loop {
@@ -36,14 +54,14 @@ impl Mutex for MutexSpin {
         }
     }
 
-    fn unlock(&self) {
+    fn unlock(&self) -> bool {
         let mut locked = self.locked.exclusive_access();
-        *locked = false;
+        core::mem::replace(&mut *locked, false)
     }
 }
 
 pub struct MutexBlocking {
-    inner: UPIntrFreeCell<MutexBlockingInner>,
+    inner: SpinMutexIrqSave<MutexBlockingInner>,
 }
 
 pub struct MutexBlockingInner {
@@ -54,16 +72,20 @@ pub struct MutexBlockingInner {
 impl MutexBlocking {
     pub fn new() -> Self {
         Self {
-            inner: unsafe {
-                UPIntrFreeCell::new(MutexBlockingInner {
-                    locked: false,
-                    wait_queue: VecDeque::new(),
-                })
-            },
+            inner: SpinMutexIrqSave::new(MutexBlockingInner {
+                locked: false,
+                wait_queue: VecDeque::new(),
+            }),
         }
     }
 }
 
+impl KernelObject for MutexBlocking {
+    fn kind(&self) -> ObjectKind {
+        ObjectKind::Mutex
+    }
+}
+
 impl Mutex for MutexBlocking {
     fn lock(&self) {
         let mut mutex_inner = self.inner.exclusive_access();
@@ -76,13 +98,16 @@ impl Mutex for MutexBlocking {
         }
     }
 
-    fn unlock(&self) {
+    fn unlock(&self) -> bool {
         let mut mutex_inner = self.inner.exclusive_access();
-        assert!(mutex_inner.locked);
+        if !mutex_inner.locked {
+            return false;
+        }
         if let Some(waking_task) = mutex_inner.wait_queue.pop_front() {
             wakeup_task(waking_task);
         } else {
             mutex_inner.locked = false;
         }
+        true
     }
 }
@@ -0,0 +1,84 @@
+//! Waiting for any of several files at once, for `sys_ppoll` and
+//! `sys_pselect6`. The poller registers one waker with each file before
+//! looking at them, and a file wakes the wakers registered with it
+//! whenever it may have become ready, so the poller looks again instead
+//! of missing it.
+
+use super::{SpinMutexIrqSave, WaitQueue};
+use crate::task::schedule;
+use crate::timer::add_timer;
+use alloc::sync::{Arc, Weak};
+use alloc::vec::Vec;
+
+pub struct PollWaker {
+    /// a file woke it since the last `reset`
+    woken: SpinMutexIrqSave<bool>,
+    waiters: WaitQueue,
+}
+
+impl PollWaker {
+    pub fn new() -> Self {
+        Self {
+            woken: SpinMutexIrqSave::new(false),
+            waiters: WaitQueue::new(),
+        }
+    }
+    /// Forget the wakes so far, before looking at the files again.
+    pub fn reset(&self) {
+        *self.woken.exclusive_access() = false;
+    }
+    pub fn wake(&self) {
+        let mut woken = self.woken.exclusive_access();
+        *woken = true;
+        self.waiters.wake_all();
+    }
+    /// Have the timer wake the poller at `expire_ms`, which does not
+    /// count as a wake by a file.
+    pub fn wake_at(&self, expire_ms: usize) {
+        add_timer(expire_ms, self.waiters.clone());
+    }
+    /// Wait unless a file woke the poller since `reset`. Returns false if
+    /// a signal came instead.
+    pub fn wait(&self) -> bool {
+        let woken = self.woken.exclusive_access();
+        if *woken {
+            return true;
+        }
+        match self.waiters.wait_no_sched_interruptible() {
+            Some(task_cx_ptr) => {
+                drop(woken);
+                schedule(task_cx_ptr);
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+/// The wakers registered with a file, kept under the lock guarding its
+/// readiness. Each is woken once and then has to register again.
+pub struct PollWakers(Vec<Weak<PollWaker>>);
+
+impl PollWakers {
+    pub fn new() -> Self {
+        Self(Vec::new())
+    }
+    pub fn register(&mut self, waker: &Arc<PollWaker>) {
+        // wakers of the polls that are over go
+        self.0.retain(|other| other.strong_count() > 0);
+        if !self
+            .0
+            .iter()
+            .any(|other| other.as_ptr() == Arc::as_ptr(waker))
+        {
+            self.0.push(Arc::downgrade(waker));
+        }
+    }
+    pub fn wake_all(&mut self) {
+        for waker in self.0.drain(..) {
+            if let Some(waker) = waker.upgrade() {
+                waker.wake();
+            }
+        }
+    }
+}
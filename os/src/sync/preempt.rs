@@ -0,0 +1,41 @@
+//! Kernel preemption. A timer interrupt taken in the kernel switches to
+//! another task on its way out, unless the hart is in a section counted
+//! by `preempt_disable`, such as while holding a spinlock.
+
+use crate::config::MAX_HARTS;
+use crate::task::hart_id;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use lazy_static::*;
+use riscv::register::sstatus;
+
+lazy_static! {
+    /// Indexed by hart id, sections entered and not yet left.
+    static ref PREEMPT_COUNT: [AtomicUsize; MAX_HARTS] =
+        core::array::from_fn(|_| AtomicUsize::new(0));
+}
+
+/// Keep the current task on this hart until `preempt_enable`.
+pub fn preempt_disable() {
+    // a switch to another hart between reading the hart id and counting
+    // would count on the wrong one
+    let sie = sstatus::read().sie();
+    unsafe {
+        sstatus::clear_sie();
+    }
+    PREEMPT_COUNT[hart_id()].fetch_add(1, Ordering::Relaxed);
+    if sie {
+        unsafe {
+            sstatus::set_sie();
+        }
+    }
+}
+
+pub fn preempt_enable() {
+    let count = PREEMPT_COUNT[hart_id()].fetch_sub(1, Ordering::Relaxed);
+    assert!(count > 0, "preempt_enable without preempt_disable");
+}
+
+/// Whether the kernel may switch away from the current task here.
+pub fn preemptible() -> bool {
+    PREEMPT_COUNT[hart_id()].load(Ordering::Relaxed) == 0
+}
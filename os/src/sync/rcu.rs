@@ -0,0 +1,138 @@
+//! Read-copy-update, for data read far more often than it changes. Readers
+//! take no lock: they pin the current version and read it with
+//! preemption off, so they must not sleep until they let it go. A writer
+//! builds a new version from the current one and publishes it, and the old
+//! one is freed once every hart has been through `schedule` since, or has
+//! been seen outside any read section, as then none can still be reading
+//! it.
+
+use super::{preempt_disable, preempt_enable, SpinMutex, SpinMutexIrqSave};
+use crate::config::MAX_HARTS;
+use crate::task::hart_id;
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+use core::ops::Deref;
+use core::sync::atomic::{AtomicPtr, AtomicUsize, Ordering};
+use lazy_static::*;
+
+lazy_static! {
+    /// Times each hart went through the scheduler, its quiescent state.
+    static ref QUIESCENT: [AtomicUsize; MAX_HARTS] = core::array::from_fn(|_| AtomicUsize::new(0));
+    /// Read sections each hart is in, nested ones included.
+    static ref READERS: [AtomicUsize; MAX_HARTS] = core::array::from_fn(|_| AtomicUsize::new(0));
+    /// Old versions waiting for the readers that may see them.
+    static ref RETIRED: SpinMutexIrqSave<Vec<Retired>> = SpinMutexIrqSave::new(Vec::new());
+}
+
+/// Any old version, only ever dropped.
+trait Version {}
+
+impl<T> Version for T {}
+
+struct Retired {
+    /// `QUIESCENT` when it was retired
+    seen: [usize; MAX_HARTS],
+    value: Box<dyn Version>,
+}
+
+impl Retired {
+    /// No reader can see it any more.
+    fn expired(&self) -> bool {
+        (0..MAX_HARTS).all(|hart| {
+            QUIESCENT[hart].load(Ordering::SeqCst) != self.seen[hart]
+                || READERS[hart].load(Ordering::SeqCst) == 0
+        })
+    }
+}
+
+pub struct Rcu<T: 'static> {
+    current: AtomicPtr<T>,
+    /// writers take turns
+    writer: SpinMutex<()>,
+}
+
+unsafe impl<T> Sync for Rcu<T> {}
+
+pub struct RcuReadGuard<'a, T> {
+    value: &'a T,
+    hart: usize,
+}
+
+impl<T: 'static> Rcu<T> {
+    pub fn new(value: T) -> Self {
+        Self {
+            current: AtomicPtr::new(Box::into_raw(Box::new(value))),
+            writer: SpinMutex::new(()),
+        }
+    }
+
+    /// The current version, which stays valid until the guard is dropped.
+    pub fn read(&self) -> RcuReadGuard<'_, T> {
+        preempt_disable();
+        let hart = hart_id();
+        READERS[hart].fetch_add(1, Ordering::SeqCst);
+        let value = unsafe { &*self.current.load(Ordering::SeqCst) };
+        RcuReadGuard { value, hart }
+    }
+
+    /// Replace the current version with what `f` makes of it. Readers that
+    /// got the old one keep it until they are done.
+    pub fn update(&self, f: impl FnOnce(&T) -> T) {
+        let writer = self.writer.exclusive_access();
+        let old = self.current.load(Ordering::SeqCst);
+        let new = Box::into_raw(Box::new(f(unsafe { &*old })));
+        self.current.store(new, Ordering::SeqCst);
+        drop(writer);
+        retire(unsafe { Box::from_raw(old) });
+    }
+}
+
+impl<T: 'static> Drop for Rcu<T> {
+    fn drop(&mut self) {
+        drop(unsafe { Box::from_raw(*self.current.get_mut()) });
+    }
+}
+
+impl<'a, T> Deref for RcuReadGuard<'a, T> {
+    type Target = T;
+    fn deref(&self) -> &Self::Target {
+        self.value
+    }
+}
+
+impl<'a, T> Drop for RcuReadGuard<'a, T> {
+    fn drop(&mut self) {
+        READERS[self.hart].fetch_sub(1, Ordering::SeqCst);
+        preempt_enable();
+    }
+}
+
+fn retire(value: Box<dyn Version>) {
+    let seen = core::array::from_fn(|hart| QUIESCENT[hart].load(Ordering::SeqCst));
+    RETIRED.exclusive_access().push(Retired { seen, value });
+    reclaim();
+}
+
+/// Free the old versions no reader can see any more, unless another hart
+/// is at it.
+fn reclaim() {
+    let expired: Vec<Retired> = match RETIRED.try_exclusive_access() {
+        Some(retired) if retired.is_empty() => return,
+        Some(mut retired) => {
+            let (expired, kept) = core::mem::take(&mut *retired)
+                .into_iter()
+                .partition(Retired::expired);
+            *retired = kept;
+            expired
+        }
+        None => return,
+    };
+    // dropping them may take other locks
+    drop(expired);
+}
+
+/// This hart went through the scheduler and holds no read section.
+pub fn rcu_quiescent() {
+    QUIESCENT[hart_id()].fetch_add(1, Ordering::SeqCst);
+    reclaim();
+}
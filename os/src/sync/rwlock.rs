@@ -0,0 +1,139 @@
+//! A reader-writer spinlock, for read-mostly data such as the kernel
+//! address space: any number of harts may share it, or one may hold it
+//! exclusively. Interrupts are masked and the task kept on its hart while
+//! it is held, as with `SpinMutexIrqSave`. A writer waiting keeps new
+//! readers out, so a steady stream of them cannot starve it; a hart must
+//! not take the lock again while holding it either way.
+
+use super::spin::{mask_interrupts, unmask_interrupts};
+use super::{preempt_disable, preempt_enable};
+use crate::ipi::handle_tlb_shootdown;
+use crate::task::hart_id;
+use core::cell::UnsafeCell;
+use core::ops::{Deref, DerefMut};
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+/// Held by a writer, otherwise the count of readers.
+const WRITER: usize = 1 << (usize::BITS - 1);
+/// A writer waits for the readers to leave.
+const WRITER_WAITING: usize = 1 << (usize::BITS - 2);
+
+pub struct RwSpinLock<T> {
+    state: AtomicUsize,
+    /// id + 1 of the hart holding it exclusively, 0 if none does
+    writer: AtomicUsize,
+    inner: UnsafeCell<T>,
+}
+
+unsafe impl<T> Sync for RwSpinLock<T> {}
+
+pub struct RwSpinLockReadGuard<'a, T>(&'a RwSpinLock<T>);
+pub struct RwSpinLockWriteGuard<'a, T>(&'a RwSpinLock<T>);
+
+fn enter() {
+    mask_interrupts();
+    preempt_disable();
+}
+
+fn leave() {
+    preempt_enable();
+    unmask_interrupts();
+}
+
+fn wait() {
+    // the holder may be waiting for this hart to fence its TLB
+    handle_tlb_shootdown();
+    core::hint::spin_loop();
+}
+
+impl<T> RwSpinLock<T> {
+    pub fn new(value: T) -> Self {
+        Self {
+            state: AtomicUsize::new(0),
+            writer: AtomicUsize::new(0),
+            inner: UnsafeCell::new(value),
+        }
+    }
+
+    fn check_recursion(&self) {
+        if self.writer.load(Ordering::Relaxed) == hart_id() + 1 {
+            panic!("rwlock already held exclusively by this hart");
+        }
+    }
+
+    /// Spin until no writer holds or waits for the data, then share it.
+    pub fn shared_access(&self) -> RwSpinLockReadGuard<'_, T> {
+        enter();
+        self.check_recursion();
+        loop {
+            let state = self.state.load(Ordering::Relaxed);
+            if state & (WRITER | WRITER_WAITING) == 0
+                && self
+                    .state
+                    .compare_exchange_weak(state, state + 1, Ordering::Acquire, Ordering::Relaxed)
+                    .is_ok()
+            {
+                return RwSpinLockReadGuard(self);
+            }
+            wait();
+        }
+    }
+
+    /// Spin until no hart holds the data, then hold it alone.
+    pub fn exclusive_access(&self) -> RwSpinLockWriteGuard<'_, T> {
+        enter();
+        self.check_recursion();
+        loop {
+            let state = self.state.load(Ordering::Relaxed);
+            if state & !WRITER_WAITING == 0 {
+                // clears the flag, other writers still waiting set it again
+                if self
+                    .state
+                    .compare_exchange_weak(state, WRITER, Ordering::Acquire, Ordering::Relaxed)
+                    .is_ok()
+                {
+                    self.writer.store(hart_id() + 1, Ordering::Relaxed);
+                    return RwSpinLockWriteGuard(self);
+                }
+            } else if state & WRITER_WAITING == 0 {
+                self.state.fetch_or(WRITER_WAITING, Ordering::Relaxed);
+            }
+            wait();
+        }
+    }
+}
+
+impl<'a, T> Drop for RwSpinLockReadGuard<'a, T> {
+    fn drop(&mut self) {
+        self.0.state.fetch_sub(1, Ordering::Release);
+        leave();
+    }
+}
+
+impl<'a, T> Drop for RwSpinLockWriteGuard<'a, T> {
+    fn drop(&mut self) {
+        self.0.writer.store(0, Ordering::Relaxed);
+        self.0.state.fetch_and(!WRITER, Ordering::Release);
+        leave();
+    }
+}
+
+impl<'a, T> Deref for RwSpinLockReadGuard<'a, T> {
+    type Target = T;
+    fn deref(&self) -> &Self::Target {
+        unsafe { &*self.0.inner.get() }
+    }
+}
+
+impl<'a, T> Deref for RwSpinLockWriteGuard<'a, T> {
+    type Target = T;
+    fn deref(&self) -> &Self::Target {
+        unsafe { &*self.0.inner.get() }
+    }
+}
+
+impl<'a, T> DerefMut for RwSpinLockWriteGuard<'a, T> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        unsafe { &mut *self.0.inner.get() }
+    }
+}
@@ -0,0 +1,126 @@
+//! System V semaphore sets: counters changed together by `semop`, which
+//! applies all of its operations at once or, while one of them cannot go
+//! ahead, none of them, waiting until they all can or a signal comes.
+
+use super::{SpinMutexIrqSave, WaitQueue};
+use crate::task::{schedule, KernelObject, ObjectKind};
+use alloc::vec;
+use alloc::vec::Vec;
+
+/// Semaphores a set holds at most, `SEMMSL` of Linux.
+pub const SEM_SET_MAX: usize = 256;
+/// The largest value of a semaphore, `SEMVMX` of Linux.
+pub const SEM_VALUE_MAX: usize = 32767;
+
+/// One operation of a `semop`.
+pub struct SemOp {
+    /// the semaphore in the set
+    pub num: usize,
+    /// added to the semaphore if positive, taken from it if negative,
+    /// waiting for it to be 0 if 0
+    pub op: isize,
+    /// fail instead of waiting for this one
+    pub nowait: bool,
+}
+
+pub struct SemSet {
+    inner: SpinMutexIrqSave<SemSetInner>,
+}
+
+struct SemSetInner {
+    values: Vec<usize>,
+    /// waiting for some value to change
+    waiters: WaitQueue,
+}
+
+impl SemSetInner {
+    /// Apply `ops` if every one of them can go ahead. Otherwise change
+    /// nothing and give the first that would wait, or none if one is out
+    /// of range.
+    fn try_apply<'a>(&mut self, ops: &'a [SemOp]) -> Result<(), Option<&'a SemOp>> {
+        let mut values = self.values.clone();
+        for op in ops {
+            let value = values.get_mut(op.num).ok_or(None)?;
+            match op.op {
+                0 if *value != 0 => return Err(Some(op)),
+                0 => {}
+                op_value if op_value < 0 => {
+                    *value = value.checked_sub(op_value.unsigned_abs()).ok_or(Some(op))?;
+                }
+                op_value => {
+                    *value += op_value as usize;
+                    if *value > SEM_VALUE_MAX {
+                        return Err(None);
+                    }
+                }
+            }
+        }
+        self.values = values;
+        Ok(())
+    }
+}
+
+impl SemSet {
+    /// A set of `len` semaphores, all 0.
+    pub fn new(len: usize) -> Self {
+        Self {
+            inner: SpinMutexIrqSave::new(SemSetInner {
+                values: vec![0; len],
+                waiters: WaitQueue::new(),
+            }),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.inner.exclusive_access().values.len()
+    }
+
+    pub fn value(&self, num: usize) -> Option<usize> {
+        self.inner.exclusive_access().values.get(num).copied()
+    }
+
+    /// Set semaphore `num` to `value`, false if either is out of range.
+    pub fn set_value(&self, num: usize, value: usize) -> bool {
+        let mut inner = self.inner.exclusive_access();
+        match inner.values.get_mut(num) {
+            Some(slot) if value <= SEM_VALUE_MAX => {
+                *slot = value;
+                inner.waiters.wake_all();
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Apply `ops` all at once, waiting until they can be unless one that
+    /// cannot go ahead has `nowait`. Returns false if none were applied,
+    /// as one was out of range, would have waited with `nowait` or a
+    /// signal came.
+    pub fn apply(&self, ops: &[SemOp]) -> bool {
+        loop {
+            let mut inner = self.inner.exclusive_access();
+            match inner.try_apply(ops) {
+                Ok(()) => {
+                    // each waiter may be after another semaphore
+                    inner.waiters.wake_all();
+                    return true;
+                }
+                Err(Some(op)) if !op.nowait => {}
+                Err(_) => return false,
+            }
+            match inner.waiters.wait_no_sched_interruptible() {
+                Some(task_cx_ptr) => {
+                    drop(inner);
+                    schedule(task_cx_ptr);
+                }
+                None => return false,
+            }
+        }
+    }
+}
+
+impl KernelObject for SemSet {
+    fn kind(&self) -> ObjectKind {
+        ObjectKind::SemSet
+    }
+}
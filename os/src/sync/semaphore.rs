@@ -1,9 +1,12 @@
-use crate::sync::UPIntrFreeCell;
-use crate::task::{block_current_and_run_next, current_task, wakeup_task, TaskControlBlock};
+use crate::sync::SpinMutexIrqSave;
+use crate::task::{
+    block_current_and_run_next, current_task, wakeup_task, KernelObject, ObjectKind,
+    TaskControlBlock,
+};
 use alloc::{collections::VecDeque, sync::Arc};
 
 pub struct Semaphore {
-    pub inner: UPIntrFreeCell<SemaphoreInner>,
+    pub inner: SpinMutexIrqSave<SemaphoreInner>,
 }
 
 pub struct SemaphoreInner {
@@ -11,15 +14,19 @@ pub struct SemaphoreInner {
     pub wait_queue: VecDeque<Arc<TaskControlBlock>>,
 }
 
+impl KernelObject for Semaphore {
+    fn kind(&self) -> ObjectKind {
+        ObjectKind::Semaphore
+    }
+}
+
 impl Semaphore {
     pub fn new(res_count: usize) -> Self {
         Self {
-            inner: unsafe {
-                UPIntrFreeCell::new(SemaphoreInner {
-                    count: res_count as isize,
-                    wait_queue: VecDeque::new(),
-                })
-            },
+            inner: SpinMutexIrqSave::new(SemaphoreInner {
+                count: res_count as isize,
+                wait_queue: VecDeque::new(),
+            }),
         }
     }
 
@@ -0,0 +1,323 @@
+//! Spinlocks, for kernel data shared between harts. Either kind keeps the
+//! current task on its hart while held, and `SpinMutexIrqSave` also masks
+//! the interrupts of the hart, for data an interrupt handler takes too.
+//! Taking a lock the hart holds already is a bug and panics, debug builds
+//! tell where it was taken the first time. With `LOCK_STAT` set, each
+//! place a lock is taken at counts how long it was held for, printed by
+//! `dump_lock_stats` at shutdown.
+
+use super::{preempt_disable, preempt_enable};
+use crate::config::{LOCK_STAT, MAX_HARTS};
+use crate::ipi::handle_tlb_shootdown;
+use crate::task::hart_id;
+use crate::timer::get_time;
+use alloc::format;
+use alloc::vec::Vec;
+use core::cell::UnsafeCell;
+use core::ops::{Deref, DerefMut};
+use core::panic::Location;
+use core::ptr::null_mut;
+use core::sync::atomic::{AtomicPtr, AtomicUsize, Ordering};
+use lazy_static::*;
+use riscv::register::sstatus;
+
+/// Only ever touched by its own hart, with interrupts masked.
+struct PerHart<T>(UnsafeCell<T>);
+
+unsafe impl<T> Sync for PerHart<T> {}
+
+struct IntrMaskingInfo {
+    nested_level: usize,
+    sie_before_masking: bool,
+}
+
+lazy_static! {
+    /// Indexed by hart id, each hart masks its own interrupts.
+    static ref INTR_MASKING_INFO: [PerHart<IntrMaskingInfo>; MAX_HARTS] =
+        core::array::from_fn(|_| PerHart(UnsafeCell::new(IntrMaskingInfo::new())));
+}
+
+fn intr_masking_info() -> &'static mut IntrMaskingInfo {
+    unsafe { &mut *INTR_MASKING_INFO[hart_id()].0.get() }
+}
+
+/// Mask the interrupts of this hart until the matching `unmask_interrupts`,
+/// which enables them again only if they were on before the outermost one.
+pub(super) fn mask_interrupts() {
+    // a switch to another hart between reading the hart id and masking
+    // would count on the wrong one, so the slot is picked once masked
+    let sie = sstatus::read().sie();
+    unsafe {
+        sstatus::clear_sie();
+    }
+    intr_masking_info().enter(sie);
+}
+
+pub(super) fn unmask_interrupts() {
+    intr_masking_info().exit();
+}
+
+impl IntrMaskingInfo {
+    fn new() -> Self {
+        Self {
+            nested_level: 0,
+            sie_before_masking: false,
+        }
+    }
+
+    fn enter(&mut self, sie: bool) {
+        if self.nested_level == 0 {
+            self.sie_before_masking = sie;
+        }
+        self.nested_level += 1;
+    }
+
+    fn exit(&mut self) {
+        self.nested_level -= 1;
+        if self.nested_level == 0 && self.sie_before_masking {
+            unsafe {
+                sstatus::set_sie();
+            }
+        }
+    }
+}
+
+/// A spinlock, masking interrupts while held if `IRQ_SAVE`. Use it as
+/// `SpinMutex` or `SpinMutexIrqSave`.
+pub struct SpinLock<T, const IRQ_SAVE: bool> {
+    /// id + 1 of the hart holding the lock, 0 if it is free
+    owner: AtomicUsize,
+    /// where the holder took it
+    #[cfg(debug_assertions)]
+    taken_at: AtomicPtr<Location<'static>>,
+    /// inner data
+    inner: UnsafeCell<T>,
+}
+
+/// Data that interrupt handlers never take.
+pub type SpinMutex<T> = SpinLock<T, false>;
+/// Data that interrupt handlers may take as well.
+pub type SpinMutexIrqSave<T> = SpinLock<T, true>;
+
+unsafe impl<T, const IRQ_SAVE: bool> Sync for SpinLock<T, IRQ_SAVE> {}
+
+pub struct SpinLockGuard<'a, T, const IRQ_SAVE: bool> {
+    lock: &'a SpinLock<T, IRQ_SAVE>,
+    /// where it was taken and when, with `LOCK_STAT`
+    stat: Option<(&'static LockSite, usize)>,
+}
+
+pub type SpinMutexGuard<'a, T> = SpinLockGuard<'a, T, false>;
+pub type SpinMutexIrqSaveGuard<'a, T> = SpinLockGuard<'a, T, true>;
+
+impl<T, const IRQ_SAVE: bool> SpinLock<T, IRQ_SAVE> {
+    pub fn new(value: T) -> Self {
+        Self {
+            owner: AtomicUsize::new(0),
+            #[cfg(debug_assertions)]
+            taken_at: AtomicPtr::new(null_mut()),
+            inner: UnsafeCell::new(value),
+        }
+    }
+
+    fn enter() {
+        if IRQ_SAVE {
+            mask_interrupts();
+        }
+        preempt_disable();
+    }
+
+    fn leave() {
+        preempt_enable();
+        if IRQ_SAVE {
+            unmask_interrupts();
+        }
+    }
+
+    fn try_lock(&self, me: usize) -> Result<(), usize> {
+        self.owner
+            .compare_exchange(0, me, Ordering::Acquire, Ordering::Relaxed)
+            .map(|_| ())
+    }
+
+    fn guard(&self, location: &'static Location<'static>) -> SpinLockGuard<'_, T, IRQ_SAVE> {
+        #[cfg(debug_assertions)]
+        self.taken_at
+            .store(location as *const _ as *mut _, Ordering::Relaxed);
+        SpinLockGuard {
+            lock: self,
+            stat: LOCK_STAT
+                .then(|| lock_site(location))
+                .flatten()
+                .map(|site| (site, get_time())),
+        }
+    }
+
+    /// Spin until the data is free, panic if this hart holds it already.
+    #[track_caller]
+    pub fn exclusive_access(&self) -> SpinLockGuard<'_, T, IRQ_SAVE> {
+        let location = Location::caller();
+        Self::enter();
+        let me = hart_id() + 1;
+        let mut contended = false;
+        while let Err(owner) = self.try_lock(me) {
+            if owner == me {
+                self.recursion();
+            }
+            contended = true;
+            // the holder may be waiting for this hart to fence its TLB
+            handle_tlb_shootdown();
+            core::hint::spin_loop();
+        }
+        let guard = self.guard(location);
+        if let Some((site, _)) = guard.stat.filter(|_| contended) {
+            site.contended.fetch_add(1, Ordering::Relaxed);
+        }
+        guard
+    }
+
+    /// Take the data only if no hart holds it, for the panic handler, which
+    /// cannot wait for a hart that is halted or is itself.
+    #[track_caller]
+    pub fn try_exclusive_access(&self) -> Option<SpinLockGuard<'_, T, IRQ_SAVE>> {
+        let location = Location::caller();
+        Self::enter();
+        if self.try_lock(hart_id() + 1).is_err() {
+            Self::leave();
+            return None;
+        }
+        Some(self.guard(location))
+    }
+
+    #[track_caller]
+    pub fn exclusive_session<F, V>(&self, f: F) -> V
+    where
+        F: FnOnce(&mut T) -> V,
+    {
+        let mut inner = self.exclusive_access();
+        f(inner.deref_mut())
+    }
+
+    #[cfg(debug_assertions)]
+    fn recursion(&self) -> ! {
+        let taken_at = self.taken_at.load(Ordering::Relaxed);
+        match unsafe { taken_at.as_ref() } {
+            Some(location) => panic!("lock already held by this hart, taken at {}", location),
+            None => panic!("lock already held by this hart"),
+        }
+    }
+
+    #[cfg(not(debug_assertions))]
+    fn recursion(&self) -> ! {
+        panic!("lock already held by this hart");
+    }
+}
+
+impl<'a, T, const IRQ_SAVE: bool> Drop for SpinLockGuard<'a, T, IRQ_SAVE> {
+    fn drop(&mut self) {
+        if let Some((site, start)) = self.stat {
+            site.record(get_time() - start);
+        }
+        #[cfg(debug_assertions)]
+        self.lock.taken_at.store(null_mut(), Ordering::Relaxed);
+        self.lock.owner.store(0, Ordering::Release);
+        SpinLock::<T, IRQ_SAVE>::leave();
+    }
+}
+
+impl<'a, T, const IRQ_SAVE: bool> Deref for SpinLockGuard<'a, T, IRQ_SAVE> {
+    type Target = T;
+    fn deref(&self) -> &Self::Target {
+        unsafe { &*self.lock.inner.get() }
+    }
+}
+
+impl<'a, T, const IRQ_SAVE: bool> DerefMut for SpinLockGuard<'a, T, IRQ_SAVE> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        unsafe { &mut *self.lock.inner.get() }
+    }
+}
+
+/// Places locks are taken at that `LOCK_STAT` keeps apart, the rest are
+/// not counted.
+const LOCK_SITES: usize = 256;
+
+/// How long the locks taken at one place were held, in timer ticks.
+struct LockSite {
+    location: AtomicPtr<Location<'static>>,
+    count: AtomicUsize,
+    /// times it had to wait for another hart
+    contended: AtomicUsize,
+    total: AtomicUsize,
+    max: AtomicUsize,
+}
+
+impl LockSite {
+    const EMPTY: Self = Self {
+        location: AtomicPtr::new(null_mut()),
+        count: AtomicUsize::new(0),
+        contended: AtomicUsize::new(0),
+        total: AtomicUsize::new(0),
+        max: AtomicUsize::new(0),
+    };
+
+    fn record(&self, ticks: usize) {
+        self.count.fetch_add(1, Ordering::Relaxed);
+        self.total.fetch_add(ticks, Ordering::Relaxed);
+        self.max.fetch_max(ticks, Ordering::Relaxed);
+    }
+}
+
+/// Open addressed by the location, filled in without a lock of its own.
+static LOCK_SITE_TABLE: [LockSite; LOCK_SITES] = [LockSite::EMPTY; LOCK_SITES];
+
+fn lock_site(location: &'static Location<'static>) -> Option<&'static LockSite> {
+    let wanted = location as *const _ as *mut Location<'static>;
+    let start = (wanted as usize >> 3) % LOCK_SITES;
+    for i in 0..LOCK_SITES {
+        let site = &LOCK_SITE_TABLE[(start + i) % LOCK_SITES];
+        match site.location.compare_exchange(
+            null_mut(),
+            wanted,
+            Ordering::AcqRel,
+            Ordering::Acquire,
+        ) {
+            Ok(_) => return Some(site),
+            Err(found) if found == wanted => return Some(site),
+            Err(_) => {}
+        }
+    }
+    None
+}
+
+/// Print the places locks were taken at, those held the longest in total
+/// first.
+pub fn dump_lock_stats() {
+    if !LOCK_STAT {
+        return;
+    }
+    let mut sites: Vec<(&'static Location<'static>, &LockSite)> = LOCK_SITE_TABLE
+        .iter()
+        .filter_map(|site| {
+            let location = unsafe { site.location.load(Ordering::Acquire).as_ref() }?;
+            Some((location, site))
+        })
+        .collect();
+    sites.sort_unstable_by_key(|(_, site)| core::cmp::Reverse(site.total.load(Ordering::Relaxed)));
+    println!("---START LOCK STATS (timer ticks)---");
+    println!(
+        "{:<40} {:>10} {:>10} {:>14} {:>10}",
+        "taken at", "count", "contended", "held", "max"
+    );
+    for (location, site) in sites {
+        println!(
+            "{:<40} {:>10} {:>10} {:>14} {:>10}",
+            format!("{}", location),
+            site.count.load(Ordering::Relaxed),
+            site.contended.load(Ordering::Relaxed),
+            site.total.load(Ordering::Relaxed),
+            site.max.load(Ordering::Relaxed)
+        );
+    }
+    println!("---END LOCK STATS---");
+}
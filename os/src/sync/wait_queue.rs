@@ -0,0 +1,79 @@
+//! Queues of tasks blocked until some condition holds, woken by whoever
+//! makes it hold. A waiter checks the condition and queues itself under
+//! the lock guarding it, and wakers hold that lock too, so no wakeup is
+//! lost in between.
+
+use crate::sync::SpinMutexIrqSave;
+use crate::task::{
+    block_current_task, current_signal_pending, current_task, wakeup_task, TaskContext,
+    TaskControlBlock,
+};
+use alloc::collections::VecDeque;
+use alloc::sync::{Arc, Weak};
+
+pub type Waiters = SpinMutexIrqSave<VecDeque<Arc<TaskControlBlock>>>;
+
+/// Clones share the same queue.
+#[derive(Clone)]
+pub struct WaitQueue(Arc<Waiters>);
+
+impl WaitQueue {
+    pub fn new() -> Self {
+        Self(Arc::new(SpinMutexIrqSave::new(VecDeque::new())))
+    }
+
+    /// Queue and block the current task, which must `schedule` to the
+    /// returned context once it released the lock guarding the condition.
+    pub fn wait_no_sched(&self) -> *mut TaskContext {
+        self.enqueue(None);
+        block_current_task()
+    }
+
+    /// Like `wait_no_sched`, but a signal for the process wakes the task
+    /// too. Returns none without blocking if a signal is pending already.
+    pub fn wait_no_sched_interruptible(&self) -> Option<*mut TaskContext> {
+        // queued first, so a signal sent meanwhile finds the task
+        self.enqueue(Some(Arc::downgrade(&self.0)));
+        if current_signal_pending() {
+            let task = current_task().unwrap();
+            let mut waiters = self.0.exclusive_access();
+            if let Some(idx) = waiters.iter().position(|other| Arc::ptr_eq(other, &task)) {
+                waiters.remove(idx);
+                return None;
+            }
+            // already woken, so it has to go through the scheduler anyway
+        }
+        Some(block_current_task())
+    }
+
+    fn enqueue(&self, interruptible: Option<Weak<Waiters>>) {
+        let task = current_task().unwrap();
+        self.0.exclusive_access().push_back(task.clone());
+        task.inner_exclusive_access().wait_queue = interruptible;
+    }
+
+    /// Wake the task waiting longest, returning whether there was one.
+    pub fn wake_one(&self) -> bool {
+        let task = self.0.exclusive_access().pop_front();
+        task.map(wakeup_task).is_some()
+    }
+
+    /// Wake every waiting task.
+    pub fn wake_all(&self) {
+        let mut waiters = self.0.exclusive_access();
+        for task in waiters.drain(..) {
+            wakeup_task(task);
+        }
+    }
+}
+
+/// Wake `task` if it waits interruptibly, as a signal came for it.
+pub fn interrupt_wait(task: &Arc<TaskControlBlock>) {
+    let waiters = task.inner_exclusive_access().wait_queue.take();
+    if let Some(waiters) = waiters.and_then(|waiters| waiters.upgrade()) {
+        let mut waiters = waiters.exclusive_access();
+        if let Some(idx) = waiters.iter().position(|other| Arc::ptr_eq(other, task)) {
+            wakeup_task(waiters.remove(idx).unwrap());
+        }
+    }
+}
@@ -1,6 +1,10 @@
-use crate::fs::{make_pipe, open_file, OpenFlags};
-use crate::mm::{translated_byte_buffer, translated_refmut, translated_str, UserBuffer};
-use crate::task::{current_process, current_user_token};
+use crate::drivers::rng;
+use crate::fs::{
+    absolute_path, lookup, lookup_parent, make_pipe, open_device, open_file, sync_all, EventFd,
+    File, OpenFlags, Stat, EAGAIN,
+};
+use crate::mm::{copy_bytes_to_user, read_user_str, UserBuffer, UserPtr};
+use crate::task::{current_process, current_user_token, RLIMIT_NOFILE};
 use alloc::sync::Arc;
 
 pub fn sys_write(fd: usize, buf: *const u8, len: usize) -> isize {
@@ -17,7 +21,14 @@ pub fn sys_write(fd: usize, buf: *const u8, len: usize) -> isize {
         let file = file.clone();
         // release current task TCB manually to avoid multi-borrow
         drop(inner);
-        file.write(UserBuffer::new(translated_byte_buffer(token, buf, len))) as isize
+        match UserBuffer::new(token, buf, len, false) {
+            Ok(buffer) if file.nonblocking() => match file.try_write(buffer) {
+                Some(written) => written as isize,
+                None => EAGAIN,
+            },
+            Ok(buffer) => file.write(buffer) as isize,
+            Err(err) => err,
+        }
     } else {
         -1
     }
@@ -37,7 +48,14 @@ pub fn sys_read(fd: usize, buf: *const u8, len: usize) -> isize {
         }
         // release current task TCB manually to avoid multi-borrow
         drop(inner);
-        file.read(UserBuffer::new(translated_byte_buffer(token, buf, len))) as isize
+        match UserBuffer::new(token, buf, len, true) {
+            Ok(buffer) if file.nonblocking() => match file.try_read(buffer) {
+                Some(read) => read as isize,
+                None => EAGAIN,
+            },
+            Ok(buffer) => file.read(buffer) as isize,
+            Err(err) => err,
+        }
     } else {
         -1
     }
@@ -46,15 +64,32 @@ pub fn sys_read(fd: usize, buf: *const u8, len: usize) -> isize {
 pub fn sys_open(path: *const u8, flags: u32) -> isize {
     let process = current_process();
     let token = current_user_token();
-    let path = translated_str(token, path);
-    if let Some(inode) = open_file(path.as_str(), OpenFlags::from_bits(flags).unwrap()) {
-        let mut inner = process.inner_exclusive_access();
-        let fd = inner.alloc_fd();
-        inner.fd_table[fd] = Some(inode);
-        fd as isize
-    } else {
-        -1
+    let path = match read_user_str(token, path) {
+        Ok(path) => path,
+        Err(err) => return err,
+    };
+    let flags = match OpenFlags::from_bits(flags) {
+        Some(flags) => flags,
+        None => return -1,
+    };
+    let path = absolute_path(&process.inner_exclusive_access().cwd, &path);
+    let file: Arc<dyn File + Send + Sync> = match open_device(&path) {
+        Some(device) => device,
+        None => match open_file(path.as_str(), flags) {
+            Some(inode) => inode,
+            None => return -1,
+        },
+    };
+    if flags.contains(OpenFlags::NONBLOCK) {
+        file.set_nonblocking(true);
     }
+    let mut inner = process.inner_exclusive_access();
+    let fd = match inner.alloc_fd() {
+        Some(fd) => fd,
+        None => return -1,
+    };
+    inner.fd_table[fd] = Some(file);
+    fd as isize
 }
 
 pub fn sys_close(fd: usize) -> isize {
@@ -66,7 +101,10 @@ pub fn sys_close(fd: usize) -> isize {
     if inner.fd_table[fd].is_none() {
         return -1;
     }
-    inner.fd_table[fd].take();
+    let file = inner.fd_table[fd].take();
+    // closing a pipe end wakes the other end, which may need our inner
+    drop(inner);
+    drop(file);
     0
 }
 
@@ -75,15 +113,53 @@ pub fn sys_pipe(pipe: *mut usize) -> isize {
     let token = current_user_token();
     let mut inner = process.inner_exclusive_access();
     let (pipe_read, pipe_write) = make_pipe();
-    let read_fd = inner.alloc_fd();
+    let read_fd = match inner.alloc_fd() {
+        Some(fd) => fd,
+        None => return -1,
+    };
     inner.fd_table[read_fd] = Some(pipe_read);
-    let write_fd = inner.alloc_fd();
+    let write_fd = match inner.alloc_fd() {
+        Some(fd) => fd,
+        None => {
+            inner.fd_table[read_fd] = None;
+            return -1;
+        }
+    };
     inner.fd_table[write_fd] = Some(pipe_write);
-    *translated_refmut(token, pipe) = read_fd;
-    *translated_refmut(token, unsafe { pipe.add(1) }) = write_fd;
+    // writing to user memory may fault the page in, which needs inner
+    drop(inner);
+    let fds = UserPtr::new(token, pipe);
+    if let Err(err) = fds.write(read_fd).and_then(|_| fds.add(1).write(write_fd)) {
+        let mut inner = process.inner_exclusive_access();
+        inner.fd_table[read_fd] = None;
+        inner.fd_table[write_fd] = None;
+        return err;
+    }
     0
 }
 
+/// Reads of the eventfd take one of the count at a time.
+const EFD_SEMAPHORE: usize = 1;
+/// The eventfd starts out nonblocking.
+const EFD_NONBLOCK: usize = 0o4000;
+
+/// A new eventfd counting from `initval`.
+pub fn sys_eventfd2(initval: usize, flags: usize) -> isize {
+    if flags & !(EFD_SEMAPHORE | EFD_NONBLOCK) != 0 {
+        return -1;
+    }
+    let process = current_process();
+    let mut inner = process.inner_exclusive_access();
+    let fd = match inner.alloc_fd() {
+        Some(fd) => fd,
+        None => return -1,
+    };
+    let eventfd = EventFd::new(initval as u64, flags & EFD_SEMAPHORE != 0);
+    eventfd.set_nonblocking(flags & EFD_NONBLOCK != 0);
+    inner.fd_table[fd] = Some(Arc::new(eventfd));
+    fd as isize
+}
+
 pub fn sys_dup(fd: usize) -> isize {
     let process = current_process();
     let mut inner = process.inner_exclusive_access();
@@ -93,7 +169,211 @@ pub fn sys_dup(fd: usize) -> isize {
     if inner.fd_table[fd].is_none() {
         return -1;
     }
-    let new_fd = inner.alloc_fd();
+    let new_fd = match inner.alloc_fd() {
+        Some(fd) => fd,
+        None => return -1,
+    };
     inner.fd_table[new_fd] = Some(Arc::clone(inner.fd_table[fd].as_ref().unwrap()));
     new_fd as isize
 }
+
+/// Make `new_fd` refer to the file of `old_fd`, closing whatever
+/// `new_fd` referred to before.
+pub fn sys_dup2(old_fd: usize, new_fd: usize) -> isize {
+    let process = current_process();
+    let mut inner = process.inner_exclusive_access();
+    let file = match inner.fd_table.get(old_fd) {
+        Some(Some(file)) => Arc::clone(file),
+        _ => return -1,
+    };
+    if new_fd >= inner.rlimits[RLIMIT_NOFILE].cur {
+        return -1;
+    }
+    if inner.fd_table.len() <= new_fd {
+        inner.fd_table.resize(new_fd + 1, None);
+    }
+    let old_file = inner.fd_table[new_fd].replace(file);
+    // the replaced file goes away without holding inner
+    drop(inner);
+    drop(old_file);
+    new_fd as isize
+}
+
+pub fn sys_mkdir(path: *const u8) -> isize {
+    let process = current_process();
+    let token = current_user_token();
+    let path = match read_user_str(token, path) {
+        Ok(path) => path,
+        Err(err) => return err,
+    };
+    let path = absolute_path(&process.inner_exclusive_access().cwd, &path);
+    match lookup_parent(&path) {
+        Some((parent, name)) if parent.create_dir(name).is_some() => 0,
+        _ => -1,
+    }
+}
+
+pub fn sys_chdir(path: *const u8) -> isize {
+    let process = current_process();
+    let token = current_user_token();
+    let path = match read_user_str(token, path) {
+        Ok(path) => path,
+        Err(err) => return err,
+    };
+    let path = absolute_path(&process.inner_exclusive_access().cwd, &path);
+    match lookup(&path) {
+        Some(inode) if inode.is_dir() => {
+            process.inner_exclusive_access().cwd = path;
+            0
+        }
+        _ => -1,
+    }
+}
+
+/// Copy the working directory and a trailing NUL into `buf`, returning
+/// its length, or -1 if `len` bytes are not enough.
+pub fn sys_getcwd(buf: *mut u8, len: usize) -> isize {
+    let process = current_process();
+    let token = current_user_token();
+    let cwd = process.inner_exclusive_access().cwd.clone();
+    if cwd.len() + 1 > len {
+        return -1;
+    }
+    let mut bytes = cwd.into_bytes();
+    bytes.push(0);
+    match copy_bytes_to_user(token, buf, &bytes) {
+        Ok(()) => bytes.len() as isize - 1,
+        Err(err) => err,
+    }
+}
+
+/// Add `new_path` as another name of the file at `old_path`.
+pub fn sys_linkat(old_path: *const u8, new_path: *const u8) -> isize {
+    let process = current_process();
+    let token = current_user_token();
+    let old_path = match read_user_str(token, old_path) {
+        Ok(old_path) => old_path,
+        Err(err) => return err,
+    };
+    let new_path = match read_user_str(token, new_path) {
+        Ok(new_path) => new_path,
+        Err(err) => return err,
+    };
+    let inner = process.inner_exclusive_access();
+    let old_path = absolute_path(&inner.cwd, &old_path);
+    let new_path = absolute_path(&inner.cwd, &new_path);
+    drop(inner);
+    match (lookup(&old_path), lookup_parent(&new_path)) {
+        (Some(target), Some((parent, name))) if parent.link(name, &target) => 0,
+        _ => -1,
+    }
+}
+
+/// Remove the name `path` of a file, which goes away with its last name.
+pub fn sys_unlinkat(path: *const u8) -> isize {
+    let process = current_process();
+    let token = current_user_token();
+    let path = match read_user_str(token, path) {
+        Ok(path) => path,
+        Err(err) => return err,
+    };
+    let path = absolute_path(&process.inner_exclusive_access().cwd, &path);
+    match lookup_parent(&path) {
+        Some((parent, name)) if parent.unlink(name) => 0,
+        _ => -1,
+    }
+}
+
+pub fn sys_fstat(fd: usize, st: *mut Stat) -> isize {
+    let process = current_process();
+    let token = current_user_token();
+    let inner = process.inner_exclusive_access();
+    let file = match inner.fd_table.get(fd) {
+        Some(Some(file)) => Arc::clone(file),
+        _ => return -1,
+    };
+    drop(inner);
+    let stat = match file.stat() {
+        Some(stat) => stat,
+        None => return -1,
+    };
+    match copy_bytes_to_user(token, st as *mut u8, stat.as_bytes()) {
+        Ok(()) => 0,
+        Err(err) => err,
+    }
+}
+
+/// Pass a device specific request to the file behind `fd`.
+pub fn sys_ioctl(fd: usize, cmd: u32, arg: usize) -> isize {
+    let process = current_process();
+    let inner = process.inner_exclusive_access();
+    let file = match inner.fd_table.get(fd) {
+        Some(Some(file)) => Arc::clone(file),
+        _ => return -1,
+    };
+    drop(inner);
+    file.ioctl(cmd, arg)
+}
+
+/// Get the access mode and status flags of the file behind `fd`.
+const F_GETFL: u32 = 3;
+/// Set its status flags, of which only `O_NONBLOCK` can change.
+const F_SETFL: u32 = 4;
+
+pub fn sys_fcntl(fd: usize, cmd: u32, arg: usize) -> isize {
+    let process = current_process();
+    let inner = process.inner_exclusive_access();
+    let file = match inner.fd_table.get(fd) {
+        Some(Some(file)) => Arc::clone(file),
+        _ => return -1,
+    };
+    drop(inner);
+    match cmd {
+        F_GETFL => {
+            let mut flags = match (file.readable(), file.writable()) {
+                (true, false) => OpenFlags::RDONLY,
+                (false, true) => OpenFlags::WRONLY,
+                _ => OpenFlags::RDWR,
+            };
+            if file.nonblocking() {
+                flags |= OpenFlags::NONBLOCK;
+            }
+            flags.bits() as isize
+        }
+        F_SETFL => {
+            file.set_nonblocking(arg as u32 & OpenFlags::NONBLOCK.bits() != 0);
+            0
+        }
+        _ => -1,
+    }
+}
+
+/// Write the cached blocks of every mounted file system back.
+pub fn sys_sync() -> isize {
+    sync_all();
+    0
+}
+
+/// `GRND_NONBLOCK`, `GRND_RANDOM` and `GRND_INSECURE`, all the same here
+/// as the pool is seeded at boot and never runs dry.
+const GRND_FLAGS: u32 = 0x7;
+
+/// Fill `buf` with `len` bytes from the entropy pool, returning how many
+/// were written before a fault stopped it.
+pub fn sys_getrandom(buf: *mut u8, len: usize, flags: u32) -> isize {
+    if flags & !GRND_FLAGS != 0 {
+        return -1;
+    }
+    let token = current_user_token();
+    let mut chunk = [0u8; 256];
+    let mut done = 0;
+    while done < len {
+        let n = (len - done).min(chunk.len());
+        rng::fill_bytes(&mut chunk[..n]);
+        if let Err(err) = copy_bytes_to_user(token, buf.wrapping_add(done), &chunk[..n]) {
+            return if done == 0 { err } else { done as isize };
+        }
+        done += n;
+    }
+    done as isize
+}
@@ -1,25 +1,25 @@
-//use crate::drivers::{KEYBOARD_DEVICE,MOUSE_DEVICE,INPUT_CONDVAR,read_input_event};
 use crate::drivers::{KEYBOARD_DEVICE, MOUSE_DEVICE};
 
+/// Take the oldest keyboard event, else mouse event, as type, code and
+/// value packed from the high bits down; 0 if there is none.
 pub fn sys_event_get() -> isize {
-    let kb = KEYBOARD_DEVICE.clone();
-    let mouse = MOUSE_DEVICE.clone();
-    //let input=INPUT_CONDVAR.clone();
-    //read_input_event() as isize
-    if !kb.is_empty() {
-        kb.read_event() as isize
-    } else if !mouse.is_empty() {
-        mouse.read_event() as isize
-    } else {
-        0
+    match KEYBOARD_DEVICE
+        .read_event(false)
+        .or_else(|_| MOUSE_DEVICE.read_event(false))
+    {
+        Ok(event) => {
+            ((event.event_type as u64) << 48 | (event.code as u64) << 32 | event.value as u64)
+                as isize
+        }
+        Err(_) => 0,
     }
 }
 
-use crate::drivers::chardev::UART;
+use crate::drivers::chardev::TTY;
 
-/// check UART's read-buffer is empty or not
+/// check whether console input is waiting to be read
 pub fn sys_key_pressed() -> isize {
-    let res = !UART.read_buffer_is_empty();
+    let res = TTY.can_read();
     if res {
         1
     } else {
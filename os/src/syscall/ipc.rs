@@ -0,0 +1,306 @@
+//! System V IPC. Objects are found by a key shared between processes,
+//! `IPC_PRIVATE` making a new one only the caller and its children know
+//! of, and each process refers to the objects it got by a handle of its
+//! own. Any process may open an object by its key, the kernel has no
+//! users to tell apart.
+
+use crate::config::PAGE_SIZE;
+use crate::mm::{
+    copy_bytes_to_user, copy_from_user, copy_to_user, reclaim, MapPermission, ShmSegment,
+    UserBuffer,
+};
+use crate::sync::{Message, MsgQueue, SemOp, SemSet, SEM_SET_MAX};
+use crate::task::{
+    current_process, current_user_token, insert_named_object, named_object, KernelObject,
+    ObjectKind,
+};
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::mem::size_of;
+
+const IPC_PRIVATE: usize = 0;
+/// Create the object if the key has none.
+const IPC_CREAT: usize = 0o1000;
+/// With `IPC_CREAT`, fail if the key has one already.
+const IPC_EXCL: usize = 0o2000;
+/// Fail instead of waiting.
+const IPC_NOWAIT: usize = 0o4000;
+/// Attach a segment for reading only.
+const SHM_RDONLY: usize = 0o10000;
+/// Cut a message too long for the buffer short instead of failing.
+const MSG_NOERROR: usize = 0o10000;
+/// Undo the operation when the process exits, which is not kept track
+/// of, so an operation asking for it fails.
+const SEM_UNDO: i16 = 0o10000;
+/// `semctl` commands.
+const GETVAL: usize = 12;
+const SETVAL: usize = 16;
+/// Operations one `semop` takes at most, `SEMOPM` of Linux.
+const SEM_OPS_MAX: usize = 500;
+
+/// `struct sembuf`.
+#[derive(Clone, Copy)]
+#[repr(C)]
+struct SemBuf {
+    sem_num: u16,
+    sem_op: i16,
+    sem_flg: i16,
+}
+
+/// The object of `kind` under `key`, or a new one from `create` as
+/// `flags` ask. `fits` tells whether an existing one will do.
+fn ipc_get<T: KernelObject>(
+    kind: ObjectKind,
+    key: usize,
+    flags: usize,
+    fits: impl FnOnce(&T) -> bool,
+    create: impl FnOnce() -> Option<T>,
+) -> Option<Arc<T>> {
+    if key != IPC_PRIVATE {
+        if let Some(object) = named_object::<T>(kind, key) {
+            let exclusive = flags & IPC_CREAT != 0 && flags & IPC_EXCL != 0;
+            return (!exclusive && fits(&object)).then_some(object);
+        }
+        if flags & IPC_CREAT == 0 {
+            return None;
+        }
+    }
+    let object = Arc::new(create()?);
+    let named: Arc<dyn KernelObject> = object.clone();
+    // another process may have taken the key meanwhile
+    if key != IPC_PRIVATE && !insert_named_object(key, &named) {
+        return None;
+    }
+    Some(object)
+}
+
+/// The handle of the shared memory segment under `key`, of at least
+/// `size` bytes if it is created.
+pub fn sys_shmget(key: usize, size: usize, flags: usize) -> isize {
+    let shm = ipc_get(
+        ObjectKind::Shm,
+        key,
+        flags,
+        |shm: &ShmSegment| size <= shm.pages() * PAGE_SIZE,
+        || {
+            if size == 0 {
+                return None;
+            }
+            reclaim(size.div_ceil(PAGE_SIZE));
+            ShmSegment::new(size).ok()
+        },
+    );
+    match shm {
+        Some(shm) => current_process()
+            .inner_exclusive_access()
+            .handles
+            .insert(shm) as isize,
+        None => -1,
+    }
+}
+
+/// Map the segment of handle `shmid` at `addr`, or where there is room
+/// if `addr` is 0, and return where it went.
+pub fn sys_shmat(shmid: usize, addr: usize, flags: usize) -> isize {
+    let process = current_process();
+    let mut process_inner = process.inner_exclusive_access();
+    let shm = match process_inner
+        .handles
+        .get_as::<ShmSegment>(ObjectKind::Shm, shmid)
+    {
+        Some(shm) => shm,
+        None => return -1,
+    };
+    let mut permission = MapPermission::U | MapPermission::R;
+    if flags & SHM_RDONLY == 0 {
+        permission |= MapPermission::W;
+    }
+    let addr = if addr == 0 {
+        match process_inner
+            .memory_set
+            .find_free_area(shm.pages() * PAGE_SIZE)
+        {
+            Some(addr) => addr,
+            None => return -1,
+        }
+    } else {
+        addr
+    };
+    if process_inner
+        .memory_set
+        .attach_shm(addr.into(), shm, permission)
+    {
+        addr as isize
+    } else {
+        -1
+    }
+}
+
+/// Unmap the segment attached at `addr`.
+pub fn sys_shmdt(addr: usize) -> isize {
+    let process = current_process();
+    let mut process_inner = process.inner_exclusive_access();
+    if process_inner.memory_set.detach_shm(addr.into()) {
+        0
+    } else {
+        -1
+    }
+}
+
+/// The handle of the message queue under `key`.
+pub fn sys_msgget(key: usize, flags: usize) -> isize {
+    match ipc_get(
+        ObjectKind::MsgQueue,
+        key,
+        flags,
+        |_| true,
+        || Some(MsgQueue::new()),
+    ) {
+        Some(queue) => current_process()
+            .inner_exclusive_access()
+            .handles
+            .insert(queue) as isize,
+        None => -1,
+    }
+}
+
+fn current_msg_queue(msqid: usize) -> Option<Arc<MsgQueue>> {
+    current_process()
+        .inner_exclusive_access()
+        .handles
+        .get_as::<MsgQueue>(ObjectKind::MsgQueue, msqid)
+}
+
+/// Send the message at `msgp`, its type in a `usize` followed by `msgsz`
+/// bytes of text, waiting for room in the queue unless `IPC_NOWAIT`.
+pub fn sys_msgsnd(msqid: usize, msgp: usize, msgsz: usize, flags: usize) -> isize {
+    let queue = match current_msg_queue(msqid) {
+        Some(queue) => queue,
+        None => return -1,
+    };
+    let token = current_user_token();
+    let mtype = match copy_from_user(token, msgp as *const usize) {
+        Ok(mtype) if (mtype as isize) > 0 => mtype,
+        Ok(_) => return -1,
+        Err(err) => return err,
+    };
+    let text = match UserBuffer::new(
+        token,
+        (msgp + size_of::<usize>()) as *const u8,
+        msgsz,
+        false,
+    ) {
+        Ok(text) => text.buffers.concat(),
+        Err(err) => return err,
+    };
+    if queue.send(Message { mtype, text }, flags & IPC_NOWAIT != 0) {
+        0
+    } else {
+        -1
+    }
+}
+
+/// Receive the message `msgtyp` selects into `msgp`, laid out as for
+/// `sys_msgsnd` with room for `msgsz` bytes of text, waiting for one
+/// unless `IPC_NOWAIT`. Returns the length of the text.
+pub fn sys_msgrcv(msqid: usize, msgp: usize, msgsz: usize, msgtyp: isize, flags: usize) -> isize {
+    let queue = match current_msg_queue(msqid) {
+        Some(queue) => queue,
+        None => return -1,
+    };
+    let max_len = (flags & MSG_NOERROR == 0).then_some(msgsz);
+    let message = match queue.receive(msgtyp, max_len, flags & IPC_NOWAIT != 0) {
+        Some(message) => message,
+        None => return -1,
+    };
+    let len = message.text.len().min(msgsz);
+    let token = current_user_token();
+    if let Err(err) = copy_to_user(token, msgp as *mut usize, message.mtype) {
+        return err;
+    }
+    match copy_bytes_to_user(
+        token,
+        (msgp + size_of::<usize>()) as *mut u8,
+        &message.text[..len],
+    ) {
+        Ok(()) => len as isize,
+        Err(err) => err,
+    }
+}
+
+/// The handle of the semaphore set under `key`, of at least `nsems`
+/// semaphores if it is created.
+pub fn sys_semget(key: usize, nsems: usize, flags: usize) -> isize {
+    match ipc_get(
+        ObjectKind::SemSet,
+        key,
+        flags,
+        |set: &SemSet| nsems <= set.len(),
+        || {
+            (1..=SEM_SET_MAX)
+                .contains(&nsems)
+                .then(|| SemSet::new(nsems))
+        },
+    ) {
+        Some(set) => current_process()
+            .inner_exclusive_access()
+            .handles
+            .insert(set) as isize,
+        None => -1,
+    }
+}
+
+fn current_sem_set(semid: usize) -> Option<Arc<SemSet>> {
+    current_process()
+        .inner_exclusive_access()
+        .handles
+        .get_as::<SemSet>(ObjectKind::SemSet, semid)
+}
+
+/// Apply the `nsops` operations at `sops` to the set `semid` all at
+/// once, waiting until they can be unless the one that cannot go ahead
+/// has `IPC_NOWAIT`.
+pub fn sys_semop(semid: usize, sops: usize, nsops: usize) -> isize {
+    let set = match current_sem_set(semid) {
+        Some(set) => set,
+        None => return -1,
+    };
+    if nsops == 0 || nsops > SEM_OPS_MAX {
+        return -1;
+    }
+    let token = current_user_token();
+    let mut ops = Vec::with_capacity(nsops);
+    for i in 0..nsops {
+        let sembuf = match copy_from_user(token, (sops as *const SemBuf).wrapping_add(i)) {
+            Ok(sembuf) => sembuf,
+            Err(err) => return err,
+        };
+        if sembuf.sem_flg & SEM_UNDO != 0 {
+            return -1;
+        }
+        ops.push(SemOp {
+            num: sembuf.sem_num as usize,
+            op: sembuf.sem_op as isize,
+            nowait: sembuf.sem_flg as usize & IPC_NOWAIT != 0,
+        });
+    }
+    if set.apply(&ops) {
+        0
+    } else {
+        -1
+    }
+}
+
+/// `GETVAL` gives the value of semaphore `semnum` of the set `semid`,
+/// `SETVAL` sets it to `arg`.
+pub fn sys_semctl(semid: usize, semnum: usize, cmd: usize, arg: usize) -> isize {
+    let set = match current_sem_set(semid) {
+        Some(set) => set,
+        None => return -1,
+    };
+    match cmd {
+        GETVAL => set.value(semnum).map_or(-1, |value| value as isize),
+        SETVAL if set.set_value(semnum, arg) => 0,
+        _ => -1,
+    }
+}
@@ -1,53 +1,335 @@
-use crate::net::port_table::{accept, listen, port_acceptable, PortFd};
-use crate::net::udp::UDP;
-use crate::net::{net_interrupt_handler, IPv4};
-use crate::task::{current_process, current_task, current_trap_cx};
+use crate::fs::{absolute_path, make_socket_pair, File, LocalSocket};
+use crate::mm::{
+    copy_bytes_to_user, copy_from_user, copy_to_user, read_user_str, UserBuffer, UserPtr,
+};
+use crate::net::{TcpSocket, UdpSocket};
+use crate::task::{current_process, current_user_token};
+use alloc::string::String;
 use alloc::sync::Arc;
+use core::mem::size_of;
+use core::net::{Ipv4Addr, SocketAddrV4};
 
-// just support udp
-pub fn sys_connect(raddr: u32, lport: u16, rport: u16) -> isize {
+/// Local sockets, named by a path.
+const AF_UNIX: usize = 1;
+/// IPv4 sockets.
+const AF_INET: usize = 2;
+const SOCK_STREAM: usize = 1;
+const SOCK_DGRAM: usize = 2;
+/// Or'ed into the type for a nonblocking socket.
+const SOCK_NONBLOCK: usize = 0o4000;
+
+/// A new socket, neither bound nor connected: a local stream socket, or
+/// a TCP or UDP one.
+pub fn sys_socket(domain: usize, socket_type: usize, _protocol: usize) -> isize {
+    let socket: Arc<dyn File + Send + Sync> = match (domain, socket_type & !SOCK_NONBLOCK) {
+        (AF_UNIX, SOCK_STREAM) => Arc::new(LocalSocket::new()),
+        (AF_INET, SOCK_STREAM) => Arc::new(TcpSocket::new()),
+        (AF_INET, SOCK_DGRAM) => Arc::new(UdpSocket::new()),
+        _ => return -1,
+    };
+    socket.set_nonblocking(socket_type & SOCK_NONBLOCK != 0);
     let process = current_process();
     let mut inner = process.inner_exclusive_access();
-    let fd = inner.alloc_fd();
-    let udp_node = UDP::new(IPv4::from_u32(raddr), lport, rport);
-    inner.fd_table[fd] = Some(Arc::new(udp_node));
+    let fd = match inner.alloc_fd() {
+        Some(fd) => fd,
+        None => return -1,
+    };
+    inner.fd_table[fd] = Some(socket);
     fd as isize
 }
 
-// listen a port
-pub fn sys_listen(port: u16) -> isize {
-    match listen(port) {
-        Some(port_index) => {
-            let process = current_process();
-            let mut inner = process.inner_exclusive_access();
-            let fd = inner.alloc_fd();
-            let port_fd = PortFd::new(port_index);
-            inner.fd_table[fd] = Some(Arc::new(port_fd));
-
-            // NOTICE: this return the port index, not the fd
-            port_index as isize
+/// Two local stream sockets connected to each other, their fds stored
+/// at `sv` like `sys_pipe` does.
+pub fn sys_socketpair(
+    domain: usize,
+    socket_type: usize,
+    _protocol: usize,
+    sv: *mut usize,
+) -> isize {
+    if domain != AF_UNIX || socket_type & !SOCK_NONBLOCK != SOCK_STREAM {
+        return -1;
+    }
+    let process = current_process();
+    let token = current_user_token();
+    let mut inner = process.inner_exclusive_access();
+    let (socket0, socket1) = make_socket_pair();
+    socket0.set_nonblocking(socket_type & SOCK_NONBLOCK != 0);
+    socket1.set_nonblocking(socket_type & SOCK_NONBLOCK != 0);
+    let fd0 = match inner.alloc_fd() {
+        Some(fd) => fd,
+        None => return -1,
+    };
+    inner.fd_table[fd0] = Some(socket0);
+    let fd1 = match inner.alloc_fd() {
+        Some(fd) => fd,
+        None => {
+            inner.fd_table[fd0] = None;
+            return -1;
         }
-        None => -1,
+    };
+    inner.fd_table[fd1] = Some(socket1);
+    // writing to user memory may fault the page in, which needs inner
+    drop(inner);
+    let fds = UserPtr::new(token, sv);
+    if let Err(err) = fds.write(fd0).and_then(|_| fds.add(1).write(fd1)) {
+        let mut inner = process.inner_exclusive_access();
+        inner.fd_table[fd0] = None;
+        inner.fd_table[fd1] = None;
+        return err;
     }
+    0
+}
+
+/// The local socket behind `fd`.
+fn local_socket(fd: usize) -> Option<Arc<LocalSocket>> {
+    let process = current_process();
+    let inner = process.inner_exclusive_access();
+    let file = inner.fd_table.get(fd)?.clone()?;
+    file.as_any().downcast::<LocalSocket>().ok()
+}
+
+/// The TCP socket behind `fd`.
+fn tcp_socket(fd: usize) -> Option<Arc<TcpSocket>> {
+    let process = current_process();
+    let inner = process.inner_exclusive_access();
+    let file = inner.fd_table.get(fd)?.clone()?;
+    file.as_any().downcast::<TcpSocket>().ok()
+}
+
+/// The UDP socket behind `fd`.
+fn udp_socket(fd: usize) -> Option<Arc<UdpSocket>> {
+    let process = current_process();
+    let inner = process.inner_exclusive_access();
+    let file = inner.fd_table.get(fd)?.clone()?;
+    file.as_any().downcast::<UdpSocket>().ok()
+}
+
+/// `struct sockaddr_in`, the port and address in network byte order.
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct SockAddrIn {
+    family: u16,
+    port: [u8; 2],
+    addr: [u8; 4],
+    zero: [u8; 8],
 }
 
-// accept a tcp connection
-pub fn sys_accept(port_index: usize) -> isize {
-    println!("accepting port {}", port_index);
+/// The IPv4 address in the `sockaddr_in` at `addr`.
+fn read_inet_addr(addr: *const u8, addrlen: usize) -> Result<SocketAddrV4, isize> {
+    if addrlen < size_of::<SockAddrIn>() {
+        return Err(-1);
+    }
+    let addr = copy_from_user(current_user_token(), addr as *const SockAddrIn)?;
+    if addr.family as usize != AF_INET {
+        return Err(-1);
+    }
+    Ok(SocketAddrV4::new(
+        Ipv4Addr::from(addr.addr),
+        u16::from_be_bytes(addr.port),
+    ))
+}
 
-    let task = current_task().unwrap();
-    accept(port_index, task);
-    // block_current_and_run_next();
+/// Store `sender` in the `sockaddr_in` at `addr`, cut to the length at
+/// `addrlen`, and its whole length at `addrlen`.
+fn write_inet_addr(addr: *mut u8, addrlen: *mut u32, sender: SocketAddrV4) -> Result<(), isize> {
+    let token = current_user_token();
+    let room = copy_from_user(token, addrlen)? as usize;
+    let sender = SockAddrIn {
+        family: AF_INET as u16,
+        port: sender.port().to_be_bytes(),
+        addr: sender.ip().octets(),
+        zero: [0; 8],
+    };
+    let bytes = unsafe {
+        core::slice::from_raw_parts(&sender as *const _ as *const u8, size_of::<SockAddrIn>())
+    };
+    copy_bytes_to_user(token, addr, &bytes[..room.min(bytes.len())])?;
+    copy_to_user(token, addrlen, size_of::<SockAddrIn>() as u32)
+}
 
-    // NOTICE: There does not have interrupt handler, just call it munually.
-    loop {
-        net_interrupt_handler();
+/// The absolute path in the `sockaddr_un` at `addr`, a `u16` family
+/// followed by a NUL terminated path.
+fn read_local_addr(addr: *const u8, addrlen: usize) -> Result<String, isize> {
+    let token = current_user_token();
+    if addrlen <= size_of::<u16>() || copy_from_user(token, addr as *const u16)? as usize != AF_UNIX
+    {
+        return Err(-1);
+    }
+    let path = read_user_str(token, (addr as usize + size_of::<u16>()) as *const u8)?;
+    if path.is_empty() {
+        return Err(-1);
+    }
+    Ok(absolute_path(
+        &current_process().inner_exclusive_access().cwd,
+        &path,
+    ))
+}
 
-        if !port_acceptable(port_index) {
-            break;
+/// Name the socket `fd` by the path at `addr` for others to connect to,
+/// or give the TCP or UDP socket `fd` the port at `addr`.
+pub fn sys_bind(fd: usize, addr: *const u8, addrlen: usize) -> isize {
+    if let Some(socket) = tcp_socket(fd) {
+        return match read_inet_addr(addr, addrlen) {
+            Ok(addr) if TcpSocket::bind(&socket, addr) => 0,
+            Ok(_) => -1,
+            Err(err) => err,
+        };
+    }
+    if let Some(socket) = udp_socket(fd) {
+        return match read_inet_addr(addr, addrlen) {
+            Ok(addr) if UdpSocket::bind(&socket, addr) => 0,
+            Ok(_) => -1,
+            Err(err) => err,
+        };
+    }
+    let socket = match local_socket(fd) {
+        Some(socket) => socket,
+        None => return -1,
+    };
+    let path = match read_local_addr(addr, addrlen) {
+        Ok(path) => path,
+        Err(err) => return err,
+    };
+    if LocalSocket::bind(&socket, path) {
+        0
+    } else {
+        -1
+    }
+}
+
+pub fn sys_listen(fd: usize, backlog: usize) -> isize {
+    if let Some(socket) = tcp_socket(fd) {
+        return if TcpSocket::listen(&socket, backlog) {
+            0
+        } else {
+            -1
+        };
+    }
+    match local_socket(fd) {
+        Some(socket) if socket.listen(backlog) => 0,
+        _ => -1,
+    }
+}
+
+/// Wait for a connection to the listening socket `fd` and return the
+/// fd of a new socket connected to the peer. For TCP the address of the
+/// peer goes to `addr` unless it is null, as `sys_recvfrom` stores it.
+pub fn sys_accept(fd: usize, addr: *mut u8, addrlen: *mut u32) -> isize {
+    let socket: Arc<dyn File + Send + Sync> = if let Some(socket) = tcp_socket(fd) {
+        let (socket, peer) = match socket.accept() {
+            Ok(accepted) => accepted,
+            Err(err) => return err,
+        };
+        if !addr.is_null() {
+            if let Err(err) = write_inet_addr(addr, addrlen, peer) {
+                return err;
+            }
+        }
+        socket
+    } else {
+        match local_socket(fd).map(|socket| socket.accept()) {
+            Some(Ok(socket)) => socket,
+            Some(Err(err)) => return err,
+            None => return -1,
         }
+    };
+    let process = current_process();
+    let mut inner = process.inner_exclusive_access();
+    let fd = match inner.alloc_fd() {
+        Some(fd) => fd,
+        None => return -1,
+    };
+    inner.fd_table[fd] = Some(socket);
+    fd as isize
+}
+
+/// Connect the socket `fd` to the one listening at the path at `addr`,
+/// or the TCP socket `fd` to the address at `addr`.
+pub fn sys_connect(fd: usize, addr: *const u8, addrlen: usize) -> isize {
+    if let Some(socket) = tcp_socket(fd) {
+        return match read_inet_addr(addr, addrlen) {
+            Ok(addr) => match TcpSocket::connect(&socket, addr) {
+                Ok(()) => 0,
+                Err(err) => err,
+            },
+            Err(err) => err,
+        };
+    }
+    let socket = match local_socket(fd) {
+        Some(socket) => socket,
+        None => return -1,
+    };
+    let path = match read_local_addr(addr, addrlen) {
+        Ok(path) => path,
+        Err(err) => return err,
+    };
+    if socket.connect(&path) {
+        0
+    } else {
+        -1
     }
+}
+
+/// Do not wait for a datagram even if the socket blocks.
+const MSG_DONTWAIT: u32 = 0x40;
 
-    let cx = current_trap_cx();
-    cx.x[10] as isize
+/// Send `len` bytes at `buf` as one datagram from the UDP socket `fd` to
+/// the address at `addr`.
+pub fn sys_sendto(
+    fd: usize,
+    buf: *const u8,
+    len: usize,
+    flags: u32,
+    addr: *const u8,
+    addrlen: usize,
+) -> isize {
+    let socket = match udp_socket(fd) {
+        Some(socket) if flags & !MSG_DONTWAIT == 0 => socket,
+        _ => return -1,
+    };
+    let dst = match read_inet_addr(addr, addrlen) {
+        Ok(dst) => dst,
+        Err(err) => return err,
+    };
+    let payload = match UserBuffer::new(current_user_token(), buf, len, false) {
+        Ok(buffer) => buffer.buffers.concat(),
+        Err(err) => return err,
+    };
+    if UdpSocket::send_to(&socket, &payload, dst) {
+        len as isize
+    } else {
+        -1
+    }
+}
+
+/// Take the next datagram of the UDP socket `fd` into `len` bytes at
+/// `buf`, dropping the rest of it, and store who sent it at `addr` unless
+/// it is null. Returns how many bytes were taken.
+pub fn sys_recvfrom(
+    fd: usize,
+    buf: *mut u8,
+    len: usize,
+    flags: u32,
+    addr: *mut u8,
+    addrlen: *mut u32,
+) -> isize {
+    let socket = match udp_socket(fd) {
+        Some(socket) if flags & !MSG_DONTWAIT == 0 => socket,
+        _ => return -1,
+    };
+    let wait = flags & MSG_DONTWAIT == 0 && !socket.nonblocking();
+    let (sender, data) = match socket.receive_from(wait) {
+        Ok(datagram) => datagram,
+        Err(err) => return err,
+    };
+    let data = &data[..len.min(data.len())];
+    if let Err(err) = copy_bytes_to_user(current_user_token(), buf, data) {
+        return err;
+    }
+    if !addr.is_null() {
+        if let Err(err) = write_inet_addr(addr, addrlen, sender) {
+            return err;
+        }
+    }
+    data.len() as isize
 }
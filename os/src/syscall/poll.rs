@@ -0,0 +1,222 @@
+//! Waiting on several fds at once. Both calls come down to `poll_files`,
+//! which sleeps on one `PollWaker` registered with all the files.
+
+use crate::fs::{File, PollFlags};
+use crate::mm::{copy_from_user, copy_to_user};
+use crate::sync::PollWaker;
+use crate::task::{current_process, current_user_token, RLIMIT_NOFILE};
+use crate::timer::{get_time_ms, TimeSpec};
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+
+/// An entry of the `sys_ppoll` array, laid out as `struct pollfd`.
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct PollFd {
+    /// negative to skip the entry
+    fd: i32,
+    events: i16,
+    revents: i16,
+}
+
+type PolledFile = Option<Arc<dyn File + Send + Sync>>;
+
+/// The open files of `fds`, none for those that are not open.
+fn polled_files(fds: impl Iterator<Item = usize>) -> Vec<PolledFile> {
+    let process = current_process();
+    let inner = process.inner_exclusive_access();
+    fds.map(|fd| inner.fd_table.get(fd).cloned().flatten())
+        .collect()
+}
+
+/// When a wait for `timeout` from now ends, none if it is null.
+fn expire_ms_of(timeout: *const TimeSpec) -> Result<Option<usize>, isize> {
+    if timeout.is_null() {
+        return Ok(None);
+    }
+    match copy_from_user(current_user_token(), timeout)? {
+        timeout if timeout.is_valid() => Ok(Some(get_time_ms() + timeout.to_ms())),
+        _ => Err(-1),
+    }
+}
+
+/// Wait until one of `files` is ready for the events asked of it, or
+/// until `expire_ms`. Returns what each is ready for, with `ERR` and
+/// `HUP` always reported and `NVAL` for the fds not open, or none if a
+/// signal came first.
+fn poll_files(
+    files: &[(PolledFile, PollFlags)],
+    expire_ms: Option<usize>,
+) -> Option<Vec<PollFlags>> {
+    let waker = Arc::new(PollWaker::new());
+    if let Some(expire_ms) = expire_ms {
+        waker.wake_at(expire_ms);
+    }
+    loop {
+        waker.reset();
+        // registered before looking, so a file getting ready after is seen
+        let revents: Vec<PollFlags> = files
+            .iter()
+            .map(|(file, events)| match file {
+                Some(file) => {
+                    file.register_waker(&waker);
+                    file.poll() & (*events | PollFlags::ERR | PollFlags::HUP)
+                }
+                None => PollFlags::NVAL,
+            })
+            .collect();
+        let expired = expire_ms.map_or(false, |expire_ms| get_time_ms() >= expire_ms);
+        if expired || revents.iter().any(|revents| !revents.is_empty()) {
+            return Some(revents);
+        }
+        if !waker.wait() {
+            return None;
+        }
+    }
+}
+
+/// Wait for any of the `nfds` fds in `fds` to be ready for its events,
+/// for at most `timeout` unless it is null, and return how many are.
+/// Changing the signal mask meanwhile is not supported, so `sigmask`
+/// must be null.
+pub fn sys_ppoll(fds: *mut PollFd, nfds: usize, timeout: *const TimeSpec, sigmask: usize) -> isize {
+    if sigmask != 0 || nfds > current_process().inner_exclusive_access().rlimits[RLIMIT_NOFILE].cur
+    {
+        return -1;
+    }
+    let token = current_user_token();
+    let mut entries = Vec::with_capacity(nfds);
+    for i in 0..nfds {
+        match copy_from_user(token, fds.wrapping_add(i)) {
+            Ok(entry) => entries.push(entry),
+            Err(err) => return err,
+        }
+    }
+    let expire_ms = match expire_ms_of(timeout) {
+        Ok(expire_ms) => expire_ms,
+        Err(err) => return err,
+    };
+    let files = polled_files(entries.iter().map(|entry| entry.fd as usize));
+    let polled: Vec<(PolledFile, PollFlags)> = entries
+        .iter()
+        .zip(files)
+        .filter(|(entry, _)| entry.fd >= 0)
+        .map(|(entry, file)| (file, PollFlags::from_bits_truncate(entry.events as u16)))
+        .collect();
+    let mut revents = match poll_files(&polled, expire_ms) {
+        Some(revents) => revents.into_iter(),
+        None => return -1,
+    };
+    let mut ready = 0;
+    for (i, entry) in entries.iter_mut().enumerate() {
+        entry.revents = if entry.fd >= 0 {
+            revents.next().unwrap().bits() as i16
+        } else {
+            0
+        };
+        if entry.revents != 0 {
+            ready += 1;
+        }
+        if let Err(err) = copy_to_user(token, fds.wrapping_add(i), *entry) {
+            return err;
+        }
+    }
+    ready
+}
+
+/// Bits of an `fd_set`.
+const FD_SET_WORD_BITS: usize = u64::BITS as usize;
+
+/// The fds below `nfds` in the `fd_set` at `set`, none if it is null.
+fn read_fd_set(set: *mut u64, nfds: usize) -> Result<Vec<usize>, isize> {
+    let mut fds = Vec::new();
+    if set.is_null() {
+        return Ok(fds);
+    }
+    let token = current_user_token();
+    for word_idx in 0..nfds.div_ceil(FD_SET_WORD_BITS) {
+        let word = copy_from_user(token, set.wrapping_add(word_idx))?;
+        for bit in 0..FD_SET_WORD_BITS {
+            let fd = word_idx * FD_SET_WORD_BITS + bit;
+            if fd < nfds && word & (1 << bit) != 0 {
+                fds.push(fd);
+            }
+        }
+    }
+    Ok(fds)
+}
+
+/// Leave only `fds` in the `fd_set` at `set`, unless it is null.
+fn write_fd_set(set: *mut u64, nfds: usize, fds: &[usize]) -> Result<(), isize> {
+    if set.is_null() {
+        return Ok(());
+    }
+    let token = current_user_token();
+    for word_idx in 0..nfds.div_ceil(FD_SET_WORD_BITS) {
+        let word = fds
+            .iter()
+            .filter(|&&fd| fd / FD_SET_WORD_BITS == word_idx)
+            .fold(0u64, |word, fd| word | 1 << (fd % FD_SET_WORD_BITS));
+        copy_to_user(token, set.wrapping_add(word_idx), word)?;
+    }
+    Ok(())
+}
+
+/// Wait for any fd below `nfds` in `readfds` to be readable or in
+/// `writefds` to be writable, for at most `timeout` unless it is null.
+/// The sets are left with the ready fds only, and how many there are in
+/// all is returned. Nothing is ever exceptional, and the signal mask of
+/// Linux is not supported.
+pub fn sys_pselect6(
+    nfds: usize,
+    readfds: *mut u64,
+    writefds: *mut u64,
+    exceptfds: *mut u64,
+    timeout: *const TimeSpec,
+) -> isize {
+    if nfds > current_process().inner_exclusive_access().rlimits[RLIMIT_NOFILE].cur {
+        return -1;
+    }
+    let (read_fds, write_fds) = match (read_fd_set(readfds, nfds), read_fd_set(writefds, nfds)) {
+        (Ok(read_fds), Ok(write_fds)) => (read_fds, write_fds),
+        (Err(err), _) | (_, Err(err)) => return err,
+    };
+    let expire_ms = match expire_ms_of(timeout) {
+        Ok(expire_ms) => expire_ms,
+        Err(err) => return err,
+    };
+    let files = polled_files(read_fds.iter().chain(write_fds.iter()).copied());
+    // select fails on fds that are not open instead of reporting them
+    if files.iter().any(Option::is_none) {
+        return -1;
+    }
+    let events = read_fds
+        .iter()
+        .map(|_| PollFlags::IN)
+        .chain(write_fds.iter().map(|_| PollFlags::OUT));
+    let polled: Vec<(PolledFile, PollFlags)> = files.into_iter().zip(events).collect();
+    let revents = match poll_files(&polled, expire_ms) {
+        Some(revents) => revents,
+        None => return -1,
+    };
+    let (read_revents, write_revents) = revents.split_at(read_fds.len());
+    let ready_read: Vec<usize> = read_fds
+        .iter()
+        .zip(read_revents)
+        .filter(|(_, revents)| !revents.is_empty())
+        .map(|(&fd, _)| fd)
+        .collect();
+    let ready_write: Vec<usize> = write_fds
+        .iter()
+        .zip(write_revents)
+        .filter(|(_, revents)| revents.intersects(PollFlags::OUT | PollFlags::ERR))
+        .map(|(&fd, _)| fd)
+        .collect();
+    let written = write_fd_set(readfds, nfds, &ready_read)
+        .and_then(|_| write_fd_set(writefds, nfds, &ready_write))
+        .and_then(|_| write_fd_set(exceptfds, nfds, &[]));
+    match written {
+        Ok(()) => (ready_read.len() + ready_write.len()) as isize,
+        Err(err) => err,
+    }
+}
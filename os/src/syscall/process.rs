@@ -1,26 +1,56 @@
-use crate::fs::{open_file, OpenFlags};
-use crate::mm::{translated_ref, translated_refmut, translated_str};
+use crate::config::{KMSG_SIZE, PAGE_SIZE, USER_SPACE_END};
+use crate::fs::{absolute_path, open_file, OpenFlags};
+use crate::ipi::online_harts;
+use crate::logging::{kmsg, kmsg_clear};
+use crate::mm::{
+    copy_bytes_to_user, copy_from_user, copy_to_user, meminfo, read_user_str, reclaim,
+    MapPermission, PhysPageNum, UserPtr,
+};
+use crate::perf::{PerfEvent, PerfEventKind};
 use crate::task::{
-    current_process, current_task, current_user_token, exit_current_and_run_next, pid2process,
-    suspend_current_and_run_next, SignalFlags,
+    account_kernel, current_process, current_task, current_user_token, exit_current_and_run_next,
+    exit_group_and_run_next, hart_id, pid2process, process_group, schedule, set_task_affinity,
+    set_task_deadline, set_task_priority, surrender_slice, suspend_current_and_run_next,
+    ObjectKind, Pidfd, ProcessControlBlock, RLimit, RUsage, SignalAction, SignalFlags,
+    TaskControlBlock, Tms, ALL_HARTS, MAX_PRIORITY, MIN_PRIORITY, RLIM_NLIMITS,
+};
+use crate::timer::{
+    get_time, ns_to_time, time_to_ticks, ClockId, ITimerSpec, ITimerVal, PosixTimer, SigEvent,
+    TimeSpec, TimeVal, SIGEV_NONE, SIGEV_SIGNAL, SIGEV_THREAD_ID,
 };
-use crate::timer::get_time_ms;
 use alloc::string::String;
 use alloc::sync::Arc;
+use alloc::vec;
 use alloc::vec::Vec;
+use core::sync::atomic::Ordering;
 
 pub fn sys_exit(exit_code: i32) -> ! {
     exit_current_and_run_next(exit_code);
     panic!("Unreachable in sys_exit!");
 }
 
+/// End the process with all its threads, reporting `exit_code` to the
+/// parent.
+pub fn sys_exit_group(exit_code: i32) -> ! {
+    exit_group_and_run_next(exit_code);
+    panic!("Unreachable in sys_exit_group!");
+}
+
+/// Give up the rest of the time slice.
 pub fn sys_yield() -> isize {
+    surrender_slice();
     suspend_current_and_run_next();
     0
 }
 
-pub fn sys_get_time() -> isize {
-    get_time_ms() as isize
+/// Read the wall-clock time into `tv`. There are no time zones, so `tz`
+/// is ignored.
+pub fn sys_gettimeofday(tv: *mut TimeVal, _tz: usize) -> isize {
+    let now_ns = ClockId::Realtime.now_ns();
+    match copy_to_user(current_user_token(), tv, TimeVal::from_ns(now_ns)) {
+        Ok(()) => 0,
+        Err(err) => err,
+    }
 }
 
 pub fn sys_getpid() -> isize {
@@ -29,7 +59,16 @@ pub fn sys_getpid() -> isize {
 
 pub fn sys_fork() -> isize {
     let current_process = current_process();
-    let new_process = current_process.fork();
+    // make room for copying the resident pages
+    let pages = current_process
+        .inner_exclusive_access()
+        .memory_set
+        .rss_pages();
+    reclaim(pages);
+    let new_process = match current_process.fork() {
+        Ok(process) => process,
+        Err(_) => return -1,
+    };
     let new_pid = new_process.getpid();
     // modify trap context of new_task, because it returns immediately after switching
     let new_process_inner = new_process.inner_exclusive_access();
@@ -41,77 +80,1067 @@ pub fn sys_fork() -> isize {
     new_pid as isize
 }
 
-pub fn sys_exec(path: *const u8, mut args: *const usize) -> isize {
-    let token = current_user_token();
-    let path = translated_str(token, path);
-    let mut args_vec: Vec<String> = Vec::new();
+/// Read the NULL terminated array of strings at `ptr`, none if it is null.
+fn read_user_str_array(token: usize, ptr: *const usize) -> Result<Vec<String>, isize> {
+    let mut strings = Vec::new();
+    let mut ptr = UserPtr::new(token, ptr);
+    if ptr.is_null() {
+        return Ok(strings);
+    }
     loop {
-        let arg_str_ptr = *translated_ref(token, args);
-        if arg_str_ptr == 0 {
+        let str_ptr = ptr.read()?;
+        if str_ptr == 0 {
             break;
         }
-        args_vec.push(translated_str(token, arg_str_ptr as *const u8));
-        unsafe {
-            args = args.add(1);
+        strings.push(read_user_str(token, str_ptr as *const u8)?);
+        ptr = ptr.add(1);
+    }
+    Ok(strings)
+}
+
+/// The path, arguments and environment passed to exec or spawn.
+fn read_exec_args(
+    token: usize,
+    path: *const u8,
+    args: *const usize,
+    envs: *const usize,
+) -> Result<(String, Vec<String>, Vec<String>), isize> {
+    Ok((
+        read_user_str(token, path)?,
+        read_user_str_array(token, args)?,
+        read_user_str_array(token, envs)?,
+    ))
+}
+
+pub fn sys_exec(path: *const u8, args: *const usize, envs: *const usize) -> isize {
+    let token = current_user_token();
+    let (path, args_vec, envs_vec) = match read_exec_args(token, path, args, envs) {
+        Ok(exec_args) => exec_args,
+        Err(err) => return err,
+    };
+    let process = current_process();
+    let path = absolute_path(&process.inner_exclusive_access().cwd, &path);
+    match open_file(path.as_str(), OpenFlags::RDONLY) {
+        Some(app_inode) if !app_inode.inode().is_dir() => {
+            reclaim(0);
+            let argc = args_vec.len();
+            match process.exec(&app_inode, args_vec, envs_vec) {
+                // return argc because cx.x[10] will be covered with it later
+                Ok(()) => argc as isize,
+                Err(_) => -1,
+            }
         }
+        _ => -1,
+    }
+}
+
+/// Start `path` in a new child process, without the copy of the address
+/// space fork would make. Returns the pid of the child.
+pub fn sys_spawn(path: *const u8, args: *const usize, envs: *const usize) -> isize {
+    let token = current_user_token();
+    let (path, args_vec, envs_vec) = match read_exec_args(token, path, args, envs) {
+        Ok(exec_args) => exec_args,
+        Err(err) => return err,
+    };
+    let process = current_process();
+    let path = absolute_path(&process.inner_exclusive_access().cwd, &path);
+    match open_file(path.as_str(), OpenFlags::RDONLY) {
+        Some(app_inode) if !app_inode.inode().is_dir() => {
+            reclaim(0);
+            match process.spawn(&app_inode, args_vec, envs_vec) {
+                Ok(child) => child.getpid() as isize,
+                Err(_) => -1,
+            }
+        }
+        _ => -1,
+    }
+}
+
+/// Return at once if no child has exited yet.
+const WNOHANG: usize = 1;
+/// Also report a child that has stopped.
+const WUNTRACED: usize = 2;
+
+/// Reap an exited child, the one with `pid` or any if it is -1, writing
+/// its exit status to `exit_status_ptr` unless that is null. Sleeps until
+/// one exits, or returns 0 with `WNOHANG`. With `WUNTRACED` a stop not
+/// reported yet is returned too, leaving the child in place. Returns -1 if
+/// there is no such child.
+pub fn sys_waitpid(pid: isize, exit_status_ptr: *mut i32, options: usize) -> isize {
+    if options & !(WNOHANG | WUNTRACED) != 0 {
+        return -1;
+    }
+    let process = current_process();
+    loop {
+        let mut inner = process.inner_exclusive_access();
+        if !inner
+            .children
+            .iter()
+            .any(|p| pid == -1 || pid as usize == p.getpid())
+        {
+            return -1;
+        }
+        let pair = inner.children.iter().enumerate().find(|(_, p)| {
+            // ++++ temporarily access child PCB exclusively
+            p.inner_exclusive_access().is_zombie && (pid == -1 || pid as usize == p.getpid())
+            // ++++ release child PCB
+        });
+        if let Some((idx, _)) = pair {
+            let child = inner.children.remove(idx);
+            // confirm that child will be deallocated after being removed from children list
+            assert_eq!(Arc::strong_count(&child), 1);
+            let found_pid = child.getpid();
+            let child_inner = child.inner_exclusive_access();
+            let exit_status = child_inner.exit_status;
+            let child_times = child_inner.cpu_times() + child_inner.children_times;
+            let child_max_rss = child_inner
+                .max_rss_pages()
+                .max(child_inner.children_max_rss);
+            drop(child_inner);
+            inner.children_times += child_times;
+            inner.children_max_rss = inner.children_max_rss.max(child_max_rss);
+            let token = inner.memory_set.token();
+            // writing the exit status may fault the page in, which needs inner
+            drop(inner);
+            if !exit_status_ptr.is_null() {
+                if let Err(err) = copy_to_user(token, exit_status_ptr, exit_status) {
+                    return err;
+                }
+            }
+            return found_pid as isize;
+        }
+        if options & WUNTRACED != 0 {
+            let stopped = inner.children.iter().find_map(|p| {
+                if pid != -1 && pid as usize != p.getpid() {
+                    return None;
+                }
+                let stop_signal = p.inner_exclusive_access().stop_signal.take()?;
+                Some((p.getpid(), stop_signal))
+            });
+            if let Some((found_pid, stop_signal)) = stopped {
+                let token = inner.memory_set.token();
+                drop(inner);
+                let status = ((stop_signal.signum() as i32) << 8) | 0x7f;
+                if !exit_status_ptr.is_null() {
+                    if let Err(err) = copy_to_user(token, exit_status_ptr, status) {
+                        return err;
+                    }
+                }
+                return found_pid as isize;
+            }
+        }
+        if options & WNOHANG != 0 {
+            return 0;
+        }
+        // exiting children broadcast with our inner held, none is missed
+        let task_cx_ptr = process.child_exited.wait_no_sched();
+        drop(inner);
+        schedule(task_cx_ptr);
+    }
+}
+
+/// Get the limit of `resource` for the process `pid`, the caller if 0,
+/// into `old_limit` and then set it to `new_limit`, either may be null.
+/// A hard limit can only be lowered.
+pub fn sys_prlimit64(
+    pid: usize,
+    resource: usize,
+    new_limit: *const RLimit,
+    old_limit: *mut RLimit,
+) -> isize {
+    if resource >= RLIM_NLIMITS {
+        return -1;
     }
-    if let Some(app_inode) = open_file(path.as_str(), OpenFlags::RDONLY) {
-        let all_data = app_inode.read_all();
-        let process = current_process();
-        let argc = args_vec.len();
-        process.exec(all_data.as_slice(), args_vec);
-        // return argc because cx.x[10] will be covered with it later
-        argc as isize
+    let process = if pid == 0 {
+        current_process()
     } else {
-        -1
+        match pid2process(pid) {
+            Some(process) => process,
+            None => return -1,
+        }
+    };
+    let token = current_user_token();
+    let new = if new_limit.is_null() {
+        None
+    } else {
+        match copy_from_user(token, new_limit) {
+            Ok(new) => Some(new),
+            Err(err) => return err,
+        }
+    };
+    let old = {
+        let mut inner = process.inner_exclusive_access();
+        let old = inner.rlimits[resource];
+        if let Some(new) = new {
+            if new.cur > new.max || new.max > old.max {
+                return -1;
+            }
+            inner.rlimits[resource] = new;
+        }
+        old
+    };
+    if old_limit.is_null() {
+        return 0;
+    }
+    match copy_to_user(token, old_limit, old) {
+        Ok(()) => 0,
+        Err(err) => err,
+    }
+}
+
+/// Fill `buf` with the CPU times of the caller and its reaped children.
+/// Returns the clock ticks since boot.
+pub fn sys_times(buf: *mut Tms) -> isize {
+    let token = current_user_token();
+    account_kernel(&mut current_task().unwrap().inner_exclusive_access());
+    let process = current_process();
+    let tms = {
+        let inner = process.inner_exclusive_access();
+        Tms::new(inner.cpu_times(), inner.children_times)
+    };
+    if !buf.is_null() {
+        if let Err(err) = copy_to_user(token, buf, tms) {
+            return err;
+        }
+    }
+    time_to_ticks(get_time()) as isize
+}
+
+const RUSAGE_SELF: isize = 0;
+/// The reaped children, with their own children.
+const RUSAGE_CHILDREN: isize = -1;
+const RUSAGE_THREAD: isize = 1;
+
+/// Fill `usage` with the CPU times and peak resident size of `who`.
+pub fn sys_getrusage(who: isize, usage: *mut RUsage) -> isize {
+    if usage.is_null() {
+        return -1;
+    }
+    let token = current_user_token();
+    let task = current_task().unwrap();
+    account_kernel(&mut task.inner_exclusive_access());
+    let process = current_process();
+    let (times, max_rss) = {
+        let inner = process.inner_exclusive_access();
+        match who {
+            RUSAGE_SELF => (inner.cpu_times(), inner.max_rss_pages()),
+            RUSAGE_CHILDREN => (inner.children_times, inner.children_max_rss),
+            RUSAGE_THREAD => (task.inner_exclusive_access().times, inner.max_rss_pages()),
+            _ => return -1,
+        }
+    };
+    let usage_value = RUsage::new(times, max_rss * PAGE_SIZE / 1024);
+    match copy_to_user(token, usage, usage_value) {
+        Ok(()) => 0,
+        Err(err) => err,
+    }
+}
+
+/// Send `signal` to the process `pid`, to the process group `-pid` if it
+/// is negative or to the caller's own group if it is 0. Sending to every
+/// process with -1 is not supported.
+pub fn sys_kill(pid: isize, signal: u32) -> isize {
+    let flags = match SignalFlags::from_bits(signal) {
+        Some(flags) => flags,
+        None => return -1,
+    };
+    let targets = match pid {
+        -1 => return -1,
+        0 => process_group(current_process().inner_exclusive_access().pgid),
+        pid if pid < 0 => process_group(-pid as usize),
+        pid => pid2process(pid as usize).into_iter().collect(),
+    };
+    if targets.is_empty() {
+        return -1;
+    }
+    for process in targets {
+        send_signals(&process, flags);
+    }
+    0
+}
+
+fn send_signals(process: &ProcessControlBlock, flags: SignalFlags) {
+    for signum in 1..32 {
+        let signal = SignalFlags::from_bits_truncate(1 << signum);
+        if flags.contains(signal) {
+            process.send_signal(signal);
+        }
     }
 }
 
-/// If there is not a child process whose pid is same as given, return -1.
-/// Else if there is a child process but it is still running, return -2.
-pub fn sys_waitpid(pid: isize, exit_code_ptr: *mut i32) -> isize {
+/// A handle to the process `pid` that keeps referring to it, and to no
+/// other process, after it exits.
+pub fn sys_pidfd_open(pid: usize, flags: usize) -> isize {
+    if flags != 0 {
+        return -1;
+    }
+    let process = match pid2process(pid) {
+        Some(process) => process,
+        None => return -1,
+    };
+    current_process()
+        .inner_exclusive_access()
+        .handles
+        .insert(Arc::new(Pidfd::new(&process))) as isize
+}
+
+/// `sys_kill` for the process of `pidfd`, failing once it has exited.
+/// There is no `siginfo` to pass along, so `info` must be null.
+pub fn sys_pidfd_send_signal(pidfd: usize, signal: u32, info: usize, flags: usize) -> isize {
+    let signals = match SignalFlags::from_bits(signal) {
+        Some(signals) if info == 0 && flags == 0 => signals,
+        _ => return -1,
+    };
+    let pidfd = current_process()
+        .inner_exclusive_access()
+        .handles
+        .get_as::<Pidfd>(ObjectKind::Pidfd, pidfd);
+    match pidfd.and_then(|pidfd| pidfd.process()) {
+        Some(process) => {
+            send_signals(&process, signals);
+            0
+        }
+        None => -1,
+    }
+}
+
+/// Move the process `pid`, the caller if 0, into the group `pgid`, a new
+/// group of its own if 0. Only the caller and its children can be moved,
+/// within their session.
+pub fn sys_setpgid(pid: usize, pgid: usize) -> isize {
     let process = current_process();
-    // find a child process
+    let target = if pid == 0 || pid == process.getpid() {
+        Arc::clone(&process)
+    } else {
+        match process
+            .inner_exclusive_access()
+            .children
+            .iter()
+            .find(|child| child.getpid() == pid)
+        {
+            Some(child) => Arc::clone(child),
+            None => return -1,
+        }
+    };
+    let target_pid = target.getpid();
+    let pgid = if pgid == 0 { target_pid } else { pgid };
+    let sid = target.inner_exclusive_access().sid;
+    // a session leader stays the leader of its group
+    if sid == target_pid || sid != process.inner_exclusive_access().sid {
+        return -1;
+    }
+    if pgid != target_pid
+        && !process_group(pgid)
+            .iter()
+            .any(|member| member.inner_exclusive_access().sid == sid)
+    {
+        return -1;
+    }
+    target.inner_exclusive_access().pgid = pgid;
+    0
+}
+
+/// The process group of the process `pid`, the caller if 0.
+pub fn sys_getpgid(pid: usize) -> isize {
+    let process = if pid == 0 {
+        current_process()
+    } else {
+        match pid2process(pid) {
+            Some(process) => process,
+            None => return -1,
+        }
+    };
+    let pgid = process.inner_exclusive_access().pgid;
+    pgid as isize
+}
 
+/// Start a new session, and a group in it, led by the caller. A group
+/// leader cannot, its group would be split between two sessions.
+pub fn sys_setsid() -> isize {
+    let process = current_process();
+    let pid = process.getpid();
+    if !process_group(pid).is_empty() {
+        return -1;
+    }
     let mut inner = process.inner_exclusive_access();
-    if !inner
-        .children
+    inner.pgid = pid;
+    inner.sid = pid;
+    pid as isize
+}
+
+/// Flag of the single, catchable signal `signal`.
+fn catchable_signal(signal: u32) -> Option<SignalFlags> {
+    match SignalFlags::from_bits(signal) {
+        Some(flag) if signal.count_ones() == 1 && !SignalFlags::UNBLOCKABLE.contains(flag) => {
+            Some(flag)
+        }
+        _ => None,
+    }
+}
+
+/// Install `action` for `signal` if it is not null, after storing the
+/// previous action in `old_action` if that is not null.
+pub fn sys_sigaction(
+    signal: u32,
+    action: *const SignalAction,
+    old_action: *mut SignalAction,
+) -> isize {
+    let flag = match catchable_signal(signal) {
+        Some(flag) => flag,
+        None => return -1,
+    };
+    let token = current_user_token();
+    let new_action = if action.is_null() {
+        None
+    } else {
+        let mut new_action: SignalAction = match copy_from_user(token, action) {
+            Ok(new_action) => new_action,
+            Err(err) => return err,
+        };
+        new_action.mask =
+            SignalFlags::from_bits_truncate(new_action.mask.bits()) - SignalFlags::UNBLOCKABLE;
+        Some(new_action)
+    };
+    let process = current_process();
+    let mut process_inner = process.inner_exclusive_access();
+    let prev_action = process_inner.signal_actions[flag.signum()];
+    if let Some(new_action) = new_action {
+        process_inner.signal_actions[flag.signum()] = new_action;
+    }
+    drop(process_inner);
+    if old_action.is_null() {
+        return 0;
+    }
+    match copy_to_user(token, old_action, prev_action) {
+        Ok(()) => 0,
+        Err(err) => err,
+    }
+}
+
+/// Replace the signal mask of the current process, returning the old one.
+pub fn sys_sigprocmask(mask: u32) -> isize {
+    let mask = match SignalFlags::from_bits(mask) {
+        Some(mask) => mask - SignalFlags::UNBLOCKABLE,
+        None => return -1,
+    };
+    let process = current_process();
+    let mut process_inner = process.inner_exclusive_access();
+    let old_mask = process_inner.signal_mask;
+    process_inner.signal_mask = mask;
+    old_mask.bits() as isize
+}
+
+/// Return from a signal handler to the context it interrupted.
+pub fn sys_sigreturn() -> isize {
+    let task = current_task().unwrap();
+    let mut task_inner = task.inner_exclusive_access();
+    let (trap_cx, mask) = match task_inner.signal_backup.take() {
+        Some(backup) => backup,
+        None => return -1,
+    };
+    *task_inner.get_trap_cx() = trap_cx;
+    drop(task_inner);
+    current_process().inner_exclusive_access().signal_mask = mask;
+    // the syscall return value goes to a0, keep the interrupted one
+    trap_cx.x[10] as isize
+}
+
+pub const PRIO_PROCESS: usize = 0;
+
+/// The process selected by `which`/`who`, `who` 0 meaning the caller.
+fn priority_target(which: usize, who: usize) -> Option<Arc<ProcessControlBlock>> {
+    match (which, who) {
+        (PRIO_PROCESS, 0) => Some(current_process()),
+        (PRIO_PROCESS, pid) => pid2process(pid),
+        _ => None,
+    }
+}
+
+/// Set the scheduling priority of every thread of the target process.
+pub fn sys_setpriority(which: usize, who: usize, priority: usize) -> isize {
+    if !(MIN_PRIORITY..=MAX_PRIORITY).contains(&priority) {
+        return -1;
+    }
+    if let Some(process) = priority_target(which, who) {
+        let process_inner = process.inner_exclusive_access();
+        for task in process_inner.tasks.iter().flatten() {
+            set_task_priority(task, priority);
+        }
+        0
+    } else {
+        -1
+    }
+}
+
+/// Return the highest priority among the threads of the target process.
+pub fn sys_getpriority(which: usize, who: usize) -> isize {
+    if let Some(process) = priority_target(which, who) {
+        let process_inner = process.inner_exclusive_access();
+        process_inner
+            .tasks
+            .iter()
+            .flatten()
+            .map(|task| task.inner_exclusive_access().priority)
+            .max()
+            .map_or(-1, |priority| priority as isize)
+    } else {
+        -1
+    }
+}
+
+/// The threads `pid` selects for the affinity calls, 0 meaning the caller
+/// alone.
+fn affinity_targets(pid: usize) -> Option<Vec<Arc<TaskControlBlock>>> {
+    if pid == 0 {
+        return Some(vec![current_task().unwrap()]);
+    }
+    let process = pid2process(pid)?;
+    let process_inner = process.inner_exclusive_access();
+    Some(process_inner.tasks.iter().flatten().cloned().collect())
+}
+
+/// Pin the target threads to the harts set in the mask at `mask`, at
+/// least one of which must be online. The caller moves off a hart it may
+/// no longer run on right away.
+pub fn sys_sched_setaffinity(pid: usize, size: usize, mask: *const usize) -> isize {
+    if size < core::mem::size_of::<usize>() {
+        return -1;
+    }
+    let affinity = match copy_from_user(current_user_token(), mask) {
+        Ok(affinity) => affinity & ALL_HARTS,
+        Err(err) => return err,
+    };
+    if affinity & online_harts() == 0 {
+        return -1;
+    }
+    let tasks = match affinity_targets(pid) {
+        Some(tasks) => tasks,
+        None => return -1,
+    };
+    // deadline threads keep the hart they were admitted on
+    if tasks
         .iter()
-        .any(|p| pid == -1 || pid as usize == p.getpid())
+        .any(|task| task.inner_exclusive_access().deadline.is_some())
     {
         return -1;
-        // ---- release current PCB
     }
-    let pair = inner.children.iter().enumerate().find(|(_, p)| {
-        // ++++ temporarily access child PCB exclusively
-        p.inner_exclusive_access().is_zombie && (pid == -1 || pid as usize == p.getpid())
-        // ++++ release child PCB
+    for task in tasks.iter() {
+        set_task_affinity(task, affinity);
+    }
+    if affinity & 1 << hart_id() == 0 {
+        suspend_current_and_run_next();
+    }
+    0
+}
+
+/// Write the harts any of the target threads may run on to `mask`,
+/// returning the bytes written.
+pub fn sys_sched_getaffinity(pid: usize, size: usize, mask: *mut usize) -> isize {
+    if size < core::mem::size_of::<usize>() {
+        return -1;
+    }
+    let tasks = match affinity_targets(pid) {
+        Some(tasks) => tasks,
+        None => return -1,
+    };
+    let affinity = tasks.iter().fold(0, |affinity, task| {
+        affinity | task.inner_exclusive_access().affinity
     });
-    if let Some((idx, _)) = pair {
-        let child = inner.children.remove(idx);
-        // confirm that child will be deallocated after being removed from children list
-        assert_eq!(Arc::strong_count(&child), 1);
-        let found_pid = child.getpid();
-        // ++++ temporarily access child PCB exclusively
-        let exit_code = child.inner_exclusive_access().exit_code;
-        // ++++ release child PCB
-        *translated_refmut(inner.memory_set.token(), exit_code_ptr) = exit_code;
-        found_pid as isize
-    } else {
-        -2
-    }
-    // ---- release current PCB automatically
-}
-
-pub fn sys_kill(pid: usize, signal: u32) -> isize {
-    if let Some(process) = pid2process(pid) {
-        if let Some(flag) = SignalFlags::from_bits(signal) {
-            process.inner_exclusive_access().signals |= flag;
+    match copy_to_user(current_user_token(), mask, affinity) {
+        Ok(()) => core::mem::size_of::<usize>() as isize,
+        Err(err) => err,
+    }
+}
+
+pub const SCHED_NORMAL: usize = 0;
+pub const SCHED_DEADLINE: usize = 6;
+/// Longest period a deadline thread may have, as on Linux.
+const DEADLINE_PERIOD_MAX_NS: usize = (1 << 22) * 1000;
+
+/// Parameters of the deadline class, in nanoseconds. The deadline of each
+/// period is its end.
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SchedParam {
+    pub runtime: usize,
+    pub period: usize,
+}
+
+/// The thread `pid` selects for the scheduler calls, 0 meaning the caller
+/// and any other pid the main thread of that process.
+fn sched_target(pid: usize) -> Option<Arc<TaskControlBlock>> {
+    if pid == 0 {
+        return current_task();
+    }
+    let process = pid2process(pid)?;
+    let process_inner = process.inner_exclusive_access();
+    process_inner.tasks.first()?.clone()
+}
+
+/// Put the target thread into `policy`. SCHED_DEADLINE takes the runtime
+/// and period at `param` and fails unless the thread can be admitted on a
+/// hart with all other deadline threads there still meeting theirs. The
+/// caller starts out in its new class right away.
+pub fn sys_sched_setscheduler(pid: usize, policy: usize, param: *const SchedParam) -> isize {
+    let task = match sched_target(pid) {
+        Some(task) => task,
+        None => return -1,
+    };
+    let params = match policy {
+        SCHED_NORMAL => None,
+        SCHED_DEADLINE => {
+            let param = match copy_from_user(current_user_token(), param) {
+                Ok(param) => param,
+                Err(err) => return err,
+            };
+            if param.runtime > param.period || param.period > DEADLINE_PERIOD_MAX_NS {
+                return -1;
+            }
+            let (runtime, period) = (ns_to_time(param.runtime), ns_to_time(param.period));
+            if runtime == 0 {
+                return -1;
+            }
+            Some((runtime, period))
+        }
+        _ => return -1,
+    };
+    if !set_task_deadline(&task, params) {
+        return -1;
+    }
+    if pid == 0 {
+        suspend_current_and_run_next();
+    }
+    0
+}
+
+/// The policy the target thread is scheduled with.
+pub fn sys_sched_getscheduler(pid: usize) -> isize {
+    match sched_target(pid) {
+        Some(task) if task.inner_exclusive_access().deadline.is_some() => SCHED_DEADLINE as isize,
+        Some(_) => SCHED_NORMAL as isize,
+        None => -1,
+    }
+}
+
+/// Open a counter of `config`, a generic hardware event, over the threads
+/// of process `pid` (0 for the caller) and return its fd. It counts once
+/// enabled with ioctl and reads as a u64.
+pub fn sys_perf_event_open(config: usize, pid: usize) -> isize {
+    let kind = match PerfEventKind::from_config(config) {
+        Some(kind) => kind,
+        None => return -1,
+    };
+    let target = match pid {
+        0 => current_process(),
+        pid => match pid2process(pid) {
+            Some(process) => process,
+            None => return -1,
+        },
+    };
+    let event = Arc::new(PerfEvent::new(&target, kind));
+    let process = current_process();
+    let mut inner = process.inner_exclusive_access();
+    match inner.alloc_fd() {
+        Some(fd) => {
+            inner.fd_table[fd] = Some(event);
+            fd as isize
+        }
+        None => -1,
+    }
+}
+
+/// Start or stop logging every syscall of process `pid` (0 for the
+/// caller). Children forked or spawned later are traced too.
+pub fn sys_trace(pid: usize, enable: bool) -> isize {
+    let target = match pid {
+        0 => current_process(),
+        pid => match pid2process(pid) {
+            Some(process) => process,
+            None => return -1,
+        },
+    };
+    target.traced.store(enable, Ordering::Relaxed);
+    0
+}
+
+pub const TIMER_ABSTIME: u32 = 1;
+pub const ITIMER_REAL: usize = 0;
+
+/// Create a POSIX timer on `clock_id` and return its id. A null `sevp`
+/// raises SIGALRM on expiration.
+pub fn sys_timer_create(clock_id: usize, sevp: *const SigEvent) -> isize {
+    let clock = match ClockId::from_id(clock_id) {
+        Some(clock) => clock,
+        None => return -1,
+    };
+    let sev = if sevp.is_null() {
+        SigEvent {
+            notify: SIGEV_SIGNAL,
+            signal: SignalFlags::SIGALRM.bits(),
+            tid: 0,
+        }
+    } else {
+        match copy_from_user(current_user_token(), sevp) {
+            Ok(sev) => sev,
+            Err(err) => return err,
+        }
+    };
+    let signal = match SignalFlags::from_bits(sev.signal) {
+        Some(signal) if signal.bits().count_ones() == 1 => Some(signal),
+        _ if sev.notify == SIGEV_NONE => None,
+        _ => return -1,
+    };
+    let process = current_process();
+    let mut process_inner = process.inner_exclusive_access();
+    let (signal, thread) = match sev.notify {
+        SIGEV_NONE => (None, None),
+        SIGEV_SIGNAL => (signal, None),
+        SIGEV_THREAD_ID => match process_inner.tasks.get(sev.tid).and_then(Option::as_ref) {
+            Some(thread) => (signal, Some(Arc::downgrade(thread))),
+            None => return -1,
+        },
+        _ => return -1,
+    };
+    let timer = Arc::new(PosixTimer::new(
+        Arc::downgrade(&process),
+        thread,
+        clock,
+        signal,
+    ));
+    process_inner.handles.insert(timer) as isize
+}
+
+/// Read `clock_id` into `tp`, to the nanosecond.
+pub fn sys_clock_gettime(clock_id: usize, tp: *mut TimeSpec) -> isize {
+    let clock = match ClockId::from_id(clock_id) {
+        Some(clock) => clock,
+        None => return -1,
+    };
+    match copy_to_user(current_user_token(), tp, TimeSpec::from_ns(clock.now_ns())) {
+        Ok(()) => 0,
+        Err(err) => err,
+    }
+}
+
+fn current_posix_timer(timer_id: usize) -> Option<Arc<PosixTimer>> {
+    current_process()
+        .inner_exclusive_access()
+        .handles
+        .get_as::<PosixTimer>(ObjectKind::Timer, timer_id)
+}
+
+/// Arm or disarm a timer of the caller, with what was left of its previous
+/// setting written to `old_value` unless it is null.
+pub fn sys_timer_settime(
+    timer_id: usize,
+    flags: u32,
+    new_value: *const ITimerSpec,
+    old_value: *mut ITimerSpec,
+) -> isize {
+    let token = current_user_token();
+    let spec = match copy_from_user(token, new_value) {
+        Ok(spec) => spec,
+        Err(err) => return err,
+    };
+    let timer = match current_posix_timer(timer_id) {
+        Some(timer) => timer,
+        None => return -1,
+    };
+    let old = timer.set(&spec, flags & TIMER_ABSTIME != 0);
+    if old_value.is_null() {
+        return 0;
+    }
+    match copy_to_user(token, old_value, old) {
+        Ok(()) => 0,
+        Err(err) => err,
+    }
+}
+
+pub fn sys_timer_gettime(timer_id: usize, curr_value: *mut ITimerSpec) -> isize {
+    let token = current_user_token();
+    if let Some(timer) = current_posix_timer(timer_id) {
+        match copy_to_user(token, curr_value, timer.get()) {
+            Ok(()) => 0,
+            Err(err) => err,
+        }
+    } else {
+        -1
+    }
+}
+
+pub fn sys_timer_getoverrun(timer_id: usize) -> isize {
+    if let Some(timer) = current_posix_timer(timer_id) {
+        timer.overrun() as isize
+    } else {
+        -1
+    }
+}
+
+pub fn sys_timer_delete(timer_id: usize) -> isize {
+    let process = current_process();
+    let mut process_inner = process.inner_exclusive_access();
+    // pending expirations only hold a weak reference and die with it
+    if process_inner
+        .handles
+        .remove_as::<PosixTimer>(ObjectKind::Timer, timer_id)
+        .is_some()
+    {
+        0
+    } else {
+        -1
+    }
+}
+
+/// Close `handle` of the kind numbered `kind`, whatever object it refers
+/// to. The object goes once nothing else holds it: a mutex a thread
+/// waits on lasts until the wait is over, an attached segment until it
+/// is detached, and a keyed object can no longer be found by its key
+/// once gone.
+pub fn sys_close_handle(kind: usize, handle: usize) -> isize {
+    let kind = match ObjectKind::from_usize(kind) {
+        Some(kind) => kind,
+        None => return -1,
+    };
+    if current_process()
+        .inner_exclusive_access()
+        .handles
+        .remove(kind, handle)
+        .is_some()
+    {
+        0
+    } else {
+        -1
+    }
+}
+
+/// Copy the kernel memory report into `buf`, truncated to `len` bytes.
+/// Returns the full length of the report.
+pub fn sys_meminfo(buf: *mut u8, len: usize) -> isize {
+    let report = meminfo();
+    let bytes = report.as_bytes();
+    let len = len.min(bytes.len());
+    match copy_bytes_to_user(current_user_token(), buf, &bytes[..len]) {
+        Ok(()) => bytes.len() as isize,
+        Err(err) => err,
+    }
+}
+
+const SYSLOG_ACTION_READ_ALL: usize = 3;
+const SYSLOG_ACTION_READ_CLEAR: usize = 4;
+const SYSLOG_ACTION_CLEAR: usize = 5;
+const SYSLOG_ACTION_SIZE_UNREAD: usize = 9;
+const SYSLOG_ACTION_SIZE_BUFFER: usize = 10;
+
+/// The kernel log as Linux `syslog` offers it, reads copy out the last
+/// `len` bytes of it.
+pub fn sys_syslog(action: usize, buf: *mut u8, len: usize) -> isize {
+    match action {
+        SYSLOG_ACTION_READ_ALL | SYSLOG_ACTION_READ_CLEAR => {
+            let log = kmsg();
+            let bytes = log.as_bytes();
+            let bytes = &bytes[bytes.len().saturating_sub(len)..];
+            if let Err(err) = copy_bytes_to_user(current_user_token(), buf, bytes) {
+                return err;
+            }
+            if action == SYSLOG_ACTION_READ_CLEAR {
+                kmsg_clear();
+            }
+            bytes.len() as isize
+        }
+        SYSLOG_ACTION_CLEAR => {
+            kmsg_clear();
             0
-        } else {
-            -1
+        }
+        SYSLOG_ACTION_SIZE_UNREAD => kmsg().len() as isize,
+        SYSLOG_ACTION_SIZE_BUFFER => KMSG_SIZE as isize,
+        _ => -1,
+    }
+}
+
+/// Changes to the mapping are the file's, the only way files are mapped.
+const MAP_SHARED: usize = 0x01;
+/// Changes to the mapping are the process's own, the only way anonymous
+/// memory is mapped.
+const MAP_PRIVATE: usize = 0x02;
+/// Zeroed memory backed by no file, `fd` and `offset` are ignored.
+const MAP_ANONYMOUS: usize = 0x20;
+
+/// Map `len` bytes at `start`: zeroed memory with `MAP_ANONYMOUS`, else
+/// the device memory behind `fd` from `offset` on, such as that of
+/// `/dev/fb0`. `port` bit 0/1/2 stands for R/W/X, at least one must be
+/// set and W requires R. With `start` 0 the kernel picks the place and
+/// returns it, otherwise 0 is returned.
+pub fn sys_mmap(
+    start: usize,
+    len: usize,
+    port: usize,
+    flags: usize,
+    fd: usize,
+    offset: usize,
+) -> isize {
+    if start % PAGE_SIZE != 0 || len == 0 || port & !0x7 != 0 || port & 0x7 == 0 {
+        return -1;
+    }
+    if port & 0x2 != 0 && port & 0x1 == 0 {
+        return -1;
+    }
+    let anonymous = flags & MAP_ANONYMOUS != 0;
+    let sharing = if anonymous { MAP_PRIVATE } else { MAP_SHARED };
+    if flags & (MAP_SHARED | MAP_PRIVATE) != sharing {
+        return -1;
+    }
+    let end = match start.checked_add(len) {
+        Some(end) if end <= USER_SPACE_END => end,
+        _ => return -1,
+    };
+    let mut permission = MapPermission::U;
+    if port & 0x1 != 0 {
+        permission |= MapPermission::R;
+    }
+    if port & 0x2 != 0 {
+        permission |= MapPermission::W;
+    }
+    if port & 0x4 != 0 {
+        permission |= MapPermission::X;
+    }
+    if anonymous {
+        reclaim((end - start).div_ceil(PAGE_SIZE));
+    }
+    let process = current_process();
+    let mut process_inner = process.inner_exclusive_access();
+    let device_ppn = if anonymous {
+        None
+    } else {
+        let file = match process_inner.fd_table.get(fd) {
+            Some(Some(file)) => file.clone(),
+            _ => return -1,
+        };
+        if port & 0x2 != 0 && !file.writable() {
+            return -1;
+        }
+        let (ppn, pages) = match file.mmap_pages() {
+            Some(pages) => pages,
+            None => return -1,
+        };
+        if offset % PAGE_SIZE != 0 || offset / PAGE_SIZE + len.div_ceil(PAGE_SIZE) > pages {
+            return -1;
+        }
+        Some(PhysPageNum(ppn.0 + offset / PAGE_SIZE))
+    };
+    let (start, end, placed) = if start == 0 {
+        match process_inner.memory_set.find_free_area(len) {
+            Some(start) => (start, start + len, true),
+            None => return -1,
         }
     } else {
+        (start, end, false)
+    };
+    let mapped = match device_ppn {
+        Some(ppn) => process_inner.memory_set.insert_linear_area_checked(
+            start.into(),
+            end.into(),
+            ppn,
+            permission,
+        ),
+        None => process_inner.memory_set.insert_framed_area_checked(
+            start.into(),
+            end.into(),
+            permission,
+        ),
+    };
+    if !mapped {
         -1
+    } else if placed {
+        start as isize
+    } else {
+        0
+    }
+}
+
+pub fn sys_munmap(start: usize, len: usize) -> isize {
+    if start % PAGE_SIZE != 0 || len == 0 {
+        return -1;
+    }
+    let end = match start.checked_add(len) {
+        Some(end) if end <= USER_SPACE_END => end,
+        _ => return -1,
+    };
+    let process = current_process();
+    let mut process_inner = process.inner_exclusive_access();
+    if process_inner
+        .memory_set
+        .remove_area_range(start.into(), end.into())
+    {
+        0
+    } else {
+        -1
+    }
+}
+
+/// Move the program break to `addr` and return where it is afterwards,
+/// where it was if it could not be moved. 0 only asks where it is.
+pub fn sys_brk(addr: usize) -> isize {
+    let process = current_process();
+    let mut process_inner = process.inner_exclusive_access();
+    let memory_set = &mut process_inner.memory_set;
+    if addr != 0 {
+        memory_set.set_brk(addr);
+    }
+    memory_set.brk() as isize
+}
+
+/// Arm the ITIMER_REAL of the caller with `new_value`, raising SIGALRM
+/// on expiration, and read its previous setting into `old_value` unless
+/// it is null. The virtual and profiling timers are not supported.
+pub fn sys_setitimer(
+    which: usize,
+    new_value: *const ITimerVal,
+    old_value: *mut ITimerVal,
+) -> isize {
+    if which != ITIMER_REAL {
+        return -1;
+    }
+    let token = current_user_token();
+    let new: ITimerVal = match copy_from_user(token, new_value) {
+        Ok(new) if new.interval.is_valid() && new.value.is_valid() => new,
+        Ok(_) => return -1,
+        Err(err) => return err,
+    };
+    let process = current_process();
+    let timer = process
+        .inner_exclusive_access()
+        .itimer_real
+        .get_or_insert_with(|| {
+            Arc::new(PosixTimer::new(
+                Arc::downgrade(&process),
+                None,
+                ClockId::Monotonic,
+                Some(SignalFlags::SIGALRM),
+            ))
+        })
+        .clone();
+    let old = timer.set(&new.into(), false);
+    if old_value.is_null() {
+        return 0;
+    }
+    match copy_to_user(token, old_value, old.into()) {
+        Ok(()) => 0,
+        Err(err) => err,
+    }
+}
+
+/// Read the ITIMER_REAL of the caller into `curr_value`.
+pub fn sys_getitimer(which: usize, curr_value: *mut ITimerVal) -> isize {
+    if which != ITIMER_REAL {
+        return -1;
+    }
+    let timer = current_process()
+        .inner_exclusive_access()
+        .itimer_real
+        .clone();
+    let value = timer.map_or(ITimerVal::default(), |timer| timer.get().into());
+    match copy_to_user(current_user_token(), curr_value, value) {
+        Ok(()) => 0,
+        Err(err) => err,
     }
 }
@@ -0,0 +1,114 @@
+use crate::mm::copy_to_user;
+use crate::task::{
+    current_process, current_user_token, ptrace_access, ptrace_clear_steps, ptrace_detach,
+    ptrace_plant_steps, ptrace_regs, ptrace_resume, PtraceState, SignalFlags,
+};
+use core::mem::size_of;
+
+/// Requests numbered as on Linux.
+pub const PTRACE_PEEKDATA: usize = 2;
+pub const PTRACE_POKEDATA: usize = 5;
+pub const PTRACE_CONT: usize = 7;
+pub const PTRACE_SINGLESTEP: usize = 9;
+pub const PTRACE_GETREGS: usize = 12;
+pub const PTRACE_ATTACH: usize = 16;
+pub const PTRACE_DETACH: usize = 17;
+
+/// Registers as `user_regs_struct` of Linux on RISC-V, the pc in place of
+/// the zero register.
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct UserRegs {
+    pub regs: [usize; 32],
+}
+
+/// Trace the child `pid`. ATTACH stops it with SIGSTOP and every other
+/// request needs it stopped, as waitpid with WUNTRACED reports: PEEKDATA
+/// reads the word at `addr` into `*data`, POKEDATA writes `data` there,
+/// GETREGS fills the `UserRegs` at `data` from its main thread, CONT runs
+/// it on with the signal numbered `data` unless 0, SINGLESTEP runs it for
+/// one instruction before it stops with SIGTRAP, and DETACH lets it go.
+pub fn sys_ptrace(request: usize, pid: usize, addr: usize, data: usize) -> isize {
+    let process = current_process();
+    let tracee = match process
+        .inner_exclusive_access()
+        .children
+        .iter()
+        .find(|child| child.getpid() == pid)
+    {
+        Some(child) => child.clone(),
+        None => return -1,
+    };
+    let mut inner = tracee.inner_exclusive_access();
+    if inner.is_zombie {
+        return -1;
+    }
+    if request == PTRACE_ATTACH {
+        if inner.ptrace.is_some() {
+            return -1;
+        }
+        inner.ptrace = Some(PtraceState::default());
+        drop(inner);
+        tracee.send_signal(SignalFlags::SIGSTOP);
+        return 0;
+    }
+    if inner.ptrace.is_none() || !inner.stopped {
+        return -1;
+    }
+    drop(inner);
+    // breakpoints of a step that did not finish are in the way
+    ptrace_clear_steps(&tracee);
+    let token = current_user_token();
+    match request {
+        PTRACE_PEEKDATA => {
+            let mut word = [0u8; size_of::<usize>()];
+            if !ptrace_access(&tracee, addr, &mut word, false) {
+                return -1;
+            }
+            match copy_to_user(token, data as *mut usize, usize::from_le_bytes(word)) {
+                Ok(()) => 0,
+                Err(err) => err,
+            }
+        }
+        PTRACE_POKEDATA => {
+            if !ptrace_access(&tracee, addr, &mut data.to_le_bytes(), true) {
+                return -1;
+            }
+            0
+        }
+        PTRACE_GETREGS => {
+            let trap_cx = match ptrace_regs(&tracee) {
+                Some(trap_cx) => trap_cx,
+                None => return -1,
+            };
+            let mut regs = UserRegs { regs: trap_cx.x };
+            regs.regs[0] = trap_cx.sepc;
+            match copy_to_user(token, data as *mut UserRegs, regs) {
+                Ok(()) => 0,
+                Err(err) => err,
+            }
+        }
+        PTRACE_CONT => {
+            if data != 0 {
+                match SignalFlags::from_bits(1u32.wrapping_shl(data as u32)) {
+                    Some(signal) if data < 32 => tracee.send_signal(signal),
+                    _ => return -1,
+                }
+            }
+            ptrace_resume(&tracee);
+            0
+        }
+        PTRACE_SINGLESTEP => {
+            if !ptrace_plant_steps(&tracee) {
+                return -1;
+            }
+            ptrace_resume(&tracee);
+            0
+        }
+        PTRACE_DETACH => {
+            ptrace_detach(&tracee);
+            0
+        }
+        _ => -1,
+    }
+}
@@ -1,134 +1,272 @@
-use crate::sync::{Condvar, Mutex, MutexBlocking, MutexSpin, Semaphore};
-use crate::task::{block_current_and_run_next, current_process, current_task};
-use crate::timer::{add_timer, get_time_ms};
+use crate::mm::{copy_from_user, UserBuffer};
+use crate::sync::{
+    as_mutex, futex_wait, futex_wake, Condvar, MutexBlocking, MutexSpin, Semaphore, WaitQueue,
+    DEADLOCK, FUTEX_WAIT, FUTEX_WAKE,
+};
+use crate::task::{
+    current_process, current_task, current_user_token, schedule, KernelObject, ObjectKind,
+};
+use crate::timer::{add_timer, get_time_ms, TimeSpec};
 use alloc::sync::Arc;
 
-pub fn sys_sleep(ms: usize) -> isize {
-    let expire_ms = get_time_ms() + ms;
-    let task = current_task().unwrap();
-    add_timer(expire_ms, task);
-    block_current_and_run_next();
+/// Block for at least `req`, rounded up to the millisecond the timer heap
+/// counts in. Signals do not cut the sleep short, so `rem` is never
+/// written.
+pub fn sys_nanosleep(req: *const TimeSpec, _rem: *mut TimeSpec) -> isize {
+    let req = match copy_from_user(current_user_token(), req) {
+        Ok(req) if req.is_valid() => req,
+        Ok(_) => return -1,
+        Err(err) => return err,
+    };
+    let expire_ms = get_time_ms() + req.to_ms();
+    // queued before the timer is armed, so it cannot fire too early
+    let queue = WaitQueue::new();
+    let task_cx_ptr = queue.wait_no_sched();
+    add_timer(expire_ms, queue);
+    schedule(task_cx_ptr);
     0
 }
 
+/// With `FUTEX_WAIT`, sleep while the u32 at `uaddr` holds `val`, for at
+/// most `timeout` unless it is null, see `futex_wait`. With `FUTEX_WAKE`,
+/// wake up to `val` threads sleeping on it and return how many.
+pub fn sys_futex(uaddr: usize, op: usize, val: usize, timeout: *const TimeSpec) -> isize {
+    if uaddr % core::mem::size_of::<u32>() != 0 {
+        return -1;
+    }
+    let token = current_user_token();
+    let word = match UserBuffer::new(
+        token,
+        uaddr as *const u8,
+        core::mem::size_of::<u32>(),
+        false,
+    ) {
+        Ok(word) => word,
+        Err(err) => return err,
+    };
+    match op {
+        FUTEX_WAIT => {
+            let expire_ms = if timeout.is_null() {
+                None
+            } else {
+                match copy_from_user(token, timeout) {
+                    Ok(timeout) if timeout.is_valid() => Some(get_time_ms() + timeout.to_ms()),
+                    Ok(_) => return -1,
+                    Err(err) => return err,
+                }
+            };
+            futex_wait(word, val as u32, expire_ms)
+        }
+        FUTEX_WAKE => futex_wake(&word, val),
+        _ => -1,
+    }
+}
+
+fn current_tid() -> usize {
+    current_task()
+        .unwrap()
+        .inner_exclusive_access()
+        .res
+        .as_ref()
+        .unwrap()
+        .tid
+}
+
 pub fn sys_mutex_create(blocking: bool) -> isize {
     let process = current_process();
-    let mutex: Option<Arc<dyn Mutex>> = if !blocking {
-        Some(Arc::new(MutexSpin::new()))
+    let mutex: Arc<dyn KernelObject> = if !blocking {
+        Arc::new(MutexSpin::new())
     } else {
-        Some(Arc::new(MutexBlocking::new()))
+        Arc::new(MutexBlocking::new())
     };
     let mut process_inner = process.inner_exclusive_access();
-    if let Some(id) = process_inner
-        .mutex_list
-        .iter()
-        .enumerate()
-        .find(|(_, item)| item.is_none())
-        .map(|(id, _)| id)
-    {
-        process_inner.mutex_list[id] = mutex;
-        id as isize
-    } else {
-        process_inner.mutex_list.push(mutex);
-        process_inner.mutex_list.len() as isize - 1
-    }
+    let id = process_inner.handles.insert(mutex);
+    process_inner.mutex_detector.add_resource(id, 1);
+    id as isize
 }
 
 pub fn sys_mutex_lock(mutex_id: usize) -> isize {
+    let tid = current_tid();
     let process = current_process();
-    let process_inner = process.inner_exclusive_access();
-    let mutex = Arc::clone(process_inner.mutex_list[mutex_id].as_ref().unwrap());
+    let mut process_inner = process.inner_exclusive_access();
+    let mutex = match process_inner
+        .handles
+        .get(ObjectKind::Mutex, mutex_id)
+        .and_then(as_mutex)
+    {
+        Some(mutex) => mutex,
+        None => return -1,
+    };
+    let detect = process_inner.deadlock_detect;
+    if !process_inner.mutex_detector.request(tid, mutex_id, detect) {
+        return DEADLOCK;
+    }
     drop(process_inner);
     drop(process);
     mutex.lock();
+    current_process()
+        .inner_exclusive_access()
+        .mutex_detector
+        .acquire(tid, mutex_id);
     0
 }
 
 pub fn sys_mutex_unlock(mutex_id: usize) -> isize {
+    let tid = current_tid();
     let process = current_process();
-    let process_inner = process.inner_exclusive_access();
-    let mutex = Arc::clone(process_inner.mutex_list[mutex_id].as_ref().unwrap());
+    let mut process_inner = process.inner_exclusive_access();
+    let mutex = match process_inner
+        .handles
+        .get(ObjectKind::Mutex, mutex_id)
+        .and_then(as_mutex)
+    {
+        Some(mutex) => mutex,
+        None => return -1,
+    };
+    // given back before the unlock wakes a waiter, which then records its
+    // own acquire
+    if process_inner.mutex_detector.holds(tid, mutex_id) {
+        process_inner.mutex_detector.release(tid, mutex_id);
+    }
     drop(process_inner);
     drop(process);
-    mutex.unlock();
-    0
+    if mutex.unlock() {
+        0
+    } else {
+        -1
+    }
 }
 
 pub fn sys_semaphore_create(res_count: usize) -> isize {
     let process = current_process();
     let mut process_inner = process.inner_exclusive_access();
-    let id = if let Some(id) = process_inner
-        .semaphore_list
-        .iter()
-        .enumerate()
-        .find(|(_, item)| item.is_none())
-        .map(|(id, _)| id)
-    {
-        process_inner.semaphore_list[id] = Some(Arc::new(Semaphore::new(res_count)));
-        id
-    } else {
-        process_inner
-            .semaphore_list
-            .push(Some(Arc::new(Semaphore::new(res_count))));
-        process_inner.semaphore_list.len() - 1
-    };
+    let id = process_inner
+        .handles
+        .insert(Arc::new(Semaphore::new(res_count)));
+    process_inner.semaphore_detector.add_resource(id, res_count);
     id as isize
 }
 
 pub fn sys_semaphore_up(sem_id: usize) -> isize {
+    let tid = current_tid();
     let process = current_process();
-    let process_inner = process.inner_exclusive_access();
-    let sem = Arc::clone(process_inner.semaphore_list[sem_id].as_ref().unwrap());
+    let mut process_inner = process.inner_exclusive_access();
+    let sem = match process_inner
+        .handles
+        .get_as::<Semaphore>(ObjectKind::Semaphore, sem_id)
+    {
+        Some(sem) => sem,
+        None => return -1,
+    };
+    process_inner.semaphore_detector.release(tid, sem_id);
     drop(process_inner);
     sem.up();
     0
 }
 
 pub fn sys_semaphore_down(sem_id: usize) -> isize {
+    let tid = current_tid();
     let process = current_process();
-    let process_inner = process.inner_exclusive_access();
-    let sem = Arc::clone(process_inner.semaphore_list[sem_id].as_ref().unwrap());
+    let mut process_inner = process.inner_exclusive_access();
+    let sem = match process_inner
+        .handles
+        .get_as::<Semaphore>(ObjectKind::Semaphore, sem_id)
+    {
+        Some(sem) => sem,
+        None => return -1,
+    };
+    let detect = process_inner.deadlock_detect;
+    if !process_inner
+        .semaphore_detector
+        .request(tid, sem_id, detect)
+    {
+        return DEADLOCK;
+    }
     drop(process_inner);
+    drop(process);
     sem.down();
+    current_process()
+        .inner_exclusive_access()
+        .semaphore_detector
+        .acquire(tid, sem_id);
     0
 }
 
+/// Turn deadlock detection for the current process on (1) or off (0).
+pub fn sys_enable_deadlock_detect(enabled: usize) -> isize {
+    let process = current_process();
+    match enabled {
+        0 | 1 => {
+            process.inner_exclusive_access().deadlock_detect = enabled == 1;
+            0
+        }
+        _ => -1,
+    }
+}
+
 pub fn sys_condvar_create() -> isize {
     let process = current_process();
     let mut process_inner = process.inner_exclusive_access();
-    let id = if let Some(id) = process_inner
-        .condvar_list
-        .iter()
-        .enumerate()
-        .find(|(_, item)| item.is_none())
-        .map(|(id, _)| id)
-    {
-        process_inner.condvar_list[id] = Some(Arc::new(Condvar::new()));
-        id
-    } else {
-        process_inner
-            .condvar_list
-            .push(Some(Arc::new(Condvar::new())));
-        process_inner.condvar_list.len() - 1
-    };
-    id as isize
+    process_inner.handles.insert(Arc::new(Condvar::new())) as isize
 }
 
 pub fn sys_condvar_signal(condvar_id: usize) -> isize {
     let process = current_process();
     let process_inner = process.inner_exclusive_access();
-    let condvar = Arc::clone(process_inner.condvar_list[condvar_id].as_ref().unwrap());
+    let condvar = match process_inner
+        .handles
+        .get_as::<Condvar>(ObjectKind::Condvar, condvar_id)
+    {
+        Some(condvar) => condvar,
+        None => return -1,
+    };
     drop(process_inner);
     condvar.signal();
     0
 }
 
-pub fn sys_condvar_wait(condvar_id: usize, mutex_id: usize) -> isize {
+pub fn sys_condvar_broadcast(condvar_id: usize) -> isize {
     let process = current_process();
     let process_inner = process.inner_exclusive_access();
-    let condvar = Arc::clone(process_inner.condvar_list[condvar_id].as_ref().unwrap());
-    let mutex = Arc::clone(process_inner.mutex_list[mutex_id].as_ref().unwrap());
+    let condvar = match process_inner
+        .handles
+        .get_as::<Condvar>(ObjectKind::Condvar, condvar_id)
+    {
+        Some(condvar) => condvar,
+        None => return -1,
+    };
+    drop(process_inner);
+    condvar.broadcast();
+    0
+}
+
+pub fn sys_condvar_wait(condvar_id: usize, mutex_id: usize) -> isize {
+    let tid = current_tid();
+    let process = current_process();
+    let mut process_inner = process.inner_exclusive_access();
+    let (condvar, mutex) = match (
+        process_inner
+            .handles
+            .get_as::<Condvar>(ObjectKind::Condvar, condvar_id),
+        process_inner
+            .handles
+            .get(ObjectKind::Mutex, mutex_id)
+            .and_then(as_mutex),
+    ) {
+        (Some(condvar), Some(mutex)) => (condvar, mutex),
+        _ => return -1,
+    };
+    // the mutex is free for others while we wait
+    if process_inner.mutex_detector.holds(tid, mutex_id) {
+        process_inner.mutex_detector.release(tid, mutex_id);
+    }
     drop(process_inner);
-    condvar.wait_with_mutex(mutex);
+    if !condvar.wait_with_mutex(mutex) {
+        return -1;
+    }
+    process
+        .inner_exclusive_access()
+        .mutex_detector
+        .acquire(tid, mutex_id);
     0
 }
@@ -9,7 +9,7 @@ pub fn sys_thread_create(entry: usize, arg: usize) -> isize {
     let task = current_task().unwrap();
     let process = task.process.upgrade().unwrap();
     // create a new thread
-    let new_task = Arc::new(TaskControlBlock::new(
+    let new_task = match TaskControlBlock::new(
         Arc::clone(&process),
         task.inner_exclusive_access()
             .res
@@ -17,10 +17,15 @@ pub fn sys_thread_create(entry: usize, arg: usize) -> isize {
             .unwrap()
             .ustack_base,
         true,
-    ));
-    // add new task to scheduler
-    add_task(Arc::clone(&new_task));
-    let new_task_inner = new_task.inner_exclusive_access();
+    ) {
+        Ok(new_task) => Arc::new(new_task),
+        Err(_) => return -1,
+    };
+    let mut new_task_inner = new_task.inner_exclusive_access();
+    let task_inner = task.inner_exclusive_access();
+    new_task_inner.priority = task_inner.priority;
+    new_task_inner.affinity = task_inner.affinity;
+    drop(task_inner);
     let new_task_res = new_task_inner.res.as_ref().unwrap();
     let new_task_tid = new_task_res.tid;
     let mut process_inner = process.inner_exclusive_access();
@@ -39,6 +44,10 @@ pub fn sys_thread_create(entry: usize, arg: usize) -> isize {
         trap_handler as usize,
     );
     (*new_task_trap_cx).x[10] = arg;
+    drop(new_task_inner);
+    drop(process_inner);
+    // add new task to scheduler once it is ready to run
+    add_task(new_task);
     new_task_tid as isize
 }
 
@@ -58,14 +67,13 @@ pub fn sys_gettid() -> isize {
 pub fn sys_waittid(tid: usize) -> i32 {
     let task = current_task().unwrap();
     let process = task.process.upgrade().unwrap();
-    let task_inner = task.inner_exclusive_access();
-    let mut process_inner = process.inner_exclusive_access();
     // a thread cannot wait for itself
-    if task_inner.res.as_ref().unwrap().tid == tid {
+    if task.inner_exclusive_access().res.as_ref().unwrap().tid == tid {
         return -1;
     }
+    let mut process_inner = process.inner_exclusive_access();
     let mut exit_code: Option<i32> = None;
-    let waited_task = process_inner.tasks[tid].as_ref();
+    let waited_task = process_inner.tasks.get(tid).and_then(Option::as_ref);
     if let Some(waited_task) = waited_task {
         if let Some(waited_exit_code) = waited_task.inner_exclusive_access().exit_code {
             exit_code = Some(waited_exit_code);
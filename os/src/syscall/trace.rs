@@ -0,0 +1,165 @@
+//! Syscall tracing. Every syscall of a process with `traced` set is logged
+//! as `[pid:tid] name(args) = ret`, its arguments decoded by the kinds in
+//! `SYSCALLS`: `d` is a signed number, `x` an address or flags in hex and
+//! `s` a string in user memory.
+
+use super::*;
+use crate::mm::read_user_str;
+use crate::task::{current_process, current_user_token};
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::sync::atomic::Ordering;
+use log::info;
+
+/// Strings longer than this are cut short in the log.
+const STR_MAX: usize = 32;
+
+const SYSCALLS: &[(usize, &str, &str)] = &[
+    (SYSCALL_GETCWD, "getcwd", "xd"),
+    (SYSCALL_EVENTFD2, "eventfd2", "dx"),
+    (SYSCALL_DUP2, "dup2", "dd"),
+    (SYSCALL_DUP, "dup", "d"),
+    (SYSCALL_FCNTL, "fcntl", "ddx"),
+    (SYSCALL_MKDIR, "mkdir", "s"),
+    (SYSCALL_UNLINKAT, "unlinkat", "s"),
+    (SYSCALL_LINKAT, "linkat", "ss"),
+    (SYSCALL_CHDIR, "chdir", "s"),
+    (SYSCALL_OPEN, "open", "sx"),
+    (SYSCALL_CLOSE, "close", "d"),
+    (SYSCALL_PIPE, "pipe", "x"),
+    (SYSCALL_READ, "read", "dxd"),
+    (SYSCALL_WRITE, "write", "dxd"),
+    (SYSCALL_PSELECT6, "pselect6", "dxxxx"),
+    (SYSCALL_PPOLL, "ppoll", "xdxx"),
+    (SYSCALL_FSTAT, "fstat", "dx"),
+    (SYSCALL_SYNC, "sync", ""),
+    (SYSCALL_EXIT, "exit", "d"),
+    (SYSCALL_EXIT_GROUP, "exit_group", "d"),
+    (SYSCALL_FUTEX, "futex", "xddx"),
+    (SYSCALL_NANOSLEEP, "nanosleep", "xx"),
+    (SYSCALL_GETITIMER, "getitimer", "dx"),
+    (SYSCALL_SETITIMER, "setitimer", "dxx"),
+    (SYSCALL_TIMER_CREATE, "timer_create", "dx"),
+    (SYSCALL_TIMER_GETTIME, "timer_gettime", "dx"),
+    (SYSCALL_TIMER_GETOVERRUN, "timer_getoverrun", "d"),
+    (SYSCALL_TIMER_SETTIME, "timer_settime", "dxxx"),
+    (SYSCALL_TIMER_DELETE, "timer_delete", "d"),
+    (SYSCALL_CLOCK_GETTIME, "clock_gettime", "dx"),
+    (SYSCALL_SYSLOG, "syslog", "dxd"),
+    (SYSCALL_PTRACE, "ptrace", "ddxx"),
+    (SYSCALL_SCHED_SETSCHEDULER, "sched_setscheduler", "ddx"),
+    (SYSCALL_SCHED_GETSCHEDULER, "sched_getscheduler", "d"),
+    (SYSCALL_SCHED_SETAFFINITY, "sched_setaffinity", "ddx"),
+    (SYSCALL_SCHED_GETAFFINITY, "sched_getaffinity", "ddx"),
+    (SYSCALL_YIELD, "yield", ""),
+    (SYSCALL_KILL, "kill", "dd"),
+    (SYSCALL_SIGACTION, "sigaction", "dxx"),
+    (SYSCALL_SIGPROCMASK, "sigprocmask", "x"),
+    (SYSCALL_SIGRETURN, "sigreturn", ""),
+    (SYSCALL_SETPRIORITY, "setpriority", "ddd"),
+    (SYSCALL_GETPRIORITY, "getpriority", "dd"),
+    (SYSCALL_TIMES, "times", "x"),
+    (SYSCALL_SETPGID, "setpgid", "dd"),
+    (SYSCALL_GETPGID, "getpgid", "d"),
+    (SYSCALL_SETSID, "setsid", ""),
+    (SYSCALL_GETRUSAGE, "getrusage", "dx"),
+    (SYSCALL_GETTIMEOFDAY, "gettimeofday", "xx"),
+    (SYSCALL_GETPID, "getpid", ""),
+    (SYSCALL_MSGGET, "msgget", "dx"),
+    (SYSCALL_MSGRCV, "msgrcv", "dxddx"),
+    (SYSCALL_MSGSND, "msgsnd", "dxdx"),
+    (SYSCALL_SEMGET, "semget", "ddx"),
+    (SYSCALL_SEMCTL, "semctl", "dddd"),
+    (SYSCALL_SEMOP, "semop", "dxd"),
+    (SYSCALL_SHMGET, "shmget", "dxx"),
+    (SYSCALL_SHMAT, "shmat", "dxx"),
+    (SYSCALL_SHMDT, "shmdt", "x"),
+    (SYSCALL_SOCKET, "socket", "ddd"),
+    (SYSCALL_SOCKETPAIR, "socketpair", "dddx"),
+    (SYSCALL_BIND, "bind", "dxd"),
+    (SYSCALL_LISTEN, "listen", "dd"),
+    (SYSCALL_ACCEPT, "accept", "dxx"),
+    (SYSCALL_CONNECT, "connect", "dxd"),
+    (SYSCALL_SENDTO, "sendto", "dxdxxd"),
+    (SYSCALL_RECVFROM, "recvfrom", "dxdxxx"),
+    (SYSCALL_BRK, "brk", "x"),
+    (SYSCALL_MUNMAP, "munmap", "xx"),
+    (SYSCALL_FORK, "fork", ""),
+    (SYSCALL_EXEC, "exec", "sxx"),
+    (SYSCALL_MMAP, "mmap", "xxxxdx"),
+    (SYSCALL_PERF_EVENT_OPEN, "perf_event_open", "dd"),
+    (SYSCALL_WAITPID, "waitpid", "dxx"),
+    (SYSCALL_PRLIMIT64, "prlimit64", "ddxx"),
+    (SYSCALL_GETRANDOM, "getrandom", "xdx"),
+    (SYSCALL_SPAWN, "spawn", "sxx"),
+    (SYSCALL_PIDFD_SEND_SIGNAL, "pidfd_send_signal", "dxxx"),
+    (SYSCALL_PIDFD_OPEN, "pidfd_open", "dx"),
+    (SYSCALL_ENABLE_DEADLOCK_DETECT, "deadlock_detect", "d"),
+    (SYSCALL_THREAD_CREATE, "thread_create", "xx"),
+    (SYSCALL_GETTID, "gettid", ""),
+    (SYSCALL_WAITTID, "waittid", "d"),
+    (SYSCALL_MUTEX_CREATE, "mutex_create", "d"),
+    (SYSCALL_MUTEX_LOCK, "mutex_lock", "d"),
+    (SYSCALL_MUTEX_UNLOCK, "mutex_unlock", "d"),
+    (SYSCALL_SEMAPHORE_CREATE, "semaphore_create", "d"),
+    (SYSCALL_SEMAPHORE_UP, "semaphore_up", "d"),
+    (SYSCALL_SEMAPHORE_DOWN, "semaphore_down", "d"),
+    (SYSCALL_CONDVAR_CREATE, "condvar_create", ""),
+    (SYSCALL_CONDVAR_SIGNAL, "condvar_signal", "d"),
+    (SYSCALL_CONDVAR_WAIT, "condvar_wait", "dd"),
+    (SYSCALL_CONDVAR_BROADCAST, "condvar_broadcast", "d"),
+    (SYSCALL_CLOSE_HANDLE, "close_handle", "dd"),
+    (SYSCALL_FRAMEBUFFER_FLUSH, "framebuffer_flush", ""),
+    (SYSCALL_EVENT_GET, "event_get", ""),
+    (SYSCALL_KEY_PRESSED, "key_pressed", ""),
+    (SYSCALL_IOCTL, "ioctl", "dxx"),
+    (SYSCALL_MEMINFO, "meminfo", "xd"),
+    (SYSCALL_TRACE, "trace", "dd"),
+];
+
+fn format_arg(kind: u8, arg: usize) -> String {
+    match kind {
+        b's' => match read_user_str(current_user_token(), arg as *const u8) {
+            Ok(string) if string.chars().count() > STR_MAX => {
+                let head: String = string.chars().take(STR_MAX).collect();
+                format!("{:?}...", head)
+            }
+            Ok(string) => format!("{:?}", string),
+            Err(_) => format!("{:#x}", arg),
+        },
+        b'x' => format!("{:#x}", arg),
+        _ => format!("{}", arg as isize),
+    }
+}
+
+/// Run `dispatch` for the syscall and log it if the process is traced.
+/// A syscall that does not return is logged before it runs.
+pub fn trace_syscall(
+    syscall_id: usize,
+    args: [usize; 6],
+    dispatch: fn(usize, [usize; 6]) -> isize,
+) -> isize {
+    if !current_process().traced.load(Ordering::Relaxed) {
+        return dispatch(syscall_id, args);
+    }
+    let (name, kinds) = SYSCALLS
+        .iter()
+        .find(|(id, _, _)| *id == syscall_id)
+        .map_or(("unknown", "dddd"), |&(_, name, kinds)| (name, kinds));
+    let args_str = kinds
+        .bytes()
+        .zip(args)
+        .map(|(kind, arg)| format_arg(kind, arg))
+        .collect::<Vec<_>>()
+        .join(", ");
+    let pid = current_process().getpid();
+    let tid = sys_gettid();
+    if matches!(syscall_id, SYSCALL_EXIT | SYSCALL_EXIT_GROUP) {
+        info!("[{}:{}] {}({}) = ?", pid, tid, name, args_str);
+        return dispatch(syscall_id, args);
+    }
+    let ret = dispatch(syscall_id, args);
+    info!("[{}:{}] {}({}) = {}", pid, tid, name, args_str, ret);
+    ret
+}
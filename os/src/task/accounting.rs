@@ -0,0 +1,221 @@
+//! CPU time accounting. A thread's clock starts when it is switched in,
+//! and the time since the last boundary is charged to user mode when it
+//! traps into the kernel and to kernel mode when it returns or switches
+//! out. Blocked and ready time is not charged.
+//!
+//! Each hart also keeps the time it spent in user mode and idle since it
+//! came online, the rest of that time went to the kernel.
+
+use super::manager::deadline_preempts;
+use super::task::{TaskControlBlockInner, TaskStatus};
+use super::{current_task, hart_id};
+use crate::config::MAX_HARTS;
+use crate::ipi::online_harts;
+use crate::perf::read_counters;
+use crate::timer::{get_time, time_to_ticks, TimeVal, TIME_SLICE};
+use core::ops::{Add, AddAssign};
+use core::sync::atomic::{AtomicUsize, Ordering};
+use lazy_static::*;
+
+/// Time spent running, in timer units.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct CpuTimes {
+    pub user: usize,
+    pub kernel: usize,
+}
+
+impl Add for CpuTimes {
+    type Output = Self;
+    fn add(self, other: Self) -> Self {
+        Self {
+            user: self.user + other.user,
+            kernel: self.kernel + other.kernel,
+        }
+    }
+}
+
+impl AddAssign for CpuTimes {
+    fn add_assign(&mut self, other: Self) {
+        *self = *self + other;
+    }
+}
+
+/// CPU times as `sys_times` reports them, in clock ticks.
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Tms {
+    pub utime: usize,
+    pub stime: usize,
+    /// of the reaped children, with their own children
+    pub cutime: usize,
+    pub cstime: usize,
+}
+
+impl Tms {
+    pub fn new(times: CpuTimes, children_times: CpuTimes) -> Self {
+        Self {
+            utime: time_to_ticks(times.user),
+            stime: time_to_ticks(times.kernel),
+            cutime: time_to_ticks(children_times.user),
+            cstime: time_to_ticks(children_times.kernel),
+        }
+    }
+}
+
+/// Resource usage laid out as on Linux, where only the times and the peak
+/// resident size are kept track of.
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct RUsage {
+    pub utime: TimeVal,
+    pub stime: TimeVal,
+    /// in KiB
+    pub maxrss: usize,
+    /// page faults, context switches and the like, all 0
+    pub unused: [usize; 13],
+}
+
+impl RUsage {
+    pub fn new(times: CpuTimes, maxrss: usize) -> Self {
+        Self {
+            utime: TimeVal::from_time(times.user),
+            stime: TimeVal::from_time(times.kernel),
+            maxrss,
+            ..Default::default()
+        }
+    }
+}
+
+/// Time a hart spent since it came online, in timer units.
+#[derive(Default)]
+struct HartTimes {
+    online_since: AtomicUsize,
+    user: AtomicUsize,
+    idle: AtomicUsize,
+}
+
+lazy_static! {
+    static ref HART_TIMES: [HartTimes; MAX_HARTS] = Default::default();
+}
+
+/// Start the clocks of this hart, which starts scheduling.
+pub fn account_hart_online() {
+    HART_TIMES[hart_id()]
+        .online_since
+        .store(get_time(), Ordering::Relaxed);
+}
+
+/// Charge `time` spent waiting for a task to this hart.
+pub fn account_idle(time: usize) {
+    HART_TIMES[hart_id()]
+        .idle
+        .fetch_add(time, Ordering::Relaxed);
+}
+
+/// User, kernel and idle time of each online hart, in clock ticks.
+pub fn hart_times() -> impl Iterator<Item = (usize, [usize; 3])> {
+    let now = get_time();
+    let online = online_harts();
+    (0..MAX_HARTS)
+        .filter(move |hart| online & 1 << hart != 0)
+        .map(move |hart| {
+            let times = &HART_TIMES[hart];
+            let user = times.user.load(Ordering::Relaxed);
+            let idle = times.idle.load(Ordering::Relaxed);
+            let total = now - times.online_since.load(Ordering::Relaxed);
+            let kernel = total.saturating_sub(user + idle);
+            (hart, [user, kernel, idle].map(time_to_ticks))
+        })
+}
+
+/// Start the clock and the time slice of a thread being switched in. A
+/// deadline thread runs until its budget is used up instead.
+pub fn account_switch_in(task_inner: &mut TaskControlBlockInner) {
+    let now = get_time();
+    task_inner.time_stamp = now;
+    task_inner.perf_stamp = read_counters();
+    if let Some(params) = task_inner.deadline.as_ref() {
+        task_inner.slice_end = now + params.budget;
+        return;
+    }
+    if task_inner.time_slice == 0 {
+        task_inner.time_slice = TIME_SLICE;
+    }
+    task_inner.slice_end = now + task_inner.time_slice;
+}
+
+/// Charge a thread that just switched out and keep what is left of its
+/// time slice for the next time it runs.
+pub fn account_switch_out(task_inner: &mut TaskControlBlockInner) {
+    account_kernel(task_inner);
+    account_perf(task_inner);
+    if task_inner.deadline.is_none() {
+        task_inner.time_slice = task_inner.slice_end.saturating_sub(task_inner.time_stamp);
+    }
+    if task_inner.task_status == TaskStatus::Ready {
+        task_inner.nivcsw += 1;
+    } else {
+        task_inner.nvcsw += 1;
+    }
+}
+
+/// Keep what is left of the budget of a deadline thread that stops
+/// running. Unlike the time slice this is needed before it switches out,
+/// as it decides where the thread is queued.
+pub fn account_budget(task_inner: &mut TaskControlBlockInner) {
+    let now = get_time();
+    if let Some(params) = task_inner.deadline.as_mut() {
+        params.budget = task_inner.slice_end.saturating_sub(now);
+    }
+}
+
+/// Whether the current thread used up its time slice, or a deadline task
+/// due before it waits for this hart.
+pub fn slice_expired() -> bool {
+    current_task().map_or(false, |task| {
+        let slice_end = task.inner_exclusive_access().slice_end;
+        slice_end <= get_time() || deadline_preempts(&task)
+    })
+}
+
+/// Give up the rest of the time slice of the current thread, it gets a
+/// new one the next time it runs. A deadline thread waits for its next
+/// period.
+pub fn surrender_slice() {
+    current_task().unwrap().inner_exclusive_access().slice_end = 0;
+}
+
+/// Charge the kernel time of a thread up to now, when it switches out or
+/// its times are read.
+pub fn account_kernel(task_inner: &mut TaskControlBlockInner) {
+    let now = get_time();
+    task_inner.times.kernel += now - task_inner.time_stamp;
+    task_inner.time_stamp = now;
+}
+
+/// Charge the hardware counts of a thread up to now, when it switches out
+/// or they are read. It must be running on this hart.
+pub fn account_perf(task_inner: &mut TaskControlBlockInner) {
+    let now = read_counters();
+    task_inner.perf += now - task_inner.perf_stamp;
+    task_inner.perf_stamp = now;
+}
+
+/// Charge the user time of the current thread, which just trapped in.
+pub fn account_trap_entry() {
+    let task = current_task().unwrap();
+    let mut task_inner = task.inner_exclusive_access();
+    let now = get_time();
+    task_inner.times.user += now - task_inner.time_stamp;
+    HART_TIMES[hart_id()]
+        .user
+        .fetch_add(now - task_inner.time_stamp, Ordering::Relaxed);
+    task_inner.time_stamp = now;
+}
+
+/// Charge the kernel time of the current thread, which returns to user
+/// mode.
+pub fn account_trap_return() {
+    let task = current_task().unwrap();
+    account_kernel(&mut task.inner_exclusive_access());
+}
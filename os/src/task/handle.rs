@@ -0,0 +1,150 @@
+use crate::sync::SpinMutex;
+use alloc::collections::BTreeMap;
+use alloc::sync::{Arc, Weak};
+use alloc::vec::Vec;
+use core::any::Any;
+use lazy_static::*;
+
+/// What a handle refers to. Each kind has handles of its own, numbered
+/// from 0, so the first mutex and the first semaphore are both 0. User
+/// space names a kind by its number in this order.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum ObjectKind {
+    Mutex,
+    Semaphore,
+    Condvar,
+    Timer,
+    Shm,
+    MsgQueue,
+    SemSet,
+    Pidfd,
+}
+
+impl ObjectKind {
+    const ALL: [Self; 8] = [
+        Self::Mutex,
+        Self::Semaphore,
+        Self::Condvar,
+        Self::Timer,
+        Self::Shm,
+        Self::MsgQueue,
+        Self::SemSet,
+        Self::Pidfd,
+    ];
+    const COUNT: usize = Self::ALL.len();
+
+    pub fn from_usize(kind: usize) -> Option<Self> {
+        Self::ALL.get(kind).copied()
+    }
+}
+
+/// Downcasting for `KernelObject` and `File`, implemented for every type.
+pub trait AsAny {
+    fn as_any(self: Arc<Self>) -> Arc<dyn Any + Send + Sync>;
+}
+
+impl<T: Any + Send + Sync> AsAny for T {
+    fn as_any(self: Arc<Self>) -> Arc<dyn Any + Send + Sync> {
+        self
+    }
+}
+
+/// Anything a process holds a handle to besides its files. The object
+/// lives as long as some handle or kernel user keeps it, so closing the
+/// handle or exiting releases it whatever it is.
+pub trait KernelObject: Any + AsAny + Send + Sync {
+    fn kind(&self) -> ObjectKind;
+}
+
+/// Slots of kernel objects addressed by small integer handles,
+/// the same way file descriptors index `fd_table`.
+///
+/// Freed handles are reused lowest-first, and every lookup is checked,
+/// so a bad handle from user space is reported as `None` instead of
+/// panicking the kernel.
+pub struct HandleTable {
+    /// indexed by kind, then by handle
+    slots: [Vec<Option<Arc<dyn KernelObject>>>; ObjectKind::COUNT],
+}
+
+impl HandleTable {
+    pub fn new() -> Self {
+        Self {
+            slots: core::array::from_fn(|_| Vec::new()),
+        }
+    }
+    pub fn insert(&mut self, object: Arc<dyn KernelObject>) -> usize {
+        let slots = &mut self.slots[object.kind() as usize];
+        if let Some(handle) = slots.iter().position(|slot| slot.is_none()) {
+            slots[handle] = Some(object);
+            handle
+        } else {
+            slots.push(Some(object));
+            slots.len() - 1
+        }
+    }
+    pub fn get(&self, kind: ObjectKind, handle: usize) -> Option<Arc<dyn KernelObject>> {
+        self.slots[kind as usize]
+            .get(handle)
+            .and_then(|slot| slot.clone())
+    }
+    pub fn remove(&mut self, kind: ObjectKind, handle: usize) -> Option<Arc<dyn KernelObject>> {
+        self.slots[kind as usize]
+            .get_mut(handle)
+            .and_then(|slot| slot.take())
+    }
+    /// Drop every object, e.g. when the owning process exits.
+    pub fn clear(&mut self) {
+        self.slots.iter_mut().for_each(Vec::clear);
+    }
+    /// Look up `handle` and downcast it, failing if it refers to an
+    /// object of another type.
+    pub fn get_as<T: KernelObject>(&self, kind: ObjectKind, handle: usize) -> Option<Arc<T>> {
+        self.get(kind, handle)
+            .and_then(|object| object.as_any().downcast::<T>().ok())
+    }
+    /// Remove `handle` only if it refers to an object of type `T`.
+    pub fn remove_as<T: KernelObject>(
+        &mut self,
+        kind: ObjectKind,
+        handle: usize,
+    ) -> Option<Arc<T>> {
+        self.get_as::<T>(kind, handle)?;
+        self.remove(kind, handle)
+            .and_then(|object| object.as_any().downcast::<T>().ok())
+    }
+}
+
+lazy_static! {
+    /// Objects looked up by a key shared between processes (e.g. System V
+    /// IPC keys), each kind with keys of its own. Only the handles and
+    /// mappings of the object keep it alive, the key goes with it.
+    static ref NAMED_OBJECTS: SpinMutex<BTreeMap<(ObjectKind, usize), Weak<dyn KernelObject>>> =
+        SpinMutex::new(BTreeMap::new());
+}
+
+/// Find the object of `kind` registered under `key` if it has type `T`.
+/// There are no users or permission bits to check, so any process that
+/// knows the key may open the object, as root may on Unix; one made with
+/// `IPC_PRIVATE` is never registered and stays with the processes handed
+/// its handle.
+pub fn named_object<T: KernelObject>(kind: ObjectKind, key: usize) -> Option<Arc<T>> {
+    NAMED_OBJECTS
+        .exclusive_access()
+        .get(&(kind, key))
+        .and_then(Weak::upgrade)
+        .and_then(|object| object.as_any().downcast::<T>().ok())
+}
+
+/// Register `object` under `key`, returns false if the key is taken.
+pub fn insert_named_object(key: usize, object: &Arc<dyn KernelObject>) -> bool {
+    let mut named = NAMED_OBJECTS.exclusive_access();
+    // keys of the objects that went away are free again
+    named.retain(|_, object| object.strong_count() > 0);
+    let key = (object.kind(), key);
+    if named.contains_key(&key) {
+        return false;
+    }
+    named.insert(key, Arc::downgrade(object));
+    true
+}
@@ -1,7 +1,10 @@
 use super::ProcessControlBlock;
-use crate::config::{KERNEL_STACK_SIZE, PAGE_SIZE, TRAMPOLINE, TRAP_CONTEXT_BASE, USER_STACK_SIZE};
-use crate::mm::{MapPermission, PhysPageNum, VirtAddr, KERNEL_SPACE};
-use crate::sync::UPIntrFreeCell;
+use crate::config::{
+    KERNEL_STACK_SIZE, PAGE_SIZE, TRAMPOLINE, TRAP_CONTEXT_BASE, USER_SPACE_END, USER_STACK_MAX,
+    USER_STACK_SIZE,
+};
+use crate::mm::{MapPermission, MemorySet, OutOfMemory, PhysPageNum, VirtAddr, KERNEL_SPACE};
+use crate::sync::SpinMutexIrqSave;
 use alloc::{
     sync::{Arc, Weak},
     vec::Vec,
@@ -40,10 +43,10 @@ impl RecycleAllocator {
 }
 
 lazy_static! {
-    static ref PID_ALLOCATOR: UPIntrFreeCell<RecycleAllocator> =
-        unsafe { UPIntrFreeCell::new(RecycleAllocator::new()) };
-    static ref KSTACK_ALLOCATOR: UPIntrFreeCell<RecycleAllocator> =
-        unsafe { UPIntrFreeCell::new(RecycleAllocator::new()) };
+    static ref PID_ALLOCATOR: SpinMutexIrqSave<RecycleAllocator> =
+        SpinMutexIrqSave::new(RecycleAllocator::new());
+    static ref KSTACK_ALLOCATOR: SpinMutexIrqSave<RecycleAllocator> =
+        SpinMutexIrqSave::new(RecycleAllocator::new());
 }
 
 pub const IDLE_PID: usize = 0;
@@ -67,31 +70,59 @@ pub fn kernel_stack_position(kstack_id: usize) -> (usize, usize) {
     (bottom, top)
 }
 
-pub struct KernelStack(pub usize);
+/// The kernel stack whose guard page holds `va`, if any.
+pub fn kernel_stack_guard(va: usize) -> Option<usize> {
+    // kernel stacks are stacked down from the trampoline in the high half
+    if !(USER_SPACE_END..TRAMPOLINE).contains(&va) {
+        return None;
+    }
+    let kstack_id = (TRAMPOLINE - 1 - va) / (KERNEL_STACK_SIZE + PAGE_SIZE);
+    (va < kernel_stack_position(kstack_id).0).then_some(kstack_id)
+}
+
+/// A kernel stack slot, given back when dropped. Slots are numbered
+/// apart from pids, so every thread can have one.
+pub struct KStackId(pub usize);
 
-pub fn kstack_alloc() -> KernelStack {
-    let kstack_id = KSTACK_ALLOCATOR.exclusive_access().alloc();
-    let (kstack_bottom, kstack_top) = kernel_stack_position(kstack_id);
-    KERNEL_SPACE.exclusive_access().insert_framed_area(
-        kstack_bottom.into(),
-        kstack_top.into(),
-        MapPermission::R | MapPermission::W,
-    );
-    KernelStack(kstack_id)
+pub fn kstack_id_alloc() -> KStackId {
+    KStackId(KSTACK_ALLOCATOR.exclusive_access().alloc())
+}
+
+impl Drop for KStackId {
+    fn drop(&mut self) {
+        KSTACK_ALLOCATOR.exclusive_access().dealloc(self.0);
+    }
+}
+
+pub struct KernelStack(pub KStackId);
+
+pub fn kstack_alloc() -> Result<KernelStack, OutOfMemory> {
+    KernelStack::new(kstack_id_alloc())
 }
 
 impl Drop for KernelStack {
     fn drop(&mut self) {
-        let (kernel_stack_bottom, _) = kernel_stack_position(self.0);
+        let (kernel_stack_bottom, _) = kernel_stack_position(self.0 .0);
         let kernel_stack_bottom_va: VirtAddr = kernel_stack_bottom.into();
         KERNEL_SPACE
             .exclusive_access()
             .remove_area_with_start_vpn(kernel_stack_bottom_va.into());
-        KSTACK_ALLOCATOR.exclusive_access().dealloc(self.0);
     }
 }
 
 impl KernelStack {
+    /// Map the kernel stack in slot `kstack_id`.
+    pub fn new(kstack_id: KStackId) -> Result<Self, OutOfMemory> {
+        let kstack = Self(kstack_id);
+        let (kstack_bottom, kstack_top) = kernel_stack_position(kstack.0 .0);
+        // dropping the stack on failure needs KERNEL_SPACE again
+        let mapped = KERNEL_SPACE.exclusive_access().insert_framed_area(
+            kstack_bottom.into(),
+            kstack_top.into(),
+            MapPermission::R | MapPermission::W,
+        );
+        mapped.map(|_| kstack)
+    }
     #[allow(unused)]
     pub fn push_on_top<T>(&self, value: T) -> *mut T
     where
@@ -105,7 +136,7 @@ impl KernelStack {
         ptr_mut
     }
     pub fn get_top(&self) -> usize {
-        let (_, kernel_stack_top) = kernel_stack_position(self.0);
+        let (_, kernel_stack_top) = kernel_stack_position(self.0 .0);
         kernel_stack_top
     }
 }
@@ -120,8 +151,33 @@ fn trap_cx_bottom_from_tid(tid: usize) -> usize {
     TRAP_CONTEXT_BASE - tid * PAGE_SIZE
 }
 
+/// Bottom of the room the stack of `tid` may grow into, above a guard page.
 fn ustack_bottom_from_tid(ustack_base: usize, tid: usize) -> usize {
-    ustack_base + tid * (PAGE_SIZE + USER_STACK_SIZE)
+    ustack_base + tid * (PAGE_SIZE + USER_STACK_MAX)
+}
+
+/// Map the initial user stack and the trap context of `tid` into
+/// `memory_set`.
+pub fn map_user_res(
+    memory_set: &mut MemorySet,
+    ustack_base: usize,
+    tid: usize,
+) -> Result<(), OutOfMemory> {
+    // alloc user stack, the top of its room, the rest is faulted in
+    let ustack_top = ustack_bottom_from_tid(ustack_base, tid) + USER_STACK_MAX;
+    memory_set.insert_framed_area(
+        (ustack_top - USER_STACK_SIZE).into(),
+        ustack_top.into(),
+        MapPermission::R | MapPermission::W | MapPermission::U,
+    )?;
+    // alloc trap_cx
+    let trap_cx_bottom = trap_cx_bottom_from_tid(tid);
+    let trap_cx_top = trap_cx_bottom + PAGE_SIZE;
+    memory_set.insert_framed_area(
+        trap_cx_bottom.into(),
+        trap_cx_top.into(),
+        MapPermission::R | MapPermission::W,
+    )
 }
 
 impl TaskUserRes {
@@ -129,49 +185,35 @@ impl TaskUserRes {
         process: Arc<ProcessControlBlock>,
         ustack_base: usize,
         alloc_user_res: bool,
-    ) -> Self {
+    ) -> Result<Self, OutOfMemory> {
         let tid = process.inner_exclusive_access().alloc_tid();
         let task_user_res = Self {
             tid,
             ustack_base,
             process: Arc::downgrade(&process),
         };
+        // on failure dropping it takes back what did get mapped
         if alloc_user_res {
-            task_user_res.alloc_user_res();
+            task_user_res.alloc_user_res()?;
         }
-        task_user_res
+        Ok(task_user_res)
     }
 
-    pub fn alloc_user_res(&self) {
+    pub fn alloc_user_res(&self) -> Result<(), OutOfMemory> {
         let process = self.process.upgrade().unwrap();
         let mut process_inner = process.inner_exclusive_access();
-        // alloc user stack
-        let ustack_bottom = ustack_bottom_from_tid(self.ustack_base, self.tid);
-        let ustack_top = ustack_bottom + USER_STACK_SIZE;
-        process_inner.memory_set.insert_framed_area(
-            ustack_bottom.into(),
-            ustack_top.into(),
-            MapPermission::R | MapPermission::W | MapPermission::U,
-        );
-        // alloc trap_cx
-        let trap_cx_bottom = trap_cx_bottom_from_tid(self.tid);
-        let trap_cx_top = trap_cx_bottom + PAGE_SIZE;
-        process_inner.memory_set.insert_framed_area(
-            trap_cx_bottom.into(),
-            trap_cx_top.into(),
-            MapPermission::R | MapPermission::W,
-        );
+        map_user_res(&mut process_inner.memory_set, self.ustack_base, self.tid)
     }
 
     fn dealloc_user_res(&self) {
         // dealloc tid
         let process = self.process.upgrade().unwrap();
         let mut process_inner = process.inner_exclusive_access();
-        // dealloc ustack manually
-        let ustack_bottom_va: VirtAddr = ustack_bottom_from_tid(self.ustack_base, self.tid).into();
+        // dealloc ustack manually, however far it has grown
+        let ustack_top_va: VirtAddr = (self.ustack_top() - 1).into();
         process_inner
             .memory_set
-            .remove_area_with_start_vpn(ustack_bottom_va.into());
+            .remove_area_containing(ustack_top_va.floor());
         // dealloc trap_cx manually
         let trap_cx_bottom_va: VirtAddr = trap_cx_bottom_from_tid(self.tid).into();
         process_inner
@@ -214,7 +256,13 @@ impl TaskUserRes {
         self.ustack_base
     }
     pub fn ustack_top(&self) -> usize {
-        ustack_bottom_from_tid(self.ustack_base, self.tid) + USER_STACK_SIZE
+        ustack_bottom_from_tid(self.ustack_base, self.tid) + USER_STACK_MAX
+    }
+    /// Whether `va` is below the stack but in its slot, in the room it
+    /// may grow into or the guard page beneath.
+    pub fn below_ustack(&self, va: usize) -> bool {
+        let guard = ustack_bottom_from_tid(self.ustack_base, self.tid) - PAGE_SIZE;
+        (guard..self.ustack_top()).contains(&va)
     }
 }
 
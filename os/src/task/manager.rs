@@ -1,62 +1,530 @@
+use super::hart_id;
 use super::{ProcessControlBlock, TaskControlBlock, TaskStatus};
-use crate::sync::UPIntrFreeCell;
+use crate::config::{DEADLINE_BANDWIDTH_PERCENT, LOAD_BALANCE_INTERVAL, MAX_HARTS};
+use crate::ipi::{kick_idle_hart, online_harts, preempt_hart};
+use crate::sync::{Rcu, SpinMutexIrqSave};
+use crate::timer::get_time;
 use alloc::collections::{BTreeMap, VecDeque};
 use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::fmt::{self, Write};
+use core::sync::atomic::{AtomicUsize, Ordering};
 use lazy_static::*;
 
+/// Stride added for a task of priority 1, each task advances by
+/// `BIG_STRIDE / priority` whenever it gets picked.
+const BIG_STRIDE: usize = 1 << 32;
+
+/// Whether stride `a` comes before `b`. Strides wrap around, but those of
+/// ready tasks are never more than `BIG_STRIDE / MIN_PRIORITY` apart, so
+/// the sign of the wrapping difference gives their order.
+fn stride_before(a: usize, b: usize) -> bool {
+    (a.wrapping_sub(b) as isize) < 0
+}
+
+/// Fixed point unit of bandwidth, the share of a hart a deadline task
+/// reserves.
+const BW_UNIT: usize = 1 << 20;
+
+/// Scheduling parameters of a thread in the deadline class, in timer
+/// units. It may run for `runtime` out of every `period`, and holds the
+/// bandwidth it was admitted with on `hart` until it is dropped.
+pub struct DeadlineParams {
+    pub runtime: usize,
+    pub period: usize,
+    /// end of the current period, the deadline EDF orders by
+    pub due: usize,
+    /// runtime left in the current period
+    pub budget: usize,
+    hart: usize,
+    bandwidth: usize,
+}
+
+impl Drop for DeadlineParams {
+    fn drop(&mut self) {
+        DEADLINE_BANDWIDTH.exclusive_access()[self.hart] -= self.bandwidth;
+    }
+}
+
 pub struct TaskManager {
-    ready_queue: VecDeque<Arc<TaskControlBlock>>,
+    /// ready tasks with the strides they were queued with
+    ready_queue: VecDeque<(usize, Arc<TaskControlBlock>)>,
+    /// ready deadline tasks with budget left, they run before any other
+    deadline_queue: Vec<Arc<TaskControlBlock>>,
+    /// deadline tasks that used up their budget, until their period ends
+    throttled: Vec<Arc<TaskControlBlock>>,
+    /// stride of the last picked task, queued tasks never lag behind it
+    min_stride: usize,
+    /// tasks picked since the last look at the other queues
+    picks: usize,
 }
 
-/// A simple FIFO scheduler.
+/// A stride scheduler: the ready task with the smallest stride runs next,
+/// so each task gets CPU time in proportion to its priority.
 impl TaskManager {
     pub fn new() -> Self {
         Self {
             ready_queue: VecDeque::new(),
+            deadline_queue: Vec::new(),
+            throttled: Vec::new(),
+            min_stride: 0,
+            picks: 0,
         }
     }
-    pub fn add(&mut self, task: Arc<TaskControlBlock>) {
-        self.ready_queue.push_back(task);
+    pub fn add(&mut self, hart: usize, task: Arc<TaskControlBlock>) {
+        let mut task_inner = task.inner_exclusive_access();
+        if let Some(params) = task_inner.deadline.as_mut() {
+            let now = get_time();
+            if params.budget == 0 && now < params.due {
+                drop(task_inner);
+                self.throttled.push(task);
+                return;
+            }
+            // a period that is over, or a budget that would take more than
+            // the reserved share in what is left of it, starts a new one
+            if now >= params.due
+                || params.budget * params.period > (params.due - now) * params.runtime
+            {
+                params.due = now + params.period;
+                params.budget = params.runtime;
+            }
+            drop(task_inner);
+            self.deadline_queue.push(task);
+            return;
+        }
+        // new or long blocked tasks must not catch up on the time they
+        // missed, and strides from another queue mean nothing here
+        if task_inner.hart != hart || stride_before(task_inner.stride, self.min_stride) {
+            task_inner.stride = self.min_stride;
+        }
+        task_inner.hart = hart;
+        let stride = task_inner.stride;
+        drop(task_inner);
+        self.ready_queue.push_back((stride, task));
+    }
+    /// Take the deadline task due first, dropping dead ones on the way.
+    fn fetch_deadline(&mut self) -> Option<Arc<TaskControlBlock>> {
+        loop {
+            let next = self
+                .deadline_queue
+                .iter()
+                .enumerate()
+                .min_by_key(|(_, task)| task_due(task))?
+                .0;
+            let task = self.deadline_queue.swap_remove(next);
+            if task.inner_exclusive_access().res.is_some() {
+                return Some(task);
+            }
+        }
+    }
+    /// Give the throttled tasks whose period is over a new budget, and drop
+    /// those that died meanwhile.
+    fn replenish(&mut self, hart: usize) {
+        let now = get_time();
+        self.throttled
+            .retain(|task| task.inner_exclusive_access().res.is_some());
+        let (due, throttled): (Vec<_>, Vec<_>) = self
+            .throttled
+            .drain(..)
+            .partition(|task| task_due(task) <= now);
+        self.throttled = throttled;
+        for task in due {
+            self.add(hart, task);
+        }
     }
-    pub fn fetch(&mut self) -> Option<Arc<TaskControlBlock>> {
-        self.ready_queue.pop_front()
+    /// Publish the state of this queue for lock-free readers.
+    fn publish(&self, hart: usize) {
+        QUEUED[hart].store(self.ready_queue.len(), Ordering::Relaxed);
+        let earliest = self.deadline_queue.iter().map(task_due).min();
+        EARLIEST_DUE[hart].store(earliest.unwrap_or(usize::MAX), Ordering::Relaxed);
+        let replenish = self.throttled.iter().map(task_due).min();
+        REPLENISH_AT[hart].store(replenish.unwrap_or(usize::MAX), Ordering::Relaxed);
+    }
+    /// Take the task with the smallest stride among those that may run on
+    /// `hart`, after any deadline task if this is the queue of `hart`.
+    pub fn fetch(&mut self, hart: usize, own: bool) -> Option<Arc<TaskControlBlock>> {
+        if own {
+            if let Some(task) = self.fetch_deadline() {
+                return Some(task);
+            }
+        }
+        loop {
+            let mut next = None;
+            for (idx, (stride, task)) in self.ready_queue.iter().enumerate() {
+                if task.inner_exclusive_access().affinity & 1 << hart == 0 {
+                    continue;
+                }
+                if next.map_or(true, |next| {
+                    stride_before(*stride, self.ready_queue[next].0)
+                }) {
+                    next = Some(idx);
+                }
+            }
+            let (stride, task) = self.ready_queue.remove(next?)?;
+            let mut task_inner = task.inner_exclusive_access();
+            // threads of an exited process are dropped once they come up
+            if task_inner.res.is_none() {
+                continue;
+            }
+            // a stolen task is charged in the queue it moves to
+            if task_inner.hart == hart {
+                self.min_stride = stride;
+                task_inner.stride = stride.wrapping_add(BIG_STRIDE / task_inner.priority);
+            }
+            drop(task_inner);
+            return Some(task);
+        }
     }
 }
 
+/// The deadline of a queued deadline task, `usize::MAX` if it left the
+/// class.
+fn task_due(task: &Arc<TaskControlBlock>) -> usize {
+    task.inner_exclusive_access()
+        .deadline
+        .as_ref()
+        .map_or(usize::MAX, |params| params.due)
+}
+
 lazy_static! {
-    pub static ref TASK_MANAGER: UPIntrFreeCell<TaskManager> =
-        unsafe { UPIntrFreeCell::new(TaskManager::new()) };
-    pub static ref PID2PCB: UPIntrFreeCell<BTreeMap<usize, Arc<ProcessControlBlock>>> =
-        unsafe { UPIntrFreeCell::new(BTreeMap::new()) };
+    /// One ready queue per hart, each hart picks from its own and steals
+    /// from the others when it runs dry.
+    static ref TASK_MANAGERS: [SpinMutexIrqSave<TaskManager>; MAX_HARTS] =
+        core::array::from_fn(|_| SpinMutexIrqSave::new(TaskManager::new()));
+    /// Length of each ready queue, read without taking its lock.
+    static ref QUEUED: [AtomicUsize; MAX_HARTS] = core::array::from_fn(|_| AtomicUsize::new(0));
+    /// Deadline of the first ready deadline task of each hart, and when the
+    /// first throttled one gets a new budget, `usize::MAX` if none.
+    static ref EARLIEST_DUE: [AtomicUsize; MAX_HARTS] =
+        core::array::from_fn(|_| AtomicUsize::new(usize::MAX));
+    static ref REPLENISH_AT: [AtomicUsize; MAX_HARTS] =
+        core::array::from_fn(|_| AtomicUsize::new(usize::MAX));
+    /// Bandwidth the deadline tasks admitted on each hart reserve.
+    static ref DEADLINE_BANDWIDTH: SpinMutexIrqSave<[usize; MAX_HARTS]> =
+        SpinMutexIrqSave::new([0; MAX_HARTS]);
+    /// Every process by pid. Readers go without a lock, so that kill and
+    /// procfs never wait for fork or exit.
+    static ref PID2PCB: Rcu<BTreeMap<usize, Arc<ProcessControlBlock>>> = Rcu::new(BTreeMap::new());
+}
+
+/// The queue a task goes to: that of the hart it ran on last if it still
+/// may run there, else the shortest one it may run on. A deadline task
+/// always goes to the hart it was admitted on.
+fn pick_hart(task: &Arc<TaskControlBlock>) -> usize {
+    let task_inner = task.inner_exclusive_access();
+    if let Some(params) = task_inner.deadline.as_ref() {
+        return params.hart;
+    }
+    let allowed = task_inner.affinity & online_harts();
+    if allowed & 1 << task_inner.hart != 0 {
+        return task_inner.hart;
+    }
+    (0..MAX_HARTS)
+        .filter(|hart| allowed & 1 << hart != 0)
+        .min_by_key(|&hart| QUEUED[hart].load(Ordering::Relaxed))
+        // before the harts come online everything starts out here
+        .unwrap_or_else(hart_id)
 }
 
+/// Queue a ready task. A deadline task due before the others queued on
+/// another hart has that hart look at its queue at once, this hart does
+/// so at its next timer interrupt.
 pub fn add_task(task: Arc<TaskControlBlock>) {
-    TASK_MANAGER.exclusive_access().add(task);
+    let hart = pick_hart(&task);
+    let mut manager = TASK_MANAGERS[hart].exclusive_access();
+    let earliest = EARLIEST_DUE[hart].load(Ordering::Relaxed);
+    manager.add(hart, task);
+    manager.publish(hart);
+    drop(manager);
+    if hart != hart_id() && EARLIEST_DUE[hart].load(Ordering::Relaxed) < earliest {
+        preempt_hart(hart);
+    } else {
+        kick_idle_hart(hart);
+    }
+}
+
+/// Whether some queue has a task, which this hart may be able to take.
+/// Deadline tasks count on their own hart only.
+pub fn has_ready_task() -> bool {
+    EARLIEST_DUE[hart_id()].load(Ordering::Relaxed) != usize::MAX
+        || QUEUED
+            .iter()
+            .any(|queued| queued.load(Ordering::Relaxed) != 0)
+}
+
+fn fetch_from(victim: usize, hart: usize) -> Option<Arc<TaskControlBlock>> {
+    let mut manager = TASK_MANAGERS[victim].exclusive_access();
+    let task = manager.fetch(hart, victim == hart);
+    // dead threads may have been dropped as well
+    manager.publish(victim);
+    task
+}
+
+/// Take a task from the longest other queue longer than `at_least` and
+/// make it start out in the queue of `hart`.
+fn steal(hart: usize, at_least: usize) -> Option<Arc<TaskControlBlock>> {
+    let mut victims: Vec<usize> = (0..MAX_HARTS)
+        .filter(|&victim| victim != hart && QUEUED[victim].load(Ordering::Relaxed) > at_least)
+        .collect();
+    victims
+        .sort_unstable_by_key(|&victim| core::cmp::Reverse(QUEUED[victim].load(Ordering::Relaxed)));
+    let task = victims
+        .into_iter()
+        .find_map(|victim| fetch_from(victim, hart))?;
+    let min_stride = TASK_MANAGERS[hart].exclusive_access().min_stride;
+    let mut task_inner = task.inner_exclusive_access();
+    task_inner.hart = hart;
+    task_inner.stride = min_stride.wrapping_add(BIG_STRIDE / task_inner.priority);
+    drop(task_inner);
+    Some(task)
 }
 
+/// The next task for this hart. Every `LOAD_BALANCE_INTERVAL` picks, or
+/// when its own queue is empty, it pulls a task over from a queue that is
+/// longer than its own by two or more.
+pub fn fetch_task() -> Option<Arc<TaskControlBlock>> {
+    let hart = hart_id();
+    let balance = TASK_MANAGERS[hart].exclusive_session(|manager| {
+        manager.picks += 1;
+        if manager.picks < LOAD_BALANCE_INTERVAL {
+            return false;
+        }
+        manager.picks = 0;
+        true
+    });
+    if balance {
+        let queued = QUEUED[hart].load(Ordering::Relaxed);
+        if let Some(task) = steal(hart, queued + 1) {
+            return Some(task);
+        }
+    }
+    fetch_from(hart, hart).or_else(|| steal(hart, 0))
+}
+
+/// The new priority applies from the next time the task gets picked.
+pub fn set_task_priority(task: &Arc<TaskControlBlock>, priority: usize) {
+    task.inner_exclusive_access().priority = priority;
+}
+
+/// Restrict `task` to the harts in `affinity`. A ready task queued on a
+/// hart it may no longer run on moves to another queue now, a running one
+/// the next time it gets queued.
+pub fn set_task_affinity(task: &Arc<TaskControlBlock>, affinity: usize) {
+    let hart = {
+        let mut task_inner = task.inner_exclusive_access();
+        task_inner.affinity = affinity;
+        task_inner.hart
+    };
+    if affinity & 1 << hart != 0 {
+        return;
+    }
+    if let Some(task) = dequeue(hart, task) {
+        add_task(task);
+    }
+}
+
+/// Take `task` out of the queues of `hart` if it is queued there.
+fn dequeue(hart: usize, task: &Arc<TaskControlBlock>) -> Option<Arc<TaskControlBlock>> {
+    let mut manager = TASK_MANAGERS[hart].exclusive_access();
+    let task = if let Some(idx) = manager
+        .ready_queue
+        .iter()
+        .position(|(_, queued)| Arc::ptr_eq(queued, task))
+    {
+        manager.ready_queue.remove(idx).map(|(_, task)| task)
+    } else if let Some(idx) = manager
+        .deadline_queue
+        .iter()
+        .position(|queued| Arc::ptr_eq(queued, task))
+    {
+        Some(manager.deadline_queue.swap_remove(idx))
+    } else {
+        let idx = manager
+            .throttled
+            .iter()
+            .position(|queued| Arc::ptr_eq(queued, task))?;
+        Some(manager.throttled.swap_remove(idx))
+    };
+    manager.publish(hart);
+    task
+}
+
+/// Move `task` into the deadline class with `runtime` out of every
+/// `period`, or back to the stride class with `None`. A deadline task is
+/// only admitted on a hart it may run on whose deadline tasks keep within
+/// `DEADLINE_BANDWIDTH_PERCENT` of it, so that all of them meet their
+/// deadlines. A queued task is queued again in its new class. Returns
+/// whether the task was admitted, it is left as it was if not.
+pub fn set_task_deadline(task: &Arc<TaskControlBlock>, params: Option<(usize, usize)>) -> bool {
+    let params = match params {
+        Some((runtime, period)) => {
+            let bandwidth = runtime * BW_UNIT / period;
+            let limit = BW_UNIT * DEADLINE_BANDWIDTH_PERCENT / 100;
+            let task_inner = task.inner_exclusive_access();
+            let allowed = task_inner.affinity & online_harts();
+            // what the task holds now is given back when it is replaced
+            let held = task_inner
+                .deadline
+                .as_ref()
+                .map(|params| (params.hart, params.bandwidth));
+            let preferred = task_inner.hart;
+            drop(task_inner);
+            let mut bandwidths = DEADLINE_BANDWIDTH.exclusive_access();
+            let free = |hart: usize| {
+                let held = match held {
+                    Some((held_hart, held)) if held_hart == hart => held,
+                    _ => 0,
+                };
+                limit.saturating_sub(bandwidths[hart] - held)
+            };
+            let fits = |hart: &usize| allowed & 1 << hart != 0 && free(*hart) >= bandwidth;
+            let hart = if fits(&preferred) {
+                preferred
+            } else {
+                match (0..MAX_HARTS).filter(fits).max_by_key(|&hart| free(hart)) {
+                    Some(hart) => hart,
+                    None => return false,
+                }
+            };
+            bandwidths[hart] += bandwidth;
+            Some(DeadlineParams {
+                runtime,
+                period,
+                due: 0,
+                budget: 0,
+                hart,
+                bandwidth,
+            })
+        }
+        None => None,
+    };
+    let hart = task.inner_exclusive_access().hart;
+    let queued = dequeue(hart, task);
+    let mut task_inner = task.inner_exclusive_access();
+    if let Some(params) = params.as_ref() {
+        task_inner.hart = params.hart;
+    }
+    let old = core::mem::replace(&mut task_inner.deadline, params);
+    drop(task_inner);
+    // frees the bandwidth held before, not under the task lock
+    drop(old);
+    if let Some(task) = queued {
+        add_task(task);
+    }
+    true
+}
+
+/// Whether a ready deadline task should take this hart from the one the
+/// running task is in.
+pub fn deadline_preempts(task: &Arc<TaskControlBlock>) -> bool {
+    let due = task
+        .inner_exclusive_access()
+        .deadline
+        .as_ref()
+        .map_or(usize::MAX, |params| params.due);
+    EARLIEST_DUE[hart_id()].load(Ordering::Relaxed) < due
+}
+
+/// When the next throttled deadline task of this hart gets its budget
+/// back, `usize::MAX` if none waits.
+pub fn next_replenish() -> usize {
+    REPLENISH_AT[hart_id()].load(Ordering::Relaxed)
+}
+
+/// Queue the throttled deadline tasks of this hart whose period is over.
+pub fn replenish_deadline_tasks() {
+    let hart = hart_id();
+    if REPLENISH_AT[hart].load(Ordering::Relaxed) > get_time() {
+        return;
+    }
+    let mut manager = TASK_MANAGERS[hart].exclusive_access();
+    manager.replenish(hart);
+    manager.publish(hart);
+}
+
+/// Make a blocked task ready again. Threads torn down by the exit of
+/// their process while they slept or waited are left alone.
 pub fn wakeup_task(task: Arc<TaskControlBlock>) {
     let mut task_inner = task.inner_exclusive_access();
+    if task_inner.res.is_none() {
+        return;
+    }
     task_inner.task_status = TaskStatus::Ready;
     drop(task_inner);
     add_task(task);
 }
 
-pub fn fetch_task() -> Option<Arc<TaskControlBlock>> {
-    TASK_MANAGER.exclusive_access().fetch()
+pub fn pid2process(pid: usize) -> Option<Arc<ProcessControlBlock>> {
+    PID2PCB.read().get(&pid).map(Arc::clone)
 }
 
-pub fn pid2process(pid: usize) -> Option<Arc<ProcessControlBlock>> {
-    let map = PID2PCB.exclusive_access();
-    map.get(&pid).map(Arc::clone)
+pub fn processes() -> Vec<Arc<ProcessControlBlock>> {
+    PID2PCB.read().values().cloned().collect()
+}
+
+/// A line for each thread of each process, its pid, tid and state, with
+/// `?` for whatever is locked; for the crash dump, where a lock may never
+/// be let go.
+pub fn write_task_list(w: &mut impl Write) -> fmt::Result {
+    let map = PID2PCB.read();
+    for (pid, process) in map.iter() {
+        let inner = match process.try_inner_exclusive_access() {
+            Some(inner) => inner,
+            None => {
+                writeln!(w, "{} ?", pid)?;
+                continue;
+            }
+        };
+        for (tid, task) in inner.tasks.iter().enumerate() {
+            let task = match task {
+                Some(task) => task,
+                None => continue,
+            };
+            let status = match task.inner.try_exclusive_access() {
+                Some(task_inner) => match task_inner.task_status {
+                    TaskStatus::Ready => "ready",
+                    TaskStatus::Running => "running",
+                    TaskStatus::Blocked => "blocked",
+                },
+                None => "?",
+            };
+            let state = if inner.is_zombie {
+                "zombie"
+            } else if inner.stopped {
+                "stopped"
+            } else {
+                status
+            };
+            writeln!(w, "{} {} {}", pid, tid, state)?;
+        }
+    }
+    Ok(())
+}
+
+/// The processes in the process group `pgid`.
+pub fn process_group(pgid: usize) -> Vec<Arc<ProcessControlBlock>> {
+    PID2PCB
+        .read()
+        .values()
+        .filter(|process| process.inner_exclusive_access().pgid == pgid)
+        .cloned()
+        .collect()
 }
 
 pub fn insert_into_pid2process(pid: usize, process: Arc<ProcessControlBlock>) {
-    PID2PCB.exclusive_access().insert(pid, process);
+    PID2PCB.update(|map| {
+        let mut map = map.clone();
+        map.insert(pid, process);
+        map
+    });
 }
 
 pub fn remove_from_pid2process(pid: usize) {
-    let mut map = PID2PCB.exclusive_access();
-    if map.remove(&pid).is_none() {
-        panic!("cannot find pid {} in pid2task!", pid);
-    }
+    PID2PCB.update(|map| {
+        let mut map = map.clone();
+        if map.remove(&pid).is_none() {
+            panic!("cannot find pid {} in pid2task!", pid);
+        }
+        map
+    });
 }
@@ -1,31 +1,61 @@
+mod accounting;
 mod context;
+mod handle;
 mod id;
 mod manager;
+mod pidfd;
 mod process;
 mod processor;
+mod ptrace;
+mod rlimit;
 mod signal;
 mod switch;
 #[allow(clippy::module_inception)]
 mod task;
+mod watchdog;
 
 use self::id::TaskUserRes;
-use crate::fs::{open_file, OpenFlags};
+use crate::fs::{open_file, sync_all, OpenFlags};
+use crate::perf::PerfCounts;
+use crate::profile::dump_profile;
 use crate::sbi::shutdown;
+use crate::sync::dump_lock_stats;
+use accounting::account_budget;
 use alloc::{sync::Arc, vec::Vec};
 use lazy_static::*;
-use manager::fetch_task;
-use process::ProcessControlBlock;
+use log::info;
+use manager::{fetch_task, has_ready_task};
 use switch::__switch;
 
+pub use accounting::{
+    account_kernel, account_perf, account_trap_entry, account_trap_return, hart_times,
+    slice_expired, surrender_slice, CpuTimes, RUsage, Tms,
+};
 pub use context::TaskContext;
-pub use id::{kstack_alloc, pid_alloc, KernelStack, PidHandle, IDLE_PID};
-pub use manager::{add_task, pid2process, remove_from_pid2process, wakeup_task};
+pub use handle::{insert_named_object, named_object, AsAny, HandleTable, KernelObject, ObjectKind};
+pub use id::{kernel_stack_guard, kstack_alloc, pid_alloc, KernelStack, PidHandle, IDLE_PID};
+pub use manager::{
+    add_task, next_replenish, pid2process, process_group, processes, remove_from_pid2process,
+    replenish_deadline_tasks, set_task_affinity, set_task_deadline, set_task_priority, wakeup_task,
+    write_task_list,
+};
+pub use pidfd::Pidfd;
+pub use process::ProcessControlBlock;
 pub use processor::{
-    current_kstack_top, current_process, current_task, current_trap_cx, current_trap_cx_user_va,
-    current_user_token, run_tasks, schedule, take_current_task,
+    current_kstack, current_process, current_task, current_trap_cx, current_trap_cx_user_va,
+    current_user_token, hart_id, run_tasks, schedule, take_current_task, try_current_task,
+};
+pub use ptrace::{
+    breakpoint_trap, next_pcs, ptrace_access, ptrace_clear_steps, ptrace_detach,
+    ptrace_plant_steps, ptrace_regs, ptrace_resume, PtraceState, C_EBREAK,
 };
-pub use signal::SignalFlags;
-pub use task::{TaskControlBlock, TaskStatus};
+pub use rlimit::{RLimit, RLIMIT_NOFILE, RLIM_NLIMITS};
+pub use signal::{
+    current_signal_pending, handle_signals, wait_while_stopped, SignalAction, SignalActions,
+    SignalFlags, SIG_DFL, SIG_IGN,
+};
+pub use task::{TaskControlBlock, TaskStatus, ALL_HARTS, MAX_PRIORITY, MIN_PRIORITY};
+pub use watchdog::{watchdog_answer, watchdog_check};
 
 pub fn suspend_current_and_run_next() {
     // There must be an application running.
@@ -36,6 +66,7 @@ pub fn suspend_current_and_run_next() {
     let task_cx_ptr = &mut task_inner.task_cx as *mut TaskContext;
     // Change status to Ready
     task_inner.task_status = TaskStatus::Ready;
+    account_budget(&mut task_inner);
     drop(task_inner);
     // ---- release current TCB
 
@@ -50,6 +81,7 @@ pub fn block_current_task() -> *mut TaskContext {
     let task = take_current_task().unwrap();
     let mut task_inner = task.inner_exclusive_access();
     task_inner.task_status = TaskStatus::Blocked;
+    account_budget(&mut task_inner);
     &mut task_inner.task_cx as *mut TaskContext
 }
 
@@ -60,26 +92,85 @@ pub fn block_current_and_run_next() {
 
 /// Exit the current 'Running' task and run the next task in task list.
 pub fn exit_current_and_run_next(exit_code: i32) {
+    exit_current(exit_code, (exit_code & 0xff) << 8, false);
+}
+
+/// Exit the whole process of the current task, whichever thread it is.
+pub fn exit_group_and_run_next(exit_code: i32) {
+    exit_current(exit_code, (exit_code & 0xff) << 8, true);
+}
+
+/// Terminate the current task for the default action of `signal`, the
+/// whole process if it is the main thread.
+pub fn kill_current_and_run_next(signal: SignalFlags) {
+    let signum = signal.signum() as i32;
+    exit_current(-signum, signum, false);
+}
+
+/// Leave at once if another thread ended the process while this one was
+/// in the kernel, as its user resources are gone.
+pub fn exit_current_if_torn_down() {
+    if current_task()
+        .unwrap()
+        .inner_exclusive_access()
+        .res
+        .is_none()
+    {
+        exit_current(0, 0, false);
+    }
+}
+
+/// `exit_code` is for the threads of the process, `exit_status` for its
+/// parent to wait for. The process ends with the main thread, or with any
+/// thread if `group` is set.
+fn exit_current(exit_code: i32, exit_status: i32, group: bool) {
     let task = take_current_task().unwrap();
-    let mut task_inner = task.inner_exclusive_access();
     let process = task.process.upgrade().unwrap();
-    let tid = task_inner.res.as_ref().unwrap().tid;
+    // none if the process has been torn down under this thread
+    let tid = task
+        .inner_exclusive_access()
+        .res
+        .as_ref()
+        .map(|res| res.tid);
+    // clear its rows before the tid can be handed out again
+    if let Some(tid) = tid {
+        let mut process_inner = process.inner_exclusive_access();
+        process_inner.mutex_detector.remove_thread(tid);
+        process_inner.semaphore_detector.remove_thread(tid);
+    }
+    let mut task_inner = task.inner_exclusive_access();
     // record exit code
     task_inner.exit_code = Some(exit_code);
     task_inner.res = None;
+    task_inner.deadline = None;
+    account_kernel(&mut task_inner);
+    account_perf(&mut task_inner);
+    let times = core::mem::take(&mut task_inner.times);
+    let perf = core::mem::take(&mut task_inner.perf);
     // here we do not remove the thread since we are still using the kstack
     // it will be deallocated when sys_waittid is called
     drop(task_inner);
     drop(task);
+    let mut process_inner = process.inner_exclusive_access();
+    process_inner.times += times;
+    process_inner.perf += perf;
+    drop(process_inner);
+    let mut parent = None;
     // however, if this is the main thread of current process
-    // the process should terminate at once
-    if tid == 0 {
+    // the process should terminate at once, unless another thread is
+    // already doing so
+    let terminate = tid.is_some()
+        && (tid == Some(0) || group)
+        && !core::mem::replace(&mut process.inner_exclusive_access().exiting, true);
+    if terminate {
         let pid = process.getpid();
         if pid == IDLE_PID {
-            println!(
-                "[kernel] Idle process exit with exit_code {} ...",
-                exit_code
-            );
+            info!("Idle process exit with exit_code {} ...", exit_code);
+            dump_profile();
+            dump_lock_stats();
+            // this thread can no longer sleep, so flush with polling I/O
+            *crate::DEV_NON_BLOCKING_ACCESS.exclusive_access() = false;
+            sync_all();
             if exit_code != 0 {
                 //crate::sbi::shutdown(255); //255 == -1 for err hint
                 shutdown(true);
@@ -92,29 +183,51 @@ pub fn exit_current_and_run_next(exit_code: i32) {
         let mut process_inner = process.inner_exclusive_access();
         // mark this process as a zombie process
         process_inner.is_zombie = true;
-        // record exit code of main process
-        process_inner.exit_code = exit_code;
+        // record exit status of the process
+        process_inner.exit_status = exit_status;
+        parent = process_inner
+            .parent
+            .as_ref()
+            .and_then(|parent| parent.upgrade());
 
+        let mut traced = Vec::new();
         {
             // move all child processes under init process
             let mut initproc_inner = INITPROC.inner_exclusive_access();
             for child in process_inner.children.iter() {
-                child.inner_exclusive_access().parent = Some(Arc::downgrade(&INITPROC));
+                let mut child_inner = child.inner_exclusive_access();
+                child_inner.parent = Some(Arc::downgrade(&INITPROC));
+                if child_inner.ptrace.is_some() {
+                    traced.push(child.clone());
+                }
+                drop(child_inner);
                 initproc_inner.children.push(child.clone());
             }
+            // some of them may have exited already
+            if !process_inner.children.is_empty() {
+                INITPROC.child_exited.wake_all();
+            }
         }
 
         // deallocate user res (including tid/trap_cx/ustack) of all threads
         // it has to be done before we dealloc the whole memory_set
         // otherwise they will be deallocated twice
         let mut recycle_res = Vec::<TaskUserRes>::new();
+        let mut times = CpuTimes::default();
+        let mut perf = PerfCounts::default();
         for task in process_inner.tasks.iter().filter(|t| t.is_some()) {
             let task = task.as_ref().unwrap();
             let mut task_inner = task.inner_exclusive_access();
             if let Some(res) = task_inner.res.take() {
                 recycle_res.push(res);
             }
+            task_inner.deadline = None;
+            // the threads go away with the process
+            times += core::mem::take(&mut task_inner.times);
+            perf += core::mem::take(&mut task_inner.perf);
         }
+        process_inner.times += times;
+        process_inner.perf += perf;
         // dealloc_tid and dealloc_user_res require access to PCB inner, so we
         // need to collect those user res first, then release process_inner
         // for now to avoid deadlock/double borrow problem.
@@ -125,17 +238,29 @@ pub fn exit_current_and_run_next(exit_code: i32) {
         process_inner.children.clear();
         // deallocate other data in user space i.e. program code/data section
         process_inner.memory_set.recycle_data_pages();
-        // drop file descriptors
-        process_inner.fd_table.clear();
-        // Remove all tasks except for the main thread itself.
-        // This is because we are still using the kstack under the TCB
-        // of the main thread. This TCB, including its kstack, will be
-        // deallocated when the process is reaped via waitpid.
-        while process_inner.tasks.len() > 1 {
-            process_inner.tasks.pop();
+        // drop file descriptors and other kernel objects
+        let fd_table = core::mem::take(&mut process_inner.fd_table);
+        process_inner.handles.clear();
+        process_inner.itimer_real = None;
+        // closing a pipe end wakes the other end, which may need our inner
+        drop(process_inner);
+        drop(fd_table);
+        // init does not trace, whatever this process traced runs on
+        for child in traced {
+            ptrace_detach(&child);
         }
+        // The tasks are kept, as this thread and siblings still running on
+        // other harts are using the kstacks under their TCBs. These TCBs,
+        // including their kstacks, will be deallocated when the process is
+        // reaped via waitpid.
     }
     drop(process);
+    // under the lock, so a waitpid cannot miss it between checking the
+    // children and going to sleep
+    if let Some(parent) = parent {
+        let _parent_inner = parent.inner_exclusive_access();
+        parent.child_exited.wake_all();
+    }
     // we do not have to save task context
     let mut _unused = TaskContext::zero_init();
     schedule(&mut _unused as *mut _);
@@ -143,9 +268,8 @@ pub fn exit_current_and_run_next(exit_code: i32) {
 
 lazy_static! {
     pub static ref INITPROC: Arc<ProcessControlBlock> = {
-        let inode = open_file("initproc", OpenFlags::RDONLY).unwrap();
-        let v = inode.read_all();
-        ProcessControlBlock::new(v.as_slice())
+        let inode = open_file("/initproc", OpenFlags::RDONLY).unwrap();
+        ProcessControlBlock::new(&inode).unwrap()
     };
 }
 
@@ -153,12 +277,6 @@ pub fn add_initproc() {
     let _initproc = INITPROC.clone();
 }
 
-pub fn check_signals_of_current() -> Option<(i32, &'static str)> {
-    let process = current_process();
-    let process_inner = process.inner_exclusive_access();
-    process_inner.signals.check_error()
-}
-
 pub fn current_add_signal(signal: SignalFlags) {
     let process = current_process();
     let mut process_inner = process.inner_exclusive_access();
@@ -0,0 +1,26 @@
+//! Handles to a process that stay with the process they were opened for,
+//! where a pid names whatever process gets it next once the first one is
+//! reaped.
+
+use super::{KernelObject, ObjectKind, ProcessControlBlock};
+use alloc::sync::{Arc, Weak};
+
+pub struct Pidfd(Weak<ProcessControlBlock>);
+
+impl Pidfd {
+    pub fn new(process: &Arc<ProcessControlBlock>) -> Self {
+        Self(Arc::downgrade(process))
+    }
+    /// The process, unless it has exited.
+    pub fn process(&self) -> Option<Arc<ProcessControlBlock>> {
+        self.0
+            .upgrade()
+            .filter(|process| !process.inner_exclusive_access().is_zombie)
+    }
+}
+
+impl KernelObject for Pidfd {
+    fn kind(&self) -> ObjectKind {
+        ObjectKind::Pidfd
+    }
+}
@@ -1,37 +1,91 @@
-use super::id::RecycleAllocator;
+use super::handle::HandleTable;
+use super::id::{map_user_res, RecycleAllocator};
 use super::manager::insert_into_pid2process;
-use super::TaskControlBlock;
-use super::{add_task, SignalFlags};
+use super::ptrace::PtraceState;
+use super::rlimit::{default_rlimits, RLimits, RLIMIT_NOFILE, RLIMIT_STACK};
+use super::{add_task, SignalAction, SignalActions, SignalFlags, SIG_DFL, SIG_IGN};
 use super::{pid_alloc, PidHandle};
-use crate::fs::{File, Stdin, Stdout};
-use crate::mm::{translated_refmut, MemorySet, KERNEL_SPACE};
-use crate::sync::{Condvar, Mutex, Semaphore, UPIntrFreeCell, UPIntrRefMut};
+use super::{CpuTimes, TaskControlBlock};
+use crate::config::{ELF_DEMAND_PAGING, USER_SPACE_END, USER_STACK_MAX};
+use crate::fs::{File, OSInode, Stdin, Stdout};
+use crate::mm::{
+    copy_bytes_to_user, copy_to_user, frame_alloc, MemorySet, OutOfMemory, VirtAddr, VirtPageNum,
+    KERNEL_SPACE,
+};
+use crate::perf::PerfCounts;
+use crate::sync::{
+    interrupt_wait, DeadlockDetector, SpinMutexIrqSave, SpinMutexIrqSaveGuard, WaitQueue,
+};
+use crate::timer::PosixTimer;
 use crate::trap::{trap_handler, TrapContext};
 use alloc::string::String;
 use alloc::sync::{Arc, Weak};
 use alloc::vec;
 use alloc::vec::Vec;
+use core::sync::atomic::{AtomicBool, Ordering};
 
 pub struct ProcessControlBlock {
     // immutable
     pub pid: PidHandle,
+    /// signalled when a child becomes a zombie or stops
+    pub child_exited: WaitQueue,
+    /// signalled when this process is continued after a stop
+    pub continued: WaitQueue,
+    /// log every syscall of the process, inherited by its children
+    pub traced: AtomicBool,
     // mutable
-    inner: UPIntrFreeCell<ProcessControlBlockInner>,
+    inner: SpinMutexIrqSave<ProcessControlBlockInner>,
 }
 
 pub struct ProcessControlBlockInner {
     pub is_zombie: bool,
+    /// set by the thread tearing the process down, so only one does
+    pub exiting: bool,
     pub memory_set: MemorySet,
     pub parent: Option<Weak<ProcessControlBlock>>,
     pub children: Vec<Arc<ProcessControlBlock>>,
-    pub exit_code: i32,
+    /// as waitpid reports it, the exit code shifted left by 8 or the
+    /// number of the signal that killed the process
+    pub exit_status: i32,
+    /// CPU time of the threads that have exited
+    pub times: CpuTimes,
+    /// hardware counts of the threads that have exited
+    pub perf: PerfCounts,
+    /// CPU time of the children reaped so far, with their own children
+    pub children_times: CpuTimes,
+    /// peak resident pages of the images exec replaced
+    pub max_rss: usize,
+    /// the largest peak among the children reaped so far
+    pub children_max_rss: usize,
     pub fd_table: Vec<Option<Arc<dyn File + Send + Sync>>>,
+    /// absolute path of the working directory
+    pub cwd: String,
+    pub rlimits: RLimits,
+    /// process group and session, each named by the pid of its leader
+    pub pgid: usize,
+    pub sid: usize,
+    /// pending signals
     pub signals: SignalFlags,
+    pub signal_mask: SignalFlags,
+    pub signal_actions: SignalActions,
+    /// stopped by a signal, threads wait in `wait_while_stopped`
+    pub stopped: bool,
+    /// the signal of a stop the parent has not waited for yet
+    pub stop_signal: Option<SignalFlags>,
+    /// traced by the parent with `sys_ptrace`
+    pub ptrace: Option<PtraceState>,
     pub tasks: Vec<Option<Arc<TaskControlBlock>>>,
     pub task_res_allocator: RecycleAllocator,
-    pub mutex_list: Vec<Option<Arc<dyn Mutex>>>,
-    pub semaphore_list: Vec<Option<Arc<Semaphore>>>,
-    pub condvar_list: Vec<Option<Arc<Condvar>>>,
+    /// refuse lock and down requests that could deadlock
+    pub deadlock_detect: bool,
+    pub mutex_detector: DeadlockDetector,
+    pub semaphore_detector: DeadlockDetector,
+    /// mutexes, semaphores, condvars, timers and the like, one table for
+    /// all so that exit releases them all the same way
+    pub handles: HandleTable,
+    /// ITIMER_REAL of `setitimer`, created on first use and not inherited
+    /// by `fork`
+    pub itimer_real: Option<Arc<PosixTimer>>,
 }
 
 impl ProcessControlBlockInner {
@@ -40,15 +94,39 @@ impl ProcessControlBlockInner {
         self.memory_set.token()
     }
 
-    pub fn alloc_fd(&mut self) -> usize {
+    /// The lowest free file descriptor, `None` if all below RLIMIT_NOFILE
+    /// are taken.
+    pub fn alloc_fd(&mut self) -> Option<usize> {
+        let limit = self.rlimits[RLIMIT_NOFILE].cur;
         if let Some(fd) = (0..self.fd_table.len()).find(|fd| self.fd_table[*fd].is_none()) {
-            fd
-        } else {
+            (fd < limit).then_some(fd)
+        } else if self.fd_table.len() < limit {
             self.fd_table.push(None);
-            self.fd_table.len() - 1
+            Some(self.fd_table.len() - 1)
+        } else {
+            None
         }
     }
 
+    /// CPU time of the process, its live threads included.
+    pub fn cpu_times(&self) -> CpuTimes {
+        self.tasks.iter().flatten().fold(self.times, |times, task| {
+            times + task.inner_exclusive_access().times
+        })
+    }
+
+    /// Hardware counts of the process, its live threads included.
+    pub fn perf_counts(&self) -> PerfCounts {
+        self.tasks.iter().flatten().fold(self.perf, |perf, task| {
+            perf + task.inner_exclusive_access().perf
+        })
+    }
+
+    /// Peak resident pages of the process over all its images.
+    pub fn max_rss_pages(&self) -> usize {
+        self.max_rss.max(self.memory_set.peak_rss_pages())
+    }
+
     pub fn alloc_tid(&mut self) -> usize {
         self.task_res_allocator.alloc()
     }
@@ -67,47 +145,74 @@ impl ProcessControlBlockInner {
 }
 
 impl ProcessControlBlock {
-    pub fn inner_exclusive_access(&self) -> UPIntrRefMut<'_, ProcessControlBlockInner> {
+    #[track_caller]
+    pub fn inner_exclusive_access(&self) -> SpinMutexIrqSaveGuard<'_, ProcessControlBlockInner> {
         self.inner.exclusive_access()
     }
 
-    pub fn new(elf_data: &[u8]) -> Arc<Self> {
+    #[track_caller]
+    pub fn try_inner_exclusive_access(
+        &self,
+    ) -> Option<SpinMutexIrqSaveGuard<'_, ProcessControlBlockInner>> {
+        self.inner.try_exclusive_access()
+    }
+
+    pub fn new(app: &OSInode) -> Result<Arc<Self>, OutOfMemory> {
         // memory_set with elf program headers/trampoline/trap context/user stack
-        let (memory_set, ustack_base, entry_point) = MemorySet::from_elf(elf_data);
+        let (memory_set, ustack_base, entry_point) = load_app(app)?;
         // allocate a pid
         let pid_handle = pid_alloc();
+        let pid = pid_handle.0;
         let process = Arc::new(Self {
             pid: pid_handle,
-            inner: unsafe {
-                UPIntrFreeCell::new(ProcessControlBlockInner {
-                    is_zombie: false,
-                    memory_set,
-                    parent: None,
-                    children: Vec::new(),
-                    exit_code: 0,
-                    fd_table: vec![
-                        // 0 -> stdin
-                        Some(Arc::new(Stdin)),
-                        // 1 -> stdout
-                        Some(Arc::new(Stdout)),
-                        // 2 -> stderr
-                        Some(Arc::new(Stdout)),
-                    ],
-                    signals: SignalFlags::empty(),
-                    tasks: Vec::new(),
-                    task_res_allocator: RecycleAllocator::new(),
-                    mutex_list: Vec::new(),
-                    semaphore_list: Vec::new(),
-                    condvar_list: Vec::new(),
-                })
-            },
+            child_exited: WaitQueue::new(),
+            continued: WaitQueue::new(),
+            traced: AtomicBool::new(false),
+            inner: SpinMutexIrqSave::new(ProcessControlBlockInner {
+                is_zombie: false,
+                exiting: false,
+                memory_set,
+                parent: None,
+                children: Vec::new(),
+                exit_status: 0,
+                times: CpuTimes::default(),
+                perf: PerfCounts::default(),
+                children_times: CpuTimes::default(),
+                max_rss: 0,
+                children_max_rss: 0,
+                fd_table: vec![
+                    // 0 -> stdin
+                    Some(Arc::new(Stdin::new())),
+                    // 1 -> stdout
+                    Some(Arc::new(Stdout)),
+                    // 2 -> stderr
+                    Some(Arc::new(Stdout)),
+                ],
+                cwd: String::from("/"),
+                rlimits: default_rlimits(),
+                pgid: pid,
+                sid: pid,
+                signals: SignalFlags::empty(),
+                signal_mask: SignalFlags::empty(),
+                signal_actions: [SignalAction::default(); 32],
+                stopped: false,
+                stop_signal: None,
+                ptrace: None,
+                tasks: Vec::new(),
+                task_res_allocator: RecycleAllocator::new(),
+                deadlock_detect: false,
+                mutex_detector: DeadlockDetector::new(),
+                semaphore_detector: DeadlockDetector::new(),
+                handles: HandleTable::new(),
+                itimer_real: None,
+            }),
         });
         // create a main thread, we should allocate ustack and trap_cx here
         let task = Arc::new(TaskControlBlock::new(
             Arc::clone(&process),
             ustack_base,
             true,
-        ));
+        )?);
         // prepare trap_cx of main thread
         let task_inner = task.inner_exclusive_access();
         let trap_cx = task_inner.get_trap_cx();
@@ -117,7 +222,7 @@ impl ProcessControlBlock {
         *trap_cx = TrapContext::app_init_context(
             entry_point,
             ustack_top,
-            KERNEL_SPACE.exclusive_access().token(),
+            KERNEL_SPACE.shared_access().token(),
             kstack_top,
             trap_handler as usize,
         );
@@ -128,68 +233,64 @@ impl ProcessControlBlock {
         insert_into_pid2process(process.getpid(), Arc::clone(&process));
         // add main thread to scheduler
         add_task(task);
-        process
+        Ok(process)
     }
 
-    /// Only support processes with a single thread.
-    pub fn exec(self: &Arc<Self>, elf_data: &[u8], args: Vec<String>) {
+    /// Only support processes with a single thread. `args` and `envs` are
+    /// passed to the new image as argc/argv/envp in a0/a1/a2. On failure
+    /// the old image is left as it was.
+    pub fn exec(
+        self: &Arc<Self>,
+        app: &OSInode,
+        args: Vec<String>,
+        envs: Vec<String>,
+    ) -> Result<(), OutOfMemory> {
         assert_eq!(self.inner_exclusive_access().thread_count(), 1);
         // memory_set with elf program headers/trampoline/trap context/user stack
-        let (memory_set, ustack_base, entry_point) = MemorySet::from_elf(elf_data);
+        let (mut memory_set, ustack_base, entry_point) = load_app(app)?;
         let new_token = memory_set.token();
-        // substitute memory_set
-        self.inner_exclusive_access().memory_set = memory_set;
-        // then we alloc user resource for main thread again
-        // since memory_set has been changed
+        // the user resources of the main thread go into the new image
+        // before the old one is given up
         let task = self.inner_exclusive_access().get_task(0);
+        let tid = task.inner_exclusive_access().res.as_ref().unwrap().tid;
+        map_user_res(&mut memory_set, ustack_base, tid)?;
+        // substitute memory_set
+        let mut process_inner = self.inner_exclusive_access();
+        process_inner.max_rss = process_inner.max_rss_pages();
+        process_inner.memory_set = memory_set;
+        reset_handlers(&mut process_inner.signal_actions);
+        // a single step ends in the old image
+        if let Some(state) = process_inner.ptrace.as_mut() {
+            state.steps.clear();
+        }
+        drop(process_inner);
         let mut task_inner = task.inner_exclusive_access();
         task_inner.res.as_mut().unwrap().ustack_base = ustack_base;
-        task_inner.res.as_mut().unwrap().alloc_user_res();
         task_inner.trap_cx_ppn = task_inner.res.as_mut().unwrap().trap_cx_ppn();
-        // push arguments on user stack
-        let mut user_sp = task_inner.res.as_mut().unwrap().ustack_top();
-        user_sp -= (args.len() + 1) * core::mem::size_of::<usize>();
-        let argv_base = user_sp;
-        let mut argv: Vec<_> = (0..=args.len())
-            .map(|arg| {
-                translated_refmut(
-                    new_token,
-                    (argv_base + arg * core::mem::size_of::<usize>()) as *mut usize,
-                )
-            })
-            .collect();
-        *argv[args.len()] = 0;
-        for i in 0..args.len() {
-            user_sp -= args[i].len() + 1;
-            *argv[i] = user_sp;
-            let mut p = user_sp;
-            for c in args[i].as_bytes() {
-                *translated_refmut(new_token, p as *mut u8) = *c;
-                p += 1;
-            }
-            *translated_refmut(new_token, p as *mut u8) = 0;
-        }
-        // make the user_sp aligned to 8B for k210 platform
-        user_sp -= user_sp % core::mem::size_of::<usize>();
+        task_inner.signal_backup = None;
+        let user_sp = task_inner.res.as_mut().unwrap().ustack_top();
+        let (user_sp, argv_base, envp_base) = push_strings(new_token, user_sp, &args, &envs);
         // initialize trap_cx
         let mut trap_cx = TrapContext::app_init_context(
             entry_point,
             user_sp,
-            KERNEL_SPACE.exclusive_access().token(),
+            KERNEL_SPACE.shared_access().token(),
             task.kstack.get_top(),
             trap_handler as usize,
         );
         trap_cx.x[10] = args.len();
         trap_cx.x[11] = argv_base;
+        trap_cx.x[12] = envp_base;
         *task_inner.get_trap_cx() = trap_cx;
+        Ok(())
     }
 
     /// Only support processes with a single thread.
-    pub fn fork(self: &Arc<Self>) -> Arc<Self> {
+    pub fn fork(self: &Arc<Self>) -> Result<Arc<Self>, OutOfMemory> {
         let mut parent = self.inner_exclusive_access();
         assert_eq!(parent.thread_count(), 1);
         // clone parent's memory_set completely including trampoline/ustacks/trap_cxs
-        let memory_set = MemorySet::from_existed_user(&parent.memory_set);
+        let memory_set = MemorySet::from_existed_user(&parent.memory_set)?;
         // alloc a pid
         let pid = pid_alloc();
         // copy fd table
@@ -204,25 +305,41 @@ impl ProcessControlBlock {
         // create child process pcb
         let child = Arc::new(Self {
             pid,
-            inner: unsafe {
-                UPIntrFreeCell::new(ProcessControlBlockInner {
-                    is_zombie: false,
-                    memory_set,
-                    parent: Some(Arc::downgrade(self)),
-                    children: Vec::new(),
-                    exit_code: 0,
-                    fd_table: new_fd_table,
-                    signals: SignalFlags::empty(),
-                    tasks: Vec::new(),
-                    task_res_allocator: RecycleAllocator::new(),
-                    mutex_list: Vec::new(),
-                    semaphore_list: Vec::new(),
-                    condvar_list: Vec::new(),
-                })
-            },
+            child_exited: WaitQueue::new(),
+            continued: WaitQueue::new(),
+            traced: AtomicBool::new(self.traced.load(Ordering::Relaxed)),
+            inner: SpinMutexIrqSave::new(ProcessControlBlockInner {
+                is_zombie: false,
+                exiting: false,
+                memory_set,
+                parent: Some(Arc::downgrade(self)),
+                children: Vec::new(),
+                exit_status: 0,
+                times: CpuTimes::default(),
+                perf: PerfCounts::default(),
+                children_times: CpuTimes::default(),
+                max_rss: 0,
+                children_max_rss: 0,
+                fd_table: new_fd_table,
+                cwd: parent.cwd.clone(),
+                rlimits: parent.rlimits,
+                pgid: parent.pgid,
+                sid: parent.sid,
+                signals: SignalFlags::empty(),
+                signal_mask: parent.signal_mask,
+                signal_actions: parent.signal_actions,
+                stopped: false,
+                stop_signal: None,
+                ptrace: None,
+                tasks: Vec::new(),
+                task_res_allocator: RecycleAllocator::new(),
+                deadlock_detect: false,
+                mutex_detector: DeadlockDetector::new(),
+                semaphore_detector: DeadlockDetector::new(),
+                handles: HandleTable::new(),
+                itimer_real: None,
+            }),
         });
-        // add child
-        parent.children.push(Arc::clone(&child));
         // create main thread of child process
         let task = Arc::new(TaskControlBlock::new(
             Arc::clone(&child),
@@ -236,23 +353,250 @@ impl ProcessControlBlock {
             // here we do not allocate trap_cx or ustack again
             // but mention that we allocate a new kstack here
             false,
-        ));
+        )?);
+        // add child
+        parent.children.push(Arc::clone(&child));
         // attach task to child process
         let mut child_inner = child.inner_exclusive_access();
         child_inner.tasks.push(Some(Arc::clone(&task)));
         drop(child_inner);
         // modify kstack_top in trap_cx of this thread
-        let task_inner = task.inner_exclusive_access();
+        let mut task_inner = task.inner_exclusive_access();
+        let parent_task = parent.get_task(0);
+        let parent_task_inner = parent_task.inner_exclusive_access();
+        task_inner.priority = parent_task_inner.priority;
+        task_inner.affinity = parent_task_inner.affinity;
+        drop(parent_task_inner);
         let trap_cx = task_inner.get_trap_cx();
         trap_cx.kernel_sp = task.kstack.get_top();
         drop(task_inner);
         insert_into_pid2process(child.getpid(), Arc::clone(&child));
         // add this thread to scheduler
         add_task(task);
+        Ok(child)
+    }
+
+    /// Start `app` in a new child process without copying this address
+    /// space, leaving the child as fork followed by exec would.
+    pub fn spawn(
+        self: &Arc<Self>,
+        app: &OSInode,
+        args: Vec<String>,
+        envs: Vec<String>,
+    ) -> Result<Arc<Self>, OutOfMemory> {
+        let (memory_set, ustack_base, entry_point) = load_app(app)?;
+        let token = memory_set.token();
+        let parent = self.inner_exclusive_access();
+        let mut signal_actions = parent.signal_actions;
+        reset_handlers(&mut signal_actions);
+        let child = Arc::new(Self {
+            pid: pid_alloc(),
+            child_exited: WaitQueue::new(),
+            continued: WaitQueue::new(),
+            traced: AtomicBool::new(self.traced.load(Ordering::Relaxed)),
+            inner: SpinMutexIrqSave::new(ProcessControlBlockInner {
+                is_zombie: false,
+                exiting: false,
+                memory_set,
+                parent: Some(Arc::downgrade(self)),
+                children: Vec::new(),
+                exit_status: 0,
+                times: CpuTimes::default(),
+                perf: PerfCounts::default(),
+                children_times: CpuTimes::default(),
+                max_rss: 0,
+                children_max_rss: 0,
+                fd_table: parent.fd_table.clone(),
+                cwd: parent.cwd.clone(),
+                rlimits: parent.rlimits,
+                pgid: parent.pgid,
+                sid: parent.sid,
+                signals: SignalFlags::empty(),
+                signal_mask: parent.signal_mask,
+                signal_actions,
+                stopped: false,
+                stop_signal: None,
+                ptrace: None,
+                tasks: Vec::new(),
+                task_res_allocator: RecycleAllocator::new(),
+                deadlock_detect: false,
+                mutex_detector: DeadlockDetector::new(),
+                semaphore_detector: DeadlockDetector::new(),
+                handles: HandleTable::new(),
+                itimer_real: None,
+            }),
+        });
+        let parent_task = parent.get_task(0);
+        let parent_task_inner = parent_task.inner_exclusive_access();
+        let (priority, affinity) = (parent_task_inner.priority, parent_task_inner.affinity);
+        drop(parent_task_inner);
+        drop(parent);
+        // create a main thread with its ustack and trap_cx
+        let task = Arc::new(TaskControlBlock::new(
+            Arc::clone(&child),
+            ustack_base,
+            true,
+        )?);
+        let mut task_inner = task.inner_exclusive_access();
+        task_inner.priority = priority;
+        task_inner.affinity = affinity;
+        let ustack_top = task_inner.res.as_ref().unwrap().ustack_top();
+        let (user_sp, argv_base, envp_base) = push_strings(token, ustack_top, &args, &envs);
+        let mut trap_cx = TrapContext::app_init_context(
+            entry_point,
+            user_sp,
+            KERNEL_SPACE.shared_access().token(),
+            task.kstack.get_top(),
+            trap_handler as usize,
+        );
+        trap_cx.x[10] = args.len();
+        trap_cx.x[11] = argv_base;
+        trap_cx.x[12] = envp_base;
+        *task_inner.get_trap_cx() = trap_cx;
+        drop(task_inner);
         child
+            .inner_exclusive_access()
+            .tasks
+            .push(Some(Arc::clone(&task)));
+        self.inner_exclusive_access()
+            .children
+            .push(Arc::clone(&child));
+        insert_into_pid2process(child.getpid(), Arc::clone(&child));
+        add_task(task);
+        Ok(child)
+    }
+
+    /// Make `signal` pending unless it would be discarded anyway. SIGCONT
+    /// and SIGKILL also wake the process if it is stopped.
+    pub fn send_signal(&self, signal: SignalFlags) {
+        let mut inner = self.inner_exclusive_access();
+        if signal == SignalFlags::SIGCONT {
+            inner.signals -= SignalFlags::STOP;
+        } else if SignalFlags::STOP.contains(signal) {
+            inner.signals -= SignalFlags::SIGCONT;
+        }
+        if (signal == SignalFlags::SIGCONT || signal == SignalFlags::SIGKILL) && inner.stopped {
+            inner.stopped = false;
+            inner.stop_signal = None;
+            self.continued.wake_all();
+        }
+        if !signal.ignored_by(&inner.signal_actions[signal.signum()]) {
+            inner.signals |= signal;
+            // cut interruptible waits short, with inner held so the waiters
+            // either see the signal or get woken
+            if !inner.signal_mask.contains(signal) {
+                for task in inner.tasks.iter().flatten() {
+                    interrupt_wait(task);
+                }
+            }
+        }
     }
 
     pub fn getpid(&self) -> usize {
         self.pid.0
     }
+
+    /// Fault in the page containing `va` if it is demand-paged or
+    /// swapped out, or grow a stack down to it. Returns false if there is
+    /// nothing mapped there.
+    pub fn handle_page_fault(&self, va: usize) -> Result<bool, OutOfMemory> {
+        if va >= USER_SPACE_END {
+            return Ok(false);
+        }
+        let vpn = VirtAddr::from(va).floor();
+        let source = match self.inner_exclusive_access().memory_set.fault_source(vpn) {
+            Some(source) => source,
+            None => return Ok(self.grow_stack(vpn)),
+        };
+        let frame = frame_alloc().ok_or(OutOfMemory)?;
+        // the read may block on the disk, so do it without holding inner
+        source.fill(vpn, frame.ppn);
+        self.inner_exclusive_access()
+            .memory_set
+            .map_faulted_page(vpn, frame, &source)
+    }
+
+    /// The thread whose stack `va` lies below, in the room the stack
+    /// could not grow into or the guard page beneath.
+    pub fn overflowed_stack(&self, va: usize) -> Option<usize> {
+        let inner = self.inner_exclusive_access();
+        let tid = inner.tasks.iter().flatten().find_map(|task| {
+            let task_inner = task.inner_exclusive_access();
+            let res = task_inner.res.as_ref()?;
+            res.below_ustack(va).then_some(res.tid)
+        });
+        tid
+    }
+
+    /// Extend the stack of the thread whose room holds `vpn` down to it,
+    /// if that keeps the stack within RLIMIT_STACK.
+    fn grow_stack(&self, vpn: VirtPageNum) -> bool {
+        let mut inner = self.inner_exclusive_access();
+        let limit = inner.rlimits[RLIMIT_STACK].cur;
+        let va: usize = VirtAddr::from(vpn).into();
+        let top = inner.tasks.iter().flatten().find_map(|task| {
+            let top = task.inner_exclusive_access().res.as_ref()?.ustack_top();
+            (va < top && top - va <= limit.min(USER_STACK_MAX)).then_some(top)
+        });
+        match top {
+            Some(top) => inner
+                .memory_set
+                .extend_area_down(VirtAddr::from(top).floor(), vpn),
+            None => false,
+        }
+    }
+}
+
+/// Build the address space of `app`, leaving its code to be faulted in
+/// from the file when demand paging is enabled.
+fn load_app(app: &OSInode) -> Result<(MemorySet, usize, usize), OutOfMemory> {
+    if ELF_DEMAND_PAGING {
+        MemorySet::from_elf_inode(&app.inode())
+    } else {
+        MemorySet::from_elf(app.read_all().as_slice())
+    }
+}
+
+/// Handlers are gone with the old image, ignored signals stay ignored.
+fn reset_handlers(signal_actions: &mut SignalActions) {
+    for action in signal_actions.iter_mut() {
+        if action.handler != SIG_IGN {
+            action.handler = SIG_DFL;
+        }
+    }
+}
+
+/// Copy `args` and `envs` below `user_sp` in the address space of `token`,
+/// then the NULL terminated pointer arrays to them. Returns the new stack
+/// pointer, 16 byte aligned as the ABI wants, and where argv and envp are.
+fn push_strings(
+    token: usize,
+    mut user_sp: usize,
+    args: &[String],
+    envs: &[String],
+) -> (usize, usize, usize) {
+    // the initial stack is mapped, so these only fail on arguments too
+    // large for it
+    let mut push_str = |string: &String| {
+        user_sp -= string.len() + 1;
+        let mut bytes = string.clone().into_bytes();
+        bytes.push(0);
+        copy_bytes_to_user(token, user_sp as *mut u8, &bytes)
+            .expect("arguments overflow the user stack");
+        user_sp
+    };
+    let env_ptrs: Vec<usize> = envs.iter().map(&mut push_str).collect();
+    let arg_ptrs: Vec<usize> = args.iter().map(&mut push_str).collect();
+    let ptr_size = core::mem::size_of::<usize>();
+    user_sp -= (arg_ptrs.len() + 1 + env_ptrs.len() + 1) * ptr_size;
+    user_sp -= user_sp % 16;
+    let argv_base = user_sp;
+    let envp_base = argv_base + (arg_ptrs.len() + 1) * ptr_size;
+    for (base, ptrs) in [(argv_base, &arg_ptrs), (envp_base, &env_ptrs)] {
+        for (i, ptr) in ptrs.iter().chain(core::iter::once(&0)).enumerate() {
+            copy_to_user(token, (base + i * ptr_size) as *mut usize, *ptr)
+                .expect("arguments overflow the user stack");
+        }
+    }
+    (user_sp, argv_base, envp_base)
 }
@@ -1,10 +1,16 @@
 use super::__switch;
-use super::{fetch_task, TaskStatus};
+use super::accounting::{account_hart_online, account_idle, account_switch_in, account_switch_out};
+use super::watchdog::watchdog_touch;
+use super::{fetch_task, has_ready_task, TaskStatus};
 use super::{ProcessControlBlock, TaskContext, TaskControlBlock};
-use crate::sync::UPIntrFreeCell;
+use crate::config::{BOOT_STACK_SIZE, KERNEL_STACK_SIZE, MAX_HARTS};
+use crate::ipi::{handle_tlb_shootdown, idle_wait};
+use crate::sync::{rcu_quiescent, SpinMutexIrqSave};
+use crate::timer::{get_time, set_next_trigger};
 use crate::trap::TrapContext;
 use alloc::sync::Arc;
 use core::arch::asm;
+use core::sync::atomic::Ordering;
 use lazy_static::*;
 
 pub struct Processor {
@@ -31,38 +37,79 @@ impl Processor {
 }
 
 lazy_static! {
-    pub static ref PROCESSOR: UPIntrFreeCell<Processor> =
-        unsafe { UPIntrFreeCell::new(Processor::new()) };
+    static ref PROCESSORS: [SpinMutexIrqSave<Processor>; MAX_HARTS] =
+        core::array::from_fn(|_| SpinMutexIrqSave::new(Processor::new()));
+}
+
+/// The kernel keeps the id of the hart it runs on in `tp`.
+pub fn hart_id() -> usize {
+    let hart_id;
+    unsafe {
+        asm!("mv {}, tp", out(reg) hart_id);
+    }
+    hart_id
+}
+
+fn processor() -> &'static SpinMutexIrqSave<Processor> {
+    &PROCESSORS[hart_id()]
 }
 
 pub fn run_tasks() {
+    account_hart_online();
     loop {
-        let mut processor = PROCESSOR.exclusive_access();
-        if let Some(task) = fetch_task() {
+        watchdog_touch();
+        rcu_quiescent();
+        let mut processor = processor().exclusive_access();
+        if let Some(task) = statistic_time!("fetch_task", fetch_task()) {
+            // the hart that ran it last may not have switched away yet
+            while task
+                .on_cpu
+                .compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed)
+                .is_err()
+            {
+                handle_tlb_shootdown();
+                core::hint::spin_loop();
+            }
             let idle_task_cx_ptr = processor.get_idle_task_cx_ptr();
             // access coming task TCB exclusively
             let next_task_cx_ptr = task.inner.exclusive_session(|task_inner| {
                 task_inner.task_status = TaskStatus::Running;
+                account_switch_in(task_inner);
                 &task_inner.task_cx as *const TaskContext
             });
-            processor.current = Some(task);
+            processor.current = Some(Arc::clone(&task));
             // release processor manually
             drop(processor);
+            // the timer goes off when its time slice runs out
+            set_next_trigger();
             unsafe {
                 __switch(idle_task_cx_ptr, next_task_cx_ptr);
             }
+            account_switch_out(&mut task.inner_exclusive_access());
+            // its context is saved now, and an exited task only frees its
+            // kernel stack when `task` is dropped below
+            task.on_cpu.store(false, Ordering::Release);
         } else {
-            println!("no tasks available in run_tasks");
+            drop(processor);
+            set_next_trigger();
+            let start = get_time();
+            idle_wait(has_ready_task);
+            account_idle(get_time() - start);
         }
     }
 }
 
 pub fn take_current_task() -> Option<Arc<TaskControlBlock>> {
-    PROCESSOR.exclusive_access().take_current()
+    processor().exclusive_access().take_current()
 }
 
 pub fn current_task() -> Option<Arc<TaskControlBlock>> {
-    PROCESSOR.exclusive_access().current()
+    processor().exclusive_access().current()
+}
+
+/// The task this hart runs, `None` if the processor is locked.
+pub fn try_current_task() -> Option<Option<Arc<TaskControlBlock>>> {
+    Some(processor().try_exclusive_access()?.current())
 }
 
 pub fn current_process() -> Arc<ProcessControlBlock> {
@@ -91,20 +138,23 @@ pub fn current_trap_cx_user_va() -> usize {
         .trap_cx_user_va()
 }
 
-pub fn current_kstack_top() -> usize {
+/// The bottom and top of the stack this hart runs on: the kernel stack of
+/// the current task, or the boot stack of the hart without one.
+pub fn current_kstack() -> (usize, usize) {
     if let Some(task) = current_task() {
-        task.kstack.get_top()
+        let top = task.kstack.get_top();
+        (top - KERNEL_STACK_SIZE, top)
     } else {
-        let mut boot_stack_top;
+        let mut boot_stack_top: usize;
         unsafe { asm!("la {},boot_stack_top",out(reg) boot_stack_top) };
-        boot_stack_top
+        let top = boot_stack_top - hart_id() * BOOT_STACK_SIZE;
+        (top - BOOT_STACK_SIZE, top)
     }
-    // current_task().unwrap().kstack.get_top()
 }
 
 pub fn schedule(switched_task_cx_ptr: *mut TaskContext) {
     let idle_task_cx_ptr =
-        PROCESSOR.exclusive_session(|processor| processor.get_idle_task_cx_ptr());
+        processor().exclusive_session(|processor| processor.get_idle_task_cx_ptr());
     unsafe {
         __switch(switched_task_cx_ptr, idle_task_cx_ptr);
     }
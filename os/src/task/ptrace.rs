@@ -0,0 +1,224 @@
+//! Tracing of a child by its parent, enough for a debugger in user space.
+//! A traced process stops with SIGTRAP when it hits an ebreak, whether its
+//! own or one planted to single-step it, and the parent looks at and
+//! changes it while it is stopped.
+
+use super::{current_add_signal, current_process, ProcessControlBlock, SignalFlags};
+use crate::config::USER_SPACE_END;
+use crate::ipi::online_harts;
+use crate::sbi::{remote_fence_i, HartMask};
+use crate::trap::TrapContext;
+use alloc::vec;
+use alloc::vec::Vec;
+
+/// The compressed ebreak, short enough to plant over any instruction.
+pub const C_EBREAK: u16 = 0x9002;
+
+#[derive(Default)]
+pub struct PtraceState {
+    /// c.ebreak planted for a single step, with the halfword each replaced
+    pub steps: Vec<(usize, u16)>,
+}
+
+/// Copy between `buf` and the memory of `process` at `va`, into it if
+/// `write`, faulting the pages in as needed. What is written may be code,
+/// such as a breakpoint, so the harts `process` may run on fetch it anew.
+pub fn ptrace_access(
+    process: &ProcessControlBlock,
+    va: usize,
+    buf: &mut [u8],
+    write: bool,
+) -> bool {
+    if va
+        .checked_add(buf.len())
+        .map_or(true, |end| end > USER_SPACE_END)
+    {
+        return false;
+    }
+    loop {
+        let copied = process
+            .inner_exclusive_access()
+            .memory_set
+            .debug_copy(va, buf, write);
+        let missing = match copied {
+            Ok(()) => {
+                if write {
+                    fence_i_harts_of(process);
+                }
+                return true;
+            }
+            Err(missing) => missing,
+        };
+        if !matches!(process.handle_page_fault(missing), Ok(true)) {
+            return false;
+        }
+    }
+}
+
+/// `fence.i` on every hart a thread of `process` may run on.
+fn fence_i_harts_of(process: &ProcessControlBlock) {
+    let tasks: Vec<_> = process
+        .inner_exclusive_access()
+        .tasks
+        .iter()
+        .flatten()
+        .cloned()
+        .collect();
+    let harts = tasks.iter().fold(0, |harts, task| {
+        harts | task.inner_exclusive_access().affinity
+    }) & online_harts();
+    if harts != 0 {
+        let _ = remote_fence_i(HartMask::from_mask(harts));
+    }
+}
+
+/// The trap context of the main thread of `process`, where it stopped.
+pub fn ptrace_regs(process: &ProcessControlBlock) -> Option<&'static mut TrapContext> {
+    let task = process.inner_exclusive_access().tasks.first()?.clone()?;
+    let trap_cx = task.inner_exclusive_access().get_trap_cx();
+    Some(trap_cx)
+}
+
+fn bits(inst: u32, hi: u32, lo: u32) -> usize {
+    ((inst >> lo) & ((1 << (hi - lo + 1)) - 1)) as usize
+}
+
+/// `value` of `width` bits sign-extended.
+fn sign_extend(value: usize, width: u32) -> usize {
+    (((value << (usize::BITS - width)) as isize) >> (usize::BITS - width)) as usize
+}
+
+/// Where the instruction `inst` at `pc` may go next, given the registers
+/// `x`.
+pub fn next_pcs(inst: u32, pc: usize, x: &[usize; 32]) -> Vec<usize> {
+    if inst & 0b11 != 0b11 {
+        let (op, funct3) = (inst & 0b11, bits(inst, 15, 13));
+        let rs1 = bits(inst, 11, 7);
+        return match (op, funct3) {
+            // c.j
+            (0b01, 0b101) => {
+                let offset = bits(inst, 12, 12) << 11
+                    | bits(inst, 11, 11) << 4
+                    | bits(inst, 10, 9) << 8
+                    | bits(inst, 8, 8) << 10
+                    | bits(inst, 7, 7) << 6
+                    | bits(inst, 6, 6) << 7
+                    | bits(inst, 5, 3) << 1
+                    | bits(inst, 2, 2) << 5;
+                vec![pc.wrapping_add(sign_extend(offset, 12))]
+            }
+            // c.beqz, c.bnez
+            (0b01, 0b110) | (0b01, 0b111) => {
+                let offset = bits(inst, 12, 12) << 8
+                    | bits(inst, 11, 10) << 3
+                    | bits(inst, 6, 5) << 6
+                    | bits(inst, 4, 3) << 1
+                    | bits(inst, 2, 2) << 5;
+                vec![pc.wrapping_add(sign_extend(offset, 9)), pc + 2]
+            }
+            // c.jr, c.jalr
+            (0b10, 0b100) if bits(inst, 6, 2) == 0 && rs1 != 0 => vec![x[rs1] & !1],
+            _ => vec![pc + 2],
+        };
+    }
+    let rs1 = bits(inst, 19, 15);
+    match inst & 0x7f {
+        // jal
+        0x6f => {
+            let offset = bits(inst, 31, 31) << 20
+                | bits(inst, 30, 21) << 1
+                | bits(inst, 20, 20) << 11
+                | bits(inst, 19, 12) << 12;
+            vec![pc.wrapping_add(sign_extend(offset, 21))]
+        }
+        // jalr
+        0x67 => vec![x[rs1].wrapping_add(sign_extend(bits(inst, 31, 20), 12)) & !1],
+        // branches
+        0x63 => {
+            let offset = bits(inst, 31, 31) << 12
+                | bits(inst, 30, 25) << 5
+                | bits(inst, 11, 8) << 1
+                | bits(inst, 7, 7) << 11;
+            vec![pc.wrapping_add(sign_extend(offset, 13)), pc + 4]
+        }
+        _ => vec![pc + 4],
+    }
+}
+
+/// Plant a c.ebreak at each instruction the stopped main thread of
+/// `process` may run next, so that it stops again after one step.
+pub fn ptrace_plant_steps(process: &ProcessControlBlock) -> bool {
+    let trap_cx = match ptrace_regs(process) {
+        Some(trap_cx) => trap_cx,
+        None => return false,
+    };
+    let pc = trap_cx.sepc;
+    let mut half = [0u8; 2];
+    if !ptrace_access(process, pc, &mut half, false) {
+        return false;
+    }
+    let mut inst = u16::from_le_bytes(half) as u32;
+    if inst & 0b11 == 0b11 {
+        if !ptrace_access(process, pc + 2, &mut half, false) {
+            return false;
+        }
+        inst |= (u16::from_le_bytes(half) as u32) << 16;
+    }
+    let mut x = trap_cx.x;
+    x[0] = 0;
+    let mut targets = next_pcs(inst, pc, &x);
+    targets.dedup();
+    let mut steps = Vec::new();
+    for target in targets {
+        // a jump to nowhere faults instead of stopping
+        let mut orig = [0u8; 2];
+        if ptrace_access(process, target, &mut orig, false)
+            && ptrace_access(process, target, &mut C_EBREAK.to_le_bytes(), true)
+        {
+            steps.push((target, u16::from_le_bytes(orig)));
+        }
+    }
+    if let Some(state) = process.inner_exclusive_access().ptrace.as_mut() {
+        state.steps = steps;
+    }
+    true
+}
+
+/// Put back what the breakpoints of a single step of `process` replaced.
+pub fn ptrace_clear_steps(process: &ProcessControlBlock) {
+    let steps = match process.inner_exclusive_access().ptrace.as_mut() {
+        Some(state) => core::mem::take(&mut state.steps),
+        None => return,
+    };
+    for (addr, orig) in steps.into_iter().rev() {
+        ptrace_access(process, addr, &mut orig.to_le_bytes(), true);
+    }
+}
+
+/// Let the stopped `process` run on.
+pub fn ptrace_resume(process: &ProcessControlBlock) {
+    let mut inner = process.inner_exclusive_access();
+    inner.stopped = false;
+    inner.stop_signal = None;
+    process.continued.wake_all();
+}
+
+/// Stop tracing `process`, running on if it is stopped.
+pub fn ptrace_detach(process: &ProcessControlBlock) {
+    ptrace_clear_steps(process);
+    let stopped = {
+        let mut inner = process.inner_exclusive_access();
+        inner.ptrace = None;
+        inner.stopped
+    };
+    if stopped {
+        ptrace_resume(process);
+    }
+}
+
+/// The current thread ran into an ebreak. A single step ends there, and
+/// SIGTRAP stops the process if it is traced.
+pub fn breakpoint_trap() {
+    ptrace_clear_steps(&current_process());
+    current_add_signal(SignalFlags::SIGTRAP);
+}
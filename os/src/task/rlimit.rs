@@ -0,0 +1,39 @@
+//! Resource limits of a process, numbered as on Linux. Only the stack and
+//! the number of open files are enforced.
+
+use crate::config::USER_STACK_MAX;
+
+/// A soft limit, which is enforced, below a hard one, which caps it.
+#[derive(Clone, Copy)]
+#[repr(C)]
+pub struct RLimit {
+    pub cur: usize,
+    pub max: usize,
+}
+
+pub const RLIM_INFINITY: usize = usize::MAX;
+
+/// Bytes a user stack may grow to, the room for it is `USER_STACK_MAX`.
+pub const RLIMIT_STACK: usize = 3;
+/// Highest file descriptor a process may open, plus one.
+pub const RLIMIT_NOFILE: usize = 7;
+pub const RLIM_NLIMITS: usize = 16;
+
+pub type RLimits = [RLimit; RLIM_NLIMITS];
+
+/// The limits of the initial process, inherited from there on.
+pub fn default_rlimits() -> RLimits {
+    let mut rlimits = [RLimit {
+        cur: RLIM_INFINITY,
+        max: RLIM_INFINITY,
+    }; RLIM_NLIMITS];
+    rlimits[RLIMIT_STACK] = RLimit {
+        cur: USER_STACK_MAX,
+        max: USER_STACK_MAX,
+    };
+    rlimits[RLIMIT_NOFILE] = RLimit {
+        cur: 1024,
+        max: 4096,
+    };
+    rlimits
+}
@@ -1,29 +1,228 @@
+use super::{current_process, current_task, schedule};
 use bitflags::*;
 
 bitflags! {
     pub struct SignalFlags: u32 {
+        const SIGHUP    = 1 << 1;
         const SIGINT    = 1 << 2;
+        const SIGQUIT   = 1 << 3;
         const SIGILL    = 1 << 4;
+        const SIGTRAP   = 1 << 5;
         const SIGABRT   = 1 << 6;
+        const SIGBUS    = 1 << 7;
         const SIGFPE    = 1 << 8;
+        const SIGKILL   = 1 << 9;
+        const SIGUSR1   = 1 << 10;
         const SIGSEGV   = 1 << 11;
+        const SIGUSR2   = 1 << 12;
+        const SIGPIPE   = 1 << 13;
+        const SIGALRM   = 1 << 14;
+        const SIGTERM   = 1 << 15;
+        const SIGSTKFLT = 1 << 16;
+        const SIGCHLD   = 1 << 17;
+        const SIGCONT   = 1 << 18;
+        const SIGSTOP   = 1 << 19;
+        const SIGTSTP   = 1 << 20;
+        const SIGTTIN   = 1 << 21;
+        const SIGTTOU   = 1 << 22;
+        const SIGURG    = 1 << 23;
+        const SIGXCPU   = 1 << 24;
+        const SIGXFSZ   = 1 << 25;
+        const SIGVTALRM = 1 << 26;
+        const SIGPROF   = 1 << 27;
+        const SIGWINCH  = 1 << 28;
+        const SIGIO     = 1 << 29;
+        const SIGPWR    = 1 << 30;
+        const SIGSYS    = 1 << 31;
     }
 }
 
 impl SignalFlags {
-    pub fn check_error(&self) -> Option<(i32, &'static str)> {
-        if self.contains(Self::SIGINT) {
-            Some((-2, "Killed, SIGINT=2"))
-        } else if self.contains(Self::SIGILL) {
-            Some((-4, "Illegal Instruction, SIGILL=4"))
-        } else if self.contains(Self::SIGABRT) {
-            Some((-6, "Aborted, SIGABRT=6"))
-        } else if self.contains(Self::SIGFPE) {
-            Some((-8, "Erroneous Arithmetic Operation, SIGFPE=8"))
-        } else if self.contains(Self::SIGSEGV) {
-            Some((-11, "Segmentation Fault, SIGSEGV=11"))
+    /// Signals that can be neither caught, ignored nor masked.
+    pub const UNBLOCKABLE: Self =
+        Self::from_bits_truncate(Self::SIGKILL.bits() | Self::SIGSTOP.bits());
+    /// Signals whose default action is to do nothing.
+    const DEFAULT_IGNORED: Self = Self::from_bits_truncate(
+        Self::SIGCHLD.bits() | Self::SIGCONT.bits() | Self::SIGURG.bits() | Self::SIGWINCH.bits(),
+    );
+    /// Signals whose default action is to stop the process until SIGCONT.
+    pub const STOP: Self = Self::from_bits_truncate(
+        Self::SIGSTOP.bits() | Self::SIGTSTP.bits() | Self::SIGTTIN.bits() | Self::SIGTTOU.bits(),
+    );
+    /// Signals raised by a faulting instruction, retrying it without
+    /// running a handler would just fault again.
+    const SYNCHRONOUS: Self = Self::from_bits_truncate(
+        Self::SIGILL.bits()
+            | Self::SIGTRAP.bits()
+            | Self::SIGBUS.bits()
+            | Self::SIGFPE.bits()
+            | Self::SIGSEGV.bits(),
+    );
+
+    /// Whether this single signal would be discarded under `action`.
+    pub fn ignored_by(&self, action: &SignalAction) -> bool {
+        !Self::UNBLOCKABLE.contains(*self)
+            && (action.handler == SIG_IGN
+                || (action.handler == SIG_DFL && Self::DEFAULT_IGNORED.contains(*self)))
+    }
+
+    /// The signal number of a single signal flag.
+    pub fn signum(&self) -> usize {
+        self.bits().trailing_zeros() as usize
+    }
+
+    /// Message of the default action of this single signal, which
+    /// terminates the process, `None` if it is ignored by default.
+    pub fn default_action(&self) -> Option<&'static str> {
+        let msg = if *self == Self::SIGINT {
+            "Killed, SIGINT=2"
+        } else if *self == Self::SIGILL {
+            "Illegal Instruction, SIGILL=4"
+        } else if *self == Self::SIGTRAP {
+            "Trace/Breakpoint Trap, SIGTRAP=5"
+        } else if *self == Self::SIGABRT {
+            "Aborted, SIGABRT=6"
+        } else if *self == Self::SIGFPE {
+            "Erroneous Arithmetic Operation, SIGFPE=8"
+        } else if *self == Self::SIGKILL {
+            "Killed, SIGKILL=9"
+        } else if *self == Self::SIGSEGV {
+            "Segmentation Fault, SIGSEGV=11"
+        } else if *self == Self::SIGALRM {
+            "Alarm Clock, SIGALRM=14"
+        } else if Self::DEFAULT_IGNORED.contains(*self) {
+            return None;
         } else {
-            None
+            "Terminated by signal"
+        };
+        Some(msg)
+    }
+}
+
+/// Handler address meaning the default action.
+pub const SIG_DFL: usize = 0;
+/// Handler address meaning the signal is discarded.
+pub const SIG_IGN: usize = 1;
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct SignalAction {
+    pub handler: usize,
+    /// signals blocked in addition while the handler runs
+    pub mask: SignalFlags,
+}
+
+impl Default for SignalAction {
+    fn default() -> Self {
+        Self {
+            handler: SIG_DFL,
+            mask: SignalFlags::empty(),
+        }
+    }
+}
+
+/// One action per signal number.
+pub type SignalActions = [SignalAction; 32];
+
+/// Whether the current thread has a signal to act on, sent to it or to
+/// its process.
+pub fn current_signal_pending() -> bool {
+    let task = current_task().unwrap();
+    let process = current_process();
+    let inner = process.inner_exclusive_access();
+    let signals = inner.signals | task.inner_exclusive_access().signals;
+    !(signals - inner.signal_mask).is_empty()
+}
+
+/// Act on the pending, unmasked signals of the current thread and its
+/// process before it returns to user mode. Returns the signal and message
+/// if one of them terminates the process, otherwise at most one handler
+/// is set up to run on the current thread. A stop signal only marks the process stopped,
+/// see `wait_while_stopped`.
+pub fn handle_signals() -> Option<(SignalFlags, &'static str)> {
+    let task = current_task().unwrap();
+    let process = current_process();
+    let mut process_inner = process.inner_exclusive_access();
+    let mut task_inner = task.inner_exclusive_access();
+    // handlers do not nest, other signals wait until sigreturn
+    let in_handler = task_inner.signal_backup.is_some();
+    let mask = process_inner.signal_mask;
+    let pending = (process_inner.signals | task_inner.signals) & (!mask | SignalFlags::SYNCHRONOUS);
+    for signum in 1..32 {
+        let signal = SignalFlags::from_bits_truncate(1 << signum);
+        if !pending.contains(signal) {
+            continue;
+        }
+        let action = process_inner.signal_actions[signum];
+        let mut handler = action.handler;
+        if SignalFlags::UNBLOCKABLE.contains(signal) {
+            handler = SIG_DFL;
+        } else if SignalFlags::SYNCHRONOUS.contains(signal) {
+            if handler == SIG_IGN || mask.contains(signal) || in_handler {
+                handler = SIG_DFL;
+            }
+        } else if handler != SIG_DFL && handler != SIG_IGN && in_handler {
+            continue;
+        }
+        if task_inner.signals.contains(signal) {
+            task_inner.signals.remove(signal);
+        } else {
+            process_inner.signals.remove(signal);
+        }
+        // the tracer gets to look at a traced process that hit a breakpoint
+        let trapped = signal == SignalFlags::SIGTRAP && process_inner.ptrace.is_some();
+        if trapped {
+            handler = SIG_DFL;
+        }
+        match handler {
+            SIG_DFL if SignalFlags::STOP.contains(signal) || trapped => {
+                process_inner.stopped = true;
+                process_inner.stop_signal = Some(signal);
+            }
+            SIG_DFL => {
+                if let Some(msg) = signal.default_action() {
+                    return Some((signal, msg));
+                }
+            }
+            SIG_IGN => {}
+            _ => {
+                // sys_sigreturn restores the interrupted context and mask
+                let trap_cx = task_inner.get_trap_cx();
+                task_inner.signal_backup = Some((*trap_cx, mask));
+                process_inner.signal_mask |= (signal | action.mask) - SignalFlags::UNBLOCKABLE;
+                trap_cx.sepc = handler;
+                trap_cx.x[10] = signal.bits() as usize;
+                return None;
+            }
+        }
+    }
+    None
+}
+
+/// Block the current thread while its process is stopped, after telling
+/// the parent. Returns whether it was stopped, so signals that came in the
+/// meantime can be handled.
+pub fn wait_while_stopped() -> bool {
+    let process = current_process();
+    let parent = {
+        let inner = process.inner_exclusive_access();
+        if !inner.stopped {
+            return false;
+        }
+        inner.parent.as_ref().and_then(|parent| parent.upgrade())
+    };
+    if let Some(parent) = parent {
+        let _parent_inner = parent.inner_exclusive_access();
+        parent.child_exited.wake_all();
+    }
+    loop {
+        let inner = process.inner_exclusive_access();
+        if !inner.stopped {
+            return true;
         }
+        // send_signal continues the process with inner held
+        let task_cx_ptr = process.continued.wait_no_sched();
+        drop(inner);
+        schedule(task_cx_ptr);
     }
 }
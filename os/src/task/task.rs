@@ -1,22 +1,30 @@
+use super::accounting::CpuTimes;
 use super::id::TaskUserRes;
-use super::{kstack_alloc, KernelStack, ProcessControlBlock, TaskContext};
+use super::manager::DeadlineParams;
+use super::{kstack_alloc, KernelStack, ProcessControlBlock, SignalFlags, TaskContext};
+use crate::config::MAX_HARTS;
+use crate::perf::PerfCounts;
 use crate::trap::TrapContext;
 use crate::{
-    mm::PhysPageNum,
-    sync::{UPIntrFreeCell, UPIntrRefMut},
+    mm::{OutOfMemory, PhysPageNum},
+    sync::{interrupt_wait, SpinMutexIrqSave, SpinMutexIrqSaveGuard, Waiters},
 };
 use alloc::sync::{Arc, Weak};
+use core::sync::atomic::AtomicBool;
 
 pub struct TaskControlBlock {
     // immutable
     pub process: Weak<ProcessControlBlock>,
     pub kstack: KernelStack,
+    /// set while some hart runs on this task's kernel stack
+    pub on_cpu: AtomicBool,
     // mutable
-    pub inner: UPIntrFreeCell<TaskControlBlockInner>,
+    pub inner: SpinMutexIrqSave<TaskControlBlockInner>,
 }
 
 impl TaskControlBlock {
-    pub fn inner_exclusive_access(&self) -> UPIntrRefMut<'_, TaskControlBlockInner> {
+    #[track_caller]
+    pub fn inner_exclusive_access(&self) -> SpinMutexIrqSaveGuard<'_, TaskControlBlockInner> {
         self.inner.exclusive_access()
     }
 
@@ -25,6 +33,31 @@ impl TaskControlBlock {
         let inner = process.inner_exclusive_access();
         inner.memory_set.token()
     }
+
+    /// Make `signal` pending for this thread alone, unless the process
+    /// would discard it anyway. Stopping, continuing and killing act on
+    /// the whole process, so those are sent to the process instead.
+    pub fn send_signal(self: &Arc<Self>, signal: SignalFlags) {
+        let process = match self.process.upgrade() {
+            Some(process) => process,
+            None => return,
+        };
+        if signal == SignalFlags::SIGCONT
+            || signal == SignalFlags::SIGKILL
+            || SignalFlags::STOP.contains(signal)
+        {
+            process.send_signal(signal);
+            return;
+        }
+        let process_inner = process.inner_exclusive_access();
+        if signal.ignored_by(&process_inner.signal_actions[signal.signum()]) {
+            return;
+        }
+        self.inner_exclusive_access().signals |= signal;
+        if !process_inner.signal_mask.contains(signal) {
+            interrupt_wait(self);
+        }
+    }
 }
 
 pub struct TaskControlBlockInner {
@@ -33,6 +66,43 @@ pub struct TaskControlBlockInner {
     pub task_cx: TaskContext,
     pub task_status: TaskStatus,
     pub exit_code: Option<i32>,
+    /// share of CPU time, within `MIN_PRIORITY..=MAX_PRIORITY`
+    pub priority: usize,
+    /// scheduler position within the queue of `hart`, wraps around
+    pub stride: usize,
+    /// harts this thread may run on, one bit each
+    pub affinity: usize,
+    /// the hart whose queue it was last taken from, queued there again
+    /// while the affinity allows it to keep its cache warm
+    pub hart: usize,
+    /// set while it is in the deadline class, which runs before the
+    /// stride scheduled threads
+    pub deadline: Option<DeadlineParams>,
+    /// context and signal mask to restore on sigreturn while a signal
+    /// handler runs on this thread
+    pub signal_backup: Option<(TrapContext, SignalFlags)>,
+    /// signals sent to this thread alone, handled by it together with
+    /// those of the process
+    pub signals: SignalFlags,
+    /// CPU time used so far, see `accounting`
+    pub times: CpuTimes,
+    /// when the time not yet charged to `times` started
+    pub time_stamp: usize,
+    /// hardware counts while it ran so far, see `perf`
+    pub perf: PerfCounts,
+    /// the counters when the counts not yet charged to `perf` started
+    pub perf_stamp: PerfCounts,
+    /// time slice left from when it last ran, a new one starts once it
+    /// is used up
+    pub time_slice: usize,
+    /// when the time slice runs out, while it runs
+    pub slice_end: usize,
+    /// context switches because it blocked or exited
+    pub nvcsw: usize,
+    /// context switches because it was preempted or yielded
+    pub nivcsw: usize,
+    /// the queue this thread waits on interruptibly, see `WaitQueue`
+    pub wait_queue: Option<Weak<Waiters>>,
 }
 
 impl TaskControlBlockInner {
@@ -51,27 +121,47 @@ impl TaskControlBlock {
         process: Arc<ProcessControlBlock>,
         ustack_base: usize,
         alloc_user_res: bool,
-    ) -> Self {
-        let res = TaskUserRes::new(Arc::clone(&process), ustack_base, alloc_user_res);
+    ) -> Result<Self, OutOfMemory> {
+        let res = TaskUserRes::new(Arc::clone(&process), ustack_base, alloc_user_res)?;
         let trap_cx_ppn = res.trap_cx_ppn();
-        let kstack = kstack_alloc();
+        let kstack = kstack_alloc()?;
         let kstack_top = kstack.get_top();
-        Self {
+        Ok(Self {
             process: Arc::downgrade(&process),
             kstack,
-            inner: unsafe {
-                UPIntrFreeCell::new(TaskControlBlockInner {
-                    res: Some(res),
-                    trap_cx_ppn,
-                    task_cx: TaskContext::goto_trap_return(kstack_top),
-                    task_status: TaskStatus::Ready,
-                    exit_code: None,
-                })
-            },
-        }
+            on_cpu: AtomicBool::new(false),
+            inner: SpinMutexIrqSave::new(TaskControlBlockInner {
+                res: Some(res),
+                trap_cx_ppn,
+                task_cx: TaskContext::goto_trap_return(kstack_top),
+                task_status: TaskStatus::Ready,
+                exit_code: None,
+                priority: DEFAULT_PRIORITY,
+                stride: 0,
+                affinity: ALL_HARTS,
+                hart: 0,
+                deadline: None,
+                signal_backup: None,
+                signals: SignalFlags::empty(),
+                times: CpuTimes::default(),
+                time_stamp: 0,
+                perf: PerfCounts::default(),
+                perf_stamp: PerfCounts::default(),
+                time_slice: 0,
+                slice_end: 0,
+                nvcsw: 0,
+                nivcsw: 0,
+                wait_queue: None,
+            }),
+        })
     }
 }
 
+pub const MIN_PRIORITY: usize = 2;
+pub const MAX_PRIORITY: usize = 64;
+pub const DEFAULT_PRIORITY: usize = 16;
+pub const ALL_HARTS: usize = (1 << MAX_HARTS) - 1;
+
 #[derive(Copy, Clone, PartialEq)]
 pub enum TaskStatus {
     Ready,
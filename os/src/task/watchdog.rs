@@ -0,0 +1,120 @@
+//! Soft lockup detection. Every pass of `run_tasks` stamps the hart, and
+//! the timer interrupt of any hart looks for one that is busy yet has not
+//! been back in the scheduler for `WATCHDOG_SECS`. A stalled hart reports
+//! where it is from its own interrupt handler, asked by a software
+//! interrupt when another hart found it; one that does not take
+//! interrupts at all is only named.
+
+use super::hart_id;
+use super::processor::try_current_task;
+use crate::config::{CLOCK_FREQ, MAX_HARTS, WATCHDOG_SECS};
+use crate::ipi::{idle_harts, online_harts, poke_hart};
+use crate::lang_items::{symbolize, trace_stack};
+use crate::timer::get_time;
+use crate::trap::TrapContext;
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use lazy_static::*;
+use log::error;
+
+lazy_static! {
+    /// When each hart last went through the scheduler, 0 before it does.
+    static ref LAST_SCHEDULE: [AtomicUsize; MAX_HARTS] =
+        core::array::from_fn(|_| AtomicUsize::new(0));
+    /// The stall of each hart was reported already.
+    static ref REPORTED: [AtomicBool; MAX_HARTS] = core::array::from_fn(|_| AtomicBool::new(false));
+    /// Another hart found this one stalled and wants it to report.
+    static ref DUMP_ASKED: [AtomicBool; MAX_HARTS] =
+        core::array::from_fn(|_| AtomicBool::new(false));
+}
+
+/// This hart is in the scheduler.
+pub fn watchdog_touch() {
+    let hart = hart_id();
+    LAST_SCHEDULE[hart].store(get_time(), Ordering::Relaxed);
+    REPORTED[hart].store(false, Ordering::Relaxed);
+    DUMP_ASKED[hart].store(false, Ordering::Relaxed);
+}
+
+/// Look for stalled harts from the timer interrupt. `trap_cx` is where
+/// this hart was interrupted if it was in the kernel, only then can it be
+/// the one stalled.
+pub fn watchdog_check(trap_cx: Option<&TrapContext>) {
+    if WATCHDOG_SECS == 0 {
+        return;
+    }
+    let now = get_time();
+    let busy = online_harts() & !idle_harts();
+    for hart in (0..MAX_HARTS).filter(|hart| busy & 1 << hart != 0) {
+        let last = LAST_SCHEDULE[hart].load(Ordering::Relaxed);
+        if last == 0 || now.saturating_sub(last) < WATCHDOG_SECS * CLOCK_FREQ {
+            continue;
+        }
+        if REPORTED[hart].swap(true, Ordering::Relaxed) {
+            continue;
+        }
+        let secs = (now - last) / CLOCK_FREQ;
+        if hart == hart_id() {
+            if let Some(trap_cx) = trap_cx {
+                report_stall(trap_cx, secs);
+            }
+        } else {
+            error!(
+                "[watchdog] hart {} has not scheduled for {}s, it reports if it takes interrupts",
+                hart, secs
+            );
+            DUMP_ASKED[hart].store(true, Ordering::Relaxed);
+            poke_hart(hart);
+        }
+    }
+}
+
+/// Report from the software interrupt taken in the kernel at `trap_cx`,
+/// if another hart asked.
+pub fn watchdog_answer(trap_cx: &TrapContext) {
+    if DUMP_ASKED[hart_id()].swap(false, Ordering::Relaxed) {
+        let last = LAST_SCHEDULE[hart_id()].load(Ordering::Relaxed);
+        report_stall(trap_cx, (get_time() - last) / CLOCK_FREQ);
+    }
+}
+
+/// Print the task and the kernel context of this stalled hart, taking no
+/// lock that may be what it waits for.
+fn report_stall(trap_cx: &TrapContext, secs: usize) {
+    let hart = hart_id();
+    match try_current_task() {
+        Some(Some(task)) => {
+            let pid = task.process.upgrade().map_or(0, |process| process.getpid());
+            match task.inner.try_exclusive_access() {
+                Some(inner) => error!(
+                    "[watchdog] hart {} stuck for {}s in pid {} tid {}",
+                    hart,
+                    secs,
+                    pid,
+                    inner.res.as_ref().map_or(0, |res| res.tid)
+                ),
+                None => error!(
+                    "[watchdog] hart {} stuck for {}s in pid {}",
+                    hart, secs, pid
+                ),
+            }
+        }
+        Some(None) => error!(
+            "[watchdog] hart {} stuck for {}s in the scheduler",
+            hart, secs
+        ),
+        None => error!("[watchdog] hart {} stuck for {}s", hart, secs),
+    }
+    match symbolize(trap_cx.sepc) {
+        Some((name, offset)) => {
+            error!("[watchdog] sepc={:#x} {}+{:#x}", trap_cx.sepc, name, offset)
+        }
+        None => error!("[watchdog] sepc={:#x}", trap_cx.sepc),
+    }
+    error!(
+        "[watchdog] ra={:#x} sp={:#x} s0={:#x}",
+        trap_cx.x[1], trap_cx.x[2], trap_cx.x[8]
+    );
+    unsafe {
+        trace_stack(trap_cx.x[8]);
+    }
+}
@@ -1,16 +1,26 @@
 use core::cmp::Ordering;
 
-use crate::config::CLOCK_FREQ;
+use crate::config::{CLOCK_FREQ, MAX_HARTS, TIME_SLICE_MS, WATCHDOG_SECS};
+use crate::drivers::rtc::RTC;
+use crate::net::TcpConnection;
 use crate::sbi::set_timer;
-use crate::sync::UPIntrFreeCell;
-use crate::task::{wakeup_task, TaskControlBlock};
+use crate::sync::{futex_timeout, FutexWaiter, SpinMutexIrqSave, WaitQueue};
+use crate::task::{
+    current_task, hart_id, next_replenish, replenish_deadline_tasks, KernelObject, ObjectKind,
+    ProcessControlBlock, SignalFlags, TaskControlBlock,
+};
 use alloc::collections::BinaryHeap;
-use alloc::sync::Arc;
+use alloc::sync::{Arc, Weak};
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicUsize, Ordering as AtomicOrdering};
 use lazy_static::*;
 use riscv::register::time;
 
 const TICKS_PER_SEC: usize = 100;
 const MSEC_PER_SEC: usize = 1000;
+const USEC_PER_SEC: usize = 1_000_000;
+const NSEC_PER_SEC: usize = 1_000_000_000;
+const NSEC_PER_MSEC: usize = 1_000_000;
 
 pub fn get_time() -> usize {
     time::read()
@@ -20,13 +30,236 @@ pub fn get_time_ms() -> usize {
     time::read() / (CLOCK_FREQ / MSEC_PER_SEC)
 }
 
+/// Nanoseconds since boot, as fine as the timer counts.
+pub fn get_time_ns() -> usize {
+    let time = time::read();
+    time / CLOCK_FREQ * NSEC_PER_SEC + time % CLOCK_FREQ * NSEC_PER_SEC / CLOCK_FREQ
+}
+
+/// `time` read from the timer in clock ticks, `TICKS_PER_SEC` a second as
+/// USER_HZ on Linux.
+pub fn time_to_ticks(time: usize) -> usize {
+    time / (CLOCK_FREQ / TICKS_PER_SEC)
+}
+
+const fn ms_to_time(ms: usize) -> usize {
+    ms * (CLOCK_FREQ / MSEC_PER_SEC)
+}
+
+/// `ns` nanoseconds in timer units, rounded down.
+pub fn ns_to_time(ns: usize) -> usize {
+    ns / NSEC_PER_SEC * CLOCK_FREQ + ns % NSEC_PER_SEC * CLOCK_FREQ / NSEC_PER_SEC
+}
+
+/// How long a task runs before it gets preempted, in timer units.
+pub const TIME_SLICE: usize = ms_to_time(TIME_SLICE_MS);
+
+lazy_static! {
+    /// When the timer of each hart goes off, `usize::MAX` if it is off.
+    static ref NEXT_TRIGGER: [AtomicUsize; MAX_HARTS] =
+        core::array::from_fn(|_| AtomicUsize::new(usize::MAX));
+}
+
+fn program_timer(deadline: usize) {
+    NEXT_TRIGGER[hart_id()].store(deadline, AtomicOrdering::Relaxed);
+    set_timer(deadline as u64).expect("SBI timer failed");
+}
+
+/// Set the timer of this hart for the next thing due: the earliest entry
+/// of the timer heap, the next budget of a throttled deadline task and,
+/// while a task runs, the end of its time slice. An idle hart with nothing
+/// due only wakes up for the watchdog, if that is on.
 pub fn set_next_trigger() {
-    set_timer(get_time() + CLOCK_FREQ / TICKS_PER_SEC);
+    let mut deadline = TIMERS
+        .exclusive_access()
+        .peek()
+        .map_or(usize::MAX, |timer| ms_to_time(timer.expire_ms))
+        .min(next_replenish());
+    if let Some(task) = current_task() {
+        let now = get_time();
+        let slice_end = task.inner_exclusive_access().slice_end;
+        // a slice that ran out where the task could not be preempted is
+        // looked at again a slice later
+        deadline = deadline.min(if slice_end > now {
+            slice_end
+        } else {
+            now + TIME_SLICE
+        });
+    }
+    if WATCHDOG_SECS != 0 {
+        // even an idle hart looks at the others now and then
+        deadline = deadline.min(get_time() + WATCHDOG_SECS * CLOCK_FREQ);
+    }
+    program_timer(deadline);
+}
+
+/// Bring the timer of this hart forward to `expire_ms` if it is set for
+/// later. Other harts pick the new timer up the next time they set theirs.
+fn trigger_by(expire_ms: usize) {
+    let deadline = ms_to_time(expire_ms);
+    if deadline < NEXT_TRIGGER[hart_id()].load(AtomicOrdering::Relaxed) {
+        program_timer(deadline);
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct TimeSpec {
+    pub sec: usize,
+    pub nsec: usize,
+}
+
+impl TimeSpec {
+    /// Rounded up, so that a non-zero interval never becomes zero.
+    pub fn to_ms(&self) -> usize {
+        self.sec * MSEC_PER_SEC + (self.nsec + 999_999) / 1_000_000
+    }
+    pub fn from_ms(ms: usize) -> Self {
+        Self {
+            sec: ms / MSEC_PER_SEC,
+            nsec: ms % MSEC_PER_SEC * NSEC_PER_MSEC,
+        }
+    }
+    pub fn from_ns(ns: usize) -> Self {
+        Self {
+            sec: ns / NSEC_PER_SEC,
+            nsec: ns % NSEC_PER_SEC,
+        }
+    }
+    /// Whether `nsec` is in range.
+    pub fn is_valid(&self) -> bool {
+        self.nsec < NSEC_PER_SEC
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct TimeVal {
+    pub sec: usize,
+    pub usec: usize,
+}
+
+impl TimeVal {
+    /// Convert a duration in timer units.
+    pub fn from_time(time: usize) -> Self {
+        Self {
+            sec: time / CLOCK_FREQ,
+            usec: time % CLOCK_FREQ * USEC_PER_SEC / CLOCK_FREQ,
+        }
+    }
+    pub fn from_ns(ns: usize) -> Self {
+        Self {
+            sec: ns / NSEC_PER_SEC,
+            usec: ns % NSEC_PER_SEC / 1000,
+        }
+    }
+    /// Rounded up, so that a non-zero interval never becomes zero.
+    pub fn to_ms(&self) -> usize {
+        self.sec * MSEC_PER_SEC + (self.usec + 999) / 1000
+    }
+    pub fn from_ms(ms: usize) -> Self {
+        Self {
+            sec: ms / MSEC_PER_SEC,
+            usec: ms % MSEC_PER_SEC * 1000,
+        }
+    }
+    /// Whether `usec` is in range.
+    pub fn is_valid(&self) -> bool {
+        self.usec < USEC_PER_SEC
+    }
+}
+
+/// The setting of an interval timer of `setitimer(2)`, to the microsecond.
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct ITimerVal {
+    pub interval: TimeVal,
+    pub value: TimeVal,
+}
+
+impl From<ITimerVal> for ITimerSpec {
+    fn from(val: ITimerVal) -> Self {
+        Self {
+            interval: TimeSpec::from_ms(val.interval.to_ms()),
+            value: TimeSpec::from_ms(val.value.to_ms()),
+        }
+    }
+}
+
+impl From<ITimerSpec> for ITimerVal {
+    fn from(spec: ITimerSpec) -> Self {
+        Self {
+            interval: TimeVal::from_ms(spec.interval.to_ms()),
+            value: TimeVal::from_ms(spec.value.to_ms()),
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct ITimerSpec {
+    pub interval: TimeSpec,
+    pub value: TimeSpec,
+}
+
+pub const CLOCK_REALTIME: usize = 0;
+pub const CLOCK_MONOTONIC: usize = 1;
+
+lazy_static! {
+    /// Wall-clock time at boot, CLOCK_REALTIME = CLOCK_MONOTONIC + this.
+    pub static ref REALTIME_OFFSET_NS: SpinMutexIrqSave<usize> = SpinMutexIrqSave::new(0);
+}
+
+/// Seed CLOCK_REALTIME from the RTC.
+pub fn init_realtime() {
+    *REALTIME_OFFSET_NS.exclusive_access() = (RTC.read_ns() as usize).saturating_sub(get_time_ns());
+}
+
+/// A clock user programs can read and arm timers on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ClockId {
+    Realtime,
+    Monotonic,
+}
+
+impl ClockId {
+    pub fn from_id(clock_id: usize) -> Option<Self> {
+        match clock_id {
+            CLOCK_REALTIME => Some(Self::Realtime),
+            CLOCK_MONOTONIC => Some(Self::Monotonic),
+            _ => None,
+        }
+    }
+    pub fn now_ns(self) -> usize {
+        match self {
+            Self::Realtime => get_time_ns() + *REALTIME_OFFSET_NS.exclusive_access(),
+            Self::Monotonic => get_time_ns(),
+        }
+    }
+    pub fn now_ms(self) -> usize {
+        match self {
+            Self::Realtime => self.now_ns() / NSEC_PER_MSEC,
+            // the same as the timer heap uses
+            Self::Monotonic => get_time_ms(),
+        }
+    }
+}
+
+pub enum TimerAction {
+    /// Wake up the tasks sleeping on the queue.
+    Wakeup(WaitQueue),
+    /// Expire an armed POSIX timer, ignored if it was re-armed or deleted.
+    Expire(Weak<PosixTimer>, usize),
+    /// Time out a futex wait, ignored if it was woken already.
+    FutexTimeout(Weak<FutexWaiter>),
+    /// Time out a TCP connection, ignored if its timer was set again or
+    /// stopped since.
+    TcpTimeout(Weak<TcpConnection>, usize),
 }
 
 pub struct TimerCondVar {
     pub expire_ms: usize,
-    pub task: Arc<TaskControlBlock>,
+    pub action: TimerAction,
 }
 
 impl PartialEq for TimerCondVar {
@@ -50,25 +283,246 @@ impl Ord for TimerCondVar {
 }
 
 lazy_static! {
-    static ref TIMERS: UPIntrFreeCell<BinaryHeap<TimerCondVar>> =
-        unsafe { UPIntrFreeCell::new(BinaryHeap::<TimerCondVar>::new()) };
+    static ref TIMERS: SpinMutexIrqSave<BinaryHeap<TimerCondVar>> =
+        SpinMutexIrqSave::new(BinaryHeap::<TimerCondVar>::new());
 }
 
-pub fn add_timer(expire_ms: usize, task: Arc<TaskControlBlock>) {
+/// Wake up whoever waits on `queue` at `expire_ms`.
+pub fn add_timer(expire_ms: usize, queue: WaitQueue) {
     let mut timers = TIMERS.exclusive_access();
-    timers.push(TimerCondVar { expire_ms, task });
+    timers.push(TimerCondVar {
+        expire_ms,
+        action: TimerAction::Wakeup(queue),
+    });
+    drop(timers);
+    trigger_by(expire_ms);
+}
+
+/// Time out the futex wait of `waiter` at `expire_ms`.
+pub fn add_futex_timer(expire_ms: usize, waiter: Weak<FutexWaiter>) {
+    TIMERS.exclusive_access().push(TimerCondVar {
+        expire_ms,
+        action: TimerAction::FutexTimeout(waiter),
+    });
+    trigger_by(expire_ms);
+}
+
+/// Time out `connection` at `expire_ms`, unless its timer `generation`
+/// is over by then.
+pub fn add_tcp_timer(expire_ms: usize, connection: Weak<TcpConnection>, generation: usize) {
+    TIMERS.exclusive_access().push(TimerCondVar {
+        expire_ms,
+        action: TimerAction::TcpTimeout(connection, generation),
+    });
+    trigger_by(expire_ms);
 }
 
 pub fn check_timer() {
     let current_ms = get_time_ms();
+    let mut expired = Vec::new();
+    let mut timeouts = Vec::new();
+    let mut tcp_timeouts = Vec::new();
     TIMERS.exclusive_session(|timers| {
         while let Some(timer) = timers.peek() {
             if timer.expire_ms <= current_ms {
-                wakeup_task(Arc::clone(&timer.task));
-                timers.pop();
+                match timers.pop().unwrap().action {
+                    TimerAction::Wakeup(queue) => queue.wake_all(),
+                    TimerAction::Expire(timer, generation) => expired.push((timer, generation)),
+                    TimerAction::FutexTimeout(waiter) => timeouts.push(waiter),
+                    TimerAction::TcpTimeout(connection, generation) => {
+                        tcp_timeouts.push((connection, generation))
+                    }
+                }
             } else {
                 break;
             }
         }
     });
+    // re-arming pushes into TIMERS again, so do it outside the session
+    for (timer, generation) in expired {
+        if let Some(timer) = timer.upgrade() {
+            timer.expire(generation, current_ms);
+        }
+    }
+    // a futex wait adds its timer under the futex lock, so take that after
+    for waiter in timeouts {
+        if let Some(waiter) = waiter.upgrade() {
+            futex_timeout(&waiter);
+        }
+    }
+    // retransmitting sets the timer again
+    for (connection, generation) in tcp_timeouts {
+        if let Some(connection) = connection.upgrade() {
+            connection.timeout(generation);
+        }
+    }
+    replenish_deadline_tasks();
+}
+
+/// How a POSIX timer reports its expiration.
+pub const SIGEV_SIGNAL: u32 = 0;
+pub const SIGEV_NONE: u32 = 1;
+/// Raise the signal for one thread of the process only.
+pub const SIGEV_THREAD_ID: u32 = 4;
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct SigEvent {
+    pub notify: u32,
+    /// Signal to raise, in the same `SignalFlags` encoding as `sys_kill`.
+    pub signal: u32,
+    /// The thread to raise it for with `SIGEV_THREAD_ID`, as `gettid`
+    /// numbers it.
+    pub tid: usize,
+}
+
+/// A per-process interval timer created by `sys_timer_create`, driven by
+/// the same heap as `sys_nanosleep`.
+pub struct PosixTimer {
+    process: Weak<ProcessControlBlock>,
+    /// the only thread the signal is for, if not the whole process
+    thread: Option<Weak<TaskControlBlock>>,
+    clock: ClockId,
+    signal: Option<SignalFlags>,
+    inner: SpinMutexIrqSave<PosixTimerInner>,
+}
+
+struct PosixTimerInner {
+    /// Monotonic expiration time, 0 if disarmed.
+    expire_ms: usize,
+    interval_ms: usize,
+    /// Expirations that could not be delivered, see `timer_getoverrun(2)`.
+    overrun: usize,
+    /// Bumped on every (re-)arm so stale entries in TIMERS are ignored.
+    generation: usize,
+}
+
+impl KernelObject for PosixTimer {
+    fn kind(&self) -> ObjectKind {
+        ObjectKind::Timer
+    }
+}
+
+impl PosixTimer {
+    pub fn new(
+        process: Weak<ProcessControlBlock>,
+        thread: Option<Weak<TaskControlBlock>>,
+        clock: ClockId,
+        signal: Option<SignalFlags>,
+    ) -> Self {
+        Self {
+            process,
+            thread,
+            clock,
+            signal,
+            inner: SpinMutexIrqSave::new(PosixTimerInner {
+                expire_ms: 0,
+                interval_ms: 0,
+                overrun: 0,
+                generation: 0,
+            }),
+        }
+    }
+
+    /// Arm the timer with `spec` (disarm if its value is zero) and
+    /// return the previous setting. With `absolute`, the value is a time
+    /// on the timer's clock instead of an offset from now.
+    pub fn set(self: &Arc<Self>, spec: &ITimerSpec, absolute: bool) -> ITimerSpec {
+        let old = self.get();
+        let now = get_time_ms();
+        let mut value_ms = spec.value.to_ms();
+        if absolute && value_ms != 0 {
+            // an absolute time in the past expires at once
+            let clock_now = self.clock.now_ms();
+            value_ms = value_ms.saturating_sub(clock_now).max(1);
+        }
+        let mut inner = self.inner.exclusive_access();
+        inner.generation += 1;
+        inner.overrun = 0;
+        inner.interval_ms = spec.interval.to_ms();
+        inner.expire_ms = if value_ms == 0 { 0 } else { now + value_ms };
+        if inner.expire_ms != 0 {
+            let (expire_ms, generation) = (inner.expire_ms, inner.generation);
+            drop(inner);
+            self.schedule(expire_ms, generation);
+        }
+        old
+    }
+
+    /// Time left until the next expiration and the reload interval.
+    pub fn get(&self) -> ITimerSpec {
+        let inner = self.inner.exclusive_access();
+        let value_ms = if inner.expire_ms == 0 {
+            0
+        } else {
+            inner.expire_ms.saturating_sub(get_time_ms()).max(1)
+        };
+        ITimerSpec {
+            interval: TimeSpec::from_ms(inner.interval_ms),
+            value: TimeSpec::from_ms(value_ms),
+        }
+    }
+
+    pub fn overrun(&self) -> usize {
+        self.inner.exclusive_access().overrun
+    }
+
+    fn schedule(self: &Arc<Self>, expire_ms: usize, generation: usize) {
+        TIMERS.exclusive_access().push(TimerCondVar {
+            expire_ms,
+            action: TimerAction::Expire(Arc::downgrade(self), generation),
+        });
+        trigger_by(expire_ms);
+    }
+
+    fn expire(self: &Arc<Self>, generation: usize, current_ms: usize) {
+        let mut inner = self.inner.exclusive_access();
+        if inner.generation != generation || inner.expire_ms == 0 {
+            return;
+        }
+        // periods that went by completely while nobody looked
+        let mut missed = 0;
+        if inner.interval_ms != 0 {
+            missed = (current_ms - inner.expire_ms) / inner.interval_ms;
+            inner.expire_ms += (missed + 1) * inner.interval_ms;
+        } else {
+            inner.expire_ms = 0;
+        }
+        if let Some(signal) = self.signal {
+            match self.raise(signal) {
+                Some(true) => inner.overrun = missed,
+                // still pending, this expiration is lost as well
+                Some(false) => inner.overrun += missed + 1,
+                None => {}
+            }
+        } else {
+            // nobody is told, so every expiration counts
+            inner.overrun += missed + 1;
+        }
+        if inner.expire_ms != 0 {
+            let (expire_ms, generation) = (inner.expire_ms, inner.generation);
+            drop(inner);
+            self.schedule(expire_ms, generation);
+        }
+    }
+
+    /// Raise `signal` for the thread or the process it is for, unless it
+    /// is still pending there. Returns whether it was raised, none if
+    /// whoever it was for is gone.
+    fn raise(&self, signal: SignalFlags) -> Option<bool> {
+        if let Some(thread) = &self.thread {
+            let thread = thread.upgrade()?;
+            let pending = thread.inner_exclusive_access().signals.contains(signal);
+            if !pending {
+                thread.send_signal(signal);
+            }
+            return Some(!pending);
+        }
+        let process = self.process.upgrade()?;
+        let pending = process.inner_exclusive_access().signals.contains(signal);
+        if !pending {
+            process.send_signal(signal);
+        }
+        Some(!pending)
+    }
 }
@@ -1,13 +1,21 @@
 mod context;
 
-use crate::config::TRAMPOLINE;
+use crate::config::{TRAMPOLINE, TRAP_BARRIER};
+use crate::debug::gdbstub::handle_breakpoint;
+use crate::ipi::handle_ipi;
+use crate::mm::{enter_kernel, enter_user, reclaim};
+use crate::sync::preemptible;
 use crate::syscall::syscall;
 use crate::task::{
-    check_signals_of_current, current_add_signal, current_trap_cx, current_trap_cx_user_va,
-    current_user_token, exit_current_and_run_next, suspend_current_and_run_next, SignalFlags,
+    account_trap_entry, account_trap_return, breakpoint_trap, current_add_signal, current_process,
+    current_task, current_trap_cx, current_trap_cx_user_va, current_user_token,
+    exit_current_if_torn_down, handle_signals, hart_id, kernel_stack_guard,
+    kill_current_and_run_next, slice_expired, suspend_current_and_run_next, wait_while_stopped,
+    watchdog_answer, watchdog_check, SignalFlags,
 };
 use crate::timer::{check_timer, set_next_trigger};
 use core::arch::{asm, global_asm};
+use log::{info, warn};
 use riscv::register::{
     mtvec::TrapMode,
     scause::{self, Exception, Interrupt, Trap},
@@ -56,9 +64,23 @@ fn disable_supervisor_interrupt() {
     }
 }
 
+/// Drain outstanding memory accesses and the instruction stream when
+/// crossing the user/kernel boundary, if enabled in `config`.
+#[inline(always)]
+fn boundary_barrier() {
+    if TRAP_BARRIER {
+        unsafe {
+            asm!("fence", "fence.i");
+        }
+    }
+}
+
 #[no_mangle]
 pub fn trap_handler() -> ! {
+    boundary_barrier();
     set_kernel_trap_entry();
+    enter_kernel();
+    account_trap_entry();
     let scause = scause::read();
     let stval = stval::read();
     // println!("into {:?}", scause.cause());
@@ -71,38 +93,76 @@ pub fn trap_handler() -> ! {
             enable_supervisor_interrupt();
 
             // get system call return value
-            let result = syscall(cx.x[17], [cx.x[10], cx.x[11], cx.x[12]]);
+            let result = statistic_time!(
+                "syscall",
+                syscall(
+                    cx.x[17],
+                    [cx.x[10], cx.x[11], cx.x[12], cx.x[13], cx.x[14], cx.x[15]]
+                )
+            );
+            // the trap context went with the process if a sibling ended it
+            exit_current_if_torn_down();
             // cx is changed during sys_exec, so we have to call it again
             cx = current_trap_cx();
             cx.x[10] = result as usize;
         }
-        Trap::Exception(Exception::StoreFault)
-        | Trap::Exception(Exception::StorePageFault)
-        | Trap::Exception(Exception::InstructionFault)
+        Trap::Exception(Exception::StorePageFault)
         | Trap::Exception(Exception::InstructionPageFault)
-        | Trap::Exception(Exception::LoadFault)
         | Trap::Exception(Exception::LoadPageFault) => {
-            /*
-            println!(
-                "[kernel] {:?} in application, bad addr = {:#x}, bad instruction = {:#x}, kernel killed it.",
-                scause.cause(),
-                stval,
-                current_trap_cx().sepc,
-            );
-            */
+            // demand paging and swapping may have to wait for the disk
+            enable_supervisor_interrupt();
+            reclaim(1);
+            let process = current_process();
+            match statistic_time!("page_fault", process.handle_page_fault(stval)) {
+                Ok(true) => {}
+                Ok(false) => {
+                    report_user_fault(scause.cause(), stval);
+                    if let Some(tid) = process.overflowed_stack(stval) {
+                        warn!(
+                            "stack overflow in pid {} tid {}, bad addr = {:#x}",
+                            process.getpid(),
+                            tid,
+                            stval
+                        );
+                    }
+                    current_add_signal(SignalFlags::SIGSEGV);
+                }
+                // nothing left to reclaim, only this process goes
+                Err(_) => {
+                    warn!("out of memory, killing pid {}", process.getpid());
+                    current_add_signal(SignalFlags::SIGKILL);
+                }
+            }
+        }
+        Trap::Exception(Exception::StoreFault)
+        | Trap::Exception(Exception::InstructionFault)
+        | Trap::Exception(Exception::LoadFault) => {
+            report_user_fault(scause.cause(), stval);
             current_add_signal(SignalFlags::SIGSEGV);
         }
         Trap::Exception(Exception::IllegalInstruction) => {
             current_add_signal(SignalFlags::SIGILL);
         }
+        Trap::Exception(Exception::Breakpoint) => {
+            breakpoint_trap();
+        }
         Trap::Interrupt(Interrupt::SupervisorTimer) => {
-            set_next_trigger();
             check_timer();
-            suspend_current_and_run_next();
+            watchdog_check(None);
+            if slice_expired() {
+                suspend_current_and_run_next();
+            } else {
+                set_next_trigger();
+            }
         }
         Trap::Interrupt(Interrupt::SupervisorExternal) => {
             crate::board::irq_handler();
         }
+        Trap::Interrupt(Interrupt::SupervisorSoft) => {
+            if handle_ipi() {
+                suspend_current_and_run_next();
+            }
+        }
         _ => {
             panic!(
                 "Unsupported trap {:?}, stval = {:#x}!",
@@ -111,26 +171,62 @@ pub fn trap_handler() -> ! {
             );
         }
     }
-    // check signals
-    if let Some((errno, msg)) = check_signals_of_current() {
-        println!("[kernel] {}", msg);
-        exit_current_and_run_next(errno);
+    exit_current_if_torn_down();
+    // deliver signals, again after a stop as more may have come
+    loop {
+        if let Some((signal, msg)) = handle_signals() {
+            info!("{}", msg);
+            kill_current_and_run_next(signal);
+        }
+        if !wait_while_stopped() {
+            break;
+        }
     }
     trap_return();
 }
 
+/// Tell what the current process faulted on at `addr` before it gets
+/// SIGSEGV for it.
+fn report_user_fault(cause: Trap, addr: usize) {
+    let access = match cause {
+        Trap::Exception(Exception::LoadPageFault) | Trap::Exception(Exception::LoadFault) => "read",
+        Trap::Exception(Exception::StorePageFault) | Trap::Exception(Exception::StoreFault) => {
+            "write"
+        }
+        _ => "execute",
+    };
+    let process = current_process();
+    let mapping = process
+        .inner_exclusive_access()
+        .memory_set
+        .describe(addr.into());
+    warn!(
+        "segmentation fault in pid {}: {} at {:#x}, sepc = {:#x}, {}",
+        process.getpid(),
+        access,
+        addr,
+        current_trap_cx().sepc,
+        mapping
+    );
+}
+
 #[no_mangle]
 pub fn trap_return() -> ! {
+    account_trap_return();
     disable_supervisor_interrupt();
     set_user_trap_entry();
+    // the next trap may come in on this hart only
+    current_trap_cx().kernel_tp = hart_id();
     let trap_cx_user_va = current_trap_cx_user_va();
     let user_satp = current_user_token();
+    enter_user(user_satp);
     extern "C" {
         fn __alltraps();
         fn __restore();
     }
     let restore_va = __restore as usize - __alltraps as usize + TRAMPOLINE;
     //println!("before return");
+    boundary_barrier();
     unsafe {
         asm!(
             "fence.i",
@@ -143,8 +239,12 @@ pub fn trap_return() -> ! {
     }
 }
 
+/// Handle a trap taken in S-mode, with the registers trapped at saved in
+/// `trap_cx` on the kernel stack. Interrupts are handled and the kernel
+/// carries on, any fault is a kernel bug. An ebreak goes to the GDB stub,
+/// which may change the registers restored from `trap_cx`.
 #[no_mangle]
-pub fn trap_from_kernel(_trap_cx: &TrapContext) {
+pub fn trap_from_kernel(trap_cx: &mut TrapContext) {
     let scause = scause::read();
     let stval = stval::read();
     match scause.cause() {
@@ -152,15 +252,42 @@ pub fn trap_from_kernel(_trap_cx: &TrapContext) {
             crate::board::irq_handler();
         }
         Trap::Interrupt(Interrupt::SupervisorTimer) => {
-            set_next_trigger();
             check_timer();
-            // do not schedule now
+            watchdog_check(Some(trap_cx));
+            set_next_trigger();
+            // the interrupted task goes back to the ready queue like one
+            // interrupted in user mode once its slice is used up, unless it
+            // may not leave this hart now or the hart was idle
+            if preemptible() && slice_expired() {
+                suspend_current_and_run_next();
+            }
+        }
+        Trap::Interrupt(Interrupt::SupervisorSoft) => {
+            watchdog_answer(trap_cx);
+            if handle_ipi() && preemptible() && current_task().is_some() {
+                suspend_current_and_run_next();
+            }
+        }
+        Trap::Exception(Exception::Breakpoint) if handle_breakpoint(trap_cx) => {}
+        Trap::Exception(Exception::StorePageFault)
+        | Trap::Exception(Exception::LoadPageFault)
+        | Trap::Exception(Exception::InstructionPageFault)
+            if kernel_stack_guard(stval).is_some() =>
+        {
+            panic!(
+                "Kernel stack {} overflowed, stval = {:#x}!",
+                kernel_stack_guard(stval).unwrap(),
+                stval
+            );
         }
         _ => {
             panic!(
-                "Unsupported trap from kernel: {:?}, stval = {:#x}!",
+                "Unsupported trap from kernel: {:?}, stval = {:#x}, sepc = {:#x}, ra = {:#x}, sp = {:#x}!",
                 scause.cause(),
-                stval
+                stval,
+                trap_cx.sepc,
+                trap_cx.x[1],
+                trap_cx.x[2]
             );
         }
     }
@@ -0,0 +1,73 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+extern crate alloc;
+
+use alloc::vec::Vec;
+use core::ptr::addr_of_mut;
+use core::sync::atomic::AtomicU32;
+use user_lib::{exit, futex_wait, get_time, thread_create, waittid, TimeSpec};
+use user_lib::{FutexMutex, ETIMEDOUT};
+
+static mut A: usize = 0;
+const PER_THREAD_DEFAULT: usize = 10000;
+const THREAD_COUNT_DEFAULT: usize = 16;
+static mut PER_THREAD: usize = 0;
+static MUTEX: FutexMutex = FutexMutex::new();
+
+unsafe fn critical_section(t: &mut usize) {
+    let a = addr_of_mut!(A);
+    let cur = a.read_volatile();
+    for _ in 0..500 {
+        *t = (*t) * (*t) % 10007;
+    }
+    a.write_volatile(cur + 1);
+}
+unsafe fn f() -> ! {
+    let mut t = 2usize;
+    for _ in 0..PER_THREAD {
+        MUTEX.lock();
+        critical_section(&mut t);
+        MUTEX.unlock();
+    }
+    exit(t as i32)
+}
+
+#[no_mangle]
+pub fn main(argc: usize, argv: &[&str]) -> i32 {
+    let mut thread_count = THREAD_COUNT_DEFAULT;
+    let mut per_thread = PER_THREAD_DEFAULT;
+    if argc >= 2 {
+        thread_count = argv[1].parse().unwrap();
+        if argc >= 3 {
+            per_thread = argv[2].parse().unwrap();
+        }
+    }
+    unsafe {
+        PER_THREAD = per_thread;
+    }
+
+    // nobody wakes this word, so a wait only ends on a mismatch or timeout
+    let word = AtomicU32::new(1);
+    assert_eq!(futex_wait(&word, 0, None), -1);
+    let start = get_time();
+    assert_eq!(
+        futex_wait(&word, 1, Some(&TimeSpec::from_ms(20))),
+        ETIMEDOUT
+    );
+    assert!(get_time() - start >= 20);
+
+    let start = get_time();
+    let mut v = Vec::new();
+    for _ in 0..thread_count {
+        v.push(thread_create(f as usize, 0) as usize);
+    }
+    for tid in v.into_iter() {
+        waittid(tid);
+    }
+    println!("time cost is {}ms", get_time() - start);
+    assert_eq!(unsafe { A }, unsafe { PER_THREAD } * thread_count);
+    0
+}
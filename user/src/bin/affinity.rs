@@ -0,0 +1,40 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{exit, fork, getpid, sched_getaffinity, sched_setaffinity, waitpid, yield_};
+
+#[no_mangle]
+pub fn main() -> i32 {
+    // the boot hart is always online
+    let all = sched_getaffinity(0);
+    assert!(all & 1 != 0);
+    assert_eq!(sched_getaffinity(getpid() as usize), all);
+    assert_eq!(sched_getaffinity(usize::MAX), -1);
+    assert_eq!(sched_setaffinity(0, 0), -1);
+    assert_eq!(sched_setaffinity(0, 1 << 63), -1);
+    assert_eq!(sched_setaffinity(usize::MAX, 1), -1);
+
+    assert_eq!(sched_setaffinity(0, 1), 0);
+    assert_eq!(sched_getaffinity(0), 1);
+    yield_();
+    assert_eq!(sched_getaffinity(0), 1);
+    // children start out pinned like their parent
+    let pid = fork();
+    if pid == 0 {
+        assert_eq!(sched_getaffinity(0), 1);
+        yield_();
+        exit(0);
+    }
+    assert_eq!(sched_getaffinity(pid as usize), 1);
+    let mut exit_code = 0;
+    assert_eq!(waitpid(pid as usize, &mut exit_code), pid);
+    assert_eq!(exit_code, 0);
+
+    assert_eq!(sched_setaffinity(0, all as usize), 0);
+    assert_eq!(sched_getaffinity(0), all);
+    println!("affinity passed!");
+    0
+}
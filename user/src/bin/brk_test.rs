@@ -0,0 +1,56 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+extern crate alloc;
+
+use alloc::vec;
+use user_lib::{brk, exit, fork, sbrk, waitpid};
+
+const PAGE_SIZE: usize = 4096;
+
+#[no_mangle]
+pub fn main() -> i32 {
+    // the allocator keeps the break where it left it, move on from there
+    let base = sbrk(0);
+    assert!(base > 0);
+    let base = base as usize;
+    let end = base + 3 * PAGE_SIZE + 5;
+    assert_eq!(brk(end), end as isize);
+    assert_eq!(sbrk(0), end as isize);
+    let heap = unsafe { core::slice::from_raw_parts_mut(base as *mut u8, end - base) };
+    for (i, byte) in heap.iter_mut().enumerate() {
+        *byte = i as u8;
+    }
+
+    // a child gets a copy of the heap
+    let pid = fork();
+    if pid == 0 {
+        assert!(heap.iter().enumerate().all(|(i, &byte)| byte == i as u8));
+        heap.fill(0);
+        exit(0);
+    }
+    let mut exit_code = 0;
+    assert_eq!(waitpid(pid as usize, &mut exit_code), pid);
+    assert_eq!(exit_code, 0);
+    assert_eq!(heap[1], 1);
+
+    // shrinking keeps the pages still below the break
+    assert_eq!(sbrk(-(2 * PAGE_SIZE as isize)), end as isize);
+    assert_eq!(sbrk(0), (end - 2 * PAGE_SIZE) as isize);
+    assert_eq!(heap[PAGE_SIZE], PAGE_SIZE as u8);
+    assert_eq!(brk(base), base as isize);
+
+    // not below where the heap started, nor over the mmap areas
+    assert_eq!(brk(PAGE_SIZE), base as isize);
+    assert_eq!(sbrk(-(1 << 40)), -1);
+    assert_eq!(sbrk(1 << 40), -1);
+    assert_eq!(sbrk(0), base as isize);
+
+    // far more than the old static heap of 32KiB
+    let big = vec![7u8; 1 << 20];
+    assert!(big.iter().all(|&byte| byte == 7));
+    println!("brk_test passed!");
+    0
+}
@@ -0,0 +1,41 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{clock_gettime, nanosleep, TimeSpec, CLOCK_MONOTONIC, CLOCK_REALTIME};
+
+fn now(clock_id: usize) -> TimeSpec {
+    let mut tp = TimeSpec::default();
+    assert_eq!(clock_gettime(clock_id, &mut tp), 0);
+    assert!(tp.nsec < 1_000_000_000);
+    tp
+}
+
+#[no_mangle]
+pub fn main() -> i32 {
+    let mut tp = TimeSpec::default();
+    assert_eq!(clock_gettime(99, &mut tp), -1);
+    // seeded from the RTC, well after 2020
+    assert!(now(CLOCK_REALTIME).sec > 1_577_836_800);
+
+    // never going back, and finer than whole milliseconds
+    let a = now(CLOCK_MONOTONIC).to_ns();
+    let b = now(CLOCK_MONOTONIC).to_ns();
+    assert!(b >= a);
+    assert!(a % 1_000_000 != 0 || b % 1_000_000 != 0);
+
+    let start = now(CLOCK_MONOTONIC).to_ns();
+    assert_eq!(nanosleep(&TimeSpec::from_ms(100)), 0);
+    let slept = now(CLOCK_MONOTONIC).to_ns() - start;
+    assert!(slept >= 100_000_000);
+
+    let invalid = TimeSpec {
+        sec: 0,
+        nsec: 1_000_000_000,
+    };
+    assert_eq!(nanosleep(&invalid), -1);
+    println!("clock_nanosleep passed!");
+    0
+}
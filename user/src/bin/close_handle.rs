@@ -0,0 +1,57 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{
+    close_handle, condvar_create, msgget, mutex_blocking_create, mutex_lock, mutex_unlock,
+    semaphore_create, shmat, shmdt, shmget, HandleKind, IPC_CREAT,
+};
+
+const MSG_KEY: usize = 0x4348;
+const SHM_KEY: usize = 0x4349;
+
+#[no_mangle]
+pub fn main() -> i32 {
+    let mutex = mutex_blocking_create();
+    assert!(mutex >= 0);
+    let mutex = mutex as usize;
+    assert_eq!(close_handle(HandleKind::Mutex, mutex), 0);
+    assert_eq!(mutex_lock(mutex), -1);
+    assert_eq!(close_handle(HandleKind::Mutex, mutex), -1);
+    // the handle is free for the next mutex
+    assert_eq!(mutex_blocking_create(), mutex as isize);
+    assert_eq!(mutex_lock(mutex), 0);
+    assert_eq!(mutex_unlock(mutex), 0);
+
+    // each kind has handles of its own
+    let sem = semaphore_create(1);
+    assert!(sem >= 0);
+    assert_eq!(close_handle(HandleKind::Condvar, sem as usize), -1);
+    assert_eq!(close_handle(HandleKind::Semaphore, sem as usize), 0);
+    let condvar = condvar_create();
+    assert!(condvar >= 0);
+    assert_eq!(close_handle(HandleKind::Condvar, condvar as usize), 0);
+
+    // a keyed queue only its handle kept goes with it
+    let msqid = msgget(MSG_KEY, IPC_CREAT);
+    assert!(msqid >= 0);
+    assert_eq!(close_handle(HandleKind::MsgQueue, msqid as usize), 0);
+    assert_eq!(msgget(MSG_KEY, 0), -1);
+
+    // an attached segment stays until it is detached
+    let shmid = shmget(SHM_KEY, 4096, IPC_CREAT);
+    assert!(shmid >= 0);
+    let addr = shmat(shmid as usize, 0, 0);
+    assert!(addr > 0);
+    assert_eq!(close_handle(HandleKind::Shm, shmid as usize), 0);
+    unsafe { (addr as *mut u8).write_volatile(42) };
+    let shmid = shmget(SHM_KEY, 0, 0);
+    assert!(shmid >= 0);
+    assert_eq!(close_handle(HandleKind::Shm, shmid as usize), 0);
+    assert_eq!(shmdt(addr as usize), 0);
+    assert_eq!(shmget(SHM_KEY, 0, 0), -1);
+    println!("close_handle passed!");
+    0
+}
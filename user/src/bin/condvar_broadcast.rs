@@ -0,0 +1,56 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+extern crate alloc;
+
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use user_lib::{
+    condvar_broadcast, condvar_create, condvar_wait, exit, mutex_blocking_create, mutex_lock,
+    mutex_unlock, thread_create, waittid, yield_,
+};
+
+const THREAD_NUM: usize = 4;
+const MUTEX_ID: usize = 0;
+const CONDVAR_ID: usize = 0;
+
+static WAITING: AtomicUsize = AtomicUsize::new(0);
+static READY: AtomicBool = AtomicBool::new(false);
+
+fn waiter() -> ! {
+    mutex_lock(MUTEX_ID);
+    WAITING.fetch_add(1, Ordering::SeqCst);
+    while !READY.load(Ordering::SeqCst) {
+        condvar_wait(CONDVAR_ID, MUTEX_ID);
+    }
+    mutex_unlock(MUTEX_ID);
+    exit(0)
+}
+
+#[no_mangle]
+pub fn main() -> i32 {
+    assert_eq!(mutex_blocking_create() as usize, MUTEX_ID);
+    assert_eq!(condvar_create() as usize, CONDVAR_ID);
+    let threads: Vec<isize> = (0..THREAD_NUM)
+        .map(|_| thread_create(waiter as usize, 0))
+        .collect();
+    // a single broadcast has to get all of them going
+    loop {
+        mutex_lock(MUTEX_ID);
+        if WAITING.load(Ordering::SeqCst) == THREAD_NUM {
+            break;
+        }
+        mutex_unlock(MUTEX_ID);
+        yield_();
+    }
+    READY.store(true, Ordering::SeqCst);
+    condvar_broadcast(CONDVAR_ID);
+    mutex_unlock(MUTEX_ID);
+    for tid in threads {
+        assert_eq!(waittid(tid as usize), 0);
+    }
+    println!("condvar_broadcast passed!");
+    0
+}
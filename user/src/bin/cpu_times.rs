@@ -0,0 +1,61 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use core::hint::black_box;
+use user_lib::{
+    exit, fork, get_time, getrusage, mmap, times, waitpid, RUsage, Tms, RUSAGE_CHILDREN,
+    RUSAGE_SELF, RUSAGE_THREAD,
+};
+
+/// Compute in user mode for about `ms` milliseconds.
+fn spin(ms: isize) {
+    let start = get_time();
+    let mut x = 0usize;
+    while get_time() - start < ms {
+        for i in 0..10000 {
+            x = black_box(x.wrapping_add(i));
+        }
+    }
+}
+
+#[no_mangle]
+pub fn main() -> i32 {
+    spin(200);
+    let mut tms = Tms::default();
+    assert!(times(&mut tms) > 0);
+    assert!(tms.utime > 0);
+    assert_eq!((tms.cutime, tms.cstime), (0, 0));
+    let mut usage = RUsage::default();
+    assert_eq!(getrusage(RUSAGE_SELF, &mut usage), 0);
+    assert!(usage.utime.to_us() > 0);
+    assert!(usage.maxrss > 0);
+    assert_eq!(getrusage(RUSAGE_THREAD, &mut usage), 0);
+    assert!(usage.utime.to_us() > 0);
+    assert_eq!(getrusage(5, &mut usage), -1);
+
+    let pid = fork();
+    if pid == 0 {
+        // 256 KiB more at its peak, given back at exit
+        let start = 0x2000_0000;
+        assert_eq!(mmap(start, 64 * 4096, 0x3), 0);
+        for page in 0..64 {
+            unsafe { *((start + page * 4096) as *mut u8) = 1 };
+        }
+        spin(200);
+        exit(0);
+    }
+    assert_eq!(getrusage(RUSAGE_CHILDREN, &mut usage), 0);
+    assert_eq!(usage.utime.to_us(), 0);
+    let mut exit_code = -1;
+    assert_eq!(waitpid(pid as usize, &mut exit_code), pid);
+    assert_eq!(getrusage(RUSAGE_CHILDREN, &mut usage), 0);
+    assert!(usage.utime.to_us() > 0);
+    assert!(usage.maxrss >= 256);
+    times(&mut tms);
+    assert!(tms.cutime > 0);
+    println!("cpu_times passed!");
+    0
+}
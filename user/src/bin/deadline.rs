@@ -0,0 +1,86 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{
+    exit, fork, get_time, sched_getscheduler, sched_setaffinity, sched_setscheduler, setpriority,
+    waitpid, MAX_PRIORITY, MIN_PRIORITY, SCHED_DEADLINE, SCHED_NORMAL,
+};
+
+const NSEC_PER_MSEC: usize = 1_000_000;
+const MEASURE_MS: isize = 300;
+
+/// Milliseconds we got to run out of the next `MEASURE_MS`, counting the
+/// steps of the clock we saw go by one at a time.
+fn measure_run_ms() -> isize {
+    let end = get_time() + MEASURE_MS;
+    let mut last = get_time();
+    let mut ran = 0;
+    while last < end {
+        let now = get_time();
+        if now - last <= 1 {
+            ran += now - last;
+        }
+        last = now;
+    }
+    ran
+}
+
+#[no_mangle]
+pub fn main() -> i32 {
+    assert_eq!(sched_getscheduler(0), SCHED_NORMAL as isize);
+    assert_eq!(sched_getscheduler(usize::MAX), -1);
+    let ms = NSEC_PER_MSEC;
+    assert_eq!(sched_setscheduler(0, SCHED_DEADLINE, 0, 100 * ms), -1);
+    assert_eq!(
+        sched_setscheduler(0, SCHED_DEADLINE, 200 * ms, 100 * ms),
+        -1
+    );
+    assert_eq!(sched_setscheduler(0, 3, 50 * ms, 100 * ms), -1);
+    assert_eq!(
+        sched_setscheduler(usize::MAX, SCHED_DEADLINE, 50 * ms, 100 * ms),
+        -1
+    );
+
+    // everything here competes for hart 0
+    assert_eq!(sched_setaffinity(0, 1), 0);
+    assert_eq!(sched_setscheduler(0, SCHED_DEADLINE, 50 * ms, 100 * ms), 0);
+    assert_eq!(sched_getscheduler(0), SCHED_DEADLINE as isize);
+    // a deadline thread stays on the hart it was admitted on
+    assert_eq!(sched_setaffinity(0, 1), -1);
+
+    let pid = fork();
+    if pid == 0 {
+        // children start out in the normal class
+        assert_eq!(sched_getscheduler(0), SCHED_NORMAL as isize);
+        // together with the parent that is more than hart 0 has
+        assert_eq!(sched_setscheduler(0, SCHED_DEADLINE, 60 * ms, 100 * ms), -1);
+        let end = get_time() + MEASURE_MS * 2;
+        while get_time() < end {}
+        exit(0);
+    }
+    // in the normal class this would leave us next to nothing
+    assert_eq!(setpriority(pid as usize, MAX_PRIORITY), 0);
+    assert_eq!(setpriority(0, MIN_PRIORITY), 0);
+    let ran = measure_run_ms();
+    println!(
+        "ran {} of {} ms with half of the hart reserved",
+        ran, MEASURE_MS
+    );
+    assert!(ran >= MEASURE_MS / 3);
+    // the budget runs out, and the spinning child gets the rest
+    assert!(ran <= MEASURE_MS * 3 / 4);
+    let mut exit_code = 0;
+    assert_eq!(waitpid(pid as usize, &mut exit_code), pid);
+    assert_eq!(exit_code, 0);
+
+    // leaving the class gives the bandwidth back
+    assert_eq!(sched_setscheduler(0, SCHED_NORMAL, 0, 0), 0);
+    assert_eq!(sched_getscheduler(0), SCHED_NORMAL as isize);
+    assert_eq!(sched_setscheduler(0, SCHED_DEADLINE, 90 * ms, 100 * ms), 0);
+    assert_eq!(sched_setscheduler(0, SCHED_NORMAL, 0, 0), 0);
+    println!("deadline passed!");
+    0
+}
@@ -0,0 +1,49 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use core::sync::atomic::{AtomicBool, Ordering};
+use user_lib::{
+    enable_deadlock_detect, exit, mutex_blocking_create, mutex_lock, mutex_unlock, sleep,
+    thread_create, waittid, DEADLOCK,
+};
+
+const MUTEX_A: usize = 0;
+const MUTEX_B: usize = 1;
+
+static HOLDS_B: AtomicBool = AtomicBool::new(false);
+
+fn holder_of_b() -> ! {
+    assert_eq!(mutex_lock(MUTEX_B), 0);
+    HOLDS_B.store(true, Ordering::SeqCst);
+    // blocks until main gives A up
+    assert_eq!(mutex_lock(MUTEX_A), 0);
+    mutex_unlock(MUTEX_A);
+    mutex_unlock(MUTEX_B);
+    exit(0)
+}
+
+#[no_mangle]
+pub fn main() -> i32 {
+    assert_eq!(enable_deadlock_detect(true), 0);
+    assert_eq!(mutex_blocking_create() as usize, MUTEX_A);
+    assert_eq!(mutex_blocking_create() as usize, MUTEX_B);
+    assert_eq!(mutex_lock(MUTEX_A), 0);
+    let tid = thread_create(holder_of_b as usize, 0);
+    while !HOLDS_B.load(Ordering::SeqCst) {
+        sleep(1);
+    }
+    // let the thread block on A
+    sleep(10);
+    // main holds A and the thread holds B while waiting for A
+    assert_eq!(mutex_lock(MUTEX_B), DEADLOCK);
+    mutex_unlock(MUTEX_A);
+    assert_eq!(waittid(tid as usize), 0);
+    // once the thread is done B is free
+    assert_eq!(mutex_lock(MUTEX_B), 0);
+    mutex_unlock(MUTEX_B);
+    println!("deadlock_mutex passed!");
+    0
+}
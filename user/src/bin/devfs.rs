@@ -0,0 +1,44 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{close, fstat, open, read, write, OpenFlags, Stat, StatMode};
+
+#[no_mangle]
+pub fn main() -> i32 {
+    let zero = open("/dev/zero\0", OpenFlags::RDONLY);
+    assert!(zero >= 0);
+    let mut buf = [0xffu8; 100];
+    assert_eq!(read(zero as usize, &mut buf), 100);
+    assert!(buf.iter().all(|&b| b == 0));
+    close(zero as usize);
+
+    let null = open("/dev/null\0", OpenFlags::RDWR);
+    assert!(null >= 0);
+    assert_eq!(write(null as usize, b"discarded"), 9);
+    assert_eq!(read(null as usize, &mut buf), 0);
+    let mut stat = Stat::new();
+    assert_eq!(fstat(null as usize, &mut stat), 0);
+    assert_eq!(stat.mode, StatMode::CHR);
+    close(null as usize);
+
+    let tty = open("/dev/tty\0", OpenFlags::WRONLY);
+    assert!(tty >= 0);
+    let greeting = b"devfs: hello from /dev/tty\n";
+    assert_eq!(write(tty as usize, greeting), greeting.len() as isize);
+    close(tty as usize);
+
+    // nanoseconds since the epoch, some time after 2020
+    let rtc = open("/dev/rtc\0", OpenFlags::RDONLY);
+    assert!(rtc >= 0);
+    let mut time = [0u8; 8];
+    assert_eq!(read(rtc as usize, &mut time), 8);
+    assert!(u64::from_le_bytes(time) > 1_577_836_800 * 1_000_000_000);
+    close(rtc as usize);
+
+    assert_eq!(open("/dev/none\0", OpenFlags::RDONLY), -1);
+    println!("devfs passed!");
+    0
+}
@@ -0,0 +1,54 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{chdir, close, getcwd, mkdir, open, read, write, OpenFlags};
+
+fn cwd(buf: &mut [u8; 64]) -> &str {
+    let len = getcwd(buf);
+    assert!(len > 0);
+    core::str::from_utf8(&buf[..len as usize]).unwrap()
+}
+
+#[no_mangle]
+pub fn main() -> i32 {
+    let mut path = [0u8; 64];
+    assert_eq!(cwd(&mut path), "/");
+    // the directory is left behind by an earlier run of this test
+    let _ = mkdir("dir_test\0");
+    assert_eq!(mkdir("dir_test\0"), -1);
+    assert_eq!(mkdir("\0"), -1);
+    assert_eq!(chdir("dir_test\0"), 0);
+    assert_eq!(cwd(&mut path), "/dir_test");
+
+    let _ = mkdir("sub\0");
+    assert_eq!(chdir("./sub/../sub\0"), 0);
+    assert_eq!(cwd(&mut path), "/dir_test/sub");
+    let fd = open("file\0", OpenFlags::CREATE | OpenFlags::WRONLY);
+    assert!(fd > 0);
+    write(fd as usize, b"nested");
+    close(fd as usize);
+    // files are not directories
+    assert_eq!(chdir("file\0"), -1);
+    assert_eq!(mkdir("file/dir\0"), -1);
+    assert_eq!(open("..\0", OpenFlags::WRONLY), -1);
+    assert_eq!(open("..\0", OpenFlags::CREATE), -1);
+
+    assert_eq!(chdir("/\0"), 0);
+    let fd = open("dir_test/sub/file\0", OpenFlags::RDONLY);
+    assert!(fd > 0);
+    let mut buf = [0u8; 16];
+    let len = read(fd as usize, &mut buf) as usize;
+    close(fd as usize);
+    assert_eq!(&buf[..len], b"nested");
+    assert_eq!(chdir("../..\0"), 0);
+    assert_eq!(cwd(&mut path), "/");
+    assert_eq!(chdir("nothing\0"), -1);
+
+    let mut small = [0u8; 1];
+    assert_eq!(getcwd(&mut small), -1);
+    println!("dir_test passed!");
+    0
+}
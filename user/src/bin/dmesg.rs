@@ -0,0 +1,36 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+extern crate alloc;
+
+use alloc::vec;
+use user_lib::{
+    close, open, read, syslog, OpenFlags, SYSLOG_ACTION_READ_ALL, SYSLOG_ACTION_SIZE_BUFFER,
+    SYSLOG_ACTION_SIZE_UNREAD,
+};
+
+#[no_mangle]
+pub fn main() -> i32 {
+    let size = syslog(SYSLOG_ACTION_SIZE_BUFFER, &mut []);
+    assert!(size > 0);
+    let mut buf = vec![0u8; size as usize];
+    let len = syslog(SYSLOG_ACTION_READ_ALL, &mut buf);
+    assert!(len >= 0 && len <= size);
+    print!("{}", core::str::from_utf8(&buf[..len as usize]).unwrap());
+    assert!(syslog(SYSLOG_ACTION_SIZE_UNREAD, &mut []) <= size);
+
+    // short reads get the newest bytes only
+    let mut tail = [0u8; 16];
+    assert!(syslog(SYSLOG_ACTION_READ_ALL, &mut tail) <= 16);
+    assert_eq!(syslog(1000, &mut tail), -1);
+
+    let fd = open("/proc/kmsg\0", OpenFlags::RDONLY);
+    assert!(fd >= 0);
+    assert!(read(fd as usize, &mut buf) >= 0);
+    close(fd as usize);
+    println!("dmesg passed!");
+    0
+}
@@ -0,0 +1,28 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{close, dup, dup2, pipe, read};
+
+#[no_mangle]
+pub fn main() -> i32 {
+    assert_eq!(dup2(100, 5), -1);
+    assert_eq!(dup2(0, 4096), -1);
+
+    // send stdout into a pipe for a moment
+    let mut pipe_fd = [0usize; 2];
+    pipe(&mut pipe_fd);
+    let stdout = dup(1) as usize;
+    assert_eq!(dup2(pipe_fd[1], 1), 1);
+    print!("redirected");
+    assert_eq!(dup2(stdout, 1), 1);
+    close(stdout);
+    close(pipe_fd[1]);
+    let mut buf = [0u8; 16];
+    let len = read(pipe_fd[0], &mut buf) as usize;
+    assert_eq!(&buf[..len], b"redirected");
+    println!("dup2 passed!");
+    0
+}
@@ -0,0 +1,54 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{exec, execve, getenv};
+
+/// Runs itself again with a new environment, then once more with the
+/// environment inherited.
+#[no_mangle]
+pub fn main(argc: usize, argv: &[&str]) -> i32 {
+    match argv.get(1) {
+        None => {
+            let args = [
+                "exec_env\0".as_ptr(),
+                "with env\0".as_ptr(),
+                core::ptr::null::<u8>(),
+            ];
+            let envs = [
+                "FOO=bar\0".as_ptr(),
+                "EMPTY=\0".as_ptr(),
+                core::ptr::null::<u8>(),
+            ];
+            execve("exec_env\0", &args, &envs);
+            panic!("execve failed");
+        }
+        Some(&"with env") => {
+            assert_eq!(argc, 2);
+            assert_eq!(getenv("FOO"), Some("bar"));
+            assert_eq!(getenv("EMPTY"), Some(""));
+            assert_eq!(getenv("NONE"), None);
+            // frames keep the alignment the kernel left below the strings
+            let sp: usize;
+            unsafe {
+                core::arch::asm!("mv {}, sp", out(reg) sp);
+            }
+            assert_eq!(sp % 16, 0);
+            let args = [
+                "exec_env\0".as_ptr(),
+                "inherited\0".as_ptr(),
+                core::ptr::null::<u8>(),
+            ];
+            exec("exec_env\0", &args);
+            panic!("exec failed");
+        }
+        Some(&"inherited") => {
+            assert_eq!(getenv("FOO"), Some("bar"));
+            println!("exec_env passed!");
+            0
+        }
+        Some(_) => -1,
+    }
+}
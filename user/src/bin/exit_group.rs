@@ -0,0 +1,48 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{exit, exit_group, fork, sleep, thread_create, waitpid, yield_};
+
+fn spin() -> ! {
+    loop {
+        yield_();
+    }
+}
+
+fn end_all() -> ! {
+    sleep(10);
+    exit_group(42)
+}
+
+fn end_thread() -> ! {
+    exit(1)
+}
+
+#[no_mangle]
+pub fn main() -> i32 {
+    // a thread other than the main one ends the process, while the main
+    // thread and another one never exit on their own
+    let pid = fork();
+    if pid == 0 {
+        thread_create(spin as usize, 0);
+        thread_create(end_all as usize, 0);
+        spin();
+    }
+    let mut exit_code = 0;
+    assert_eq!(waitpid(pid as usize, &mut exit_code), pid);
+    assert_eq!(exit_code, 42);
+    // a plain exit from a thread still ends only that thread
+    let pid = fork();
+    if pid == 0 {
+        thread_create(end_thread as usize, 0);
+        sleep(50);
+        exit(7);
+    }
+    assert_eq!(waitpid(pid as usize, &mut exit_code), pid);
+    assert_eq!(exit_code, 7);
+    println!("exit_group passed!");
+    0
+}
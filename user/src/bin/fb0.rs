@@ -0,0 +1,54 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{
+    close, framebuffer_flush, fstat, mmap_file, munmap, open, OpenFlags, Stat, VIRTGPU_LEN,
+};
+
+const PAGE_SIZE: usize = 4096;
+
+#[no_mangle]
+pub fn main() -> i32 {
+    let fd = open("/dev/fb0", OpenFlags::RDWR);
+    assert!(fd >= 0);
+    let fd = fd as usize;
+    let mut stat = Stat::new();
+    assert_eq!(fstat(fd, &mut stat), 0);
+    assert_eq!(stat.size as usize, VIRTGPU_LEN);
+
+    // not past its end, nor from the middle of a page
+    assert_eq!(mmap_file(0, VIRTGPU_LEN + PAGE_SIZE, 0x3, fd, 0), -1);
+    assert_eq!(mmap_file(0, PAGE_SIZE, 0x3, fd, 1), -1);
+    let fb = mmap_file(0, VIRTGPU_LEN, 0x3, fd, 0);
+    assert!(fb > 0);
+    let fb = fb as usize;
+    // the mapping stays once the fd is closed
+    close(fd);
+    let pixels = unsafe { core::slice::from_raw_parts_mut(fb as *mut u8, VIRTGPU_LEN) };
+    pixels.fill(0x80);
+    assert!(pixels.iter().all(|&byte| byte == 0x80));
+    assert_eq!(framebuffer_flush(), 0);
+
+    // a second mapping from some page on sees the same pixels
+    let fd = open("/dev/fb0", OpenFlags::RDWR) as usize;
+    let tail = mmap_file(0, PAGE_SIZE, 0x1, fd, VIRTGPU_LEN - PAGE_SIZE);
+    assert!(tail > 0);
+    let tail = tail as usize;
+    assert_eq!(unsafe { *(tail as *const u8) }, 0x80);
+    // device memory is only unmapped whole
+    assert_eq!(munmap(fb, PAGE_SIZE), -1);
+    assert_eq!(munmap(fb, VIRTGPU_LEN), 0);
+    assert_eq!(munmap(tail, PAGE_SIZE), 0);
+    close(fd);
+
+    // files without device memory are not mapped
+    let fd = open("/dev/null", OpenFlags::RDWR);
+    assert!(fd >= 0);
+    assert_eq!(mmap_file(0, PAGE_SIZE, 0x1, fd as usize, 0), -1);
+    close(fd as usize);
+    println!("fb0 passed!");
+    0
+}
@@ -4,7 +4,7 @@
 #[macro_use]
 extern crate user_lib;
 
-use user_lib::{close, open, read, write, OpenFlags};
+use user_lib::{close, open, read, sync, write, OpenFlags};
 
 #[no_mangle]
 pub fn main() -> i32 {
@@ -15,6 +15,7 @@ pub fn main() -> i32 {
     let fd = fd as usize;
     write(fd, test_str.as_bytes());
     close(fd);
+    assert_eq!(sync(), 0);
 
     let fd = open(filea, OpenFlags::RDONLY);
     assert!(fd > 0);
@@ -24,6 +25,11 @@ pub fn main() -> i32 {
     close(fd);
 
     assert_eq!(test_str, core::str::from_utf8(&buffer[..read_len]).unwrap(),);
+
+    // missing files are only made with CREATE, unknown flags are refused
+    assert_eq!(open("fileb\0", OpenFlags::RDONLY), -1);
+    let bad_flags = unsafe { OpenFlags::from_bits_unchecked(1 << 20) };
+    assert_eq!(open(filea, bad_flags), -1);
     println!("file_test passed!");
     0
 }
@@ -0,0 +1,43 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{close, fstat, open, pipe, write, OpenFlags, Stat, StatMode};
+
+#[no_mangle]
+pub fn main() -> i32 {
+    let fd = open("fstat_file\0", OpenFlags::CREATE | OpenFlags::WRONLY);
+    assert!(fd > 0);
+    let fd = fd as usize;
+    write(fd, &[b'x'; 600]);
+    let mut stat = Stat::new();
+    assert_eq!(fstat(fd, &mut stat), 0);
+    close(fd);
+    assert_eq!(stat.mode, StatMode::FILE);
+    assert_eq!(stat.nlink, 1);
+    assert_eq!(stat.size, 600);
+
+    let mut pipe_fd = [0usize; 2];
+    pipe(&mut pipe_fd);
+    write(pipe_fd[1], b"hello");
+    assert_eq!(fstat(pipe_fd[0], &mut stat), 0);
+    assert_eq!(stat.mode, StatMode::FIFO);
+    assert_eq!(stat.size, 5);
+    close(pipe_fd[0]);
+    close(pipe_fd[1]);
+
+    assert_eq!(fstat(1, &mut stat), 0);
+    assert_eq!(stat.mode, StatMode::CHR);
+
+    // a Stat lying across a page boundary is filled in whole
+    let mut buf = [0u64; 1024];
+    let page_end = (buf.as_mut_ptr() as usize + 4096) & !4095;
+    let stat = unsafe { &mut *((page_end - 16) as *mut Stat) };
+    assert_eq!(fstat(0, stat), 0);
+    assert_eq!(stat.mode, StatMode::CHR);
+    assert_eq!(stat.nlink, 1);
+    println!("fstat passed!");
+    0
+}
@@ -0,0 +1,41 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{close, getrandom, open, read, write, OpenFlags, GRND_NONBLOCK};
+
+#[no_mangle]
+pub fn main() -> i32 {
+    let mut a = [0u8; 64];
+    let mut b = [0u8; 64];
+    assert_eq!(getrandom(&mut a, 0), 64);
+    assert_eq!(getrandom(&mut b, GRND_NONBLOCK), 64);
+    assert_ne!(a, b);
+    assert!(a.iter().any(|&byte| byte != 0));
+    // flags Linux does not have
+    assert_eq!(getrandom(&mut a, 0x8), -1);
+
+    // past a chunk of the kernel, nearly every byte value turns up
+    let fd = open("/dev/urandom", OpenFlags::RDWR);
+    assert!(fd >= 0);
+    let fd = fd as usize;
+    let mut buf = [0u8; 1000];
+    assert_eq!(read(fd, &mut buf), 1000);
+    let mut seen = [false; 256];
+    for &byte in buf.iter() {
+        seen[byte as usize] = true;
+    }
+    assert!(seen.iter().filter(|&&seen| seen).count() > 200);
+    // writes are taken into the pool
+    assert_eq!(write(fd, b"not so random"), 13);
+    close(fd);
+
+    let fd = open("/dev/random", OpenFlags::RDONLY);
+    assert!(fd >= 0);
+    assert_eq!(read(fd as usize, &mut a), 64);
+    close(fd as usize);
+    println!("getrandom passed!");
+    0
+}
@@ -0,0 +1,48 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{clock_gettime, gettimeofday, sleep, TimeSpec, TimeVal, CLOCK_REALTIME};
+
+/// 2020-01-01T00:00:00Z, well before any real boot of this kernel.
+const YEAR_2020: usize = 1_577_836_800;
+
+/// The UTC calendar date of `secs` since the epoch, as (year, month, day).
+fn civil_date(secs: usize) -> (usize, usize, usize) {
+    // days since 0000-03-01, so that leap days end a year
+    let days = secs / 86400 + 719_468;
+    let era = days / 146_097;
+    let day_of_era = days % 146_097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = era * 400 + year_of_era + (month <= 2) as usize;
+    (year, month, day)
+}
+
+#[no_mangle]
+pub fn main() -> i32 {
+    assert_eq!(civil_date(0), (1970, 1, 1));
+    assert_eq!(civil_date(951_782_400), (2000, 2, 29));
+    let mut tv = TimeVal::default();
+    assert_eq!(gettimeofday(&mut tv), 0);
+    assert!(tv.sec > YEAR_2020);
+    assert!(tv.usec < 1_000_000);
+    let (year, month, day) = civil_date(tv.sec);
+    println!("today is {}-{:02}-{:02} (UTC)", year, month, day);
+    // the same clock as CLOCK_REALTIME
+    let mut ts = TimeSpec::default();
+    assert_eq!(clock_gettime(CLOCK_REALTIME, &mut ts), 0);
+    assert!(ts.sec >= tv.sec && ts.sec - tv.sec <= 1);
+    sleep(100);
+    let mut later = TimeVal::default();
+    assert_eq!(gettimeofday(&mut later), 0);
+    assert!(later.to_us() >= tv.to_us() + 100_000);
+    println!("gettimeofday passed!");
+    0
+}
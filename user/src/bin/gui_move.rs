@@ -5,7 +5,7 @@ extern crate user_lib;
 extern crate alloc;
 
 use user_lib::console::getchar;
-use user_lib::{Display, VIRTGPU_XRES, VIRTGPU_YRES};
+use user_lib::{ioctl, Display, LocalFlags, TCGETS, TCSETS, VIRTGPU_XRES, VIRTGPU_YRES};
 
 use embedded_graphics::pixelcolor::Rgb888;
 use embedded_graphics::prelude::{Drawable, Point, RgbColor, Size};
@@ -61,6 +61,10 @@ pub fn main() -> i32 {
     let mut board = DrawingBoard::new();
     let _ = board.disp.clear(Rgb888::BLACK).unwrap();
     board.disp.flush();
+    // keys are taken as soon as they are pressed, without echo
+    let lflag = ioctl(0, TCGETS, 0) as usize;
+    let raw = LocalFlags::from_bits_truncate(lflag as u32) - LocalFlags::ICANON - LocalFlags::ECHO;
+    ioctl(0, TCSETS, raw.bits() as usize);
     loop {
         let c = getchar();
         if c == LF || c == CR {
@@ -78,5 +82,6 @@ pub fn main() -> i32 {
             board.disp.flush();
         }
     }
+    ioctl(0, TCSETS, lflag);
     0
 }
@@ -5,7 +5,9 @@ extern crate alloc;
 extern crate user_lib;
 
 use user_lib::console::getchar;
-use user_lib::{key_pressed, sleep, Display, VIRTGPU_XRES, VIRTGPU_YRES};
+use user_lib::{
+    ioctl, key_pressed, sleep, Display, LocalFlags, TCGETS, TCSETS, VIRTGPU_XRES, VIRTGPU_YRES,
+};
 
 use embedded_graphics::pixelcolor::*;
 use embedded_graphics::prelude::{Drawable, Point, RgbColor, Size};
@@ -330,6 +332,10 @@ pub fn main() -> i32 {
     let mut disp = Display::new(Size::new(VIRTGPU_XRES, VIRTGPU_YRES));
     let mut game = SnakeGame::<20, Rgb888>::new(1280, 800, 20, 20, Rgb888::RED, Rgb888::YELLOW, 200);
     let _ = disp.clear(Rgb888::BLACK).unwrap();
+    // keys are taken as soon as they are pressed, without echo
+    let lflag = ioctl(0, TCGETS, 0) as usize;
+    let raw = LocalFlags::from_bits_truncate(lflag as u32) - LocalFlags::ICANON - LocalFlags::ECHO;
+    ioctl(0, TCSETS, raw.bits() as usize);
     loop {
         if key_pressed() {
             let c = getchar();
@@ -348,5 +354,6 @@ pub fn main() -> i32 {
         disp.flush();
         sleep(40);
     }
+    ioctl(0, TCSETS, lflag);
     0
 }
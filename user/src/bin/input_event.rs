@@ -0,0 +1,35 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{
+    close, fstat, open, ppoll, read, write, OpenFlags, PollFd, PollFlags, Stat, StatMode, TimeSpec,
+    EAGAIN, INPUT_EVENT_LEN,
+};
+
+/// Nobody types while the tests run, so no event ever comes.
+#[no_mangle]
+pub fn main() -> i32 {
+    for path in ["/dev/input/event0", "/dev/input/event1"] {
+        let fd = open(path, OpenFlags::RDONLY | OpenFlags::NONBLOCK);
+        assert!(fd >= 0);
+        let fd = fd as usize;
+        let mut stat = Stat::new();
+        assert_eq!(fstat(fd, &mut stat), 0);
+        assert_eq!(stat.mode, StatMode::CHR);
+
+        let mut buf = [0u8; INPUT_EVENT_LEN * 4];
+        assert_eq!(read(fd, &mut buf), EAGAIN);
+        // events are read whole, a smaller buffer takes none
+        assert_eq!(read(fd, &mut buf[..INPUT_EVENT_LEN - 1]), 0);
+        assert_eq!(write(fd, &buf), -1);
+        let mut fds = [PollFd::new(fd, PollFlags::IN)];
+        assert_eq!(ppoll(&mut fds, Some(&TimeSpec::default())), 0);
+        close(fd);
+    }
+    assert!(open("/dev/input/event2", OpenFlags::RDONLY) < 0);
+    println!("input_event passed!");
+    0
+}
@@ -1,25 +1,46 @@
 #![no_std]
 #![no_main]
 
-use user_lib::{event_get, DecodeType, Key, KeyType};
+use user_lib::{
+    open, ppoll, read, DecodeType, InputEvent, Key, KeyType, OpenFlags, PollFd, PollFlags,
+    INPUT_EVENT_LEN,
+};
 
 #[macro_use]
 extern crate user_lib;
 
+/// Print what the keyboard and the mouse send until Enter is pressed.
 #[no_mangle]
 pub fn main() -> i32 {
     println!("Input device event test");
+    let keyboard = open("/dev/input/event0", OpenFlags::RDONLY);
+    let mouse = open("/dev/input/event1", OpenFlags::RDONLY);
+    if keyboard < 0 || mouse < 0 {
+        println!("no input devices");
+        return -1;
+    }
+    let mut fds = [
+        PollFd::new(keyboard as usize, PollFlags::IN),
+        PollFd::new(mouse as usize, PollFlags::IN),
+    ];
+    let mut buf = [0u8; INPUT_EVENT_LEN * 16];
     loop {
-        if let Some(event) = event_get() {
-            if let Some(decoder_type) = event.decode() {
-                println!("{:?}", decoder_type);
-                if let DecodeType::Key(key, keytype) = decoder_type {
-                    if key == Key::Enter && keytype == KeyType::Press {
-                        break;
+        if ppoll(&mut fds, None) < 0 {
+            return -1;
+        }
+        for fd in fds.iter().filter(|fd| fd.revents.contains(PollFlags::IN)) {
+            let len = read(fd.fd as usize, &mut buf);
+            for bytes in buf[..len.max(0) as usize].chunks(INPUT_EVENT_LEN) {
+                let event = InputEvent::from_bytes(bytes);
+                if let Some(decoder_type) = event.decode() {
+                    println!("{:?}", decoder_type);
+                    if let DecodeType::Key(key, keytype) = decoder_type {
+                        if key == Key::Enter && keytype == KeyType::Press {
+                            return 0;
+                        }
                     }
                 }
             }
         }
     }
-    0
 }
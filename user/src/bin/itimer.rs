@@ -0,0 +1,78 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use core::sync::atomic::{AtomicUsize, Ordering};
+use user_lib::{
+    alarm, getitimer, setitimer, sigaction, sigreturn, sleep, ITimerVal, SignalAction, SignalFlags,
+    TimeVal, ITIMER_REAL,
+};
+
+static ALARMS: AtomicUsize = AtomicUsize::new(0);
+
+fn handler(signal: usize) {
+    assert_eq!(signal as i32, SignalFlags::SIGALRM.bits());
+    ALARMS.fetch_add(1, Ordering::SeqCst);
+    sigreturn();
+}
+
+#[no_mangle]
+pub fn main() -> i32 {
+    let action = SignalAction {
+        handler: handler as usize,
+        ..Default::default()
+    };
+    assert_eq!(sigaction(SignalFlags::SIGALRM, Some(&action), None), 0);
+    // nothing is armed at first
+    let mut curr = ITimerVal::default();
+    assert_eq!(getitimer(ITIMER_REAL, &mut curr), 0);
+    assert_eq!(curr.value.to_us(), 0);
+    // a periodic timer keeps raising SIGALRM
+    let periodic = ITimerVal {
+        interval: TimeVal::from_ms(20),
+        value: TimeVal::from_ms(20),
+    };
+    assert_eq!(setitimer(ITIMER_REAL, &periodic, None), 0);
+    while ALARMS.load(Ordering::SeqCst) < 3 {
+        sleep(10);
+    }
+    assert_eq!(getitimer(ITIMER_REAL, &mut curr), 0);
+    assert_eq!(curr.interval.to_us(), 20_000);
+    assert!(curr.value.to_us() > 0);
+    // disarming hands back the old setting and stops the signals
+    let mut old = ITimerVal::default();
+    assert_eq!(
+        setitimer(ITIMER_REAL, &ITimerVal::default(), Some(&mut old)),
+        0
+    );
+    assert_eq!(old.interval.to_us(), 20_000);
+    let alarms = ALARMS.load(Ordering::SeqCst);
+    sleep(100);
+    assert_eq!(ALARMS.load(Ordering::SeqCst), alarms);
+    // a one-shot alarm fires once and reports the time it had left
+    assert_eq!(alarm(10), 0);
+    assert_eq!(alarm(0), 10);
+    let one_shot = ITimerVal {
+        interval: TimeVal::default(),
+        value: TimeVal::from_ms(30),
+    };
+    assert_eq!(setitimer(ITIMER_REAL, &one_shot, None), 0);
+    sleep(100);
+    assert_eq!(ALARMS.load(Ordering::SeqCst), alarms + 1);
+    assert_eq!(getitimer(ITIMER_REAL, &mut curr), 0);
+    assert_eq!(curr.value.to_us(), 0);
+    // only ITIMER_REAL exists, and microseconds must be in range
+    assert_eq!(getitimer(1, &mut curr), -1);
+    let bad = ITimerVal {
+        interval: TimeVal::default(),
+        value: TimeVal {
+            sec: 0,
+            usec: 1_000_000,
+        },
+    };
+    assert_eq!(setitimer(ITIMER_REAL, &bad, None), -1);
+    println!("itimer passed!");
+    0
+}
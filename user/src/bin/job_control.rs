@@ -0,0 +1,58 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{
+    exit, fork, getpgid, getpid, killpg, setpgid, setsid, sleep, waitpid, waitpid_options,
+    wifsignaled, wifstopped, wstopsig, wtermsig, SignalFlags, WNOHANG, WUNTRACED,
+};
+
+#[no_mangle]
+pub fn main() -> i32 {
+    let pid = getpid();
+    assert_eq!(setpgid(0, 0), 0);
+    assert_eq!(getpgid(0), pid);
+    // a group leader cannot start a session
+    assert_eq!(setsid(), -1);
+    assert_eq!(setpgid(0, 99999), -1);
+    assert_eq!(killpg(99999, SignalFlags::SIGTERM.bits()), -1);
+
+    let child = fork();
+    if child == 0 {
+        loop {
+            sleep(10);
+        }
+    }
+    assert_eq!(getpgid(child as usize), pid);
+    assert_eq!(setpgid(child as usize, 0), 0);
+    assert_eq!(getpgid(child as usize), child);
+    let mut status = 0;
+    assert_eq!(killpg(child as usize, SignalFlags::SIGSTOP.bits()), 0);
+    assert_eq!(waitpid_options(child, &mut status, WUNTRACED), child);
+    assert!(wifstopped(status) && !wifsignaled(status));
+    assert_eq!(wstopsig(status), 19);
+    // a stop is reported once
+    assert_eq!(waitpid_options(child, &mut status, WUNTRACED | WNOHANG), 0);
+    assert_eq!(killpg(child as usize, SignalFlags::SIGCONT.bits()), 0);
+    assert_eq!(killpg(child as usize, SignalFlags::SIGTERM.bits()), 0);
+    assert_eq!(waitpid_options(child, &mut status, WUNTRACED), child);
+    assert!(wifsignaled(status));
+    assert_eq!(wtermsig(status), 15);
+
+    let child = fork();
+    if child == 0 {
+        let pid = getpid();
+        assert_eq!(setsid(), pid);
+        assert_eq!(getpgid(0), pid);
+        // nor can a session leader leave its group
+        assert_eq!(setpgid(0, 0), -1);
+        exit(0);
+    }
+    let mut exit_code = -1;
+    assert_eq!(waitpid(child as usize, &mut exit_code), child);
+    assert_eq!(exit_code, 0);
+    println!("job_control passed!");
+    0
+}
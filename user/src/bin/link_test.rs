@@ -0,0 +1,54 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{close, fstat, link, open, read, unlink, write, OpenFlags, Stat, StatMode};
+
+fn stat_of(path: &str) -> Stat {
+    let fd = open(path, OpenFlags::RDONLY);
+    assert!(fd > 0);
+    let mut stat = Stat::new();
+    assert_eq!(fstat(fd as usize, &mut stat), 0);
+    close(fd as usize);
+    stat
+}
+
+#[no_mangle]
+pub fn main() -> i32 {
+    let fd = open("link_a\0", OpenFlags::CREATE | OpenFlags::WRONLY);
+    assert!(fd > 0);
+    write(fd as usize, b"linked");
+    close(fd as usize);
+    // the second name is left behind if an earlier run failed
+    unlink("link_b\0");
+
+    let stat = stat_of("link_a\0");
+    assert_eq!(stat.mode, StatMode::FILE);
+    assert_eq!(stat.nlink, 1);
+    assert_eq!(link("link_a\0", "link_b\0"), 0);
+    assert_eq!(link("link_a\0", "link_b\0"), -1);
+    assert_eq!(link("/\0", "link_c\0"), -1);
+    let stat_b = stat_of("link_b\0");
+    assert_eq!(stat_b.ino, stat.ino);
+    assert_eq!(stat_b.nlink, 2);
+
+    assert_eq!(unlink("link_a\0"), 0);
+    assert_eq!(unlink("link_a\0"), -1);
+    assert_eq!(open("link_a\0", OpenFlags::RDONLY), -1);
+    assert_eq!(stat_of("link_b\0").nlink, 1);
+    let fd = open("link_b\0", OpenFlags::RDONLY);
+    let mut buf = [0u8; 16];
+    let len = read(fd as usize, &mut buf) as usize;
+    close(fd as usize);
+    assert_eq!(&buf[..len], b"linked");
+    assert_eq!(unlink("link_b\0"), 0);
+
+    assert_eq!(stat_of("/\0").mode, StatMode::DIR);
+    let mut stat = Stat::new();
+    assert_eq!(fstat(0, &mut stat), -1);
+    assert_eq!(fstat(100, &mut stat), -1);
+    println!("link_test passed!");
+    0
+}
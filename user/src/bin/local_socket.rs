@@ -0,0 +1,95 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{
+    accept, bind, close, connect, exit, fork, listen, read, socket, socketpair, waitpid, write,
+    AF_UNIX, SOCK_STREAM,
+};
+
+const PATH: &str = "/echo.sock";
+const CLIENTS: usize = 4;
+
+fn client(id: u8) -> i32 {
+    let fd = socket(AF_UNIX, SOCK_STREAM, 0);
+    assert!(fd >= 0);
+    let fd = fd as usize;
+    assert_eq!(connect(fd, PATH), 0);
+    let message = [b'a' + id; 8];
+    assert_eq!(write(fd, &message), message.len() as isize);
+    let mut reply = [0u8; 8];
+    assert_eq!(read(fd, &mut reply), reply.len() as isize);
+    assert_eq!(reply, message);
+    // the server is done with us
+    assert_eq!(read(fd, &mut reply), 0);
+    0
+}
+
+#[no_mangle]
+pub fn main() -> i32 {
+    // a pair talks both ways
+    let mut sv = [0usize; 2];
+    assert_eq!(socketpair(AF_UNIX, SOCK_STREAM, 0, &mut sv), 0);
+    let pid = fork();
+    if pid == 0 {
+        close(sv[0]);
+        let mut buf = [0u8; 4];
+        assert_eq!(read(sv[1], &mut buf), 4);
+        assert_eq!(&buf, b"ping");
+        assert_eq!(write(sv[1], b"pong"), 4);
+        exit(0);
+    }
+    close(sv[1]);
+    assert_eq!(write(sv[0], b"ping"), 4);
+    let mut buf = [0u8; 4];
+    assert_eq!(read(sv[0], &mut buf), 4);
+    assert_eq!(&buf, b"pong");
+    let mut exit_code = 0;
+    assert_eq!(waitpid(pid as usize, &mut exit_code), pid);
+    assert_eq!(exit_code, 0);
+    // the peer is gone
+    assert_eq!(read(sv[0], &mut buf), 0);
+    close(sv[0]);
+
+    // a server takes several clients at a path
+    let server = socket(AF_UNIX, SOCK_STREAM, 0);
+    assert!(server >= 0);
+    let server = server as usize;
+    assert_eq!(bind(server, PATH), 0);
+    let other = socket(AF_UNIX, SOCK_STREAM, 0) as usize;
+    assert_eq!(bind(other, PATH), -1);
+    // nobody listens yet
+    assert_eq!(connect(other, PATH), -1);
+    close(other);
+    assert_eq!(listen(server, CLIENTS), 0);
+    let mut pids = [0isize; CLIENTS];
+    for (id, pid) in pids.iter_mut().enumerate() {
+        *pid = fork();
+        if *pid == 0 {
+            exit(client(id as u8));
+        }
+    }
+    for _ in 0..CLIENTS {
+        let conn = accept(server);
+        assert!(conn >= 0);
+        let conn = conn as usize;
+        let mut message = [0u8; 8];
+        assert_eq!(read(conn, &mut message), message.len() as isize);
+        assert!(message.iter().all(|&byte| byte == message[0]));
+        assert_eq!(write(conn, &message), message.len() as isize);
+        close(conn);
+    }
+    for pid in pids {
+        assert_eq!(waitpid(pid as usize, &mut exit_code), pid);
+        assert_eq!(exit_code, 0);
+    }
+    close(server);
+    // the path is free again
+    let server = socket(AF_UNIX, SOCK_STREAM, 0) as usize;
+    assert_eq!(bind(server, PATH), 0);
+    close(server);
+    println!("local_socket passed!");
+    0
+}
@@ -0,0 +1,90 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{
+    accept_in, bind_in, close, connect_in, listen, ppoll, read, recvfrom, sendto, socket, write,
+    PollFd, PollFlags, SockAddrIn, TimeSpec, AF_INET, EINPROGRESS, SOCK_DGRAM, SOCK_NONBLOCK,
+    SOCK_STREAM,
+};
+
+const LOCALHOST: [u8; 4] = [127, 0, 0, 1];
+/// Our address on eth0, which goes through lo as well.
+const LOCAL_IP: [u8; 4] = [10, 0, 2, 15];
+
+/// Everything here stays on lo, so it runs without virtio-net too.
+#[no_mangle]
+pub fn main() -> i32 {
+    // UDP, answered from the address it was sent to
+    let a = socket(AF_INET, SOCK_DGRAM, 0);
+    let b = socket(AF_INET, SOCK_DGRAM, 0);
+    assert!(a >= 0 && b >= 0);
+    let (a, b) = (a as usize, b as usize);
+    assert_eq!(bind_in(a, &SockAddrIn::new(LOCALHOST, 4100)), 0);
+    assert_eq!(bind_in(b, &SockAddrIn::new(LOCALHOST, 4101)), 0);
+    let mut buf = [0u8; 64];
+    let mut sender = SockAddrIn::default();
+    assert_eq!(sendto(a, b"ping", 0, &SockAddrIn::new(LOCALHOST, 4101)), 4);
+    assert_eq!(recvfrom(b, &mut buf, 0, Some(&mut sender)), 4);
+    assert_eq!(&buf[..4], b"ping");
+    assert_eq!((sender.addr(), sender.port()), (LOCALHOST, 4100));
+    assert_eq!(sendto(b, b"pong", 0, &SockAddrIn::new(LOCAL_IP, 4100)), 4);
+    assert_eq!(recvfrom(a, &mut buf, 0, Some(&mut sender)), 4);
+    assert_eq!(&buf[..4], b"pong");
+    assert_eq!((sender.addr(), sender.port()), (LOCAL_IP, 4101));
+    close(a);
+    close(b);
+
+    // TCP, connecting without waiting so one task does both ends
+    let server = socket(AF_INET, SOCK_STREAM, 0);
+    assert!(server >= 0);
+    let server = server as usize;
+    assert_eq!(bind_in(server, &SockAddrIn::new(LOCALHOST, 5100)), 0);
+    assert_eq!(listen(server, 4), 0);
+    let client = socket(AF_INET, SOCK_STREAM | SOCK_NONBLOCK, 0);
+    assert!(client >= 0);
+    let client = client as usize;
+    assert_eq!(
+        connect_in(client, &SockAddrIn::new(LOCALHOST, 5100)),
+        EINPROGRESS
+    );
+    let mut peer = SockAddrIn::default();
+    let conn = accept_in(server, &mut peer);
+    assert!(conn >= 0);
+    let conn = conn as usize;
+    assert_eq!(peer.addr(), LOCALHOST);
+    // the client is connected once the server took it
+    let mut fds = [PollFd::new(client, PollFlags::OUT)];
+    assert_eq!(ppoll(&mut fds, None), 1);
+    assert!(fds[0].revents.contains(PollFlags::OUT));
+
+    assert_eq!(write(client, b"hello"), 5);
+    let mut fds = [PollFd::new(conn, PollFlags::IN)];
+    assert_eq!(ppoll(&mut fds, None), 1);
+    assert_eq!(read(conn, &mut buf), 5);
+    assert_eq!(&buf[..5], b"hello");
+    assert_eq!(write(conn, b"world"), 5);
+    let mut fds = [PollFd::new(client, PollFlags::IN)];
+    assert_eq!(ppoll(&mut fds, None), 1);
+    assert_eq!(read(client, &mut buf), 5);
+    assert_eq!(&buf[..5], b"world");
+    // the server reads the end of the stream once the client closes
+    close(client);
+    assert_eq!(read(conn, &mut buf), 0);
+    close(conn);
+    // nothing else waits
+    let mut fds = [PollFd::new(server, PollFlags::IN)];
+    assert_eq!(ppoll(&mut fds, Some(&TimeSpec::default())), 0);
+    close(server);
+
+    // a port nobody listens on answers with a reset
+    let client = socket(AF_INET, SOCK_STREAM, 0);
+    assert!(client >= 0);
+    let client = client as usize;
+    assert_eq!(connect_in(client, &SockAddrIn::new(LOCAL_IP, 5101)), -1);
+    close(client);
+    println!("loopback passed!");
+    0
+}
@@ -0,0 +1,26 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+extern crate alloc;
+
+use alloc::vec;
+use user_lib::meminfo;
+
+#[no_mangle]
+pub fn main() -> i32 {
+    let mut buf = vec![0u8; 1024];
+    let len = meminfo(&mut buf) as usize;
+    if len > buf.len() {
+        buf.resize(len, 0);
+        meminfo(&mut buf);
+    }
+    let report = core::str::from_utf8(&buf[..len.min(buf.len())]).unwrap();
+    print!("{}", report);
+    assert!(report.starts_with("Frames:"));
+    // every process has page tables, so their call site is listed
+    assert!(report.contains("page_table.rs:"));
+    0
+}
@@ -0,0 +1,45 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{mmap, munmap};
+
+const PAGE_SIZE: usize = 4096;
+// where the kernel starts placing areas, randomized above it
+const MMAP_BASE: usize = 1 << 37;
+
+#[no_mangle]
+pub fn main() -> i32 {
+    let first = mmap(0, 3 * PAGE_SIZE, 0x3);
+    assert!(first > 0);
+    let first = first as usize;
+    assert_eq!(first % PAGE_SIZE, 0);
+    assert!(first >= MMAP_BASE);
+    let second = mmap(0, PAGE_SIZE + 1, 0x3);
+    assert!(second > 0);
+    let second = second as usize;
+    // the two do not overlap, the lowest free place is taken first
+    assert_eq!(second, first + 3 * PAGE_SIZE);
+    for (start, len) in [(first, 3 * PAGE_SIZE), (second, 2 * PAGE_SIZE)] {
+        let bytes = start as *mut u8;
+        for i in (0..len).step_by(PAGE_SIZE / 4) {
+            unsafe {
+                assert_eq!(bytes.add(i).read_volatile(), 0);
+                bytes.add(i).write_volatile(0xa5);
+                assert_eq!(bytes.add(i).read_volatile(), 0xa5);
+            }
+        }
+    }
+    // a freed place is taken again
+    assert_eq!(munmap(first, 3 * PAGE_SIZE), 0);
+    let third = mmap(0, PAGE_SIZE, 0x1);
+    assert_eq!(third as usize, first);
+    assert_eq!(munmap(third as usize, PAGE_SIZE), 0);
+    assert_eq!(munmap(second, 2 * PAGE_SIZE), 0);
+    // placing still checks the permission
+    assert_eq!(mmap(0, PAGE_SIZE, 0x2), -1);
+    println!("mmap_anywhere passed!");
+    0
+}
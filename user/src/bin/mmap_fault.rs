@@ -0,0 +1,21 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{mmap, munmap};
+
+const PAGE_SIZE: usize = 4096;
+const START: usize = 0x1000_0000;
+
+#[no_mangle]
+pub fn main() -> i32 {
+    assert_eq!(mmap(START, PAGE_SIZE, 0x3), 0);
+    assert_eq!(munmap(START, PAGE_SIZE), 0);
+    println!("Access unmapped memory, kernel should kill this application!");
+    unsafe {
+        (START as *mut u8).write_volatile(0);
+    }
+    0
+}
@@ -0,0 +1,41 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{fork, mmap, munmap, waitpid};
+
+const PAGE_SIZE: usize = 4096;
+// twice the physical memory of the board
+const HUGE: usize = 256 * 1024 * 1024;
+
+#[no_mangle]
+pub fn main() -> i32 {
+    // running out of frames fails the call instead of the kernel
+    assert_eq!(mmap(0, HUGE, 0x3), -1);
+    // and leaves nothing behind, memory is still there
+    let start = mmap(0, 4 * PAGE_SIZE, 0x3);
+    assert!(start > 0);
+    let words = start as *mut usize;
+    for i in 0..4 * PAGE_SIZE / core::mem::size_of::<usize>() {
+        unsafe {
+            assert_eq!(words.add(i).read_volatile(), 0);
+            words.add(i).write_volatile(i);
+        }
+    }
+    let pid = fork();
+    if pid == 0 {
+        unsafe {
+            assert_eq!(words.add(7).read_volatile(), 7);
+        }
+        return 0;
+    }
+    assert!(pid > 0);
+    let mut exit_code = 0;
+    assert_eq!(waitpid(pid as usize, &mut exit_code), pid);
+    assert_eq!(exit_code, 0);
+    assert_eq!(munmap(start as usize, 4 * PAGE_SIZE), 0);
+    println!("mmap_oom passed!");
+    0
+}
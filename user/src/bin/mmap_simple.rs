@@ -0,0 +1,54 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{mmap, munmap};
+
+const PAGE_SIZE: usize = 4096;
+const START: usize = 0x1000_0000;
+
+#[no_mangle]
+pub fn main() -> i32 {
+    // R/W, 4 pages
+    assert_eq!(mmap(START, 4 * PAGE_SIZE, 0x3), 0);
+    let words = START as *mut usize;
+    let count = 4 * PAGE_SIZE / core::mem::size_of::<usize>();
+    for i in 0..count {
+        unsafe {
+            assert_eq!(words.add(i).read_volatile(), 0);
+            words.add(i).write_volatile(i);
+        }
+    }
+    for i in 0..count {
+        unsafe {
+            assert_eq!(words.add(i).read_volatile(), i);
+        }
+    }
+    // misaligned, bad permission, W without R and overlaps are rejected
+    assert_eq!(mmap(START + 1, PAGE_SIZE, 0x3), -1);
+    assert_eq!(mmap(START + 8 * PAGE_SIZE, PAGE_SIZE, 0x8), -1);
+    assert_eq!(mmap(START + 8 * PAGE_SIZE, PAGE_SIZE, 0), -1);
+    assert_eq!(mmap(START + 8 * PAGE_SIZE, PAGE_SIZE, 0x2), -1);
+    assert_eq!(mmap(START + 3 * PAGE_SIZE, 2 * PAGE_SIZE, 0x3), -1);
+    // punch a hole into the middle, the rest stays usable
+    assert_eq!(munmap(START + PAGE_SIZE, PAGE_SIZE), 0);
+    assert_eq!(munmap(START + PAGE_SIZE, PAGE_SIZE), -1);
+    unsafe {
+        assert_eq!(words.read_volatile(), 0);
+        let last = START + 3 * PAGE_SIZE;
+        assert_eq!(
+            (last as *const usize).read_volatile(),
+            3 * PAGE_SIZE / core::mem::size_of::<usize>()
+        );
+    }
+    // the hole can be mapped again and comes back zeroed
+    assert_eq!(mmap(START + PAGE_SIZE, PAGE_SIZE, 0x1), 0);
+    unsafe {
+        assert_eq!(((START + PAGE_SIZE) as *const usize).read_volatile(), 0);
+    }
+    assert_eq!(munmap(START, 4 * PAGE_SIZE), 0);
+    println!("mmap_simple passed!");
+    0
+}
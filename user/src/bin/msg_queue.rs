@@ -0,0 +1,107 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{
+    exit, fork, msgget, msgrcv, msgsnd, sleep, waitpid, MsgBuf, IPC_CREAT, IPC_EXCL, IPC_NOWAIT,
+    IPC_PRIVATE, MSG_NOERROR,
+};
+
+const KEY: usize = 0x4d53;
+/// what a queue holds at most
+const QUEUE_BYTES: usize = 16384;
+const CHUNK: usize = 1024;
+
+fn send(msqid: usize, mtype: usize, text: &[u8]) {
+    let mut msg = MsgBuf::<16>::new(mtype);
+    msg.text[..text.len()].copy_from_slice(text);
+    assert_eq!(msgsnd(msqid, &msg, text.len(), 0), 0);
+}
+
+fn receive(msqid: usize, msgtyp: isize, mtype: usize, text: &[u8]) {
+    let mut msg = MsgBuf::<16>::new(0);
+    assert_eq!(msgrcv(msqid, &mut msg, msgtyp, 0), text.len() as isize);
+    assert_eq!(msg.mtype, mtype);
+    assert_eq!(&msg.text[..text.len()], text);
+}
+
+#[no_mangle]
+pub fn main() -> i32 {
+    let msqid = msgget(IPC_PRIVATE, IPC_CREAT);
+    assert!(msqid >= 0);
+    let msqid = msqid as usize;
+    let mut msg = MsgBuf::<16>::new(0);
+    assert_eq!(msgrcv(msqid, &mut msg, 0, IPC_NOWAIT), -1);
+    // the type must be positive
+    assert_eq!(msgsnd(msqid, &msg, 0, 0), -1);
+
+    // selective receive
+    send(msqid, 3, b"three");
+    send(msqid, 1, b"one");
+    send(msqid, 2, b"two");
+    send(msqid, 1, b"uno");
+    receive(msqid, 2, 2, b"two");
+    assert_eq!(msgrcv(msqid, &mut msg, 2, IPC_NOWAIT), -1);
+    receive(msqid, -3, 1, b"one");
+    receive(msqid, 0, 3, b"three");
+    receive(msqid, -1, 1, b"uno");
+
+    // a message too long for the buffer stays queued unless cut short
+    send(msqid, 5, b"too long for four");
+    let mut short = MsgBuf::<4>::new(0);
+    assert_eq!(msgrcv(msqid, &mut short, 0, 0), -1);
+    assert_eq!(msgrcv(msqid, &mut short, 0, MSG_NOERROR), 4);
+    assert_eq!(&short.text, b"too ");
+
+    // a receiver waits for a message of its type
+    let pid = fork();
+    if pid == 0 {
+        receive(msqid, 7, 7, b"late");
+        exit(0);
+    }
+    sleep(50);
+    send(msqid, 6, b"other");
+    send(msqid, 7, b"late");
+    let mut exit_code = 0;
+    assert_eq!(waitpid(pid as usize, &mut exit_code), pid);
+    assert_eq!(exit_code, 0);
+    receive(msqid, 0, 6, b"other");
+
+    // a sender waits for room
+    let mut chunk = MsgBuf::<CHUNK>::new(8);
+    for _ in 0..QUEUE_BYTES / CHUNK {
+        assert_eq!(msgsnd(msqid, &chunk, CHUNK, IPC_NOWAIT), 0);
+    }
+    assert_eq!(msgsnd(msqid, &chunk, CHUNK, IPC_NOWAIT), -1);
+    let pid = fork();
+    if pid == 0 {
+        chunk.mtype = 9;
+        assert_eq!(msgsnd(msqid, &chunk, CHUNK, 0), 0);
+        exit(0);
+    }
+    sleep(50);
+    assert_eq!(msgrcv(msqid, &mut chunk, 8, 0), CHUNK as isize);
+    assert_eq!(waitpid(pid as usize, &mut exit_code), pid);
+    assert_eq!(exit_code, 0);
+    assert_eq!(msgrcv(msqid, &mut chunk, 9, IPC_NOWAIT), CHUNK as isize);
+
+    // a keyed queue is found by a process that did not get it before
+    assert_eq!(msgget(KEY, 0), -1);
+    let msqid = msgget(KEY, IPC_CREAT);
+    assert!(msqid >= 0);
+    assert_eq!(msgget(KEY, IPC_CREAT | IPC_EXCL), -1);
+    let pid = fork();
+    if pid == 0 {
+        let msqid = msgget(KEY, 0);
+        assert!(msqid >= 0);
+        send(msqid as usize, 1, b"keyed");
+        exit(0);
+    }
+    receive(msqid as usize, 1, 1, b"keyed");
+    assert_eq!(waitpid(pid as usize, &mut exit_code), pid);
+    assert_eq!(exit_code, 0);
+    println!("msg_queue passed!");
+    0
+}
@@ -0,0 +1,21 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{mutex_blocking_create, mutex_create, mutex_lock, mutex_unlock};
+
+#[no_mangle]
+pub fn main() -> i32 {
+    for mutex_id in [mutex_create(), mutex_blocking_create()] {
+        let mutex_id = mutex_id as usize;
+        assert_eq!(mutex_unlock(mutex_id), -1);
+        mutex_lock(mutex_id);
+        assert_eq!(mutex_unlock(mutex_id), 0);
+        assert_eq!(mutex_unlock(mutex_id), -1);
+    }
+    assert_eq!(mutex_unlock(100), -1);
+    println!("mutex_unlock passed!");
+    0
+}
@@ -0,0 +1,112 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{
+    accept, bind, close, connect, eventfd, exit, fcntl, fork, listen, pipe, ppoll, read, socket,
+    waitpid, write, OpenFlags, PollFd, PollFlags, AF_UNIX, EAGAIN, EFD_NONBLOCK, F_GETFL, F_SETFL,
+    SOCK_NONBLOCK, SOCK_STREAM,
+};
+
+const PATH: &str = "/nonblock.sock";
+
+#[no_mangle]
+pub fn main() -> i32 {
+    // an empty pipe does not make a nonblocking reader wait
+    let mut pipe_fds = [0usize; 2];
+    assert_eq!(pipe(&mut pipe_fds), 0);
+    let (rfd, wfd) = (pipe_fds[0], pipe_fds[1]);
+    assert_eq!(fcntl(rfd, F_GETFL, 0), OpenFlags::RDONLY.bits() as isize);
+    assert_eq!(fcntl(rfd, F_SETFL, OpenFlags::NONBLOCK.bits() as usize), 0);
+    assert_eq!(
+        fcntl(rfd, F_GETFL, 0),
+        (OpenFlags::RDONLY | OpenFlags::NONBLOCK).bits() as isize
+    );
+    let mut buf = [0u8; 64];
+    assert_eq!(read(rfd, &mut buf), EAGAIN);
+    assert_eq!(write(wfd, b"hello"), 5);
+    assert_eq!(read(rfd, &mut buf), 5);
+    assert_eq!(&buf[..5], b"hello");
+    assert_eq!(read(rfd, &mut buf), EAGAIN);
+
+    // a full pipe takes what fits, then nothing
+    assert_eq!(fcntl(wfd, F_SETFL, OpenFlags::NONBLOCK.bits() as usize), 0);
+    let mut written = 0;
+    loop {
+        match write(wfd, &buf) {
+            EAGAIN => break,
+            len => {
+                assert!(len > 0);
+                written += len;
+            }
+        }
+    }
+    // poll tells when there is room again
+    let mut fds = [PollFd::new(wfd, PollFlags::OUT)];
+    let pid = fork();
+    if pid == 0 {
+        close(wfd);
+        let mut drained = 0;
+        while drained < written {
+            let len = read(rfd, &mut buf);
+            if len != EAGAIN {
+                drained += len;
+            }
+        }
+        exit(0);
+    }
+    assert_eq!(ppoll(&mut fds, None), 1);
+    assert_eq!(fds[0].revents, PollFlags::OUT);
+    let mut exit_code = 0;
+    assert_eq!(waitpid(pid as usize, &mut exit_code), pid);
+    assert_eq!(exit_code, 0);
+    // back to blocking, and a closed writer still ends the reads
+    assert_eq!(fcntl(rfd, F_SETFL, 0), 0);
+    assert_eq!(fcntl(rfd, F_GETFL, 0), OpenFlags::RDONLY.bits() as isize);
+    close(wfd);
+    assert_eq!(read(rfd, &mut buf), 0);
+    close(rfd);
+
+    // an eventfd with no count
+    let efd = eventfd(0, EFD_NONBLOCK);
+    assert!(efd >= 0);
+    let efd = efd as usize;
+    let mut count = [0u8; 8];
+    assert_eq!(read(efd, &mut count), EAGAIN);
+    assert_eq!(write(efd, &2u64.to_ne_bytes()), 8);
+    assert_eq!(read(efd, &mut count), 8);
+    assert_eq!(u64::from_ne_bytes(count), 2);
+    assert_eq!(
+        fcntl(efd, F_GETFL, 0),
+        (OpenFlags::RDWR | OpenFlags::NONBLOCK).bits() as isize
+    );
+    close(efd);
+
+    // a listening socket nobody connects to yet
+    let server = socket(AF_UNIX, SOCK_STREAM | SOCK_NONBLOCK, 0);
+    assert!(server >= 0);
+    let server = server as usize;
+    assert_eq!(bind(server, PATH), 0);
+    assert_eq!(listen(server, 1), 0);
+    assert_eq!(accept(server), EAGAIN);
+    let client = socket(AF_UNIX, SOCK_STREAM, 0) as usize;
+    assert_eq!(connect(client, PATH), 0);
+    let conn = accept(server);
+    assert!(conn >= 0);
+    let conn = conn as usize;
+    // the accepted socket blocks unless told otherwise
+    assert_eq!(fcntl(conn, F_GETFL, 0), OpenFlags::RDWR.bits() as isize);
+    assert_eq!(fcntl(conn, F_SETFL, OpenFlags::NONBLOCK.bits() as usize), 0);
+    assert_eq!(read(conn, &mut buf), EAGAIN);
+    assert_eq!(write(client, b"x"), 1);
+    assert_eq!(read(conn, &mut buf), 1);
+    close(conn);
+    close(client);
+    close(server);
+
+    assert_eq!(fcntl(server, F_GETFL, 0), -1);
+    println!("nonblock passed!");
+    0
+}
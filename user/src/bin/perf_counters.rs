@@ -0,0 +1,92 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{
+    close, exit, fork, ioctl, perf_event_open, perf_event_read, pipe, read, waitpid, write, yield_,
+    PERF_COUNT_HW_CPU_CYCLES, PERF_COUNT_HW_INSTRUCTIONS, PERF_EVENT_IOC_DISABLE,
+    PERF_EVENT_IOC_ENABLE, PERF_EVENT_IOC_RESET,
+};
+
+const ROUNDS: usize = 100_000;
+
+/// Spin for `ROUNDS` rounds, yielding now and then so that the counts
+/// have to add up across context switches.
+fn spin() -> usize {
+    let mut sum = 0usize;
+    for i in 0..ROUNDS {
+        sum = core::hint::black_box(sum.wrapping_add(i));
+        if i % (ROUNDS / 8) == 0 {
+            yield_();
+        }
+    }
+    sum
+}
+
+#[no_mangle]
+pub fn main() -> i32 {
+    assert_eq!(perf_event_open(usize::MAX, 0), -1);
+    assert_eq!(perf_event_open(PERF_COUNT_HW_INSTRUCTIONS, usize::MAX), -1);
+
+    let instructions = perf_event_open(PERF_COUNT_HW_INSTRUCTIONS, 0);
+    let cycles = perf_event_open(PERF_COUNT_HW_CPU_CYCLES, 0);
+    assert!(instructions >= 0 && cycles >= 0);
+    let (instructions, cycles) = (instructions as usize, cycles as usize);
+    // nothing is counted before it is enabled
+    spin();
+    assert_eq!(perf_event_read(instructions), 0);
+    assert_eq!(ioctl(instructions, 0, 0), -1);
+
+    assert_eq!(ioctl(instructions, PERF_EVENT_IOC_ENABLE, 0), 0);
+    assert_eq!(ioctl(cycles, PERF_EVENT_IOC_ENABLE, 0), 0);
+    spin();
+    assert_eq!(ioctl(instructions, PERF_EVENT_IOC_DISABLE, 0), 0);
+    assert_eq!(ioctl(cycles, PERF_EVENT_IOC_DISABLE, 0), 0);
+    let counted = perf_event_read(instructions);
+    println!(
+        "{} instructions, {} cycles for {} rounds",
+        counted,
+        perf_event_read(cycles),
+        ROUNDS
+    );
+    // every round takes a few instructions
+    assert!(counted >= ROUNDS as i64);
+    assert!(perf_event_read(cycles) > 0);
+    // a disabled counter stands still
+    spin();
+    assert_eq!(perf_event_read(instructions), counted);
+    assert_eq!(ioctl(instructions, PERF_EVENT_IOC_RESET, 0), 0);
+    assert_eq!(perf_event_read(instructions), 0);
+
+    // another process counts too, until it is reaped
+    let mut go = [0usize; 2];
+    let mut done = [0usize; 2];
+    pipe(&mut go);
+    pipe(&mut done);
+    let pid = fork();
+    if pid == 0 {
+        let mut buf = [0u8; 1];
+        assert_eq!(read(go[0], &mut buf), 1);
+        spin();
+        write(done[1], b"!");
+        exit(0);
+    }
+    let child = perf_event_open(PERF_COUNT_HW_INSTRUCTIONS, pid as usize);
+    assert!(child >= 0);
+    assert_eq!(ioctl(child as usize, PERF_EVENT_IOC_ENABLE, 0), 0);
+    write(go[1], b"!");
+    let mut buf = [0u8; 1];
+    assert_eq!(read(done[0], &mut buf), 1);
+    assert_eq!(ioctl(child as usize, PERF_EVENT_IOC_DISABLE, 0), 0);
+    assert!(perf_event_read(child as usize) >= ROUNDS as i64);
+    let mut exit_code = 0;
+    assert_eq!(waitpid(pid as usize, &mut exit_code), pid);
+    assert_eq!(exit_code, 0);
+    close(child as usize);
+    close(instructions);
+    close(cycles);
+    println!("perf_counters passed!");
+    0
+}
@@ -0,0 +1,49 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{
+    exit, fork, getpid, pidfd_open, pidfd_send_signal, sleep, waitpid_options, wexitstatus,
+    wifsignaled, wtermsig, SignalFlags,
+};
+
+#[no_mangle]
+pub fn main() -> i32 {
+    let mut status = 0;
+    assert_eq!(pidfd_open(usize::MAX), -1);
+    assert!(pidfd_open(getpid() as usize) >= 0);
+
+    let pid = fork();
+    if pid == 0 {
+        sleep(1000);
+        exit(0);
+    }
+    let pidfd = pidfd_open(pid as usize);
+    assert!(pidfd >= 0);
+    let pidfd = pidfd as usize;
+    assert_eq!(pidfd_send_signal(pidfd, SignalFlags::SIGKILL.bits()), 0);
+    assert_eq!(waitpid_options(pid, &mut status, 0), pid);
+    assert!(wifsignaled(status));
+    assert_eq!(wtermsig(status), 9);
+    // reaped, whatever gets its pid next
+    assert_eq!(pidfd_send_signal(pidfd, SignalFlags::SIGKILL.bits()), -1);
+
+    // an exited child not reaped yet takes no signals either
+    let pid = fork();
+    if pid == 0 {
+        exit(7);
+    }
+    let pidfd = pidfd_open(pid as usize);
+    assert!(pidfd >= 0);
+    sleep(50);
+    assert_eq!(
+        pidfd_send_signal(pidfd as usize, SignalFlags::SIGUSR1.bits()),
+        -1
+    );
+    assert_eq!(waitpid_options(pid, &mut status, 0), pid);
+    assert_eq!(wexitstatus(status), 7);
+    println!("pidfd passed!");
+    0
+}
@@ -0,0 +1,44 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{
+    close, exit, fork, pipe, read, sigaction, waitpid, write, SignalAction, SignalFlags, SIG_IGN,
+};
+
+#[no_mangle]
+pub fn main() -> i32 {
+    // end of file once the write end is gone
+    let mut pipe_fd = [0usize; 2];
+    pipe(&mut pipe_fd);
+    assert_eq!(write(pipe_fd[1], b"hi"), 2);
+    close(pipe_fd[1]);
+    let mut buf = [0u8; 4];
+    assert_eq!(read(pipe_fd[0], &mut buf), 2);
+    assert_eq!(read(pipe_fd[0], &mut buf), 0);
+    close(pipe_fd[0]);
+
+    // writing without readers kills the writer
+    pipe(&mut pipe_fd);
+    close(pipe_fd[0]);
+    let pid = fork();
+    if pid == 0 {
+        write(pipe_fd[1], b"lost");
+        exit(0);
+    }
+    let mut exit_code = 0;
+    assert_eq!(waitpid(pid as usize, &mut exit_code), pid);
+    assert_eq!(exit_code, -13);
+
+    // unless SIGPIPE is ignored
+    let ignore = SignalAction {
+        handler: SIG_IGN,
+        ..Default::default()
+    };
+    assert_eq!(sigaction(SignalFlags::SIGPIPE, Some(&ignore), None), 0);
+    assert_eq!(write(pipe_fd[1], b"lost"), 0);
+    println!("pipe_closed passed!");
+    0
+}
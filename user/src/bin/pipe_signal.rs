@@ -0,0 +1,40 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{close, exit, fork, kill, pipe, read, sleep, waitpid, write, SignalFlags};
+
+#[no_mangle]
+pub fn main() -> i32 {
+    let mut pipe_fd = [0usize; 2];
+    pipe(&mut pipe_fd);
+    // a reader blocked on an empty pipe still gets its signals
+    let pid = fork();
+    if pid == 0 {
+        close(pipe_fd[1]);
+        let mut buf = [0u8; 4];
+        read(pipe_fd[0], &mut buf);
+        exit(0);
+    }
+    sleep(20);
+    assert_eq!(kill(pid as usize, SignalFlags::SIGUSR1.bits()), 0);
+    let mut exit_code = 0;
+    assert_eq!(waitpid(pid as usize, &mut exit_code), pid);
+    assert_eq!(exit_code, -10);
+    // and so does a writer blocked on a full one
+    let pid = fork();
+    if pid == 0 {
+        close(pipe_fd[0]);
+        loop {
+            write(pipe_fd[1], &[0u8; 64]);
+        }
+    }
+    sleep(20);
+    assert_eq!(kill(pid as usize, SignalFlags::SIGUSR1.bits()), 0);
+    assert_eq!(waitpid(pid as usize, &mut exit_code), pid);
+    assert_eq!(exit_code, -10);
+    println!("pipe_signal passed!");
+    0
+}
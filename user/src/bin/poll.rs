@@ -0,0 +1,107 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{
+    close, eventfd, exit, fork, get_time, pipe, ppoll, pselect, read, sleep, socketpair, waitpid,
+    write, FdSet, PollFd, PollFlags, TimeSpec, AF_UNIX, EFD_SEMAPHORE, SOCK_STREAM,
+};
+
+fn read_count(fd: usize) -> u64 {
+    let mut buf = [0u8; 8];
+    assert_eq!(read(fd, &mut buf), 8);
+    u64::from_ne_bytes(buf)
+}
+
+fn add_count(fd: usize, count: u64) {
+    assert_eq!(write(fd, &count.to_ne_bytes()), 8);
+}
+
+#[no_mangle]
+pub fn main() -> i32 {
+    let now = TimeSpec::default();
+
+    // an eventfd is readable while its count is not 0
+    let efd = eventfd(0, 0);
+    assert!(efd >= 0);
+    let efd = efd as usize;
+    let mut fds = [PollFd::new(efd, PollFlags::IN | PollFlags::OUT)];
+    assert_eq!(ppoll(&mut fds, Some(&now)), 1);
+    assert_eq!(fds[0].revents, PollFlags::OUT);
+    fds[0].events = PollFlags::IN;
+    assert_eq!(ppoll(&mut fds, Some(&now)), 0);
+    add_count(efd, 2);
+    add_count(efd, 1);
+    assert_eq!(ppoll(&mut fds, Some(&now)), 1);
+    assert_eq!(fds[0].revents, PollFlags::IN);
+    assert_eq!(read_count(efd), 3);
+    let sem = eventfd(2, EFD_SEMAPHORE) as usize;
+    assert_eq!(read_count(sem), 1);
+    assert_eq!(read_count(sem), 1);
+    let mut sem_fds = [PollFd::new(sem, PollFlags::IN)];
+    assert_eq!(ppoll(&mut sem_fds, Some(&now)), 0);
+    close(sem);
+
+    // a timeout passes when nothing gets ready
+    let start = get_time();
+    assert_eq!(ppoll(&mut fds, Some(&TimeSpec::from_ms(30))), 0);
+    assert!(get_time() - start >= 30);
+
+    // whichever of a pipe, a socket and an eventfd gets ready wakes us
+    let mut pipe_fds = [0usize; 2];
+    assert_eq!(pipe(&mut pipe_fds), 0);
+    let mut sv = [0usize; 2];
+    assert_eq!(socketpair(AF_UNIX, SOCK_STREAM, 0, &mut sv), 0);
+    let pid = fork();
+    if pid == 0 {
+        sleep(30);
+        assert_eq!(write(sv[1], b"x"), 1);
+        exit(0);
+    }
+    close(sv[1]);
+    let mut fds = [
+        PollFd::new(pipe_fds[0], PollFlags::IN),
+        PollFd::new(sv[0], PollFlags::IN),
+        PollFd::new(efd, PollFlags::IN),
+    ];
+    assert_eq!(ppoll(&mut fds, None), 1);
+    assert!(fds[0].revents.is_empty());
+    assert!(fds[1].revents.contains(PollFlags::IN));
+    assert!(fds[2].revents.is_empty());
+    let mut exit_code = 0;
+    assert_eq!(waitpid(pid as usize, &mut exit_code), pid);
+    assert_eq!(exit_code, 0);
+    // the peer exited after writing
+    assert_eq!(ppoll(&mut fds[1..2], Some(&now)), 1);
+    assert_eq!(fds[1].revents, PollFlags::IN | PollFlags::HUP);
+
+    // select takes sets of fds instead
+    let mut readfds = FdSet::new();
+    readfds.insert(pipe_fds[0]);
+    readfds.insert(efd);
+    let mut writefds = FdSet::new();
+    writefds.insert(pipe_fds[1]);
+    let nfds = pipe_fds[0].max(pipe_fds[1]).max(efd) + 1;
+    add_count(efd, 1);
+    assert_eq!(
+        pselect(nfds, Some(&mut readfds), Some(&mut writefds), None),
+        2
+    );
+    assert!(!readfds.contains(pipe_fds[0]));
+    assert!(readfds.contains(efd));
+    assert!(writefds.contains(pipe_fds[1]));
+
+    // a closed write end hangs the read end up, and closed fds are invalid
+    close(pipe_fds[1]);
+    let mut fds = [
+        PollFd::new(pipe_fds[0], PollFlags::IN),
+        PollFd::new(pipe_fds[1], PollFlags::IN),
+    ];
+    assert_eq!(ppoll(&mut fds, None), 2);
+    assert_eq!(fds[0].revents, PollFlags::HUP);
+    assert_eq!(fds[1].revents, PollFlags::NVAL);
+    println!("poll passed!");
+    0
+}
@@ -0,0 +1,65 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{
+    sleep, timer_create, timer_delete, timer_getoverrun, timer_gettime, timer_settime, ITimerSpec,
+    SigEvent, TimeSpec, CLOCK_MONOTONIC, SIGEV_NONE,
+};
+
+#[no_mangle]
+pub fn main() -> i32 {
+    let sev = SigEvent {
+        notify: SIGEV_NONE,
+        signal: 0,
+        tid: 0,
+    };
+    let timer = timer_create(CLOCK_MONOTONIC, Some(&sev));
+    assert!(timer >= 0);
+    let timer = timer as usize;
+    // a one-shot timer counts down and then disarms itself
+    let mut spec = ITimerSpec {
+        interval: TimeSpec::default(),
+        value: TimeSpec::from_ms(50),
+    };
+    assert_eq!(timer_settime(timer, 0, &spec, None), 0);
+    let mut curr = ITimerSpec::default();
+    assert_eq!(timer_gettime(timer, &mut curr), 0);
+    let left = curr.value.to_ms();
+    assert!(left > 0 && left <= 50);
+    sleep(100);
+    assert_eq!(timer_gettime(timer, &mut curr), 0);
+    assert_eq!(curr.value.to_ms(), 0);
+    // nobody consumes the expirations of a SIGEV_NONE periodic timer,
+    // so they all end up in the overrun count
+    spec.interval = TimeSpec::from_ms(20);
+    spec.value = TimeSpec::from_ms(20);
+    let mut old = ITimerSpec::default();
+    assert_eq!(timer_settime(timer, 0, &spec, Some(&mut old)), 0);
+    assert_eq!(old.value.to_ms(), 0);
+    sleep(200);
+    let overrun = timer_getoverrun(timer);
+    println!("periodic timer overrun = {}", overrun);
+    assert!(overrun >= 5);
+    assert_eq!(timer_gettime(timer, &mut curr), 0);
+    assert_eq!(curr.interval.to_ms(), 20);
+    assert!(curr.value.to_ms() > 0);
+    // disarming hands back the setting it replaces
+    assert_eq!(
+        timer_settime(timer, 0, &ITimerSpec::default(), Some(&mut old)),
+        0
+    );
+    assert_eq!(old.interval.to_ms(), 20);
+    assert!(old.value.to_ms() > 0);
+    assert_eq!(timer_gettime(timer, &mut curr), 0);
+    assert_eq!(curr.value.to_ms(), 0);
+    // stale ids are rejected
+    assert_eq!(timer_delete(timer), 0);
+    assert_eq!(timer_delete(timer), -1);
+    assert_eq!(timer_gettime(timer, &mut curr), -1);
+    assert_eq!(timer_create(42, None), -1);
+    println!("posix_timer passed!");
+    0
+}
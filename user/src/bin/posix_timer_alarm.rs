@@ -0,0 +1,27 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{
+    get_time, timer_create, timer_settime, yield_, ITimerSpec, TimeSpec, CLOCK_REALTIME,
+};
+
+/// The default SIGALRM action terminates the process.
+#[no_mangle]
+pub fn main() -> i32 {
+    let timer = timer_create(CLOCK_REALTIME, None);
+    assert!(timer >= 0);
+    let spec = ITimerSpec {
+        interval: TimeSpec::default(),
+        value: TimeSpec::from_ms(50),
+    };
+    assert_eq!(timer_settime(timer as usize, 0, &spec, None), 0);
+    let start = get_time();
+    while get_time() - start < 1000 {
+        yield_();
+    }
+    println!("should have been killed by SIGALRM!");
+    0
+}
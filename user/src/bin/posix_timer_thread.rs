@@ -0,0 +1,66 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use core::sync::atomic::{AtomicIsize, Ordering};
+use user_lib::{
+    exit, get_time, gettid, sigaction, sigreturn, thread_create, timer_create, timer_settime,
+    waittid, yield_, ITimerSpec, SigEvent, SignalAction, SignalFlags, TimeSpec, CLOCK_MONOTONIC,
+    SIGEV_THREAD_ID,
+};
+
+/// The thread that ran the handler, -1 until one did.
+static HANDLED_BY: AtomicIsize = AtomicIsize::new(-1);
+
+fn handler(signal: usize) {
+    assert_eq!(signal as i32, SignalFlags::SIGUSR1.bits());
+    HANDLED_BY.store(gettid(), Ordering::SeqCst);
+    sigreturn();
+}
+
+/// Both threads keep returning to user mode, so either could take a
+/// signal for the whole process.
+fn spin_until_handled() {
+    let start = get_time();
+    while HANDLED_BY.load(Ordering::SeqCst) < 0 {
+        assert!(get_time() - start < 1000, "the timer signal never came");
+        yield_();
+    }
+}
+
+fn worker() -> ! {
+    spin_until_handled();
+    exit(0)
+}
+
+#[no_mangle]
+pub fn main() -> i32 {
+    let action = SignalAction {
+        handler: handler as usize,
+        ..Default::default()
+    };
+    assert_eq!(sigaction(SignalFlags::SIGUSR1, Some(&action), None), 0);
+    let tid = thread_create(worker as usize, 0);
+    assert!(tid > 0);
+    let mut sev = SigEvent {
+        notify: SIGEV_THREAD_ID,
+        signal: SignalFlags::SIGUSR1.bits() as u32,
+        tid: 1000,
+    };
+    assert_eq!(timer_create(CLOCK_MONOTONIC, Some(&sev)), -1);
+    sev.tid = tid as usize;
+    let timer = timer_create(CLOCK_MONOTONIC, Some(&sev));
+    assert!(timer >= 0);
+    let spec = ITimerSpec {
+        interval: TimeSpec::default(),
+        value: TimeSpec::from_ms(20),
+    };
+    assert_eq!(timer_settime(timer as usize, 0, &spec, None), 0);
+    spin_until_handled();
+    assert_eq!(HANDLED_BY.load(Ordering::SeqCst), tid);
+    assert_eq!(waittid(tid as usize), 0);
+    println!("posix_timer_thread passed!");
+    0
+}
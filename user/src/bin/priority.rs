@@ -0,0 +1,76 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{
+    exit, fork, get_time, getpriority, pipe, read, sched_setaffinity, setpriority, waitpid, write,
+    DEFAULT_PRIORITY, MAX_PRIORITY, MIN_PRIORITY,
+};
+
+const RUN_MS: isize = 400;
+
+/// Wait for the go byte on `start`, spin for `RUN_MS` and report how
+/// many rounds we got through on `done`.
+fn child(start: usize, done: usize) -> ! {
+    let mut buf = [0u8; 1];
+    assert_eq!(read(start, &mut buf), 1);
+    let deadline = get_time() + RUN_MS;
+    let mut rounds = 0usize;
+    while get_time() < deadline {
+        rounds += 1;
+    }
+    write(done, &rounds.to_ne_bytes());
+    exit(0)
+}
+
+fn read_rounds(fd: usize) -> usize {
+    let mut buf = [0u8; core::mem::size_of::<usize>()];
+    assert_eq!(read(fd, &mut buf), buf.len() as isize);
+    usize::from_ne_bytes(buf)
+}
+
+#[no_mangle]
+pub fn main() -> i32 {
+    assert_eq!(getpriority(0), DEFAULT_PRIORITY as isize);
+    assert_eq!(setpriority(0, MIN_PRIORITY - 1), -1);
+    assert_eq!(setpriority(0, MAX_PRIORITY + 1), -1);
+    assert_eq!(getpriority(usize::MAX), -1);
+    // the children only compete for time when they share a hart
+    assert_eq!(sched_setaffinity(0, 1), 0);
+
+    let mut start = [0usize; 2];
+    let mut done_low = [0usize; 2];
+    let mut done_high = [0usize; 2];
+    pipe(&mut start);
+    pipe(&mut done_low);
+    pipe(&mut done_high);
+    // children start out with the priority of their parent
+    assert_eq!(setpriority(0, MAX_PRIORITY), 0);
+    let low = fork();
+    if low == 0 {
+        child(start[0], done_low[1]);
+    }
+    assert_eq!(getpriority(low as usize), MAX_PRIORITY as isize);
+    assert_eq!(setpriority(low as usize, MIN_PRIORITY * 2), 0);
+    let high = fork();
+    if high == 0 {
+        child(start[0], done_high[1]);
+    }
+    assert_eq!(setpriority(high as usize, MIN_PRIORITY * 8), 0);
+    assert_eq!(getpriority(high as usize), (MIN_PRIORITY * 8) as isize);
+    write(start[1], b"go");
+    assert_eq!(setpriority(0, DEFAULT_PRIORITY), 0);
+
+    let low_rounds = read_rounds(done_low[0]);
+    let high_rounds = read_rounds(done_high[0]);
+    let mut exit_code = 0;
+    assert_eq!(waitpid(low as usize, &mut exit_code), low);
+    assert_eq!(waitpid(high as usize, &mut exit_code), high);
+    println!("low {} rounds, high {} rounds", low_rounds, high_rounds);
+    // a quarter of the priority should get well under half the time
+    assert!(high_rounds > low_rounds * 2);
+    println!("priority passed!");
+    0
+}
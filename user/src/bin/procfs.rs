@@ -0,0 +1,114 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+extern crate alloc;
+
+use alloc::format;
+use alloc::string::String;
+use user_lib::{chdir, close, getpid, mmap, munmap, open, read, sleep, yield_, OpenFlags};
+
+/// Read the whole file at `path`, which must exist.
+fn read_file(path: &str) -> String {
+    let fd = open(path, OpenFlags::RDONLY);
+    assert!(fd >= 0, "cannot open {}", path);
+    let mut contents = String::new();
+    let mut buf = [0u8; 64];
+    loop {
+        let len = read(fd as usize, &mut buf);
+        if len <= 0 {
+            break;
+        }
+        contents.push_str(core::str::from_utf8(&buf[..len as usize]).unwrap());
+    }
+    close(fd as usize);
+    contents
+}
+
+/// The idle column of the `cpu` line of `/proc/stat`.
+fn idle_ticks(stat: &str) -> usize {
+    let line = stat.lines().next().unwrap();
+    assert!(line.starts_with("cpu "));
+    line.split_whitespace().nth(4).unwrap().parse().unwrap()
+}
+
+/// The value of the `name` line of a status file.
+fn switches(status: &str, name: &str) -> usize {
+    status
+        .lines()
+        .find_map(|line| line.strip_prefix(name)?.strip_prefix(":\t"))
+        .unwrap()
+        .parse()
+        .unwrap()
+}
+
+#[no_mangle]
+pub fn main() -> i32 {
+    let meminfo = read_file("/proc/meminfo\0");
+    assert!(meminfo.starts_with("Frames:"));
+
+    let uptime = read_file("/proc/uptime\0");
+    let (secs, centis) = uptime.trim_end().split_once('.').unwrap();
+    assert!(secs.parse::<usize>().is_ok() && centis.len() == 2);
+
+    // the boot hart idles while this process sleeps
+    let idle_before = idle_ticks(&read_file("/proc/stat\0"));
+    sleep(100);
+    let stat = read_file("/proc/stat\0");
+    assert!(stat.lines().any(|line| line.starts_with("cpu0 ")));
+    assert!(idle_ticks(&stat) > idle_before);
+
+    let pid = getpid();
+    let status = read_file(&format!("/proc/{}/status\0", pid));
+    assert!(status.starts_with(&format!("Pid:\t{}\n", pid)));
+    assert!(status.contains("State:\tR (running)\n"));
+    assert!(status.contains("Threads:\t1\n"));
+    // sleeping above blocked, yielding is not voluntary
+    assert!(switches(&status, "voluntary_ctxt_switches") >= 1);
+    let nonvoluntary = switches(&status, "nonvoluntary_ctxt_switches");
+    yield_();
+    let status = read_file(&format!("/proc/{}/status\0", pid));
+    assert!(switches(&status, "nonvoluntary_ctxt_switches") > nonvoluntary);
+
+    // an area shows up once mapped, all of it resident
+    let area = mmap(0, 0x2000, 0x3);
+    assert!(area > 0);
+    let area = area as usize;
+    let line = |maps: &str| {
+        let prefix = format!("{:08x}-{:08x} ", area, area + 0x2000);
+        maps.lines()
+            .find(|line| line.starts_with(&prefix))
+            .map(String::from)
+    };
+    let maps = read_file(&format!("/proc/{}/maps\0", pid));
+    assert!(line(&maps).unwrap().contains(" rw-p 2/2 Framed"));
+    // the code of this program is there, and the trap context
+    let code = main as usize;
+    assert!(maps.lines().any(|line| {
+        let (start, end) = line.split_once(' ').unwrap().0.split_once('-').unwrap();
+        let start = usize::from_str_radix(start, 16).unwrap();
+        let end = usize::from_str_radix(end, 16).unwrap();
+        (start..end).contains(&code) && line.contains(" r-xp ")
+    }));
+    assert!(maps.lines().any(|line| line.ends_with(" [kernel]")));
+    assert_eq!(munmap(area, 0x2000), 0);
+    assert!(line(&read_file(&format!("/proc/{}/maps\0", pid))).is_none());
+
+    // relative paths cross into the mount too
+    assert_eq!(chdir("/proc\0"), 0);
+    assert!(read_file("uptime\0").contains('.'));
+    assert_eq!(chdir("/\0"), 0);
+
+    assert_eq!(open("/proc/99999/status\0", OpenFlags::RDONLY), -1);
+    assert_eq!(
+        open("/proc/uptime\0", OpenFlags::CREATE | OpenFlags::WRONLY),
+        -1
+    );
+    assert_eq!(
+        open("/proc/new\0", OpenFlags::CREATE | OpenFlags::WRONLY),
+        -1
+    );
+    println!("procfs passed!");
+    0
+}
@@ -0,0 +1,122 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use core::sync::atomic::{AtomicUsize, Ordering};
+use user_lib::{
+    exit, fork, getpid, ptrace, waitpid, waitpid_options, wifstopped, wstopsig, SignalFlags,
+    UserRegs, PTRACE_ATTACH, PTRACE_CONT, PTRACE_DETACH, PTRACE_GETREGS, PTRACE_PEEKDATA,
+    PTRACE_POKEDATA, PTRACE_SINGLESTEP, WUNTRACED,
+};
+
+/// The child spins until the parent writes it through ptrace.
+static RELEASED: AtomicUsize = AtomicUsize::new(0);
+
+fn wait_stop(pid: usize) -> i32 {
+    let mut status = 0;
+    assert_eq!(
+        waitpid_options(pid as isize, &mut status, WUNTRACED),
+        pid as isize
+    );
+    assert!(wifstopped(status));
+    wstopsig(status)
+}
+
+fn regs(pid: usize) -> UserRegs {
+    let mut regs = UserRegs::default();
+    assert_eq!(
+        ptrace(PTRACE_GETREGS, pid, 0, &mut regs as *mut _ as usize),
+        0
+    );
+    regs
+}
+
+#[no_mangle]
+pub fn main() -> i32 {
+    assert_eq!(ptrace(PTRACE_ATTACH, getpid() as usize, 0, 0), -1);
+    let pid = fork();
+    if pid == 0 {
+        while RELEASED.load(Ordering::Relaxed) == 0 {
+            core::hint::spin_loop();
+        }
+        exit(RELEASED.load(Ordering::Relaxed) as i32);
+    }
+    let pid = pid as usize;
+    let addr = &RELEASED as *const _ as usize;
+    assert_eq!(ptrace(PTRACE_ATTACH, pid, 0, 0), 0);
+    assert_eq!(ptrace(PTRACE_ATTACH, pid, 0, 0), -1);
+    assert_eq!(
+        wait_stop(pid),
+        SignalFlags::SIGSTOP.bits().trailing_zeros() as i32
+    );
+
+    let before = regs(pid);
+    assert_ne!(before.regs[0], 0);
+    let mut word = usize::MAX;
+    assert_eq!(
+        ptrace(PTRACE_PEEKDATA, pid, addr, &mut word as *mut _ as usize),
+        0
+    );
+    assert_eq!(word, 0);
+    assert_eq!(
+        ptrace(
+            PTRACE_PEEKDATA,
+            pid,
+            usize::MAX - 4,
+            &mut word as *mut _ as usize
+        ),
+        -1
+    );
+    let mut code = 0;
+    assert_eq!(
+        ptrace(
+            PTRACE_PEEKDATA,
+            pid,
+            before.regs[0],
+            &mut code as *mut _ as usize
+        ),
+        0
+    );
+
+    // a few steps through the spin loop
+    for _ in 0..4 {
+        let pc = regs(pid).regs[0];
+        assert_eq!(ptrace(PTRACE_SINGLESTEP, pid, 0, 0), 0);
+        assert_eq!(
+            wait_stop(pid),
+            SignalFlags::SIGTRAP.bits().trailing_zeros() as i32
+        );
+        let next = regs(pid).regs[0];
+        assert_ne!(next, pc);
+        println!("stepped from {:#x} to {:#x}", pc, next);
+    }
+    // no breakpoint is left behind
+    let mut after = 0;
+    assert_eq!(
+        ptrace(
+            PTRACE_PEEKDATA,
+            pid,
+            before.regs[0],
+            &mut after as *mut _ as usize
+        ),
+        0
+    );
+    assert_eq!(after, code);
+
+    assert_eq!(ptrace(PTRACE_POKEDATA, pid, addr, 42), 0);
+    assert_eq!(
+        ptrace(PTRACE_PEEKDATA, pid, addr, &mut word as *mut _ as usize),
+        0
+    );
+    assert_eq!(word, 42);
+    assert_eq!(RELEASED.load(Ordering::Relaxed), 0);
+    assert_eq!(ptrace(PTRACE_CONT, pid, 0, 0), 0);
+    assert_eq!(ptrace(PTRACE_DETACH, pid, 0, 0), -1);
+    let mut exit_code = 0;
+    assert_eq!(waitpid(pid, &mut exit_code), pid as isize);
+    assert_eq!(exit_code, 42);
+    println!("ptrace_step passed!");
+    0
+}
@@ -0,0 +1,104 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use core::hint::black_box;
+use user_lib::{
+    close, dup, dup2, exit, fork, getrlimit, pipe, setrlimit, waitpid_options, wexitstatus,
+    wifexited, wifsignaled, wtermsig, RLimit, RLIMIT_NOFILE, RLIMIT_STACK,
+};
+
+/// Use about `depth` pages of stack.
+fn use_stack(depth: usize) -> usize {
+    let mut buf = [0u8; 4096];
+    buf[0] = depth as u8;
+    let buf = black_box(&mut buf);
+    if depth == 0 {
+        buf[0] as usize
+    } else {
+        use_stack(depth - 1) + buf[0] as usize
+    }
+}
+
+fn nofile() {
+    let mut old = RLimit::default();
+    assert_eq!(getrlimit(RLIMIT_NOFILE, &mut old), 0);
+    assert!(old.cur <= old.max);
+    // the hard limit cannot be raised, nor the soft one above it
+    let raised = RLimit {
+        cur: old.cur,
+        max: old.max + 1,
+    };
+    assert_eq!(setrlimit(RLIMIT_NOFILE, &raised), -1);
+    let inverted = RLimit {
+        cur: old.max + 1,
+        max: old.max,
+    };
+    assert_eq!(setrlimit(RLIMIT_NOFILE, &inverted), -1);
+
+    let low = RLimit {
+        cur: 8,
+        max: old.max,
+    };
+    assert_eq!(setrlimit(RLIMIT_NOFILE, &low), 0);
+    let mut last = 0;
+    loop {
+        let fd = dup(0);
+        if fd < 0 {
+            break;
+        }
+        last = fd;
+    }
+    assert_eq!(last, 7);
+    let mut pipe_fd = [0usize; 2];
+    assert_eq!(pipe(&mut pipe_fd), -1);
+    assert_eq!(dup2(0, 8), -1);
+    close(7);
+    // a pipe needs two descriptors, none is left taken
+    assert_eq!(pipe(&mut pipe_fd), -1);
+    assert_eq!(dup(0), 7);
+    for fd in 3..=7 {
+        close(fd);
+    }
+    assert_eq!(setrlimit(RLIMIT_NOFILE, &old), 0);
+    assert_eq!(dup2(0, 8), 8);
+    close(8);
+}
+
+fn stack() {
+    let mut old = RLimit::default();
+    assert_eq!(getrlimit(RLIMIT_STACK, &mut old), 0);
+    // the stack grows on demand within the limit
+    let pid = fork();
+    if pid == 0 {
+        exit(use_stack(6) as i32);
+    }
+    let mut status = 0;
+    assert_eq!(waitpid_options(pid, &mut status, 0), pid);
+    assert!(wifexited(status));
+    assert_eq!(wexitstatus(status), 21);
+    // and the process is killed when it grows past it
+    let pid = fork();
+    if pid == 0 {
+        let low = RLimit {
+            cur: 4096 * 4,
+            max: old.max,
+        };
+        assert_eq!(setrlimit(RLIMIT_STACK, &low), 0);
+        use_stack(6);
+        exit(0);
+    }
+    assert_eq!(waitpid_options(pid, &mut status, 0), pid);
+    assert!(wifsignaled(status));
+    assert_eq!(wtermsig(status), 11);
+}
+
+#[no_mangle]
+pub fn main() -> i32 {
+    nofile();
+    stack();
+    println!("rlimit_test passed!");
+    0
+}
@@ -0,0 +1,85 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{
+    exit, fork, shmat, shmdt, shmget, waitpid, IPC_CREAT, IPC_EXCL, IPC_PRIVATE, SHM_RDONLY,
+};
+
+const PAGE_SIZE: usize = 4096;
+const KEY: usize = 0x5348;
+/// bytes between the ones checked
+const STRIDE: usize = PAGE_SIZE / 8;
+
+fn fill(addr: usize, len: usize, seed: u8) {
+    let bytes = addr as *mut u8;
+    for i in (0..len).step_by(STRIDE) {
+        unsafe {
+            bytes
+                .add(i)
+                .write_volatile(seed.wrapping_add((i / STRIDE) as u8))
+        };
+    }
+}
+
+fn check(addr: usize, len: usize, seed: u8) {
+    let bytes = addr as *const u8;
+    for i in (0..len).step_by(STRIDE) {
+        assert_eq!(
+            unsafe { bytes.add(i).read_volatile() },
+            seed.wrapping_add((i / STRIDE) as u8)
+        );
+    }
+}
+
+#[no_mangle]
+pub fn main() -> i32 {
+    // a private segment is shared with the children through fork
+    let shmid = shmget(IPC_PRIVATE, 2 * PAGE_SIZE, IPC_CREAT);
+    assert!(shmid >= 0);
+    let addr = shmat(shmid as usize, 0, 0);
+    assert!(addr > 0);
+    let addr = addr as usize;
+    // a new segment reads as zero
+    let bytes = addr as *const u8;
+    assert!((0..2 * PAGE_SIZE).all(|i| unsafe { bytes.add(i).read_volatile() } == 0));
+    let pid = fork();
+    if pid == 0 {
+        fill(addr, 2 * PAGE_SIZE, 0x11);
+        exit(0);
+    }
+    let mut exit_code = 0;
+    assert_eq!(waitpid(pid as usize, &mut exit_code), pid);
+    assert_eq!(exit_code, 0);
+    check(addr, 2 * PAGE_SIZE, 0x11);
+    assert_eq!(shmdt(addr), 0);
+    assert_eq!(shmdt(addr), -1);
+
+    // a keyed one is found by a process that did not attach it before
+    assert_eq!(shmget(KEY, PAGE_SIZE, 0), -1);
+    let shmid = shmget(KEY, PAGE_SIZE, IPC_CREAT);
+    assert!(shmid >= 0);
+    assert_eq!(shmget(KEY, PAGE_SIZE, IPC_CREAT | IPC_EXCL), -1);
+    assert_eq!(shmget(KEY, 2 * PAGE_SIZE, 0), -1);
+    let pid = fork();
+    if pid == 0 {
+        let shmid = shmget(KEY, PAGE_SIZE, 0);
+        assert!(shmid >= 0);
+        let addr = shmat(shmid as usize, 0, 0);
+        assert!(addr > 0);
+        fill(addr as usize, PAGE_SIZE, 0x22);
+        exit(0);
+    }
+    assert_eq!(waitpid(pid as usize, &mut exit_code), pid);
+    assert_eq!(exit_code, 0);
+    let addr = shmat(shmid as usize, 0, SHM_RDONLY);
+    assert!(addr > 0);
+    check(addr as usize, PAGE_SIZE, 0x22);
+    // a segment cannot go over another mapping
+    assert_eq!(shmat(shmid as usize, addr as usize, 0), -1);
+    assert_eq!(shmdt(addr as usize), 0);
+    println!("shm passed!");
+    0
+}
@@ -0,0 +1,75 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use core::sync::atomic::{AtomicUsize, Ordering};
+use user_lib::{
+    fork, getpid, kill, sigaction, sigprocmask, sigreturn, waitpid, yield_, SignalAction,
+    SignalFlags, SIG_IGN,
+};
+
+static HANDLED: AtomicUsize = AtomicUsize::new(0);
+
+fn handler(signal: usize) {
+    assert_eq!(signal as i32, SignalFlags::SIGUSR1.bits());
+    HANDLED.fetch_add(1, Ordering::SeqCst);
+    sigreturn();
+}
+
+fn raise(signal: SignalFlags) {
+    assert_eq!(kill(getpid() as usize, signal.bits()), 0);
+}
+
+#[no_mangle]
+pub fn main() -> i32 {
+    // SIGKILL can be neither caught nor ignored
+    let ignore = SignalAction {
+        handler: SIG_IGN,
+        ..Default::default()
+    };
+    assert_eq!(sigaction(SignalFlags::SIGKILL, Some(&ignore), None), -1);
+
+    let action = SignalAction {
+        handler: handler as usize,
+        ..Default::default()
+    };
+    let mut old_action = SignalAction::default();
+    assert_eq!(
+        sigaction(SignalFlags::SIGUSR1, Some(&action), Some(&mut old_action)),
+        0
+    );
+    assert_eq!(old_action.handler, 0);
+    raise(SignalFlags::SIGUSR1);
+    assert_eq!(HANDLED.load(Ordering::SeqCst), 1);
+
+    // a masked signal waits until it gets unmasked
+    assert_eq!(sigprocmask(SignalFlags::SIGUSR1), 0);
+    raise(SignalFlags::SIGUSR1);
+    assert_eq!(HANDLED.load(Ordering::SeqCst), 1);
+    assert_eq!(
+        sigprocmask(SignalFlags::empty()),
+        SignalFlags::SIGUSR1.bits() as isize
+    );
+    assert_eq!(HANDLED.load(Ordering::SeqCst), 2);
+
+    // ignored by request and by default
+    assert_eq!(sigaction(SignalFlags::SIGINT, Some(&ignore), None), 0);
+    raise(SignalFlags::SIGINT);
+    raise(SignalFlags::SIGCHLD);
+
+    // the default action of SIGUSR2 terminates
+    let pid = fork();
+    if pid == 0 {
+        loop {
+            yield_();
+        }
+    }
+    assert_eq!(kill(pid as usize, SignalFlags::SIGUSR2.bits()), 0);
+    let mut exit_code = 0;
+    assert_eq!(waitpid(pid as usize, &mut exit_code), pid);
+    assert_eq!(exit_code, -12);
+    println!("sig_simple passed!");
+    0
+}
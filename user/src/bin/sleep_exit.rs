@@ -0,0 +1,30 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{exit, fork, sleep, thread_create, waitpid};
+
+pub fn sleeper() -> ! {
+    sleep(100);
+    println!("should be gone with its process!");
+    exit(1)
+}
+
+#[no_mangle]
+pub fn main() -> i32 {
+    let pid = fork();
+    if pid == 0 {
+        // leave a thread asleep behind
+        thread_create(sleeper as usize, 0);
+        exit(0);
+    }
+    let mut exit_code = 0;
+    assert_eq!(waitpid(pid as usize, &mut exit_code), pid);
+    assert_eq!(exit_code, 0);
+    // the timer of the dead thread goes off in the meantime
+    sleep(200);
+    println!("sleep_exit passed!");
+    0
+}
@@ -0,0 +1,34 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{getpid, spawn, waitpid};
+
+const CHILD_EXIT_CODE: i32 = 42;
+
+#[no_mangle]
+pub fn main(argc: usize, argv: &[&str]) -> i32 {
+    if argc == 2 {
+        assert_eq!(argv[1], "child");
+        println!("spawned child {} running", getpid());
+        return CHILD_EXIT_CODE;
+    }
+    let args = [
+        "spawn_test\0".as_ptr(),
+        "child\0".as_ptr(),
+        core::ptr::null::<u8>(),
+    ];
+    let pid = spawn("spawn_test\0", &args);
+    assert!(pid > 0);
+    let mut exit_code = 0;
+    assert_eq!(waitpid(pid as usize, &mut exit_code), pid);
+    assert_eq!(exit_code, CHILD_EXIT_CODE);
+    // a child that was never started cannot be waited for
+    assert_eq!(spawn("no_such_app\0", &args), -1);
+    assert_eq!(spawn("/\0", &args), -1);
+    assert_eq!(waitpid(pid as usize, &mut exit_code), -1);
+    println!("spawn_test passed!");
+    0
+}
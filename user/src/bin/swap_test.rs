@@ -0,0 +1,54 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+extern crate alloc;
+
+use alloc::vec;
+use user_lib::{meminfo, mmap, munmap};
+
+const PAGE_SIZE: usize = 4096;
+const START: usize = 0x1000_0000;
+/// Pages mapped on top of all free memory, they must come out of swap.
+const EXTRA_PAGES: usize = 512;
+
+fn free_frames() -> usize {
+    let mut buf = vec![0u8; 1024];
+    let len = meminfo(&mut buf) as usize;
+    let report = core::str::from_utf8(&buf[..len.min(buf.len())]).unwrap();
+    // "Frames:     used / total pages used ..."
+    let mut words = report.split_whitespace().skip(1);
+    let used: usize = words.next().unwrap().parse().unwrap();
+    let total: usize = words.nth(1).unwrap().parse().unwrap();
+    total - used
+}
+
+fn page_word(i: usize) -> *mut usize {
+    (START + i * PAGE_SIZE) as *mut usize
+}
+
+#[no_mangle]
+pub fn main() -> i32 {
+    let pages = free_frames() + EXTRA_PAGES;
+    // map in two steps, the second one has to evict the first
+    let first = pages - EXTRA_PAGES * 2;
+    assert_eq!(mmap(START, first * PAGE_SIZE, 0x3), 0);
+    for i in 0..first {
+        unsafe { page_word(i).write_volatile(i) };
+    }
+    assert_eq!(
+        mmap(START + first * PAGE_SIZE, (pages - first) * PAGE_SIZE, 0x3),
+        0
+    );
+    for i in first..pages {
+        unsafe { page_word(i).write_volatile(i) };
+    }
+    for i in 0..pages {
+        assert_eq!(unsafe { page_word(i).read_volatile() }, i);
+    }
+    assert_eq!(munmap(START, pages * PAGE_SIZE), 0);
+    println!("swap_test passed!");
+    0
+}
@@ -0,0 +1,37 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+extern crate alloc;
+
+use alloc::format;
+use alloc::string::String;
+use alloc::vec;
+use user_lib::{getpid, open, syslog, trace, OpenFlags, SYSLOG_ACTION_READ_ALL};
+
+#[no_mangle]
+pub fn main() -> i32 {
+    assert_eq!(trace(usize::MAX, true), -1);
+    let pid = getpid();
+    assert_eq!(trace(0, true), 0);
+    assert_eq!(open("trace_missing\0", OpenFlags::RDONLY), -1);
+    getpid();
+    assert_eq!(trace(0, false), 0);
+    // not logged any more
+    open("untraced_missing\0", OpenFlags::RDONLY);
+
+    // the newest records are the ones above
+    let mut buf = vec![0u8; 2048];
+    let len = syslog(SYSLOG_ACTION_READ_ALL, &mut buf);
+    assert!(len > 0);
+    let log = String::from_utf8_lossy(&buf[..len as usize]);
+    let prefix = format!("[{}:0] ", pid);
+    assert!(log.contains(&format!("{}open(\"trace_missing\", 0x0) = -1", prefix)));
+    assert!(log.contains(&format!("{}getpid() = {}", prefix, pid)));
+    assert!(log.contains(&format!("{}trace(0, 0) = 0", prefix)));
+    assert!(!log.contains("untraced_missing"));
+    println!("syscall_trace passed!");
+    0
+}
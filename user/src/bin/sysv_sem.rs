@@ -0,0 +1,90 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{
+    exit, fork, semctl, semget, semop, sleep, waitpid, SemBuf, GETVAL, IPC_CREAT, IPC_EXCL,
+    IPC_NOWAIT, IPC_PRIVATE, SETVAL,
+};
+
+const KEY: usize = 0x5345;
+/// asking for the operation to be undone at exit
+const SEM_UNDO: i16 = 0o10000;
+
+fn op(sem_num: u16, sem_op: i16, sem_flg: i16) -> SemBuf {
+    SemBuf {
+        sem_num,
+        sem_op,
+        sem_flg,
+    }
+}
+
+fn wait_child(pid: isize) {
+    let mut exit_code = 0;
+    assert_eq!(waitpid(pid as usize, &mut exit_code), pid);
+    assert_eq!(exit_code, 0);
+}
+
+#[no_mangle]
+pub fn main() -> i32 {
+    let nowait = IPC_NOWAIT as i16;
+    let semid = semget(IPC_PRIVATE, 2, IPC_CREAT);
+    assert!(semid >= 0);
+    let semid = semid as usize;
+    assert_eq!(semctl(semid, 0, GETVAL, 0), 0);
+    assert_eq!(semctl(semid, 2, GETVAL, 0), -1);
+    assert_eq!(semop(semid, &[op(0, -1, nowait)]), -1);
+    assert_eq!(semop(semid, &[op(2, 1, 0)]), -1);
+    assert_eq!(semop(semid, &[op(0, 1, SEM_UNDO)]), -1);
+
+    // all or nothing
+    assert_eq!(semctl(semid, 0, SETVAL, 3), 0);
+    assert_eq!(semop(semid, &[op(0, -1, 0), op(1, -1, nowait)]), -1);
+    assert_eq!(semctl(semid, 0, GETVAL, 0), 3);
+    assert_eq!(semop(semid, &[op(0, -2, 0), op(1, 2, 0)]), 0);
+    assert_eq!(semctl(semid, 0, GETVAL, 0), 1);
+    assert_eq!(semctl(semid, 1, GETVAL, 0), 2);
+
+    // a taker waits for the value to go up
+    let pid = fork();
+    if pid == 0 {
+        assert_eq!(semop(semid, &[op(0, -3, 0)]), 0);
+        exit(0);
+    }
+    sleep(50);
+    assert_eq!(semctl(semid, 0, GETVAL, 0), 1);
+    assert_eq!(semop(semid, &[op(0, 2, 0)]), 0);
+    wait_child(pid);
+    assert_eq!(semctl(semid, 0, GETVAL, 0), 0);
+
+    // and one after 0 for it to go down
+    let pid = fork();
+    if pid == 0 {
+        assert_eq!(semop(semid, &[op(1, 0, 0)]), 0);
+        exit(0);
+    }
+    assert_eq!(semop(semid, &[op(1, 0, nowait)]), -1);
+    sleep(50);
+    assert_eq!(semop(semid, &[op(1, -2, 0)]), 0);
+    wait_child(pid);
+
+    // a keyed set is found by a process that did not get it before
+    assert_eq!(semget(KEY, 1, 0), -1);
+    let semid = semget(KEY, 1, IPC_CREAT);
+    assert!(semid >= 0);
+    assert_eq!(semget(KEY, 1, IPC_CREAT | IPC_EXCL), -1);
+    assert_eq!(semget(KEY, 2, 0), -1);
+    let pid = fork();
+    if pid == 0 {
+        let semid = semget(KEY, 1, 0);
+        assert!(semid >= 0);
+        assert_eq!(semop(semid as usize, &[op(0, 1, 0)]), 0);
+        exit(0);
+    }
+    assert_eq!(semop(semid as usize, &[op(0, -1, 0)]), 0);
+    wait_child(pid);
+    println!("sysv_sem passed!");
+    0
+}
@@ -0,0 +1,69 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+// try it with `nc localhost 6202` on the host
+
+use user_lib::{
+    accept_in, bind_in, close, exit, fork, listen, read, socket, waitpid_options, write,
+    SockAddrIn, AF_INET, SOCK_STREAM, WNOHANG,
+};
+
+/// QEMU forwards TCP port 6202 of the host here.
+const PORT: u16 = 7;
+
+/// Send back whatever the client sends until it closes.
+fn echo(client: usize) {
+    let mut buf = [0u8; 1024];
+    loop {
+        let len = read(client, &mut buf);
+        if len <= 0 {
+            break;
+        }
+        let mut sent = 0;
+        while sent < len as usize {
+            let written = write(client, &buf[sent..len as usize]);
+            if written <= 0 {
+                return;
+            }
+            sent += written as usize;
+        }
+    }
+}
+
+/// An echo server, a child process for each client.
+#[no_mangle]
+pub fn main() -> i32 {
+    let server = socket(AF_INET, SOCK_STREAM, 0);
+    if server < 0
+        || bind_in(server as usize, &SockAddrIn::new([0, 0, 0, 0], PORT)) < 0
+        || listen(server as usize, 8) < 0
+    {
+        println!("failed to listen on port {}", PORT);
+        return -1;
+    }
+    let server = server as usize;
+    println!("echo server listening on port {}", PORT);
+    loop {
+        let mut peer = SockAddrIn::default();
+        let client = accept_in(server, &mut peer);
+        if client < 0 {
+            println!("failed to accept a client");
+            return -1;
+        }
+        let client = client as usize;
+        let [a, b, c, d] = peer.addr();
+        println!("client {}.{}.{}.{}:{}", a, b, c, d, peer.port());
+        if fork() == 0 {
+            close(server);
+            echo(client);
+            exit(0);
+        }
+        close(client);
+        // reap the clients that are done
+        let mut exit_code = 0;
+        while waitpid_options(-1, &mut exit_code, WNOHANG) > 0 {}
+    }
+}
@@ -0,0 +1,58 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{
+    accept, bind_in, close, connect_in, fcntl, listen, ppoll, read, socket, OpenFlags, PollFd,
+    PollFlags, SockAddrIn, TimeSpec, AF_INET, EAGAIN, EINPROGRESS, F_SETFL, SOCK_NONBLOCK,
+    SOCK_STREAM,
+};
+
+#[no_mangle]
+pub fn main() -> i32 {
+    let server = socket(AF_INET, SOCK_STREAM, 0);
+    assert!(server >= 0);
+    let server = server as usize;
+    assert_eq!(bind_in(server, &SockAddrIn::new([0, 0, 0, 0], 5000)), 0);
+    // a socket binds once, and a port is taken by one socket
+    assert_eq!(bind_in(server, &SockAddrIn::new([0, 0, 0, 0], 5001)), -1);
+    let other = socket(AF_INET, SOCK_STREAM, 0);
+    assert!(other >= 0);
+    let other = other as usize;
+    assert_eq!(bind_in(other, &SockAddrIn::new([0, 0, 0, 0], 5000)), -1);
+    // nothing to read before it is connected
+    let mut buf = [0u8; 16];
+    assert_eq!(read(other, &mut buf), 0);
+    close(other);
+
+    // nobody connects yet
+    assert_eq!(listen(server, 4), 0);
+    assert_eq!(
+        fcntl(server, F_SETFL, OpenFlags::NONBLOCK.bits() as usize),
+        0
+    );
+    assert_eq!(accept(server), EAGAIN);
+    let mut fds = [PollFd::new(server, PollFlags::IN)];
+    assert_eq!(ppoll(&mut fds, Some(&TimeSpec::default())), 0);
+    // a listening socket does not connect
+    let gateway = SockAddrIn::new([10, 0, 2, 2], 9);
+    assert_eq!(connect_in(server, &gateway), -1);
+    close(server);
+    // and its port is free again once closed
+    let server = socket(AF_INET, SOCK_STREAM, 0) as usize;
+    assert_eq!(bind_in(server, &SockAddrIn::new([0, 0, 0, 0], 5000)), 0);
+    close(server);
+
+    // a nonblocking connect goes on without waiting
+    let client = socket(AF_INET, SOCK_STREAM | SOCK_NONBLOCK, 0);
+    assert!(client >= 0);
+    let client = client as usize;
+    assert_eq!(connect_in(client, &gateway), EINPROGRESS);
+    // and may not connect twice
+    assert_eq!(connect_in(client, &gateway), -1);
+    close(client);
+    println!("tcp_socket passed!");
+    0
+}
@@ -40,6 +40,9 @@ pub fn main() -> i32 {
         let exit_code = waittid(*tid as usize);
         println!("thread#{} exited with code {}", tid, exit_code);
     }
+    // the threads are gone once waited for, and there never was a 100th
+    assert_eq!(waittid(v[0] as usize), -1);
+    assert_eq!(waittid(100), -1);
     println!("main thread exited.");
     0
 }
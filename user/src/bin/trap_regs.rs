@@ -0,0 +1,173 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use core::arch::{asm, global_asm};
+use user_lib::{exit, thread_create, waittid};
+
+const SYSCALL_YIELD: usize = 124;
+const SYSCALL_GETPID: usize = 172;
+
+fn pattern(seed: usize, reg: usize) -> usize {
+    0x5a5a_0000_0000_0000 | (seed << 8) | reg
+}
+
+/// Issue `ecall` with the temporaries and argument registers (except a0,
+/// which carries the return value) preloaded, and return what came back.
+fn probe_temporaries(id: usize, seed: usize) -> [usize; 15] {
+    let mut r = [0usize; 15];
+    for (i, v) in r.iter_mut().enumerate() {
+        *v = pattern(seed, i);
+    }
+    r[14] = id;
+    unsafe {
+        asm!(
+            "ecall",
+            inout("ra") r[0],
+            inout("t0") r[1],
+            inout("t1") r[2],
+            inout("t2") r[3],
+            inout("t3") r[4],
+            inout("t4") r[5],
+            inout("t5") r[6],
+            inout("t6") r[7],
+            inout("a1") r[8],
+            inout("a2") r[9],
+            inout("a3") r[10],
+            inout("a4") r[11],
+            inout("a5") r[12],
+            inout("a6") r[13],
+            inout("a7") r[14],
+            lateout("a0") _,
+        );
+    }
+    r
+}
+
+/// Same as `probe_temporaries` for the callee-saved registers and tp. LLVM
+/// keeps s1 for itself, so like tp it is saved and loaded in the template,
+/// and what came back is stored over the probe value in `r[0]`.
+fn probe_saved(id: usize, seed: usize) -> [usize; 13] {
+    let mut r = [0usize; 13];
+    for (i, v) in r.iter_mut().enumerate() {
+        *v = pattern(seed, 0x80 | i);
+    }
+    r[12] = id;
+    let s1 = r.as_mut_ptr();
+    unsafe {
+        asm!(
+            "addi sp, sp, -32",
+            "sd tp, 0(sp)",
+            "sd s1, 8(sp)",
+            "sd {s1}, 16(sp)",
+            "ld s1, 0({s1})",
+            "mv tp, a0",
+            "ecall",
+            "mv a0, tp",
+            "ld tp, 16(sp)",
+            "sd s1, 0(tp)",
+            "ld s1, 8(sp)",
+            "ld tp, 0(sp)",
+            "addi sp, sp, 32",
+            s1 = in(reg) s1,
+            inout("s2") r[1],
+            inout("s3") r[2],
+            inout("s4") r[3],
+            inout("s5") r[4],
+            inout("s6") r[5],
+            inout("s7") r[6],
+            inout("s8") r[7],
+            inout("s9") r[8],
+            inout("s10") r[9],
+            inout("s11") r[10],
+            inout("a0") r[11],
+            inout("a7") r[12],
+        );
+    }
+    r
+}
+
+// A new thread starts from a trap context the kernel built, so apart from
+// sp and the argument in a0 every register it sees was put there by the
+// kernel. Spill them all before anything else runs.
+global_asm!(
+    ".globl fresh_thread",
+    "fresh_thread:",
+    "addi sp, sp, -32*8",
+    ".irp n, 1,3,4,5,6,7,8,9,10,11,12,13,14,15,16,17,18,19,20,21,22,23,24,25,26,27,28,29,30,31",
+    "sd x\\n, \\n*8(sp)",
+    ".endr",
+    "mv a0, sp",
+    "call fresh_thread_check",
+);
+
+extern "C" {
+    fn fresh_thread();
+}
+
+const FRESH_ARG: usize = 0x5a5a_f00d;
+
+/// Every register of a new thread other than sp and a0 must still hold
+/// the zero it was scrubbed to, not a kernel value.
+#[no_mangle]
+extern "C" fn fresh_thread_check(regs: &[usize; 32]) -> ! {
+    for (i, &v) in regs.iter().enumerate() {
+        let expected = match i {
+            0 | 2 => continue,
+            10 => FRESH_ARG,
+            _ => 0,
+        };
+        if v != expected {
+            println!(
+                "register x{} of a new thread: expected {:#x}, got {:#x}",
+                i, expected, v
+            );
+            exit(-1);
+        }
+    }
+    exit(0)
+}
+
+fn verify(name: &str, got: &[usize], seed: usize, tag: usize, id: usize) -> bool {
+    let last = got.len() - 1;
+    for (i, &v) in got.iter().enumerate() {
+        let expected = if i == last {
+            id
+        } else {
+            pattern(seed, tag | i)
+        };
+        if v != expected {
+            println!(
+                "{} register #{} after syscall {}: expected {:#x}, got {:#x}",
+                name, i, id, expected, v
+            );
+            return false;
+        }
+    }
+    true
+}
+
+#[no_mangle]
+pub fn main() -> i32 {
+    for seed in 0..64 {
+        // yield switches to other tasks in between, getpid returns directly
+        let id = if seed % 2 == 0 {
+            SYSCALL_GETPID
+        } else {
+            SYSCALL_YIELD
+        };
+        if !verify("temporary", &probe_temporaries(id, seed), seed, 0, id)
+            || !verify("saved", &probe_saved(id, seed), seed, 0x80, id)
+        {
+            return -1;
+        }
+    }
+    let tid = thread_create(fresh_thread as usize, FRESH_ARG);
+    if tid < 0 || waittid(tid as usize) != 0 {
+        return -1;
+    }
+    println!("trap_regs passed!");
+    0
+}
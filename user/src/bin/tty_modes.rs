@@ -0,0 +1,39 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{close, getpgid, ioctl, pipe, LocalFlags, TCGETS, TCSETS, TIOCGPGRP, TIOCSPGRP};
+
+#[no_mangle]
+pub fn main() -> i32 {
+    let lflag = ioctl(0, TCGETS, 0);
+    assert!(lflag >= 0);
+    let lflag = LocalFlags::from_bits(lflag as u32).unwrap();
+    assert!(lflag.contains(LocalFlags::ICANON));
+
+    let raw = lflag - LocalFlags::ICANON - LocalFlags::ECHO;
+    assert_eq!(ioctl(0, TCSETS, raw.bits() as usize), 0);
+    assert_eq!(ioctl(0, TCGETS, 0), raw.bits() as isize);
+    assert_eq!(ioctl(0, TCSETS, 0o100000), -1);
+    assert_eq!(ioctl(0, TCSETS, lflag.bits() as usize), 0);
+    assert_eq!(ioctl(0, TCGETS, 0), lflag.bits() as isize);
+
+    let foreground = ioctl(0, TIOCGPGRP, 0);
+    assert!(foreground >= 0);
+    assert_eq!(ioctl(0, TIOCSPGRP, getpgid(0) as usize), 0);
+    assert_eq!(ioctl(0, TIOCGPGRP, 0), getpgid(0));
+    assert_eq!(ioctl(0, TIOCSPGRP, 99999), -1);
+    assert_eq!(ioctl(0, TIOCSPGRP, foreground as usize), 0);
+
+    // only the console takes requests
+    let mut pipe_fd = [0usize; 2];
+    assert_eq!(pipe(&mut pipe_fd), 0);
+    assert_eq!(ioctl(pipe_fd[0], TCGETS, 0), -1);
+    close(pipe_fd[0]);
+    close(pipe_fd[1]);
+    assert_eq!(ioctl(42, TCGETS, 0), -1);
+    println!("tty_modes passed!");
+    0
+}
@@ -8,16 +8,25 @@ extern crate user_lib;
 #[macro_use]
 extern crate alloc;
 
-use user_lib::{connect, read, write};
+use user_lib::{bind_in, recvfrom, sendto, socket, SockAddrIn, AF_INET, SOCK_DGRAM};
 
+/// QEMU forwards UDP port 6200 of the host here.
+const LOCAL_PORT: u16 = 2000;
+
+/// Talks to ping.py, run on the host: it replies to us directly and
+/// through the forwarded port.
 #[no_mangle]
 pub fn main() -> i32 {
     println!("udp test open!");
 
-    let udp_fd = connect(10 << 24 | 0 << 16 | 2 << 8 | 2, 2001, 26099);
-
+    let udp_fd = socket(AF_INET, SOCK_DGRAM, 0);
     if udp_fd < 0 {
-        println!("failed to create udp connection.");
+        println!("failed to create udp socket.");
+        return -1;
+    }
+    let udp_fd = udp_fd as usize;
+    if bind_in(udp_fd, &SockAddrIn::new([0, 0, 0, 0], LOCAL_PORT)) < 0 {
+        println!("failed to bind port {}.", LOCAL_PORT);
         return -1;
     }
 
@@ -25,22 +34,39 @@ pub fn main() -> i32 {
 
     println!("send <{}>", buf);
 
-    write(udp_fd as usize, buf.as_bytes());
+    // the host as QEMU user networking shows it
+    let host = SockAddrIn::new([10, 0, 2, 2], 26099);
+    if sendto(udp_fd, buf.as_bytes(), 0, &host) < 0 {
+        println!("can't send udp packet");
+        return -1;
+    }
 
     println!("udp send done, waiting for reply.");
 
     let mut buf = vec![0u8; 1024];
 
-    let len = read(udp_fd as usize, &mut buf);
-
-    if len < 0 {
-        println!("can't receive udp packet");
-        return -1;
+    for _ in 0..2 {
+        let mut sender = SockAddrIn::default();
+        let len = recvfrom(udp_fd, &mut buf, 0, Some(&mut sender));
+
+        if len < 0 {
+            println!("can't receive udp packet");
+            return -1;
+        }
+
+        let recv_str = String::from_utf8_lossy(&buf[..len as usize]);
+
+        let [a, b, c, d] = sender.addr();
+        println!(
+            "receive reply <{}> from {}.{}.{}.{}:{}",
+            recv_str,
+            a,
+            b,
+            c,
+            d,
+            sender.port()
+        );
     }
 
-    let recv_str = String::from_utf8_lossy(&buf[..len as usize]);
-
-    println!("receive reply <{}>", recv_str);
-
     0
 }
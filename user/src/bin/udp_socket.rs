@@ -0,0 +1,46 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{
+    bind_in, close, fcntl, recvfrom, sendto, socket, OpenFlags, SockAddrIn, AF_INET, EAGAIN,
+    F_SETFL, MSG_DONTWAIT, SOCK_DGRAM, SOCK_NONBLOCK,
+};
+
+#[no_mangle]
+pub fn main() -> i32 {
+    let fd = socket(AF_INET, SOCK_DGRAM, 0);
+    assert!(fd >= 0);
+    let fd = fd as usize;
+    assert_eq!(bind_in(fd, &SockAddrIn::new([0, 0, 0, 0], 4000)), 0);
+    // a socket binds once, and a port is taken by one socket
+    assert_eq!(bind_in(fd, &SockAddrIn::new([0, 0, 0, 0], 4001)), -1);
+    let other = socket(AF_INET, SOCK_DGRAM | SOCK_NONBLOCK, 0);
+    assert!(other >= 0);
+    let other = other as usize;
+    assert_eq!(bind_in(other, &SockAddrIn::new([0, 0, 0, 0], 4000)), -1);
+    // nor can it take an address that is not ours
+    assert_eq!(bind_in(other, &SockAddrIn::new([10, 0, 2, 2], 4001)), -1);
+
+    // nothing has come yet
+    let mut buf = [0u8; 64];
+    assert_eq!(recvfrom(fd, &mut buf, MSG_DONTWAIT, None), EAGAIN);
+    assert_eq!(recvfrom(other, &mut buf, 0, None), EAGAIN);
+    assert_eq!(fcntl(fd, F_SETFL, OpenFlags::NONBLOCK.bits() as usize), 0);
+    assert_eq!(recvfrom(fd, &mut buf, 0, None), EAGAIN);
+
+    // sending does not wait for a reply, an unbound socket gets a port
+    let gateway = SockAddrIn::new([10, 0, 2, 2], 9);
+    assert_eq!(sendto(fd, b"hello", 0, &gateway), 5);
+    assert_eq!(sendto(other, b"hello", 0, &gateway), 5);
+    // a port is free again once its socket is closed
+    close(fd);
+    let fd = socket(AF_INET, SOCK_DGRAM, 0) as usize;
+    assert_eq!(bind_in(fd, &SockAddrIn::new([0, 0, 0, 0], 4000)), 0);
+    close(fd);
+    close(other);
+    println!("udp_socket passed!");
+    0
+}
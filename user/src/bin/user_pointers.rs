@@ -0,0 +1,34 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use core::slice;
+use user_lib::{close, pipe, read, write};
+
+/// Where the kernel is loaded, never mapped for user programs.
+const KERNEL_ADDR: usize = 0x8020_0000;
+const PAGE_SIZE: usize = 4096;
+
+#[no_mangle]
+pub fn main() -> i32 {
+    let mut fds = [0usize; 2];
+    assert_eq!(pipe(&mut fds), 0);
+    // memory that is not ours fails the syscall instead of the kernel
+    let kernel = unsafe { slice::from_raw_parts(KERNEL_ADDR as *const u8, 16) };
+    assert_eq!(write(fds[1], kernel), -1);
+    // the text is readable but not writable
+    let text = unsafe { slice::from_raw_parts_mut(main as *const () as *mut u8, 16) };
+    assert_eq!(write(fds[1], text), 16);
+    assert_eq!(read(fds[0], text), -1);
+    // a buffer straddling two pages is copied whole
+    let mut buf = [0u8; 8192];
+    let start = PAGE_SIZE - buf.as_ptr() as usize % PAGE_SIZE - 6;
+    assert_eq!(read(fds[0], &mut buf[start..start + 16]), 16);
+    assert_eq!(&buf[start..start + 16], &text[..]);
+    close(fds[0]);
+    close(fds[1]);
+    println!("user_pointers passed!");
+    0
+}
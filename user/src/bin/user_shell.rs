@@ -1,6 +1,5 @@
 #![no_std]
 #![no_main]
-#![allow(clippy::println_empty_string)]
 
 extern crate alloc;
 
@@ -9,14 +8,16 @@ extern crate user_lib;
 
 const LF: u8 = 0x0au8;
 const CR: u8 = 0x0du8;
-const DL: u8 = 0x7fu8;
-const BS: u8 = 0x08u8;
 const LINE_START: &str = ">> ";
 
 use alloc::string::String;
 use alloc::vec::Vec;
 use user_lib::console::getchar;
-use user_lib::{close, dup, exec, fork, open, pipe, waitpid, OpenFlags};
+use user_lib::{
+    close, dup, environ, execve, fork, getpgid, ioctl, killpg, open, pipe, setpgid, sigaction,
+    trace, waitpid_options, wifstopped, OpenFlags, SignalAction, SignalFlags, SIG_IGN, TIOCSPGRP,
+    WNOHANG, WUNTRACED,
+};
 
 #[derive(Debug)]
 struct ProcessArguments {
@@ -24,6 +25,7 @@ struct ProcessArguments {
     output: String,
     args_copy: Vec<String>,
     args_addr: Vec<*const u8>,
+    envs_copy: Vec<String>,
 }
 
 impl ProcessArguments {
@@ -62,6 +64,22 @@ impl ProcessArguments {
             args_copy.drain(idx..=idx + 1);
         }
 
+        // leading NAME=value words add to the environment of the command
+        let mut envs_copy: Vec<String> = environ()
+            .iter()
+            .map(|env| {
+                let mut string = String::from(*env);
+                string.push('\0');
+                string
+            })
+            .collect();
+        while args_copy.len() > 1 && args_copy[0].contains('=') {
+            let env = args_copy.remove(0);
+            let name = &env[..env.find('=').unwrap() + 1];
+            envs_copy.retain(|old| !old.starts_with(name));
+            envs_copy.push(env);
+        }
+
         let mut args_addr: Vec<*const u8> = args_copy.iter().map(|arg| arg.as_ptr()).collect();
         args_addr.push(core::ptr::null::<u8>());
 
@@ -70,22 +88,133 @@ impl ProcessArguments {
             output,
             args_copy,
             args_addr,
+            envs_copy,
         }
     }
 }
 
+/// A pipeline started from one line, its processes form the process group
+/// `pgid`.
+struct Job {
+    id: usize,
+    pgid: usize,
+    /// the processes not reaped yet
+    pids: Vec<usize>,
+    command: String,
+    stopped: bool,
+}
+
+/// Give `job` the terminal, continuing it if it is stopped, and wait until
+/// it finishes or stops again. A stopped job goes back to `jobs`.
+fn run_foreground(mut job: Job, jobs: &mut Vec<Job>, shell_pgid: usize) {
+    ioctl(0, TIOCSPGRP, job.pgid);
+    if job.stopped {
+        killpg(job.pgid, SignalFlags::SIGCONT.bits());
+        job.stopped = false;
+    }
+    let mut status = 0;
+    job.pids.retain(|&pid| {
+        waitpid_options(pid as isize, &mut status, WUNTRACED) == pid as isize && wifstopped(status)
+    });
+    ioctl(0, TIOCSPGRP, shell_pgid);
+    if !job.pids.is_empty() {
+        job.stopped = true;
+        println!("[{}]+ Stopped\t{}", job.id, job.command);
+        jobs.push(job);
+    }
+}
+
+/// Reap the jobs that finished in the background and report them.
+fn reap_jobs(jobs: &mut Vec<Job>) {
+    let mut status = 0;
+    for job in jobs.iter_mut() {
+        job.pids
+            .retain(|&pid| waitpid_options(pid as isize, &mut status, WNOHANG) == 0);
+    }
+    jobs.retain(|job| {
+        if job.pids.is_empty() {
+            println!("[{}]  Done\t{}", job.id, job.command);
+        }
+        !job.pids.is_empty()
+    });
+}
+
+/// Run `command` if it is one of the job control builtins `jobs`, `fg [n]`
+/// and `bg [n]`, returning whether it was. Without `n` the latest job is
+/// meant.
+fn run_builtin(command: &str, jobs: &mut Vec<Job>, shell_pgid: usize) -> bool {
+    let words: Vec<_> = command.split(' ').filter(|word| !word.is_empty()).collect();
+    match words.first() {
+        Some(&"jobs") => {
+            for job in jobs.iter() {
+                let state = if job.stopped { "Stopped" } else { "Running" };
+                println!("[{}]  {}\t{}", job.id, state, job.command);
+            }
+            return true;
+        }
+        Some(&"fg") | Some(&"bg") => {}
+        _ => return false,
+    }
+    let idx = match words.get(1) {
+        Some(id) => jobs
+            .iter()
+            .position(|job| id.parse::<usize>() == Ok(job.id)),
+        None => jobs.len().checked_sub(1),
+    };
+    let idx = match idx {
+        Some(idx) => idx,
+        None => {
+            println!("{}: no such job", words[0]);
+            return true;
+        }
+    };
+    if words[0] == "fg" {
+        let job = jobs.remove(idx);
+        println!("{}", job.command);
+        run_foreground(job, jobs, shell_pgid);
+    } else {
+        let job = &mut jobs[idx];
+        killpg(job.pgid, SignalFlags::SIGCONT.bits());
+        job.stopped = false;
+        println!("[{}]+ {} &", job.id, job.command);
+    }
+    true
+}
+
 #[no_mangle]
 pub fn main() -> i32 {
     println!("Rust user shell");
+    // Ctrl-C and Ctrl-Z are for the jobs, which get the default actions back
+    let ignore = SignalAction {
+        handler: SIG_IGN,
+        ..Default::default()
+    };
+    sigaction(SignalFlags::SIGINT, Some(&ignore), None);
+    sigaction(SignalFlags::SIGTSTP, Some(&ignore), None);
+    setpgid(0, 0);
+    let shell_pgid = getpgid(0) as usize;
+    ioctl(0, TIOCSPGRP, shell_pgid);
+    let mut jobs: Vec<Job> = Vec::new();
     let mut line: String = String::new();
     print!("{}", LINE_START);
     loop {
+        // the console echoes and edits the line
         let c = getchar();
         match c {
             LF | CR => {
-                println!("");
-                if !line.is_empty() {
-                    let splited: Vec<_> = line.as_str().split('|').collect();
+                let mut command = line.trim_end();
+                // a trailing & runs the job in the background
+                let background = command.ends_with('&');
+                if background {
+                    command = command[..command.len() - 1].trim_end();
+                }
+                // a leading `trace` logs the syscalls of the job to the kernel log
+                let traced = command.starts_with("trace ");
+                if traced {
+                    command = command["trace ".len()..].trim_start();
+                }
+                if !command.is_empty() && !run_builtin(command, &mut jobs, shell_pgid) {
+                    let splited: Vec<_> = command.split('|').collect();
                     let process_arguments_list: Vec<_> = splited
                         .iter()
                         .map(|&cmd| ProcessArguments::new(cmd))
@@ -120,14 +249,23 @@ pub fn main() -> i32 {
                                 pipes_fd.push(pipe_fd);
                             }
                         }
-                        let mut children: Vec<_> = Vec::new();
+                        let mut children: Vec<usize> = Vec::new();
                         for (i, process_argument) in process_arguments_list.iter().enumerate() {
+                            // the first process leads the group of the job
+                            let pgid = children.first().copied().unwrap_or(0);
                             let pid = fork();
                             if pid == 0 {
+                                setpgid(0, pgid);
                                 let input = &process_argument.input;
                                 let output = &process_argument.output;
                                 let args_copy = &process_argument.args_copy;
                                 let args_addr = &process_argument.args_addr;
+                                let mut envs_addr: Vec<*const u8> = process_argument
+                                    .envs_copy
+                                    .iter()
+                                    .map(|env| env.as_ptr())
+                                    .collect();
+                                envs_addr.push(core::ptr::null::<u8>());
                                 // redirect input
                                 if !input.is_empty() {
                                     let input_fd = open(input.as_str(), OpenFlags::RDONLY);
@@ -172,41 +310,53 @@ pub fn main() -> i32 {
                                     close(pipe_fd[0]);
                                     close(pipe_fd[1]);
                                 }
+                                for signal in [SignalFlags::SIGINT, SignalFlags::SIGTSTP] {
+                                    sigaction(signal, Some(&SignalAction::default()), None);
+                                }
+                                if traced {
+                                    trace(0, true);
+                                }
                                 // execute new application
-                                if exec(args_copy[0].as_str(), args_addr.as_slice()) == -1 {
+                                if execve(
+                                    args_copy[0].as_str(),
+                                    args_addr.as_slice(),
+                                    envs_addr.as_slice(),
+                                ) == -1
+                                {
                                     println!("Error when executing!");
                                     return -4;
                                 }
                                 unreachable!();
                             } else {
-                                children.push(pid);
+                                // also here, whichever of the two runs first
+                                setpgid(pid as usize, if pgid == 0 { pid as usize } else { pgid });
+                                children.push(pid as usize);
                             }
                         }
                         for pipe_fd in pipes_fd.iter() {
                             close(pipe_fd[0]);
                             close(pipe_fd[1]);
                         }
-                        let mut exit_code: i32 = 0;
-                        for pid in children.into_iter() {
-                            let exit_pid = waitpid(pid as usize, &mut exit_code);
-                            assert_eq!(pid, exit_pid);
-                            //println!("Shell: Process {} exited with code {}", pid, exit_code);
+                        let job = Job {
+                            id: jobs.iter().map(|job| job.id).max().unwrap_or(0) + 1,
+                            pgid: children[0],
+                            pids: children,
+                            command: String::from(command),
+                            stopped: false,
+                        };
+                        if background {
+                            println!("[{}] {}", job.id, job.pgid);
+                            jobs.push(job);
+                        } else {
+                            run_foreground(job, &mut jobs, shell_pgid);
                         }
                     }
-                    line.clear();
                 }
+                line.clear();
+                reap_jobs(&mut jobs);
                 print!("{}", LINE_START);
             }
-            BS | DL => {
-                if !line.is_empty() {
-                    print!("{}", BS as char);
-                    print!(" ");
-                    print!("{}", BS as char);
-                    line.pop();
-                }
-            }
             _ => {
-                print!("{}", c as char);
                 line.push(c as char);
             }
         }
@@ -12,6 +12,15 @@ static SUCC_TESTS: &[(&str, &str, &str, &str, i32)] = &[
     ("filetest_simple\0", "\0", "\0", "\0", 0),
     ("cat\0", "filea\0", "\0", "\0", 0),
     ("cmdline_args\0", "1\0", "2\0", "3\0", 0),
+    ("exec_env\0", "\0", "\0", "\0", 0),
+    ("spawn_test\0", "\0", "\0", "\0", 0),
+    ("waitpid_options\0", "\0", "\0", "\0", 0),
+    ("job_control\0", "\0", "\0", "\0", 0),
+    ("rlimit_test\0", "\0", "\0", "\0", 0),
+    ("cpu_times\0", "\0", "\0", "\0", 0),
+    ("clock_nanosleep\0", "\0", "\0", "\0", 0),
+    ("gettimeofday\0", "\0", "\0", "\0", 0),
+    ("user_pointers\0", "\0", "\0", "\0", 0),
     ("eisenberg\0", "\0", "\0", "\0", 0),
     ("exit\0", "\0", "\0", "\0", 0),
     ("fantastic_text\0", "\0", "\0", "\0", 0),
@@ -22,25 +31,72 @@ static SUCC_TESTS: &[(&str, &str, &str, &str, i32)] = &[
     ("hello_world\0", "\0", "\0", "\0", 0),
     ("huge_write\0", "\0", "\0", "\0", 0),
     ("matrix\0", "\0", "\0", "\0", 0),
+    ("meminfo\0", "\0", "\0", "\0", 0),
+    ("dmesg\0", "\0", "\0", "\0", 0),
+    ("mmap_simple\0", "\0", "\0", "\0", 0),
+    ("mmap_anywhere\0", "\0", "\0", "\0", 0),
+    ("mmap_oom\0", "\0", "\0", "\0", 0),
+    ("fb0\0", "\0", "\0", "\0", 0),
+    ("input_event\0", "\0", "\0", "\0", 0),
+    ("getrandom\0", "\0", "\0", "\0", 0),
+    ("brk_test\0", "\0", "\0", "\0", 0),
+    ("shm\0", "\0", "\0", "\0", 0),
+    ("msg_queue\0", "\0", "\0", "\0", 0),
+    ("sysv_sem\0", "\0", "\0", "\0", 0),
+    ("pidfd\0", "\0", "\0", "\0", 0),
+    ("close_handle\0", "\0", "\0", "\0", 0),
+    ("swap_test\0", "\0", "\0", "\0", 0),
+    ("priority\0", "\0", "\0", "\0", 0),
+    ("affinity\0", "\0", "\0", "\0", 0),
+    ("deadline\0", "\0", "\0", "\0", 0),
+    ("perf_counters\0", "\0", "\0", "\0", 0),
+    ("syscall_trace\0", "\0", "\0", "\0", 0),
+    ("ptrace_step\0", "\0", "\0", "\0", 0),
+    ("sig_simple\0", "\0", "\0", "\0", 0),
     ("mpsc_sem\0", "\0", "\0", "\0", 0),
     ("peterson\0", "\0", "\0", "\0", 0),
     ("phil_din_mutex\0", "\0", "\0", "\0", 0),
     ("pipe_large_test\0", "\0", "\0", "\0", 0),
     ("pipetest\0", "\0", "\0", "\0", 0),
+    ("pipe_closed\0", "\0", "\0", "\0", 0),
+    ("pipe_signal\0", "\0", "\0", "\0", 0),
+    ("local_socket\0", "\0", "\0", "\0", 0),
+    ("poll\0", "\0", "\0", "\0", 0),
+    ("nonblock\0", "\0", "\0", "\0", 0),
+    ("udp_socket\0", "\0", "\0", "\0", 0),
+    ("tcp_socket\0", "\0", "\0", "\0", 0),
+    ("loopback\0", "\0", "\0", "\0", 0),
+    ("dup2\0", "\0", "\0", "\0", 0),
+    ("dir_test\0", "\0", "\0", "\0", 0),
+    ("link_test\0", "\0", "\0", "\0", 0),
+    ("fstat\0", "\0", "\0", "\0", 0),
+    ("procfs\0", "\0", "\0", "\0", 0),
+    ("devfs\0", "\0", "\0", "\0", 0),
+    ("tty_modes\0", "\0", "\0", "\0", 0),
     ("adder_peterson_spin\0", "\0", "\0", "\0", 0),
     ("adder_peterson_yield\0", "\0", "\0", "\0", 0),
     ("adder_mutex_blocking\0", "\0", "\0", "\0", 0),
     ("adder_mutex_spin\0", "\0", "\0", "\0", 0),
+    ("adder_futex\0", "\0", "\0", "\0", 0),
+    ("mutex_unlock\0", "\0", "\0", "\0", 0),
+    ("deadlock_mutex\0", "\0", "\0", "\0", 0),
     ("run_pipe_test\0", "\0", "\0", "\0", 0),
     ("sleep_simple\0", "\0", "\0", "\0", 0),
     ("sleep\0", "\0", "\0", "\0", 0),
+    ("sleep_exit\0", "\0", "\0", "\0", 0),
     ("sleep_simple\0", "\0", "\0", "\0", 0),
     ("sync_sem\0", "\0", "\0", "\0", 0),
     ("condsync_sem\0", "\0", "\0", "\0", 0),
     ("condsync_condvar\0", "\0", "\0", "\0", 0),
+    ("condvar_broadcast\0", "\0", "\0", "\0", 0),
     ("threads_arg\0", "\0", "\0", "\0", 0),
     ("threads\0", "\0", "\0", "\0", 0),
+    ("exit_group\0", "\0", "\0", "\0", 0),
     ("yield\0", "\0", "\0", "\0", 0),
+    ("trap_regs\0", "\0", "\0", "\0", 0),
+    ("posix_timer\0", "\0", "\0", "\0", 0),
+    ("posix_timer_thread\0", "\0", "\0", "\0", 0),
+    ("itimer\0", "\0", "\0", "\0", 0),
     ("barrier_fail\0", "\0", "\0", "\0", 0),
     ("barrier_condvar\0", "\0", "\0", "\0", 0),
 ];
@@ -51,10 +107,12 @@ static FAIL_TESTS: &[(&str, &str, &str, &str, i32)] = &[
     ("priv_csr\0", "\0", "\0", "\0", -4),
     ("priv_inst\0", "\0", "\0", "\0", -4),
     ("store_fault\0", "\0", "\0", "\0", -11),
+    ("mmap_fault\0", "\0", "\0", "\0", -11),
     ("until_timeout\0", "\0", "\0", "\0", -6),
     ("adder\0", "\0", "\0", "\0", -6),
     ("adder_simple_spin\0", "\0", "\0", "\0", -6),
     ("adder_simple_yield\0", "\0", "\0", "\0", -6),
+    ("posix_timer_alarm\0", "\0", "\0", "\0", -14),
 ];
 
 use user_lib::{exec, fork, waitpid};
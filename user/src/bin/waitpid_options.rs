@@ -0,0 +1,48 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{
+    exit, fork, getpid, kill, sleep, waitpid, waitpid_options, wexitstatus, wifexited, wifsignaled,
+    wtermsig, SignalFlags, WNOHANG,
+};
+
+#[no_mangle]
+pub fn main() -> i32 {
+    let pid = fork();
+    if pid == 0 {
+        sleep(50);
+        exit(3);
+    }
+    let mut status = 0;
+    assert_eq!(waitpid_options(pid, &mut status, WNOHANG), 0);
+    // sleeps until the child exits
+    assert_eq!(waitpid_options(-1, &mut status, 0), pid);
+    assert!(wifexited(status));
+    assert_eq!(wexitstatus(status), 3);
+
+    let pid = fork();
+    if pid == 0 {
+        exit(-1);
+    }
+    let mut exit_code = 0;
+    assert_eq!(waitpid(pid as usize, &mut exit_code), pid);
+    assert_eq!(exit_code, -1);
+
+    let pid = fork();
+    if pid == 0 {
+        kill(getpid() as usize, SignalFlags::SIGKILL.bits());
+        sleep(1000);
+        exit(0);
+    }
+    assert_eq!(waitpid_options(pid, &mut status, 0), pid);
+    assert!(wifsignaled(status));
+    assert_eq!(wtermsig(status), 9);
+
+    assert_eq!(waitpid_options(-1, &mut status, 0), -1);
+    assert_eq!(waitpid_options(-1, &mut status, 0x80), -1);
+    println!("waitpid_options passed!");
+    0
+}
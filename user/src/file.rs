@@ -7,15 +7,137 @@ bitflags! {
         const RDWR = 1 << 1;
         const CREATE = 1 << 9;
         const TRUNC = 1 << 10;
+        const NONBLOCK = 1 << 11;
     }
 }
 
+/// What a read or write on a nonblocking file returns instead of waiting.
+pub const EAGAIN: isize = -3;
+
+/// File status filled in by `fstat`, laid out the same as in the kernel.
+#[repr(C)]
+#[derive(Debug, Default)]
+pub struct Stat {
+    /// ID of the device holding the file
+    pub dev: u64,
+    /// inode number
+    pub ino: u64,
+    /// file type
+    pub mode: StatMode,
+    /// number of hard links
+    pub nlink: u32,
+    /// size in bytes, or bytes waiting to be read from a pipe
+    pub size: u64,
+    /// last access, easy-fs keeps no times so these are 0 for now
+    pub atime: i64,
+    /// last modification
+    pub mtime: i64,
+    /// last status change
+    pub ctime: i64,
+}
+
+impl Stat {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+bitflags! {
+    #[derive(Default)]
+    pub struct StatMode: u32 {
+        const NULL = 0;
+        const FIFO = 0o010000;
+        const CHR = 0o020000;
+        const DIR = 0o040000;
+        const FILE = 0o100000;
+    }
+}
+
+bitflags! {
+    /// Local modes of the console, set with `TCSETS`.
+    pub struct LocalFlags: u32 {
+        /// Ctrl-C raises SIGINT in the foreground process
+        const ISIG = 0o1;
+        /// input is read a line at a time, after editing
+        const ICANON = 0o2;
+        const ECHO = 0o10;
+    }
+}
+
+/// Returns the local modes.
+pub const TCGETS: u32 = 0x5401;
+/// Sets the local modes to the argument.
+pub const TCSETS: u32 = 0x5402;
+/// Returns the pid of the foreground process.
+pub const TIOCGPGRP: u32 = 0x540f;
+/// Makes the argument the foreground process, which receives SIGINT on
+/// Ctrl-C with its descendants.
+pub const TIOCSPGRP: u32 = 0x5410;
+
 pub fn dup(fd: usize) -> isize {
     sys_dup(fd)
 }
+/// Make `new_fd` a copy of `old_fd`, closing it first if it was open.
+pub fn dup2(old_fd: usize, new_fd: usize) -> isize {
+    sys_dup2(old_fd, new_fd)
+}
 pub fn open(path: &str, flags: OpenFlags) -> isize {
     sys_open(path, flags.bits)
 }
+pub fn mkdir(path: &str) -> isize {
+    sys_mkdir(path)
+}
+pub fn link(old_path: &str, new_path: &str) -> isize {
+    sys_linkat(old_path, new_path)
+}
+pub fn unlink(path: &str) -> isize {
+    sys_unlinkat(path)
+}
+pub fn fstat(fd: usize, st: &mut Stat) -> isize {
+    sys_fstat(fd, st)
+}
+/// Write cached file system blocks back to the disk.
+pub fn sync() -> isize {
+    sys_sync()
+}
+pub fn chdir(path: &str) -> isize {
+    sys_chdir(path)
+}
+/// Fill `buf` with the NUL terminated working directory, returning its length.
+pub fn getcwd(buf: &mut [u8]) -> isize {
+    sys_getcwd(buf)
+}
+/// Arguments and results are passed as values, not through pointers.
+pub fn ioctl(fd: usize, cmd: u32, arg: usize) -> isize {
+    sys_ioctl(fd, cmd, arg)
+}
+/// Get the `OpenFlags` of an fd, of which only `NONBLOCK` is a status flag.
+pub const F_GETFL: u32 = 3;
+/// Set the status flags of an fd.
+pub const F_SETFL: u32 = 4;
+pub fn fcntl(fd: usize, cmd: u32, arg: usize) -> isize {
+    sys_fcntl(fd, cmd, arg)
+}
+pub const PERF_COUNT_HW_CPU_CYCLES: usize = 0;
+pub const PERF_COUNT_HW_INSTRUCTIONS: usize = 1;
+pub const PERF_EVENT_IOC_ENABLE: u32 = 0x2400;
+pub const PERF_EVENT_IOC_DISABLE: u32 = 0x2401;
+pub const PERF_EVENT_IOC_RESET: u32 = 0x2403;
+
+/// Open a counter of hardware event `config` over the threads of process
+/// `pid` (0 for the caller), disabled at first. Enable, disable and reset
+/// it with ioctl.
+pub fn perf_event_open(config: usize, pid: usize) -> isize {
+    sys_perf_event_open(config, pid)
+}
+/// The count of the perf event `fd`, -1 if it is none.
+pub fn perf_event_read(fd: usize) -> i64 {
+    let mut buf = [0u8; 8];
+    match sys_read(fd, &mut buf) {
+        8 => u64::from_le_bytes(buf) as i64,
+        _ => -1,
+    }
+}
 pub fn close(fd: usize) -> isize {
     sys_close(fd)
 }
@@ -28,3 +150,100 @@ pub fn read(fd: usize, buf: &mut [u8]) -> isize {
 pub fn write(fd: usize, buf: &[u8]) -> isize {
     sys_write(fd, buf)
 }
+
+/// Fail instead of waiting for entropy, which never happens here.
+pub const GRND_NONBLOCK: u32 = 1;
+
+/// Fill `buf` from the entropy pool of the kernel, returning how many
+/// bytes were written.
+pub fn getrandom(buf: &mut [u8], flags: u32) -> isize {
+    sys_getrandom(buf, flags)
+}
+
+/// Reads of the eventfd take one of the count at a time.
+pub const EFD_SEMAPHORE: usize = 1;
+/// The eventfd starts out nonblocking.
+pub const EFD_NONBLOCK: usize = 0o4000;
+
+/// A file holding a count that writes add to and reads take.
+pub fn eventfd(initval: u64, flags: usize) -> isize {
+    sys_eventfd2(initval, flags)
+}
+
+bitflags! {
+    /// Readiness of a file for `ppoll`, numbered as in Linux.
+    pub struct PollFlags: i16 {
+        const IN = 0x1;
+        const OUT = 0x4;
+        /// the other end is gone, writes fail
+        const ERR = 0x8;
+        /// the other end is gone, reads end
+        const HUP = 0x10;
+        /// the fd is not open
+        const NVAL = 0x20;
+    }
+}
+
+/// An fd to `ppoll`, laid out as `struct pollfd`.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct PollFd {
+    /// negative to skip the entry
+    pub fd: i32,
+    pub events: PollFlags,
+    pub revents: PollFlags,
+}
+
+impl PollFd {
+    pub fn new(fd: usize, events: PollFlags) -> Self {
+        Self {
+            fd: fd as i32,
+            events,
+            revents: PollFlags::empty(),
+        }
+    }
+}
+
+/// Wait for any of `fds` to be ready for its events, for at most
+/// `timeout` if given, and return how many are.
+pub fn ppoll(fds: &mut [PollFd], timeout: Option<&TimeSpec>) -> isize {
+    sys_ppoll(fds, timeout.map_or(core::ptr::null(), |timeout| timeout))
+}
+
+/// Fds below this fit an `FdSet`.
+pub const FD_SETSIZE: usize = 1024;
+
+/// A set of fds for `pselect`, laid out as `fd_set`.
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct FdSet([u64; FD_SETSIZE / 64]);
+
+impl FdSet {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    pub fn insert(&mut self, fd: usize) {
+        self.0[fd / 64] |= 1 << (fd % 64);
+    }
+    pub fn contains(&self, fd: usize) -> bool {
+        self.0[fd / 64] & (1 << (fd % 64)) != 0
+    }
+}
+
+/// Wait for any fd below `nfds` in `readfds` to be readable or in
+/// `writefds` to be writable, for at most `timeout` if given. The sets
+/// are left with the ready fds, and how many there are is returned.
+pub fn pselect(
+    nfds: usize,
+    readfds: Option<&mut FdSet>,
+    writefds: Option<&mut FdSet>,
+    timeout: Option<&TimeSpec>,
+) -> isize {
+    sys_pselect6(
+        nfds,
+        readfds.map_or(core::ptr::null_mut(), |set| set),
+        writefds.map_or(core::ptr::null_mut(), |set| set),
+        core::ptr::null_mut(),
+        timeout.map_or(core::ptr::null(), |timeout| timeout),
+    )
+}
@@ -9,9 +9,18 @@ pub const VIRTGPU_XRES: u32 = 1280;
 pub const VIRTGPU_YRES: u32 = 800;
 pub const VIRTGPU_LEN: usize = (VIRTGPU_XRES * VIRTGPU_YRES * 4) as usize;
 
+/// Map `/dev/fb0` anywhere, returns where or -1.
 pub fn framebuffer() -> isize {
-    sys_framebuffer()
+    let fd = open("/dev/fb0", OpenFlags::RDWR);
+    if fd < 0 {
+        return -1;
+    }
+    let fb = mmap_file(0, VIRTGPU_LEN, 0x3, fd as usize, 0);
+    // the mapping outlives the fd
+    close(fd as usize);
+    fb
 }
+/// Show what was drawn on the framebuffer.
 pub fn framebuffer_flush() -> isize {
     sys_framebuffer_flush()
 }
@@ -23,7 +32,9 @@ pub struct Display {
 
 impl Display {
     pub fn new(size: Size) -> Self {
-        let fb_ptr = framebuffer() as *mut u8;
+        let fb_ptr = framebuffer();
+        assert!(fb_ptr > 0, "no framebuffer");
+        let fb_ptr = fb_ptr as *mut u8;
         let fb = unsafe { core::slice::from_raw_parts_mut(fb_ptr, VIRTGPU_LEN as usize) };
         Self { size, fb }
     }
@@ -105,7 +116,19 @@ impl From<u64> for InputEvent {
     }
 }
 
+/// Bytes of one event read from `/dev/input/event*`, `struct input_event`
+/// of Linux.
+pub const INPUT_EVENT_LEN: usize = 24;
+
 impl InputEvent {
+    /// An event as read from `/dev/input/event*`, without its time.
+    pub fn from_bytes(bytes: &[u8]) -> Self {
+        Self {
+            event_type: u16::from_le_bytes([bytes[16], bytes[17]]),
+            code: u16::from_le_bytes([bytes[18], bytes[19]]),
+            value: u32::from_le_bytes([bytes[20], bytes[21], bytes[22], bytes[23]]),
+        }
+    }
     pub fn decode(&self) -> Option<DecodeType> {
         Decoder::decode(
             self.event_type as usize,
@@ -19,6 +19,8 @@ extern crate bitflags;
 
 use alloc::vec::Vec;
 use buddy_system_allocator::LockedHeap;
+use core::alloc::{GlobalAlloc, Layout};
+use core::ptr::NonNull;
 pub use file::*;
 pub use io::*;
 pub use net::*;
@@ -26,38 +28,65 @@ pub use sync::*;
 use syscall::*;
 pub use task::*;
 
-const USER_HEAP_SIZE: usize = 32768;
+/// The least the heap grows by at once.
+const HEAP_GROW: usize = 0x1_0000;
 
-static mut HEAP_SPACE: [u8; USER_HEAP_SIZE] = [0; USER_HEAP_SIZE];
+/// A buddy heap that takes more memory with `sbrk` whenever an allocation
+/// does not fit, starting out empty.
+struct Heap(LockedHeap);
+
+unsafe impl GlobalAlloc for Heap {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let mut heap = self.0.lock();
+        if let Ok(ptr) = heap.alloc(layout) {
+            return ptr.as_ptr();
+        }
+        // twice the block size holds an aligned block whatever the break
+        let grow = (layout.size().max(layout.align()).next_power_of_two() * 2).max(HEAP_GROW);
+        let start = sbrk(grow as isize);
+        if start < 0 {
+            return core::ptr::null_mut();
+        }
+        heap.add_to_heap(start as usize, start as usize + grow);
+        heap.alloc(layout)
+            .map_or(core::ptr::null_mut(), |ptr| ptr.as_ptr())
+    }
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        self.0.lock().dealloc(NonNull::new_unchecked(ptr), layout)
+    }
+}
 
 #[global_allocator]
-static HEAP: LockedHeap = LockedHeap::empty();
+static HEAP: Heap = Heap(LockedHeap::empty());
 
 #[alloc_error_handler]
-pub fn handle_alloc_error(layout: core::alloc::Layout) -> ! {
+pub fn handle_alloc_error(layout: Layout) -> ! {
     panic!("Heap allocation error, layout = {:?}", layout);
 }
 
+/// The environment passed to `_start`, a NULL terminated array of
+/// pointers to `NAME=value` strings, or null if there is none.
+static mut ENVP: usize = 0;
+
+/// The NUL terminated string at `start`.
+fn c_str(start: usize) -> &'static str {
+    let len = (0usize..)
+        .find(|i| unsafe { ((start + *i) as *const u8).read_volatile() == 0 })
+        .unwrap();
+    core::str::from_utf8(unsafe { core::slice::from_raw_parts(start as *const u8, len) }).unwrap()
+}
+
 #[no_mangle]
 #[link_section = ".text.entry"]
-pub extern "C" fn _start(argc: usize, argv: usize) -> ! {
+pub extern "C" fn _start(argc: usize, argv: usize, envp: usize) -> ! {
     unsafe {
-        HEAP.lock()
-            .init(HEAP_SPACE.as_ptr() as usize, USER_HEAP_SIZE);
+        ENVP = envp;
     }
     let mut v: Vec<&'static str> = Vec::new();
     for i in 0..argc {
         let str_start =
             unsafe { ((argv + i * core::mem::size_of::<usize>()) as *const usize).read_volatile() };
-        let len = (0usize..)
-            .find(|i| unsafe { ((str_start + *i) as *const u8).read_volatile() == 0 })
-            .unwrap();
-        v.push(
-            core::str::from_utf8(unsafe {
-                core::slice::from_raw_parts(str_start as *const u8, len)
-            })
-            .unwrap(),
-        );
+        v.push(c_str(str_start));
     }
     exit(main(argc, v.as_slice()));
 }
@@ -72,7 +101,9 @@ fn main(_argc: usize, _argv: &[&str]) -> i32 {
 macro_rules! vstore {
     ($var: expr, $value: expr) => {
         // unsafe { core::intrinsics::volatile_store($var_ref as *const _ as _, $value) }
-        unsafe { core::ptr::write_volatile(core::ptr::addr_of_mut!($var), $value); }
+        unsafe {
+            core::ptr::write_volatile(core::ptr::addr_of_mut!($var), $value);
+        }
     };
 }
 
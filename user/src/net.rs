@@ -1,13 +1,132 @@
 use super::*;
+use core::mem::size_of;
 
-pub fn connect(ip: u32, sport: u16, dport: u16) -> isize {
-    sys_connect(ip, sport, dport)
+/// Local sockets, named by a path.
+pub const AF_UNIX: usize = 1;
+/// IPv4 sockets, named by an address and port.
+pub const AF_INET: usize = 2;
+/// A byte stream in both directions, TCP for `AF_INET`.
+pub const SOCK_STREAM: usize = 1;
+/// Datagrams, UDP for `AF_INET`.
+pub const SOCK_DGRAM: usize = 2;
+/// `recvfrom` does not wait even if the socket blocks.
+pub const MSG_DONTWAIT: u32 = 0x40;
+/// Or'ed into the type for a nonblocking socket.
+pub const SOCK_NONBLOCK: usize = 0o4000;
+/// `connect` on a nonblocking socket goes on without it, poll says when
+/// it is done.
+pub const EINPROGRESS: isize = -4;
+
+/// Address of a local socket, laid out as the kernel reads it.
+#[repr(C)]
+struct SockAddrUn {
+    family: u16,
+    /// NUL terminated
+    path: [u8; 108],
+}
+
+impl SockAddrUn {
+    fn new(path: &str) -> Option<Self> {
+        let path = path.trim_end_matches('\0').as_bytes();
+        let mut addr = Self {
+            family: AF_UNIX as u16,
+            path: [0; 108],
+        };
+        // leave room for the NUL
+        if path.len() >= addr.path.len() {
+            return None;
+        }
+        addr.path[..path.len()].copy_from_slice(path);
+        Some(addr)
+    }
+}
+
+pub fn socket(domain: usize, socket_type: usize, protocol: usize) -> isize {
+    sys_socket(domain, socket_type, protocol)
+}
+/// Two sockets connected to each other, their fds stored in `sv`.
+pub fn socketpair(domain: usize, socket_type: usize, protocol: usize, sv: &mut [usize]) -> isize {
+    sys_socketpair(domain, socket_type, protocol, sv)
+}
+/// Name the local socket `fd` by `path` for others to connect to.
+pub fn bind(fd: usize, path: &str) -> isize {
+    match SockAddrUn::new(path) {
+        Some(addr) => sys_bind(fd, &addr as *const _ as *const u8, size_of::<SockAddrUn>()),
+        None => -1,
+    }
+}
+pub fn listen(fd: usize, backlog: usize) -> isize {
+    sys_listen(fd, backlog)
+}
+/// Wait for a connection to `fd` and return a socket connected to it.
+pub fn accept(fd: usize) -> isize {
+    sys_accept(fd, core::ptr::null_mut(), core::ptr::null_mut())
+}
+/// Connect the local socket `fd` to the one listening at `path`.
+pub fn connect(fd: usize, path: &str) -> isize {
+    match SockAddrUn::new(path) {
+        Some(addr) => sys_connect(fd, &addr as *const _ as *const u8, size_of::<SockAddrUn>()),
+        None => -1,
+    }
+}
+
+/// An IPv4 address and port, laid out as `struct sockaddr_in`.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SockAddrIn {
+    family: u16,
+    /// in network byte order, as is the address
+    port: [u8; 2],
+    addr: [u8; 4],
+    zero: [u8; 8],
 }
 
-pub fn listen(sport: u16) -> isize {
-    sys_listen(sport)
+impl SockAddrIn {
+    pub fn new(addr: [u8; 4], port: u16) -> Self {
+        Self {
+            family: AF_INET as u16,
+            port: port.to_be_bytes(),
+            addr,
+            zero: [0; 8],
+        }
+    }
+    pub fn addr(&self) -> [u8; 4] {
+        self.addr
+    }
+    pub fn port(&self) -> u16 {
+        u16::from_be_bytes(self.port)
+    }
 }
 
-pub fn accept(socket_fd: usize) -> isize {
-    sys_accept(socket_fd)
+/// Give the IPv4 socket `fd` the local address `addr`, any free port if
+/// its port is 0.
+pub fn bind_in(fd: usize, addr: &SockAddrIn) -> isize {
+    sys_bind(fd, addr as *const _ as *const u8, size_of::<SockAddrIn>())
+}
+/// Wait for a connection to the TCP socket `fd`, return a socket
+/// connected to it and store the address of the peer in `addr`.
+pub fn accept_in(fd: usize, addr: &mut SockAddrIn) -> isize {
+    let mut addrlen = size_of::<SockAddrIn>() as u32;
+    sys_accept(fd, addr as *mut _ as *mut u8, &mut addrlen)
+}
+/// Connect the TCP socket `fd` to `addr`.
+pub fn connect_in(fd: usize, addr: &SockAddrIn) -> isize {
+    sys_connect(fd, addr as *const _ as *const u8, size_of::<SockAddrIn>())
+}
+/// Send `buf` as one datagram to `addr`.
+pub fn sendto(fd: usize, buf: &[u8], flags: u32, addr: &SockAddrIn) -> isize {
+    sys_sendto(
+        fd,
+        buf,
+        flags,
+        addr as *const _ as *const u8,
+        size_of::<SockAddrIn>(),
+    )
+}
+/// Take the next datagram into `buf`, dropping what does not fit, and who
+/// sent it into `addr`.
+pub fn recvfrom(fd: usize, buf: &mut [u8], flags: u32, addr: Option<&mut SockAddrIn>) -> isize {
+    let mut addrlen = size_of::<SockAddrIn>() as u32;
+    let addr = addr.map_or(core::ptr::null_mut(), |addr| addr as *mut _ as *mut u8);
+    sys_recvfrom(fd, buf, flags, addr, &mut addrlen)
 }
@@ -1,4 +1,11 @@
 use super::*;
+use core::sync::atomic::{AtomicU32, Ordering};
+
+pub const DEADLOCK: isize = -0xdead;
+
+pub fn enable_deadlock_detect(enabled: bool) -> isize {
+    sys_enable_deadlock_detect(enabled as usize)
+}
 
 pub fn mutex_create() -> isize {
     sys_mutex_create(false)
@@ -6,11 +13,14 @@ pub fn mutex_create() -> isize {
 pub fn mutex_blocking_create() -> isize {
     sys_mutex_create(true)
 }
-pub fn mutex_lock(mutex_id: usize) {
-    sys_mutex_lock(mutex_id);
+/// Returns `DEADLOCK` instead of blocking if that could deadlock and
+/// detection is enabled.
+pub fn mutex_lock(mutex_id: usize) -> isize {
+    sys_mutex_lock(mutex_id)
 }
-pub fn mutex_unlock(mutex_id: usize) {
-    sys_mutex_unlock(mutex_id);
+/// Fails if the mutex is not locked.
+pub fn mutex_unlock(mutex_id: usize) -> isize {
+    sys_mutex_unlock(mutex_id)
 }
 pub fn semaphore_create(res_count: usize) -> isize {
     sys_semaphore_create(res_count)
@@ -18,8 +28,10 @@ pub fn semaphore_create(res_count: usize) -> isize {
 pub fn semaphore_up(sem_id: usize) {
     sys_semaphore_up(sem_id);
 }
-pub fn semaphore_down(sem_id: usize) {
-    sys_semaphore_down(sem_id);
+/// Returns `DEADLOCK` instead of blocking if that could deadlock and
+/// detection is enabled.
+pub fn semaphore_down(sem_id: usize) -> isize {
+    sys_semaphore_down(sem_id)
 }
 pub fn condvar_create() -> isize {
     sys_condvar_create()
@@ -30,3 +42,54 @@ pub fn condvar_signal(condvar_id: usize) {
 pub fn condvar_wait(condvar_id: usize, mutex_id: usize) {
     sys_condvar_wait(condvar_id, mutex_id);
 }
+pub fn condvar_broadcast(condvar_id: usize) {
+    sys_condvar_broadcast(condvar_id);
+}
+
+const FUTEX_WAIT: usize = 0;
+const FUTEX_WAKE: usize = 1;
+/// Returned by `futex_wait` if `timeout` passed first.
+pub const ETIMEDOUT: isize = -2;
+
+/// Sleep while `word` holds `val`, for at most `timeout` if given.
+/// Returns 0 when woken, -1 at once if `word` held something else, or
+/// `ETIMEDOUT`.
+pub fn futex_wait(word: &AtomicU32, val: u32, timeout: Option<&TimeSpec>) -> isize {
+    let timeout = timeout.map_or(core::ptr::null(), |timeout| timeout as *const _);
+    sys_futex(word.as_ptr(), FUTEX_WAIT, val as usize, timeout)
+}
+/// Wake up to `count` threads sleeping on `word`, returning how many.
+pub fn futex_wake(word: &AtomicU32, count: usize) -> isize {
+    sys_futex(word.as_ptr(), FUTEX_WAKE, count, core::ptr::null())
+}
+
+/// A mutex that only enters the kernel when contended.
+pub struct FutexMutex {
+    /// 0 unlocked, 1 locked, 2 locked with threads possibly waiting
+    state: AtomicU32,
+}
+
+impl FutexMutex {
+    pub const fn new() -> Self {
+        Self {
+            state: AtomicU32::new(0),
+        }
+    }
+    pub fn lock(&self) {
+        if self
+            .state
+            .compare_exchange(0, 1, Ordering::Acquire, Ordering::Relaxed)
+            .is_ok()
+        {
+            return;
+        }
+        while self.state.swap(2, Ordering::Acquire) != 0 {
+            futex_wait(&self.state, 2, None);
+        }
+    }
+    pub fn unlock(&self) {
+        if self.state.swap(0, Ordering::Release) == 2 {
+            futex_wake(&self.state, 1);
+        }
+    }
+}
@@ -1,21 +1,88 @@
+use super::{
+    FdSet, ITimerSpec, ITimerVal, PollFd, RLimit, RUsage, SchedParam, SigEvent, SignalAction, Stat,
+    TimeSpec, TimeVal, Tms,
+};
+
+const SYSCALL_GETCWD: usize = 17;
+const SYSCALL_EVENTFD2: usize = 19;
+const SYSCALL_DUP2: usize = 23;
 const SYSCALL_DUP: usize = 24;
-const SYSCALL_CONNECT: usize = 29;
-const SYSCALL_LISTEN: usize = 30;
-const SYSCALL_ACCEPT: usize = 31;
+const SYSCALL_FCNTL: usize = 25;
+const SYSCALL_MKDIR: usize = 34;
+const SYSCALL_UNLINKAT: usize = 35;
+const SYSCALL_LINKAT: usize = 37;
+const SYSCALL_CHDIR: usize = 49;
 const SYSCALL_OPEN: usize = 56;
 const SYSCALL_CLOSE: usize = 57;
 const SYSCALL_PIPE: usize = 59;
 const SYSCALL_READ: usize = 63;
 const SYSCALL_WRITE: usize = 64;
+const SYSCALL_PSELECT6: usize = 72;
+const SYSCALL_PPOLL: usize = 73;
+const SYSCALL_FSTAT: usize = 80;
+const SYSCALL_SYNC: usize = 81;
 const SYSCALL_EXIT: usize = 93;
-const SYSCALL_SLEEP: usize = 101;
+const SYSCALL_EXIT_GROUP: usize = 94;
+const SYSCALL_FUTEX: usize = 98;
+const SYSCALL_NANOSLEEP: usize = 101;
+const SYSCALL_GETITIMER: usize = 102;
+const SYSCALL_SETITIMER: usize = 103;
+const SYSCALL_TIMER_CREATE: usize = 107;
+const SYSCALL_TIMER_GETTIME: usize = 108;
+const SYSCALL_TIMER_GETOVERRUN: usize = 109;
+const SYSCALL_TIMER_SETTIME: usize = 110;
+const SYSCALL_TIMER_DELETE: usize = 111;
+const SYSCALL_CLOCK_GETTIME: usize = 113;
+const SYSCALL_SYSLOG: usize = 116;
+const SYSCALL_PTRACE: usize = 117;
+const SYSCALL_SCHED_SETSCHEDULER: usize = 119;
+const SYSCALL_SCHED_GETSCHEDULER: usize = 120;
+const SYSCALL_SCHED_SETAFFINITY: usize = 122;
+const SYSCALL_SCHED_GETAFFINITY: usize = 123;
 const SYSCALL_YIELD: usize = 124;
 const SYSCALL_KILL: usize = 129;
-const SYSCALL_GET_TIME: usize = 169;
+const SYSCALL_SIGACTION: usize = 134;
+const SYSCALL_SIGPROCMASK: usize = 135;
+const SYSCALL_SIGRETURN: usize = 139;
+const SYSCALL_SETPRIORITY: usize = 140;
+const SYSCALL_GETPRIORITY: usize = 141;
+const SYSCALL_TIMES: usize = 153;
+const SYSCALL_SETPGID: usize = 154;
+const SYSCALL_GETPGID: usize = 155;
+const SYSCALL_SETSID: usize = 157;
+const SYSCALL_GETRUSAGE: usize = 165;
+const SYSCALL_GETTIMEOFDAY: usize = 169;
 const SYSCALL_GETPID: usize = 172;
+const SYSCALL_MSGGET: usize = 186;
+const SYSCALL_MSGRCV: usize = 188;
+const SYSCALL_MSGSND: usize = 189;
+const SYSCALL_SEMGET: usize = 190;
+const SYSCALL_SEMCTL: usize = 191;
+const SYSCALL_SEMOP: usize = 193;
+const SYSCALL_SHMGET: usize = 194;
+const SYSCALL_SHMAT: usize = 196;
+const SYSCALL_SHMDT: usize = 197;
+const SYSCALL_SOCKET: usize = 198;
+const SYSCALL_SOCKETPAIR: usize = 199;
+const SYSCALL_BIND: usize = 200;
+const SYSCALL_LISTEN: usize = 201;
+const SYSCALL_ACCEPT: usize = 202;
+const SYSCALL_CONNECT: usize = 203;
+const SYSCALL_SENDTO: usize = 206;
+const SYSCALL_RECVFROM: usize = 207;
+const SYSCALL_BRK: usize = 214;
+const SYSCALL_MUNMAP: usize = 215;
 const SYSCALL_FORK: usize = 220;
 const SYSCALL_EXEC: usize = 221;
+const SYSCALL_MMAP: usize = 222;
+const SYSCALL_PERF_EVENT_OPEN: usize = 241;
 const SYSCALL_WAITPID: usize = 260;
+const SYSCALL_PRLIMIT64: usize = 261;
+const SYSCALL_GETRANDOM: usize = 278;
+const SYSCALL_SPAWN: usize = 400;
+const SYSCALL_PIDFD_SEND_SIGNAL: usize = 424;
+const SYSCALL_PIDFD_OPEN: usize = 434;
+const SYSCALL_ENABLE_DEADLOCK_DETECT: usize = 469;
 const SYSCALL_THREAD_CREATE: usize = 1000;
 const SYSCALL_GETTID: usize = 1001;
 const SYSCALL_WAITTID: usize = 1002;
@@ -28,10 +95,14 @@ const SYSCALL_SEMAPHORE_DOWN: usize = 1022;
 const SYSCALL_CONDVAR_CREATE: usize = 1030;
 const SYSCALL_CONDVAR_SIGNAL: usize = 1031;
 const SYSCALL_CONDVAR_WAIT: usize = 1032;
-const SYSCALL_FRAMEBUFFER: usize = 2000;
+const SYSCALL_CONDVAR_BROADCAST: usize = 1033;
+const SYSCALL_CLOSE_HANDLE: usize = 1040;
 const SYSCALL_FRAMEBUFFER_FLUSH: usize = 2001;
 const SYSCALL_EVENT_GET: usize = 3000;
 const SYSCALL_KEY_PRESSED: usize = 3001;
+const SYSCALL_IOCTL: usize = 3002;
+const SYSCALL_MEMINFO: usize = 4000;
+const SYSCALL_TRACE: usize = 4001;
 
 fn syscall(id: usize, args: [usize; 3]) -> isize {
     let mut ret: isize;
@@ -47,24 +118,161 @@ fn syscall(id: usize, args: [usize; 3]) -> isize {
     ret
 }
 
+fn syscall4(id: usize, args: [usize; 4]) -> isize {
+    let mut ret: isize;
+    unsafe {
+        core::arch::asm!(
+            "ecall",
+            inlateout("x10") args[0] => ret,
+            in("x11") args[1],
+            in("x12") args[2],
+            in("x13") args[3],
+            in("x17") id
+        );
+    }
+    ret
+}
+
+fn syscall5(id: usize, args: [usize; 5]) -> isize {
+    let mut ret: isize;
+    unsafe {
+        core::arch::asm!(
+            "ecall",
+            inlateout("x10") args[0] => ret,
+            in("x11") args[1],
+            in("x12") args[2],
+            in("x13") args[3],
+            in("x14") args[4],
+            in("x17") id
+        );
+    }
+    ret
+}
+
+fn syscall6(id: usize, args: [usize; 6]) -> isize {
+    let mut ret: isize;
+    unsafe {
+        core::arch::asm!(
+            "ecall",
+            inlateout("x10") args[0] => ret,
+            in("x11") args[1],
+            in("x12") args[2],
+            in("x13") args[3],
+            in("x14") args[4],
+            in("x15") args[5],
+            in("x17") id
+        );
+    }
+    ret
+}
+
 pub fn sys_dup(fd: usize) -> isize {
     syscall(SYSCALL_DUP, [fd, 0, 0])
 }
 
-pub fn sys_connect(dest: u32, sport: u16, dport: u16) -> isize {
+pub fn sys_fcntl(fd: usize, cmd: u32, arg: usize) -> isize {
+    syscall(SYSCALL_FCNTL, [fd, cmd as usize, arg])
+}
+
+pub fn sys_dup2(old_fd: usize, new_fd: usize) -> isize {
+    syscall(SYSCALL_DUP2, [old_fd, new_fd, 0])
+}
+
+pub fn sys_socket(domain: usize, socket_type: usize, protocol: usize) -> isize {
+    syscall(SYSCALL_SOCKET, [domain, socket_type, protocol])
+}
+
+pub fn sys_socketpair(
+    domain: usize,
+    socket_type: usize,
+    protocol: usize,
+    sv: &mut [usize],
+) -> isize {
+    syscall4(
+        SYSCALL_SOCKETPAIR,
+        [domain, socket_type, protocol, sv.as_mut_ptr() as usize],
+    )
+}
+
+pub fn sys_bind(fd: usize, addr: *const u8, addrlen: usize) -> isize {
+    syscall(SYSCALL_BIND, [fd, addr as usize, addrlen])
+}
+
+pub fn sys_listen(fd: usize, backlog: usize) -> isize {
+    syscall(SYSCALL_LISTEN, [fd, backlog, 0])
+}
+
+pub fn sys_accept(fd: usize, addr: *mut u8, addrlen: *mut u32) -> isize {
+    syscall(SYSCALL_ACCEPT, [fd, addr as usize, addrlen as usize])
+}
+
+pub fn sys_connect(fd: usize, addr: *const u8, addrlen: usize) -> isize {
+    syscall(SYSCALL_CONNECT, [fd, addr as usize, addrlen])
+}
+
+pub fn sys_sendto(fd: usize, buf: &[u8], flags: u32, addr: *const u8, addrlen: usize) -> isize {
+    syscall6(
+        SYSCALL_SENDTO,
+        [
+            fd,
+            buf.as_ptr() as usize,
+            buf.len(),
+            flags as usize,
+            addr as usize,
+            addrlen,
+        ],
+    )
+}
+
+pub fn sys_recvfrom(
+    fd: usize,
+    buf: &mut [u8],
+    flags: u32,
+    addr: *mut u8,
+    addrlen: *mut u32,
+) -> isize {
+    syscall6(
+        SYSCALL_RECVFROM,
+        [
+            fd,
+            buf.as_mut_ptr() as usize,
+            buf.len(),
+            flags as usize,
+            addr as usize,
+            addrlen as usize,
+        ],
+    )
+}
+
+pub fn sys_getcwd(buf: &mut [u8]) -> isize {
+    syscall(SYSCALL_GETCWD, [buf.as_mut_ptr() as usize, buf.len(), 0])
+}
+
+pub fn sys_mkdir(path: &str) -> isize {
+    syscall(SYSCALL_MKDIR, [path.as_ptr() as usize, 0, 0])
+}
+
+pub fn sys_unlinkat(path: &str) -> isize {
+    syscall(SYSCALL_UNLINKAT, [path.as_ptr() as usize, 0, 0])
+}
+
+pub fn sys_linkat(old_path: &str, new_path: &str) -> isize {
     syscall(
-        SYSCALL_CONNECT,
-        [dest as usize, sport as usize, dport as usize],
+        SYSCALL_LINKAT,
+        [old_path.as_ptr() as usize, new_path.as_ptr() as usize, 0],
     )
 }
 
-// just listen for tcp connections now
-pub fn sys_listen(sport: u16) -> isize {
-    syscall(SYSCALL_LISTEN, [sport as usize, 0, 0])
+pub fn sys_fstat(fd: usize, st: &mut Stat) -> isize {
+    syscall(SYSCALL_FSTAT, [fd, st as *mut _ as usize, 0])
+}
+
+pub fn sys_sync() -> isize {
+    syscall(SYSCALL_SYNC, [0, 0, 0])
 }
 
-pub fn sys_accept(socket_fd: usize) -> isize {
-    syscall(SYSCALL_ACCEPT, [socket_fd, 0, 0])
+pub fn sys_chdir(path: &str) -> isize {
+    syscall(SYSCALL_CHDIR, [path.as_ptr() as usize, 0, 0])
 }
 
 pub fn sys_open(path: &str, flags: u32) -> isize {
@@ -90,25 +298,191 @@ pub fn sys_write(fd: usize, buffer: &[u8]) -> isize {
     syscall(SYSCALL_WRITE, [fd, buffer.as_ptr() as usize, buffer.len()])
 }
 
+pub fn sys_eventfd2(initval: u64, flags: usize) -> isize {
+    syscall(SYSCALL_EVENTFD2, [initval as usize, flags, 0])
+}
+
+pub fn sys_ppoll(fds: &mut [PollFd], timeout: *const TimeSpec) -> isize {
+    syscall4(
+        SYSCALL_PPOLL,
+        [fds.as_mut_ptr() as usize, fds.len(), timeout as usize, 0],
+    )
+}
+
+pub fn sys_pselect6(
+    nfds: usize,
+    readfds: *mut FdSet,
+    writefds: *mut FdSet,
+    exceptfds: *mut FdSet,
+    timeout: *const TimeSpec,
+) -> isize {
+    syscall5(
+        SYSCALL_PSELECT6,
+        [
+            nfds,
+            readfds as usize,
+            writefds as usize,
+            exceptfds as usize,
+            timeout as usize,
+        ],
+    )
+}
+
 pub fn sys_exit(exit_code: i32) -> ! {
     syscall(SYSCALL_EXIT, [exit_code as usize, 0, 0]);
     panic!("sys_exit never returns!");
 }
 
-pub fn sys_sleep(sleep_ms: usize) -> isize {
-    syscall(SYSCALL_SLEEP, [sleep_ms, 0, 0])
+pub fn sys_exit_group(exit_code: i32) -> ! {
+    syscall(SYSCALL_EXIT_GROUP, [exit_code as usize, 0, 0]);
+    panic!("sys_exit_group never returns!");
+}
+
+pub fn sys_futex(uaddr: *const u32, op: usize, val: usize, timeout: *const TimeSpec) -> isize {
+    syscall4(SYSCALL_FUTEX, [uaddr as usize, op, val, timeout as usize])
+}
+
+pub fn sys_nanosleep(req: *const TimeSpec, rem: *mut TimeSpec) -> isize {
+    syscall(SYSCALL_NANOSLEEP, [req as usize, rem as usize, 0])
+}
+
+pub fn sys_sched_setscheduler(pid: usize, policy: usize, param: &SchedParam) -> isize {
+    syscall(
+        SYSCALL_SCHED_SETSCHEDULER,
+        [pid, policy, param as *const _ as usize],
+    )
+}
+
+pub fn sys_sched_getscheduler(pid: usize) -> isize {
+    syscall(SYSCALL_SCHED_GETSCHEDULER, [pid, 0, 0])
+}
+
+pub fn sys_sched_setaffinity(pid: usize, mask: &usize) -> isize {
+    syscall(
+        SYSCALL_SCHED_SETAFFINITY,
+        [
+            pid,
+            core::mem::size_of::<usize>(),
+            mask as *const _ as usize,
+        ],
+    )
+}
+
+pub fn sys_sched_getaffinity(pid: usize, mask: &mut usize) -> isize {
+    syscall(
+        SYSCALL_SCHED_GETAFFINITY,
+        [pid, core::mem::size_of::<usize>(), mask as *mut _ as usize],
+    )
 }
 
 pub fn sys_yield() -> isize {
     syscall(SYSCALL_YIELD, [0, 0, 0])
 }
 
+pub fn sys_timer_create(clock_id: usize, sevp: *const SigEvent) -> isize {
+    syscall(SYSCALL_TIMER_CREATE, [clock_id, sevp as usize, 0])
+}
+
+pub fn sys_timer_settime(
+    timer_id: usize,
+    flags: u32,
+    new_value: &ITimerSpec,
+    old_value: *mut ITimerSpec,
+) -> isize {
+    syscall4(
+        SYSCALL_TIMER_SETTIME,
+        [
+            timer_id,
+            flags as usize,
+            new_value as *const _ as usize,
+            old_value as usize,
+        ],
+    )
+}
+
+pub fn sys_timer_gettime(timer_id: usize, curr_value: &mut ITimerSpec) -> isize {
+    syscall(
+        SYSCALL_TIMER_GETTIME,
+        [timer_id, curr_value as *mut _ as usize, 0],
+    )
+}
+
+pub fn sys_timer_getoverrun(timer_id: usize) -> isize {
+    syscall(SYSCALL_TIMER_GETOVERRUN, [timer_id, 0, 0])
+}
+
+pub fn sys_timer_delete(timer_id: usize) -> isize {
+    syscall(SYSCALL_TIMER_DELETE, [timer_id, 0, 0])
+}
+
+pub fn sys_getitimer(which: usize, curr_value: &mut ITimerVal) -> isize {
+    syscall(SYSCALL_GETITIMER, [which, curr_value as *mut _ as usize, 0])
+}
+
+pub fn sys_setitimer(which: usize, new_value: &ITimerVal, old_value: *mut ITimerVal) -> isize {
+    syscall(
+        SYSCALL_SETITIMER,
+        [which, new_value as *const _ as usize, old_value as usize],
+    )
+}
+
+pub fn sys_clock_gettime(clock_id: usize, tp: *mut TimeSpec) -> isize {
+    syscall(SYSCALL_CLOCK_GETTIME, [clock_id, tp as usize, 0])
+}
+
 pub fn sys_kill(pid: usize, signal: i32) -> isize {
     syscall(SYSCALL_KILL, [pid, signal as usize, 0])
 }
 
-pub fn sys_get_time() -> isize {
-    syscall(SYSCALL_GET_TIME, [0, 0, 0])
+pub fn sys_sigaction(
+    signal: i32,
+    action: *const SignalAction,
+    old_action: *mut SignalAction,
+) -> isize {
+    syscall(
+        SYSCALL_SIGACTION,
+        [signal as usize, action as usize, old_action as usize],
+    )
+}
+
+pub fn sys_sigprocmask(mask: i32) -> isize {
+    syscall(SYSCALL_SIGPROCMASK, [mask as usize, 0, 0])
+}
+
+pub fn sys_sigreturn() -> isize {
+    syscall(SYSCALL_SIGRETURN, [0, 0, 0])
+}
+
+pub fn sys_setpriority(which: usize, who: usize, priority: usize) -> isize {
+    syscall(SYSCALL_SETPRIORITY, [which, who, priority])
+}
+
+pub fn sys_getpriority(which: usize, who: usize) -> isize {
+    syscall(SYSCALL_GETPRIORITY, [which, who, 0])
+}
+
+pub fn sys_times(buf: *mut Tms) -> isize {
+    syscall(SYSCALL_TIMES, [buf as usize, 0, 0])
+}
+
+pub fn sys_setpgid(pid: usize, pgid: usize) -> isize {
+    syscall(SYSCALL_SETPGID, [pid, pgid, 0])
+}
+
+pub fn sys_getpgid(pid: usize) -> isize {
+    syscall(SYSCALL_GETPGID, [pid, 0, 0])
+}
+
+pub fn sys_setsid() -> isize {
+    syscall(SYSCALL_SETSID, [0, 0, 0])
+}
+
+pub fn sys_getrusage(who: isize, usage: *mut RUsage) -> isize {
+    syscall(SYSCALL_GETRUSAGE, [who as usize, usage as usize, 0])
+}
+
+pub fn sys_gettimeofday(tv: &mut TimeVal) -> isize {
+    syscall(SYSCALL_GETTIMEOFDAY, [tv as *mut _ as usize, 0, 0])
 }
 
 pub fn sys_getpid() -> isize {
@@ -119,15 +493,122 @@ pub fn sys_fork() -> isize {
     syscall(SYSCALL_FORK, [0, 0, 0])
 }
 
-pub fn sys_exec(path: &str, args: &[*const u8]) -> isize {
+pub fn sys_exec(path: &str, args: &[*const u8], envs: *const *const u8) -> isize {
     syscall(
         SYSCALL_EXEC,
-        [path.as_ptr() as usize, args.as_ptr() as usize, 0],
+        [
+            path.as_ptr() as usize,
+            args.as_ptr() as usize,
+            envs as usize,
+        ],
+    )
+}
+
+pub fn sys_mmap(
+    start: usize,
+    len: usize,
+    prot: usize,
+    flags: usize,
+    fd: usize,
+    offset: usize,
+) -> isize {
+    syscall6(SYSCALL_MMAP, [start, len, prot, flags, fd, offset])
+}
+
+pub fn sys_perf_event_open(config: usize, pid: usize) -> isize {
+    syscall(SYSCALL_PERF_EVENT_OPEN, [config, pid, 0])
+}
+
+pub fn sys_brk(addr: usize) -> isize {
+    syscall(SYSCALL_BRK, [addr, 0, 0])
+}
+
+pub fn sys_munmap(start: usize, len: usize) -> isize {
+    syscall(SYSCALL_MUNMAP, [start, len, 0])
+}
+
+pub fn sys_msgget(key: usize, flags: usize) -> isize {
+    syscall(SYSCALL_MSGGET, [key, flags, 0])
+}
+
+pub fn sys_msgsnd(msqid: usize, msgp: *const u8, msgsz: usize, flags: usize) -> isize {
+    syscall4(SYSCALL_MSGSND, [msqid, msgp as usize, msgsz, flags])
+}
+
+pub fn sys_msgrcv(msqid: usize, msgp: *mut u8, msgsz: usize, msgtyp: isize, flags: usize) -> isize {
+    syscall5(
+        SYSCALL_MSGRCV,
+        [msqid, msgp as usize, msgsz, msgtyp as usize, flags],
     )
 }
 
-pub fn sys_waitpid(pid: isize, exit_code: *mut i32) -> isize {
-    syscall(SYSCALL_WAITPID, [pid as usize, exit_code as usize, 0])
+pub fn sys_semget(key: usize, nsems: usize, flags: usize) -> isize {
+    syscall(SYSCALL_SEMGET, [key, nsems, flags])
+}
+
+pub fn sys_semctl(semid: usize, semnum: usize, cmd: usize, arg: usize) -> isize {
+    syscall4(SYSCALL_SEMCTL, [semid, semnum, cmd, arg])
+}
+
+pub fn sys_semop(semid: usize, sops: *const u8, nsops: usize) -> isize {
+    syscall(SYSCALL_SEMOP, [semid, sops as usize, nsops])
+}
+
+pub fn sys_shmget(key: usize, size: usize, flags: usize) -> isize {
+    syscall(SYSCALL_SHMGET, [key, size, flags])
+}
+
+pub fn sys_shmat(shmid: usize, addr: usize, flags: usize) -> isize {
+    syscall(SYSCALL_SHMAT, [shmid, addr, flags])
+}
+
+pub fn sys_shmdt(addr: usize) -> isize {
+    syscall(SYSCALL_SHMDT, [addr, 0, 0])
+}
+
+pub fn sys_waitpid(pid: isize, exit_status: *mut i32, options: usize) -> isize {
+    syscall(
+        SYSCALL_WAITPID,
+        [pid as usize, exit_status as usize, options],
+    )
+}
+
+pub fn sys_prlimit64(
+    pid: usize,
+    resource: usize,
+    new_limit: *const RLimit,
+    old_limit: *mut RLimit,
+) -> isize {
+    syscall4(
+        SYSCALL_PRLIMIT64,
+        [pid, resource, new_limit as usize, old_limit as usize],
+    )
+}
+
+pub fn sys_getrandom(buf: &mut [u8], flags: u32) -> isize {
+    syscall(
+        SYSCALL_GETRANDOM,
+        [buf.as_mut_ptr() as usize, buf.len(), flags as usize],
+    )
+}
+
+pub fn sys_spawn(path: &str, args: &[*const u8], envs: *const *const u8) -> isize {
+    syscall(
+        SYSCALL_SPAWN,
+        [
+            path.as_ptr() as usize,
+            args.as_ptr() as usize,
+            envs as usize,
+        ],
+    )
+}
+
+pub fn sys_pidfd_send_signal(pidfd: usize, signal: i32) -> isize {
+    syscall4(SYSCALL_PIDFD_SEND_SIGNAL, [pidfd, signal as usize, 0, 0])
+}
+
+pub fn sys_pidfd_open(pid: usize) -> isize {
+    syscall(SYSCALL_PIDFD_OPEN, [pid, 0, 0])
 }
 
 pub fn sys_thread_create(entry: usize, arg: usize) -> isize {
@@ -178,8 +659,16 @@ pub fn sys_condvar_wait(condvar_id: usize, mutex_id: usize) -> isize {
     syscall(SYSCALL_CONDVAR_WAIT, [condvar_id, mutex_id, 0])
 }
 
-pub fn sys_framebuffer() -> isize {
-    syscall(SYSCALL_FRAMEBUFFER, [0, 0, 0])
+pub fn sys_condvar_broadcast(condvar_id: usize) -> isize {
+    syscall(SYSCALL_CONDVAR_BROADCAST, [condvar_id, 0, 0])
+}
+
+pub fn sys_close_handle(kind: usize, handle: usize) -> isize {
+    syscall(SYSCALL_CLOSE_HANDLE, [kind, handle, 0])
+}
+
+pub fn sys_enable_deadlock_detect(enabled: usize) -> isize {
+    syscall(SYSCALL_ENABLE_DEADLOCK_DETECT, [enabled, 0, 0])
 }
 
 pub fn sys_framebuffer_flush() -> isize {
@@ -193,3 +682,26 @@ pub fn sys_event_get() -> isize {
 pub fn sys_key_pressed() -> isize {
     syscall(SYSCALL_KEY_PRESSED, [0, 0, 0])
 }
+
+pub fn sys_ioctl(fd: usize, cmd: u32, arg: usize) -> isize {
+    syscall(SYSCALL_IOCTL, [fd, cmd as usize, arg])
+}
+
+pub fn sys_meminfo(buf: &mut [u8]) -> isize {
+    syscall(SYSCALL_MEMINFO, [buf.as_mut_ptr() as usize, buf.len(), 0])
+}
+
+pub fn sys_trace(pid: usize, enable: bool) -> isize {
+    syscall(SYSCALL_TRACE, [pid, enable as usize, 0])
+}
+
+pub fn sys_ptrace(request: usize, pid: usize, addr: usize, data: usize) -> isize {
+    syscall4(SYSCALL_PTRACE, [request, pid, addr, data])
+}
+
+pub fn sys_syslog(action: usize, buf: &mut [u8]) -> isize {
+    syscall(
+        SYSCALL_SYSLOG,
+        [action, buf.as_mut_ptr() as usize, buf.len()],
+    )
+}
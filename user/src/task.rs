@@ -3,66 +3,704 @@ use super::*;
 pub fn exit(exit_code: i32) -> ! {
     sys_exit(exit_code);
 }
+/// End the process from any of its threads, taking the others with it.
+pub fn exit_group(exit_code: i32) -> ! {
+    sys_exit_group(exit_code);
+}
 pub fn yield_() -> isize {
     sys_yield()
 }
+/// Milliseconds since boot.
 pub fn get_time() -> isize {
-    sys_get_time()
+    let mut ts = TimeSpec::default();
+    clock_gettime(CLOCK_MONOTONIC, &mut ts);
+    (ts.sec * 1000 + ts.nsec / 1_000_000) as isize
 }
 pub fn getpid() -> isize {
     sys_getpid()
 }
+/// Move the process `pid`, 0 for the caller, into the process group
+/// `pgid`, 0 for a new group named after it.
+pub fn setpgid(pid: usize, pgid: usize) -> isize {
+    sys_setpgid(pid, pgid)
+}
+/// The process group of `pid`, 0 for the caller.
+pub fn getpgid(pid: usize) -> isize {
+    sys_getpgid(pid)
+}
+/// Start a new session and process group led by the caller.
+pub fn setsid() -> isize {
+    sys_setsid()
+}
+/// Fill `buf` with the kernel memory report and return its full length,
+/// which may exceed `buf.len()`.
+pub fn meminfo(buf: &mut [u8]) -> isize {
+    sys_meminfo(buf)
+}
+
+/// Start or stop logging every syscall of process `pid` (0 for the
+/// caller) to the kernel log, children forked later included.
+pub fn trace(pid: usize, enable: bool) -> isize {
+    sys_trace(pid, enable)
+}
+
+pub const PTRACE_PEEKDATA: usize = 2;
+pub const PTRACE_POKEDATA: usize = 5;
+pub const PTRACE_CONT: usize = 7;
+pub const PTRACE_SINGLESTEP: usize = 9;
+pub const PTRACE_GETREGS: usize = 12;
+pub const PTRACE_ATTACH: usize = 16;
+pub const PTRACE_DETACH: usize = 17;
+
+/// Registers of a traced thread, the pc in place of the zero register.
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct UserRegs {
+    pub regs: [usize; 32],
+}
+
+/// Trace the child `pid`, as on Linux: `data` is where PEEKDATA and
+/// GETREGS put the word at `addr` and the `UserRegs`. The child has to be
+/// stopped, as ATTACH and SINGLESTEP leave it, for anything but ATTACH.
+pub fn ptrace(request: usize, pid: usize, addr: usize, data: usize) -> isize {
+    sys_ptrace(request, pid, addr, data)
+}
+
+pub const SYSLOG_ACTION_READ_ALL: usize = 3;
+pub const SYSLOG_ACTION_READ_CLEAR: usize = 4;
+pub const SYSLOG_ACTION_CLEAR: usize = 5;
+pub const SYSLOG_ACTION_SIZE_UNREAD: usize = 9;
+pub const SYSLOG_ACTION_SIZE_BUFFER: usize = 10;
+
+/// Read or clear the kernel log, reads fill `buf` with its last bytes and
+/// return how many.
+pub fn syslog(action: usize, buf: &mut [u8]) -> isize {
+    sys_syslog(action, buf)
+}
 pub fn fork() -> isize {
     sys_fork()
 }
+/// Run `path` with the current environment.
 pub fn exec(path: &str, args: &[*const u8]) -> isize {
-    sys_exec(path, args)
+    sys_exec(path, args, unsafe { ENVP } as *const *const u8)
+}
+/// Run `path` with `envs`, NUL terminated `NAME=value` strings ending with
+/// a null pointer, as its environment.
+pub fn execve(path: &str, args: &[*const u8], envs: &[*const u8]) -> isize {
+    sys_exec(path, args, envs.as_ptr())
+}
+/// Run `path` in a new child process with the current environment, as
+/// fork then exec would but cheaper. Returns the pid of the child.
+pub fn spawn(path: &str, args: &[*const u8]) -> isize {
+    sys_spawn(path, args, unsafe { ENVP } as *const *const u8)
+}
+/// The `NAME=value` strings of the environment.
+pub fn environ() -> Vec<&'static str> {
+    let envp = unsafe { ENVP };
+    let mut envs = Vec::new();
+    if envp == 0 {
+        return envs;
+    }
+    for i in 0.. {
+        let str_start =
+            unsafe { ((envp + i * core::mem::size_of::<usize>()) as *const usize).read_volatile() };
+        if str_start == 0 {
+            break;
+        }
+        envs.push(c_str(str_start));
+    }
+    envs
+}
+pub fn getenv(name: &str) -> Option<&'static str> {
+    environ().into_iter().find_map(|env| {
+        env.split_once('=')
+            .filter(|(env_name, _)| *env_name == name)
+            .map(|(_, value)| value)
+    })
 }
+/// The mapping is shared with the file it maps.
+pub const MAP_SHARED: usize = 0x01;
+/// The mapping is the process's own.
+pub const MAP_PRIVATE: usize = 0x02;
+/// Zeroed memory backed by no file.
+pub const MAP_ANONYMOUS: usize = 0x20;
 
-pub fn wait(exit_code: &mut i32) -> isize {
-    loop {
-        match sys_waitpid(-1, exit_code as *mut _) {
-            -2 => {
-                yield_();
-            }
-            // -1 or a real pid
-            exit_pid => return exit_pid,
+/// Map zeroed memory. `prot` bit 0/1/2 stands for R/W/X. With `start` 0
+/// the kernel picks the place and returns it.
+pub fn mmap(start: usize, len: usize, prot: usize) -> isize {
+    sys_mmap(start, len, prot, MAP_PRIVATE | MAP_ANONYMOUS, usize::MAX, 0)
+}
+/// Map the device memory behind `fd` from `offset` on, such as that of
+/// `/dev/fb0`, as `mmap` does memory.
+pub fn mmap_file(start: usize, len: usize, prot: usize, fd: usize, offset: usize) -> isize {
+    sys_mmap(start, len, prot, MAP_SHARED, fd, offset)
+}
+pub fn munmap(start: usize, len: usize) -> isize {
+    sys_munmap(start, len)
+}
+/// Move the program break to `addr`, returning where it is afterwards,
+/// which is `addr` only if it moved. 0 only asks where it is.
+pub fn brk(addr: usize) -> isize {
+    sys_brk(addr)
+}
+/// Move the program break by `increment` bytes, returning where it was,
+/// or -1 if it could not be moved.
+pub fn sbrk(increment: isize) -> isize {
+    let old = sys_brk(0);
+    let new = match old.checked_add(increment) {
+        Some(new) if new >= 0 => new,
+        _ => return -1,
+    };
+    if increment != 0 && sys_brk(new as usize) != new {
+        return -1;
+    }
+    old
+}
+
+/// Key of a new object that only this process and its children know of.
+pub const IPC_PRIVATE: usize = 0;
+/// Create the object if the key has none.
+pub const IPC_CREAT: usize = 0o1000;
+/// With `IPC_CREAT`, fail if the key has an object already.
+pub const IPC_EXCL: usize = 0o2000;
+/// Fail instead of waiting for room or for a message.
+pub const IPC_NOWAIT: usize = 0o4000;
+/// Attach a segment for reading only.
+pub const SHM_RDONLY: usize = 0o10000;
+/// Cut a message too long for the buffer short instead of failing.
+pub const MSG_NOERROR: usize = 0o10000;
+/// `semctl` commands: the value of a semaphore, and setting it.
+pub const GETVAL: usize = 12;
+pub const SETVAL: usize = 16;
+
+/// The handle of the shared memory segment under `key`, created with
+/// `size` bytes if `flags` has `IPC_CREAT`.
+pub fn shmget(key: usize, size: usize, flags: usize) -> isize {
+    sys_shmget(key, size, flags)
+}
+/// Map a segment at `addr`, or anywhere with 0, returning where.
+pub fn shmat(shmid: usize, addr: usize, flags: usize) -> isize {
+    sys_shmat(shmid, addr, flags)
+}
+pub fn shmdt(addr: usize) -> isize {
+    sys_shmdt(addr)
+}
+
+/// A message as `msgsnd` and `msgrcv` lay it out, with room for `N`
+/// bytes of text.
+#[repr(C)]
+pub struct MsgBuf<const N: usize> {
+    /// positive
+    pub mtype: usize,
+    pub text: [u8; N],
+}
+
+impl<const N: usize> MsgBuf<N> {
+    pub fn new(mtype: usize) -> Self {
+        Self {
+            mtype,
+            text: [0; N],
         }
     }
 }
 
+/// The handle of the message queue under `key`.
+pub fn msgget(key: usize, flags: usize) -> isize {
+    sys_msgget(key, flags)
+}
+/// Send the first `len` bytes of `msg`'s text.
+pub fn msgsnd<const N: usize>(msqid: usize, msg: &MsgBuf<N>, len: usize, flags: usize) -> isize {
+    sys_msgsnd(msqid, msg as *const _ as *const u8, len.min(N), flags)
+}
+/// Receive the first message of type `msgtyp`, any with 0, or of the
+/// lowest type up to `-msgtyp` if negative, returning the text length.
+pub fn msgrcv<const N: usize>(
+    msqid: usize,
+    msg: &mut MsgBuf<N>,
+    msgtyp: isize,
+    flags: usize,
+) -> isize {
+    sys_msgrcv(msqid, msg as *mut _ as *mut u8, N, msgtyp, flags)
+}
+
+/// One operation of `semop`: `sem_op` is added to semaphore `sem_num`
+/// if positive, taken from it if negative, and waited to be 0 if 0.
+/// `sem_flg` may have `IPC_NOWAIT`.
+#[repr(C)]
+pub struct SemBuf {
+    pub sem_num: u16,
+    pub sem_op: i16,
+    pub sem_flg: i16,
+}
+
+/// The handle of the semaphore set under `key`, created with `nsems`
+/// semaphores, all 0, if `flags` has `IPC_CREAT`.
+pub fn semget(key: usize, nsems: usize, flags: usize) -> isize {
+    sys_semget(key, nsems, flags)
+}
+/// Apply all of `sops` at once, waiting until they can be.
+pub fn semop(semid: usize, sops: &[SemBuf]) -> isize {
+    sys_semop(semid, sops.as_ptr() as *const u8, sops.len())
+}
+pub fn semctl(semid: usize, semnum: usize, cmd: usize, arg: usize) -> isize {
+    sys_semctl(semid, semnum, cmd, arg)
+}
+
+/// What a handle from `mutex_create`, `timer_create`, `semget` and the
+/// like refers to, each kind numbering its handles from 0.
+#[repr(usize)]
+pub enum HandleKind {
+    Mutex,
+    Semaphore,
+    Condvar,
+    Timer,
+    Shm,
+    MsgQueue,
+    SemSet,
+    Pidfd,
+}
+
+/// Close `handle` of `kind`. Its object goes once nothing else holds it,
+/// an attached segment staying until it is detached.
+pub fn close_handle(kind: HandleKind, handle: usize) -> isize {
+    sys_close_handle(kind as usize, handle)
+}
+
+/// Do not wait if no child has exited yet, return 0 instead.
+pub const WNOHANG: usize = 1;
+/// Also return a child that stopped, without reaping it.
+pub const WUNTRACED: usize = 2;
+
+/// Whether the process exited by itself, rather than being killed.
+pub fn wifexited(status: i32) -> bool {
+    status & 0x7f == 0
+}
+/// The exit code passed to `exit`, modulo 256.
+pub fn wexitstatus(status: i32) -> i32 {
+    (status >> 8) & 0xff
+}
+pub fn wifsignaled(status: i32) -> bool {
+    status & 0x7f != 0 && status & 0x7f != 0x7f
+}
+/// The number of the signal that killed the process.
+pub fn wtermsig(status: i32) -> i32 {
+    status & 0x7f
+}
+/// Whether the process stopped, only reported with `WUNTRACED`.
+pub fn wifstopped(status: i32) -> bool {
+    status & 0xff == 0x7f
+}
+/// The number of the signal that stopped the process.
+pub fn wstopsig(status: i32) -> i32 {
+    (status >> 8) & 0xff
+}
+
+/// Wait for the child `pid`, or any child if it is -1, filling `status`
+/// for the `w*` functions above.
+pub fn waitpid_options(pid: isize, status: &mut i32, options: usize) -> isize {
+    sys_waitpid(pid, status as *mut _, options)
+}
+
+/// The exit code of a child from its status, negative exit codes passed
+/// to `exit` come out as they went in, a signal number comes out negated.
+fn exit_code_of(status: i32) -> i32 {
+    if wifsignaled(status) {
+        -wtermsig(status)
+    } else {
+        wexitstatus(status) as i8 as i32
+    }
+}
+
+pub fn wait(exit_code: &mut i32) -> isize {
+    waitpid(-1isize as usize, exit_code)
+}
+
 pub fn waitpid(pid: usize, exit_code: &mut i32) -> isize {
-    loop {
-        match sys_waitpid(pid as isize, exit_code as *mut _) {
-            -2 => {
-                yield_();
-            }
-            // -1 or a real pid
-            exit_pid => return exit_pid,
-        }
+    let mut status = 0;
+    let exit_pid = waitpid_options(pid as isize, &mut status, 0);
+    if exit_pid > 0 {
+        *exit_code = exit_code_of(status);
     }
+    exit_pid
 }
 
 pub fn waitpid_nb(pid: usize, exit_code: &mut i32) -> isize {
-    sys_waitpid(pid as isize, exit_code as *mut _)
+    let mut status = 0;
+    let exit_pid = waitpid_options(pid as isize, &mut status, WNOHANG);
+    if exit_pid > 0 {
+        *exit_code = exit_code_of(status);
+    }
+    exit_pid
 }
 
 bitflags! {
     pub struct SignalFlags: i32 {
+        const SIGHUP    = 1 << 1;
         const SIGINT    = 1 << 2;
+        const SIGQUIT   = 1 << 3;
         const SIGILL    = 1 << 4;
+        const SIGTRAP   = 1 << 5;
         const SIGABRT   = 1 << 6;
+        const SIGBUS    = 1 << 7;
         const SIGFPE    = 1 << 8;
+        const SIGKILL   = 1 << 9;
+        const SIGUSR1   = 1 << 10;
         const SIGSEGV   = 1 << 11;
+        const SIGUSR2   = 1 << 12;
+        const SIGPIPE   = 1 << 13;
+        const SIGALRM   = 1 << 14;
+        const SIGTERM   = 1 << 15;
+        const SIGSTKFLT = 1 << 16;
+        const SIGCHLD   = 1 << 17;
+        const SIGCONT   = 1 << 18;
+        const SIGSTOP   = 1 << 19;
+        const SIGTSTP   = 1 << 20;
+        const SIGTTIN   = 1 << 21;
+        const SIGTTOU   = 1 << 22;
+        const SIGURG    = 1 << 23;
+        const SIGXCPU   = 1 << 24;
+        const SIGXFSZ   = 1 << 25;
+        const SIGVTALRM = 1 << 26;
+        const SIGPROF   = 1 << 27;
+        const SIGWINCH  = 1 << 28;
+        const SIGIO     = 1 << 29;
+        const SIGPWR    = 1 << 30;
+        const SIGSYS    = 1 << 31;
     }
 }
 
 pub fn kill(pid: usize, signal: i32) -> isize {
     sys_kill(pid, signal)
 }
+/// Send `signal` to every process in the group `pgid`.
+pub fn killpg(pgid: usize, signal: i32) -> isize {
+    sys_kill(-(pgid as isize) as usize, signal)
+}
+/// A handle to the process `pid` that never refers to another process,
+/// even once `pid` is reused.
+pub fn pidfd_open(pid: usize) -> isize {
+    sys_pidfd_open(pid)
+}
+/// `kill` the process of `pidfd`, failing once it has exited.
+pub fn pidfd_send_signal(pidfd: usize, signal: i32) -> isize {
+    sys_pidfd_send_signal(pidfd, signal)
+}
+
+pub const SIG_DFL: usize = 0;
+pub const SIG_IGN: usize = 1;
+
+/// A signal handler gets the flag of its signal and must end with
+/// `sigreturn`.
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct SignalAction {
+    pub handler: usize,
+    pub mask: SignalFlags,
+}
+
+impl Default for SignalAction {
+    fn default() -> Self {
+        Self {
+            handler: SIG_DFL,
+            mask: SignalFlags::empty(),
+        }
+    }
+}
+
+pub fn sigaction(
+    signal: SignalFlags,
+    action: Option<&SignalAction>,
+    old_action: Option<&mut SignalAction>,
+) -> isize {
+    sys_sigaction(
+        signal.bits(),
+        action.map_or(core::ptr::null(), |a| a),
+        old_action.map_or(core::ptr::null_mut(), |a| a),
+    )
+}
+/// Replace the signal mask, returning the old one.
+pub fn sigprocmask(mask: SignalFlags) -> isize {
+    sys_sigprocmask(mask.bits())
+}
+pub fn sigreturn() -> isize {
+    sys_sigreturn()
+}
+
+pub const PRIO_PROCESS: usize = 0;
+pub const MIN_PRIORITY: usize = 2;
+pub const MAX_PRIORITY: usize = 64;
+pub const DEFAULT_PRIORITY: usize = 16;
+
+/// Set the priority of process `pid` (0 for the caller), larger values
+/// get scheduled first.
+pub fn setpriority(pid: usize, priority: usize) -> isize {
+    sys_setpriority(PRIO_PROCESS, pid, priority)
+}
+pub fn getpriority(pid: usize) -> isize {
+    sys_getpriority(PRIO_PROCESS, pid)
+}
+
+/// Let the threads of process `pid` (0 for the calling thread) run only
+/// on the harts set in `mask`.
+pub fn sched_setaffinity(pid: usize, mask: usize) -> isize {
+    sys_sched_setaffinity(pid, &mask)
+}
+/// The harts any thread of process `pid` (0 for the calling thread) may
+/// run on, -1 if there is no such process.
+pub fn sched_getaffinity(pid: usize) -> isize {
+    let mut mask = 0;
+    match sys_sched_getaffinity(pid, &mut mask) {
+        err if err < 0 => err,
+        _ => mask as isize,
+    }
+}
+
+pub const SCHED_NORMAL: usize = 0;
+pub const SCHED_DEADLINE: usize = 6;
+
+/// Runtime out of every period of a SCHED_DEADLINE thread, in
+/// nanoseconds.
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SchedParam {
+    pub runtime: usize,
+    pub period: usize,
+}
+
+/// Schedule the calling thread (pid 0) or the main thread of process
+/// `pid` with `policy`. SCHED_DEADLINE has it run for `runtime_ns` out of
+/// every `period_ns` ahead of all other threads, -1 if its hart cannot fit
+/// that in next to the deadline threads it has.
+pub fn sched_setscheduler(pid: usize, policy: usize, runtime_ns: usize, period_ns: usize) -> isize {
+    let param = SchedParam {
+        runtime: runtime_ns,
+        period: period_ns,
+    };
+    sys_sched_setscheduler(pid, policy, &param)
+}
+pub fn sched_getscheduler(pid: usize) -> isize {
+    sys_sched_getscheduler(pid)
+}
+
+/// Clock ticks a second, the unit of `times`.
+pub const CLOCKS_PER_SEC: usize = 100;
+
+/// CPU times in clock ticks, the `c` ones of the reaped children.
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Tms {
+    pub utime: usize,
+    pub stime: usize,
+    pub cutime: usize,
+    pub cstime: usize,
+}
+
+/// Fill `buf` with the CPU times used so far and return the clock ticks
+/// since boot.
+pub fn times(buf: &mut Tms) -> isize {
+    sys_times(buf as *mut _)
+}
+
+pub const RUSAGE_SELF: isize = 0;
+pub const RUSAGE_CHILDREN: isize = -1;
+pub const RUSAGE_THREAD: isize = 1;
+
+/// Resource usage, only the times and `maxrss` are filled in.
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct RUsage {
+    pub utime: TimeVal,
+    pub stime: TimeVal,
+    /// peak resident size in KiB
+    pub maxrss: usize,
+    pub unused: [usize; 13],
+}
+
+pub fn getrusage(who: isize, usage: &mut RUsage) -> isize {
+    sys_getrusage(who, usage as *mut _)
+}
+
+pub const RLIMIT_STACK: usize = 3;
+pub const RLIMIT_NOFILE: usize = 7;
+pub const RLIM_INFINITY: usize = usize::MAX;
+
+/// A resource limit: the soft limit is enforced, the hard one caps it and
+/// can only be lowered.
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct RLimit {
+    pub cur: usize,
+    pub max: usize,
+}
+
+/// Get the limit of `resource` for process `pid` (0 for the caller) into
+/// `old_limit`, then replace it with `new_limit`.
+pub fn prlimit(
+    pid: usize,
+    resource: usize,
+    new_limit: Option<&RLimit>,
+    old_limit: Option<&mut RLimit>,
+) -> isize {
+    sys_prlimit64(
+        pid,
+        resource,
+        new_limit.map_or(core::ptr::null(), |limit| limit as *const _),
+        old_limit.map_or(core::ptr::null_mut(), |limit| limit as *mut _),
+    )
+}
+pub fn getrlimit(resource: usize, limit: &mut RLimit) -> isize {
+    prlimit(0, resource, None, Some(limit))
+}
+pub fn setrlimit(resource: usize, limit: &RLimit) -> isize {
+    prlimit(0, resource, Some(limit), None)
+}
 
 pub fn sleep(sleep_ms: usize) {
-    sys_sleep(sleep_ms);
+    nanosleep(&TimeSpec::from_ms(sleep_ms));
+}
+/// Sleep for at least `req`, in whole milliseconds.
+pub fn nanosleep(req: &TimeSpec) -> isize {
+    sys_nanosleep(req, core::ptr::null_mut())
+}
+
+pub const CLOCK_REALTIME: usize = 0;
+pub const CLOCK_MONOTONIC: usize = 1;
+pub const TIMER_ABSTIME: u32 = 1;
+pub const SIGEV_SIGNAL: u32 = 0;
+pub const SIGEV_NONE: u32 = 1;
+/// Raise the signal for the thread `tid` of `SigEvent` only.
+pub const SIGEV_THREAD_ID: u32 = 4;
+
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct TimeSpec {
+    pub sec: usize,
+    pub nsec: usize,
+}
+
+impl TimeSpec {
+    pub fn from_ms(ms: usize) -> Self {
+        Self {
+            sec: ms / 1000,
+            nsec: ms % 1000 * 1_000_000,
+        }
+    }
+    pub fn to_ms(&self) -> usize {
+        self.sec * 1000 + (self.nsec + 999_999) / 1_000_000
+    }
+    pub fn to_ns(&self) -> usize {
+        self.sec * 1_000_000_000 + self.nsec
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct TimeVal {
+    pub sec: usize,
+    pub usec: usize,
+}
+
+impl TimeVal {
+    pub fn from_ms(ms: usize) -> Self {
+        Self {
+            sec: ms / 1000,
+            usec: ms % 1000 * 1000,
+        }
+    }
+    pub fn to_us(&self) -> usize {
+        self.sec * 1_000_000 + self.usec
+    }
+}
+
+pub const ITIMER_REAL: usize = 0;
+
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct ITimerVal {
+    pub interval: TimeVal,
+    pub value: TimeVal,
+}
+
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct ITimerSpec {
+    pub interval: TimeSpec,
+    pub value: TimeSpec,
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct SigEvent {
+    pub notify: u32,
+    pub signal: u32,
+    /// as `gettid` numbers it
+    pub tid: usize,
+}
+
+/// Read the wall-clock time into `tv`.
+pub fn gettimeofday(tv: &mut TimeVal) -> isize {
+    sys_gettimeofday(tv)
+}
+
+/// Read `clock_id` into `tp`, to the nanosecond.
+pub fn clock_gettime(clock_id: usize, tp: &mut TimeSpec) -> isize {
+    sys_clock_gettime(clock_id, tp)
+}
+
+/// Create a timer that notifies as described by `sev`, or raises
+/// SIGALRM if it is `None`.
+pub fn timer_create(clock_id: usize, sev: Option<&SigEvent>) -> isize {
+    sys_timer_create(
+        clock_id,
+        sev.map_or(core::ptr::null(), |sev| sev as *const _),
+    )
+}
+pub fn timer_settime(
+    timer_id: usize,
+    flags: u32,
+    new_value: &ITimerSpec,
+    old_value: Option<&mut ITimerSpec>,
+) -> isize {
+    sys_timer_settime(
+        timer_id,
+        flags,
+        new_value,
+        old_value.map_or(core::ptr::null_mut(), |old| old as *mut _),
+    )
+}
+pub fn timer_gettime(timer_id: usize, curr_value: &mut ITimerSpec) -> isize {
+    sys_timer_gettime(timer_id, curr_value)
+}
+pub fn timer_getoverrun(timer_id: usize) -> isize {
+    sys_timer_getoverrun(timer_id)
+}
+pub fn timer_delete(timer_id: usize) -> isize {
+    sys_timer_delete(timer_id)
+}
+
+/// Arm the interval timer `which`, reading its previous setting into
+/// `old_value` if given.
+pub fn setitimer(which: usize, new_value: &ITimerVal, old_value: Option<&mut ITimerVal>) -> isize {
+    sys_setitimer(
+        which,
+        new_value,
+        old_value.map_or(core::ptr::null_mut(), |old| old as *mut _),
+    )
+}
+pub fn getitimer(which: usize, curr_value: &mut ITimerVal) -> isize {
+    sys_getitimer(which, curr_value)
+}
+/// Raise SIGALRM once after `secs` seconds, or cancel it if 0. Returns
+/// the seconds that were left on the previous alarm.
+pub fn alarm(secs: usize) -> usize {
+    let new_value = ITimerVal {
+        interval: TimeVal::default(),
+        value: TimeVal { sec: secs, usec: 0 },
+    };
+    let mut old_value = ITimerVal::default();
+    setitimer(ITIMER_REAL, &new_value, Some(&mut old_value));
+    // a pending alarm never reports 0 seconds left
+    old_value.value.sec + (old_value.value.usec != 0) as usize
 }
 
 pub fn thread_create(entry: usize, arg: usize) -> isize {